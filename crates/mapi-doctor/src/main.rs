@@ -0,0 +1,272 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Collect diagnostics about the local Outlook MAPI installation, so they can be pasted into a
+//! bug report. Every check runs even if an earlier one fails, and the process exits with a
+//! non-zero status if anything failed.
+
+use outlook_mapi::{sys, *};
+use std::{process, sync::Arc, time::Instant};
+use windows::Win32::System::Registry::*;
+use windows_core::*;
+
+/// Office versions which might have an `Outlook\Bitness` value, newest first.
+const OFFICE_VERSIONS: [&str; 3] = ["16.0", "15.0", "14.0"];
+
+/// Number of rows to fetch in the hierarchy table query.
+const QUERY_ROWS: usize = 10;
+
+fn main() {
+    println!("mapi-doctor {}", env!("CARGO_PKG_VERSION"));
+
+    let mut doctor = Doctor::default();
+    doctor.installation();
+    doctor.bitness();
+
+    section("MAPI");
+    let Some(initialized) = doctor.check("MAPIInitialize", Initialize::new(Default::default()))
+    else {
+        process::exit(doctor.finish());
+    };
+    doctor.profiles();
+    if let Some(store) = doctor.default_store(initialized) {
+        doctor.table_query(&store);
+    }
+
+    process::exit(doctor.finish());
+}
+
+#[derive(Default)]
+struct Doctor {
+    failures: usize,
+}
+
+impl Doctor {
+    /// Print the outcome of a single check and count it if it failed.
+    fn check<T>(&mut self, label: &str, result: Result<T>) -> Option<T> {
+        match result {
+            Ok(value) => {
+                println!("  {label}: OK");
+                Some(value)
+            }
+            Err(err) => {
+                self.failures += 1;
+                let message = err.message();
+                if message.is_empty() {
+                    println!("  {label}: FAILED ({code})", code = err.code());
+                } else {
+                    println!("  {label}: FAILED ({code}) {message}", code = err.code());
+                }
+                None
+            }
+        }
+    }
+
+    fn installation(&mut self) {
+        section("Installation");
+        let installed = is_outlook_mapi_installed();
+        println!(
+            "  Outlook MAPI (olmapi32.dll): {}",
+            if installed { "found" } else { "not found" }
+        );
+        if !installed {
+            println!("  Falling back to the system mapi32.dll stub");
+        }
+        self.check("Architecture", check_outlook_mapi_installation());
+        if let Some(info) = self.check("Load MAPI", mapi_runtime_info()) {
+            println!("  Path: {}", info.path.display());
+            match info.file_version {
+                Some(version) => println!("  FileVersion: {version}"),
+                None => println!("  FileVersion: unknown"),
+            }
+            println!("  Architecture: {}", info.architecture);
+            println!("  Source: {:?}", info.source);
+        }
+    }
+
+    fn bitness(&mut self) {
+        section("Bitness");
+        let process_bitness = if cfg!(target_pointer_width = "64") {
+            "x64"
+        } else {
+            "x86"
+        };
+        println!("  Process: {process_bitness}");
+
+        let outlook_bitness = OFFICE_VERSIONS.iter().find_map(|version| {
+            read_registry_string(
+                &format!(r"Software\Microsoft\Office\{version}\Outlook"),
+                "Bitness",
+            )
+            .map(|bitness| (*version, bitness))
+        });
+        match outlook_bitness {
+            Some((version, bitness)) => {
+                println!("  Outlook {version}: {bitness}");
+                if bitness != process_bitness {
+                    self.failures += 1;
+                    println!(
+                        "  Bitness mismatch: a {process_bitness} process cannot load {bitness} MAPI"
+                    );
+                }
+            }
+            None => println!("  Outlook: unknown"),
+        }
+
+        if let Some(platform) = read_registry_string(
+            r"Software\Microsoft\Office\ClickToRun\Configuration",
+            "Platform",
+        ) {
+            println!("  Click-to-Run platform: {platform}");
+        }
+    }
+
+    fn profiles(&mut self) {
+        let Some(table) = self.check(
+            "MAPIAdminProfiles",
+            unsafe { sys::MAPIAdminProfiles(0) }
+                .and_then(|prof_admin| unsafe { prof_admin.GetProfileTable(0) })
+                .map(Table::new),
+        ) else {
+            return;
+        };
+        let Some(rows) = self.check(
+            "Profile table",
+            table.query_all_rows(
+                &[
+                    PropTag(sys::PR_DISPLAY_NAME_A),
+                    PropTag(sys::PR_DEFAULT_PROFILE),
+                ],
+                None,
+                None,
+            ),
+        ) else {
+            return;
+        };
+
+        println!("  Found {} profile(s)", rows.len());
+        for row in rows {
+            let mut values = row.iter();
+            let name = values
+                .next()
+                .and_then(|value| value.value.as_string())
+                .unwrap_or_else(|| String::from("<missing name>"));
+            let default = values.next().is_some_and(
+                |value| matches!(value.value, PropValueData::Boolean(value) if value != 0),
+            );
+            println!(
+                "    {name}{default}",
+                default = if default { " (default)" } else { "" }
+            );
+        }
+    }
+
+    fn default_store(&mut self, initialized: Arc<Initialize>) -> Option<MsgStore> {
+        section("Default profile");
+        let logon = self.check(
+            "Logon",
+            Logon::new(
+                initialized,
+                Default::default(),
+                None,
+                None,
+                LogonFlags {
+                    extended: true,
+                    new_session: true,
+                    no_mail: true,
+                    unicode: true,
+                    use_default: true,
+                    ..Default::default()
+                },
+            ),
+        )?;
+        self.check("Open default store", MsgStore::default_store(&logon))
+    }
+
+    fn table_query(&mut self, store: &MsgStore) {
+        section("Table query");
+        let start = Instant::now();
+        let result = query_ipm_subtree(store);
+        let elapsed = start.elapsed();
+        let Some((count, names)) = self.check("IPM subtree hierarchy table", result) else {
+            return;
+        };
+        println!("  {count} folder(s) in {elapsed:?}");
+        for name in names {
+            println!("    {name}");
+        }
+    }
+
+    /// Print a summary and return the exit code.
+    fn finish(self) -> i32 {
+        section("Summary");
+        if self.failures == 0 {
+            println!("  All checks passed");
+            0
+        } else {
+            println!("  {} check(s) failed", self.failures);
+            1
+        }
+    }
+}
+
+fn section(title: &str) {
+    println!();
+    println!("{title}");
+}
+
+/// Read a `REG_SZ` value from `HKEY_LOCAL_MACHINE`, always looking at the 64-bit registry view.
+fn read_registry_string(subkey: &str, value: &str) -> Option<String> {
+    let subkey = HSTRING::from(subkey);
+    let value = HSTRING::from(value);
+    let flags = RRF_RT_REG_SZ | RRF_SUBKEY_WOW6464KEY;
+    let mut size = 0;
+    unsafe {
+        RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            &subkey,
+            &value,
+            flags,
+            None,
+            None,
+            Some(&mut size),
+        )
+        .ok()
+        .ok()?;
+        let mut buffer = vec![0_u16; (size as usize).div_ceil(2)];
+        RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            &subkey,
+            &value,
+            flags,
+            None,
+            Some(buffer.as_mut_ptr() as *mut _),
+            Some(&mut size),
+        )
+        .ok()
+        .ok()?;
+        let len = buffer
+            .iter()
+            .position(|ch| *ch == 0)
+            .unwrap_or(buffer.len());
+        String::from_utf16(&buffer[..len]).ok()
+    }
+}
+
+/// Count the folders in the IPM subtree and read the names of the first few of them.
+fn query_ipm_subtree(store: &MsgStore) -> Result<(usize, Vec<String>)> {
+    let root = store.special_folder(SpecialFolder::IpmSubtree)?;
+
+    let table = root.open_hierarchy_table(TableFlags {
+        convenient_depth: true,
+        ..Default::default()
+    })?;
+    table.set_columns(&[PropTag(sys::PR_DISPLAY_NAME_W)])?;
+    let count = table.get_row_count()?;
+    let rows = table.query_rows(QUERY_ROWS)?;
+    let names = rows
+        .into_iter()
+        .filter_map(|row| row.iter().next().and_then(|value| value.value.as_string()))
+        .collect();
+    Ok((count, names))
+}
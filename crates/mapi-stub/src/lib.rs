@@ -206,6 +206,25 @@ fn no_arg_size(undecorated: &str) -> bool {
     no_arg_size_mapi.contains(undecorated) || no_arg_size_olmapi.contains(undecorated)
 }
 
+/// Check if a missing export should return a failure status instead of a neutral value. Besides
+/// the `HRESULT` functions, the `SCODE` functions (`Sc*` and a few others) and the Simple MAPI
+/// functions (`MAPI*`) return a status. The rest return a count, a size, a reference count, or a
+/// comparison result, e.g. `UlPropSize`, `UlRelease`, `LPropCompareProp`, or `MNLS_lstrlenW`.
+fn returns_status(undecorated: &str) -> bool {
+    const STATUS_EXPORTS: [&str; 6] = [
+        "CreateIProp",
+        "CreateTable",
+        "MapStorageSCode",
+        "OpenIMsgOnIStg",
+        "OpenIMsgSession",
+        "PropCopyMore",
+    ];
+
+    undecorated.starts_with("Sc")
+        || undecorated.starts_with("MAPI")
+        || STATUS_EXPORTS.contains(&undecorated)
+}
+
 fn impl_delay_load(attr: &DelayLoadAttr, ast: &ExternDecl) -> TokenStream {
     let dll = &attr.name.value();
     let abi = &ast.abi;
@@ -251,41 +270,40 @@ fn impl_delay_load(attr: &DelayLoadAttr, ast: &ExternDecl) -> TokenStream {
         }
     };
 
-    let call_export = if dll.as_str() == "olmapi32" {
-        quote! {
-            static EXPORT: OnceLock<Option<#func_type>> = OnceLock::new();
-
-            use ::windows::Win32::{Foundation::E_FAIL, System::LibraryLoader::*};
-
-            match (EXPORT.get_or_init(|| {
-                unsafe {
-                    let module = crate::get_mapi_module();
-                    GetProcAddress(module, proc_name).map(|export| unsafe { mem::transmute(export) })
-                }
-            })) {
-                Some(export) => {
-                    unsafe {
-                        export(#forward_args)
-                    }
-                },
-                None => E_FAIL
-            }
-        }
+    let dll_name = LitStr::new(dll, attr.name.span());
+    let missing = if returns_status(undecorated.as_str()) {
+        quote! { missing_status }
     } else {
-        let missing_export =
-            LitStr::new(&format!("{name} is not exported from {dll}"), name.span());
+        quote! { missing_value }
+    };
+    let return_type = match output {
+        ReturnType::Default => quote! { () },
+        ReturnType::Type(_, ty) => quote! { #ty },
+    };
 
-        quote! {
-            static EXPORT: OnceLock<#func_type> = OnceLock::new();
+    let call_export = quote! {
+        static EXPORT: OnceLock<Option<#func_type>> = OnceLock::new();
 
-            (EXPORT.get_or_init(|| {
-                use ::windows::Win32::System::LibraryLoader::*;
+        let export = match crate::isolated_mapi_module() {
+            Some(module) => crate::resolve_export(module, #proc_name, proc_name)
+                .map(|export| unsafe { mem::transmute::<_, #func_type>(export) }),
+            None => *EXPORT.get_or_init(|| {
+                let module = crate::get_mapi_module();
+                crate::resolve_export(module, #proc_name, proc_name)
+                    .map(|export| unsafe { mem::transmute(export) })
+            }),
+        };
 
+        match export {
+            Some(export) => {
                 unsafe {
-                    let module = crate::get_mapi_module();
-                    mem::transmute(GetProcAddress(module, proc_name).expect(#missing_export))
+                    export(#forward_args)
                 }
-            }))(#forward_args)
+            },
+            None => {
+                crate::missing_export(#dll_name, #proc_name);
+                <#return_type as crate::MissingExport>::#missing()
+            }
         }
     };
 
@@ -304,3 +322,32 @@ fn impl_delay_load(attr: &DelayLoadAttr, ast: &ExternDecl) -> TokenStream {
 
     gen.into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_exports() {
+        for name in [
+            "MAPIAllocateBuffer",
+            "MAPILogoff",
+            "ScCountProps",
+            "CreateIProp",
+        ] {
+            assert!(returns_status(name), "{name}");
+        }
+        for name in [
+            "MNLS_lstrlenW",
+            "MNLS_MultiByteToWideChar",
+            "MNLS_WideCharToMultiByte",
+            "UlPropSize",
+            "UlAddRef",
+            "UlRelease",
+            "UFromSz",
+            "LPropCompareProp",
+        ] {
+            assert!(!returns_status(name), "{name}");
+        }
+    }
+}
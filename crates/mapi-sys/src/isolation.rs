@@ -0,0 +1,102 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Opt-in isolation for processes which need more than one MAPI DLL.
+//!
+//! By default, every exported MAPI function is resolved once from the process-wide DLL returned
+//! by [`crate::mapi_module`]. A [`MapiModule`] loads another DLL from an explicit path, and while
+//! a [`MapiModuleScope`] from [`MapiModule::enter`] is alive, the exported functions called on
+//! that thread are resolved from it instead. Methods on the COM interfaces returned from those
+//! functions already call into the DLL which created them, so they do not need the scope.
+//!
+//! Every flat export which touches objects or buffers from that DLL, e.g. `MAPIFreeBuffer`, has
+//! to be called inside of the scope as well. Mixing memory or sessions from two DLLs is not
+//! supported by MAPI.
+
+use core::{cell::Cell, marker::PhantomData};
+use std::path::{Path, PathBuf};
+use windows::Win32::{Foundation::*, System::LibraryLoader::*};
+use windows_core::*;
+
+thread_local! {
+    static CURRENT: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// A MAPI DLL loaded from an explicit path with [`MapiModule::load`], independent of the one
+/// returned from [`crate::mapi_module`].
+///
+/// The DLL is never unloaded, even after this is dropped. COM objects, vtables, and
+/// `MAPIAllocateBuffer` memory which came from it may still be alive, and calling `FreeLibrary`
+/// would leave them pointing at unmapped code.
+#[derive(Debug)]
+pub struct MapiModule {
+    module: usize,
+    path: PathBuf,
+}
+
+impl MapiModule {
+    /// Call [`LoadLibraryExW`] with [`LOAD_WITH_ALTERED_SEARCH_PATH`], so the dependencies of
+    /// the DLL are loaded from its own directory first.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let buffer: Vec<_> = path
+            .to_str()
+            .ok_or_else(|| Error::from(E_INVALIDARG))?
+            .encode_utf16()
+            .chain(core::iter::once(0))
+            .collect();
+        let module = unsafe {
+            LoadLibraryExW(
+                PCWSTR::from_raw(buffer.as_ptr()),
+                None,
+                LOAD_WITH_ALTERED_SEARCH_PATH,
+            )?
+        };
+        Ok(Self {
+            module: module.0 as usize,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Get the [`HMODULE`] of the DLL.
+    pub fn handle(&self) -> HMODULE {
+        HMODULE(self.module as *mut _)
+    }
+
+    /// Get the path which was passed to [`MapiModule::load`].
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Resolve exported MAPI functions from this DLL on the current thread until the
+    /// [`MapiModuleScope`] is dropped. Scopes may be nested, and dropping one restores the
+    /// previous module.
+    pub fn enter(&self) -> MapiModuleScope<'_> {
+        MapiModuleScope {
+            previous: CURRENT.with(|current| current.replace(Some(self.module))),
+            _module: PhantomData,
+        }
+    }
+}
+
+/// Returned from [`MapiModule::enter`]. This is neither [`Send`] nor [`Sync`], since it only
+/// affects the thread which created it.
+#[must_use]
+pub struct MapiModuleScope<'a> {
+    previous: Option<usize>,
+    _module: PhantomData<(&'a MapiModule, *const ())>,
+}
+
+impl Drop for MapiModuleScope<'_> {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.previous));
+    }
+}
+
+/// Get the module from the innermost [`MapiModuleScope`] on this thread, if there is one. The
+/// delay load helpers check this before using their cached exports.
+pub(crate) fn isolated_mapi_module() -> Option<HMODULE> {
+    CURRENT
+        .with(Cell::get)
+        .map(|module| HMODULE(module as *mut _))
+}
@@ -7,28 +7,63 @@
 
 use windows::Win32::{Foundation::*, System::LibraryLoader::*};
 
+mod isolation;
+
 #[cfg(feature = "olmapi32")]
 mod load_mapi;
 
-fn get_mapi_module() -> HMODULE {
+mod load_observer;
+mod missing_export;
+
+use isolation::isolated_mapi_module;
+pub use isolation::{MapiModule, MapiModuleScope};
+use load_observer::resolve_export;
+pub use load_observer::{set_load_observer, LoadObserver};
+use missing_export::missing_export;
+pub use missing_export::{set_missing_export_handler, MissingExport, MissingExportHandler};
+
+/// How the DLL returned from [`mapi_module`] was found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapiModuleSource {
+    /// `olmapi32.dll` was already loaded in the process, e.g. in an Outlook add-in.
+    AlreadyLoaded,
+
+    /// `olmapi32.dll` was loaded from the Outlook installation found with
+    /// `MsiProvideQualifiedComponentW` for this version of Office.
+    OutlookInstall(&'static str),
+
+    /// The DLL of the default mail client registered under `HKLM\Software\Clients\Mail`, found
+    /// with `FGetComponentPath` and its `MSIComponentID`.
+    MailClient,
+
+    /// `mapi32.dll`, i.e. the MAPI stub library which forwards to the default mail client.
+    Mapi32,
+}
+
+/// Get the DLL which the bindings call into, loading it the first time, and how it was found.
+pub fn mapi_module() -> (HMODULE, MapiModuleSource) {
     use std::sync::OnceLock;
     use windows_core::*;
 
-    static MAPI_MODULE: OnceLock<usize> = OnceLock::new();
-    HMODULE(*MAPI_MODULE.get_or_init(|| unsafe {
+    static MAPI_MODULE: OnceLock<(usize, MapiModuleSource)> = OnceLock::new();
+    let (module, source) = *MAPI_MODULE.get_or_init(|| unsafe {
         #[cfg(feature = "olmapi32")]
-        if let Ok(module) = load_mapi::ensure_olmapi32() {
-            return module.0 as usize;
+        if let Ok((module, source)) = load_mapi::load_olmapi32() {
+            return (module.0 as usize, source);
         }
 
-        LoadLibraryW(w!("mapi32"))
-            .expect("mapi32 should be loaded on demand")
-            .0 as usize
-    }) as *mut _)
+        let module = LoadLibraryW(w!("mapi32")).expect("mapi32 should be loaded on demand");
+        (module.0 as usize, MapiModuleSource::Mapi32)
+    });
+    (HMODULE(module as *mut _), source)
+}
+
+fn get_mapi_module() -> HMODULE {
+    mapi_module().0
 }
 
 #[cfg(feature = "olmapi32")]
-pub use load_mapi::ensure_olmapi32;
+pub use load_mapi::{ensure_olmapi32, find_olmapi32};
 
 #[macro_use]
 extern crate outlook_mapi_stub;
@@ -1,15 +1,28 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
-use std::{iter, path::PathBuf};
+use crate::MapiModuleSource;
+use core::mem;
+use std::{
+    iter,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
 use windows::Win32::{
     Foundation::*,
-    System::{ApplicationInstallationAndServicing::*, LibraryLoader::*},
+    System::{ApplicationInstallationAndServicing::*, LibraryLoader::*, Registry::*},
 };
 use windows_core::*;
 
+const OLMAPI32_FILE_NAME: &str = "olmapi32.dll";
 const OLMAPI32_MODULE: PCWSTR = w!("olmapi32.dll");
 
+/// Registry key under `HKEY_LOCAL_MACHINE` with the default mail client and its MSI data.
+const MAIL_CLIENTS_KEY: PCSTR = s!(r"Software\Clients\Mail");
+
+/// MAPI stub libraries in the system directory which export `FGetComponentPath`.
+const MAPI_STUB_MODULES: [PCWSTR; 2] = [w!("mapistub.dll"), w!("mapi32.dll")];
+
 const O16_CATEGORY_GUID_CORE_OFFICE_RETAIL: PCWSTR = w!("{5812C571-53F0-4467-BEFA-0A4F47A9437C}");
 const O15_CATEGORY_GUID_CORE_OFFICE_RETAIL: PCWSTR = w!("{E83B4360-C208-4325-9504-0D23003A74A5}");
 const O14_CATEGORY_GUID_CORE_OFFICE_RETAIL: PCWSTR = w!("{1E77DE88-BCAB-4C37-B9E5-073AF52DFD7A}");
@@ -17,25 +30,29 @@ const O12_CATEGORY_GUID_CORE_OFFICE_RETAIL: PCWSTR = w!("{24AAE126-0911-478F-A01
 const O11_CATEGORY_GUID_CORE_OFFICE_RETAIL: PCWSTR = w!("{BC174BAD-2F53-4855-A1D5-0D575C19B1EA}");
 const O11_CATEGORY_GUID_CORE_OFFICE_DEBUG: PCWSTR = w!("{BC174BAD-2F53-4855-A1D5-1D575C19B1EA}");
 
-const OUTLOOK_QUALIFIED_COMPONENTS: [PCWSTR; 6] = [
-    O16_CATEGORY_GUID_CORE_OFFICE_RETAIL,
-    O15_CATEGORY_GUID_CORE_OFFICE_RETAIL,
-    O14_CATEGORY_GUID_CORE_OFFICE_RETAIL,
-    O12_CATEGORY_GUID_CORE_OFFICE_RETAIL,
-    O11_CATEGORY_GUID_CORE_OFFICE_RETAIL,
-    O11_CATEGORY_GUID_CORE_OFFICE_DEBUG,
+const OUTLOOK_QUALIFIED_COMPONENTS: [(PCWSTR, &str); 6] = [
+    (O16_CATEGORY_GUID_CORE_OFFICE_RETAIL, "Office 2016 or later"),
+    (O15_CATEGORY_GUID_CORE_OFFICE_RETAIL, "Office 2013"),
+    (O14_CATEGORY_GUID_CORE_OFFICE_RETAIL, "Office 2010"),
+    (O12_CATEGORY_GUID_CORE_OFFICE_RETAIL, "Office 2007"),
+    (O11_CATEGORY_GUID_CORE_OFFICE_RETAIL, "Office 2003"),
+    (O11_CATEGORY_GUID_CORE_OFFICE_DEBUG, "Office 2003 (debug)"),
 ];
 
-unsafe fn get_outlook_path(category: PCWSTR) -> Result<PathBuf> {
-    #[cfg(target_arch = "x86_64")]
-    const QUALIFIER: PCWSTR = w!("outlook.x64.exe");
-    #[cfg(not(target_arch = "x86_64"))]
-    const QUALIFIER: PCWSTR = w!("outlook.exe");
+/// Remember how [`load_olmapi32`] found olmapi32.dll the first time, so later calls which find
+/// it already loaded still report the same [`MapiModuleSource`].
+static LOADED_FROM: OnceLock<MapiModuleSource> = OnceLock::new();
 
+#[cfg(target_arch = "x86_64")]
+const QUALIFIERS: [PCWSTR; 2] = [w!("outlook.x64.exe"), w!("outlook.exe")];
+#[cfg(not(target_arch = "x86_64"))]
+const QUALIFIERS: [PCWSTR; 2] = [w!("outlook.exe"), w!("outlook.x64.exe")];
+
+unsafe fn get_outlook_path(category: PCWSTR, qualifier: PCWSTR) -> Result<PathBuf> {
     let mut size = 0;
     if WIN32_ERROR(MsiProvideQualifiedComponentW(
         category,
-        QUALIFIER,
+        qualifier,
         INSTALLMODE_DEFAULT,
         None,
         Some(&mut size),
@@ -48,7 +65,7 @@ unsafe fn get_outlook_path(category: PCWSTR) -> Result<PathBuf> {
     let mut buffer = vec![0; size as usize];
     if WIN32_ERROR(MsiProvideQualifiedComponentW(
         category,
-        QUALIFIER,
+        qualifier,
         INSTALLMODE_DEFAULT,
         Some(PWSTR::from_raw(buffer.as_mut_ptr())),
         Some(&mut size),
@@ -63,30 +80,220 @@ unsafe fn get_outlook_path(category: PCWSTR) -> Result<PathBuf> {
         return Err(Error::from(E_INVALIDARG));
     }
 
-    path.push("olmapi32.dll");
+    path.push(OLMAPI32_FILE_NAME);
     Ok(path)
 }
 
+/// Find the path to the MAPI DLL of the default mail client registered under
+/// `HKLM\Software\Clients\Mail`, using `FGetComponentPath` from the MAPI stub library with the
+/// `MSIComponentID` of that client. This works for some installations which do not register the
+/// MSI qualified components, e.g. some Click-to-Run configurations.
+unsafe fn get_mail_client_path() -> Option<PathBuf> {
+    type FGetComponentPath = unsafe extern "system" fn(
+        component: PCSTR,
+        qualifier: PCSTR,
+        dll_path: PSTR,
+        buffer_size: u32,
+        install: BOOL,
+    ) -> BOOL;
+
+    let client = read_registry_value(MAIL_CLIENTS_KEY, PCSTR::null(), RRF_RT_REG_SZ)?;
+    let client_key_buffer = mail_client_key(&client);
+    let client_key = PCSTR::from_raw(client_key_buffer.as_ptr());
+    let component = read_registry_value(client_key, s!("MSIComponentID"), RRF_RT_REG_SZ)?;
+
+    let stub = MAPI_STUB_MODULES
+        .into_iter()
+        .find_map(|name| LoadLibraryExW(name, None, LOAD_LIBRARY_SEARCH_SYSTEM32).ok())?;
+    let path = GetProcAddress(stub, s!("FGetComponentPath")).and_then(|proc| {
+        let get_component_path: FGetComponentPath = mem::transmute(proc);
+
+        // Try the qualifiers in the same order as MFCMAPI, ending with no qualifier at all.
+        let qualifiers = mail_client_qualifiers(
+            [s!("MSIApplicationLCID"), s!("MSIOfficeLCID")]
+                .into_iter()
+                .filter_map(|value| read_registry_value(client_key, value, RRF_RT_REG_MULTI_SZ)),
+        );
+        let mut buffer = vec![0_u8; MAX_PATH as usize];
+        qualifiers.into_iter().find_map(|qualifier| {
+            if !get_component_path(
+                PCSTR::from_raw(component.as_ptr()),
+                PCSTR::from_raw(qualifier.as_ptr()),
+                PSTR::from_raw(buffer.as_mut_ptr()),
+                buffer.len() as u32,
+                FALSE,
+            )
+            .as_bool()
+            {
+                return None;
+            }
+            let len = buffer.iter().position(|ch| *ch == 0)?;
+            (len > 0).then(|| PathBuf::from(String::from_utf8_lossy(&buffer[..len]).as_ref()))
+        })
+    });
+    let _ = FreeLibrary(stub);
+
+    // The default mail client might not be Outlook, so only report its DLL if it is olmapi32.dll.
+    path.filter(|path| is_olmapi32(path))
+}
+
+/// Build the null-terminated `HKLM\Software\Clients\Mail\<client>` subkey for the default
+/// mail client from the `REG_SZ` value of `HKLM\Software\Clients\Mail`.
+fn mail_client_key(client: &[u8]) -> Vec<u8> {
+    // SAFETY: `MAIL_CLIENTS_KEY` is a null-terminated string literal.
+    unsafe { MAIL_CLIENTS_KEY.as_bytes() }
+        .iter()
+        .copied()
+        .chain(*b"\\")
+        .chain(client.iter().copied().take_while(|ch| *ch != 0))
+        .chain(iter::once(0))
+        .collect()
+}
+
+/// Append the empty qualifier to the `REG_MULTI_SZ` qualifiers from the registry, so
+/// `FGetComponentPath` is called without a qualifier if none of them match.
+fn mail_client_qualifiers(qualifiers: impl Iterator<Item = Vec<u8>>) -> Vec<Vec<u8>> {
+    qualifiers.chain(iter::once(vec![0, 0])).collect()
+}
+
+/// Check if the file name of `path` is olmapi32.dll, ignoring case.
+fn is_olmapi32(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.eq_ignore_ascii_case(OLMAPI32_FILE_NAME))
+}
+
+/// Read a string value from `HKEY_LOCAL_MACHINE`, keeping the null terminator(s), since both
+/// `FGetComponentPath` and the `REG_MULTI_SZ` qualifiers expect them.
+unsafe fn read_registry_value(
+    subkey: PCSTR,
+    value: PCSTR,
+    flags: REG_ROUTINE_FLAGS,
+) -> Option<Vec<u8>> {
+    let mut size = 0;
+    RegGetValueA(
+        HKEY_LOCAL_MACHINE,
+        subkey,
+        value,
+        flags,
+        None,
+        None,
+        Some(&mut size),
+    )
+    .ok()
+    .ok()?;
+    let mut buffer = vec![0_u8; size as usize];
+    RegGetValueA(
+        HKEY_LOCAL_MACHINE,
+        subkey,
+        value,
+        flags,
+        None,
+        Some(buffer.as_mut_ptr() as *mut _),
+        Some(&mut size),
+    )
+    .ok()
+    .ok()?;
+    buffer.truncate(size as usize);
+    (buffer.len() > 1).then_some(buffer)
+}
+
+/// Find the MAPI DLL which can be loaded in this process, first with the MSI qualified
+/// components for each version of Outlook, then with [`get_mail_client_path`].
+unsafe fn find_native() -> Option<(PathBuf, MapiModuleSource)> {
+    OUTLOOK_QUALIFIED_COMPONENTS
+        .into_iter()
+        .find_map(|(category, office_version)| {
+            get_outlook_path(category, QUALIFIERS[0])
+                .ok()
+                .map(|path| (path, MapiModuleSource::OutlookInstall(office_version)))
+        })
+        .or_else(|| get_mail_client_path().map(|path| (path, MapiModuleSource::MailClient)))
+}
+
+/// Find olmapi32.dll in the Outlook installation without loading it, and return the path with
+/// how it was found. This looks for an installation matching the bitness of the current process
+/// first, then for one which does not match, so the caller can explain why it cannot be loaded.
+pub fn find_olmapi32() -> Option<(PathBuf, MapiModuleSource)> {
+    unsafe {
+        find_native().or_else(|| {
+            OUTLOOK_QUALIFIED_COMPONENTS
+                .into_iter()
+                .find_map(|(category, office_version)| {
+                    get_outlook_path(category, QUALIFIERS[1])
+                        .ok()
+                        .map(|path| (path, MapiModuleSource::OutlookInstall(office_version)))
+                })
+        })
+    }
+}
+
 pub fn ensure_olmapi32() -> Result<HMODULE> {
+    load_olmapi32().map(|(module, _)| module)
+}
+
+pub(crate) fn load_olmapi32() -> Result<(HMODULE, MapiModuleSource)> {
     unsafe {
         // If olmapi32.dll is already loaded, we're done.
-        let module = GetModuleHandleW(OLMAPI32_MODULE);
-        if module.is_ok() {
-            return module;
+        if let Ok(module) = GetModuleHandleW(OLMAPI32_MODULE) {
+            let source = *LOADED_FROM.get_or_init(|| MapiModuleSource::AlreadyLoaded);
+            return Ok((module, source));
         }
 
-        for category in OUTLOOK_QUALIFIED_COMPONENTS {
-            if let Ok(path) = get_outlook_path(category) {
-                let buffer: Vec<_> = path
-                    .to_str()
-                    .ok_or_else(|| Error::from(E_INVALIDARG))?
-                    .encode_utf16()
-                    .chain(iter::once(0))
-                    .collect();
-                return LoadLibraryW(PCWSTR::from_raw(buffer.as_ptr()));
-            }
+        if let Some((path, source)) = find_native() {
+            let buffer: Vec<_> = path
+                .to_str()
+                .ok_or_else(|| Error::from(E_INVALIDARG))?
+                .encode_utf16()
+                .chain(iter::once(0))
+                .collect();
+            let module = LoadLibraryW(PCWSTR::from_raw(buffer.as_ptr()))?;
+            let source = *LOADED_FROM.get_or_init(|| source);
+            return Ok((module, source));
         }
     }
 
     Err(Error::from(E_NOTIMPL))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_key() {
+        assert_eq!(
+            mail_client_key(b"Microsoft Outlook\0"),
+            b"Software\\Clients\\Mail\\Microsoft Outlook\0"
+        );
+        assert_eq!(
+            mail_client_key(b"Outlook"),
+            b"Software\\Clients\\Mail\\Outlook\0"
+        );
+    }
+
+    #[test]
+    fn client_qualifiers() {
+        let qualifiers = mail_client_qualifiers(
+            [b"1033\0\0".to_vec(), b"1033\x001031\0\0".to_vec()].into_iter(),
+        );
+        assert_eq!(
+            qualifiers,
+            [
+                b"1033\0\0".to_vec(),
+                b"1033\x001031\0\0".to_vec(),
+                vec![0, 0]
+            ]
+        );
+        assert_eq!(mail_client_qualifiers(iter::empty()), [vec![0, 0]]);
+    }
+
+    #[test]
+    fn olmapi32_file_name() {
+        assert!(is_olmapi32(&Path::new("Office16").join("OLMAPI32.DLL")));
+        assert!(!is_olmapi32(
+            &Path::new("Thunderbird").join("mozMapi32.dll")
+        ));
+        assert!(!is_olmapi32(Path::new("")));
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Observe the exports which the delay load helpers resolve at runtime.
+
+use std::sync::RwLock;
+use windows::Win32::{Foundation::*, System::LibraryLoader::GetProcAddress};
+use windows_core::*;
+
+/// Callback for [`set_load_observer`], which gets the name of the export and either the module
+/// it was found in or the error from `GetProcAddress`.
+pub type LoadObserver = fn(export: &str, result: Result<HMODULE>);
+
+static OBSERVER: RwLock<Option<LoadObserver>> = RwLock::new(None);
+
+/// Register a callback which is called every time a delay loaded MAPI function is looked up
+/// with `GetProcAddress`, e.g. to log which exports an unusual Office installation provides.
+/// Pass [`None`] to remove it.
+///
+/// Exports from the process-wide DLL are only looked up the first time they are called, so
+/// register the observer before calling into MAPI. Exports resolved inside of a
+/// [`crate::MapiModuleScope`] are looked up, and observed, on every call.
+///
+/// The first lookup of each export runs inside of [`std::sync::OnceLock::get_or_init`], so the
+/// observer must not call any MAPI functions itself. Calling the same export would deadlock, and
+/// calling another one would report it before the first one finished.
+pub fn set_load_observer(observer: Option<LoadObserver>) {
+    *OBSERVER
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = observer;
+}
+
+/// Call `GetProcAddress` for the delay load helpers and report the outcome to the
+/// [`LoadObserver`], if there is one.
+pub(crate) fn resolve_export(module: HMODULE, export: &str, proc_name: PCSTR) -> FARPROC {
+    let address = unsafe { GetProcAddress(module, proc_name) };
+    let error = address.is_none().then(Error::from_win32);
+    let observer = *OBSERVER
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(observer) = observer {
+        observer(export, error.map_or(Ok(module), Err));
+    }
+    address
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+
+    const FOUND: &str = "GetTickCount";
+    const MISSING: &str = "OutlookMapiMissingExport";
+
+    /// [`HMODULE`] is not [`Send`], so keep the address instead.
+    static OBSERVED: Mutex<Vec<(String, Result<usize>)>> = Mutex::new(Vec::new());
+
+    fn record(export: &str, result: Result<HMODULE>) {
+        if export == FOUND || export == MISSING {
+            OBSERVED
+                .lock()
+                .expect("lock poisoned")
+                .push((export.to_string(), result.map(|module| module.0 as usize)));
+        }
+    }
+
+    #[test]
+    fn observe_kernel32_exports() {
+        let kernel32 = unsafe { GetModuleHandleW(w!("kernel32.dll")) }.expect("no kernel32");
+        set_load_observer(Some(record));
+        let found = resolve_export(kernel32, FOUND, s!("GetTickCount"));
+        let missing = resolve_export(kernel32, MISSING, s!("OutlookMapiMissingExport"));
+        set_load_observer(None);
+        let observed = OBSERVED.lock().expect("lock poisoned");
+
+        assert!(found.is_some());
+        assert!(missing.is_none());
+        assert_eq!(observed.len(), 2);
+        assert_eq!(observed[0], (FOUND.to_string(), Ok(kernel32.0 as usize)));
+        let (export, result) = &observed[1];
+        assert_eq!(export, MISSING);
+        assert_eq!(
+            result.as_ref().map_err(Error::code),
+            Err(ERROR_PROC_NOT_FOUND.to_hresult())
+        );
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Handle exports which are missing from the MAPI DLL.
+//!
+//! The delay load helpers look up each function with `GetProcAddress` the first time it is
+//! called. Not every MAPI DLL exports every function, e.g. the `mapi32.dll` stub only forwards a
+//! subset of them, so if the lookup fails, the helper calls the handler registered with
+//! [`set_missing_export_handler`] and returns a [`MissingExport`] value instead of calling the
+//! function.
+
+use std::sync::RwLock;
+use windows::Win32::Foundation::*;
+use windows_core::*;
+
+/// Callback for [`set_missing_export_handler`], which gets the name of the DLL and the export.
+pub type MissingExportHandler = fn(dll: &str, export: &str);
+
+static HANDLER: RwLock<Option<MissingExportHandler>> = RwLock::new(None);
+
+/// Register a callback which is called every time a delay loaded MAPI function is not exported
+/// from the DLL, e.g. to log it or to panic in a debug build. Pass [`None`] to remove it.
+pub fn set_missing_export_handler(handler: Option<MissingExportHandler>) {
+    *HANDLER
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = handler;
+}
+
+/// Called by the delay load helpers when `GetProcAddress` fails.
+pub(crate) fn missing_export(dll: &str, export: &str) {
+    let handler = *HANDLER
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(handler) = handler {
+        handler(dll, export);
+    }
+}
+
+/// Value returned from a delay loaded MAPI function which is not exported from the DLL.
+///
+/// The delay load helpers use [`MissingExport::missing_status`] for exports which return a status
+/// code, e.g. an `HRESULT`, an `SCODE`, or a Simple MAPI `ULONG`, and
+/// [`MissingExport::missing_value`] for exports which return a count, a size, a reference count,
+/// or a comparison result, so the caller does not mistake an error code for a real value.
+pub trait MissingExport: Sized {
+    /// Get the failure status for this return type. Defaults to [`MissingExport::missing_value`].
+    fn missing_status() -> Self {
+        Self::missing_value()
+    }
+
+    /// Get a neutral value for this return type, e.g. 0, [`FALSE`], or `null`.
+    fn missing_value() -> Self;
+}
+
+impl MissingExport for () {
+    fn missing_value() -> Self {}
+}
+
+/// `HRESULT` is always a status, so both return `MAPI_E_CALL_FAILED`, which is the same as
+/// [`E_FAIL`].
+impl MissingExport for HRESULT {
+    fn missing_value() -> Self {
+        E_FAIL
+    }
+}
+
+/// `SCODE` functions return the same value as `HRESULT` functions.
+impl MissingExport for i32 {
+    fn missing_status() -> Self {
+        E_FAIL.0
+    }
+
+    fn missing_value() -> Self {
+        0
+    }
+}
+
+/// Simple MAPI functions return `ULONG` error codes, so the status is `MAPI_E_FAILURE`.
+impl MissingExport for u32 {
+    fn missing_status() -> Self {
+        crate::Microsoft::Office::Outlook::MAPI::Win32::MAPI_E_FAILURE
+    }
+
+    fn missing_value() -> Self {
+        0
+    }
+}
+
+impl MissingExport for BOOL {
+    fn missing_value() -> Self {
+        FALSE
+    }
+}
+
+impl MissingExport for FILETIME {
+    fn missing_value() -> Self {
+        Default::default()
+    }
+}
+
+impl MissingExport for PWSTR {
+    fn missing_value() -> Self {
+        PWSTR::null()
+    }
+}
+
+impl<T> MissingExport for *mut T {
+    fn missing_value() -> Self {
+        core::ptr::null_mut()
+    }
+}
+
+impl<T> MissingExport for Option<T> {
+    fn missing_value() -> Self {
+        None
+    }
+}
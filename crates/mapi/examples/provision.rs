@@ -0,0 +1,393 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Provision a mailbox from scratch: create a MAPI profile with a PST store, log on to it, find
+//! the well-known folders, create a folder tree from a declarative spec, grant permissions on
+//! the folders that ask for them, and register a set of Outlook categories.
+//!
+//! Usage: `cargo run --example provision -- <profile name> <path to .pst file>`
+
+use core::ptr;
+use outlook_mapi::{sys::*, *};
+use std::{
+    env,
+    io::{Read, Write},
+};
+use windows::Win32::Foundation::*;
+use windows_core::*;
+
+/// Service name of the Unicode PST provider.
+const PST_SERVICE: &str = "MSUPST MS";
+
+/// `PR_ROAMING_XMLSTREAM`, which holds the XML for `IPM.Configuration.*` messages.
+const PR_ROAMING_XMLSTREAM: u32 = 0x7C08_0102;
+
+/// Message class of the FAI message in the Calendar folder which holds the master category list.
+const CATEGORY_LIST_CLASS: &str = "IPM.Configuration.CategoryList";
+
+/// Declarative description of a folder to create under the IPM subtree.
+struct FolderSpec {
+    name: &'static str,
+    container_class: &'static str,
+    default_read_only: bool,
+    children: &'static [FolderSpec],
+}
+
+const FOLDERS: &[FolderSpec] = &[
+    FolderSpec {
+        name: "Projects",
+        container_class: "IPF.Note",
+        default_read_only: false,
+        children: &[
+            FolderSpec {
+                name: "Active",
+                container_class: "IPF.Note",
+                default_read_only: false,
+                children: &[],
+            },
+            FolderSpec {
+                name: "Archive",
+                container_class: "IPF.Note",
+                default_read_only: true,
+                children: &[],
+            },
+        ],
+    },
+    FolderSpec {
+        name: "Team Calendar",
+        container_class: "IPF.Appointment",
+        default_read_only: true,
+        children: &[],
+    },
+    FolderSpec {
+        name: "Vendors",
+        container_class: "IPF.Contact",
+        default_read_only: false,
+        children: &[],
+    },
+];
+
+/// Outlook categories to register, with their `olCategoryColor` index.
+const CATEGORIES: &[(&str, u32)] = &[("Follow Up", 1), ("Customer", 5), ("Internal", 8)];
+
+fn main() -> Result<()> {
+    let mut args = env::args().skip(1);
+    let (Some(profile_name), Some(pst_path)) = (args.next(), args.next()) else {
+        eprintln!("Usage: provision <profile name> <path to .pst file>");
+        return Err(Error::from(E_INVALIDARG));
+    };
+
+    println!("Initializing MAPI...");
+    let initialized = Initialize::new(Default::default())?;
+
+    println!("Creating profile {profile_name}...");
+    create_profile(&profile_name, &pst_path)?;
+
+    println!("Logging on to {profile_name}...");
+    let logon = Logon::new(
+        initialized,
+        Default::default(),
+        Some(&profile_name),
+        None,
+        LogonFlags {
+            explicit_profile: true,
+            extended: true,
+            new_session: true,
+            no_mail: true,
+            unicode: true,
+            ..Default::default()
+        },
+    )?;
+
+    println!("Opening the default store...");
+    let store = MsgStore::default_store(&logon)?;
+
+    let root = store.special_folder(SpecialFolder::IpmSubtree)?;
+    let inbox = store.inbox()?;
+    let calendar = store.special_folder(SpecialFolder::Calendar)?;
+    println!(
+        "Found the IPM subtree, {inbox}, and {calendar}",
+        inbox = display_name(&inbox)?,
+        calendar = display_name(&calendar)?
+    );
+
+    println!("Creating folders...");
+    for spec in FOLDERS {
+        provision_folder(&root, spec, 1)?;
+    }
+
+    println!("Registering categories...");
+    register_categories(&calendar)?;
+
+    println!("Done!");
+    Ok(())
+}
+
+/// Create the profile if it does not exist yet, and add a PST service pointing at `pst_path`.
+fn create_profile(profile_name: &str, pst_path: &str) -> Result<()> {
+    let mut profile = to_pcstr_buffer(profile_name)?;
+    let prof_admin = unsafe { MAPIAdminProfiles(0)? };
+    match unsafe { prof_admin.CreateProfile(profile.as_mut_ptr() as *mut _, ptr::null_mut(), 0, 0) }
+    {
+        Ok(()) => {}
+        Err(err) if err.code() == MAPI_E_NO_ACCESS => {
+            println!("Profile {profile_name} already exists, reusing it");
+            return Ok(());
+        }
+        Err(err) => return Err(err),
+    }
+
+    let service_admin = unsafe {
+        let mut service_admin = None;
+        prof_admin.AdminServices(
+            profile.as_mut_ptr() as *mut _,
+            ptr::null_mut(),
+            0,
+            0,
+            &mut service_admin,
+        )?;
+        service_admin.ok_or_else(|| Error::from(E_POINTER))?
+    };
+
+    let mut service = to_pcstr_buffer(PST_SERVICE)?;
+    let mut display_name: Vec<_> = b"Provisioned Mailbox\0".to_vec();
+    unsafe {
+        service_admin.CreateMsgService(
+            service.as_mut_ptr() as *mut _,
+            display_name.as_mut_ptr() as *mut _,
+            0,
+            0,
+        )?;
+    }
+
+    // CreateMsgService doesn't return the MAPIUID, so look for the PST service we just added.
+    let services = Table::new(unsafe { service_admin.GetMsgServiceTable(0)? });
+    let rows = services.query_all_rows(
+        &[PropTag(PR_SERVICE_UID), PropTag(PR_SERVICE_NAME_A)],
+        None,
+        None,
+    )?;
+    let mut service_uid = rows
+        .into_iter()
+        .filter_map(|row| {
+            let mut values = row.iter();
+            let uid = values.next()?;
+            // SAFETY: The value is borrowed from a row which MAPI returned.
+            let name = unsafe { values.next()?.value.as_string_in(CodePage::ACP) }?;
+            let uid = uid.value.as_bytes()?;
+            (name == PST_SERVICE && uid.len() == size_of::<MAPIUID>()).then(|| MAPIUID {
+                ab: uid.try_into().expect("checked the length"),
+            })
+        })
+        .last()
+        .ok_or_else(|| Error::from(MAPI_E_NOT_FOUND))?;
+
+    let mut props = PropValueBuilder::new()
+        .string(
+            PropTag(PR_PST_PATH).change_prop_type(PropType::new(PT_UNICODE as u16)),
+            pst_path,
+        )?
+        .build()?;
+    unsafe {
+        service_admin.ConfigureMsgService(
+            &mut service_uid,
+            0,
+            0,
+            props.len() as u32,
+            props.as_mut_ptr(),
+        )
+    }
+}
+
+fn display_name<P: MAPIProp>(prop: &P) -> Result<String> {
+    let props = prop.get_props(&[PropTag(PR_DISPLAY_NAME_W)])?;
+    let display_name = props
+        .iter()
+        .next()
+        .and_then(|value| value.value.as_string())
+        .unwrap_or_default();
+    Ok(display_name)
+}
+
+/// Create or open the folder described by `spec`, then recurse into its children.
+fn provision_folder(parent: &Folder, spec: &FolderSpec, depth: usize) -> Result<()> {
+    let indent = "  ".repeat(depth);
+    let folder = match parent.create_subfolder(spec.name) {
+        Ok(folder) => {
+            println!("{indent}Created {name}", name = spec.name);
+            folder
+        }
+        Err(err) if err.code() == MAPI_E_COLLISION => {
+            println!("{indent}Found {name}", name = spec.name);
+            find_subfolder(parent, spec.name)?
+        }
+        Err(err) => return Err(err),
+    };
+
+    folder.set_props(&[PropValue {
+        tag: PropTag(PR_CONTAINER_CLASS_W),
+        value: PropValueData::Unicode(to_pwstr_buffer(spec.container_class)),
+    }])?;
+
+    if spec.default_read_only {
+        match grant_default_read_only(&folder) {
+            Ok(()) => println!("{indent}  Granted read-only access to Default"),
+            Err(err) if err.code() == MAPI_E_NO_SUPPORT || err.code() == E_NOINTERFACE => {
+                println!("{indent}  This store does not support folder permissions")
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    for child in spec.children {
+        provision_folder(&folder, child, depth + 1)?;
+    }
+    Ok(())
+}
+
+/// Look up an existing subfolder by [`PR_DISPLAY_NAME_W`] in the hierarchy table.
+fn find_subfolder(parent: &Folder, name: &str) -> Result<Folder> {
+    let restriction = Restriction::Property {
+        relop: RelOp::Equal,
+        value: PropValue {
+            tag: PropTag(PR_DISPLAY_NAME_W),
+            value: PropValueData::Unicode(to_pwstr_buffer(name)),
+        },
+    };
+    let rows = parent
+        .open_hierarchy_table(Default::default())?
+        .query_all_rows(&[PropTag(PR_ENTRYID)], Some(&restriction), None)?;
+    let entry_id = rows
+        .into_iter()
+        .find_map(|row| EntryId::try_from(&row.iter().next()?).ok())
+        .ok_or_else(|| Error::from(MAPI_E_NOT_FOUND))?;
+    parent.open_subfolder(&entry_id)
+}
+
+/// Open [`PR_ACL_TABLE`] as an [`IExchangeModifyTable`] and set [`PR_MEMBER_RIGHTS`] for the
+/// Default member, which always has [`PR_MEMBER_ID`] 0.
+fn grant_default_read_only(folder: &Folder) -> Result<()> {
+    let mut unknown = None;
+    unsafe {
+        folder.folder.OpenProperty(
+            PR_ACL_TABLE,
+            &IExchangeModifyTable::IID as *const _ as *mut _,
+            0,
+            MAPI_DEFERRED_ERRORS,
+            &mut unknown,
+        )?;
+    }
+    let acl: IExchangeModifyTable = unknown.ok_or_else(|| Error::from(E_POINTER))?.cast()?;
+
+    // SAFETY: Neither of these values holds a raw pointer.
+    let mut props = unsafe {
+        PropValueBuilder::new()
+            .value(PropValue {
+                tag: PropTag(PR_MEMBER_ID),
+                value: PropValueData::LargeInteger(0),
+            })
+            .value(PropValue {
+                tag: PropTag(PR_MEMBER_RIGHTS),
+                value: PropValueData::Long(rightsReadOnly),
+            })
+    }
+    .build()?;
+    let mut rows = ROWLIST {
+        cEntries: 1,
+        aEntries: [ROWENTRY {
+            ulRowFlags: ROW_MODIFY,
+            cValues: props.len() as u32,
+            rgPropVals: props.as_mut_ptr(),
+        }],
+    };
+    unsafe { acl.ModifyTable(0, &mut rows) }
+}
+
+/// Add any missing [`CATEGORIES`] to the master category list, which is stored as XML in the
+/// `IPM.Configuration.CategoryList` FAI message in the Calendar folder.
+fn register_categories(calendar: &Folder) -> Result<()> {
+    let restriction = Restriction::Property {
+        relop: RelOp::Equal,
+        value: PropValue {
+            tag: PropTag(PR_MESSAGE_CLASS_W),
+            value: PropValueData::Unicode(to_pwstr_buffer(CATEGORY_LIST_CLASS)),
+        },
+    };
+    let rows = calendar
+        .open_contents_table(TableFlags {
+            associated: true,
+            ..Default::default()
+        })?
+        .query_all_rows(&[PropTag(PR_ENTRYID)], Some(&restriction), None)?;
+    let existing = rows
+        .into_iter()
+        .find_map(|row| EntryId::try_from(&row.iter().next()?).ok());
+
+    let (message, mut xml) = match existing {
+        Some(entry_id) => {
+            let message = calendar.open_message(&entry_id)?;
+            let mut xml = String::new();
+            match message.open_property_stream(PropTag(PR_ROAMING_XMLSTREAM), Default::default()) {
+                Ok(mut stream) => {
+                    let mut bytes = Vec::new();
+                    stream.read_to_end(&mut bytes)?;
+                    xml = String::from_utf8_lossy(&bytes).into_owned();
+                }
+                Err(err) if err.code() == MAPI_E_NOT_FOUND => {}
+                Err(err) => return Err(err),
+            }
+            (message, xml)
+        }
+        None => {
+            let mut message = None;
+            unsafe {
+                calendar
+                    .folder
+                    .CreateMessage(ptr::null_mut(), MAPI_ASSOCIATED, &mut message)?;
+            }
+            let message = Message::new(message.ok_or_else(|| Error::from(E_POINTER))?);
+            message.set_props(&[PropValue {
+                tag: PropTag(PR_MESSAGE_CLASS_W),
+                value: PropValueData::Unicode(to_pwstr_buffer(CATEGORY_LIST_CLASS)),
+            }])?;
+            (message, String::new())
+        }
+    };
+
+    if !xml.contains("</categories>") {
+        xml = String::from(
+            r#"<?xml version="1.0"?><categories xmlns="CategoryList.xsd"></categories>"#,
+        );
+    }
+    let mut added = 0;
+    for (name, color) in CATEGORIES {
+        if xml.contains(&format!(r#"name="{name}""#)) {
+            println!("  {name} is already registered");
+            continue;
+        }
+        let guid = GUID::new()?;
+        let category = format!(
+            "<category name=\"{name}\" color=\"{color}\" keyboardShortcut=\"0\" \
+             guid=\"{{{guid:?}}}\" renameOnFirstUse=\"0\"/>"
+        );
+        let end = xml.rfind("</categories>").expect("checked above");
+        xml.insert_str(end, &category);
+        println!("  Registered {name}");
+        added += 1;
+    }
+    if added == 0 {
+        return Ok(());
+    }
+
+    let mut stream = message.open_property_stream(
+        PropTag(PR_ROAMING_XMLSTREAM),
+        OpenPropertyFlags {
+            create: true,
+            modify: true,
+            ..Default::default()
+        },
+    )?;
+    stream.write_all(xml.as_bytes())?;
+    stream.flush()?;
+    message.save_changes(Default::default())
+}
@@ -1,7 +1,7 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
-use core::{ptr, slice};
+use core::slice;
 use outlook_mapi::{sys::*, *};
 use windows_core::*;
 
@@ -26,80 +26,24 @@ fn main() -> Result<()> {
     println!("Success!");
 
     // Now try to list the stores in the default MAPI profile.
-    SizedSPropTagArray! { PropTagArray[2] }
-    let mut prop_tag_array = PropTagArray {
-        aulPropTag: [PR_ENTRYID, PR_DISPLAY_NAME_W],
-        ..Default::default()
-    };
-    SizedSSortOrderSet! { SortOrderSet[1] }
-    let mut sort_order_set: SortOrderSet = SortOrderSet {
-        aSort: [SSortOrder {
-            ulPropTag: PR_DISPLAY_NAME_W,
-            ulOrder: TABLE_SORT_ASCEND,
-        }],
-        ..Default::default()
-    };
-    let mut rows: RowSet = Default::default();
-    unsafe {
-        let stores_table = logon.session.GetMsgStoresTable(0)?;
-        HrQueryAllRows(
-            &stores_table,
-            prop_tag_array.as_mut_ptr(),
-            ptr::null_mut(),
-            sort_order_set.as_mut_ptr(),
-            50,
-            rows.as_mut_ptr(),
-        )?;
-    }
+    let mut stores: Vec<_> = logon.message_stores()?.collect();
+    stores.sort_by(|a, b| a.display_name.cmp(&b.display_name));
 
-    println!("Found {rows} stores", rows = rows.len());
-    for (idx, row) in rows.into_iter().enumerate() {
+    println!("Found {stores} stores", stores = stores.len());
+    for (idx, info) in stores.into_iter().enumerate() {
         // Use 1-based indices for messages.
         let idx = idx + 1;
 
-        assert_eq!(2, row.len());
-        let mut values = row.iter();
-
-        let Some(PropValue {
-            tag: PropTag(PR_ENTRYID),
-            value: PropValueData::Binary(entry_id),
-        }) = values.next()
-        else {
-            eprintln!("Store {idx}: missing entry ID");
-            continue;
-        };
-
-        let Some(PropValue {
-            tag: PropTag(PR_DISPLAY_NAME_W),
-            value: PropValueData::Unicode(display_name),
-        }) = values.next()
-        else {
-            eprintln!("Store {idx}: missing display name");
-            continue;
-        };
-        let display_name = unsafe { PCWSTR::from_raw(display_name.as_ptr()).to_string() }
-            .unwrap_or_else(|err| format!("bad display name: {err}"));
-
         println!(
-            "Store {idx}: {display_name} ({entry_id} byte ID)",
-            entry_id = entry_id.len()
+            "Store {idx}: {display_name} ({entry_id} byte ID, {kind:?}{default})",
+            display_name = info.display_name,
+            entry_id = info.entry_id.len(),
+            kind = info.kind,
+            default = if info.is_default { ", default" } else { "" }
         );
 
+        let store = logon.open_msg_store(&info.entry_id)?;
         unsafe {
-            let mut store = None;
-            logon.session.OpenMsgStore(
-                0,
-                entry_id.len() as u32,
-                entry_id.as_ptr() as *mut _,
-                &<IMsgStore as Interface>::IID as *const _ as *mut _,
-                MAPI_BEST_ACCESS | MAPI_DEFERRED_ERRORS | MDB_NO_DIALOG | MDB_NO_MAIL,
-                &mut store,
-            )?;
-            let Some(store) = store else {
-                eprintln!("OpenMsgStore succeeded but store is None");
-                continue;
-            };
-
             let mut names = [MAPINAMEID {
                 lpguid: &PS_PUBLIC_STRINGS as *const _ as *mut _,
                 ulKind: MNID_STRING,
@@ -107,8 +51,8 @@ fn main() -> Result<()> {
                     lpwstrName: PWSTR(w!("Keywords").0 as *mut _),
                 },
             }];
-            let mut prop_ids: MAPIOutParam<SPropTagArray> = Default::default();
-            store.GetIDsFromNames(
+            let mut prop_ids: MAPIOutParam<SPropTagArray> = MAPIOutParam::new();
+            store.store.GetIDsFromNames(
                 names.len() as u32,
                 &mut ((&mut names) as *mut _),
                 0,
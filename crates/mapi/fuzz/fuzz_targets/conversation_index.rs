@@ -0,0 +1,16 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Decode arbitrary bytes as a `PR_CONVERSATION_INDEX` with [`ConversationIndex::parse`].
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use outlook_mapi::ConversationIndex;
+
+fuzz_target!(|data: &[u8]| {
+    match ConversationIndex::parse(data) {
+        Ok(index) => assert_eq!(22 + index.responses.len() * 5, data.len()),
+        Err(_) => assert!(data.len() < 22 || (data.len() - 22) % 5 != 0),
+    }
+});
@@ -0,0 +1,20 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Copy arbitrary bytes into an [`EntryId`] through a [`PropValue`].
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use outlook_mapi::{sys, EntryId, PropTag, PropValue, PropValueData};
+
+fuzz_target!(|data: &[u8]| {
+    let value = PropValue {
+        tag: PropTag(sys::PR_ENTRYID),
+        value: PropValueData::Binary(data),
+    };
+    let entry_id = EntryId::try_from(&value).expect("binary values always convert");
+    assert_eq!(entry_id.as_bytes(), data);
+    assert_eq!(entry_id.len(), data.len());
+    assert_eq!(entry_id.is_empty(), data.is_empty());
+});
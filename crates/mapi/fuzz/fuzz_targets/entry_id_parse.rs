@@ -0,0 +1,25 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Decode arbitrary bytes as an entry ID header with [`entry_id::parse`], including the wrapped
+//! store entry ID.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use outlook_mapi::entry_id;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(parsed) = entry_id::parse(data) else {
+        assert!(data.len() < 4);
+        return;
+    };
+    let _ = parsed.is_short_term();
+    let _ = parsed.is_one_off();
+    assert!(parsed.data().len() <= data.len());
+    if let Some(Ok(wrapped)) = parsed.wrapped_store() {
+        let _ = wrapped.dll_name();
+        assert!(wrapped.store_entry_id().len() <= data.len());
+        let _ = wrapped.parse_store();
+    }
+});
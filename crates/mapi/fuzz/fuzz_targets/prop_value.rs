@@ -0,0 +1,267 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Synthesize a [`sys::SPropValue`] from arbitrary bytes, convert it to a [`PropValue`], and copy
+//! it back into MAPI allocations with [`PropValueBuilder`].
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use core::{iter, mem};
+use libfuzzer_sys::fuzz_target;
+use outlook_mapi::{sys, PropTag, PropValue, PropValueBuilder};
+use windows::Win32::{Foundation::FILETIME, System::Com::CY};
+use windows_core::*;
+
+/// Property types which [`PropValue`] knows how to convert, including the ones with pointers.
+const KNOWN_TYPES: [u32; 31] = [
+    sys::PT_UNSPECIFIED,
+    sys::PT_NULL,
+    sys::PT_SHORT,
+    sys::PT_LONG,
+    sys::PT_PTR,
+    sys::PT_FLOAT,
+    sys::PT_DOUBLE,
+    sys::PT_BOOLEAN,
+    sys::PT_CURRENCY,
+    sys::PT_APPTIME,
+    sys::PT_SYSTIME,
+    sys::PT_STRING8,
+    sys::PT_BINARY,
+    sys::PT_UNICODE,
+    sys::PT_CLSID,
+    sys::PT_LONGLONG,
+    sys::PT_MV_SHORT,
+    sys::PT_MV_LONG,
+    sys::PT_MV_FLOAT,
+    sys::PT_MV_DOUBLE,
+    sys::PT_MV_CURRENCY,
+    sys::PT_MV_APPTIME,
+    sys::PT_MV_SYSTIME,
+    sys::PT_MV_BINARY,
+    sys::PT_MV_STRING8,
+    sys::PT_MV_UNICODE,
+    sys::PT_MV_CLSID,
+    sys::PT_MV_LONGLONG,
+    sys::PT_ERROR,
+    sys::PT_OBJECT,
+    sys::PT_MV_UNICODE | sys::MV_INSTANCE,
+];
+
+#[derive(Arbitrary, Debug)]
+enum PropType {
+    /// Pick one of the [`KNOWN_TYPES`].
+    Known(u8),
+
+    /// Use any 16-bit property type, which is usually invalid.
+    Raw(u16),
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    prop_id: u16,
+    prop_type: PropType,
+
+    /// Bits for the scalar members of the union.
+    scalar: u64,
+
+    /// Contents of anything the union points to.
+    data: Vec<u8>,
+}
+
+/// Owns everything the synthesized [`sys::SPropValue`] points to. Every buffer is 8-byte
+/// aligned, which is enough for any of the element types.
+#[derive(Default)]
+struct Storage {
+    buffers: Vec<Vec<u64>>,
+    binaries: Vec<sys::SBinary>,
+    pointers: Vec<*mut u8>,
+}
+
+impl Storage {
+    /// Copy `bytes` into an aligned buffer, padded with zeroes to at least `min_len` bytes.
+    fn copy(&mut self, bytes: &[u8], min_len: usize) -> *mut u8 {
+        let len = bytes
+            .len()
+            .max(min_len)
+            .div_ceil(mem::size_of::<u64>())
+            .max(1);
+        let mut buffer = vec![0_u64; len];
+        let ptr = buffer.as_mut_ptr() as *mut u8;
+        unsafe {
+            ptr.copy_from_nonoverlapping(bytes.as_ptr(), bytes.len());
+        }
+        self.buffers.push(buffer);
+        ptr
+    }
+
+    /// Copy `bytes` with a `null` terminator.
+    fn string(&mut self, bytes: &[u8]) -> *mut u8 {
+        let bytes: Vec<_> = bytes.iter().copied().chain(iter::once(0)).collect();
+        self.copy(&bytes, 0)
+    }
+
+    /// Reinterpret pairs of `bytes` as UTF-16 and copy them with a `null` terminator.
+    fn wide_string(&mut self, bytes: &[u8]) -> *mut u16 {
+        let wide: Vec<u8> = bytes
+            .chunks_exact(2)
+            .flatten()
+            .copied()
+            .chain([0, 0])
+            .collect();
+        self.copy(&wide, 0) as *mut _
+    }
+}
+
+/// Fill in the union for `prop_type`, pointing at buffers in `storage` where needed.
+fn synthesize(input: &Input, storage: &mut Storage) -> sys::SPropValue {
+    let prop_type = match input.prop_type {
+        PropType::Known(index) => KNOWN_TYPES[index as usize % KNOWN_TYPES.len()],
+        PropType::Raw(prop_type) => u32::from(prop_type),
+    };
+    let mut value = sys::SPropValue {
+        ulPropTag: (u32::from(input.prop_id) << 16) | prop_type,
+        ..Default::default()
+    };
+    let data = input.data.as_slice();
+
+    // Multi-value arrays of fixed size elements all have the same layout.
+    let fixed_array = |size: usize, storage: &mut Storage| {
+        (
+            u32::try_from(data.len() / size).unwrap_or(u32::MAX),
+            storage.copy(data, 0),
+        )
+    };
+
+    value.Value.li = input.scalar as i64;
+    match prop_type & !sys::MV_INSTANCE {
+        sys::PT_STRING8 => value.Value.lpszA = PSTR(storage.string(data)),
+        sys::PT_UNICODE => value.Value.lpszW = PWSTR(storage.wide_string(data)),
+        sys::PT_BINARY => {
+            value.Value.bin = sys::SBinary {
+                cb: u32::try_from(data.len()).unwrap_or(u32::MAX),
+                lpb: storage.copy(data, 0),
+            }
+        }
+        sys::PT_CLSID => value.Value.lpguid = storage.copy(data, mem::size_of::<GUID>()) as *mut _,
+        sys::PT_MV_SHORT => {
+            let (count, ptr) = fixed_array(mem::size_of::<i16>(), storage);
+            value.Value.MVi = sys::SShortArray {
+                cValues: count,
+                lpi: ptr as *mut _,
+            };
+        }
+        sys::PT_MV_LONG => {
+            let (count, ptr) = fixed_array(mem::size_of::<i32>(), storage);
+            value.Value.MVl = sys::SLongArray {
+                cValues: count,
+                lpl: ptr as *mut _,
+            };
+        }
+        sys::PT_MV_FLOAT => {
+            let (count, ptr) = fixed_array(mem::size_of::<f32>(), storage);
+            value.Value.MVflt = sys::SRealArray {
+                cValues: count,
+                lpflt: ptr as *mut _,
+            };
+        }
+        sys::PT_MV_DOUBLE => {
+            let (count, ptr) = fixed_array(mem::size_of::<f64>(), storage);
+            value.Value.MVdbl = sys::SDoubleArray {
+                cValues: count,
+                lpdbl: ptr as *mut _,
+            };
+        }
+        sys::PT_MV_CURRENCY => {
+            let (count, ptr) = fixed_array(mem::size_of::<CY>(), storage);
+            value.Value.MVcur = sys::SCurrencyArray {
+                cValues: count,
+                lpcur: ptr as *mut _,
+            };
+        }
+        sys::PT_MV_APPTIME => {
+            let (count, ptr) = fixed_array(mem::size_of::<f64>(), storage);
+            value.Value.MVat = sys::SAppTimeArray {
+                cValues: count,
+                lpat: ptr as *mut _,
+            };
+        }
+        sys::PT_MV_SYSTIME => {
+            let (count, ptr) = fixed_array(mem::size_of::<FILETIME>(), storage);
+            value.Value.MVft = sys::SDateTimeArray {
+                cValues: count,
+                lpft: ptr as *mut _,
+            };
+        }
+        sys::PT_MV_CLSID => {
+            let (count, ptr) = fixed_array(mem::size_of::<GUID>(), storage);
+            value.Value.MVguid = sys::SGuidArray {
+                cValues: count,
+                lpguid: ptr as *mut _,
+            };
+        }
+        sys::PT_MV_LONGLONG => {
+            let (count, ptr) = fixed_array(mem::size_of::<i64>(), storage);
+            value.Value.MVli = sys::SLargeIntegerArray {
+                cValues: count,
+                lpli: ptr as *mut _,
+            };
+        }
+        sys::PT_MV_BINARY => {
+            // Use each `null` separated piece of the data as a separate binary value.
+            for piece in data.split(|byte| *byte == 0) {
+                let cb = u32::try_from(piece.len()).unwrap_or(u32::MAX);
+                let lpb = storage.copy(piece, 0);
+                storage.binaries.push(sys::SBinary { cb, lpb });
+            }
+            value.Value.MVbin = sys::SBinaryArray {
+                cValues: u32::try_from(storage.binaries.len()).unwrap_or(u32::MAX),
+                lpbin: storage.binaries.as_mut_ptr(),
+            };
+        }
+        sys::PT_MV_STRING8 => {
+            for piece in data.split(|byte| *byte == 0) {
+                let string = storage.string(piece);
+                storage.pointers.push(string);
+            }
+            value.Value.MVszA = sys::SLPSTRArray {
+                cValues: u32::try_from(storage.pointers.len()).unwrap_or(u32::MAX),
+                lppszA: storage.pointers.as_mut_ptr() as *mut _,
+            };
+        }
+        sys::PT_MV_UNICODE => {
+            for piece in data.split(|byte| *byte == 0) {
+                let string = storage.wide_string(piece);
+                storage.pointers.push(string as *mut _);
+            }
+            value.Value.MVszW = sys::SWStringArray {
+                cValues: u32::try_from(storage.pointers.len()).unwrap_or(u32::MAX),
+                lppszW: storage.pointers.as_mut_ptr() as *mut _,
+            };
+        }
+        _ => {}
+    }
+    value
+}
+
+fuzz_target!(|input: Input| {
+    let mut storage = Storage::default();
+    let raw = synthesize(&input, &mut storage);
+
+    let value = PropValue::from(&raw);
+    assert_eq!(value.tag, PropTag(raw.ulPropTag));
+    let bytes = value.value.as_bytes().map(<[u8]>::to_vec);
+    let _ = value.value.as_filetime();
+
+    let round_trip = sys::SPropValue::from(&value);
+    assert_eq!(round_trip.ulPropTag, raw.ulPropTag);
+
+    // SAFETY: `raw` only points into buffers owned by this function, like a value from MAPI.
+    let Ok(mut owned) = unsafe { PropValueBuilder::new().value(value) }.build() else {
+        return;
+    };
+    assert_eq!(owned.len(), 1);
+    let copied = owned.iter().next().expect("missing value");
+    assert_eq!(copied.tag, PropTag(raw.ulPropTag));
+    assert_eq!(copied.value.as_bytes().map(<[u8]>::to_vec), bytes);
+});
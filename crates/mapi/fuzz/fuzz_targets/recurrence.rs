@@ -0,0 +1,25 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Decode arbitrary bytes as a `PidLidAppointmentRecur` blob with [`Recurrence::parse`], and
+//! expand the first few occurrences of anything which parses.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use outlook_mapi::Recurrence;
+use std::time::{Duration, SystemTime};
+
+/// Maximum number of occurrences to expand from each input.
+const MAX_OCCURRENCES: usize = 100;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(recurrence) = Recurrence::parse(data) else {
+        return;
+    };
+    let start = SystemTime::UNIX_EPOCH;
+    let end = start + Duration::from_secs(60 * 60 * 24 * 366);
+    if let Ok(occurrences) = recurrence.occurrences(start..end) {
+        for _ in occurrences.take(MAX_OCCURRENCES) {}
+    }
+});
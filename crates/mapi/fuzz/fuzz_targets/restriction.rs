@@ -0,0 +1,196 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Build an arbitrary [`Restriction`] tree into a single chain of MAPI allocations.
+
+#![no_main]
+
+use arbitrary::{Arbitrary, Unstructured};
+use libfuzzer_sys::fuzz_target;
+use outlook_mapi::{
+    to_pwstr_buffer, BitmaskRelOp, ContentMatch, FuzzyLevel, PropTag, PropValue, PropValueData,
+    RelOp, Restriction,
+};
+
+/// Limit the depth of the tree so deeply nested inputs don't overflow the stack.
+const MAX_DEPTH: usize = 16;
+
+#[derive(Arbitrary, Debug)]
+enum Value {
+    Long(i32),
+    Boolean(bool),
+    LargeInteger(i64),
+    Binary(Vec<u8>),
+    Unicode(String),
+}
+
+impl Value {
+    fn prop_value(&self, tag: u32) -> PropValue<'_> {
+        let value = match self {
+            Self::Long(value) => PropValueData::Long(*value),
+            Self::Boolean(value) => PropValueData::Boolean(u16::from(*value)),
+            Self::LargeInteger(value) => PropValueData::LargeInteger(*value),
+            Self::Binary(value) => PropValueData::Binary(value),
+            Self::Unicode(value) => PropValueData::Unicode(to_pwstr_buffer(value)),
+        };
+        PropValue {
+            tag: PropTag(tag),
+            value,
+        }
+    }
+}
+
+#[derive(Arbitrary, Debug)]
+enum Node {
+    And(Vec<Node>),
+    Or(Vec<Node>),
+    Not(Box<Node>),
+    Content {
+        content_match: u8,
+        ignore_case: bool,
+        ignore_non_space: bool,
+        loose: bool,
+        tag: u32,
+        value: Value,
+    },
+    Property {
+        relop: u8,
+        tag: u32,
+        value: Value,
+    },
+    CompareProps {
+        relop: u8,
+        left: u32,
+        right: u32,
+    },
+    Bitmask {
+        not_equal_zero: bool,
+        tag: u32,
+        mask: u32,
+    },
+    Size {
+        relop: u8,
+        tag: u32,
+        size: u32,
+    },
+    Exist(u32),
+    SubRestriction {
+        subobject: u32,
+        restriction: Box<Node>,
+    },
+}
+
+fn relop(value: u8) -> RelOp {
+    match value % 7 {
+        0 => RelOp::LessThan,
+        1 => RelOp::LessThanOrEqual,
+        2 => RelOp::GreaterThan,
+        3 => RelOp::GreaterThanOrEqual,
+        4 => RelOp::Equal,
+        5 => RelOp::NotEqual,
+        _ => RelOp::RegularExpression,
+    }
+}
+
+impl Node {
+    /// Convert the arbitrary tree into a [`Restriction`], replacing anything deeper than
+    /// [`MAX_DEPTH`] with an empty [`Restriction::And`].
+    fn restriction(&self, depth: usize) -> Restriction<'_> {
+        if depth >= MAX_DEPTH {
+            return Restriction::And(vec![]);
+        }
+        let depth = depth + 1;
+        match self {
+            Self::And(children) => Restriction::And(
+                children
+                    .iter()
+                    .map(|child| child.restriction(depth))
+                    .collect(),
+            ),
+            Self::Or(children) => Restriction::Or(
+                children
+                    .iter()
+                    .map(|child| child.restriction(depth))
+                    .collect(),
+            ),
+            Self::Not(child) => Restriction::Not(Box::new(child.restriction(depth))),
+            Self::Content {
+                content_match,
+                ignore_case,
+                ignore_non_space,
+                loose,
+                tag,
+                value,
+            } => Restriction::Content {
+                fuzzy_level: FuzzyLevel {
+                    content_match: match content_match % 3 {
+                        0 => ContentMatch::FullString,
+                        1 => ContentMatch::Substring,
+                        _ => ContentMatch::Prefix,
+                    },
+                    ignore_case: *ignore_case,
+                    ignore_non_space: *ignore_non_space,
+                    loose: *loose,
+                },
+                value: value.prop_value(*tag),
+            },
+            Self::Property {
+                relop: op,
+                tag,
+                value,
+            } => Restriction::Property {
+                relop: relop(*op),
+                value: value.prop_value(*tag),
+            },
+            Self::CompareProps {
+                relop: op,
+                left,
+                right,
+            } => Restriction::CompareProps {
+                relop: relop(*op),
+                left: PropTag(*left),
+                right: PropTag(*right),
+            },
+            Self::Bitmask {
+                not_equal_zero,
+                tag,
+                mask,
+            } => Restriction::Bitmask {
+                relop: if *not_equal_zero {
+                    BitmaskRelOp::NotEqualZero
+                } else {
+                    BitmaskRelOp::EqualZero
+                },
+                tag: PropTag(*tag),
+                mask: *mask,
+            },
+            Self::Size {
+                relop: op,
+                tag,
+                size,
+            } => Restriction::Size {
+                relop: relop(*op),
+                tag: PropTag(*tag),
+                size: *size,
+            },
+            Self::Exist(tag) => Restriction::Exist(PropTag(*tag)),
+            Self::SubRestriction {
+                subobject,
+                restriction,
+            } => Restriction::SubRestriction {
+                subobject: PropTag(*subobject),
+                restriction: Box::new(restriction.restriction(depth)),
+            },
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(node) = Node::arbitrary_take_rest(Unstructured::new(data)) else {
+        return;
+    };
+    let restriction = node.restriction(0);
+    if let Ok(mut owned) = restriction.build() {
+        assert!(owned.as_mut_ptr().is_ok_and(|ptr| !ptr.is_null()));
+    }
+});
@@ -0,0 +1,413 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`AddrBook`], [`AddrBookEntry`], [`ResolvedRecipient`], [`AdrList`], and
+//! [`AdrListBuilder`].
+
+use crate::{
+    mapi_error::with_last_error, prop_value::chain_prop_value, sys, to_pwstr_buffer, CbNewADRLIST,
+    EntryId, InitEpoch, MAPIUninit, MapiArena, MarshalToThread, Marshaled, OwnedPropValue, PropTag,
+    PropValue, PropValueData, Table, TableFlags,
+};
+use core::{mem, ptr, slice};
+use windows::Win32::Foundation::*;
+use windows_core::*;
+
+/// Properties of a recipient returned from [`AddrBook::resolve_name`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResolvedRecipient {
+    /// [`sys::PR_DISPLAY_NAME_W`]
+    pub display_name: Option<String>,
+
+    /// [`sys::PR_ENTRYID`]
+    pub entry_id: Option<EntryId>,
+
+    /// [`sys::PR_ADDRTYPE_W`], e.g. `SMTP` or `EX`.
+    pub address_type: Option<String>,
+
+    /// [`sys::PR_EMAIL_ADDRESS_W`]
+    pub email_address: Option<String>,
+
+    /// [`sys::PR_SMTP_ADDRESS_W`], which is not always available.
+    pub smtp_address: Option<String>,
+}
+
+impl ResolvedRecipient {
+    /// Pick out the recipient properties from the [`sys::ADRENTRY::rgPropVals`] of a resolved
+    /// [`sys::ADRENTRY`].
+    pub fn from_props<'a, I>(values: I) -> Self
+    where
+        I: IntoIterator<Item = PropValue<'a>>,
+    {
+        const DISPLAY_NAME: u16 = PropTag(sys::PR_DISPLAY_NAME_W).prop_id();
+        const ENTRYID: u16 = PropTag(sys::PR_ENTRYID).prop_id();
+        const ADDRTYPE: u16 = PropTag(sys::PR_ADDRTYPE_W).prop_id();
+        const EMAIL_ADDRESS: u16 = PropTag(sys::PR_EMAIL_ADDRESS_W).prop_id();
+        const SMTP_ADDRESS: u16 = PropTag(sys::PR_SMTP_ADDRESS_W).prop_id();
+
+        let mut result = Self::default();
+        for PropValue { tag, value } in values {
+            match tag.prop_id() {
+                DISPLAY_NAME => result.display_name = value.as_string(),
+                ENTRYID => result.entry_id = EntryId::try_from(&value).ok(),
+                ADDRTYPE => result.address_type = value.as_string(),
+                EMAIL_ADDRESS => result.email_address = value.as_string(),
+                SMTP_ADDRESS => result.smtp_address = value.as_string(),
+                _ => {}
+            }
+        }
+        result
+    }
+}
+
+/// Object opened with [`AddrBook::open_entry`], depending on the type of entry.
+pub enum AddrBookEntry {
+    /// [`sys::MAPI_MAILUSER`]
+    MailUser(sys::IMailUser),
+
+    /// [`sys::MAPI_DISTLIST`]
+    DistList(sys::IDistList),
+
+    /// [`sys::MAPI_ABCONT`]
+    Container(sys::IABContainer),
+
+    /// Any other object type.
+    Other(u32, IUnknown),
+}
+
+/// Hold on to a [`sys::IAddrBook`] and expose the common address book operations without
+/// `unsafe`.
+pub struct AddrBook {
+    /// Access the [`sys::IAddrBook`].
+    pub addr_book: sys::IAddrBook,
+
+    epoch: InitEpoch,
+}
+
+impl AddrBook {
+    /// Wrap a [`sys::IAddrBook`] returned from one of the [`sys`] interface methods.
+    pub fn new(addr_book: sys::IAddrBook) -> Self {
+        Self {
+            addr_book,
+            epoch: InitEpoch::current(),
+        }
+    }
+
+    /// Call [`sys::IAddrBook::ResolveName`] without any UI to look up a display name or address.
+    ///
+    /// If the name is ambiguous, this will return [`sys::MAPI_E_AMBIGUOUS_RECIP`], and if it does
+    /// not match anything, this will return [`sys::MAPI_E_NOT_FOUND`].
+    pub fn resolve_name(&self, name: &str) -> Result<Vec<ResolvedRecipient>> {
+        self.epoch.check()?;
+        let adr_list = AdrList::new(name)?;
+        unsafe {
+            self.addr_book
+                .ResolveName(0, sys::MAPI_UNICODE, ptr::null_mut(), adr_list.0)
+                .map_err(|error| with_last_error::<sys::IMAPIProp>(&self.addr_book, error))?;
+        }
+        Ok(adr_list
+            .entries()
+            .iter()
+            .map(|entry| {
+                let values = if entry.rgPropVals.is_null() {
+                    &[]
+                } else {
+                    unsafe { slice::from_raw_parts(entry.rgPropVals, entry.cValues as usize) }
+                };
+                ResolvedRecipient::from_props(values.iter().map(PropValue::from))
+            })
+            .collect())
+    }
+
+    /// Call [`sys::IAddrBook::OpenEntry`] with [`sys::MAPI_BEST_ACCESS`] to open an address book
+    /// entry using its [`sys::PR_ENTRYID`], e.g. from a [`ResolvedRecipient`].
+    pub fn open_entry(&self, entry_id: &EntryId) -> Result<AddrBookEntry> {
+        self.epoch.check()?;
+        let (obj_type, unknown) = self.open_unknown(entry_id.len(), entry_id.as_ptr())?;
+        Ok(match obj_type {
+            sys::MAPI_MAILUSER => AddrBookEntry::MailUser(unknown.cast()?),
+            sys::MAPI_DISTLIST => AddrBookEntry::DistList(unknown.cast()?),
+            sys::MAPI_ABCONT => AddrBookEntry::Container(unknown.cast()?),
+            obj_type => AddrBookEntry::Other(obj_type, unknown),
+        })
+    }
+
+    /// Open the root container of the address book and call
+    /// [`sys::IMAPIContainer::GetHierarchyTable`] to list the address book containers, e.g. the
+    /// Global Address List and the Contacts folders.
+    pub fn open_hierarchy_table(&self, flags: TableFlags) -> Result<Table> {
+        self.epoch.check()?;
+        let (_, unknown) = self.open_unknown(0, ptr::null())?;
+        let root: sys::IABContainer = unknown.cast()?;
+        let table = unsafe {
+            root.GetHierarchyTable(flags.into())
+                .map_err(|error| with_last_error::<sys::IMAPIProp>(&root, error))?
+        };
+        Ok(Table::new(table))
+    }
+
+    fn open_unknown(&self, count: usize, entry_id: *const sys::ENTRYID) -> Result<(u32, IUnknown)> {
+        let mut obj_type = 0;
+        let mut unknown = None;
+        unsafe {
+            self.addr_book
+                .OpenEntry(
+                    u32::try_from(count)?,
+                    entry_id as *mut _,
+                    ptr::null_mut(),
+                    sys::MAPI_BEST_ACCESS,
+                    &mut obj_type,
+                    &mut unknown,
+                )
+                .map_err(|error| with_last_error::<sys::IMAPIProp>(&self.addr_book, error))?;
+        }
+        let unknown = unknown.ok_or_else(|| Error::from(E_POINTER))?;
+        Ok((obj_type, unknown))
+    }
+}
+
+impl From<sys::IAddrBook> for AddrBook {
+    fn from(value: sys::IAddrBook) -> Self {
+        Self::new(value)
+    }
+}
+
+impl MarshalToThread for AddrBook {
+    type State = ();
+    type Context = ();
+
+    fn marshal_to_thread(&self) -> Result<Marshaled<Self>> {
+        Marshaled::new(&self.addr_book, ())
+    }
+
+    fn unmarshal(marshaled: Marshaled<Self>, _context: ()) -> Result<Self> {
+        let (addr_book, ()) = marshaled.into_parts()?;
+        Ok(Self::new(addr_book))
+    }
+}
+
+/// [`sys::ADRLIST`] which [`sys::IAddrBook::ResolveName`] or [`sys::IMessage::ModifyRecipients`]
+/// may modify in place. Each [`sys::ADRENTRY::rgPropVals`] is a separate allocation, so the whole
+/// list is freed with [`sys::FreePadrlist`]. Build one of any size with [`AdrListBuilder`], e.g.
+/// for [`sys::IAddrBook::Address`].
+pub struct AdrList(*mut sys::ADRLIST);
+
+impl AdrList {
+    /// Create a list with a single entry for [`sys::IAddrBook::ResolveName`].
+    fn new(name: &str) -> Result<Self> {
+        Self::from_entries(&[vec![PropValue {
+            tag: PropTag(sys::PR_DISPLAY_NAME_W),
+            value: PropValueData::Unicode(to_pwstr_buffer(name)),
+        }]])
+    }
+
+    /// Create a list with an entry for each set of properties.
+    pub(crate) fn from_entries(entries: &[Vec<PropValue>]) -> Result<Self> {
+        let adr_list = MAPIUninit::<u8>::new(CbNewADRLIST(entries.len().max(1)))?;
+        let mut adr_list: MAPIUninit<sys::ADRLIST> = adr_list.into()?;
+        let header = adr_list.uninit()?.as_mut_ptr();
+        let result = Self(header);
+        unsafe {
+            // Fill in the count as we go, so FreePadrlist only sees initialized entries.
+            ptr::addr_of_mut!((*header).cEntries).write(0);
+        }
+        // The ADRLIST is freed with FreePadrlist now.
+        mem::forget(adr_list);
+
+        for (index, values) in entries.iter().enumerate() {
+            let props = MAPIUninit::<sys::SPropValue>::new(values.len().max(1))?;
+            let converted = {
+                let arena = MapiArena::chained(&props);
+                values
+                    .iter()
+                    // SAFETY: The values were copied into MAPI allocations by the builder.
+                    .map(|value| unsafe { chain_prop_value(&arena, value) })
+                    .collect::<Result<Vec<_>>>()?
+            };
+            for (mut prop, value) in props.iter().zip(converted) {
+                prop.uninit()?.write(value);
+            }
+            let mut props = unsafe { props.assume_init() };
+            unsafe {
+                ptr::addr_of_mut!((*header).aEntries)
+                    .cast::<sys::ADRENTRY>()
+                    .add(index)
+                    .write(sys::ADRENTRY {
+                        ulReserved1: 0,
+                        cValues: u32::try_from(values.len())?,
+                        rgPropVals: props.as_mut()?,
+                    });
+                (*header).cEntries = u32::try_from(index + 1)?;
+            }
+
+            // The ADRENTRY owns the property values now.
+            mem::forget(props);
+        }
+        Ok(result)
+    }
+
+    /// Get the [`sys::ADRLIST`] pointer to pass to an API which reads or modifies it.
+    pub fn as_mut_ptr(&mut self) -> *mut sys::ADRLIST {
+        self.0
+    }
+
+    /// Get the number of [`sys::ADRENTRY`] elements in the list.
+    pub fn len(&self) -> usize {
+        self.entries().len()
+    }
+
+    /// Test for a list without any [`sys::ADRENTRY`] elements.
+    pub fn is_empty(&self) -> bool {
+        self.entries().is_empty()
+    }
+
+    fn entries(&self) -> &[sys::ADRENTRY] {
+        unsafe {
+            let adr_list = &*self.0;
+            slice::from_raw_parts(adr_list.aEntries.as_ptr(), adr_list.cEntries as usize)
+        }
+    }
+}
+
+impl Drop for AdrList {
+    fn drop(&mut self) {
+        unsafe {
+            sys::FreePadrlist(self.0);
+        }
+    }
+}
+
+/// Collect entries of [`OwnedPropValue`] from [`crate::PropValueBuilder`] and allocate an
+/// [`AdrList`] of any size for them, instead of declaring a fixed size [`crate::AdrListBuf`].
+#[derive(Default)]
+pub struct AdrListBuilder {
+    entries: Vec<OwnedPropValue>,
+}
+
+impl AdrListBuilder {
+    /// Add an entry.
+    pub fn entry(mut self, entry: OwnedPropValue) -> Self {
+        self.push(entry);
+        self
+    }
+
+    /// Add an entry without consuming the builder, e.g. in a loop.
+    pub fn push(&mut self, entry: OwnedPropValue) {
+        self.entries.push(entry);
+    }
+
+    /// Get the number of entries which have been added.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Test if no entries have been added.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Allocate the [`sys::ADRLIST`] with [`sys::MAPIAllocateBuffer`] and move each entry into it.
+    pub fn build(self) -> Result<AdrList> {
+        // Fail before any entry is moved out of its OwnedPropValue.
+        u32::try_from(self.entries.len())?;
+        let adr_list = MAPIUninit::<u8>::new(CbNewADRLIST(self.entries.len().max(1)))?;
+        let mut adr_list: MAPIUninit<sys::ADRLIST> = adr_list.into()?;
+        let header = adr_list.uninit()?.as_mut_ptr();
+        unsafe {
+            // Fill in the count as we go, so FreePadrlist only sees initialized entries.
+            ptr::addr_of_mut!((*header).cEntries).write(0);
+        }
+        // The ADRLIST is freed with FreePadrlist now.
+        mem::forget(adr_list);
+        let result = AdrList(header);
+
+        for (index, entry) in self.entries.into_iter().enumerate() {
+            let count = u32::try_from(entry.len())?;
+            let (_, props) = entry.into_raw();
+            unsafe {
+                ptr::addr_of_mut!((*header).aEntries)
+                    .cast::<sys::ADRENTRY>()
+                    .add(index)
+                    .write(sys::ADRENTRY {
+                        ulReserved1: 0,
+                        cValues: count,
+                        rgPropVals: props,
+                    });
+                (*header).cEntries = index as u32 + 1;
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapi_ptr::backend;
+
+    #[test]
+    fn resolved_recipient() {
+        let display_name = to_pwstr_buffer("Zoë");
+        let smtp_address = to_pwstr_buffer("zoe@example.com");
+        let entry_id = [0x0, 0x0, 0x0, 0x0, 0x1, 0x2, 0x3];
+        let values = [
+            PropValue {
+                tag: PropTag(sys::PR_DISPLAY_NAME_W),
+                value: PropValueData::Unicode(display_name),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_ENTRYID),
+                value: PropValueData::Binary(&entry_id),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_SMTP_ADDRESS_W),
+                value: PropValueData::Unicode(smtp_address),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_EMAIL_ADDRESS_W),
+                value: PropValueData::Error(sys::MAPI_E_NOT_FOUND),
+            },
+        ];
+
+        assert_eq!(
+            ResolvedRecipient::from_props(values),
+            ResolvedRecipient {
+                display_name: Some(String::from("Zoë")),
+                entry_id: Some(EntryId::from(entry_id.as_slice())),
+                smtp_address: Some(String::from("zoe@example.com")),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn build_adr_list() {
+        let entry = crate::PropValueBuilder::new()
+            .string(PropTag(sys::PR_DISPLAY_NAME_W), "Zoë")
+            .expect("string failed")
+            .build()
+            .expect("build failed");
+        let mut builder = AdrListBuilder::default().entry(entry);
+        assert_eq!(builder.len(), 1);
+        builder.push(
+            crate::PropValueBuilder::new()
+                .build()
+                .expect("build failed"),
+        );
+
+        let mut adr_list = builder.build().expect("build failed");
+        assert_eq!(adr_list.len(), 2);
+        let entries = adr_list.entries();
+        assert_eq!(entries[0].cValues, 1);
+        assert_eq!(entries[1].cValues, 0);
+        let name = PropValue::from(unsafe { &*entries[0].rgPropVals });
+        let PropValueData::Unicode(name) = name.value else {
+            panic!("wrong type");
+        };
+        assert_eq!(String::from_utf16_lossy(&name), "Zoë\0");
+
+        let props: Vec<_> = entries.iter().map(|entry| entry.rgPropVals).collect();
+        let list = adr_list.as_mut_ptr();
+        unsafe { backend::free_list(adr_list, list as *mut _, props) };
+    }
+}
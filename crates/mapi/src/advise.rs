@@ -0,0 +1,744 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`EventMask`], [`Notification`], [`NotificationSink`], [`TableEvent`],
+//! [`TableNotificationSink`], and [`AdviseConnection`].
+
+use crate::{sys, CodePage, EntryId, InitEpoch, PropTag, PropValue, PropValueData, Row, RowRef};
+use core::slice;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+};
+use windows_core::*;
+
+/// Set of event types that can be passed to `Advise`, e.g. [`crate::MsgStore::advise`].
+#[derive(Default)]
+pub struct EventMask {
+    /// Pass [`sys::fnevCriticalError`].
+    pub critical_error: bool,
+
+    /// Pass [`sys::fnevExtended`].
+    pub extended: bool,
+
+    /// Pass [`sys::fnevNewMail`].
+    pub new_mail: bool,
+
+    /// Pass [`sys::fnevObjectCopied`].
+    pub object_copied: bool,
+
+    /// Pass [`sys::fnevObjectCreated`].
+    pub object_created: bool,
+
+    /// Pass [`sys::fnevObjectDeleted`].
+    pub object_deleted: bool,
+
+    /// Pass [`sys::fnevObjectModified`].
+    pub object_modified: bool,
+
+    /// Pass [`sys::fnevObjectMoved`].
+    pub object_moved: bool,
+
+    /// Pass [`sys::fnevSearchComplete`].
+    pub search_complete: bool,
+
+    /// Pass [`sys::fnevStatusObjectModified`].
+    pub status_object_modified: bool,
+
+    /// Pass [`sys::fnevTableModified`].
+    pub table_modified: bool,
+}
+
+impl From<EventMask> for u32 {
+    fn from(value: EventMask) -> Self {
+        let critical_error = if value.critical_error {
+            sys::fnevCriticalError
+        } else {
+            0
+        };
+        let extended = if value.extended { sys::fnevExtended } else { 0 };
+        let new_mail = if value.new_mail { sys::fnevNewMail } else { 0 };
+        let object_copied = if value.object_copied {
+            sys::fnevObjectCopied
+        } else {
+            0
+        };
+        let object_created = if value.object_created {
+            sys::fnevObjectCreated
+        } else {
+            0
+        };
+        let object_deleted = if value.object_deleted {
+            sys::fnevObjectDeleted
+        } else {
+            0
+        };
+        let object_modified = if value.object_modified {
+            sys::fnevObjectModified
+        } else {
+            0
+        };
+        let object_moved = if value.object_moved {
+            sys::fnevObjectMoved
+        } else {
+            0
+        };
+        let search_complete = if value.search_complete {
+            sys::fnevSearchComplete
+        } else {
+            0
+        };
+        let status_object_modified = if value.status_object_modified {
+            sys::fnevStatusObjectModified
+        } else {
+            0
+        };
+        let table_modified = if value.table_modified {
+            sys::fnevTableModified
+        } else {
+            0
+        };
+
+        critical_error
+            | extended
+            | new_mail
+            | object_copied
+            | object_created
+            | object_deleted
+            | object_modified
+            | object_moved
+            | search_complete
+            | status_object_modified
+            | table_modified
+    }
+}
+
+/// Owned copy of a [`sys::OBJECT_NOTIFICATION`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ObjectNotification {
+    /// [`sys::OBJECT_NOTIFICATION::lpEntryID`]
+    pub entry_id: Option<EntryId>,
+
+    /// [`sys::OBJECT_NOTIFICATION::ulObjType`], e.g. [`sys::MAPI_MESSAGE`] or
+    /// [`sys::MAPI_FOLDER`].
+    pub object_type: u32,
+
+    /// [`sys::OBJECT_NOTIFICATION::lpParentID`]
+    pub parent_id: Option<EntryId>,
+
+    /// [`sys::OBJECT_NOTIFICATION::lpOldID`], only for moved or copied objects.
+    pub old_id: Option<EntryId>,
+
+    /// [`sys::OBJECT_NOTIFICATION::lpOldParentID`], only for moved or copied objects.
+    pub old_parent_id: Option<EntryId>,
+
+    /// [`sys::OBJECT_NOTIFICATION::lpPropTagArray`]
+    pub prop_tags: Vec<PropTag>,
+}
+
+/// Owned copy of a [`sys::NOTIFICATION`], which can be passed to a closure and outlive the call
+/// to [`sys::IMAPIAdviseSink::OnNotify`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Notification {
+    /// [`sys::fnevCriticalError`]
+    CriticalError {
+        entry_id: Option<EntryId>,
+        error: HRESULT,
+        flags: u32,
+    },
+
+    /// [`sys::fnevNewMail`]
+    NewMail {
+        entry_id: Option<EntryId>,
+        parent_id: Option<EntryId>,
+        message_class: Option<String>,
+        message_flags: u32,
+    },
+
+    /// [`sys::fnevObjectCreated`]
+    ObjectCreated(ObjectNotification),
+
+    /// [`sys::fnevObjectDeleted`]
+    ObjectDeleted(ObjectNotification),
+
+    /// [`sys::fnevObjectModified`]
+    ObjectModified(ObjectNotification),
+
+    /// [`sys::fnevObjectMoved`]
+    ObjectMoved(ObjectNotification),
+
+    /// [`sys::fnevObjectCopied`]
+    ObjectCopied(ObjectNotification),
+
+    /// [`sys::fnevSearchComplete`]
+    SearchComplete(ObjectNotification),
+
+    /// [`sys::fnevTableModified`]. The row data in [`sys::TABLE_NOTIFICATION`] is not copied,
+    /// re-read the row from the [`crate::Table`] if you need it.
+    TableModified { table_event: u32, error: HRESULT },
+
+    /// [`sys::fnevStatusObjectModified`]
+    StatusObjectModified { entry_id: Option<EntryId> },
+
+    /// [`sys::fnevExtended`]
+    Extended { event: u32, parameters: Vec<u8> },
+
+    /// Any other event type.
+    Unknown(u32),
+}
+
+impl Notification {
+    /// Copy the data from a [`sys::NOTIFICATION`].
+    ///
+    /// # Safety
+    ///
+    /// The `value` must be a valid notification from [`sys::IMAPIAdviseSink::OnNotify`], with
+    /// valid pointers in the member of [`sys::NOTIFICATION::info`] matching
+    /// [`sys::NOTIFICATION::ulEventType`].
+    pub unsafe fn from_sys(value: &sys::NOTIFICATION) -> Self {
+        match value.ulEventType {
+            sys::fnevCriticalError => {
+                let err = &value.info.err;
+                Self::CriticalError {
+                    entry_id: copy_entry_id(err.cbEntryID, err.lpEntryID),
+                    error: HRESULT(err.scode),
+                    flags: err.ulFlags,
+                }
+            }
+            sys::fnevNewMail => {
+                let newmail = &value.info.newmail;
+                let message_class = if newmail.lpszMessageClass.is_null() {
+                    None
+                } else if newmail.ulFlags & sys::MAPI_UNICODE != 0 {
+                    PCWSTR::from_raw(newmail.lpszMessageClass as *const _)
+                        .to_string()
+                        .ok()
+                } else {
+                    CodePage::ACP
+                        .decode_pcstr(PCSTR::from_raw(newmail.lpszMessageClass as *const _))
+                        .ok()
+                };
+                Self::NewMail {
+                    entry_id: copy_entry_id(newmail.cbEntryID, newmail.lpEntryID),
+                    parent_id: copy_entry_id(newmail.cbParentID, newmail.lpParentID),
+                    message_class,
+                    message_flags: newmail.ulMessageFlags,
+                }
+            }
+            sys::fnevObjectCreated => Self::ObjectCreated(copy_object(&value.info.obj)),
+            sys::fnevObjectDeleted => Self::ObjectDeleted(copy_object(&value.info.obj)),
+            sys::fnevObjectModified => Self::ObjectModified(copy_object(&value.info.obj)),
+            sys::fnevObjectMoved => Self::ObjectMoved(copy_object(&value.info.obj)),
+            sys::fnevObjectCopied => Self::ObjectCopied(copy_object(&value.info.obj)),
+            sys::fnevSearchComplete => Self::SearchComplete(copy_object(&value.info.obj)),
+            sys::fnevTableModified => Self::TableModified {
+                table_event: value.info.tab.ulTableEvent,
+                error: value.info.tab.hResult,
+            },
+            sys::fnevStatusObjectModified => {
+                let statobj = &value.info.statobj;
+                Self::StatusObjectModified {
+                    entry_id: copy_entry_id(statobj.cbEntryID, statobj.lpEntryID),
+                }
+            }
+            sys::fnevExtended => {
+                let ext = &value.info.ext;
+                Self::Extended {
+                    event: ext.ulEvent,
+                    parameters: copy_bytes(ext.cb, ext.pbEventParameters).unwrap_or_default(),
+                }
+            }
+            event => Self::Unknown(event),
+        }
+    }
+}
+
+unsafe fn copy_bytes(count: u32, data: *const u8) -> Option<Vec<u8>> {
+    if count == 0 || data.is_null() {
+        None
+    } else {
+        Some(slice::from_raw_parts(data, count as usize).to_vec())
+    }
+}
+
+unsafe fn copy_entry_id(count: u32, entry_id: *const sys::ENTRYID) -> Option<EntryId> {
+    copy_bytes(count, entry_id as *const _).map(EntryId::new)
+}
+
+unsafe fn copy_object(obj: &sys::OBJECT_NOTIFICATION) -> ObjectNotification {
+    let prop_tags = match obj.lpPropTagArray.as_ref() {
+        Some(prop_tags) => {
+            slice::from_raw_parts(prop_tags.aulPropTag.as_ptr(), prop_tags.cValues as usize)
+                .iter()
+                .copied()
+                .map(PropTag)
+                .collect()
+        }
+        None => vec![],
+    };
+    ObjectNotification {
+        entry_id: copy_entry_id(obj.cbEntryID, obj.lpEntryID),
+        object_type: obj.ulObjType,
+        parent_id: copy_entry_id(obj.cbParentID, obj.lpParentID),
+        old_id: copy_entry_id(obj.cbOldID, obj.lpOldID),
+        old_parent_id: copy_entry_id(obj.cbOldParentID, obj.lpOldParentID),
+        prop_tags,
+    }
+}
+
+/// Call a sink's `callback` with `value`, and catch any panic so it does not unwind into MAPI.
+/// Returns `false` if the callback panicked.
+pub(crate) fn call_sink<T>(callback: impl FnOnce(T), value: T) -> bool {
+    panic::catch_unwind(AssertUnwindSafe(|| callback(value))).is_ok()
+}
+
+type Callback = Arc<dyn Fn(Notification) + Send + Sync>;
+
+/// Implementation of [`sys::IMAPIAdviseSink`] which copies each [`sys::NOTIFICATION`] into a
+/// [`Notification`] and passes it to a Rust closure.
+///
+/// MAPI may call [`sys::IMAPIAdviseSink::OnNotify`] on a different thread, or on more than one
+/// thread at a time, so the closure must be [`Send`] and [`Sync`]. It is not called under a
+/// lock, so it may cause another notification without deadlocking. If it panics, the panic is
+/// caught, and the sink keeps calling it for later notifications.
+#[implement(sys::IMAPIAdviseSink)]
+pub struct NotificationSink {
+    callback: Callback,
+}
+
+impl NotificationSink {
+    /// Create a [`sys::IMAPIAdviseSink`] which calls `callback` with each [`Notification`].
+    pub fn create<F>(callback: F) -> sys::IMAPIAdviseSink
+    where
+        F: Fn(Notification) + Send + Sync + 'static,
+    {
+        Self {
+            callback: Arc::new(callback),
+        }
+        .into()
+    }
+}
+
+impl sys::IMAPIAdviseSink_Impl for NotificationSink_Impl {
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn OnNotify(&self, cnotif: u32, lpnotifications: *mut sys::NOTIFICATION) -> u32 {
+        if cnotif == 0 || lpnotifications.is_null() {
+            return 0;
+        }
+        let notifications = unsafe { slice::from_raw_parts(lpnotifications, cnotif as usize) };
+        let callback = Arc::clone(&self.callback);
+        for notification in notifications {
+            call_sink(&*callback, unsafe { Notification::from_sys(notification) });
+        }
+        0
+    }
+}
+
+/// Owned copy of a [`sys::TABLE_NOTIFICATION`] from [`crate::Table::advise`], including the row
+/// data, so a view can apply the change without reading the row again.
+///
+/// Rows are identified by their [`sys::PR_INSTANCE_KEY`], which is what the provider puts in
+/// [`sys::TABLE_NOTIFICATION::propIndex`] and [`sys::TABLE_NOTIFICATION::propPrior`].
+pub enum TableEvent {
+    /// [`sys::TABLE_ROW_ADDED`]. The new row goes after the row with the `prior` instance key, or
+    /// at the beginning of the table if `prior` is [`None`].
+    RowAdded {
+        index: Vec<u8>,
+        prior: Option<Vec<u8>>,
+        row: Row,
+    },
+
+    /// [`sys::TABLE_ROW_MODIFIED`]. The modified row may have moved after the row with the
+    /// `prior` instance key, or to the beginning of the table if `prior` is [`None`].
+    RowModified {
+        index: Vec<u8>,
+        prior: Option<Vec<u8>>,
+        row: Row,
+    },
+
+    /// [`sys::TABLE_ROW_DELETED`]
+    RowDeleted { index: Vec<u8> },
+
+    /// [`sys::TABLE_CHANGED`]. Too much changed to describe row by row, so the view should read
+    /// the table again.
+    Changed,
+
+    /// [`sys::TABLE_RELOAD`]. The provider reloaded the table, so the view should read it again.
+    Reload,
+
+    /// [`sys::TABLE_ERROR`], or the row data could not be copied.
+    Error(HRESULT),
+
+    /// [`sys::TABLE_SORT_DONE`]
+    SortDone,
+
+    /// [`sys::TABLE_RESTRICT_DONE`]
+    RestrictDone,
+
+    /// [`sys::TABLE_SETCOL_DONE`]
+    SetColumnsDone,
+
+    /// Any other table event.
+    Unknown(u32),
+}
+
+impl TableEvent {
+    /// Copy the data from a [`sys::TABLE_NOTIFICATION`].
+    ///
+    /// # Safety
+    ///
+    /// The `value` must be a valid table notification from [`sys::IMAPIAdviseSink::OnNotify`],
+    /// with valid pointers in [`sys::TABLE_NOTIFICATION::propIndex`],
+    /// [`sys::TABLE_NOTIFICATION::propPrior`], and [`sys::TABLE_NOTIFICATION::row`].
+    pub unsafe fn from_sys(value: &sys::TABLE_NOTIFICATION) -> Result<Self> {
+        Ok(match value.ulTableEvent {
+            sys::TABLE_ROW_ADDED => Self::RowAdded {
+                index: instance_key(&value.propIndex).unwrap_or_default(),
+                prior: instance_key(&value.propPrior),
+                row: RowRef::new(&value.row).to_row()?,
+            },
+            sys::TABLE_ROW_MODIFIED => Self::RowModified {
+                index: instance_key(&value.propIndex).unwrap_or_default(),
+                prior: instance_key(&value.propPrior),
+                row: RowRef::new(&value.row).to_row()?,
+            },
+            sys::TABLE_ROW_DELETED => Self::RowDeleted {
+                index: instance_key(&value.propIndex).unwrap_or_default(),
+            },
+            sys::TABLE_CHANGED => Self::Changed,
+            sys::TABLE_RELOAD => Self::Reload,
+            sys::TABLE_ERROR => Self::Error(value.hResult),
+            sys::TABLE_SORT_DONE => Self::SortDone,
+            sys::TABLE_RESTRICT_DONE => Self::RestrictDone,
+            sys::TABLE_SETCOL_DONE => Self::SetColumnsDone,
+            event => Self::Unknown(event),
+        })
+    }
+}
+
+/// Copy the binary [`sys::PR_INSTANCE_KEY`] out of `propIndex` or `propPrior`. The provider sets
+/// `propPrior` to [`sys::PR_NULL`] when the row is first in the table.
+fn instance_key(value: &sys::SPropValue) -> Option<Vec<u8>> {
+    match PropValue::from(value).value {
+        PropValueData::Binary(key) => Some(key.to_vec()),
+        _ => None,
+    }
+}
+
+type TableCallback = Arc<dyn Fn(TableEvent) + Send + Sync>;
+
+/// Implementation of [`sys::IMAPIAdviseSink`] for [`sys::fnevTableModified`] which copies each
+/// [`sys::TABLE_NOTIFICATION`] into a [`TableEvent`] and passes it to a Rust closure. Any other
+/// notifications are ignored.
+///
+/// Like [`NotificationSink`], the closure must be [`Send`] and [`Sync`], it is not called under
+/// a lock, and panics are caught.
+#[implement(sys::IMAPIAdviseSink)]
+pub struct TableNotificationSink {
+    callback: TableCallback,
+}
+
+impl TableNotificationSink {
+    /// Create a [`sys::IMAPIAdviseSink`] which calls `callback` with each [`TableEvent`].
+    pub fn create<F>(callback: F) -> sys::IMAPIAdviseSink
+    where
+        F: Fn(TableEvent) + Send + Sync + 'static,
+    {
+        Self {
+            callback: Arc::new(callback),
+        }
+        .into()
+    }
+}
+
+impl sys::IMAPIAdviseSink_Impl for TableNotificationSink_Impl {
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn OnNotify(&self, cnotif: u32, lpnotifications: *mut sys::NOTIFICATION) -> u32 {
+        if cnotif == 0 || lpnotifications.is_null() {
+            return 0;
+        }
+        let notifications = unsafe { slice::from_raw_parts(lpnotifications, cnotif as usize) };
+        let callback = Arc::clone(&self.callback);
+        for notification in notifications {
+            if notification.ulEventType != sys::fnevTableModified {
+                continue;
+            }
+            let event = unsafe { TableEvent::from_sys(&notification.info.tab) }
+                .unwrap_or_else(|error| TableEvent::Error(error.code()));
+            call_sink(&*callback, event);
+        }
+        0
+    }
+}
+
+enum AdviseSource {
+    MsgStore(sys::IMsgStore),
+    Session(sys::IMAPISession),
+    Table(sys::IMAPITable),
+}
+
+/// Connection returned from `Advise`, e.g. [`crate::MsgStore::advise`],
+/// [`crate::Table::advise`], or [`crate::Logon::on_session_lost`]. The connection is closed
+/// with a call to `Unadvise` when this is dropped.
+pub struct AdviseConnection {
+    source: AdviseSource,
+    connection: usize,
+    epoch: InitEpoch,
+}
+
+impl AdviseConnection {
+    pub(crate) fn msg_store(store: sys::IMsgStore, connection: usize, epoch: InitEpoch) -> Self {
+        Self {
+            source: AdviseSource::MsgStore(store),
+            connection,
+            epoch,
+        }
+    }
+
+    pub(crate) fn session(session: sys::IMAPISession, connection: usize, epoch: InitEpoch) -> Self {
+        Self {
+            source: AdviseSource::Session(session),
+            connection,
+            epoch,
+        }
+    }
+
+    pub(crate) fn table(table: sys::IMAPITable, connection: usize, epoch: InitEpoch) -> Self {
+        Self {
+            source: AdviseSource::Table(table),
+            connection,
+            epoch,
+        }
+    }
+}
+
+impl Drop for AdviseConnection {
+    /// Call `Unadvise` on the object which returned the connection.
+    fn drop(&mut self) {
+        if self.epoch.check().is_err() {
+            return;
+        }
+        unsafe {
+            let _ = match &self.source {
+                AdviseSource::MsgStore(store) => store.Unadvise(self.connection),
+                AdviseSource::Session(session) => session.Unadvise(self.connection),
+                AdviseSource::Table(table) => table.Unadvise(self.connection),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::to_pwstr_buffer;
+
+    #[test]
+    fn object_notification() {
+        let mut entry_id = [0x0, 0x0, 0x0, 0x0, 0x1, 0x2];
+        let mut parent_id = [0x0, 0x0, 0x0, 0x0, 0x3];
+        let mut prop_tags = crate::PropTagArrayBuf::new([sys::PR_SUBJECT_W]);
+        let mut notification = sys::NOTIFICATION {
+            ulEventType: sys::fnevObjectModified,
+            ..Default::default()
+        };
+        notification.info.obj = sys::OBJECT_NOTIFICATION {
+            cbEntryID: entry_id.len() as u32,
+            lpEntryID: entry_id.as_mut_ptr() as *mut _,
+            ulObjType: sys::MAPI_MESSAGE,
+            cbParentID: parent_id.len() as u32,
+            lpParentID: parent_id.as_mut_ptr() as *mut _,
+            lpPropTagArray: prop_tags.as_mut_ptr(),
+            ..Default::default()
+        };
+
+        let Notification::ObjectModified(obj) = (unsafe { Notification::from_sys(&notification) })
+        else {
+            panic!("wrong notification");
+        };
+        assert_eq!(obj.entry_id, Some(EntryId::from(entry_id.as_slice())));
+        assert_eq!(obj.object_type, sys::MAPI_MESSAGE);
+        assert_eq!(obj.parent_id, Some(EntryId::from(parent_id.as_slice())));
+        assert_eq!(obj.old_id, None);
+        assert_eq!(obj.old_parent_id, None);
+        assert_eq!(obj.prop_tags, [PropTag(sys::PR_SUBJECT_W)]);
+    }
+
+    #[test]
+    fn new_mail_notification() {
+        let mut message_class = to_pwstr_buffer("IPM.Note");
+        let mut notification = sys::NOTIFICATION {
+            ulEventType: sys::fnevNewMail,
+            ..Default::default()
+        };
+        notification.info.newmail = sys::NEWMAIL_NOTIFICATION {
+            ulFlags: sys::MAPI_UNICODE,
+            lpszMessageClass: message_class.as_mut_ptr() as *mut _,
+            ulMessageFlags: sys::MSGFLAG_UNSENT,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            unsafe { Notification::from_sys(&notification) },
+            Notification::NewMail {
+                entry_id: None,
+                parent_id: None,
+                message_class: Some(String::from("IPM.Note")),
+                message_flags: sys::MSGFLAG_UNSENT,
+            }
+        );
+    }
+
+    #[test]
+    fn sink_callback() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let sink = NotificationSink::create(move |notification| {
+            sender.send(notification).expect("send failed");
+        });
+        let mut notifications = [
+            sys::NOTIFICATION {
+                ulEventType: sys::fnevReservedForMapi,
+                ..Default::default()
+            },
+            sys::NOTIFICATION {
+                ulEventType: sys::fnevTableModified,
+                ..Default::default()
+            },
+        ];
+        assert_eq!(
+            unsafe { sink.OnNotify(notifications.len() as u32, notifications.as_mut_ptr()) },
+            0
+        );
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            [
+                Notification::Unknown(sys::fnevReservedForMapi),
+                Notification::TableModified {
+                    table_event: 0,
+                    error: HRESULT(0),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn sink_callback_panic() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let sink = NotificationSink::create(move |notification| {
+            if notification == Notification::Unknown(sys::fnevReservedForMapi) {
+                panic!("callback panicked");
+            }
+            sender.send(notification).expect("send failed");
+        });
+        let mut notifications = [
+            sys::NOTIFICATION {
+                ulEventType: sys::fnevReservedForMapi,
+                ..Default::default()
+            },
+            sys::NOTIFICATION {
+                ulEventType: sys::fnevTableModified,
+                ..Default::default()
+            },
+        ];
+        assert_eq!(
+            unsafe { sink.OnNotify(notifications.len() as u32, notifications.as_mut_ptr()) },
+            0
+        );
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            [Notification::TableModified {
+                table_event: 0,
+                error: HRESULT(0),
+            }]
+        );
+    }
+
+    #[test]
+    fn table_sink_callback() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let sink = TableNotificationSink::create(move |event| {
+            sender.send(event).expect("send failed");
+        });
+
+        let mut index = [0x1_u8, 0x2];
+        let mut subject = to_pwstr_buffer("Hello");
+        let mut values = [sys::SPropValue {
+            ulPropTag: sys::PR_SUBJECT_W,
+            Value: sys::__UPV {
+                lpszW: PWSTR(subject.as_mut_ptr()),
+            },
+            ..Default::default()
+        }];
+        let mut added = sys::NOTIFICATION {
+            ulEventType: sys::fnevTableModified,
+            ..Default::default()
+        };
+        added.info.tab = sys::TABLE_NOTIFICATION {
+            ulTableEvent: sys::TABLE_ROW_ADDED,
+            propIndex: sys::SPropValue {
+                ulPropTag: sys::PR_INSTANCE_KEY,
+                Value: sys::__UPV {
+                    bin: sys::SBinary {
+                        cb: index.len() as u32,
+                        lpb: index.as_mut_ptr(),
+                    },
+                },
+                ..Default::default()
+            },
+            propPrior: sys::SPropValue {
+                ulPropTag: sys::PR_NULL,
+                ..Default::default()
+            },
+            row: sys::SRow {
+                cValues: values.len() as u32,
+                lpProps: values.as_mut_ptr(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut notifications = [
+            sys::NOTIFICATION {
+                ulEventType: sys::fnevObjectModified,
+                ..Default::default()
+            },
+            added,
+        ];
+        assert_eq!(
+            unsafe { sink.OnNotify(notifications.len() as u32, notifications.as_mut_ptr()) },
+            0
+        );
+
+        // The source row can go away once the sink returns.
+        subject.fill(0);
+        drop(subject);
+
+        let events: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(events.len(), 1);
+        let TableEvent::RowAdded { index, prior, row } = &events[0] else {
+            panic!("wrong event");
+        };
+        assert_eq!(index.as_slice(), [0x1, 0x2]);
+        assert_eq!(*prior, None);
+        let subject = row
+            .get(PropTag(sys::PR_SUBJECT_W))
+            .map(|value| value.value)
+            .expect("missing subject");
+        assert_eq!(subject.as_string().as_deref(), Some("Hello"));
+        let PropValueData::Unicode(subject) = subject else {
+            panic!("wrong type");
+        };
+        assert_eq!(subject, to_pwstr_buffer("Hello"));
+    }
+
+    #[test]
+    fn unknown_notification() {
+        let notification = sys::NOTIFICATION {
+            ulEventType: sys::fnevReservedForMapi,
+            ..Default::default()
+        };
+        assert_eq!(
+            unsafe { Notification::from_sys(&notification) },
+            Notification::Unknown(sys::fnevReservedForMapi)
+        );
+    }
+}
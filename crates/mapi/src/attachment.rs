@@ -0,0 +1,282 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`Attachment`], [`AttachMethod`], and [`OpenPropertyFlags`].
+//!
+//! Most attachments store their contents in [`sys::PR_ATTACH_DATA_BIN`], but
+//! [`sys::ATTACH_EMBEDDED_MSG`] and [`sys::ATTACH_OLE`] attachments store them in
+//! [`sys::PR_ATTACH_DATA_OBJ`], which must be opened as an object with
+//! [`sys::IMAPIProp::OpenProperty`] and the right interface ID.
+
+use crate::{
+    mapi_error::with_last_error, sys, InitEpoch, MAPIOutParam, MAPIProp, Message, ObjectKind,
+    ObjectRegistration, PropTag, PropValue, PropValueData,
+};
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+use windows::Win32::{
+    Foundation::*,
+    System::Com::{IStream, StructuredStorage::IStorage},
+};
+use windows_core::*;
+
+/// Value of [`sys::PR_ATTACH_METHOD`], which determines how the attachment contents are stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttachMethod {
+    /// [`sys::NO_ATTACHMENT`]
+    None,
+
+    /// [`sys::ATTACH_BY_VALUE`], the contents are in [`sys::PR_ATTACH_DATA_BIN`].
+    ByValue,
+
+    /// [`sys::ATTACH_BY_REFERENCE`]
+    ByReference,
+
+    /// [`sys::ATTACH_BY_REF_RESOLVE`]
+    ByRefResolve,
+
+    /// [`sys::ATTACH_BY_REF_ONLY`]
+    ByRefOnly,
+
+    /// [`sys::ATTACH_EMBEDDED_MSG`], the contents are in [`sys::PR_ATTACH_DATA_OBJ`] as a
+    /// [`sys::IMessage`].
+    EmbeddedMessage,
+
+    /// [`sys::ATTACH_OLE`], the contents are in [`sys::PR_ATTACH_DATA_OBJ`] as an [`IStorage`] or,
+    /// for OLE 1.0 objects, an [`IStream`].
+    Ole,
+
+    /// [`sys::ATTACH_BY_WEBREFERENCE`]
+    ByWebReference,
+
+    /// Any other value.
+    Unknown(u32),
+}
+
+impl From<u32> for AttachMethod {
+    fn from(value: u32) -> Self {
+        match value {
+            sys::NO_ATTACHMENT => Self::None,
+            sys::ATTACH_BY_VALUE => Self::ByValue,
+            sys::ATTACH_BY_REFERENCE => Self::ByReference,
+            sys::ATTACH_BY_REF_RESOLVE => Self::ByRefResolve,
+            sys::ATTACH_BY_REF_ONLY => Self::ByRefOnly,
+            sys::ATTACH_EMBEDDED_MSG => Self::EmbeddedMessage,
+            sys::ATTACH_OLE => Self::Ole,
+            sys::ATTACH_BY_WEBREFERENCE => Self::ByWebReference,
+            value => Self::Unknown(value),
+        }
+    }
+}
+
+impl From<AttachMethod> for u32 {
+    fn from(value: AttachMethod) -> Self {
+        match value {
+            AttachMethod::None => sys::NO_ATTACHMENT,
+            AttachMethod::ByValue => sys::ATTACH_BY_VALUE,
+            AttachMethod::ByReference => sys::ATTACH_BY_REFERENCE,
+            AttachMethod::ByRefResolve => sys::ATTACH_BY_REF_RESOLVE,
+            AttachMethod::ByRefOnly => sys::ATTACH_BY_REF_ONLY,
+            AttachMethod::EmbeddedMessage => sys::ATTACH_EMBEDDED_MSG,
+            AttachMethod::Ole => sys::ATTACH_OLE,
+            AttachMethod::ByWebReference => sys::ATTACH_BY_WEBREFERENCE,
+            AttachMethod::Unknown(value) => value,
+        }
+    }
+}
+
+/// Set of flags that can be passed to [`sys::IMAPIProp::OpenProperty`].
+#[derive(Default)]
+pub struct OpenPropertyFlags {
+    /// Pass [`sys::MAPI_CREATE`].
+    pub create: bool,
+
+    /// Pass [`sys::MAPI_DEFERRED_ERRORS`].
+    pub deferred_errors: bool,
+
+    /// Pass [`sys::MAPI_MODIFY`].
+    pub modify: bool,
+}
+
+impl From<OpenPropertyFlags> for u32 {
+    fn from(value: OpenPropertyFlags) -> Self {
+        let create = if value.create { sys::MAPI_CREATE } else { 0 };
+        let deferred_errors = if value.deferred_errors {
+            sys::MAPI_DEFERRED_ERRORS
+        } else {
+            0
+        };
+        let modify = if value.modify { sys::MAPI_MODIFY } else { 0 };
+
+        create | deferred_errors | modify
+    }
+}
+
+/// Hold on to a [`sys::IAttach`] and expose the attachment contents without `unsafe`.
+pub struct Attachment {
+    /// Access the [`sys::IAttach`].
+    pub attach: sys::IAttach,
+
+    epoch: InitEpoch,
+    registration: ObjectRegistration,
+}
+
+impl Attachment {
+    /// Wrap a [`sys::IAttach`] returned from one of the [`sys`] interface methods.
+    pub fn new(attach: sys::IAttach) -> Self {
+        Self::with_registration(attach, ObjectRegistration::new(ObjectKind::Attachment))
+    }
+
+    /// Wrap a [`sys::IAttach`] opened through another wrapper, so it belongs to the same store.
+    pub(crate) fn with_registration(
+        attach: sys::IAttach,
+        registration: ObjectRegistration,
+    ) -> Self {
+        Self {
+            attach,
+            epoch: InitEpoch::current(),
+            registration,
+        }
+    }
+
+    /// Make sure MAPI is still initialized and the store is still connected.
+    fn check(&self) -> Result<()> {
+        self.epoch.check()?;
+        Ok(self.registration.check_connected()?)
+    }
+
+    /// Get the [`AttachMethod`] from [`sys::PR_ATTACH_METHOD`].
+    pub fn attach_method(&self) -> Result<AttachMethod> {
+        self.check()?;
+        let mut prop: MAPIOutParam<sys::SPropValue> = MAPIOutParam::new();
+        unsafe {
+            sys::HrGetOneProp(&*self.attach, sys::PR_ATTACH_METHOD, prop.as_mut_ptr())
+                .map_err(|error| with_last_error(self.mapi_prop(), error))?;
+            let prop = prop.as_mut().ok_or_else(|| Error::from(E_POINTER))?;
+            match PropValue::from(&*prop).value {
+                PropValueData::Long(value) => Ok(AttachMethod::from(value as u32)),
+                PropValueData::Error(err) => Err(Error::from(err)),
+                _ => Err(Error::from(sys::MAPI_E_BAD_VALUE)),
+            }
+        }
+    }
+
+    /// Open [`sys::PR_ATTACH_DATA_OBJ`] on an [`AttachMethod::EmbeddedMessage`] attachment as a
+    /// [`Message`]. To write a new embedded message, pass [`OpenPropertyFlags::create`] and
+    /// [`OpenPropertyFlags::modify`], then save the embedded message before saving the attachment.
+    pub fn open_embedded_message(&self, flags: OpenPropertyFlags) -> Result<Message> {
+        let message: sys::IMessage = self.open_data_object(flags)?;
+        Ok(Message::with_registration(
+            message,
+            self.registration.child(ObjectKind::Message),
+        ))
+    }
+
+    /// Open [`sys::PR_ATTACH_DATA_OBJ`] on an [`AttachMethod::Ole`] attachment as an [`IStorage`].
+    /// This works for OLE 2.0 objects, and the storage can be copied with
+    /// [`IStorage::CopyTo`] to export the attachment with full fidelity.
+    pub fn open_ole_storage(&self, flags: OpenPropertyFlags) -> Result<IStorage> {
+        self.open_data_object(flags)
+    }
+
+    /// Open [`sys::PR_ATTACH_DATA_OBJ`] on an [`AttachMethod::Ole`] attachment as an [`IStream`].
+    /// This is how OLE 1.0 objects, which have [`sys::PR_ATTACH_TAG`] set to `OID_OLE1`, are
+    /// stored. It may also be used with an OLE 2.0 object if the store supports it, e.g. to pass
+    /// to [`sys::HrIStorageFromStream`].
+    pub fn open_ole_stream(&self, flags: OpenPropertyFlags) -> Result<IStream> {
+        self.open_data_object(flags)
+    }
+
+    /// Copy the contents of [`sys::PR_ATTACH_DATA_BIN`] to a new file at `path` in chunks, without
+    /// reading the whole attachment into memory. Returns the number of bytes written.
+    pub fn save_to_file<P>(&self, path: P) -> Result<u64>
+    where
+        P: AsRef<Path>,
+    {
+        let mut stream =
+            self.open_property_stream(PropTag(sys::PR_ATTACH_DATA_BIN), Default::default())?;
+        let mut file = File::create(path)?;
+        let total = io::copy(&mut stream, &mut file)?;
+        file.flush()?;
+        Ok(total)
+    }
+
+    fn open_data_object<T>(&self, flags: OpenPropertyFlags) -> Result<T>
+    where
+        T: Interface,
+    {
+        self.check()?;
+        let mut unknown = None;
+        unsafe {
+            self.attach
+                .OpenProperty(
+                    sys::PR_ATTACH_DATA_OBJ,
+                    &T::IID as *const _ as *mut _,
+                    0,
+                    flags.into(),
+                    &mut unknown,
+                )
+                .map_err(|error| with_last_error(self.mapi_prop(), error))?;
+        }
+        let unknown = unknown.ok_or_else(|| Error::from(E_POINTER))?;
+        unknown.cast()
+    }
+}
+
+impl MAPIProp for Attachment {
+    fn mapi_prop(&self) -> &sys::IMAPIProp {
+        &self.attach
+    }
+
+    fn interface_id(&self) -> GUID {
+        sys::IAttach::IID
+    }
+
+    fn init_epoch(&self) -> InitEpoch {
+        self.epoch
+    }
+
+    fn registration(&self) -> Option<&ObjectRegistration> {
+        Some(&self.registration)
+    }
+}
+
+impl From<sys::IAttach> for Attachment {
+    fn from(value: sys::IAttach) -> Self {
+        Self::new(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attach_method_round_trip() {
+        for value in 0..=8 {
+            assert_eq!(u32::from(AttachMethod::from(value)), value);
+        }
+        assert_eq!(
+            AttachMethod::from(sys::ATTACH_EMBEDDED_MSG),
+            AttachMethod::EmbeddedMessage
+        );
+        assert_eq!(AttachMethod::from(sys::ATTACH_OLE), AttachMethod::Ole);
+        assert_eq!(AttachMethod::from(8), AttachMethod::Unknown(8));
+    }
+
+    #[test]
+    fn open_property_flags() {
+        assert_eq!(u32::from(OpenPropertyFlags::default()), 0);
+        assert_eq!(
+            u32::from(OpenPropertyFlags {
+                create: true,
+                modify: true,
+                ..Default::default()
+            }),
+            sys::MAPI_CREATE | sys::MAPI_MODIFY
+        );
+    }
+}
@@ -0,0 +1,576 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`Banner`], [`BannerPosition`], and [`BodyFormat`].
+//!
+//! Adding a disclaimer or an external sender warning to a message looks like a simple
+//! [`MAPIProp::set_props`] on the body, but a message has up to three copies of the body, and the
+//! store only regenerates the others when they are written the right way. [`Banner::insert`] edits
+//! the native body in place, without decoding and re-encoding it, and then brings the other
+//! formats back in sync. The attachments are not touched, so inline images keep working.
+
+use crate::{rtf, sys, MAPIProp, Message, OpenPropertyFlags, PropTag, PropValue, PropValueData};
+use core::fmt::Write as _;
+use std::io::{Read, Write};
+use windows_core::*;
+
+/// `PidTagNativeBody` values, which are missing from [`sys`].
+const NATIVE_BODY_PLAIN_TEXT: i32 = 1;
+const NATIVE_BODY_RTF: i32 = 2;
+const NATIVE_BODY_HTML: i32 = 3;
+
+/// UTF-16 code pages, which would need the HTML to be decoded before it can be edited.
+const CP_UTF16_LE: i32 = 1200;
+const CP_UTF16_BE: i32 = 1201;
+
+/// RTF control words which are part of the document header, before the first paragraph.
+const RTF_HEADER_WORDS: &[&[u8]] = &[
+    b"rtf",
+    b"ansi",
+    b"mac",
+    b"pc",
+    b"pca",
+    b"ansicpg",
+    b"deff",
+    b"adeff",
+    b"deflang",
+    b"deflangfe",
+    b"adeflang",
+    b"stshfdbch",
+    b"stshfloch",
+    b"stshfhich",
+    b"stshfbi",
+    b"fbidis",
+    b"fromtext",
+    b"fromhtml",
+    b"htmautsp",
+    b"uc",
+    b"viewkind",
+    b"viewscale",
+];
+
+/// Where [`Banner::insert`] should put the banner in the body.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BannerPosition {
+    /// Before the existing content, e.g. an external sender warning.
+    #[default]
+    Top,
+
+    /// After the existing content, e.g. a disclaimer or signature.
+    Bottom,
+}
+
+/// Native body format of a message, which is the one [`Banner::insert`] edits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BodyFormat {
+    /// [`sys::PR_BODY_W`]
+    PlainText,
+
+    /// [`sys::PR_RTF_COMPRESSED`]
+    Rtf,
+
+    /// [`sys::PR_HTML`]
+    Html,
+}
+
+impl BodyFormat {
+    /// Properties needed by [`BodyFormat::from_props`].
+    pub const PROPS: [PropTag; 2] = [
+        PropTag(sys::PR_NATIVE_BODY_INFO),
+        PropTag(sys::PR_MSG_EDITOR_FORMAT),
+    ];
+
+    /// Use `PidTagNativeBody` from [`sys::PR_NATIVE_BODY_INFO`] to pick the native body format.
+    /// If it is missing, fall back to the [`sys::PR_MSG_EDITOR_FORMAT`], and then to
+    /// [`BodyFormat::PlainText`], which every store can convert to the other formats.
+    pub fn from_props<'a, I>(values: I) -> Self
+    where
+        I: IntoIterator<Item = PropValue<'a>>,
+    {
+        let mut native_body = None;
+        let mut editor_format = None;
+        for PropValue { tag, value } in values {
+            match (tag.0, value) {
+                (sys::PR_NATIVE_BODY_INFO, PropValueData::Long(value)) => native_body = Some(value),
+                (sys::PR_MSG_EDITOR_FORMAT, PropValueData::Long(value)) => {
+                    editor_format = Some(value as u32)
+                }
+                _ => {}
+            }
+        }
+
+        match (native_body, editor_format) {
+            (Some(NATIVE_BODY_PLAIN_TEXT), _) => Self::PlainText,
+            (Some(NATIVE_BODY_RTF), _) => Self::Rtf,
+            (Some(NATIVE_BODY_HTML), _) => Self::Html,
+            (_, Some(sys::EDITOR_FORMAT_RTF)) => Self::Rtf,
+            (_, Some(sys::EDITOR_FORMAT_HTML)) => Self::Html,
+            _ => Self::PlainText,
+        }
+    }
+}
+
+/// Text to insert in the body of a message with [`Banner::insert`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Banner<'a> {
+    /// Plain text version of the banner, which is also used to generate the HTML and RTF if they
+    /// are [`None`]. Line breaks are preserved.
+    pub text: &'a str,
+
+    /// HTML fragment to insert in the `<body>` element. Characters outside of ASCII are replaced
+    /// with numeric character references, so they survive in any code page.
+    pub html: Option<&'a str>,
+
+    /// RTF fragment to insert in the document. It should be a complete group, e.g.
+    /// `{\pard\b Warning\b0\par}`. Characters outside of ASCII are replaced with `\uN?` control
+    /// words.
+    pub rtf: Option<&'a str>,
+}
+
+impl<'a> Banner<'a> {
+    /// Create a [`Banner`] with only the plain [`Banner::text`].
+    pub fn new(text: &'a str) -> Self {
+        Self {
+            text,
+            ..Default::default()
+        }
+    }
+
+    /// Insert the banner in the native [`BodyFormat`] of the message.
+    ///
+    /// - [`BodyFormat::Html`]: The banner is inserted in the bytes of [`sys::PR_HTML`], so the
+    ///   encoding in [`sys::PR_INTERNET_CPID`] still applies, and the store regenerates the other
+    ///   formats. UTF-16 HTML is rejected with [`sys::MAPI_E_BAD_CHARWIDTH`].
+    /// - [`BodyFormat::Rtf`]: The banner is inserted in the decompressed RTF, and written back
+    ///   with [`Message::set_rtf_body`]. If the RTF encapsulates HTML, the HTML is edited instead.
+    /// - [`BodyFormat::PlainText`]: The banner is inserted in [`sys::PR_BODY_W`], and
+    ///   [`sys::RTFSync`] updates the RTF.
+    ///
+    /// Call [`MAPIProp::save_changes`] on the message to keep the changes.
+    pub fn insert(&self, message: &Message, position: BannerPosition) -> Result<BodyFormat> {
+        let format = BodyFormat::from_props(message.get_props(&BodyFormat::PROPS)?.iter());
+        match format {
+            BodyFormat::Html => self.insert_in_html_body(message, position)?,
+            BodyFormat::Rtf => {
+                let mut body = Vec::new();
+                message.open_rtf_body()?.read_to_end(&mut body)?;
+                if rtf::is_encapsulated_html(&body) {
+                    self.insert_in_html_body(message, position)?;
+                    return Ok(BodyFormat::Html);
+                }
+                message.set_rtf_body(&self.insert_rtf(&body, position))?;
+            }
+            BodyFormat::PlainText => {
+                let body = read_property(message, sys::PR_BODY_W)?;
+                let body: Vec<_> = body
+                    .chunks_exact(2)
+                    .map(|ch| u16::from_le_bytes([ch[0], ch[1]]))
+                    .collect();
+                let body =
+                    String::from_utf16(&body).map_err(|_| Error::from(sys::MAPI_E_CORRUPT_DATA))?;
+                let body = self.insert_text(body.trim_end_matches('\0'), position);
+                let body: Vec<_> = body.encode_utf16().flat_map(u16::to_le_bytes).collect();
+                write_property(message, sys::PR_BODY_W, &body)?;
+
+                message.delete_stale_bodies(&[PropTag(sys::PR_HTML)])?;
+                // The plain text was just written, so it does not matter if the RTF changed.
+                let _updated =
+                    unsafe { sys::RTFSync(&message.message, sys::RTF_SYNC_BODY_CHANGED)? };
+            }
+        }
+        Ok(format)
+    }
+
+    /// Insert the banner in a plain text body, separated from the existing text by a line break.
+    pub fn insert_text(&self, body: &str, position: BannerPosition) -> String {
+        match position {
+            BannerPosition::Top => format!("{}\r\n\r\n{body}", self.text),
+            BannerPosition::Bottom => format!("{body}\r\n\r\n{}", self.text),
+        }
+    }
+
+    /// Insert the banner in an HTML body, right after the `<body>` start tag or right before the
+    /// `</body>` end tag. The rest of the bytes are copied as-is, so this works for any code page
+    /// which is a superset of ASCII.
+    pub fn insert_html(&self, html: &[u8], position: BannerPosition) -> Vec<u8> {
+        let fragment = self.html_fragment();
+        let offset = match position {
+            BannerPosition::Top => html_body_start(html).unwrap_or_default(),
+            BannerPosition::Bottom => rfind_ignore_case(html, b"</body")
+                .or_else(|| rfind_ignore_case(html, b"</html"))
+                .unwrap_or(html.len()),
+        };
+        splice(html, offset, fragment.as_bytes())
+    }
+
+    /// Insert the banner in an RTF body, after the document header at the top, or before the
+    /// closing brace at the bottom.
+    pub fn insert_rtf(&self, rtf: &[u8], position: BannerPosition) -> Vec<u8> {
+        let mut fragment = self.rtf_fragment();
+        let offset = match position {
+            BannerPosition::Top => rtf_body_start(rtf),
+            BannerPosition::Bottom => {
+                // Make sure the banner does not run into the last paragraph.
+                fragment.insert_str(0, r"\par ");
+                rtf.iter().rposition(|ch| *ch == b'}')
+            }
+        };
+        splice(rtf, offset.unwrap_or(rtf.len()), fragment.as_bytes())
+    }
+
+    /// Get the [`Banner::html`], or generate it from the [`Banner::text`], as ASCII.
+    fn html_fragment(&self) -> String {
+        let mut fragment = String::new();
+        match self.html {
+            Some(html) => {
+                for ch in html.chars() {
+                    push_html_char(&mut fragment, ch);
+                }
+            }
+            None => {
+                fragment.push_str("<p>");
+                fragment.push_str(&escape_html(self.text));
+                fragment.push_str("</p>");
+            }
+        }
+        fragment
+    }
+
+    /// Get the [`Banner::rtf`], or generate it from the [`Banner::text`], as ASCII.
+    fn rtf_fragment(&self) -> String {
+        let mut fragment = String::new();
+        match self.rtf {
+            Some(rtf) => {
+                for ch in rtf.chars() {
+                    push_rtf_char(&mut fragment, ch);
+                }
+            }
+            None => {
+                fragment.push_str(r"{\pard\plain\uc1 ");
+                fragment.push_str(&escape_rtf(self.text));
+                fragment.push_str(r"\par}");
+            }
+        }
+        fragment
+    }
+
+    fn insert_in_html_body(&self, message: &Message, position: BannerPosition) -> Result<()> {
+        let props = message.get_props(&[PropTag(sys::PR_INTERNET_CPID)])?;
+        if props.iter().any(|value| {
+            matches!(
+                value.value,
+                PropValueData::Long(CP_UTF16_LE) | PropValueData::Long(CP_UTF16_BE)
+            )
+        }) {
+            return Err(Error::from(sys::MAPI_E_BAD_CHARWIDTH));
+        }
+
+        let html = read_property(message, sys::PR_HTML)?;
+        message.write_html_body(&self.insert_html(&html, position))
+    }
+}
+
+/// Escape plain text for HTML, as ASCII with numeric character references, so it can be inserted
+/// in HTML in any code page which is a superset of ASCII.
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::new();
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\r' => {}
+            '\n' => escaped.push_str("<br>"),
+            ch => push_html_char(&mut escaped, ch),
+        }
+    }
+    escaped
+}
+
+/// Escape plain text for RTF, as ASCII with `\uN?` control words. The `\uc1` control word must be
+/// in effect where it is inserted, which is the default.
+pub(crate) fn escape_rtf(text: &str) -> String {
+    let mut escaped = String::new();
+    for ch in text.chars() {
+        match ch {
+            '\\' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(ch);
+            }
+            '\r' => {}
+            '\n' => escaped.push_str(r"\line "),
+            '\t' => escaped.push_str(r"\tab "),
+            ch => push_rtf_char(&mut escaped, ch),
+        }
+    }
+    escaped
+}
+
+fn push_html_char(fragment: &mut String, ch: char) {
+    if ch.is_ascii() {
+        fragment.push(ch);
+    } else {
+        let _ = write!(fragment, "&#{};", u32::from(ch));
+    }
+}
+
+fn push_rtf_char(fragment: &mut String, ch: char) {
+    if ch.is_ascii() {
+        fragment.push(ch);
+    } else {
+        let mut buffer = [0; 2];
+        for unit in ch.encode_utf16(&mut buffer) {
+            // RTF uses signed 16-bit values for `\uN`.
+            let _ = write!(fragment, r"\u{}?", *unit as i16);
+        }
+    }
+}
+
+fn splice(body: &[u8], offset: usize, fragment: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(body.len() + fragment.len());
+    result.extend_from_slice(&body[..offset]);
+    result.extend_from_slice(fragment);
+    result.extend_from_slice(&body[offset..]);
+    result
+}
+
+pub(crate) fn read_property(message: &Message, tag: u32) -> Result<Vec<u8>> {
+    let mut stream = message.open_property_stream(PropTag(tag), Default::default())?;
+    let mut value = Vec::new();
+    stream.read_to_end(&mut value)?;
+    Ok(value)
+}
+
+pub(crate) fn write_property(message: &Message, tag: u32, value: &[u8]) -> Result<()> {
+    let mut stream = message.open_property_stream(
+        PropTag(tag),
+        OpenPropertyFlags {
+            create: true,
+            modify: true,
+            ..Default::default()
+        },
+    )?;
+    stream.write_all(value)?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn find_ignore_case(haystack: &[u8], needle: &[u8], start: usize) -> Option<usize> {
+    haystack
+        .get(start..)?
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle))
+        .map(|offset| start + offset)
+}
+
+fn rfind_ignore_case(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .rposition(|window| window.eq_ignore_ascii_case(needle))
+}
+
+/// Find the end of the `<body>` start tag, skipping `>` in quoted attribute values.
+fn html_body_start(html: &[u8]) -> Option<usize> {
+    const BODY: &[u8] = b"<body";
+
+    let mut start = 0;
+    let tag = loop {
+        let tag = find_ignore_case(html, BODY, start)?;
+        match html.get(tag + BODY.len()) {
+            Some(ch) if ch.is_ascii_whitespace() || *ch == b'>' || *ch == b'/' => break tag,
+            _ => start = tag + BODY.len(),
+        }
+    };
+
+    let mut quote = None;
+    for (offset, ch) in html[tag..].iter().enumerate() {
+        match (quote, *ch) {
+            (None, b'"' | b'\'') => quote = Some(*ch),
+            (Some(open), ch) if ch == open => quote = None,
+            (None, b'>') => return Some(tag + offset + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Find the first thing after the RTF document header, skipping the header control words and
+/// destination groups like `{\fonttbl ...}`.
+fn rtf_body_start(rtf: &[u8]) -> Option<usize> {
+    let mut offset = rtf.iter().position(|ch| *ch == b'{')? + 1;
+    loop {
+        match *rtf.get(offset)? {
+            b'\r' | b'\n' => offset += 1,
+            b'{' => offset = skip_rtf_group(rtf, offset)?,
+            b'\\' => {
+                let name_len = rtf[offset + 1..]
+                    .iter()
+                    .take_while(|ch| ch.is_ascii_alphabetic())
+                    .count();
+                let name = &rtf[offset + 1..offset + 1 + name_len];
+                if !RTF_HEADER_WORDS.contains(&name) {
+                    return Some(offset);
+                }
+                offset += 1 + name_len;
+                if rtf.get(offset) == Some(&b'-') {
+                    offset += 1;
+                }
+                offset += rtf[offset..]
+                    .iter()
+                    .take_while(|ch| ch.is_ascii_digit())
+                    .count();
+                if rtf.get(offset) == Some(&b' ') {
+                    offset += 1;
+                }
+            }
+            _ => return Some(offset),
+        }
+    }
+}
+
+/// Return the offset after the group which starts at `start`.
+fn skip_rtf_group(rtf: &[u8], start: usize) -> Option<usize> {
+    let mut depth = 0_usize;
+    let mut offset = start;
+    while let Some(ch) = rtf.get(offset) {
+        match ch {
+            b'\\' => offset += 1,
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(offset + 1);
+                }
+            }
+            _ => {}
+        }
+        offset += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn body_format() {
+        let native_body = || PropValue {
+            tag: PropTag(sys::PR_NATIVE_BODY_INFO),
+            value: PropValueData::Long(NATIVE_BODY_RTF),
+        };
+        let editor_format = || PropValue {
+            tag: PropTag(sys::PR_MSG_EDITOR_FORMAT),
+            value: PropValueData::Long(sys::EDITOR_FORMAT_HTML as i32),
+        };
+        assert_eq!(
+            BodyFormat::from_props([native_body(), editor_format()]),
+            BodyFormat::Rtf
+        );
+        assert_eq!(BodyFormat::from_props([editor_format()]), BodyFormat::Html);
+        assert_eq!(BodyFormat::from_props([]), BodyFormat::PlainText);
+    }
+
+    #[test]
+    fn text_banner() {
+        let banner = Banner::new("External sender");
+        assert_eq!(
+            banner.insert_text("Hello", BannerPosition::Top),
+            "External sender\r\n\r\nHello"
+        );
+        assert_eq!(
+            banner.insert_text("Hello", BannerPosition::Bottom),
+            "Hello\r\n\r\nExternal sender"
+        );
+    }
+
+    #[test]
+    fn html_fragment() {
+        let banner = Banner::new("Don't click <links>\r\nfrom caf\u{e9} & co");
+        assert_eq!(
+            banner.html_fragment(),
+            "<p>Don't click &lt;links&gt;<br>from caf&#233; &amp; co</p>"
+        );
+    }
+
+    #[test]
+    fn html_top() {
+        let banner = Banner {
+            html: Some("<b>!</b>"),
+            ..Banner::new("!")
+        };
+        let html = b"<html><BODY class=\"a>b\"><p>Hi</p></body></html>";
+        assert_eq!(
+            banner.insert_html(html, BannerPosition::Top),
+            b"<html><BODY class=\"a>b\"><b>!</b><p>Hi</p></body></html>"
+        );
+
+        // `<bodyx>` is not the body.
+        let html = b"<bodyx><p>Hi</p>";
+        assert_eq!(
+            banner.insert_html(html, BannerPosition::Top),
+            b"<b>!</b><bodyx><p>Hi</p>"
+        );
+    }
+
+    #[test]
+    fn html_bottom() {
+        let banner = Banner {
+            html: Some("<hr>"),
+            ..Banner::new("")
+        };
+        let html = b"<html><body>\xe9</BODY></html>";
+        assert_eq!(
+            banner.insert_html(html, BannerPosition::Bottom),
+            b"<html><body>\xe9<hr></BODY></html>"
+        );
+        assert_eq!(
+            banner.insert_html(b"<p>Hi</p>", BannerPosition::Bottom),
+            b"<p>Hi</p><hr>"
+        );
+    }
+
+    #[test]
+    fn rtf_fragment() {
+        let banner = Banner::new("{caf\u{e9}}\n\u{1F600}");
+        assert_eq!(
+            banner.rtf_fragment(),
+            r"{\pard\plain\uc1 \{caf\u233?\}\line \u-10179?\u-8704?\par}"
+        );
+    }
+
+    #[test]
+    fn rtf_top() {
+        let banner = Banner {
+            rtf: Some(r"{\pard B\par}"),
+            ..Banner::new("B")
+        };
+        let rtf = b"{\\rtf1\\ansi\\ansicpg1252\\deff0\\deflang1033{\\fonttbl{\\f0 Calibri;}}\r\n{\\*\\generator Riched20;}\\viewkind4\\uc1 \\pard\\f0 Hello\\par\r\n}";
+        assert_eq!(
+            banner.insert_rtf(rtf, BannerPosition::Top),
+            b"{\\rtf1\\ansi\\ansicpg1252\\deff0\\deflang1033{\\fonttbl{\\f0 Calibri;}}\r\n{\\*\\generator Riched20;}\\viewkind4\\uc1 {\\pard B\\par}\\pard\\f0 Hello\\par\r\n}"
+        );
+    }
+
+    #[test]
+    fn rtf_bottom() {
+        let banner = Banner {
+            rtf: Some(r"{\pard B\par}"),
+            ..Banner::new("B")
+        };
+        let rtf = b"{\\rtf1\\ansi Hello}\0";
+        assert_eq!(
+            banner.insert_rtf(rtf, BannerPosition::Bottom),
+            b"{\\rtf1\\ansi Hello\\par {\\pard B\\par}}\0"
+        );
+    }
+
+    #[test]
+    fn rtf_group() {
+        let rtf = br"{a\}{b}}c";
+        assert_eq!(skip_rtf_group(rtf, 0), Some(8));
+        assert_eq!(skip_rtf_group(rtf, 4), Some(7));
+        assert_eq!(skip_rtf_group(b"{open", 0), None);
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`BULK_BATCH_SIZE`] and [`Folder::bulk_get_props`].
+
+use crate::{
+    sys, CompareEntryIds, EntryId, Folder, MAPIProp, MapiStatus, PropTag, PropValue, PropValueData,
+    RelOp, Restriction, Row,
+};
+use std::collections::HashMap;
+use windows_core::*;
+
+/// Number of entry IDs matched with a single [`sys::RES_OR`] restriction in
+/// [`Folder::bulk_get_props`]. Each batch is one [`sys::HrQueryAllRows`] call on the contents
+/// table.
+pub const BULK_BATCH_SIZE: usize = 50;
+
+impl Folder {
+    /// Read the same `tags` from many messages in this folder, keyed by the [`EntryId`] values in
+    /// `entry_ids`.
+    ///
+    /// Rather than opening each message, this restricts the contents table to
+    /// [`BULK_BATCH_SIZE`] entry IDs at a time with [`sys::PR_ENTRYID`], so a whole batch comes
+    /// back in a single round-trip. The first value in each [`Row`] is [`sys::PR_ENTRYID`],
+    /// followed by the values for `tags`, so use [`Row::get`] to look up a property. The entry ID
+    /// in the table may not have the same bytes as the one which was passed in, so each row is
+    /// matched to one of `entry_ids` with `store`, e.g. the [`crate::MsgStore`].
+    ///
+    /// If the store does not support the restriction, or an entry ID is not found in the table
+    /// (e.g. it is a short-term entry ID), the message is opened with [`Folder::open_message`]
+    /// and read with [`MAPIProp::get_props`] instead. Messages for which that fails with
+    /// [`MapiStatus::is_not_found`] are left out of the result, and any other error is returned
+    /// for that entry ID without stopping the rest of the batch.
+    pub fn bulk_get_props(
+        &self,
+        store: &dyn CompareEntryIds,
+        entry_ids: &[EntryId],
+        tags: &[PropTag],
+    ) -> Result<HashMap<EntryId, Result<Row>>> {
+        let mut columns = Vec::with_capacity(tags.len() + 1);
+        columns.push(PropTag(sys::PR_ENTRYID));
+        columns.extend(tags.iter().filter(|tag| tag.0 != sys::PR_ENTRYID));
+
+        let mut results = HashMap::with_capacity(entry_ids.len());
+        let table = self.open_contents_table(Default::default())?;
+        for batch in entry_ids.chunks(BULK_BATCH_SIZE) {
+            let restriction = entry_id_restriction(batch);
+            match table.query_all_rows(&columns, Some(&restriction), None) {
+                Ok(rows) => {
+                    for row in rows {
+                        let Some(Ok(entry_id)) =
+                            row.iter().next().map(|value| EntryId::try_from(&value))
+                        else {
+                            continue;
+                        };
+                        if let Some(index) = match_entry_id(store, batch, &entry_id) {
+                            results.insert(batch[index].clone(), Ok(row));
+                        }
+                    }
+                }
+                Err(error)
+                    if error.code() == sys::MAPI_E_TOO_COMPLEX
+                        || error.code() == sys::MAPI_E_NO_SUPPORT => {}
+                Err(error) => return Err(error),
+            }
+
+            for entry_id in batch {
+                if results.contains_key(entry_id) {
+                    continue;
+                }
+                let row = match self.open_message(entry_id) {
+                    Ok(message) => message.get_props(&columns),
+                    Err(error) if MapiStatus::from(&error).is_not_found() => continue,
+                    Err(error) => Err(error),
+                };
+                results.insert(entry_id.clone(), row);
+            }
+        }
+        Ok(results)
+    }
+}
+
+/// Find the index of the entry ID in `batch` which refers to the same message as `entry_id`,
+/// comparing the bytes first and then calling [`CompareEntryIds::compare_entry_ids`]. An entry ID
+/// which the store cannot compare, e.g. because it is malformed, does not match anything, so it
+/// is reported like any other entry ID that was not found.
+fn match_entry_id(
+    store: &dyn CompareEntryIds,
+    batch: &[EntryId],
+    entry_id: &EntryId,
+) -> Option<usize> {
+    batch
+        .iter()
+        .position(|other| other == entry_id)
+        .or_else(|| {
+            batch
+                .iter()
+                .position(|other| store.compare_entry_ids(other, entry_id).unwrap_or(false))
+        })
+}
+
+/// Match any of the `entry_ids` with [`sys::PR_ENTRYID`].
+fn entry_id_restriction(entry_ids: &[EntryId]) -> Restriction<'_> {
+    Restriction::Or(
+        entry_ids
+            .iter()
+            .map(|entry_id| Restriction::Property {
+                relop: RelOp::Equal,
+                value: PropValue {
+                    tag: PropTag(sys::PR_ENTRYID),
+                    value: PropValueData::Binary(entry_id.as_bytes()),
+                },
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restrict_entry_ids() {
+        let entry_ids = [EntryId::new(vec![1, 2, 3]), EntryId::new(vec![4, 5])];
+        let Restriction::Or(children) = entry_id_restriction(&entry_ids) else {
+            panic!("expected RES_OR");
+        };
+        assert_eq!(children.len(), entry_ids.len());
+        for (child, entry_id) in children.iter().zip(entry_ids.iter()) {
+            let Restriction::Property {
+                relop: RelOp::Equal,
+                value,
+            } = child
+            else {
+                panic!("expected RES_PROPERTY");
+            };
+            assert_eq!(value.tag, PropTag(sys::PR_ENTRYID));
+            assert_eq!(
+                EntryId::try_from(value).expect("not an entry ID"),
+                *entry_id
+            );
+        }
+    }
+
+    /// Treat entry IDs as equivalent if they only differ in the first byte, like the flags in a
+    /// real entry ID.
+    struct IgnoreFlags;
+
+    impl CompareEntryIds for IgnoreFlags {
+        fn compare_entry_ids(&self, left: &EntryId, right: &EntryId) -> Result<bool> {
+            if left.as_bytes().len() < 3 || right.as_bytes().len() < 3 {
+                return Err(Error::from(sys::MAPI_E_INVALID_ENTRYID));
+            }
+            Ok(left.as_bytes()[1..] == right.as_bytes()[1..])
+        }
+    }
+
+    #[test]
+    fn match_input_entry_id() {
+        let batch = [EntryId::new(vec![0, 1, 2]), EntryId::new(vec![0, 4, 5])];
+        let matched =
+            |entry_id: Vec<u8>| match_entry_id(&IgnoreFlags, &batch, &EntryId::new(entry_id));
+        assert_eq!(matched(vec![0, 4, 5]), Some(1));
+        assert_eq!(matched(vec![8, 1, 2]), Some(0));
+        assert_eq!(matched(vec![0, 6, 7]), None);
+    }
+    #[test]
+    fn match_bad_entry_ids() {
+        let batch = [
+            EntryId::new(vec![0, 1]),
+            EntryId::new(vec![0, 1, 2]),
+            EntryId::new(vec![]),
+            EntryId::new(vec![0, 4, 5]),
+        ];
+        let matched =
+            |entry_id: Vec<u8>| match_entry_id(&IgnoreFlags, &batch, &EntryId::new(entry_id));
+        assert_eq!(matched(vec![8, 1, 2]), Some(1));
+        assert_eq!(matched(vec![8, 4, 5]), Some(3));
+        assert_eq!(matched(vec![0, 1]), Some(0));
+        assert_eq!(matched(vec![8, 1]), None);
+        assert_eq!(matched(vec![0, 6, 7]), None);
+    }
+}
@@ -0,0 +1,290 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`CodePage`].
+//!
+//! [`sys::PT_STRING8`] properties are stored as 8-bit strings in the code page of the store or
+//! message, which is not necessarily UTF-8. Converting a Rust `&str` with [`str::bytes`] only works
+//! for ASCII text, and accented characters are silently mangled on the way in or out of the store.
+//! [`CodePage`] performs checked conversions with [`MultiByteToWideChar`] and
+//! [`WideCharToMultiByte`] instead.
+
+use crate::{sys, PropValue, PropValueData};
+use core::ptr;
+use windows::Win32::{
+    Foundation::{BOOL, ERROR_NO_UNICODE_TRANSLATION, E_INVALIDARG},
+    Globalization::*,
+};
+use windows_core::*;
+
+/// Windows code page identifier used to encode and decode [`sys::PT_STRING8`] property values.
+///
+/// The code page for a message is usually found in [`sys::PR_INTERNET_CPID`] or
+/// [`sys::PR_MESSAGE_CODEPAGE`]. If neither is available, [`CodePage::default`] falls back to the
+/// system ANSI code page, which is what MAPI uses for `PT_STRING8` values by default.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CodePage(pub u32);
+
+impl CodePage {
+    /// The system default Windows ANSI code page, [`CP_ACP`].
+    pub const ACP: Self = Self(CP_ACP);
+
+    /// UTF-8, [`CP_UTF8`].
+    pub const UTF8: Self = Self(CP_UTF8);
+
+    /// Western European (Windows), `windows-1252`.
+    pub const WINDOWS_1252: Self = Self(1252);
+
+    /// Get the code page from a [`sys::PR_INTERNET_CPID`] or [`sys::PR_MESSAGE_CODEPAGE`] property
+    /// value, e.g. one returned from `GetProps` on the message or store.
+    pub fn from_prop_value(value: &PropValue) -> Option<Self> {
+        match (value.tag.0, &value.value) {
+            (sys::PR_INTERNET_CPID | sys::PR_MESSAGE_CODEPAGE, PropValueData::Long(code_page)) => {
+                Some(Self(*code_page as u32))
+            }
+            _ => None,
+        }
+    }
+
+    /// Resolve [`CodePage::ACP`] to the actual system code page with [`GetACP`], so it gets the
+    /// same special cases as passing that code page explicitly.
+    fn resolve(self) -> Self {
+        if self == Self::ACP {
+            Self(unsafe { GetACP() })
+        } else {
+            self
+        }
+    }
+
+    /// [`WideCharToMultiByte`] and [`MultiByteToWideChar`] fail with `ERROR_INVALID_FLAGS` if
+    /// they are passed any flags or `lpUsedDefaultChar` for the ISO-2022 and ISCII code pages,
+    /// UTF-7, or the symbol code page.
+    fn supports_strict_flags(self) -> bool {
+        !matches!(self.0, CP_SYMBOL | 50220..=50229 | 57002..=57011 | CP_UTF7)
+    }
+
+    /// Encode a Rust string in this code page. The result does not include a `null` terminator.
+    ///
+    /// If any of the characters cannot be represented in this code page, this will return
+    /// [`ERROR_NO_UNICODE_TRANSLATION`] rather than substituting a default character. The code
+    /// pages which do not support that check, e.g. UTF-7 or ISO-2022-JP, are converted without it.
+    pub fn encode(self, value: &str) -> Result<Vec<u8>> {
+        let code_page = self.resolve();
+        if code_page == Self::UTF8 {
+            return Ok(value.as_bytes().to_vec());
+        }
+        if value.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let wide: Vec<_> = value.encode_utf16().collect();
+        let strict = code_page.supports_strict_flags();
+        let flags = if strict { WC_NO_BEST_FIT_CHARS } else { 0 };
+        let mut used_default = BOOL::default();
+        let size = unsafe {
+            WideCharToMultiByte(
+                code_page.0,
+                flags,
+                &wide,
+                None,
+                PCSTR::null(),
+                strict.then_some(ptr::from_mut(&mut used_default)),
+            )
+        };
+        if size <= 0 {
+            return Err(Error::from_win32());
+        }
+        if used_default.as_bool() {
+            return Err(Error::from(ERROR_NO_UNICODE_TRANSLATION.to_hresult()));
+        }
+
+        let mut buffer = vec![0; size as usize];
+        let size = unsafe {
+            WideCharToMultiByte(
+                code_page.0,
+                flags,
+                &wide,
+                Some(buffer.as_mut_slice()),
+                PCSTR::null(),
+                strict.then_some(ptr::from_mut(&mut used_default)),
+            )
+        };
+        if size <= 0 {
+            return Err(Error::from_win32());
+        }
+        if used_default.as_bool() {
+            return Err(Error::from(ERROR_NO_UNICODE_TRANSLATION.to_hresult()));
+        }
+
+        buffer.truncate(size as usize);
+        Ok(buffer)
+    }
+
+    /// Encode a Rust string in this code page and append a `null` terminator, suitable for a
+    /// [`sys::PT_STRING8`] property value. Embedded `null` characters would truncate the value, so
+    /// they are rejected with [`E_INVALIDARG`].
+    pub fn encode_with_nul(self, value: &str) -> Result<Vec<u8>> {
+        let mut buffer = self.encode(value)?;
+        if buffer.contains(&0) {
+            return Err(Error::from(E_INVALIDARG));
+        }
+        buffer.push(0);
+        Ok(buffer)
+    }
+
+    /// Decode a string in this code page to a Rust [`String`]. The input should not include a
+    /// `null` terminator.
+    pub fn decode(self, value: &[u8]) -> Result<String> {
+        let code_page = self.resolve();
+        if code_page == Self::UTF8 {
+            return Ok(String::from_utf8(value.to_vec())?);
+        }
+        if value.is_empty() {
+            return Ok(String::new());
+        }
+
+        let flags = if code_page.supports_strict_flags() {
+            MB_ERR_INVALID_CHARS
+        } else {
+            MULTI_BYTE_TO_WIDE_CHAR_FLAGS(0)
+        };
+        let size = unsafe { MultiByteToWideChar(code_page.0, flags, value, None) };
+        if size <= 0 {
+            return Err(Error::from_win32());
+        }
+
+        let mut buffer = vec![0; size as usize];
+        let size =
+            unsafe { MultiByteToWideChar(code_page.0, flags, value, Some(buffer.as_mut_slice())) };
+        if size <= 0 {
+            return Err(Error::from_win32());
+        }
+
+        buffer.truncate(size as usize);
+        Ok(String::from_utf16(&buffer)?)
+    }
+
+    /// Decode a `null` terminated string in this code page, such as the
+    /// [`crate::PropValueData::AnsiString`] value of a [`sys::PT_STRING8`] property.
+    ///
+    /// # Safety
+    ///
+    /// The `value` must be a valid, `null` terminated string pointer.
+    pub unsafe fn decode_pcstr(self, value: PCSTR) -> Result<String> {
+        self.decode(value.as_bytes())
+    }
+}
+
+impl Default for CodePage {
+    /// Use the system default ANSI code page.
+    fn default() -> Self {
+        Self::ACP
+    }
+}
+
+impl From<u32> for CodePage {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<CodePage> for u32 {
+    fn from(value: CodePage) -> Self {
+        value.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{PropTag, PropType};
+
+    const ACCENTED: &str = "Crème brûlée à la façon de Zoë";
+
+    #[test]
+    fn round_trip_windows_1252() {
+        let encoded = CodePage::WINDOWS_1252
+            .encode(ACCENTED)
+            .expect("encode failed");
+        assert_eq!(encoded.len(), ACCENTED.chars().count());
+        assert_eq!(encoded[2], 0xE8);
+        let decoded = CodePage::WINDOWS_1252
+            .decode(&encoded)
+            .expect("decode failed");
+        assert_eq!(decoded, ACCENTED);
+    }
+
+    #[test]
+    fn round_trip_utf8() {
+        let encoded = CodePage::UTF8.encode(ACCENTED).expect("encode failed");
+        assert_eq!(encoded.as_slice(), ACCENTED.as_bytes());
+        let decoded = CodePage::UTF8.decode(&encoded).expect("decode failed");
+        assert_eq!(decoded, ACCENTED);
+    }
+
+    #[test]
+    fn unmappable_characters() {
+        assert!(CodePage::WINDOWS_1252.encode("日本語").is_err());
+    }
+
+    #[test]
+    fn round_trip_without_strict_flags() {
+        for code_page in [CodePage(50220), CodePage(57002), CodePage(CP_UTF7)] {
+            let encoded = code_page.encode("Hello").expect("encode failed");
+            assert_eq!(encoded.as_slice(), b"Hello");
+            let decoded = code_page.decode(&encoded).expect("decode failed");
+            assert_eq!(decoded, "Hello");
+        }
+    }
+
+    #[test]
+    fn strict_flags() {
+        assert!(CodePage::WINDOWS_1252.supports_strict_flags());
+        assert!(CodePage::UTF8.supports_strict_flags());
+        for code_page in [CP_SYMBOL, 50220, 50229, 57002, 57011, CP_UTF7] {
+            assert!(!CodePage(code_page).supports_strict_flags());
+        }
+    }
+
+    #[test]
+    fn embedded_nul() {
+        assert!(CodePage::WINDOWS_1252.encode_with_nul("a\0b").is_err());
+    }
+
+    #[test]
+    fn from_prop_value() {
+        let mut value = sys::SPropValue {
+            ulPropTag: sys::PR_INTERNET_CPID,
+            ..Default::default()
+        };
+        value.Value.l = 1252;
+        let value = PropValue::from(&value);
+        assert_eq!(
+            CodePage::from_prop_value(&value),
+            Some(CodePage::WINDOWS_1252)
+        );
+    }
+
+    #[test]
+    fn round_trip_prop_value() {
+        let encoded = CodePage::WINDOWS_1252
+            .encode_with_nul(ACCENTED)
+            .expect("encode failed");
+        assert_eq!(encoded.last(), Some(&0));
+        let mut value = sys::SPropValue {
+            ulPropTag: u32::from(
+                PropTag(sys::PR_SUBJECT_A).change_prop_type(PropType::new(sys::PT_STRING8 as u16)),
+            ),
+            ..Default::default()
+        };
+        value.Value.lpszA.0 = encoded.as_ptr() as *mut _;
+        let value = PropValue::from(&value);
+        let PropValueData::AnsiString(actual) = value.value else {
+            panic!("wrong type");
+        };
+        let decoded =
+            unsafe { CodePage::WINDOWS_1252.decode_pcstr(actual) }.expect("decode failed");
+        assert_eq!(decoded, ACCENTED);
+    }
+}
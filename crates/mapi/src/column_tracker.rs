@@ -0,0 +1,306 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`ColumnTracker`], [`TrackedRow`], and [`ColumnStats`].
+//!
+//! Reading a large contents table with a wide column set transfers every column for every row,
+//! even if the caller only looks at a few of them. [`ColumnTracker`] remembers which columns are
+//! actually read through [`TrackedRow::get`] in the first batch of rows, and
+//! [`crate::Table::for_each_row`] narrows the [`sys::IMAPITable::SetColumns`] call to exactly those
+//! columns for the rest of the table.
+
+use crate::{sys, PropTag, PropValue, PropValueData, Row};
+use core::{cell::Cell, mem};
+use std::time::Duration;
+
+/// Measurements collected by [`crate::Table::for_each_row`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ColumnStats {
+    /// Number of calls to [`sys::IMAPITable::QueryRows`] which returned rows.
+    pub batches: usize,
+
+    /// Total number of rows passed to the callback.
+    pub rows: usize,
+
+    /// Estimated size of the [`sys::SPropValue`] payload for all of the rows, in bytes.
+    pub bytes: usize,
+
+    /// Total time spent waiting for [`sys::IMAPITable::QueryRows`].
+    pub elapsed: Duration,
+
+    /// Number of columns in the column set before it was narrowed.
+    pub columns_before: usize,
+
+    /// Number of columns in the column set after it was narrowed.
+    pub columns_after: usize,
+}
+
+/// Track the columns that a callback reads from each row, so the column set can be narrowed.
+pub struct ColumnTracker {
+    columns: Vec<PropTag>,
+    read: Vec<Cell<bool>>,
+    narrowed: bool,
+    batch_size: usize,
+    target_batch_bytes: Option<usize>,
+    stats: ColumnStats,
+}
+
+impl ColumnTracker {
+    /// Start with the full set of `columns` which the callback might read. After the first batch
+    /// of rows, the column set is narrowed to the columns which were actually read.
+    ///
+    /// Rows in later batches only include the narrowed columns, so a callback which reads some
+    /// columns conditionally should use [`ColumnTracker::declared`] instead.
+    pub fn new(columns: &[PropTag]) -> Self {
+        Self {
+            columns: columns.to_vec(),
+            read: columns.iter().map(|_| Cell::new(false)).collect(),
+            narrowed: false,
+            batch_size: Self::DEFAULT_BATCH_SIZE,
+            target_batch_bytes: None,
+            stats: ColumnStats {
+                columns_before: columns.len(),
+                columns_after: columns.len(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Use exactly the declared set of `columns` from the beginning, without tracking.
+    pub fn declared(columns: &[PropTag]) -> Self {
+        Self {
+            narrowed: true,
+            ..Self::new(columns)
+        }
+    }
+
+    /// Set the number of rows to request in each call to [`sys::IMAPITable::QueryRows`]. The
+    /// default is [`ColumnTracker::DEFAULT_BATCH_SIZE`].
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.clamp(Self::MIN_BATCH_SIZE, Self::MAX_BATCH_SIZE);
+        self
+    }
+
+    /// Opt-in to tuning the number of rows in each batch. After each batch, the average payload
+    /// size per row is measured, and the batch size for the next call to
+    /// [`sys::IMAPITable::QueryRows`] is adjusted to come close to `target_batch_bytes`.
+    pub fn auto_tune(mut self, target_batch_bytes: usize) -> Self {
+        self.target_batch_bytes = Some(target_batch_bytes);
+        self
+    }
+
+    /// Get the current column set.
+    pub fn columns(&self) -> &[PropTag] {
+        &self.columns
+    }
+
+    /// Test if the column set has already been narrowed.
+    pub fn is_narrowed(&self) -> bool {
+        self.narrowed
+    }
+
+    /// Get the number of rows to request in the next batch.
+    pub fn next_batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Get the [`ColumnStats`] collected so far.
+    pub fn stats(&self) -> &ColumnStats {
+        &self.stats
+    }
+
+    /// Wrap a [`Row`] so that any columns read through [`TrackedRow::get`] are recorded.
+    pub fn track<'a>(&'a self, row: &'a Row) -> TrackedRow<'a> {
+        TrackedRow { tracker: self, row }
+    }
+
+    /// Narrow the column set to the columns which have been read so far. Returns `true` if the
+    /// column set changed and needs to be passed to [`sys::IMAPITable::SetColumns`] again.
+    ///
+    /// A table needs at least one column, so if none of them were read, this narrows it to just
+    /// [`sys::PR_INSTANCE_KEY`].
+    pub fn narrow(&mut self) -> bool {
+        if mem::replace(&mut self.narrowed, true) {
+            return false;
+        }
+
+        let before = mem::take(&mut self.columns);
+        let read = mem::take(&mut self.read);
+        self.columns = before
+            .iter()
+            .zip(read)
+            .filter_map(|(column, read)| read.get().then_some(*column))
+            .collect();
+        if self.columns.is_empty() {
+            self.columns.push(PropTag(sys::PR_INSTANCE_KEY));
+        }
+        self.read = self.columns.iter().map(|_| Cell::new(true)).collect();
+        self.stats.columns_after = self.columns.len();
+        self.columns != before
+    }
+
+    /// Record the measurements for a batch. If [`ColumnTracker::auto_tune`] is enabled, this also
+    /// adjusts [`ColumnTracker::next_batch_size`] based on the average payload size per row.
+    pub fn record_batch(&mut self, rows: usize, bytes: usize, elapsed: Duration) {
+        self.stats.batches += 1;
+        self.stats.rows += rows;
+        self.stats.bytes += bytes;
+        self.stats.elapsed += elapsed;
+
+        if let Some(target) = self.target_batch_bytes {
+            if rows > 0 && bytes > 0 {
+                let per_row = bytes.div_ceil(rows);
+                self.batch_size =
+                    (target / per_row).clamp(Self::MIN_BATCH_SIZE, Self::MAX_BATCH_SIZE);
+            }
+        }
+    }
+
+    /// Default batch size for [`ColumnTracker::next_batch_size`].
+    pub const DEFAULT_BATCH_SIZE: usize = 100;
+
+    /// Smallest batch size [`ColumnTracker::auto_tune`] will request.
+    pub const MIN_BATCH_SIZE: usize = 1;
+
+    /// Largest batch size [`ColumnTracker::auto_tune`] will request.
+    pub const MAX_BATCH_SIZE: usize = 1000;
+
+    fn mark_read(&self, tag: PropTag) {
+        let prop_id = tag.prop_id();
+        if let Some(read) = self
+            .columns
+            .iter()
+            .position(|column| column.prop_id() == prop_id)
+            .and_then(|index| self.read.get(index))
+        {
+            read.set(true);
+        }
+    }
+}
+
+/// A [`Row`] which records the columns read from it in a [`ColumnTracker`].
+pub struct TrackedRow<'a> {
+    tracker: &'a ColumnTracker,
+    row: &'a Row,
+}
+
+impl<'a> TrackedRow<'a> {
+    /// Get the value of a column, matching on the [`PropTag::prop_id`] so that [`sys::PT_ERROR`]
+    /// values are also returned, and record that the column was read.
+    pub fn get(&self, tag: PropTag) -> Option<PropValue<'a>> {
+        self.tracker.mark_read(tag);
+        self.row.get(tag)
+    }
+
+    /// Access the underlying [`Row`] without tracking any columns.
+    pub fn row(&self) -> &'a Row {
+        self.row
+    }
+}
+
+/// Estimate the size of the [`sys::SPropValue`] and any data it points to.
+pub(crate) fn payload_size(value: &PropValue) -> usize {
+    let data = match &value.value {
+        PropValueData::AnsiString(value) => unsafe { value.as_bytes().len() + 1 },
+        PropValueData::Binary(value) => value.len(),
+        PropValueData::Unicode(value) => value.len() * mem::size_of::<u16>(),
+        PropValueData::Guid(value) => mem::size_of_val(value),
+        PropValueData::ShortArray(value) => mem::size_of_val(*value),
+        PropValueData::LongArray(value) => mem::size_of_val(*value),
+        PropValueData::FloatArray(value) => mem::size_of_val(*value),
+        PropValueData::DoubleArray(value) => mem::size_of_val(value.as_ref()),
+        PropValueData::CurrencyArray(value) => mem::size_of_val(value.as_ref()),
+        PropValueData::AppTimeArray(value) => mem::size_of_val(value.as_ref()),
+        PropValueData::FileTimeArray(value) => mem::size_of_val(value.as_ref()),
+        PropValueData::BinaryArray(value) => value
+            .iter()
+            .map(|value| mem::size_of_val(value) + value.cb as usize)
+            .sum(),
+        PropValueData::AnsiStringArray(value) => value
+            .iter()
+            .map(|value| mem::size_of_val(value) + unsafe { value.as_bytes().len() + 1 })
+            .sum(),
+        PropValueData::UnicodeArray(value) => value
+            .iter()
+            .map(|value| mem::size_of_val(value) + unsafe { (value.len() + 1) * 2 })
+            .sum(),
+        PropValueData::GuidArray(value) => mem::size_of_val(value.as_ref()),
+        PropValueData::LargeIntegerArray(value) => mem::size_of_val(value.as_ref()),
+        _ => 0,
+    };
+    mem::size_of::<sys::SPropValue>() + data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn narrow_to_read_columns() {
+        let mut tracker = ColumnTracker::new(&[
+            PropTag(sys::PR_ENTRYID),
+            PropTag(sys::PR_SUBJECT_W),
+            PropTag(sys::PR_BODY_W),
+        ]);
+        tracker.mark_read(PropTag(sys::PR_SUBJECT_W));
+        tracker.mark_read(PropTag(sys::PR_ENTRYID));
+        assert!(!tracker.is_narrowed());
+        assert!(tracker.narrow());
+        assert!(tracker.is_narrowed());
+        let columns: Vec<_> = tracker.columns().iter().map(|tag| tag.0).collect();
+        assert_eq!(columns, [sys::PR_ENTRYID, sys::PR_SUBJECT_W]);
+        assert_eq!(tracker.stats().columns_before, 3);
+        assert_eq!(tracker.stats().columns_after, 2);
+        assert!(!tracker.narrow());
+    }
+
+    #[test]
+    fn narrow_without_reads() {
+        let mut tracker =
+            ColumnTracker::new(&[PropTag(sys::PR_ENTRYID), PropTag(sys::PR_SUBJECT_W)]);
+        assert!(tracker.narrow());
+        let columns: Vec<_> = tracker.columns().iter().map(|tag| tag.0).collect();
+        assert_eq!(columns, [sys::PR_INSTANCE_KEY]);
+        assert_eq!(tracker.stats().columns_after, 1);
+    }
+
+    #[test]
+    fn declared_is_not_narrowed() {
+        let mut tracker = ColumnTracker::declared(&[PropTag(sys::PR_ENTRYID)]);
+        assert!(tracker.is_narrowed());
+        assert!(!tracker.narrow());
+        assert_eq!(tracker.columns().len(), 1);
+    }
+
+    #[test]
+    fn auto_tune_batch_size() {
+        let mut tracker = ColumnTracker::new(&[PropTag(sys::PR_ENTRYID)])
+            .batch_size(10)
+            .auto_tune(10_000);
+        tracker.record_batch(10, 1_000, Duration::ZERO);
+        assert_eq!(tracker.next_batch_size(), 100);
+        tracker.record_batch(10, 1, Duration::ZERO);
+        assert_eq!(tracker.next_batch_size(), ColumnTracker::MAX_BATCH_SIZE);
+        tracker.record_batch(1, 1_000_000, Duration::ZERO);
+        assert_eq!(tracker.next_batch_size(), ColumnTracker::MIN_BATCH_SIZE);
+        assert_eq!(tracker.stats().batches, 3);
+        assert_eq!(tracker.stats().rows, 21);
+    }
+
+    #[test]
+    fn fixed_batch_size() {
+        let mut tracker = ColumnTracker::new(&[PropTag(sys::PR_ENTRYID)]).batch_size(50);
+        tracker.record_batch(7, 1_000, Duration::ZERO);
+        assert_eq!(tracker.next_batch_size(), 50);
+    }
+
+    #[test]
+    fn payload_size_binary() {
+        let data = [0_u8; 20];
+        let value = PropValue {
+            tag: PropTag(sys::PR_ENTRYID),
+            value: PropValueData::Binary(&data),
+        };
+        assert_eq!(payload_size(&value), mem::size_of::<sys::SPropValue>() + 20);
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`ColumnSet`] and the preset column lists for common tables.
+//!
+//! The presets are not re-exported from the root of the crate, so refer to them as e.g.
+//! [`columns::MESSAGE_LIST_DEFAULT`](MESSAGE_LIST_DEFAULT). Pass one directly to
+//! [`crate::Table::set_columns`], or combine it with more columns in a [`ColumnSet`].
+
+use crate::{sys, PropTag, StoreInfo};
+use core::ops::Deref;
+
+/// Columns for listing the messages in a contents table, e.g. to show an Inbox view.
+pub const MESSAGE_LIST_DEFAULT: &[PropTag] = &[
+    PropTag(sys::PR_ENTRYID),
+    PropTag(sys::PR_INSTANCE_KEY),
+    PropTag(sys::PR_MESSAGE_CLASS_W),
+    PropTag(sys::PR_SUBJECT_W),
+    PropTag(sys::PR_SENDER_NAME_W),
+    PropTag(sys::PR_MESSAGE_DELIVERY_TIME),
+    PropTag(sys::PR_MESSAGE_FLAGS),
+    PropTag(sys::PR_MESSAGE_SIZE),
+    PropTag(sys::PR_HASATTACH),
+    PropTag(sys::PR_IMPORTANCE),
+];
+
+/// Columns for walking a hierarchy table, e.g. from [`crate::Folder::open_hierarchy_table`].
+pub const FOLDER_HIERARCHY_DEFAULT: &[PropTag] = &[
+    PropTag(sys::PR_ENTRYID),
+    PropTag(sys::PR_INSTANCE_KEY),
+    PropTag(sys::PR_PARENT_ENTRYID),
+    PropTag(sys::PR_DISPLAY_NAME_W),
+    PropTag(sys::PR_CONTAINER_CLASS_W),
+    PropTag(sys::PR_DEPTH),
+    PropTag(sys::PR_SUBFOLDERS),
+    PropTag(sys::PR_CONTENT_COUNT),
+    PropTag(sys::PR_CONTENT_UNREAD),
+];
+
+/// Columns for the message store table. The first columns are [`StoreInfo::COLUMNS`], so the
+/// rows can be read with [`StoreInfo::from_row`].
+pub const STORE_TABLE_DEFAULT: &[PropTag] = &[
+    StoreInfo::COLUMNS[0],
+    StoreInfo::COLUMNS[1],
+    StoreInfo::COLUMNS[2],
+    StoreInfo::COLUMNS[3],
+    PropTag(sys::PR_RECORD_KEY),
+    PropTag(sys::PR_RESOURCE_FLAGS),
+];
+
+/// Column list for [`crate::Table::set_columns`], built from one or more presets and any
+/// additional columns. Each column is only included once, in the order it was first added.
+///
+/// [`ColumnSet`] dereferences to `[PropTag]`, so it can be passed as `&columns` anywhere a slice
+/// of columns is expected.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ColumnSet {
+    columns: Vec<PropTag>,
+}
+
+impl ColumnSet {
+    /// Add all of the columns in a preset, e.g. [`MESSAGE_LIST_DEFAULT`].
+    pub fn preset(mut self, preset: &[PropTag]) -> Self {
+        for tag in preset {
+            self.add(*tag);
+        }
+        self
+    }
+
+    /// Add a single column.
+    pub fn column(mut self, tag: PropTag) -> Self {
+        self.add(tag);
+        self
+    }
+
+    /// Remove a column which was added by a preset.
+    pub fn without(mut self, tag: PropTag) -> Self {
+        self.columns.retain(|column| *column != tag);
+        self
+    }
+
+    /// Get the columns in the order they were added.
+    pub fn columns(&self) -> &[PropTag] {
+        &self.columns
+    }
+
+    /// Append a column unless it is already in the set.
+    fn add(&mut self, tag: PropTag) {
+        if !self.columns.contains(&tag) {
+            self.columns.push(tag);
+        }
+    }
+}
+
+impl Deref for ColumnSet {
+    type Target = [PropTag];
+
+    fn deref(&self) -> &Self::Target {
+        &self.columns
+    }
+}
+
+impl From<&[PropTag]> for ColumnSet {
+    fn from(value: &[PropTag]) -> Self {
+        Self::default().preset(value)
+    }
+}
+
+impl From<ColumnSet> for Vec<PropTag> {
+    fn from(value: ColumnSet) -> Self {
+        value.columns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_presets() {
+        let columns = ColumnSet::default()
+            .preset(MESSAGE_LIST_DEFAULT)
+            .preset(FOLDER_HIERARCHY_DEFAULT)
+            .column(PropTag(sys::PR_SUBJECT_W))
+            .column(PropTag(sys::PR_BODY_W))
+            .without(PropTag(sys::PR_IMPORTANCE));
+
+        assert_eq!(&columns[..2], &MESSAGE_LIST_DEFAULT[..2]);
+        assert_eq!(columns.last(), Some(&PropTag(sys::PR_BODY_W)));
+        assert!(!columns.contains(&PropTag(sys::PR_IMPORTANCE)));
+        for (index, tag) in columns.iter().enumerate() {
+            assert!(
+                !columns[index + 1..].contains(tag),
+                "duplicate 0x{:08X}",
+                tag.0
+            );
+        }
+        assert_eq!(
+            columns.len(),
+            MESSAGE_LIST_DEFAULT.len() + FOLDER_HIERARCHY_DEFAULT.len() - 2 - 1 + 1
+        );
+    }
+
+    #[test]
+    fn store_preset() {
+        assert_eq!(&STORE_TABLE_DEFAULT[..4], &StoreInfo::COLUMNS);
+    }
+}
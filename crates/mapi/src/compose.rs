@@ -0,0 +1,294 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`mail_merge`] and its [`MailMergeOptions`].
+//!
+//! A mail merge copies a template draft once per recipient, fills in placeholders like `%name%`
+//! in the subject and body, and sends each copy. The substitutions are applied to the native body
+//! of the template, with the values escaped for that format, so HTML and RTF formatting and inline
+//! images survive the merge.
+
+use crate::{
+    banner, message, rtf, sys, to_pwstr_buffer, BodyFormat, Folder, MAPIProp, Message, PropTag,
+    PropValue, PropValueData, RecipientKind, ResolvedRecipient,
+};
+use std::{io::Read, thread, time::Duration};
+use windows_core::*;
+
+/// Properties which identify the template itself, and should not be copied to each message.
+const EXCLUDED_PROPS: [PropTag; 10] = [
+    PropTag(sys::PR_ENTRYID),
+    PropTag(sys::PR_INSTANCE_KEY),
+    PropTag(sys::PR_RECORD_KEY),
+    PropTag(sys::PR_SEARCH_KEY),
+    PropTag(sys::PR_SOURCE_KEY),
+    PropTag(sys::PR_CHANGE_KEY),
+    PropTag(sys::PR_PREDECESSOR_CHANGE_LIST),
+    PropTag(sys::PR_PARENT_ENTRYID),
+    PropTag(sys::PR_PARENT_SOURCE_KEY),
+    PropTag(sys::PR_MESSAGE_RECIPIENTS),
+];
+
+/// Options for [`mail_merge`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MailMergeOptions {
+    /// Call [`Message::submit`] on each message. If `false`, the messages are saved with
+    /// [`MAPIProp::save_changes`] in the folder, e.g. to review them as drafts.
+    pub submit: bool,
+
+    /// How long to wait after each message, to keep from flooding the transport.
+    pub delay: Option<Duration>,
+
+    /// Stop after this many recipients, whether or not they succeeded.
+    pub max_messages: Option<usize>,
+
+    /// Return the first error instead of recording it in [`MailMergeReport::failures`] and moving
+    /// on to the next recipient.
+    pub stop_on_error: bool,
+}
+
+impl Default for MailMergeOptions {
+    fn default() -> Self {
+        Self {
+            submit: true,
+            delay: None,
+            max_messages: None,
+            stop_on_error: false,
+        }
+    }
+}
+
+/// Recipient which [`mail_merge`] could not send to.
+#[derive(Debug)]
+pub struct MailMergeFailure {
+    /// Position of the recipient in the input to [`mail_merge`].
+    pub index: usize,
+
+    /// The recipient which failed.
+    pub recipient: ResolvedRecipient,
+
+    /// What went wrong.
+    pub error: Error,
+}
+
+/// Result of a [`mail_merge`].
+#[derive(Debug, Default)]
+pub struct MailMergeReport {
+    /// Number of messages which were submitted, or saved if [`MailMergeOptions::submit`] is
+    /// `false`.
+    pub sent: usize,
+
+    /// Recipients which failed, if [`MailMergeOptions::stop_on_error`] is `false`.
+    pub failures: Vec<MailMergeFailure>,
+}
+
+/// Send a copy of the `template` message to each of the `recipients`.
+///
+/// Each copy is created in `folder`, usually the [`crate::SpecialFolder::Outbox`] or the
+/// [`crate::SpecialFolder::Drafts`], with all of the properties and attachments of the template,
+/// but none of its recipients. The `substitutions` callback returns `(placeholder, value)` pairs
+/// for each recipient, which are replaced in [`sys::PR_SUBJECT_W`] and the native body of the
+/// copy:
+///
+/// - [`BodyFormat::Html`]: The values are HTML escaped and replaced in [`sys::PR_HTML`].
+/// - [`BodyFormat::Rtf`]: The values are RTF escaped and replaced in the decompressed RTF, which
+///   is written back with [`Message::set_rtf_body`]. If the RTF encapsulates HTML, the values are
+///   HTML escaped instead.
+/// - [`BodyFormat::PlainText`]: The values are replaced in [`sys::PR_BODY_W`].
+///
+/// Placeholders are matched on the raw HTML or RTF, so they should not contain characters which
+/// need to be escaped in either format, like `<`, `&`, `{`, or `\`.
+pub fn mail_merge<I, F>(
+    template: &Message,
+    folder: &Folder,
+    recipients: I,
+    mut substitutions: F,
+    options: &MailMergeOptions,
+) -> Result<MailMergeReport>
+where
+    I: IntoIterator<Item = ResolvedRecipient>,
+    F: FnMut(&ResolvedRecipient) -> Vec<(String, String)>,
+{
+    let format = BodyFormat::from_props(template.get_props(&BodyFormat::PROPS)?.iter());
+    let mut report = MailMergeReport::default();
+    let recipients = recipients
+        .into_iter()
+        .take(options.max_messages.unwrap_or(usize::MAX));
+    for (index, recipient) in recipients.enumerate() {
+        let substitutions = substitutions(&recipient);
+        match merge_one(
+            template,
+            folder,
+            format,
+            &recipient,
+            &substitutions,
+            options,
+        ) {
+            Ok(()) => report.sent += 1,
+            Err(error) if options.stop_on_error => return Err(error),
+            Err(error) => report.failures.push(MailMergeFailure {
+                index,
+                recipient,
+                error,
+            }),
+        }
+        if let Some(delay) = options.delay {
+            thread::sleep(delay);
+        }
+    }
+    Ok(report)
+}
+
+fn merge_one(
+    template: &Message,
+    folder: &Folder,
+    format: BodyFormat,
+    recipient: &ResolvedRecipient,
+    substitutions: &[(String, String)],
+    options: &MailMergeOptions,
+) -> Result<()> {
+    let message = folder.create_message()?;
+    template.copy_to(&message, &EXCLUDED_PROPS, Default::default())?;
+
+    if let Some(subject) = message
+        .get_props(&[PropTag(sys::PR_SUBJECT_W)])?
+        .iter()
+        .next()
+        .and_then(|value| value.value.as_string())
+    {
+        let subject = to_pwstr_buffer(&replace_all(&subject, substitutions));
+        message.set_props(&[PropValue {
+            tag: PropTag(sys::PR_SUBJECT_W),
+            value: PropValueData::Unicode(subject),
+        }])?;
+    }
+
+    match format {
+        BodyFormat::Html => merge_html_body(&message, substitutions)?,
+        BodyFormat::Rtf => {
+            let mut body = Vec::new();
+            message.open_rtf_body()?.read_to_end(&mut body)?;
+            if rtf::is_encapsulated_html(&body) {
+                merge_html_body(&message, substitutions)?;
+            } else {
+                let substitutions = escape_values(substitutions, banner::escape_rtf);
+                message.set_rtf_body(&replace_all_bytes(&body, &substitutions))?;
+            }
+        }
+        BodyFormat::PlainText => {
+            let body = banner::read_property(&message, sys::PR_BODY_W)?;
+            let body = replace_all(&message::decode_unicode_body(&body)?, substitutions);
+            let body: Vec<_> = body.encode_utf16().flat_map(u16::to_le_bytes).collect();
+            banner::write_property(&message, sys::PR_BODY_W, &body)?;
+
+            message.delete_stale_bodies(&[PropTag(sys::PR_HTML)])?;
+            // The plain text was just written, so it does not matter if the RTF changed.
+            let _updated = unsafe { sys::RTFSync(&message.message, sys::RTF_SYNC_BODY_CHANGED)? };
+        }
+    }
+
+    message.set_recipients([(RecipientKind::To, recipient)])?;
+    if options.submit {
+        message.submit(Default::default())
+    } else {
+        message.save_changes(Default::default())
+    }
+}
+
+fn merge_html_body(message: &Message, substitutions: &[(String, String)]) -> Result<()> {
+    let html = banner::read_property(message, sys::PR_HTML)?;
+    let substitutions = escape_values(substitutions, banner::escape_html);
+    message.write_html_body(&replace_all_bytes(&html, &substitutions))
+}
+
+fn escape_values(
+    substitutions: &[(String, String)],
+    escape: fn(&str) -> String,
+) -> Vec<(String, String)> {
+    substitutions
+        .iter()
+        .map(|(placeholder, value)| (placeholder.clone(), escape(value)))
+        .collect()
+}
+
+/// Replace every placeholder in `text`, in order.
+fn replace_all(text: &str, substitutions: &[(String, String)]) -> String {
+    substitutions
+        .iter()
+        .filter(|(placeholder, _)| !placeholder.is_empty())
+        .fold(String::from(text), |text, (placeholder, value)| {
+            text.replace(placeholder.as_str(), value)
+        })
+}
+
+/// Replace every placeholder in the bytes of an HTML or RTF body, without decoding it. The
+/// escaped values are ASCII, so this works in any code page which is a superset of ASCII.
+fn replace_all_bytes(body: &[u8], substitutions: &[(String, String)]) -> Vec<u8> {
+    let mut body = body.to_vec();
+    for (placeholder, value) in substitutions {
+        let placeholder = placeholder.as_bytes();
+        if placeholder.is_empty() {
+            continue;
+        }
+        let mut result = Vec::with_capacity(body.len());
+        let mut rest = body.as_slice();
+        while let Some(offset) = rest
+            .windows(placeholder.len())
+            .position(|window| window == placeholder)
+        {
+            result.extend_from_slice(&rest[..offset]);
+            result.extend_from_slice(value.as_bytes());
+            rest = &rest[offset + placeholder.len()..];
+        }
+        result.extend_from_slice(rest);
+        body = result;
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn substitutions() -> Vec<(String, String)> {
+        vec![
+            (String::from("%name%"), String::from("Zoë <Z>")),
+            (String::from("%team%"), String::from("R&D")),
+            (String::new(), String::from("ignored")),
+        ]
+    }
+
+    #[test]
+    fn replace_text() {
+        assert_eq!(
+            replace_all("Hi %name%, from %team% and %team%.", &substitutions()),
+            "Hi Zoë <Z>, from R&D and R&D."
+        );
+    }
+
+    #[test]
+    fn replace_html() {
+        let substitutions = escape_values(&substitutions(), banner::escape_html);
+        assert_eq!(
+            replace_all_bytes(b"<p>Hi %name%</p><p>%team%</p>", &substitutions),
+            b"<p>Hi Zo&#235; &lt;Z&gt;</p><p>R&amp;D</p>"
+        );
+    }
+
+    #[test]
+    fn replace_rtf() {
+        let substitutions = escape_values(&substitutions(), banner::escape_rtf);
+        assert_eq!(
+            replace_all_bytes(br"{\rtf1 Hi %name%\par}", &substitutions),
+            br"{\rtf1 Hi Zo\u235? <Z>\par}"
+        );
+    }
+
+    #[test]
+    fn default_options() {
+        let options = MailMergeOptions::default();
+        assert!(options.submit);
+        assert!(!options.stop_on_error);
+        assert_eq!(options.max_messages, None);
+    }
+}
@@ -0,0 +1,645 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`Contact`], [`ContactEmail`], [`EmailSlot`], and [`PhoneKind`].
+//!
+//! Outlook stores the picture of a contact as a hidden attachment on the contact message, with
+//! `PidTagAttachmentContactPhoto` set to `true`, and sets the `PidLidHasPicture` named property on
+//! the contact so it knows to look for one. Clients which only add the attachment, or which
+//! leave an old picture behind, end up with a contact that shows the wrong picture or none at
+//! all.
+//!
+//! Most of the other contact fields, like the e-mail addresses and the file-as name, are also
+//! named properties in the [`sys::PSETID_Address`] property set, so [`Contact`] looks up their
+//! property tags before reading or writing them.
+
+use crate::{
+    sys, to_pwstr_buffer, MAPIOutParam, MAPIProp, Message, OpenPropertyFlags, PropTag, PropType,
+    PropValue, PropValueData,
+};
+use core::{ptr, slice};
+use std::io::{Read, Write};
+use windows::Win32::Foundation::*;
+use windows_core::*;
+
+/// `PidTagAttachmentContactPhoto`, which is missing from [`sys`].
+const PR_ATTACHMENT_CONTACTPHOTO: u32 = 0x7FFF_000B;
+
+/// `PidTagAttachmentHidden`, which is missing from [`sys`].
+const PR_ATTACHMENT_HIDDEN: u32 = 0x7FFE_000B;
+
+/// `PidLidHasPicture` in the [`sys::PSETID_Address`] property set.
+const LID_HAS_PICTURE: i32 = 0x8015;
+
+/// `PidLidFileUnder` in the [`sys::PSETID_Address`] property set.
+const LID_FILE_UNDER: i32 = 0x8005;
+
+/// `PidLidInstantMessagingAddress` in the [`sys::PSETID_Address`] property set.
+const LID_INSTANT_MESSAGING_ADDRESS: i32 = 0x8062;
+
+/// `PidLidAddressBookProviderEmailList` in the [`sys::PSETID_Address`] property set.
+const LID_AB_PROVIDER_EMAIL_LIST: i32 = 0x8028;
+
+/// `PidLidAddressBookProviderArrayType` in the [`sys::PSETID_Address`] property set.
+const LID_AB_PROVIDER_ARRAY_TYPE: i32 = 0x8029;
+
+/// `MAPI_ONE_OFF_UNICODE`, which is missing from [`sys`].
+const MAPI_ONE_OFF_UNICODE: u16 = 0x8000;
+
+/// `MAPI_ONE_OFF_NO_RICH_INFO`, which is missing from [`sys`].
+const MAPI_ONE_OFF_NO_RICH_INFO: u16 = 0x0001;
+
+/// `MAPI_ONE_OFF_UID`, the provider UID of a one-off entry ID in [MS-OXCDATA] section 2.2.5.1.
+///
+/// [MS-OXCDATA]: https://learn.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxcdata
+const MAPI_ONE_OFF_UID: [u8; 16] = [
+    0x81, 0x2B, 0x1F, 0xA4, 0xBE, 0xA3, 0x10, 0x19, 0x9D, 0x6E, 0x00, 0xDD, 0x01, 0x0F, 0x54, 0x02,
+];
+
+/// File name Outlook gives the contact photo attachment.
+const PHOTO_FILE_NAME: &str = "ContactPicture.jpg";
+
+/// One of the three e-mail addresses Outlook stores on a contact.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmailSlot {
+    Email1,
+    Email2,
+    Email3,
+}
+
+impl EmailSlot {
+    /// `PidLidEmailDisplayName`, `PidLidEmailAddressType`, `PidLidEmailEmailAddress`,
+    /// `PidLidEmailOriginalDisplayName`, and `PidLidEmailOriginalEntryId` for this slot, in the
+    /// [`sys::PSETID_Address`] property set.
+    fn lids(self) -> [i32; 5] {
+        let base = match self {
+            Self::Email1 => 0x8080,
+            Self::Email2 => 0x8090,
+            Self::Email3 => 0x80A0,
+        };
+        [base, base + 2, base + 3, base + 4, base + 5]
+    }
+
+    /// Index of this slot in `PidLidAddressBookProviderEmailList`, and the bit for it in
+    /// `PidLidAddressBookProviderArrayType`.
+    fn index(self) -> u32 {
+        match self {
+            Self::Email1 => 0,
+            Self::Email2 => 1,
+            Self::Email3 => 2,
+        }
+    }
+}
+
+/// E-mail address in one of the [`EmailSlot`] entries on a contact.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ContactEmail {
+    /// `PidLidEmailEmailAddress`, e.g. `zoe@example.com`.
+    pub address: String,
+
+    /// `PidLidEmailAddressType`, usually `SMTP`.
+    pub address_type: String,
+
+    /// `PidLidEmailDisplayName`, which Outlook shows in address lists, e.g.
+    /// `Zoë (zoe@example.com)`.
+    pub display_name: Option<String>,
+}
+
+/// Phone numbers stored in standard properties on a contact.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PhoneKind {
+    /// [`sys::PR_BUSINESS_TELEPHONE_NUMBER_W`]
+    Business,
+
+    /// [`sys::PR_HOME_TELEPHONE_NUMBER_W`]
+    Home,
+
+    /// [`sys::PR_MOBILE_TELEPHONE_NUMBER_W`]
+    Mobile,
+
+    /// [`sys::PR_BUSINESS_FAX_NUMBER_W`]
+    BusinessFax,
+}
+
+impl From<PhoneKind> for PropTag {
+    fn from(value: PhoneKind) -> Self {
+        PropTag(match value {
+            PhoneKind::Business => sys::PR_BUSINESS_TELEPHONE_NUMBER_W,
+            PhoneKind::Home => sys::PR_HOME_TELEPHONE_NUMBER_W,
+            PhoneKind::Mobile => sys::PR_MOBILE_TELEPHONE_NUMBER_W,
+            PhoneKind::BusinessFax => sys::PR_BUSINESS_FAX_NUMBER_W,
+        })
+    }
+}
+
+/// Hold on to a contact [`Message`], e.g. from the [`crate::SpecialFolder::Contacts`] folder, and
+/// expose the contact-specific operations.
+pub struct Contact {
+    /// Access the contact [`Message`].
+    pub message: Message,
+}
+
+impl Contact {
+    /// Wrap a contact [`Message`].
+    pub fn new(message: Message) -> Self {
+        Self { message }
+    }
+
+    /// Get [`sys::PR_DISPLAY_NAME_W`].
+    pub fn display_name(&self) -> Result<Option<String>> {
+        self.get_string(PropTag(sys::PR_DISPLAY_NAME_W))
+    }
+
+    /// Set [`sys::PR_DISPLAY_NAME_W`]. Call [`MAPIProp::save_changes`] on the
+    /// [`Contact::message`] to keep the changes, the same as the other setters.
+    pub fn set_display_name(&self, display_name: &str) -> Result<()> {
+        self.set_strings(&[(PropTag(sys::PR_DISPLAY_NAME_W), display_name)])
+    }
+
+    /// Get `PidLidFileUnder`, the name Outlook sorts the contact by, e.g. `Smith, Zoë`.
+    pub fn file_as(&self) -> Result<Option<String>> {
+        self.get_named_string(LID_FILE_UNDER)
+    }
+
+    /// Set `PidLidFileUnder`.
+    pub fn set_file_as(&self, file_as: &str) -> Result<()> {
+        self.set_named_strings(&[(LID_FILE_UNDER, file_as)])
+    }
+
+    /// Get `PidLidInstantMessagingAddress`.
+    pub fn im_address(&self) -> Result<Option<String>> {
+        self.get_named_string(LID_INSTANT_MESSAGING_ADDRESS)
+    }
+
+    /// Set `PidLidInstantMessagingAddress`.
+    pub fn set_im_address(&self, im_address: &str) -> Result<()> {
+        self.set_named_strings(&[(LID_INSTANT_MESSAGING_ADDRESS, im_address)])
+    }
+
+    /// Get one of the phone numbers.
+    pub fn phone(&self, kind: PhoneKind) -> Result<Option<String>> {
+        self.get_string(kind.into())
+    }
+
+    /// Set one of the phone numbers.
+    pub fn set_phone(&self, kind: PhoneKind, number: &str) -> Result<()> {
+        self.set_strings(&[(kind.into(), number)])
+    }
+
+    /// Get the e-mail address in `slot`, or [`None`] if it is empty. The display name and
+    /// address type are optional, so this still returns the address if either one is missing.
+    pub fn email(&self, slot: EmailSlot) -> Result<Option<ContactEmail>> {
+        let [display_name, address_type, address, ..] = slot.lids();
+        let mut values = self
+            .get_named_strings(&[display_name, address_type, address])?
+            .into_iter();
+        let display_name = values.next().flatten();
+        let address_type = values.next().flatten();
+        let Some(address) = values.next().flatten() else {
+            return Ok(None);
+        };
+        Ok(Some(ContactEmail {
+            address,
+            address_type: address_type.unwrap_or_default(),
+            display_name,
+        }))
+    }
+
+    /// Set the e-mail address in `slot`. `PidLidEmailOriginalDisplayName` is set to the
+    /// [`ContactEmail::address`], and the [`ContactEmail::display_name`] defaults to the address
+    /// too.
+    ///
+    /// Outlook also needs `PidLidEmailOriginalEntryId`, which is set to a one-off entry ID for the
+    /// address, and the slot must be listed in `PidLidAddressBookProviderEmailList` and
+    /// `PidLidAddressBookProviderArrayType` for the contact to show up in the Outlook Address
+    /// Book.
+    pub fn set_email(&self, slot: EmailSlot, email: &ContactEmail) -> Result<()> {
+        let [display_name, address_type, address, original_display_name, original_entry_id] =
+            slot.lids();
+        let display_name_value = email.display_name.as_deref().unwrap_or(&email.address);
+        self.set_named_strings(&[
+            (display_name, display_name_value),
+            (address_type, &email.address_type),
+            (address, &email.address),
+            (original_display_name, &email.address),
+        ])?;
+
+        let entry_id = one_off_entry_id(display_name_value, &email.address_type, &email.address);
+        let tag = self.address_tag(original_entry_id, sys::PT_BINARY, true)?;
+        self.message.set_props(&[PropValue {
+            tag,
+            value: PropValueData::Binary(&entry_id),
+        }])?;
+
+        let array_type = self.provider_array_type()? | (1 << slot.index());
+        self.set_provider_slots(array_type)
+    }
+
+    /// Delete the e-mail address in `slot`, and remove it from
+    /// `PidLidAddressBookProviderEmailList` and `PidLidAddressBookProviderArrayType`.
+    pub fn remove_email(&self, slot: EmailSlot) -> Result<()> {
+        let tags: Vec<_> = self
+            .address_tags(&slot.lids(), false)?
+            .into_iter()
+            .flatten()
+            .collect();
+        if tags.is_empty() {
+            return Ok(());
+        }
+        self.message.delete_props(&tags)?;
+
+        let array_type = self.provider_array_type()? & !(1 << slot.index());
+        self.set_provider_slots(array_type)
+    }
+
+    /// Read the contents of the contact photo attachment, or [`None`] if the contact does not
+    /// have one.
+    pub fn photo(&self) -> Result<Option<Vec<u8>>> {
+        let Some(attach_num) = self.photo_attachments()?.into_iter().next() else {
+            return Ok(None);
+        };
+        let mut stream = self
+            .message
+            .open_attachment(attach_num)?
+            .open_property_stream(PropTag(sys::PR_ATTACH_DATA_BIN), Default::default())?;
+        let mut photo = Vec::new();
+        stream.read_to_end(&mut photo)?;
+        Ok(Some(photo))
+    }
+
+    /// Replace the contact photo with a JPEG image.
+    ///
+    /// This deletes any existing contact photo attachments, adds a new hidden attachment with the
+    /// properties Outlook expects, and sets `PidLidHasPicture` on the contact. The attachment is
+    /// saved, but you still need to call [`MAPIProp::save_changes`] on the
+    /// [`Contact::message`] to keep the changes.
+    pub fn set_photo(&self, jpeg: &[u8]) -> Result<()> {
+        for attach_num in self.photo_attachments()? {
+            self.message.delete_attachment(attach_num)?;
+        }
+
+        let attachment = self.message.create_attachment()?;
+        let names = photo_names();
+        attachment.set_props(&photo_props(&names))?;
+        let mut stream = attachment.open_property_stream(
+            PropTag(sys::PR_ATTACH_DATA_BIN),
+            OpenPropertyFlags {
+                create: true,
+                modify: true,
+                ..Default::default()
+            },
+        )?;
+        stream.write_all(jpeg)?;
+        stream.flush()?;
+        drop(stream);
+        attachment.save_changes(Default::default())?;
+
+        self.set_has_picture(true)
+    }
+
+    /// Delete any contact photo attachments and clear `PidLidHasPicture`. Call
+    /// [`MAPIProp::save_changes`] on the [`Contact::message`] to keep the changes.
+    pub fn remove_photo(&self) -> Result<()> {
+        for attach_num in self.photo_attachments()? {
+            self.message.delete_attachment(attach_num)?;
+        }
+        self.set_has_picture(false)
+    }
+
+    /// Get the [`sys::PR_ATTACH_NUM`] of every attachment with `PidTagAttachmentContactPhoto`.
+    fn photo_attachments(&self) -> Result<Vec<u32>> {
+        let rows = self
+            .message
+            .get_attachment_table(Default::default())?
+            .query_all_rows(
+                &[
+                    PropTag(sys::PR_ATTACH_NUM),
+                    PropTag(PR_ATTACHMENT_CONTACTPHOTO),
+                ],
+                None,
+                None,
+            )?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                let mut values = row.iter();
+                let attach_num = match values.next()?.value {
+                    PropValueData::Long(attach_num) => attach_num as u32,
+                    _ => return None,
+                };
+                matches!(values.next()?.value, PropValueData::Boolean(value) if value != 0)
+                    .then_some(attach_num)
+            })
+            .collect())
+    }
+
+    /// Set `PidLidHasPicture` on the contact.
+    fn set_has_picture(&self, has_picture: bool) -> Result<()> {
+        let tag = self.address_tag(LID_HAS_PICTURE, sys::PT_BOOLEAN, true)?;
+        self.message.set_props(&[PropValue {
+            tag,
+            value: PropValueData::Boolean(u16::from(has_picture)),
+        }])
+    }
+
+    /// Get `PidLidAddressBookProviderArrayType`, or `0` if it is not set.
+    fn provider_array_type(&self) -> Result<u32> {
+        let tag = self.address_tag(LID_AB_PROVIDER_ARRAY_TYPE, sys::PT_LONG, true)?;
+        let props = self.message.get_props(&[tag])?;
+        let array_type = props.iter().next().and_then(|value| match value.value {
+            PropValueData::Long(value) => Some(value as u32),
+            _ => None,
+        });
+        Ok(array_type.unwrap_or_default())
+    }
+
+    /// Set `PidLidAddressBookProviderArrayType` to `array_type`, and
+    /// `PidLidAddressBookProviderEmailList` to the slots which are set in it. The list is deleted
+    /// if none of them are set, because multi-valued properties can not be empty.
+    fn set_provider_slots(&self, array_type: u32) -> Result<()> {
+        let array_tag = self.address_tag(LID_AB_PROVIDER_ARRAY_TYPE, sys::PT_LONG, true)?;
+        let list_tag = self.address_tag(LID_AB_PROVIDER_EMAIL_LIST, sys::PT_MV_LONG, true)?;
+        let list = provider_email_list(array_type);
+        self.message.set_props(&[PropValue {
+            tag: array_tag,
+            value: PropValueData::Long(array_type as i32),
+        }])?;
+        if list.is_empty() {
+            self.message.delete_props(&[list_tag])?;
+            Ok(())
+        } else {
+            self.message.set_props(&[PropValue {
+                tag: list_tag,
+                value: PropValueData::LongArray(&list),
+            }])
+        }
+    }
+
+    /// Get the property tag for one named property in the [`sys::PSETID_Address`] property set,
+    /// with a type of `prop_type`.
+    fn address_tag(&self, lid: i32, prop_type: u32, create: bool) -> Result<PropTag> {
+        self.address_tags(&[lid], create)?
+            .into_iter()
+            .next()
+            .flatten()
+            .map(|tag| tag.change_prop_type(PropType::new(prop_type as u16)))
+            .ok_or_else(|| Error::from(sys::MAPI_E_NOT_FOUND))
+    }
+
+    /// Call [`sys::IMAPIProp::GetIDsFromNames`] to get the property tags for named properties in
+    /// the [`sys::PSETID_Address`] property set, with a type of [`sys::PT_UNICODE`]. If `create`
+    /// is `true`, this passes [`sys::MAPI_CREATE`]. Otherwise, names which the store has never
+    /// seen are returned as [`None`].
+    fn address_tags(&self, lids: &[i32], create: bool) -> Result<Vec<Option<PropTag>>> {
+        let mut names: Vec<_> = lids
+            .iter()
+            .map(|lid| sys::MAPINAMEID {
+                lpguid: &sys::PSETID_Address as *const _ as *mut _,
+                ulKind: sys::MNID_ID,
+                Kind: sys::MAPINAMEID_0 { lID: *lid },
+            })
+            .collect();
+        let mut names: Vec<_> = names.iter_mut().map(ptr::from_mut).collect();
+        let flags = if create { sys::MAPI_CREATE } else { 0 };
+        let mut prop_tags: MAPIOutParam<sys::SPropTagArray> = MAPIOutParam::new();
+        unsafe {
+            self.message.mapi_prop().GetIDsFromNames(
+                names.len() as u32,
+                names.as_mut_ptr(),
+                flags,
+                prop_tags.as_mut_ptr(),
+            )?;
+            let prop_tags = prop_tags.as_mut().ok_or_else(|| Error::from(E_POINTER))?;
+            let count = (prop_tags.cValues as usize).min(lids.len());
+            let tags = slice::from_raw_parts(prop_tags.aulPropTag.as_ptr(), count);
+            Ok(tags
+                .iter()
+                .map(|tag| {
+                    let tag = PropTag(*tag);
+                    (u32::from(tag.prop_type()) != sys::PT_ERROR)
+                        .then(|| tag.change_prop_type(PropType::new(sys::PT_UNICODE as u16)))
+                })
+                .collect())
+        }
+    }
+
+    fn get_string(&self, tag: PropTag) -> Result<Option<String>> {
+        let props = self.message.get_props(&[tag])?;
+        let value = props
+            .iter()
+            .next()
+            .and_then(|value| value.value.as_string());
+        Ok(value)
+    }
+
+    fn get_named_string(&self, lid: i32) -> Result<Option<String>> {
+        Ok(self.get_named_strings(&[lid])?.into_iter().next().flatten())
+    }
+
+    /// Get the named string properties in `lids`, with [`None`] for each one which is missing.
+    fn get_named_strings(&self, lids: &[i32]) -> Result<Vec<Option<String>>> {
+        let tags = self.address_tags(lids, false)?;
+        let present: Vec<_> = tags.iter().flatten().copied().collect();
+        if present.is_empty() {
+            return Ok(vec![None; lids.len()]);
+        }
+        let props = self.message.get_props(&present)?;
+        let mut values = props.iter().map(|value| value.value.as_string());
+        Ok(tags
+            .iter()
+            .map(|tag| tag.and_then(|_| values.next().flatten()))
+            .collect())
+    }
+
+    fn set_strings(&self, values: &[(PropTag, &str)]) -> Result<()> {
+        let values: Vec<_> = values
+            .iter()
+            .map(|(tag, value)| PropValue {
+                tag: *tag,
+                value: PropValueData::Unicode(to_pwstr_buffer(value)),
+            })
+            .collect();
+        self.message.set_props(&values)
+    }
+
+    fn set_named_strings(&self, values: &[(i32, &str)]) -> Result<()> {
+        let lids: Vec<_> = values.iter().map(|(lid, _)| *lid).collect();
+        let tags = self.address_tags(&lids, true)?;
+        let values = tags
+            .into_iter()
+            .zip(values)
+            .map(|(tag, (_, value))| {
+                Ok((
+                    tag.ok_or_else(|| Error::from(sys::MAPI_E_NOT_FOUND))?,
+                    *value,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.set_strings(&values)
+    }
+}
+
+impl From<Message> for Contact {
+    fn from(value: Message) -> Self {
+        Self::new(value)
+    }
+}
+
+/// Build a Unicode one-off entry ID for an e-mail address, as described in [MS-OXCDATA] section
+/// 2.2.5.1.
+///
+/// [MS-OXCDATA]: https://learn.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxcdata
+fn one_off_entry_id(display_name: &str, address_type: &str, address: &str) -> Vec<u8> {
+    let mut entry_id = vec![0; 4];
+    entry_id.extend_from_slice(&MAPI_ONE_OFF_UID);
+    entry_id.extend_from_slice(&0_u16.to_le_bytes());
+    entry_id.extend_from_slice(&(MAPI_ONE_OFF_UNICODE | MAPI_ONE_OFF_NO_RICH_INFO).to_le_bytes());
+    for value in [display_name, address_type, address] {
+        entry_id.extend(
+            to_pwstr_buffer(value)
+                .into_iter()
+                .flat_map(u16::to_le_bytes),
+        );
+    }
+    entry_id
+}
+
+/// List the slots in `PidLidAddressBookProviderArrayType` for
+/// `PidLidAddressBookProviderEmailList`, including the fax slots `3` to `5`.
+fn provider_email_list(array_type: u32) -> Vec<i32> {
+    (0..6)
+        .filter(|index| array_type & (1 << index) != 0)
+        .collect()
+}
+
+/// `null` terminated [`PHOTO_FILE_NAME`], extension, and MIME type for [`photo_props`].
+struct PhotoNames {
+    file_name: Vec<u16>,
+    extension: Vec<u16>,
+    mime_tag: Vec<u16>,
+}
+
+fn photo_names() -> PhotoNames {
+    PhotoNames {
+        file_name: to_pwstr_buffer(PHOTO_FILE_NAME),
+        extension: to_pwstr_buffer(".jpg"),
+        mime_tag: to_pwstr_buffer("image/jpeg"),
+    }
+}
+
+/// Properties Outlook sets on a contact photo attachment, other than the data.
+fn photo_props(names: &PhotoNames) -> Vec<PropValue> {
+    let unicode = |tag: u32, value: &[u16]| PropValue {
+        tag: PropTag(tag),
+        value: PropValueData::Unicode(value.to_vec()),
+    };
+    let boolean = |tag: u32| PropValue {
+        tag: PropTag(tag),
+        value: PropValueData::Boolean(1),
+    };
+    vec![
+        PropValue {
+            tag: PropTag(sys::PR_ATTACH_METHOD),
+            value: PropValueData::Long(sys::ATTACH_BY_VALUE as i32),
+        },
+        PropValue {
+            tag: PropTag(sys::PR_RENDERING_POSITION),
+            value: PropValueData::Long(-1),
+        },
+        unicode(sys::PR_ATTACH_FILENAME_W, &names.file_name),
+        unicode(sys::PR_ATTACH_LONG_FILENAME_W, &names.file_name),
+        unicode(sys::PR_DISPLAY_NAME_W, &names.file_name),
+        unicode(sys::PR_ATTACH_EXTENSION_W, &names.extension),
+        unicode(sys::PR_ATTACH_MIME_TAG_W, &names.mime_tag),
+        boolean(PR_ATTACHMENT_CONTACTPHOTO),
+        boolean(PR_ATTACHMENT_HIDDEN),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn photo_attachment_props() {
+        let names = photo_names();
+        let props = photo_props(&names);
+        let find = |tag: u32| {
+            props
+                .iter()
+                .find(|value| value.tag == PropTag(tag))
+                .map(|value| &value.value)
+        };
+
+        assert!(matches!(
+            find(PR_ATTACHMENT_CONTACTPHOTO),
+            Some(PropValueData::Boolean(1))
+        ));
+        assert!(matches!(
+            find(PR_ATTACHMENT_HIDDEN),
+            Some(PropValueData::Boolean(1))
+        ));
+        assert!(matches!(
+            find(sys::PR_ATTACH_METHOD),
+            Some(PropValueData::Long(method)) if *method as u32 == sys::ATTACH_BY_VALUE
+        ));
+        assert_eq!(
+            find(sys::PR_ATTACH_LONG_FILENAME_W).and_then(PropValueData::as_string),
+            Some(String::from(PHOTO_FILE_NAME))
+        );
+        assert_eq!(
+            find(sys::PR_ATTACH_MIME_TAG_W).and_then(PropValueData::as_string),
+            Some(String::from("image/jpeg"))
+        );
+    }
+
+    #[test]
+    fn email_slot_lids() {
+        assert_eq!(
+            EmailSlot::Email1.lids(),
+            [0x8080, 0x8082, 0x8083, 0x8084, 0x8085]
+        );
+        assert_eq!(
+            EmailSlot::Email3.lids(),
+            [0x80A0, 0x80A2, 0x80A3, 0x80A4, 0x80A5]
+        );
+        assert_eq!(
+            PropTag::from(PhoneKind::Mobile),
+            PropTag(sys::PR_MOBILE_TELEPHONE_NUMBER_W)
+        );
+    }
+
+    #[test]
+    fn one_off_email_entry_id() {
+        let entry_id = one_off_entry_id("Zoë", "SMTP", "z@x");
+        assert_eq!(&entry_id[..4], &[0; 4]);
+        assert_eq!(&entry_id[4..20], &MAPI_ONE_OFF_UID);
+        assert_eq!(&entry_id[20..24], &[0x00, 0x00, 0x01, 0x80]);
+        let strings: Vec<_> = entry_id[24..]
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        let expected: Vec<_> = ["Zoë", "SMTP", "z@x"]
+            .into_iter()
+            .flat_map(to_pwstr_buffer)
+            .collect();
+        assert_eq!(strings, expected);
+    }
+
+    #[test]
+    fn provider_slots() {
+        assert_eq!(provider_email_list(0), Vec::<i32>::new());
+        assert_eq!(provider_email_list(0b101), [0, 2]);
+        assert_eq!(provider_email_list(0b11_0010), [1, 4, 5]);
+        assert_eq!(EmailSlot::Email3.index(), 2);
+    }
+
+    #[test]
+    fn photo_tags() {
+        assert_eq!(
+            u32::from(PropTag(PR_ATTACHMENT_CONTACTPHOTO).prop_type()),
+            sys::PT_BOOLEAN
+        );
+        assert_eq!(PropTag(PR_ATTACHMENT_HIDDEN).prop_id(), 0x7FFE);
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`ConversationIndex`] and [`ResponseLevel`].
+
+use crate::sys;
+use windows::Win32::Foundation::FILETIME;
+use windows_core::*;
+
+/// Size of the header block, with the reserved byte, the [`ConversationIndex::time`], and the
+/// [`ConversationIndex::guid`].
+const HEADER_SIZE: usize = 22;
+
+/// Size of each [`ResponseLevel`] block after the header.
+const RESPONSE_LEVEL_SIZE: usize = 5;
+
+/// One reply or forward in a [`ConversationIndex`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResponseLevel {
+    /// Time since the previous block, in 100-nanosecond intervals like a [`FILETIME`]. Only the
+    /// 31 bits which are stored in the block are kept, so it is rounded down.
+    pub time_delta: u64,
+
+    /// Random value from the low 4 bits of the block.
+    pub random: u8,
+
+    /// Sequence number from the high 4 bits of the last byte of the block.
+    pub level: u8,
+}
+
+/// Decoded [`sys::PR_CONVERSATION_INDEX`], described in [MS-OXOMSG] section 2.2.1.3. It starts
+/// with a header block for the first message in the conversation, followed by a
+/// [`ResponseLevel`] for each reply or forward.
+///
+/// [MS-OXOMSG]: https://learn.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxomsg
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConversationIndex {
+    /// Time the conversation started. Only the upper 40 bits are stored in the header.
+    pub time: FILETIME,
+
+    /// Unique ID of the conversation.
+    pub guid: GUID,
+
+    /// Replies and forwards, in order.
+    pub responses: Vec<ResponseLevel>,
+}
+
+impl ConversationIndex {
+    /// Decode a [`sys::PR_CONVERSATION_INDEX`] value. Returns [`sys::MAPI_E_CORRUPT_DATA`] if it
+    /// is shorter than the header, or if it ends in the middle of a [`ResponseLevel`].
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < HEADER_SIZE || (data.len() - HEADER_SIZE) % RESPONSE_LEVEL_SIZE != 0 {
+            return Err(Error::from(sys::MAPI_E_CORRUPT_DATA));
+        }
+        let (header, blocks) = data.split_at(HEADER_SIZE);
+
+        let mut time = [0; 8];
+        time[..5].copy_from_slice(&header[1..6]);
+        let time = u64::from_be_bytes(time);
+        let guid = GUID::from_values(
+            u32::from_le_bytes(header[6..10].try_into().expect("4 bytes")),
+            u16::from_le_bytes(header[10..12].try_into().expect("2 bytes")),
+            u16::from_le_bytes(header[12..14].try_into().expect("2 bytes")),
+            header[14..].try_into().expect("8 bytes"),
+        );
+
+        let responses = blocks
+            .chunks_exact(RESPONSE_LEVEL_SIZE)
+            .map(|block| {
+                let bits = u32::from_be_bytes(block[..4].try_into().expect("4 bytes"));
+                let delta = u64::from(bits & 0x7FFF_FFFF);
+                let time_delta = if bits & 0x8000_0000 == 0 {
+                    delta << 18
+                } else {
+                    delta << 23
+                };
+                ResponseLevel {
+                    time_delta,
+                    random: block[4] & 0x0F,
+                    level: block[4] >> 4,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            time: FILETIME {
+                dwLowDateTime: time as u32,
+                dwHighDateTime: (time >> 32) as u32,
+            },
+            guid,
+            responses,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_header_and_responses() {
+        let mut data = vec![0x01, 0x01, 0xD9, 0x2A, 0x3B, 0x4C];
+        data.extend_from_slice(&[
+            0x33, 0x22, 0x11, 0x00, 0x55, 0x44, 0x77, 0x66, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD,
+            0xEE, 0xFF,
+        ]);
+        data.extend_from_slice(&[0x00, 0x00, 0x00, 0x02, 0x15]);
+        data.extend_from_slice(&[0x80, 0x00, 0x00, 0x01, 0x2F]);
+
+        let index = ConversationIndex::parse(&data).expect("parse failed");
+        assert_eq!(index.time.dwHighDateTime, 0x01D9_2A3B);
+        assert_eq!(index.time.dwLowDateTime, 0x4C00_0000);
+        assert_eq!(
+            index.guid,
+            GUID::from_u128(0x0011_2233_4455_6677_8899_AABB_CCDD_EEFF)
+        );
+        assert_eq!(
+            index.responses,
+            [
+                ResponseLevel {
+                    time_delta: 2 << 18,
+                    random: 0x5,
+                    level: 0x1,
+                },
+                ResponseLevel {
+                    time_delta: 1 << 23,
+                    random: 0xF,
+                    level: 0x2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_truncated() {
+        let data = [0x01; HEADER_SIZE + RESPONSE_LEVEL_SIZE + 2];
+        for len in [0, HEADER_SIZE - 1, HEADER_SIZE + 2, data.len()] {
+            let error = ConversationIndex::parse(&data[..len]).expect_err("should not parse");
+            assert_eq!(error.code(), sys::MAPI_E_CORRUPT_DATA);
+        }
+        assert!(ConversationIndex::parse(&data[..HEADER_SIZE]).is_ok());
+    }
+}
@@ -0,0 +1,489 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`DisplayTableBuilder`], [`DisplayPage`], [`DisplayControlFlags`], and
+//! [`DisplayTable`].
+//!
+//! A display table describes the property pages of a configuration dialog to
+//! [`sys::BuildDisplayTable`]. Each [`sys::DTPAGE`] points to an array of [`sys::DTCTL`] controls,
+//! and each control points to a variable length `DTBL*` structure with its strings stored after
+//! the fixed members. The [`crate::SizedDtblLabel!`] family of macros declares those structures
+//! one at a time with sizes known at compile time; [`DisplayTableBuilder`] assembles all of them
+//! at runtime and keeps the buffers alive in the [`DisplayTable`].
+
+use crate::{sys, to_pwstr_buffer, PropTag};
+use core::{mem, ptr};
+use windows_core::*;
+
+/// Character filter for [`sys::DTCT_EDIT`] controls which allows any input. Every control is
+/// built with [`sys::MAPI_UNICODE`], so [`sys::BuildDisplayTable`] reads this as a wide string.
+const ALLOW_ANY: PCWSTR = w!("*");
+
+/// Set of flags that can be set in [`sys::DTCTL::ulCtlFlags`] for an edit control or check box.
+#[derive(Default)]
+pub struct DisplayControlFlags {
+    /// Pass [`sys::DT_EDITABLE`].
+    pub editable: bool,
+
+    /// Pass [`sys::DT_REQUIRED`].
+    pub required: bool,
+
+    /// Pass [`sys::DT_MULTILINE`].
+    pub multiline: bool,
+
+    /// Pass [`sys::DT_PASSWORD_EDIT`].
+    pub password: bool,
+
+    /// Pass [`sys::DT_SET_IMMEDIATE`].
+    pub set_immediate: bool,
+
+    /// Pass [`sys::DT_ACCEPT_DBCS`].
+    pub accept_dbcs: bool,
+}
+
+impl From<DisplayControlFlags> for u32 {
+    fn from(value: DisplayControlFlags) -> Self {
+        let editable = if value.editable { sys::DT_EDITABLE } else { 0 };
+        let required = if value.required { sys::DT_REQUIRED } else { 0 };
+        let multiline = if value.multiline {
+            sys::DT_MULTILINE
+        } else {
+            0
+        };
+        let password = if value.password {
+            sys::DT_PASSWORD_EDIT
+        } else {
+            0
+        };
+        let set_immediate = if value.set_immediate {
+            sys::DT_SET_IMMEDIATE
+        } else {
+            0
+        };
+        let accept_dbcs = if value.accept_dbcs {
+            sys::DT_ACCEPT_DBCS
+        } else {
+            0
+        };
+
+        editable | required | multiline | password | set_immediate | accept_dbcs
+    }
+}
+
+/// One control on a [`DisplayPage`], before it is laid out in a [`DisplayTable`].
+enum DisplayControl {
+    Page {
+        label: String,
+        context: u32,
+    },
+    Label {
+        item_id: u32,
+        label: String,
+    },
+    Edit {
+        item_id: u32,
+        flags: u32,
+        tag: PropTag,
+        max_chars: u32,
+    },
+    CheckBox {
+        item_id: u32,
+        flags: u32,
+        tag: PropTag,
+        label: String,
+    },
+    GroupBox {
+        item_id: u32,
+        label: String,
+    },
+}
+
+/// Page in a [`DisplayTableBuilder`], backed by a dialog template resource. The first control on
+/// every page is a [`sys::DTCT_PAGE`] control with the label for its tab.
+pub struct DisplayPage {
+    resource_id: u16,
+    component_id: u32,
+    controls: Vec<DisplayControl>,
+}
+
+impl DisplayPage {
+    /// Start a page which uses the dialog template with `resource_id` and shows `label` on its
+    /// tab.
+    pub fn new(resource_id: u16, label: &str) -> Self {
+        Self {
+            resource_id,
+            component_id: 0,
+            controls: vec![DisplayControl::Page {
+                label: label.to_string(),
+                context: 0,
+            }],
+        }
+    }
+
+    /// Set [`sys::DTPAGE::ulItemID`](sys::DTPAGE_0::ulItemID) to the resource ID of the string
+    /// with the component name for help, and the help context of the [`sys::DTBLPAGE`].
+    pub fn help(mut self, component_id: u32, context: u32) -> Self {
+        self.component_id = component_id;
+        if let Some(DisplayControl::Page {
+            context: page_context,
+            ..
+        }) = self.controls.first_mut()
+        {
+            *page_context = context;
+        }
+        self
+    }
+
+    /// Add a [`sys::DTCT_LABEL`] control.
+    pub fn label(mut self, item_id: u32, label: &str) -> Self {
+        self.controls.push(DisplayControl::Label {
+            item_id,
+            label: label.to_string(),
+        });
+        self
+    }
+
+    /// Add a [`sys::DTCT_EDIT`] control bound to the `tag` property, which accepts up to
+    /// `max_chars` characters.
+    pub fn edit(
+        mut self,
+        item_id: u32,
+        tag: PropTag,
+        max_chars: u32,
+        flags: DisplayControlFlags,
+    ) -> Self {
+        self.controls.push(DisplayControl::Edit {
+            item_id,
+            flags: flags.into(),
+            tag,
+            max_chars,
+        });
+        self
+    }
+
+    /// Add a [`sys::DTCT_CHECKBOX`] control bound to the [`sys::PT_BOOLEAN`] `tag` property.
+    pub fn check_box(
+        mut self,
+        item_id: u32,
+        tag: PropTag,
+        label: &str,
+        flags: DisplayControlFlags,
+    ) -> Self {
+        self.controls.push(DisplayControl::CheckBox {
+            item_id,
+            flags: flags.into(),
+            tag,
+            label: label.to_string(),
+        });
+        self
+    }
+
+    /// Start a section of the page with a [`sys::DTCT_GROUPBOX`] control around the controls
+    /// which follow it in the dialog template.
+    pub fn section(mut self, item_id: u32, label: &str) -> Self {
+        self.controls.push(DisplayControl::GroupBox {
+            item_id,
+            label: label.to_string(),
+        });
+        self
+    }
+}
+
+/// Compose the [`sys::DTPAGE`] array for [`sys::BuildDisplayTable`] from [`DisplayPage`] entries.
+#[derive(Default)]
+pub struct DisplayTableBuilder {
+    pages: Vec<DisplayPage>,
+}
+
+impl DisplayTableBuilder {
+    /// Add a page.
+    pub fn page(mut self, page: DisplayPage) -> Self {
+        self.pages.push(page);
+        self
+    }
+
+    /// Lay out the [`sys::DTPAGE`] and [`sys::DTCTL`] arrays and a `DTBL*` structure with
+    /// [`sys::MAPI_UNICODE`] strings for each control.
+    pub fn build(self) -> Result<DisplayTable> {
+        let mut table = DisplayTable {
+            pages: Vec::with_capacity(self.pages.len()),
+            controls: Vec::with_capacity(self.pages.len()),
+            buffers: Vec::new(),
+        };
+        for page in self.pages {
+            let mut controls = Vec::with_capacity(page.controls.len());
+            for control in page.controls {
+                let (ctl_type, ctl_flags, item_id, filter, mut buffer) = match control {
+                    DisplayControl::Page { label, context } => (
+                        sys::DTCT_PAGE,
+                        0,
+                        0,
+                        ptr::null(),
+                        control_buffer(&[&label, ""], |offsets| sys::DTBLPAGE {
+                            ulbLpszLabel: offsets[0],
+                            ulFlags: sys::MAPI_UNICODE,
+                            ulbLpszComponent: offsets[1],
+                            ulContext: context,
+                        })?,
+                    ),
+                    DisplayControl::Label { item_id, label } => (
+                        sys::DTCT_LABEL,
+                        0,
+                        item_id,
+                        ptr::null(),
+                        control_buffer(&[&label], |offsets| sys::DTBLLABEL {
+                            ulbLpszLabelName: offsets[0],
+                            ulFlags: sys::MAPI_UNICODE,
+                        })?,
+                    ),
+                    DisplayControl::Edit {
+                        item_id,
+                        flags,
+                        tag,
+                        max_chars,
+                    } => (
+                        sys::DTCT_EDIT,
+                        flags,
+                        item_id,
+                        ALLOW_ANY.as_ptr(),
+                        control_buffer(&["*"], |offsets| sys::DTBLEDIT {
+                            ulbLpszCharsAllowed: offsets[0],
+                            ulFlags: sys::MAPI_UNICODE,
+                            ulNumCharsAllowed: max_chars,
+                            ulPropTag: tag.0,
+                        })?,
+                    ),
+                    DisplayControl::CheckBox {
+                        item_id,
+                        flags,
+                        tag,
+                        label,
+                    } => (
+                        sys::DTCT_CHECKBOX,
+                        flags,
+                        item_id,
+                        ptr::null(),
+                        control_buffer(&[&label], |offsets| sys::DTBLCHECKBOX {
+                            ulbLpszLabel: offsets[0],
+                            ulFlags: sys::MAPI_UNICODE,
+                            ulPRPropertyName: tag.0,
+                        })?,
+                    ),
+                    DisplayControl::GroupBox { item_id, label } => (
+                        sys::DTCT_GROUPBOX,
+                        0,
+                        item_id,
+                        ptr::null(),
+                        control_buffer(&[&label], |offsets| sys::DTBLGROUPBOX {
+                            ulbLpszLabel: offsets[0],
+                            ulFlags: sys::MAPI_UNICODE,
+                        })?,
+                    ),
+                };
+
+                // Moving the buffer into the table does not move its contents.
+                controls.push(sys::DTCTL {
+                    ulCtlType: ctl_type,
+                    ulCtlFlags: ctl_flags,
+                    lpszFilter: filter as *mut _,
+                    ulItemID: item_id,
+                    ctl: sys::DTCTL_0 {
+                        lpv: buffer.as_mut_ptr() as *mut _,
+                    },
+                    ..Default::default()
+                });
+                table.buffers.push(buffer);
+            }
+
+            let mut controls = controls.into_boxed_slice();
+            table.pages.push(sys::DTPAGE {
+                cctl: u32::try_from(controls.len())?,
+                // MAKEINTRESOURCE
+                lpszResourceName: usize::from(page.resource_id) as *mut _,
+                Anonymous: sys::DTPAGE_0 {
+                    ulItemID: page.component_id,
+                },
+                lpctl: controls.as_mut_ptr(),
+            });
+            table.controls.push(controls);
+        }
+        Ok(table)
+    }
+}
+
+/// [`sys::DTPAGE`] array built with [`DisplayTableBuilder`], which owns all of the
+/// [`sys::DTCTL`] arrays and `DTBL*` structures it points to. Keep it alive until
+/// [`sys::BuildDisplayTable`] returns.
+pub struct DisplayTable {
+    pages: Vec<sys::DTPAGE>,
+    controls: Vec<Box<[sys::DTCTL]>>,
+    buffers: Vec<Vec<u32>>,
+}
+
+impl DisplayTable {
+    /// Get the number of pages, i.e. the `cPages` parameter of [`sys::BuildDisplayTable`].
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Test for a table without any pages.
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    /// Get the [`sys::DTPAGE`] array to pass as the `lpPage` parameter of
+    /// [`sys::BuildDisplayTable`].
+    pub fn as_mut_ptr(&mut self) -> *mut sys::DTPAGE {
+        self.pages.as_mut_ptr()
+    }
+
+    /// Borrow the [`sys::DTPAGE`] array.
+    pub fn pages(&self) -> &[sys::DTPAGE] {
+        &self.pages
+    }
+}
+
+/// Allocate a `DTBL*` structure followed by each of the `strings` as a NUL terminated UTF-16
+/// string. `header` gets the byte offset of each string, to fill in the `ulbLpsz*` members.
+fn control_buffer<T, F>(strings: &[&str], header: F) -> Result<Vec<u32>>
+where
+    F: FnOnce(&[u32]) -> T,
+{
+    let strings: Vec<Vec<u16>> = strings.iter().map(|value| to_pwstr_buffer(value)).collect();
+    let mut offsets = Vec::with_capacity(strings.len());
+    let mut size = mem::size_of::<T>();
+    for value in strings.iter() {
+        offsets.push(u32::try_from(size)?);
+        size += value.len() * mem::size_of::<u16>();
+    }
+    let header = header(&offsets);
+
+    let mut buffer = vec![0_u32; size.div_ceil(mem::size_of::<u32>())];
+    let base = buffer.as_mut_ptr().cast::<u8>();
+    unsafe {
+        base.cast::<T>().write(header);
+        for (offset, value) in offsets.into_iter().zip(strings) {
+            let target = base.add(offset as usize).cast::<u16>();
+            ptr::copy_nonoverlapping(value.as_ptr(), target, value.len());
+        }
+    }
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::slice;
+    use windows_core::PCWSTR;
+
+    /// Read the NUL terminated UTF-16 string at `offset` bytes from the start of a control.
+    unsafe fn string_at(control: *const core::ffi::c_void, offset: u32) -> String {
+        PCWSTR::from_raw(control.cast::<u8>().add(offset as usize).cast())
+            .to_string()
+            .expect("invalid string")
+    }
+
+    #[test]
+    fn display_control_flags() {
+        assert_eq!(u32::from(DisplayControlFlags::default()), 0);
+        assert_eq!(
+            u32::from(DisplayControlFlags {
+                editable: true,
+                required: true,
+                ..Default::default()
+            }),
+            sys::DT_EDITABLE | sys::DT_REQUIRED
+        );
+    }
+
+    #[test]
+    fn build_display_table() {
+        let mut table = DisplayTableBuilder::default()
+            .page(
+                DisplayPage::new(100, "General")
+                    .help(200, 7)
+                    .section(1000, "Server")
+                    .label(1001, "Name:")
+                    .edit(
+                        1002,
+                        PropTag(sys::PR_DISPLAY_NAME_W),
+                        256,
+                        DisplayControlFlags {
+                            editable: true,
+                            required: true,
+                            ..Default::default()
+                        },
+                    ),
+            )
+            .page(DisplayPage::new(101, "Advanced").check_box(
+                1003,
+                PropTag(sys::PR_DEFAULT_STORE),
+                "Use by default",
+                DisplayControlFlags {
+                    editable: true,
+                    ..Default::default()
+                },
+            ))
+            .build()
+            .expect("build failed");
+        assert_eq!(table.len(), 2);
+        assert!(!table.as_mut_ptr().is_null());
+
+        let pages = table.pages();
+        assert_eq!(pages[0].cctl, 4);
+        assert_eq!(pages[0].lpszResourceName as usize, 100);
+        assert_eq!(unsafe { pages[0].Anonymous.ulItemID }, 200);
+        assert_eq!(pages[1].cctl, 2);
+
+        let controls = unsafe { slice::from_raw_parts(pages[0].lpctl, 4) };
+        let types: Vec<_> = controls.iter().map(|control| control.ulCtlType).collect();
+        assert_eq!(
+            types,
+            [
+                sys::DTCT_PAGE,
+                sys::DTCT_GROUPBOX,
+                sys::DTCT_LABEL,
+                sys::DTCT_EDIT
+            ]
+        );
+        unsafe {
+            let page = &*controls[0].ctl.lpv.cast::<sys::DTBLPAGE>();
+            assert_eq!(page.ulContext, 7);
+            assert_eq!(string_at(controls[0].ctl.lpv, page.ulbLpszLabel), "General");
+            assert_eq!(string_at(controls[0].ctl.lpv, page.ulbLpszComponent), "");
+
+            let label = &*controls[2].ctl.lplabel;
+            assert_eq!(controls[2].ulItemID, 1001);
+            assert_eq!(label.ulFlags, sys::MAPI_UNICODE);
+            assert_eq!(
+                string_at(controls[2].ctl.lpv, label.ulbLpszLabelName),
+                "Name:"
+            );
+
+            let edit = &*controls[3].ctl.lpedit;
+            assert_eq!(controls[3].ulCtlFlags, sys::DT_EDITABLE | sys::DT_REQUIRED);
+            assert_eq!(
+                PCWSTR::from_raw(controls[3].lpszFilter as *const u16)
+                    .to_string()
+                    .expect("invalid filter"),
+                "*"
+            );
+            assert_eq!(edit.ulPropTag, sys::PR_DISPLAY_NAME_W);
+            assert_eq!(edit.ulNumCharsAllowed, 256);
+            assert_eq!(
+                string_at(controls[3].ctl.lpv, edit.ulbLpszCharsAllowed),
+                "*"
+            );
+        }
+
+        let controls = unsafe { slice::from_raw_parts(pages[1].lpctl, 2) };
+        unsafe {
+            let check_box = &*controls[1].ctl.lpcheckbox;
+            assert_eq!(check_box.ulPRPropertyName, sys::PR_DEFAULT_STORE);
+            assert_eq!(
+                string_at(controls[1].ctl.lpv, check_box.ulbLpszLabel),
+                "Use by default"
+            );
+        }
+    }
+}
@@ -0,0 +1,379 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`EntryId`], [`CompareEntryIds`], [`ParsedEntryId`], and [`parse`].
+
+use crate::{sys, Logon, PropValue, PropValueData};
+use windows::Win32::Foundation::*;
+use windows_core::*;
+
+/// Owned copy of a [`sys::PR_ENTRYID`] value, e.g. from a row in a contents or hierarchy table.
+///
+/// Two entry IDs for the same object are not necessarily byte-for-byte identical, so the
+/// [`PartialEq`] implementation only tells you if the bytes match. Use [`EntryId::eq_in`] to ask
+/// the provider whether they refer to the same object.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct EntryId(Vec<u8>);
+
+impl EntryId {
+    /// Take ownership of the bytes in an entry ID.
+    pub fn new(value: Vec<u8>) -> Self {
+        Self(value)
+    }
+
+    /// Get the bytes in the entry ID.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Get the number of bytes in the entry ID, i.e. the `cbEntryID` parameter for most methods.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Check if the entry ID is empty.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Cast to a [`sys::ENTRYID`] pointer, i.e. the `lpEntryID` parameter for most methods.
+    pub fn as_ptr(&self) -> *const sys::ENTRYID {
+        self.0.as_ptr() as *const _
+    }
+
+    /// Call `CompareEntryIDs` on the `container`, e.g. a [`Logon`] session, to check if both entry
+    /// IDs refer to the same object.
+    pub fn eq_in<C>(&self, other: &EntryId, container: &C) -> Result<bool>
+    where
+        C: CompareEntryIds,
+    {
+        container.compare_entry_ids(self, other)
+    }
+
+    /// Decode the header of the entry ID with [`parse`].
+    pub fn parse(&self) -> Result<ParsedEntryId<'_>> {
+        parse(self.as_bytes())
+    }
+}
+
+impl AsRef<[u8]> for EntryId {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl From<Vec<u8>> for EntryId {
+    fn from(value: Vec<u8>) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<&[u8]> for EntryId {
+    fn from(value: &[u8]) -> Self {
+        Self::new(value.to_vec())
+    }
+}
+
+impl TryFrom<&PropValueData<'_>> for EntryId {
+    type Error = Error;
+
+    /// Copy the bytes from a [`PropValueData::Binary`] value. Any other type of value, including
+    /// [`PropValueData::Error`] when the entry ID is missing, returns [`E_INVALIDARG`].
+    fn try_from(value: &PropValueData<'_>) -> Result<Self> {
+        match value {
+            PropValueData::Binary(value) => Ok(Self::from(*value)),
+            _ => Err(Error::from(E_INVALIDARG)),
+        }
+    }
+}
+
+impl TryFrom<&PropValue<'_>> for EntryId {
+    type Error = Error;
+
+    fn try_from(value: &PropValue<'_>) -> Result<Self> {
+        Self::try_from(&value.value)
+    }
+}
+
+/// `muidStoreWrap`, the provider UID of a store entry ID wrapped with [`sys::WrapStoreEntryID`].
+pub const STORE_WRAP_UID: sys::MAPIUID = sys::MAPIUID {
+    ab: [
+        0x38, 0xA1, 0xBB, 0x10, 0x05, 0xE5, 0x10, 0x1A, 0xA1, 0xBB, 0x08, 0x00, 0x2B, 0x2A, 0x56,
+        0xC2,
+    ],
+};
+
+/// `MAPI_ONE_OFF_UID`, the provider UID of a one-off recipient entry ID, which holds the address
+/// itself instead of pointing to an address book entry.
+pub const ONE_OFF_UID: sys::MAPIUID = sys::MAPIUID {
+    ab: [
+        0x81, 0x2B, 0x1F, 0xA4, 0xBE, 0xA3, 0x10, 0x19, 0x9D, 0x6E, 0x00, 0xDD, 0x01, 0x0F, 0x54,
+        0x02,
+    ],
+};
+
+/// Size of [`sys::ENTRYID::abFlags`].
+const FLAGS_SIZE: usize = 4;
+
+/// Size of the provider UID at the start of [`sys::ENTRYID::ab`].
+const PROVIDER_SIZE: usize = 16;
+
+/// Decoded view of an entry ID, returned from [`parse`] or [`EntryId::parse`].
+///
+/// Every entry ID starts with the 4 bytes of [`sys::ENTRYID::abFlags`], like the structs declared
+/// with [`crate::SizedENTRYID!`]. The rest of [`sys::ENTRYID::ab`] belongs to the provider, but by
+/// convention it starts with the [`sys::MAPIUID`] of the provider which created it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParsedEntryId<'a> {
+    flags: [u8; 4],
+    provider: Option<sys::MAPIUID>,
+    data: &'a [u8],
+}
+
+impl<'a> ParsedEntryId<'a> {
+    /// Get the [`sys::ENTRYID::abFlags`].
+    pub fn flags(&self) -> [u8; 4] {
+        self.flags
+    }
+
+    /// Check for [`sys::MAPI_SHORTTERM`] in the first byte of the [`ParsedEntryId::flags`]. A
+    /// short-term entry ID is only valid in the current session, e.g. the entry IDs in some
+    /// table rows, and should not be saved.
+    pub fn is_short_term(&self) -> bool {
+        u32::from(self.flags[0]) & sys::MAPI_SHORTTERM != 0
+    }
+
+    /// Check that none of the [`ParsedEntryId::flags`] are set, which means the entry ID can be
+    /// saved and used to open the object in another session.
+    pub fn is_long_term(&self) -> bool {
+        self.flags == [0; 4]
+    }
+
+    /// Check for [`sys::MAPI_NOTRECIP`] in the first byte of the [`ParsedEntryId::flags`].
+    pub fn is_not_recipient(&self) -> bool {
+        u32::from(self.flags[0]) & sys::MAPI_NOTRECIP != 0
+    }
+
+    /// Check for [`sys::MAPI_THISSESSION`] in the first byte of the [`ParsedEntryId::flags`].
+    pub fn is_this_session(&self) -> bool {
+        u32::from(self.flags[0]) & sys::MAPI_THISSESSION != 0
+    }
+
+    /// Get the provider UID from the start of [`sys::ENTRYID::ab`], or [`None`] if the entry ID
+    /// is too short to have one.
+    pub fn provider(&self) -> Option<sys::MAPIUID> {
+        self.provider
+    }
+
+    /// Get the provider specific bytes after the [`ParsedEntryId::provider`].
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Check if the [`ParsedEntryId::provider`] is [`ONE_OFF_UID`].
+    pub fn is_one_off(&self) -> bool {
+        self.provider == Some(ONE_OFF_UID)
+    }
+
+    /// If the [`ParsedEntryId::provider`] is [`STORE_WRAP_UID`], decode the wrapper around the
+    /// store entry ID. Returns [`None`] for any other provider, or
+    /// [`sys::MAPI_E_INVALID_ENTRYID`] if the wrapper is truncated.
+    pub fn wrapped_store(&self) -> Option<Result<WrappedStoreEntryId<'a>>> {
+        if self.provider != Some(STORE_WRAP_UID) {
+            return None;
+        }
+        Some(WrappedStoreEntryId::parse(self.data))
+    }
+}
+
+/// Store entry ID wrapped with [`sys::WrapStoreEntryID`], e.g. [`sys::PR_STORE_ENTRYID`], from
+/// [`ParsedEntryId::wrapped_store`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WrappedStoreEntryId<'a> {
+    version: u8,
+    flag: u8,
+    dll_name: &'a [u8],
+    store: &'a [u8],
+}
+
+impl<'a> WrappedStoreEntryId<'a> {
+    /// Decode the bytes after [`STORE_WRAP_UID`]: a version byte, a flag byte, and the `null`
+    /// terminated name of the provider DLL, padded so the store entry ID which follows it starts
+    /// on a 4 byte boundary of the whole entry ID.
+    fn parse(data: &'a [u8]) -> Result<Self> {
+        const HEADER_SIZE: usize = FLAGS_SIZE + PROVIDER_SIZE;
+
+        let [version, flag, rest @ ..] = data else {
+            return Err(Error::from(sys::MAPI_E_INVALID_ENTRYID));
+        };
+        let name_len = rest
+            .iter()
+            .position(|ch| *ch == 0)
+            .ok_or_else(|| Error::from(sys::MAPI_E_INVALID_ENTRYID))?;
+        let end = HEADER_SIZE + 2 + name_len + 1;
+        let padded = (end + 3) & !3;
+        let store = rest
+            .get(padded - HEADER_SIZE - 2..)
+            .ok_or_else(|| Error::from(sys::MAPI_E_INVALID_ENTRYID))?;
+        Ok(Self {
+            version: *version,
+            flag: *flag,
+            dll_name: &rest[..name_len],
+            store,
+        })
+    }
+
+    /// Get the version byte of the wrapper.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Get the flag byte of the wrapper.
+    pub fn flag(&self) -> u8 {
+        self.flag
+    }
+
+    /// Get the name of the store provider DLL, e.g. `emsmdb.dll` for Exchange or `mspst.dll` for
+    /// a PST file.
+    pub fn dll_name(&self) -> String {
+        String::from_utf8_lossy(self.dll_name).into_owned()
+    }
+
+    /// Get the store entry ID inside the wrapper.
+    pub fn store_entry_id(&self) -> &'a [u8] {
+        self.store
+    }
+
+    /// Decode the header of the store entry ID inside the wrapper with [`parse`].
+    pub fn parse_store(&self) -> Result<ParsedEntryId<'a>> {
+        parse(self.store)
+    }
+}
+
+/// Decode the header of an entry ID. This only fails with [`sys::MAPI_E_INVALID_ENTRYID`] if
+/// there are fewer than 4 bytes for the [`sys::ENTRYID::abFlags`]. If there are not enough bytes
+/// for a provider UID, [`ParsedEntryId::provider`] is [`None`].
+pub fn parse(entry_id: &[u8]) -> Result<ParsedEntryId<'_>> {
+    if entry_id.len() < FLAGS_SIZE {
+        return Err(Error::from(sys::MAPI_E_INVALID_ENTRYID));
+    }
+    let (flags, ab) = entry_id.split_at(FLAGS_SIZE);
+    let (provider, data) = if ab.len() < PROVIDER_SIZE {
+        (None, ab)
+    } else {
+        let (provider, data) = ab.split_at(PROVIDER_SIZE);
+        let mut uid = sys::MAPIUID::default();
+        uid.ab.copy_from_slice(provider);
+        (Some(uid), data)
+    };
+    let mut header = [0; FLAGS_SIZE];
+    header.copy_from_slice(flags);
+    Ok(ParsedEntryId {
+        flags: header,
+        provider,
+        data,
+    })
+}
+
+/// Objects which implement `CompareEntryIDs`, such as [`sys::IMAPISession`] or
+/// [`sys::IMsgStore`].
+pub trait CompareEntryIds {
+    /// Call `CompareEntryIDs` and return `true` if both entry IDs refer to the same object.
+    fn compare_entry_ids(&self, left: &EntryId, right: &EntryId) -> Result<bool>;
+}
+
+impl CompareEntryIds for Logon {
+    /// Call [`sys::IMAPISession::CompareEntryIDs`], which finds the provider for the entry IDs.
+    fn compare_entry_ids(&self, left: &EntryId, right: &EntryId) -> Result<bool> {
+        let mut result = 0;
+        unsafe {
+            self.session.CompareEntryIDs(
+                u32::try_from(left.len())?,
+                left.as_ptr() as *mut _,
+                u32::try_from(right.len())?,
+                right.as_ptr() as *mut _,
+                0,
+                &mut result,
+            )?;
+        }
+        Ok(result != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PropTag;
+
+    #[test]
+    fn from_binary() {
+        let bytes = [0x0, 0x1, 0x2, 0x3, 0x4];
+        let value = PropValue {
+            tag: PropTag(sys::PR_ENTRYID),
+            value: PropValueData::Binary(&bytes),
+        };
+        let entry_id = EntryId::try_from(&value).expect("try_from failed");
+        assert_eq!(entry_id.as_bytes(), bytes);
+        assert_eq!(entry_id.len(), bytes.len());
+        assert_eq!(entry_id.as_ptr() as *const u8, entry_id.as_bytes().as_ptr());
+    }
+
+    #[test]
+    fn from_error() {
+        let value = PropValue {
+            tag: PropTag(sys::PR_ENTRYID),
+            value: PropValueData::Error(sys::MAPI_E_NOT_FOUND),
+        };
+        let err = EntryId::try_from(&value).expect_err("try_from should fail");
+        assert_eq!(err.code(), E_INVALIDARG);
+    }
+
+    #[test]
+    fn parse_short_term() {
+        let mut bytes = vec![sys::MAPI_SHORTTERM as u8, 0x0, 0x0, 0x0];
+        bytes.extend_from_slice(&ONE_OFF_UID.ab);
+        bytes.extend_from_slice(&[0x1, 0x2]);
+        let entry_id = EntryId::new(bytes);
+        let parsed = entry_id.parse().expect("parse failed");
+        assert!(parsed.is_short_term());
+        assert!(!parsed.is_long_term());
+        assert!(parsed.is_one_off());
+        assert_eq!(parsed.data(), [0x1, 0x2]);
+        assert!(parsed.wrapped_store().is_none());
+
+        let parsed = parse(&[0x0; 6]).expect("parse failed");
+        assert!(parsed.is_long_term());
+        assert_eq!(parsed.provider(), None);
+        assert_eq!(parsed.data(), [0x0, 0x0]);
+
+        let err = parse(&[0x0; 3]).expect_err("parse should fail");
+        assert_eq!(err.code(), sys::MAPI_E_INVALID_ENTRYID);
+    }
+
+    #[test]
+    fn parse_wrapped_store() {
+        let provider = [0x42; 16];
+        let mut bytes = vec![0x0; 4];
+        bytes.extend_from_slice(&STORE_WRAP_UID.ab);
+        bytes.extend_from_slice(&[0x0, 0x0]);
+        bytes.extend_from_slice(b"emsmdb.dll\0\0\0\0");
+        assert_eq!(bytes.len() % 4, 0);
+        bytes.extend_from_slice(&[0x0; 4]);
+        bytes.extend_from_slice(&provider);
+        bytes.extend_from_slice(&[0x1, 0x2, 0x3]);
+
+        let parsed = parse(&bytes).expect("parse failed");
+        assert!(parsed.is_long_term());
+        let wrapped = parsed
+            .wrapped_store()
+            .expect("not wrapped")
+            .expect("wrapper failed");
+        assert_eq!(wrapped.dll_name(), "emsmdb.dll");
+        let store = wrapped.parse_store().expect("store failed");
+        assert_eq!(store.provider(), Some(sys::MAPIUID { ab: provider }));
+        assert_eq!(store.data(), [0x1, 0x2, 0x3]);
+
+        let truncated = parse(&bytes[..30]).expect("parse failed");
+        assert!(matches!(truncated.wrapped_store(), Some(Err(_))));
+    }
+}
@@ -0,0 +1,1526 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`Folder`], [`CopyFlags`], [`DeleteFlags`], [`EmptyFlags`], [`DeleteReport`],
+//! [`SearchFlags`], [`SearchCriteria`], [`SearchResults`], [`FolderWalk`], [`SizeBreakdown`], and
+//! [`ClassSize`].
+
+use crate::{
+    ics::{self, ImportSink, SyncFlags, SyncKind, SyncState},
+    mapi_error::with_last_error,
+    msg_export::{self, MsgFile},
+    open_policy::probe_object,
+    prop_value::chain_copy,
+    retry::with_retry,
+    sys, to_pwstr_buffer, CompareEntryIds, EntryId, InitEpoch, MAPIBuffer, MAPIOutParam, MAPIProp,
+    MAPIUninit, MapiArena, MarshalToThread, Marshaled, Message, ObjectKind, ObjectRegistration,
+    OpenPolicy, PropProblem, PropTag, PropValue, PropValueData, Restriction, RetryPolicy, Row,
+    RulesTable, SortOrder, Table, TableFlags,
+};
+use core::{cell::Cell, ptr, slice};
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::Path,
+    vec,
+};
+use windows::Win32::{Foundation::*, System::Com::StructuredStorage::IStorage};
+use windows_core::*;
+
+/// Set of flags that can be passed to [`Folder::copy_messages`] or [`Folder::move_messages`].
+#[derive(Default)]
+pub struct CopyFlags {
+    /// Pass [`sys::MAPI_DECLINE_OK`].
+    pub decline_ok: bool,
+
+    /// Pass [`sys::MESSAGE_DIALOG`].
+    pub dialog: bool,
+}
+
+impl From<CopyFlags> for u32 {
+    fn from(value: CopyFlags) -> Self {
+        let decline_ok = if value.decline_ok {
+            sys::MAPI_DECLINE_OK
+        } else {
+            0
+        };
+        let dialog = if value.dialog { sys::MESSAGE_DIALOG } else { 0 };
+
+        decline_ok | dialog
+    }
+}
+
+/// Set of flags that can be passed to [`Folder::delete_messages`].
+#[derive(Default)]
+pub struct DeleteFlags {
+    /// Pass [`sys::DELETE_HARD_DELETE`] to skip the recoverable items.
+    pub hard_delete: bool,
+
+    /// Pass [`sys::MESSAGE_DIALOG`]. [`Folder::delete_messages`] always passes it if there is a
+    /// progress sink, since providers ignore `lpProgress` without it.
+    pub dialog: bool,
+}
+
+impl From<DeleteFlags> for u32 {
+    fn from(value: DeleteFlags) -> Self {
+        let hard_delete = if value.hard_delete {
+            sys::DELETE_HARD_DELETE
+        } else {
+            0
+        };
+        let dialog = if value.dialog { sys::MESSAGE_DIALOG } else { 0 };
+
+        hard_delete | dialog
+    }
+}
+
+/// Set of flags that can be passed to [`Folder::empty`].
+#[derive(Default)]
+pub struct EmptyFlags {
+    /// Pass [`sys::DEL_ASSOCIATED`] to delete the associated messages, e.g. views and rules, as
+    /// well as the normal contents.
+    pub associated: bool,
+
+    /// Pass [`sys::DELETE_HARD_DELETE`] to skip the recoverable items.
+    pub hard_delete: bool,
+
+    /// Pass [`sys::FOLDER_DIALOG`]. [`Folder::empty`] always passes it if there is a progress
+    /// sink, since providers ignore `lpProgress` without it.
+    pub dialog: bool,
+}
+
+impl From<EmptyFlags> for u32 {
+    fn from(value: EmptyFlags) -> Self {
+        let associated = if value.associated {
+            sys::DEL_ASSOCIATED
+        } else {
+            0
+        };
+        let hard_delete = if value.hard_delete {
+            sys::DELETE_HARD_DELETE
+        } else {
+            0
+        };
+        let dialog = if value.dialog { sys::FOLDER_DIALOG } else { 0 };
+
+        associated | hard_delete | dialog
+    }
+}
+
+/// Result of [`Folder::delete_messages`] or [`Folder::empty`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeleteReport {
+    /// Messages which were still in the folder after the provider returned
+    /// [`sys::MAPI_W_PARTIAL_COMPLETION`].
+    pub failed: Vec<EntryId>,
+}
+
+impl DeleteReport {
+    /// Check if every message was deleted.
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// Number of messages and total [`sys::PR_MESSAGE_SIZE`] for one message class in a
+/// [`SizeBreakdown`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClassSize {
+    /// Number of messages.
+    pub count: usize,
+
+    /// Sum of [`sys::PR_MESSAGE_SIZE`] in bytes.
+    pub bytes: u64,
+}
+
+/// Result of [`Folder::size_breakdown`], with the size of the messages grouped by
+/// [`sys::PR_MESSAGE_CLASS_W`]. Combine the results for several folders with
+/// [`SizeBreakdown::merge`], e.g. while walking them with [`Folder::walk`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SizeBreakdown {
+    /// Totals for each message class, e.g. `IPM.Note`. Messages without a class are counted
+    /// under an empty string.
+    pub classes: BTreeMap<String, ClassSize>,
+}
+
+impl SizeBreakdown {
+    /// Count one message of `message_class` with `size` bytes.
+    pub fn add(&mut self, message_class: &str, size: u64) {
+        let class = self.classes.entry(message_class.to_string()).or_default();
+        class.count += 1;
+        class.bytes += size;
+    }
+
+    /// Add all of the totals from `other`.
+    pub fn merge(&mut self, other: &SizeBreakdown) {
+        for (message_class, size) in other.classes.iter() {
+            let class = self.classes.entry(message_class.clone()).or_default();
+            class.count += size.count;
+            class.bytes += size.bytes;
+        }
+    }
+
+    /// Get the totals across every message class.
+    pub fn total(&self) -> ClassSize {
+        self.classes
+            .values()
+            .fold(ClassSize::default(), |total, class| ClassSize {
+                count: total.count + class.count,
+                bytes: total.bytes + class.bytes,
+            })
+    }
+
+    /// Count a row with the [`SIZE_COLUMNS`].
+    fn add_row<'a>(&mut self, values: impl IntoIterator<Item = PropValue<'a>>) {
+        let mut message_class = String::new();
+        let mut size = 0;
+        for value in values {
+            match (value.tag.0, value.value) {
+                (sys::PR_MESSAGE_CLASS_W, value) => {
+                    message_class = value.as_string().unwrap_or_default()
+                }
+                (sys::PR_MESSAGE_SIZE, PropValueData::Long(value)) => {
+                    size = u64::from(value as u32)
+                }
+                _ => {}
+            }
+        }
+        self.add(&message_class, size);
+    }
+}
+
+/// Columns [`Folder::size_breakdown`] reads from the contents table.
+const SIZE_COLUMNS: [PropTag; 2] = [
+    PropTag(sys::PR_MESSAGE_CLASS_W),
+    PropTag(sys::PR_MESSAGE_SIZE),
+];
+
+/// Number of rows [`Folder::size_breakdown`] reads from the contents table at a time.
+const SIZE_BATCH_SIZE: usize = 200;
+
+/// Number of rows [`SearchResults`] reads from the contents table at a time.
+const SEARCH_BATCH_SIZE: usize = 50;
+
+/// Set of flags that can be passed to [`Folder::set_search_criteria`].
+#[derive(Default)]
+pub struct SearchFlags {
+    /// Pass [`sys::RESTART_SEARCH`].
+    pub restart: bool,
+
+    /// Pass [`sys::STOP_SEARCH`].
+    pub stop: bool,
+
+    /// Pass [`sys::RECURSIVE_SEARCH`].
+    pub recursive: bool,
+
+    /// Pass [`sys::SHALLOW_SEARCH`].
+    pub shallow: bool,
+
+    /// Pass [`sys::FOREGROUND_SEARCH`].
+    pub foreground: bool,
+
+    /// Pass [`sys::BACKGROUND_SEARCH`].
+    pub background: bool,
+}
+
+impl From<SearchFlags> for u32 {
+    fn from(value: SearchFlags) -> Self {
+        let restart = if value.restart {
+            sys::RESTART_SEARCH
+        } else {
+            0
+        };
+        let stop = if value.stop { sys::STOP_SEARCH } else { 0 };
+        let recursive = if value.recursive {
+            sys::RECURSIVE_SEARCH
+        } else {
+            0
+        };
+        let shallow = if value.shallow {
+            sys::SHALLOW_SEARCH
+        } else {
+            0
+        };
+        let foreground = if value.foreground {
+            sys::FOREGROUND_SEARCH
+        } else {
+            0
+        };
+        let background = if value.background {
+            sys::BACKGROUND_SEARCH
+        } else {
+            0
+        };
+
+        restart | stop | recursive | shallow | foreground | background
+    }
+}
+
+/// Result of [`Folder::get_search_criteria`], which holds on to the [`sys::SRestriction`] tree
+/// returned from [`sys::IMAPIContainer::GetSearchCriteria`] until it is dropped.
+pub struct SearchCriteria {
+    restriction: MAPIOutParam<sys::SRestriction>,
+    folders: Vec<EntryId>,
+    state: u32,
+}
+
+impl SearchCriteria {
+    /// Convert the restriction with [`Restriction::from_sys`]. Returns [`None`] if the search
+    /// folder does not have one.
+    pub fn restriction(&self) -> Result<Option<Restriction<'_>>> {
+        unsafe {
+            self.restriction
+                .as_ref()
+                .map(|restriction| Restriction::from_sys(restriction))
+                .transpose()
+        }
+    }
+
+    /// Get the [`sys::PR_ENTRYID`] of each folder in the search.
+    pub fn folders(&self) -> &[EntryId] {
+        &self.folders
+    }
+
+    /// [`sys::SEARCH_RUNNING`] is set, so the search folder is still being kept up to date.
+    pub fn running(&self) -> bool {
+        self.state & sys::SEARCH_RUNNING != 0
+    }
+
+    /// [`sys::SEARCH_REBUILD`] is set, so the search is still populating the initial results.
+    pub fn rebuild(&self) -> bool {
+        self.state & sys::SEARCH_REBUILD != 0
+    }
+
+    /// [`sys::SEARCH_RECURSIVE`] is set, so the search includes the subfolders of each folder.
+    pub fn recursive(&self) -> bool {
+        self.state & sys::SEARCH_RECURSIVE != 0
+    }
+
+    /// [`sys::SEARCH_FOREGROUND`] is set, so the search runs at normal priority.
+    pub fn foreground(&self) -> bool {
+        self.state & sys::SEARCH_FOREGROUND != 0
+    }
+}
+
+/// Hold on to a [`sys::IMAPIFolder`] and expose the operations needed to walk a folder hierarchy
+/// without `unsafe`.
+pub struct Folder {
+    /// Access the [`sys::IMAPIFolder`].
+    pub folder: sys::IMAPIFolder,
+
+    epoch: InitEpoch,
+    registration: ObjectRegistration,
+
+    /// Policy from [`Folder::set_retry_policy`].
+    retry: Cell<Option<RetryPolicy>>,
+}
+
+impl Folder {
+    /// Wrap a [`sys::IMAPIFolder`] returned from one of the [`sys`] interface methods.
+    pub fn new(folder: sys::IMAPIFolder) -> Self {
+        Self::with_registration(folder, ObjectRegistration::new(ObjectKind::Folder))
+    }
+
+    /// Wrap a [`sys::IMAPIFolder`] opened through another wrapper, so it belongs to the same store.
+    pub(crate) fn with_registration(
+        folder: sys::IMAPIFolder,
+        registration: ObjectRegistration,
+    ) -> Self {
+        Self {
+            folder,
+            epoch: InitEpoch::current(),
+            registration,
+            retry: Default::default(),
+        }
+    }
+
+    /// Make sure MAPI is still initialized and the store is still connected.
+    fn check(&self) -> Result<()> {
+        self.epoch.check()?;
+        Ok(self.registration.check_connected()?)
+    }
+
+    /// Retry the calls which open tables, subfolders, and messages from this folder if they fail
+    /// with a transient error, see [`RetryPolicy`]. The [`Table`], [`Folder`], and [`Message`]
+    /// objects opened from this folder start out with the same policy. Pass [`None`] to stop
+    /// retrying.
+    pub fn set_retry_policy(&self, policy: Option<RetryPolicy>) {
+        self.retry.set(policy);
+    }
+
+    /// Get the policy from [`Folder::set_retry_policy`].
+    pub fn retry_policy(&self) -> Option<RetryPolicy> {
+        self.retry.get()
+    }
+
+    /// Call [`sys::IMAPIContainer::GetContentsTable`] to list the messages in this folder.
+    pub fn open_contents_table(&self, flags: TableFlags) -> Result<Table> {
+        self.check()?;
+        let flags = flags.into();
+        let table = with_retry(self.retry_policy(), || unsafe {
+            self.folder.GetContentsTable(flags)
+        })?;
+        let table = Table::with_registration(table, self.registration.child(ObjectKind::Table));
+        table.set_retry_policy(self.retry_policy());
+        Ok(table)
+    }
+
+    /// Call [`sys::IMAPIContainer::GetHierarchyTable`] to list the subfolders of this folder.
+    pub fn open_hierarchy_table(&self, flags: TableFlags) -> Result<Table> {
+        self.check()?;
+        let flags = flags.into();
+        let table = with_retry(self.retry_policy(), || unsafe {
+            self.folder.GetHierarchyTable(flags)
+        })?;
+        let table = Table::with_registration(table, self.registration.child(ObjectKind::Table));
+        table.set_retry_policy(self.retry_policy());
+        Ok(table)
+    }
+
+    /// Call [`sys::IMAPIProp::OpenProperty`] to open the [`sys::PR_RULES_TABLE`] on this folder,
+    /// which should be the Inbox of a mailbox, as a [`RulesTable`].
+    pub fn rules(&self) -> Result<RulesTable> {
+        self.check()?;
+        let mut unknown = None;
+        unsafe {
+            self.folder
+                .OpenProperty(
+                    sys::PR_RULES_TABLE,
+                    &sys::IExchangeModifyTable::IID as *const _ as *mut _,
+                    0,
+                    sys::MAPI_DEFERRED_ERRORS,
+                    &mut unknown,
+                )
+                .map_err(|error| with_last_error(self.mapi_prop(), error))?;
+        }
+        let unknown = unknown.ok_or_else(|| Error::from(E_POINTER))?;
+        Ok(RulesTable::with_registration(
+            unknown.cast()?,
+            self.registration.child(ObjectKind::RulesTable),
+        ))
+    }
+
+    /// Open the [`SyncKind`] synchronizer on this folder as a [`sys::IExchangeExportChanges`], and
+    /// report everything that changed since `state` to `sink`. When it finishes, `state` is
+    /// updated for the next call. If it fails, `state` is left unchanged, so the same changes are
+    /// reported again the next time.
+    pub fn export_changes<S>(
+        &self,
+        kind: SyncKind,
+        state: &mut SyncState,
+        flags: SyncFlags,
+        sink: S,
+    ) -> Result<()>
+    where
+        S: ImportSink + Send + 'static,
+    {
+        self.check()?;
+        let mut unknown = None;
+        unsafe {
+            self.folder
+                .OpenProperty(
+                    kind.into(),
+                    &sys::IExchangeExportChanges::IID as *const _ as *mut _,
+                    0,
+                    0,
+                    &mut unknown,
+                )
+                .map_err(|error| with_last_error(self.mapi_prop(), error))?;
+        }
+        let exporter = unknown.ok_or_else(|| Error::from(E_POINTER))?.cast()?;
+        ics::synchronize(&exporter, state, flags, Box::new(sink))
+    }
+
+    /// Call [`sys::IMAPIContainer::OpenEntry`] with [`sys::MAPI_BEST_ACCESS`] to open a subfolder
+    /// using its [`sys::PR_ENTRYID`], e.g. from a row in the [`Folder::open_hierarchy_table`].
+    ///
+    /// If the entry ID refers to something other than a folder, this will return
+    /// [`E_NOINTERFACE`].
+    pub fn open_subfolder(&self, entry_id: &EntryId) -> Result<Folder> {
+        self.open_subfolder_with_policy(entry_id, OpenPolicy::Immediate)
+    }
+
+    /// Same as [`Folder::open_subfolder`], but choose how to handle
+    /// [`sys::MAPI_DEFERRED_ERRORS`] with an [`OpenPolicy`].
+    pub fn open_subfolder_with_policy(
+        &self,
+        entry_id: &EntryId,
+        policy: OpenPolicy,
+    ) -> Result<Folder> {
+        let unknown = self.open_entry(entry_id, sys::MAPI_FOLDER, policy)?;
+        let folder =
+            Folder::with_registration(unknown.cast()?, self.registration.child(ObjectKind::Folder));
+        folder.set_retry_policy(self.retry_policy());
+        Ok(folder)
+    }
+
+    /// Call [`sys::IMAPIContainer::OpenEntry`] with [`sys::MAPI_BEST_ACCESS`] to open a message
+    /// using its [`sys::PR_ENTRYID`], e.g. from a row in the [`Folder::open_contents_table`].
+    ///
+    /// If the entry ID refers to something other than a message, this will return
+    /// [`E_NOINTERFACE`].
+    pub fn open_message(&self, entry_id: &EntryId) -> Result<Message> {
+        self.open_message_with_policy(entry_id, OpenPolicy::Immediate)
+    }
+
+    /// Same as [`Folder::open_message`], but choose how to handle
+    /// [`sys::MAPI_DEFERRED_ERRORS`] with an [`OpenPolicy`].
+    pub fn open_message_with_policy(
+        &self,
+        entry_id: &EntryId,
+        policy: OpenPolicy,
+    ) -> Result<Message> {
+        let unknown = self.open_entry(entry_id, sys::MAPI_MESSAGE, policy)?;
+        let message = Message::with_registration(
+            unknown.cast()?,
+            self.registration.child(ObjectKind::Message),
+        );
+        message.set_retry_policy(self.retry_policy());
+        Ok(message)
+    }
+
+    /// Call [`sys::IMAPIFolder::CreateMessage`] to create a new message in this folder. Set the
+    /// properties and call [`MAPIProp::save_changes`] or [`Message::submit`] to keep it.
+    pub fn create_message(&self) -> Result<Message> {
+        self.create_message_with_flags(0)
+    }
+
+    /// Create a new message in this folder with the properties, recipients, and attachments of the
+    /// `.msg` file at `path`, and save it. See [`Folder::import_msg_from_storage`].
+    pub fn import_msg<P>(&self, path: P) -> Result<(Message, Vec<PropProblem>)>
+    where
+        P: AsRef<Path>,
+    {
+        let storage = msg_export::open_msg_storage(path.as_ref())?;
+        self.import_msg_from_storage(&storage)
+    }
+
+    /// Create a new message in this folder and copy a message in the `.msg` format from `storage`
+    /// into it with [`MAPIProp::copy_to`], e.g. from a compound file written by
+    /// [`crate::export_msg_to_storage`]. The new message is saved and returned along with any
+    /// [`PropProblem`] entries from the copy.
+    pub fn import_msg_from_storage(
+        &self,
+        storage: &IStorage,
+    ) -> Result<(Message, Vec<PropProblem>)> {
+        let source = MsgFile::open(storage, false)?;
+        let message = self.create_message()?;
+        let problems =
+            source
+                .message
+                .copy_to(&message, &msg_export::EXCLUDED_PROPS, Default::default())?;
+        message.save_changes(Default::default())?;
+        Ok((message, problems))
+    }
+
+    /// Call [`sys::IMAPIFolder::CreateFolder`] to create a [`sys::FOLDER_GENERIC`] subfolder with
+    /// the specified `name`. If a subfolder with that name already exists, this will return
+    /// [`sys::MAPI_E_COLLISION`].
+    pub fn create_subfolder(&self, name: &str) -> Result<Folder> {
+        self.create_folder_with_type(sys::FOLDER_GENERIC, name)
+    }
+
+    /// Call [`sys::IMAPIFolder::CreateFolder`] to create a [`sys::FOLDER_SEARCH`] subfolder with
+    /// the specified `name`. This is usually called on the [`crate::SpecialFolder::SearchRoot`].
+    /// The new folder is empty until [`Folder::set_search_criteria`] starts the search.
+    pub fn create_search_folder(&self, name: &str) -> Result<Folder> {
+        self.create_folder_with_type(sys::FOLDER_SEARCH, name)
+    }
+
+    /// Call [`sys::IMAPIContainer::SetSearchCriteria`] on a search folder, to search the
+    /// `folders` with the `restriction`. The search folder keeps the results up to date until the
+    /// search is stopped with [`SearchFlags::stop`].
+    pub fn set_search_criteria<E>(
+        &self,
+        restriction: &Restriction,
+        folders: &[E],
+        flags: SearchFlags,
+    ) -> Result<()>
+    where
+        E: AsRef<[u8]>,
+    {
+        self.check()?;
+        let mut restriction = restriction.build()?;
+        let mut folders = entry_list(folders)?;
+        unsafe {
+            self.folder
+                .SetSearchCriteria(
+                    restriction.as_mut_ptr()?,
+                    folders.as_mut()?,
+                    u32::from(flags),
+                )
+                .map_err(|error| with_last_error(self.mapi_prop(), error))
+        }
+    }
+
+    /// Call [`sys::IMAPIContainer::GetSearchCriteria`] with [`sys::MAPI_UNICODE`] on a search
+    /// folder, to read back the restriction, folders, and state from
+    /// [`Folder::set_search_criteria`].
+    pub fn get_search_criteria(&self) -> Result<SearchCriteria> {
+        self.check()?;
+        let mut restriction = MAPIOutParam::new();
+        let mut folders: MAPIOutParam<sys::SBinaryArray> = MAPIOutParam::new();
+        let mut state = 0;
+        unsafe {
+            self.folder
+                .GetSearchCriteria(
+                    sys::MAPI_UNICODE,
+                    restriction.as_mut_ptr(),
+                    folders.as_mut_ptr(),
+                    &mut state,
+                )
+                .map_err(|error| with_last_error(self.mapi_prop(), error))?;
+        }
+        let folders = match unsafe { folders.as_ref() } {
+            Some(folders) if !folders.lpbin.is_null() => {
+                let bins =
+                    unsafe { slice::from_raw_parts(folders.lpbin, folders.cValues as usize) };
+                bins.iter()
+                    .filter(|bin| !bin.lpb.is_null())
+                    .map(|bin| unsafe { slice::from_raw_parts(bin.lpb, bin.cb as usize) })
+                    .map(EntryId::from)
+                    .collect()
+            }
+            _ => vec![],
+        };
+        Ok(SearchCriteria {
+            restriction,
+            folders,
+            state,
+        })
+    }
+
+    /// Find the messages in the folder which match the `restriction`, sorted by the [`SortOrder`],
+    /// and return up to `limit` of them. If `limit` is [`None`], there is no limit.
+    ///
+    /// This calls [`Table::restrict`] and [`Table::sort`] on the contents table, and then the
+    /// [`SearchResults`] iterator reads the [`sys::PR_ENTRYID`] column in batches and opens each
+    /// message as it is needed.
+    pub fn search(
+        &self,
+        restriction: &Restriction,
+        order: &SortOrder,
+        limit: Option<usize>,
+    ) -> Result<SearchResults<'_>> {
+        let table = self.open_contents_table(Default::default())?;
+        table.set_columns(&[PropTag(sys::PR_ENTRYID)])?;
+        table.restrict(Some(restriction))?;
+        if !order.columns.is_empty() {
+            table.sort(order)?;
+        }
+        Ok(SearchResults {
+            folder: self,
+            table,
+            rows: Vec::new().into_iter(),
+            remaining: limit.unwrap_or(usize::MAX),
+            done: false,
+        })
+    }
+
+    /// Read [`sys::PR_MESSAGE_CLASS_W`] and [`sys::PR_MESSAGE_SIZE`] for every message in the
+    /// contents table and add them up by message class. Associated messages and subfolders are
+    /// not included.
+    pub fn size_breakdown(&self) -> Result<SizeBreakdown> {
+        let table = self.open_contents_table(Default::default())?;
+        table.set_columns(&SIZE_COLUMNS)?;
+        let mut breakdown = SizeBreakdown::default();
+        for row in table.iter_rows(SIZE_BATCH_SIZE) {
+            breakdown.add_row(row?.iter());
+        }
+        Ok(breakdown)
+    }
+
+    /// Walk the subfolders of this folder, down to `depth` levels below it, or the whole tree if
+    /// `depth` is [`None`]. The [`FolderWalk`] iterator opens each subfolder in depth-first order,
+    /// along with its path, i.e. the [`sys::PR_DISPLAY_NAME_W`] of each folder from this one down
+    /// to the subfolder.
+    ///
+    /// This reads the whole tree from a single hierarchy table with [`sys::CONVENIENT_DEPTH`] if
+    /// the store supports it. Otherwise, it falls back to reading the hierarchy table of each
+    /// subfolder as it is opened.
+    pub fn walk(&self, depth: Option<u32>) -> Result<FolderWalk<'_>> {
+        let max_depth = depth.unwrap_or(u32::MAX);
+        let mut walk = FolderWalk {
+            root: self,
+            max_depth,
+            pending: Vec::new(),
+            expand: false,
+        };
+        if max_depth == 0 {
+            return Ok(walk);
+        }
+
+        let flags = TableFlags {
+            convenient_depth: true,
+            ..Default::default()
+        };
+        let rows = self
+            .open_hierarchy_table(flags)
+            .and_then(|table| table.query_all_rows(&WALK_COLUMNS, None, None));
+        match rows {
+            Ok(rows) => {
+                let mut path = Vec::new();
+                walk.pending = subfolder_rows(rows.into_iter())
+                    .filter_map(|subfolder| {
+                        let depth = subfolder.depth?;
+                        push_path(&mut path, depth, subfolder.name);
+                        (depth <= max_depth).then(|| PendingFolder {
+                            path: path.clone(),
+                            depth,
+                            entry_id: subfolder.entry_id,
+                        })
+                    })
+                    .collect();
+                walk.pending.reverse();
+            }
+            Err(error) if WALK_FALLBACK_ERRORS.contains(&error.code()) => {
+                walk.expand = true;
+                walk.push_children(self, &[], 0)?;
+            }
+            Err(error) => return Err(error),
+        }
+        Ok(walk)
+    }
+
+    /// Call [`sys::IMAPIFolder::DeleteMessages`] to delete the messages with the specified
+    /// [`sys::PR_ENTRYID`] values, reporting to the `progress` sink if there is one, e.g. from
+    /// [`crate::ProgressSink::create`].
+    ///
+    /// If the provider returns [`sys::MAPI_W_PARTIAL_COMPLETION`], this looks for the entry IDs
+    /// in the contents table and the associated contents table, and lists the ones that are still
+    /// there in [`DeleteReport::failed`]. The entry IDs in the tables may not have the same bytes
+    /// as the ones which were passed in, so they are matched with `store`, e.g. the [`MsgStore`]
+    /// or the [`Logon`].
+    ///
+    /// [`MsgStore`]: crate::MsgStore
+    /// [`Logon`]: crate::Logon
+    pub fn delete_messages<E>(
+        &self,
+        store: &dyn CompareEntryIds,
+        entry_ids: &[E],
+        flags: DeleteFlags,
+        progress: Option<&sys::IMAPIProgress>,
+    ) -> Result<DeleteReport>
+    where
+        E: AsRef<[u8]>,
+    {
+        self.check()?;
+        let mut entry_list = entry_list(entry_ids)?;
+        let mut flags = u32::from(flags);
+        if progress.is_some() {
+            flags |= sys::MESSAGE_DIALOG;
+        }
+        let result = unsafe {
+            (Interface::vtable(&self.folder).DeleteMessages)(
+                Interface::as_raw(&self.folder),
+                entry_list.as_mut()?,
+                0,
+                progress.map_or(ptr::null_mut(), Interface::as_raw),
+                flags,
+            )
+        };
+        if result != sys::MAPI_W_PARTIAL_COMPLETION {
+            result
+                .ok()
+                .map_err(|error| with_last_error(self.mapi_prop(), error))?;
+            return Ok(Default::default());
+        }
+
+        let mut remaining = self.remaining_entry_ids(false)?;
+        remaining.extend(self.remaining_entry_ids(true)?);
+        Ok(DeleteReport {
+            failed: failed_entry_ids(store, entry_ids, &remaining)?,
+        })
+    }
+
+    /// Call [`sys::IMAPIFolder::EmptyFolder`] to delete every message and subfolder in the folder,
+    /// reporting to the `progress` sink if there is one.
+    ///
+    /// If the provider returns [`sys::MAPI_W_PARTIAL_COMPLETION`], the messages which are still
+    /// in the contents table, and the associated contents table if [`EmptyFlags::associated`] is
+    /// set, are listed in [`DeleteReport::failed`].
+    pub fn empty(
+        &self,
+        flags: EmptyFlags,
+        progress: Option<&sys::IMAPIProgress>,
+    ) -> Result<DeleteReport> {
+        self.check()?;
+        let associated = flags.associated;
+        let mut flags = u32::from(flags);
+        if progress.is_some() {
+            flags |= sys::FOLDER_DIALOG;
+        }
+        let result = unsafe {
+            (Interface::vtable(&self.folder).EmptyFolder)(
+                Interface::as_raw(&self.folder),
+                0,
+                progress.map_or(ptr::null_mut(), Interface::as_raw),
+                flags,
+            )
+        };
+        if result != sys::MAPI_W_PARTIAL_COMPLETION {
+            result
+                .ok()
+                .map_err(|error| with_last_error(self.mapi_prop(), error))?;
+            return Ok(Default::default());
+        }
+
+        let mut failed: Vec<_> = self.remaining_entry_ids(false)?.into_iter().collect();
+        if associated {
+            failed.extend(self.remaining_entry_ids(true)?);
+        }
+        Ok(DeleteReport { failed })
+    }
+
+    /// Call [`sys::IMAPIFolder::DeleteFolder`] with [`sys::DEL_FOLDERS`] and
+    /// [`sys::DEL_MESSAGES`] to delete a subfolder and everything in it, using its
+    /// [`sys::PR_ENTRYID`].
+    pub fn delete_subfolder(&self, entry_id: &EntryId) -> Result<()> {
+        self.check()?;
+        unsafe {
+            self.folder.DeleteFolder(
+                u32::try_from(entry_id.len())?,
+                entry_id.as_ptr() as *mut _,
+                0,
+                None::<&sys::IMAPIProgress>,
+                sys::DEL_FOLDERS | sys::DEL_MESSAGES,
+            )
+        }
+    }
+
+    /// Call [`sys::IMAPIFolder::CopyMessages`] to copy the messages with the specified
+    /// [`sys::PR_ENTRYID`] values to the `destination` folder.
+    pub fn copy_messages<E>(
+        &self,
+        entry_ids: &[E],
+        destination: &Folder,
+        flags: CopyFlags,
+    ) -> Result<()>
+    where
+        E: AsRef<[u8]>,
+    {
+        self.copy_or_move_messages(entry_ids, destination, u32::from(flags))
+    }
+
+    /// Call [`sys::IMAPIFolder::CopyMessages`] with [`sys::MESSAGE_MOVE`] to move the messages
+    /// with the specified [`sys::PR_ENTRYID`] values to the `destination` folder.
+    pub fn move_messages<E>(
+        &self,
+        entry_ids: &[E],
+        destination: &Folder,
+        flags: CopyFlags,
+    ) -> Result<()>
+    where
+        E: AsRef<[u8]>,
+    {
+        self.copy_or_move_messages(entry_ids, destination, u32::from(flags) | sys::MESSAGE_MOVE)
+    }
+
+    /// Copy the folder associated information (FAI) messages, such as view definitions and form
+    /// registrations, from this folder to the `destination` folder. If `message_classes` is not
+    /// empty, only the messages with a [`sys::PR_MESSAGE_CLASS_W`] starting with one of those
+    /// prefixes are copied, e.g. `IPM.Microsoft.FolderDesign.NamedView`.
+    ///
+    /// Store specific identifiers such as [`sys::PR_SOURCE_KEY`] are not copied. Any binary
+    /// property on a copy which refers to this folder by its [`sys::PR_ENTRYID`] or
+    /// [`sys::PR_SOURCE_KEY`] is updated to refer to the `destination` folder instead. Returns the
+    /// number of messages which were copied.
+    pub fn clone_associated_messages(
+        &self,
+        destination: &Folder,
+        message_classes: &[&str],
+    ) -> Result<usize> {
+        self.check()?;
+        let folder_ids = [PropTag(sys::PR_ENTRYID), PropTag(sys::PR_SOURCE_KEY)];
+        let source_ids = self.get_props(&folder_ids)?;
+        let destination_ids = destination.get_props(&folder_ids)?;
+        let replacements: Vec<_> = source_ids
+            .iter()
+            .zip(destination_ids.iter())
+            .filter_map(|(source, destination)| {
+                match (source.value.as_bytes(), destination.value.as_bytes()) {
+                    (Some(source), Some(destination)) => {
+                        Some((source.to_vec(), destination.to_vec()))
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+
+        let rows = self
+            .open_contents_table(TableFlags {
+                associated: true,
+                ..Default::default()
+            })?
+            .query_all_rows(
+                &[PropTag(sys::PR_ENTRYID), PropTag(sys::PR_MESSAGE_CLASS_W)],
+                None,
+                None,
+            )?;
+        let excluded = [
+            PropTag(sys::PR_ENTRYID),
+            PropTag(sys::PR_INSTANCE_KEY),
+            PropTag(sys::PR_RECORD_KEY),
+            PropTag(sys::PR_SEARCH_KEY),
+            PropTag(sys::PR_SOURCE_KEY),
+            PropTag(sys::PR_CHANGE_KEY),
+            PropTag(sys::PR_PREDECESSOR_CHANGE_LIST),
+            PropTag(sys::PR_PARENT_ENTRYID),
+            PropTag(sys::PR_PARENT_SOURCE_KEY),
+        ];
+
+        let mut count = 0;
+        for row in rows {
+            let mut values = row.iter();
+            let Some(Ok(entry_id)) = values.next().map(|value| EntryId::try_from(&value)) else {
+                continue;
+            };
+            let message_class = values
+                .next()
+                .and_then(|value| value.value.as_string())
+                .unwrap_or_default();
+            if !matches_message_class(&message_class, message_classes) {
+                continue;
+            }
+
+            let source = self.open_message(&entry_id)?;
+            let copy = destination.create_message_with_flags(sys::MAPI_ASSOCIATED)?;
+            source.copy_to(&copy, &excluded, Default::default())?;
+
+            let mut props = sys::SRow::default();
+            unsafe {
+                copy.message.GetProps(
+                    ptr::null_mut(),
+                    sys::MAPI_UNICODE,
+                    &mut props.cValues,
+                    &mut props.lpProps,
+                )?;
+            }
+            let props = Row::new(&mut props);
+            let fixed_up: Vec<_> = fix_up_folder_ids(props.iter(), &replacements)
+                .into_iter()
+                .map(|(tag, value)| PropValue {
+                    tag,
+                    value: PropValueData::Binary(value),
+                })
+                .collect();
+            if !fixed_up.is_empty() {
+                copy.set_props(&fixed_up)?;
+            }
+            copy.save_changes(Default::default())?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn create_folder_with_type(&self, folder_type: u32, name: &str) -> Result<Folder> {
+        self.check()?;
+        let mut name = to_pwstr_buffer(name);
+        let mut folder = None;
+        unsafe {
+            self.folder.CreateFolder(
+                folder_type,
+                name.as_mut_ptr() as *mut _,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                sys::MAPI_UNICODE,
+                &mut folder,
+            )?;
+        }
+        let folder = folder.ok_or_else(|| Error::from(E_POINTER))?;
+        Ok(Folder::with_registration(
+            folder,
+            self.registration.child(ObjectKind::Folder),
+        ))
+    }
+
+    /// Read the [`sys::PR_ENTRYID`] of every message which is still in the contents table, or the
+    /// associated contents table if `associated` is `true`.
+    fn remaining_entry_ids(&self, associated: bool) -> Result<HashSet<EntryId>> {
+        let rows = self
+            .open_contents_table(TableFlags {
+                associated,
+                ..Default::default()
+            })?
+            .query_all_rows(&[PropTag(sys::PR_ENTRYID)], None, None)?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                row.iter()
+                    .next()
+                    .and_then(|value| EntryId::try_from(&value).ok())
+            })
+            .collect())
+    }
+
+    fn create_message_with_flags(&self, flags: u32) -> Result<Message> {
+        self.check()?;
+        let mut message = None;
+        unsafe {
+            self.folder
+                .CreateMessage(ptr::null_mut(), flags, &mut message)?;
+        }
+        Ok(Message::with_registration(
+            message.ok_or_else(|| Error::from(E_POINTER))?,
+            self.registration.child(ObjectKind::Message),
+        ))
+    }
+
+    fn open_entry(
+        &self,
+        entry_id: &EntryId,
+        expected_type: u32,
+        policy: OpenPolicy,
+    ) -> Result<IUnknown> {
+        self.check()?;
+        with_retry(self.retry_policy(), || {
+            policy.open(
+                |flags| {
+                    let mut obj_type = 0;
+                    let mut unknown = None;
+                    unsafe {
+                        self.folder.OpenEntry(
+                            u32::try_from(entry_id.len())?,
+                            entry_id.as_ptr() as *mut _,
+                            ptr::null_mut(),
+                            sys::MAPI_BEST_ACCESS | flags,
+                            &mut obj_type,
+                            &mut unknown,
+                        )?;
+                    }
+                    if obj_type != expected_type {
+                        return Err(Error::from(E_NOINTERFACE));
+                    }
+                    unknown.ok_or_else(|| Error::from(E_POINTER))
+                },
+                |unknown| probe_object(&unknown.cast()?),
+            )
+        })
+    }
+
+    fn copy_or_move_messages<E>(
+        &self,
+        entry_ids: &[E],
+        destination: &Folder,
+        flags: u32,
+    ) -> Result<()>
+    where
+        E: AsRef<[u8]>,
+    {
+        self.check()?;
+        let mut entry_list = entry_list(entry_ids)?;
+        unsafe {
+            self.folder.CopyMessages(
+                entry_list.as_mut()?,
+                &<sys::IMAPIFolder as Interface>::IID as *const _ as *mut _,
+                destination.folder.as_raw(),
+                0,
+                None::<&sys::IMAPIProgress>,
+                flags,
+            )
+        }
+    }
+}
+
+/// Iterator returned from [`Folder::search`], which opens each matching [`Message`] in order.
+///
+/// If [`sys::IMAPITable::QueryRows`] fails, the error is returned once, and then the iterator
+/// stops. Rows without a [`sys::PR_ENTRYID`] are skipped.
+pub struct SearchResults<'a> {
+    folder: &'a Folder,
+    table: Table,
+    rows: vec::IntoIter<Row>,
+    remaining: usize,
+    done: bool,
+}
+
+impl Iterator for SearchResults<'_> {
+    type Item = Result<Message>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.remaining == 0 {
+                return None;
+            }
+            if let Some(row) = self.rows.next() {
+                let Some(Ok(entry_id)) = row.iter().next().map(|value| EntryId::try_from(&value))
+                else {
+                    continue;
+                };
+                self.remaining -= 1;
+                return Some(self.folder.open_message(&entry_id));
+            }
+            if self.done {
+                return None;
+            }
+
+            match self.table.query_rows(SEARCH_BATCH_SIZE.min(self.remaining)) {
+                Ok(rows) if rows.is_empty() => self.done = true,
+                Ok(rows) => self.rows = rows.into_iter(),
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+/// Columns [`Folder::walk`] reads from each hierarchy table.
+const WALK_COLUMNS: [PropTag; 3] = [
+    PropTag(sys::PR_ENTRYID),
+    PropTag(sys::PR_DISPLAY_NAME_W),
+    PropTag(sys::PR_DEPTH),
+];
+
+/// Errors from a [`sys::CONVENIENT_DEPTH`] hierarchy table which make [`Folder::walk`] fall back
+/// to reading one level at a time.
+const WALK_FALLBACK_ERRORS: [HRESULT; 4] = [
+    sys::MAPI_E_NO_SUPPORT,
+    sys::MAPI_E_TOO_COMPLEX,
+    sys::MAPI_E_UNKNOWN_FLAGS,
+    sys::MAPI_E_TOO_BIG,
+];
+
+/// Iterator returned from [`Folder::walk`], which opens each subfolder in depth-first order and
+/// returns it along with its path.
+///
+/// If a subfolder cannot be opened, or its hierarchy table cannot be read, the error is returned
+/// in its place and the walk continues with the next one.
+pub struct FolderWalk<'a> {
+    root: &'a Folder,
+    max_depth: u32,
+    pending: Vec<PendingFolder>,
+    expand: bool,
+}
+
+impl FolderWalk<'_> {
+    /// Read the subfolders of `folder` and push them on the stack, so the first one is next.
+    fn push_children(&mut self, folder: &Folder, path: &[String], depth: u32) -> Result<()> {
+        let rows = folder
+            .open_hierarchy_table(Default::default())?
+            .query_all_rows(&WALK_COLUMNS, None, None)?;
+        let children: Vec<_> = subfolder_rows(rows.into_iter())
+            .map(|subfolder| PendingFolder {
+                path: path.iter().cloned().chain([subfolder.name]).collect(),
+                depth: depth + 1,
+                entry_id: subfolder.entry_id,
+            })
+            .collect();
+        self.pending.extend(children.into_iter().rev());
+        Ok(())
+    }
+}
+
+impl Iterator for FolderWalk<'_> {
+    type Item = Result<(Vec<String>, Folder)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pending = self.pending.pop()?;
+        let folder = match self.root.open_subfolder(&pending.entry_id) {
+            Ok(folder) => folder,
+            Err(error) => return Some(Err(error)),
+        };
+        if self.expand && pending.depth < self.max_depth {
+            if let Err(error) = self.push_children(&folder, &pending.path, pending.depth) {
+                return Some(Err(error));
+            }
+        }
+        Some(Ok((pending.path, folder)))
+    }
+}
+
+/// Subfolder which [`FolderWalk`] has not opened yet.
+struct PendingFolder {
+    path: Vec<String>,
+    depth: u32,
+    entry_id: EntryId,
+}
+
+/// Row from a hierarchy table with the [`WALK_COLUMNS`].
+struct SubfolderRow {
+    entry_id: EntryId,
+    name: String,
+    depth: Option<u32>,
+}
+
+/// Read the [`WALK_COLUMNS`] from each row, skipping rows without a [`sys::PR_ENTRYID`].
+fn subfolder_rows<I>(rows: I) -> impl Iterator<Item = SubfolderRow>
+where
+    I: Iterator<Item = Row>,
+{
+    rows.filter_map(|row| {
+        let mut values = row.iter();
+        let entry_id = EntryId::try_from(&values.next()?).ok()?;
+        let name = values
+            .next()
+            .and_then(|value| value.value.as_string())
+            .unwrap_or_default();
+        let depth = match values.next().map(|value| value.value) {
+            Some(PropValueData::Long(depth)) => u32::try_from(depth).ok(),
+            _ => None,
+        };
+        Some(SubfolderRow {
+            entry_id,
+            name,
+            depth,
+        })
+    })
+}
+
+/// Update the `path` for a row in a [`sys::CONVENIENT_DEPTH`] hierarchy table, which lists each
+/// folder right after its parent, with a [`sys::PR_DEPTH`] of 1 for the direct subfolders.
+fn push_path(path: &mut Vec<String>, depth: u32, name: String) {
+    path.truncate(depth.saturating_sub(1) as usize);
+    path.push(name);
+}
+
+/// Check if the `message_class` starts with any of the `prefixes`, ignoring case. An empty list of
+/// `prefixes` matches every message class.
+fn matches_message_class(message_class: &str, prefixes: &[&str]) -> bool {
+    prefixes.is_empty()
+        || prefixes.iter().any(|prefix| {
+            message_class
+                .get(..prefix.len())
+                .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+        })
+}
+
+/// Find the binary property values which match the first half of one of the `replacements`, and
+/// pair the property tag with the second half of that replacement.
+fn fix_up_folder_ids<'a, I>(
+    values: I,
+    replacements: &'a [(Vec<u8>, Vec<u8>)],
+) -> Vec<(PropTag, &'a [u8])>
+where
+    I: IntoIterator<Item = PropValue<'a>>,
+{
+    values
+        .into_iter()
+        .filter_map(|value| {
+            let PropValueData::Binary(bytes) = value.value else {
+                return None;
+            };
+            replacements
+                .iter()
+                .find(|(from, _)| from.as_slice() == bytes)
+                .map(|(_, to)| (value.tag, to.as_slice()))
+        })
+        .collect()
+}
+
+/// Pick out the `entry_ids` which are still in the folder, in the same order.
+fn failed_entry_ids<E>(
+    store: &dyn CompareEntryIds,
+    entry_ids: &[E],
+    remaining: &HashSet<EntryId>,
+) -> Result<Vec<EntryId>>
+where
+    E: AsRef<[u8]>,
+{
+    let mut failed = Vec::new();
+    for entry_id in entry_ids {
+        let entry_id = EntryId::from(entry_id.as_ref());
+        let mut found = remaining.contains(&entry_id);
+        for other in remaining {
+            if found {
+                break;
+            }
+            found = store.compare_entry_ids(&entry_id, other)?;
+        }
+        if found {
+            failed.push(entry_id);
+        }
+    }
+    Ok(failed)
+}
+
+/// Build an [`sys::ENTRYLIST`] in a single chain of MAPI allocations, e.g. to pass to
+/// [`sys::IMAPIFolder::CopyMessages`].
+fn entry_list<E>(entry_ids: &[E]) -> Result<MAPIBuffer<'static, sys::SBinaryArray>>
+where
+    E: AsRef<[u8]>,
+{
+    let mut buffer = MAPIUninit::<sys::SBinaryArray>::new(1)?;
+    let lpbin = if entry_ids.is_empty() {
+        ptr::null_mut()
+    } else {
+        let arena = MapiArena::chained(&buffer);
+        let alloc = arena.alloc::<sys::SBinary>(entry_ids.len())?;
+        for (element, entry_id) in alloc.iter_mut().zip(entry_ids) {
+            let entry_id = entry_id.as_ref();
+            *element = sys::SBinary {
+                cb: u32::try_from(entry_id.len())?,
+                lpb: chain_copy(&arena, entry_id)?,
+            };
+        }
+        alloc.as_mut_ptr()
+    };
+    buffer.uninit()?.write(sys::SBinaryArray {
+        cValues: u32::try_from(entry_ids.len())?,
+        lpbin,
+    });
+    Ok(unsafe { buffer.assume_init() })
+}
+
+impl MAPIProp for Folder {
+    fn mapi_prop(&self) -> &sys::IMAPIProp {
+        &self.folder
+    }
+
+    fn interface_id(&self) -> GUID {
+        sys::IMAPIFolder::IID
+    }
+
+    fn init_epoch(&self) -> InitEpoch {
+        self.epoch
+    }
+
+    fn registration(&self) -> Option<&ObjectRegistration> {
+        Some(&self.registration)
+    }
+}
+
+impl From<sys::IMAPIFolder> for Folder {
+    fn from(value: sys::IMAPIFolder) -> Self {
+        Self::new(value)
+    }
+}
+
+impl MarshalToThread for Folder {
+    type State = (ObjectRegistration, Option<RetryPolicy>);
+    type Context = ();
+
+    fn marshal_to_thread(&self) -> Result<Marshaled<Self>> {
+        Marshaled::new(
+            &self.folder,
+            (
+                self.registration.child(ObjectKind::Folder),
+                self.retry_policy(),
+            ),
+        )
+    }
+
+    fn unmarshal(marshaled: Marshaled<Self>, _context: ()) -> Result<Self> {
+        let (folder, (registration, retry)) = marshaled.into_parts()?;
+        let folder = Self::with_registration(folder, registration);
+        folder.set_retry_policy(retry);
+        Ok(folder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::slice;
+
+    #[test]
+    fn size_breakdown() {
+        let note = to_pwstr_buffer("IPM.Note");
+        let mut first = SizeBreakdown::default();
+        first.add_row([
+            PropValue {
+                tag: PropTag(sys::PR_MESSAGE_CLASS_W),
+                value: PropValueData::Unicode(note),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_MESSAGE_SIZE),
+                value: PropValueData::Long(1000),
+            },
+        ]);
+        first.add_row([PropValue {
+            tag: PropTag(sys::PR_MESSAGE_SIZE),
+            value: PropValueData::Long(10),
+        }]);
+        first.add("IPM.Note", 500);
+
+        let mut second = SizeBreakdown::default();
+        second.add("IPM.Appointment", 2000);
+        second.add("IPM.Note", 1);
+        second.merge(&first);
+
+        assert_eq!(
+            second.classes.get("IPM.Note"),
+            Some(&ClassSize {
+                count: 3,
+                bytes: 1501
+            })
+        );
+        assert_eq!(second.classes.get("").map(|class| class.bytes), Some(10));
+        assert_eq!(
+            second.total(),
+            ClassSize {
+                count: 5,
+                bytes: 3511
+            }
+        );
+    }
+
+    #[test]
+    fn build_entry_list() {
+        let entry_ids: [&[u8]; 2] = [&[0x1, 0x2, 0x3], &[0x4, 0x5]];
+        let mut entry_list = entry_list(&entry_ids).expect("entry_list failed");
+        let entry_list = entry_list.as_mut().expect("as_mut failed");
+        assert_eq!(entry_list.cValues, 2);
+        let bins = unsafe { slice::from_raw_parts(entry_list.lpbin, 2) };
+        for (bin, expected) in bins.iter().zip(entry_ids) {
+            assert_eq!(bin.cb as usize, expected.len());
+            let actual = unsafe { slice::from_raw_parts(bin.lpb, bin.cb as usize) };
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn build_empty_entry_list() {
+        let mut entry_list = entry_list::<Vec<u8>>(&[]).expect("entry_list failed");
+        let entry_list = entry_list.as_mut().expect("as_mut failed");
+        assert_eq!(entry_list.cValues, 0);
+        assert!(entry_list.lpbin.is_null());
+    }
+
+    #[test]
+    fn message_class_prefixes() {
+        assert!(matches_message_class(
+            "IPM.Microsoft.FolderDesign.NamedView",
+            &[]
+        ));
+        assert!(matches_message_class(
+            "IPM.Microsoft.FolderDesign.NamedView",
+            &["ipm.microsoft.folderdesign"]
+        ));
+        assert!(!matches_message_class(
+            "IPM.Configuration.Categories",
+            &["IPM.Microsoft.FolderDesign"]
+        ));
+        assert!(!matches_message_class("IPM", &["IPM.Microsoft"]));
+    }
+
+    #[test]
+    fn fix_up_ids() {
+        let source_id = [0x1, 0x2, 0x3];
+        let other_id = [0x4, 0x5, 0x6];
+        let replacements = [(source_id.to_vec(), vec![0x7, 0x8])];
+        let values = [
+            PropValue {
+                tag: PropTag(sys::PR_PARENT_ENTRYID),
+                value: PropValueData::Binary(&source_id),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_SEARCH_KEY),
+                value: PropValueData::Binary(&other_id),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_MESSAGE_FLAGS),
+                value: PropValueData::Long(0),
+            },
+        ];
+        assert_eq!(
+            fix_up_folder_ids(values, &replacements),
+            [(PropTag(sys::PR_PARENT_ENTRYID), [0x7_u8, 0x8].as_slice())]
+        );
+    }
+
+    #[test]
+    fn delete_flags() {
+        assert_eq!(u32::from(DeleteFlags::default()), 0);
+        assert_eq!(
+            u32::from(DeleteFlags {
+                hard_delete: true,
+                dialog: true,
+            }),
+            sys::DELETE_HARD_DELETE | sys::MESSAGE_DIALOG
+        );
+        assert_eq!(
+            u32::from(EmptyFlags {
+                associated: true,
+                hard_delete: true,
+                dialog: false,
+            }),
+            sys::DEL_ASSOCIATED | sys::DELETE_HARD_DELETE
+        );
+    }
+
+    /// Treat entry IDs as equivalent if they only differ in the first byte, like the flags in a
+    /// real entry ID.
+    struct IgnoreFlags;
+
+    impl CompareEntryIds for IgnoreFlags {
+        fn compare_entry_ids(&self, left: &EntryId, right: &EntryId) -> Result<bool> {
+            Ok(left.as_bytes()[1..] == right.as_bytes()[1..])
+        }
+    }
+
+    #[test]
+    fn partial_delete() {
+        let entry_ids = [[0x1_u8, 0x2], [0x3, 0x4], [0x5, 0x6]];
+        let remaining: HashSet<_> = [EntryId::from(vec![0x5, 0x6]), EntryId::from(vec![0x0, 0x2])]
+            .into_iter()
+            .collect();
+        let report = DeleteReport {
+            failed: failed_entry_ids(&IgnoreFlags, &entry_ids, &remaining)
+                .expect("should compare entry IDs"),
+        };
+        assert!(!report.is_complete());
+        assert_eq!(
+            report.failed,
+            [EntryId::from(vec![0x1, 0x2]), EntryId::from(vec![0x5, 0x6])]
+        );
+        assert!(DeleteReport::default().is_complete());
+    }
+
+    #[test]
+    fn search_flags() {
+        assert_eq!(u32::from(SearchFlags::default()), 0);
+        assert_eq!(
+            u32::from(SearchFlags {
+                restart: true,
+                recursive: true,
+                background: true,
+                ..Default::default()
+            }),
+            sys::RESTART_SEARCH | sys::RECURSIVE_SEARCH | sys::BACKGROUND_SEARCH
+        );
+    }
+
+    #[test]
+    fn copy_flags() {
+        assert_eq!(u32::from(CopyFlags::default()), 0);
+        assert_eq!(
+            u32::from(CopyFlags {
+                decline_ok: true,
+                dialog: true,
+            }),
+            sys::MAPI_DECLINE_OK | sys::MESSAGE_DIALOG
+        );
+    }
+
+    #[test]
+    fn walk_paths() {
+        let rows = [
+            (1, "Inbox"),
+            (2, "Projects"),
+            (3, "2024"),
+            (2, "Receipts"),
+            (1, "Archive"),
+            (2, "Old"),
+        ];
+        let mut path = Vec::new();
+        let paths: Vec<_> = rows
+            .into_iter()
+            .map(|(depth, name)| {
+                push_path(&mut path, depth, String::from(name));
+                path.join("/")
+            })
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "Inbox",
+                "Inbox/Projects",
+                "Inbox/Projects/2024",
+                "Inbox/Receipts",
+                "Archive",
+                "Archive/Old"
+            ]
+        );
+    }
+}
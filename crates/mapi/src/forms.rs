@@ -0,0 +1,378 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`FormMgr`], [`FormContainer`], [`FormInfo`], [`FormProperties`], and [`FormRegistry`].
+//!
+//! Clients which implement their own form handling need to find the form registered for a message
+//! class, e.g. `IPM.Note.Custom`, in one of the form libraries. [`open_form_mgr`] wraps
+//! [`sys::MAPIOpenFormMgr`] to search them the same way Outlook does, and
+//! [`open_local_form_container`] wraps [`sys::MAPIOpenLocalFormContainer`] for the forms installed
+//! on this computer.
+
+use crate::{
+    sys, to_pcstr_buffer, Folder, InitEpoch, Logon, MAPIOutParam, MAPIProp, PropTag, PropValue,
+    PropValueData,
+};
+use core::ffi::CStr;
+use windows::Win32::Foundation::*;
+use windows_core::*;
+
+/// Form library that [`FormMgr::open_form_container`] should open.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormRegistry {
+    /// [`sys::HFRMREG_DEFAULT`]
+    Default,
+
+    /// [`sys::HFRMREG_LOCAL`], the forms installed on this computer.
+    Local,
+
+    /// [`sys::HFRMREG_PERSONAL`], the personal forms library in the default store.
+    Personal,
+
+    /// [`sys::HFRMREG_FOLDER`], the forms associated with a [`Folder`].
+    Folder,
+}
+
+impl From<FormRegistry> for u32 {
+    fn from(value: FormRegistry) -> Self {
+        match value {
+            FormRegistry::Default => sys::HFRMREG_DEFAULT,
+            FormRegistry::Local => sys::HFRMREG_LOCAL,
+            FormRegistry::Personal => sys::HFRMREG_PERSONAL,
+            FormRegistry::Folder => sys::HFRMREG_FOLDER,
+        }
+    }
+}
+
+/// Properties of a form returned from [`FormInfo::properties`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FormProperties {
+    /// [`sys::PR_MESSAGE_CLASS_W`]
+    pub message_class: Option<String>,
+
+    /// [`sys::PR_DISPLAY_NAME_W`]
+    pub display_name: Option<String>,
+
+    /// [`sys::PR_FORM_CATEGORY_W`]
+    pub category: Option<String>,
+
+    /// [`sys::PR_FORM_CATEGORY_SUB_W`]
+    pub category_sub: Option<String>,
+
+    /// [`sys::PR_FORM_VERSION_W`]
+    pub version: Option<String>,
+
+    /// [`sys::PR_FORM_DESIGNER_NAME_W`]
+    pub designer_name: Option<String>,
+
+    /// [`sys::PR_FORM_HIDDEN`]
+    pub hidden: bool,
+}
+
+impl FormProperties {
+    /// Properties requested by [`FormInfo::properties`].
+    pub const COLUMNS: [PropTag; 7] = [
+        PropTag(sys::PR_MESSAGE_CLASS_W),
+        PropTag(sys::PR_DISPLAY_NAME_W),
+        PropTag(sys::PR_FORM_CATEGORY_W),
+        PropTag(sys::PR_FORM_CATEGORY_SUB_W),
+        PropTag(sys::PR_FORM_VERSION_W),
+        PropTag(sys::PR_FORM_DESIGNER_NAME_W),
+        PropTag(sys::PR_FORM_HIDDEN),
+    ];
+
+    /// Pick out the form properties from the values of [`FormProperties::COLUMNS`].
+    pub fn from_props<'a, I>(values: I) -> Self
+    where
+        I: IntoIterator<Item = PropValue<'a>>,
+    {
+        const MESSAGE_CLASS: u16 = PropTag(sys::PR_MESSAGE_CLASS_W).prop_id();
+        const DISPLAY_NAME: u16 = PropTag(sys::PR_DISPLAY_NAME_W).prop_id();
+        const CATEGORY: u16 = PropTag(sys::PR_FORM_CATEGORY_W).prop_id();
+        const CATEGORY_SUB: u16 = PropTag(sys::PR_FORM_CATEGORY_SUB_W).prop_id();
+        const VERSION: u16 = PropTag(sys::PR_FORM_VERSION_W).prop_id();
+        const DESIGNER_NAME: u16 = PropTag(sys::PR_FORM_DESIGNER_NAME_W).prop_id();
+        const HIDDEN: u16 = PropTag(sys::PR_FORM_HIDDEN).prop_id();
+
+        let mut result = Self::default();
+        for PropValue { tag, value } in values {
+            match tag.prop_id() {
+                MESSAGE_CLASS => result.message_class = value.as_string(),
+                DISPLAY_NAME => result.display_name = value.as_string(),
+                CATEGORY => result.category = value.as_string(),
+                CATEGORY_SUB => result.category_sub = value.as_string(),
+                VERSION => result.version = value.as_string(),
+                DESIGNER_NAME => result.designer_name = value.as_string(),
+                HIDDEN => {
+                    result.hidden = matches!(value, PropValueData::Boolean(hidden) if hidden != 0)
+                }
+                _ => {}
+            }
+        }
+        result
+    }
+}
+
+/// Hold on to a [`sys::IMAPIFormInfo`] returned from one of the `resolve_message_class` methods.
+pub struct FormInfo {
+    /// Access the [`sys::IMAPIFormInfo`].
+    pub form_info: sys::IMAPIFormInfo,
+
+    epoch: InitEpoch,
+}
+
+impl FormInfo {
+    /// Wrap a [`sys::IMAPIFormInfo`] returned from one of the [`sys`] interface methods.
+    pub fn new(form_info: sys::IMAPIFormInfo) -> Self {
+        Self {
+            form_info,
+            epoch: InitEpoch::current(),
+        }
+    }
+
+    /// Read the [`FormProperties::COLUMNS`] with [`MAPIProp::get_props`].
+    pub fn properties(&self) -> Result<FormProperties> {
+        let row = self.get_props(&FormProperties::COLUMNS)?;
+        Ok(FormProperties::from_props(row.iter()))
+    }
+}
+
+impl MAPIProp for FormInfo {
+    fn mapi_prop(&self) -> &sys::IMAPIProp {
+        &self.form_info
+    }
+
+    fn init_epoch(&self) -> InitEpoch {
+        self.epoch
+    }
+}
+
+impl From<sys::IMAPIFormInfo> for FormInfo {
+    fn from(value: sys::IMAPIFormInfo) -> Self {
+        Self::new(value)
+    }
+}
+
+/// Hold on to a [`sys::IMAPIFormContainer`] for one form library.
+pub struct FormContainer {
+    /// Access the [`sys::IMAPIFormContainer`].
+    pub container: sys::IMAPIFormContainer,
+
+    epoch: InitEpoch,
+}
+
+impl FormContainer {
+    /// Wrap a [`sys::IMAPIFormContainer`] returned from one of the [`sys`] interface methods.
+    pub fn new(container: sys::IMAPIFormContainer) -> Self {
+        Self {
+            container,
+            epoch: InitEpoch::current(),
+        }
+    }
+
+    /// Call [`sys::IMAPIFormContainer::GetDisplay`] to get the name of the form library.
+    pub fn display_name(&self) -> Result<String> {
+        self.epoch.check()?;
+        let mut name: MAPIOutParam<i8> = MAPIOutParam::new();
+        unsafe {
+            self.container.GetDisplay(0, name.as_mut_ptr())?;
+            let name = name.as_mut().ok_or_else(|| Error::from(E_POINTER))?;
+            Ok(CStr::from_ptr(name).to_string_lossy().into_owned())
+        }
+    }
+
+    /// Call [`sys::IMAPIFormContainer::ResolveMessageClass`] to find the form for a message class
+    /// in this library. If there is no exact match, the form for the closest base class is
+    /// returned, e.g. `IPM.Note` for `IPM.Note.Custom`, unless `exact_match` is `true`, in which
+    /// case this returns [`sys::MAPI_E_NOT_FOUND`].
+    pub fn resolve_message_class(
+        &self,
+        message_class: &str,
+        exact_match: bool,
+    ) -> Result<FormInfo> {
+        self.epoch.check()?;
+        let message_class = ansi_message_class(message_class)?;
+        let form_info = unsafe {
+            self.container
+                .ResolveMessageClass(PCSTR(message_class.as_ptr()), resolve_flags(exact_match))?
+        };
+        Ok(FormInfo::new(form_info))
+    }
+}
+
+impl From<sys::IMAPIFormContainer> for FormContainer {
+    fn from(value: sys::IMAPIFormContainer) -> Self {
+        Self::new(value)
+    }
+}
+
+/// Hold on to a [`sys::IMAPIFormMgr`] from [`open_form_mgr`] and expose the form lookups without
+/// `unsafe`.
+pub struct FormMgr {
+    /// Access the [`sys::IMAPIFormMgr`].
+    pub form_mgr: sys::IMAPIFormMgr,
+
+    epoch: InitEpoch,
+}
+
+impl FormMgr {
+    /// Wrap a [`sys::IMAPIFormMgr`] returned from one of the [`sys`] interface methods.
+    pub fn new(form_mgr: sys::IMAPIFormMgr) -> Self {
+        Self {
+            form_mgr,
+            epoch: InitEpoch::current(),
+        }
+    }
+
+    /// Call [`sys::IMAPIFormMgr::OpenFormContainer`] to open one of the form libraries. The
+    /// `folder` is required for [`FormRegistry::Folder`] and ignored otherwise.
+    pub fn open_form_container(
+        &self,
+        registry: FormRegistry,
+        folder: Option<&Folder>,
+    ) -> Result<FormContainer> {
+        self.epoch.check()?;
+        let folder: Option<IUnknown> = match (registry, folder) {
+            (FormRegistry::Folder, Some(folder)) => Some(folder.folder.cast()?),
+            (FormRegistry::Folder, None) => return Err(Error::from(E_INVALIDARG)),
+            _ => None,
+        };
+        let container = unsafe {
+            self.form_mgr
+                .OpenFormContainer(registry.into(), folder.as_ref())?
+        };
+        Ok(FormContainer::new(container))
+    }
+
+    /// Open each of the form libraries which are available: [`FormRegistry::Local`],
+    /// [`FormRegistry::Personal`], and [`FormRegistry::Folder`] if there is a `folder`. Libraries
+    /// which fail with [`sys::MAPI_E_NOT_FOUND`] or [`sys::MAPI_E_NO_SUPPORT`] are skipped.
+    pub fn form_containers(
+        &self,
+        folder: Option<&Folder>,
+    ) -> Result<Vec<(FormRegistry, FormContainer)>> {
+        let registries = [FormRegistry::Local, FormRegistry::Personal]
+            .into_iter()
+            .chain(folder.map(|_| FormRegistry::Folder));
+        let mut containers = Vec::new();
+        for registry in registries {
+            match self.open_form_container(registry, folder) {
+                Ok(container) => containers.push((registry, container)),
+                Err(err)
+                    if err.code() == sys::MAPI_E_NOT_FOUND
+                        || err.code() == sys::MAPI_E_NO_SUPPORT => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(containers)
+    }
+
+    /// Call [`sys::IMAPIFormMgr::ResolveMessageClass`] to find the form for a message class,
+    /// searching the form libraries in order, starting with the forms for `folder` if there is
+    /// one. See [`FormContainer::resolve_message_class`] for `exact_match`.
+    pub fn resolve_message_class(
+        &self,
+        message_class: &str,
+        exact_match: bool,
+        folder: Option<&Folder>,
+    ) -> Result<FormInfo> {
+        self.epoch.check()?;
+        let message_class = ansi_message_class(message_class)?;
+        let form_info = unsafe {
+            self.form_mgr.ResolveMessageClass(
+                PCSTR(message_class.as_ptr()),
+                resolve_flags(exact_match),
+                folder.map(|folder| &folder.folder),
+            )?
+        };
+        Ok(FormInfo::new(form_info))
+    }
+}
+
+impl From<sys::IMAPIFormMgr> for FormMgr {
+    fn from(value: sys::IMAPIFormMgr) -> Self {
+        Self::new(value)
+    }
+}
+
+/// Call [`sys::MAPIOpenFormMgr`] to get the [`FormMgr`] for a session.
+pub fn open_form_mgr(logon: &Logon) -> Result<FormMgr> {
+    logon.check()?;
+    let form_mgr = unsafe { sys::MAPIOpenFormMgr(&logon.session)? };
+    Ok(FormMgr::new(form_mgr))
+}
+
+/// Call [`sys::MAPIOpenLocalFormContainer`] to open the [`FormRegistry::Local`] form library
+/// without a session.
+pub fn open_local_form_container() -> Result<FormContainer> {
+    let container = unsafe { sys::MAPIOpenLocalFormContainer()? };
+    Ok(FormContainer::new(container))
+}
+
+/// Message classes are always ANSI strings in the form APIs, so encode them with
+/// [`to_pcstr_buffer`], which fails if a character is not in the ANSI code page.
+fn ansi_message_class(message_class: &str) -> Result<Vec<u8>> {
+    to_pcstr_buffer(message_class)
+}
+
+fn resolve_flags(exact_match: bool) -> u32 {
+    if exact_match {
+        sys::MAPIFORM_EXACTMATCH
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{to_pwstr_buffer, PropType};
+
+    #[test]
+    fn form_registry() {
+        assert_eq!(u32::from(FormRegistry::Default), sys::HFRMREG_DEFAULT);
+        assert_eq!(u32::from(FormRegistry::Local), sys::HFRMREG_LOCAL);
+        assert_eq!(u32::from(FormRegistry::Personal), sys::HFRMREG_PERSONAL);
+        assert_eq!(u32::from(FormRegistry::Folder), sys::HFRMREG_FOLDER);
+    }
+
+    #[test]
+    fn form_properties() {
+        let message_class = to_pwstr_buffer("IPM.Note.Custom");
+        let values = [
+            PropValue {
+                tag: PropTag(sys::PR_MESSAGE_CLASS_W),
+                value: PropValueData::Unicode(message_class),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_FORM_HIDDEN),
+                value: PropValueData::Boolean(1),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_FORM_VERSION_W)
+                    .change_prop_type(PropType::new(sys::PT_ERROR as u16)),
+                value: PropValueData::Error(sys::MAPI_E_NOT_FOUND),
+            },
+        ];
+
+        assert_eq!(
+            FormProperties::from_props(values),
+            FormProperties {
+                message_class: Some(String::from("IPM.Note.Custom")),
+                hidden: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn message_class_terminated() {
+        assert_eq!(
+            ansi_message_class("IPM.Note").expect("encode failed"),
+            b"IPM.Note\0"
+        );
+        assert!(ansi_message_class("IPM.Note.\u{65e5}\u{672c}").is_err());
+        assert_eq!(resolve_flags(true), sys::MAPIFORM_EXACTMATCH);
+        assert_eq!(resolve_flags(false), 0);
+    }
+}
@@ -0,0 +1,456 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`FreeBusyData`], [`BusyInterval`], and [`FreeBusyStatus`].
+//!
+//! Published free/busy data is stored in pairs of properties described in [MS-OXOPFFB]: a
+//! [`sys::PT_MV_LONG`] list of months, each encoded as `year * 16 + month`, and a matching
+//! [`sys::PT_MV_BINARY`] list with the busy blocks in each month. Each block is a pair of
+//! little-endian 16-bit values with the start and end of the block in minutes since the beginning
+//! of the month, in UTC. Use [`crate::MsgStore::local_free_busy_message`] to open the local
+//! free/busy message where Outlook publishes them for the mailbox.
+//!
+//! [MS-OXOPFFB]: https://learn.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxopffb
+
+use crate::{
+    prop_value::FILETIME_UNIX_EPOCH_SECONDS, sys, MAPIProp, Message, PropTag, PropValue,
+    PropValueData,
+};
+use core::{ops::Range, slice};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use windows_core::*;
+
+/// `PidTagFreeBusyPublishStart`, in minutes since January 1, 1601.
+const PR_FREEBUSY_PUBLISH_START: u32 = 0x6847_0003;
+
+/// `PidTagFreeBusyPublishEnd`, in minutes since January 1, 1601.
+const PR_FREEBUSY_PUBLISH_END: u32 = 0x6848_0003;
+
+/// `PidTagScheduleInfoMonthsMerged`, for the combined [`FreeBusyStatus::Busy`] and
+/// [`FreeBusyStatus::OutOfOffice`] blocks.
+const PR_SCHDINFO_MONTHS_MERGED: u32 = 0x684F_1003;
+
+/// `PidTagScheduleInfoFreeBusyMerged`
+const PR_SCHDINFO_FREEBUSY_MERGED: u32 = 0x6850_1102;
+
+/// `PidTagScheduleInfoMonthsTentative`
+const PR_SCHDINFO_MONTHS_TENTATIVE: u32 = 0x6851_1003;
+
+/// `PidTagScheduleInfoFreeBusyTentative`
+const PR_SCHDINFO_FREEBUSY_TENTATIVE: u32 = 0x6852_1102;
+
+/// `PidTagScheduleInfoMonthsBusy`
+const PR_SCHDINFO_MONTHS_BUSY: u32 = 0x6853_1003;
+
+/// `PidTagScheduleInfoFreeBusyBusy`
+const PR_SCHDINFO_FREEBUSY_BUSY: u32 = 0x6854_1102;
+
+/// `PidTagScheduleInfoMonthsAway`
+const PR_SCHDINFO_MONTHS_OOF: u32 = 0x6855_1003;
+
+/// `PidTagScheduleInfoFreeBusyAway`
+const PR_SCHDINFO_FREEBUSY_OOF: u32 = 0x6856_1102;
+
+pub(crate) const MINUTES_PER_DAY: i64 = 24 * 60;
+
+/// Status of a [`BusyInterval`], which determines the pair of properties it is published in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FreeBusyStatus {
+    /// `PidTagScheduleInfoMonthsTentative` and `PidTagScheduleInfoFreeBusyTentative`.
+    Tentative,
+
+    /// `PidTagScheduleInfoMonthsBusy` and `PidTagScheduleInfoFreeBusyBusy`.
+    Busy,
+
+    /// `PidTagScheduleInfoMonthsAway` and `PidTagScheduleInfoFreeBusyAway`.
+    OutOfOffice,
+}
+
+impl FreeBusyStatus {
+    /// Each status, in the order [`FreeBusyData::read`] returns them.
+    const ALL: [Self; 3] = [Self::Tentative, Self::Busy, Self::OutOfOffice];
+
+    /// The months and blocks properties for this status.
+    fn props(self) -> (u32, u32) {
+        match self {
+            Self::Tentative => (PR_SCHDINFO_MONTHS_TENTATIVE, PR_SCHDINFO_FREEBUSY_TENTATIVE),
+            Self::Busy => (PR_SCHDINFO_MONTHS_BUSY, PR_SCHDINFO_FREEBUSY_BUSY),
+            Self::OutOfOffice => (PR_SCHDINFO_MONTHS_OOF, PR_SCHDINFO_FREEBUSY_OOF),
+        }
+    }
+}
+
+/// A busy block from the published free/busy data, with the times rounded down to the minute.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusyInterval {
+    /// Start of the block.
+    pub start: SystemTime,
+
+    /// End of the block, which is not included in the interval.
+    pub end: SystemTime,
+
+    /// Which set of properties the block belongs to.
+    pub status: FreeBusyStatus,
+}
+
+/// Free/busy data published on a message like the
+/// [`crate::MsgStore::local_free_busy_message`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FreeBusyData {
+    /// `PidTagFreeBusyPublishStart`, the beginning of the published range.
+    pub publish_start: Option<SystemTime>,
+
+    /// `PidTagFreeBusyPublishEnd`, the end of the published range.
+    pub publish_end: Option<SystemTime>,
+
+    /// Busy blocks sorted by [`BusyInterval::status`] and then by [`BusyInterval::start`].
+    /// Blocks which are split across months in the properties are joined back together.
+    pub intervals: Vec<BusyInterval>,
+}
+
+impl FreeBusyData {
+    /// Properties which [`FreeBusyData::read`] reads and [`FreeBusyData::publish`] writes.
+    pub const PROPS: [PropTag; 10] = [
+        PropTag(PR_FREEBUSY_PUBLISH_START),
+        PropTag(PR_FREEBUSY_PUBLISH_END),
+        PropTag(PR_SCHDINFO_MONTHS_TENTATIVE),
+        PropTag(PR_SCHDINFO_FREEBUSY_TENTATIVE),
+        PropTag(PR_SCHDINFO_MONTHS_BUSY),
+        PropTag(PR_SCHDINFO_FREEBUSY_BUSY),
+        PropTag(PR_SCHDINFO_MONTHS_OOF),
+        PropTag(PR_SCHDINFO_FREEBUSY_OOF),
+        PropTag(PR_SCHDINFO_MONTHS_MERGED),
+        PropTag(PR_SCHDINFO_FREEBUSY_MERGED),
+    ];
+
+    /// Read and decode the free/busy properties on a `message`. Missing properties mean there
+    /// are no blocks with that status. Malformed blocks return [`sys::MAPI_E_CORRUPT_DATA`].
+    pub fn read(message: &Message) -> Result<Self> {
+        let props = message.get_props(&Self::PROPS)?;
+        let mut data = Self::default();
+        let mut months: BTreeMap<u32, &[i32]> = BTreeMap::new();
+        let mut blocks: BTreeMap<u32, Vec<&[u8]>> = BTreeMap::new();
+        for value in props.iter() {
+            match (value.tag.0, &value.value) {
+                (PR_FREEBUSY_PUBLISH_START, PropValueData::Long(value)) => {
+                    data.publish_start = Some(from_rtime(i64::from(*value)))
+                }
+                (PR_FREEBUSY_PUBLISH_END, PropValueData::Long(value)) => {
+                    data.publish_end = Some(from_rtime(i64::from(*value)))
+                }
+                (tag, PropValueData::LongArray(value)) => {
+                    months.insert(tag, *value);
+                }
+                (tag, PropValueData::BinaryArray(value)) => {
+                    let value = value
+                        .iter()
+                        .map(|value| {
+                            if value.cb == 0 || value.lpb.is_null() {
+                                &[][..]
+                            } else {
+                                unsafe { slice::from_raw_parts(value.lpb, value.cb as usize) }
+                            }
+                        })
+                        .collect();
+                    blocks.insert(tag, value);
+                }
+                _ => {}
+            }
+        }
+
+        for status in FreeBusyStatus::ALL {
+            let (months_tag, blocks_tag) = status.props();
+            let months = months.get(&months_tag).copied().unwrap_or_default();
+            let blocks = blocks.remove(&blocks_tag).unwrap_or_default();
+            data.intervals.extend(
+                decode(months, &blocks)?
+                    .into_iter()
+                    .map(|range| BusyInterval {
+                        start: from_rtime(range.start),
+                        end: from_rtime(range.end),
+                        status,
+                    }),
+            );
+        }
+
+        Ok(data)
+    }
+
+    /// Encode and write the free/busy properties on a `message`, and call
+    /// [`MAPIProp::save_changes`]. This also writes the merged
+    /// [`FreeBusyStatus::Busy`] and [`FreeBusyStatus::OutOfOffice`] properties which other clients
+    /// read, and deletes the properties for any status without blocks.
+    pub fn publish(&self, message: &Message) -> Result<()> {
+        let mut encoded = Vec::new();
+        let mut empty = Vec::new();
+        let mut encode_status = |months_tag: u32, blocks_tag: u32, statuses: &[FreeBusyStatus]| {
+            let (months, blocks) = encode(
+                self.intervals
+                    .iter()
+                    .filter(|interval| statuses.contains(&interval.status))
+                    .map(|interval| to_rtime(interval.start)..to_rtime(interval.end)),
+            );
+            if months.is_empty() {
+                empty.extend([PropTag(months_tag), PropTag(blocks_tag)]);
+            } else {
+                encoded.push((months_tag, months, blocks_tag, blocks));
+            }
+        };
+        for status in FreeBusyStatus::ALL {
+            let (months_tag, blocks_tag) = status.props();
+            encode_status(months_tag, blocks_tag, &[status]);
+        }
+        encode_status(
+            PR_SCHDINFO_MONTHS_MERGED,
+            PR_SCHDINFO_FREEBUSY_MERGED,
+            &[FreeBusyStatus::Busy, FreeBusyStatus::OutOfOffice],
+        );
+
+        let mut values = Vec::new();
+        for (tag, time) in [
+            (PR_FREEBUSY_PUBLISH_START, self.publish_start),
+            (PR_FREEBUSY_PUBLISH_END, self.publish_end),
+        ] {
+            if let Some(time) = time {
+                values.push(PropValue {
+                    tag: PropTag(tag),
+                    value: PropValueData::Long(i32::try_from(to_rtime(time))?),
+                });
+            }
+        }
+        for (months_tag, months, blocks_tag, blocks) in &encoded {
+            values.push(PropValue {
+                tag: PropTag(*months_tag),
+                value: PropValueData::LongArray(months),
+            });
+            let blocks = blocks
+                .iter()
+                .map(|block| {
+                    Ok(sys::SBinary {
+                        cb: u32::try_from(block.len())?,
+                        lpb: block.as_ptr() as *mut _,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            values.push(PropValue {
+                tag: PropTag(*blocks_tag),
+                value: PropValueData::BinaryArray(Cow::Owned(blocks)),
+            });
+        }
+
+        if !empty.is_empty() {
+            message.delete_props(&empty)?;
+        }
+        message.set_props(&values)?;
+        message.save_changes(Default::default())
+    }
+}
+
+/// Convert a [`SystemTime`] to minutes since January 1, 1601, rounding down.
+pub(crate) fn to_rtime(time: SystemTime) -> i64 {
+    let epoch = (FILETIME_UNIX_EPOCH_SECONDS / 60) as i64;
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => epoch.saturating_add((since.as_secs() / 60) as i64),
+        Err(err) => epoch.saturating_sub(err.duration().as_secs().div_ceil(60) as i64),
+    }
+}
+
+/// Convert minutes since January 1, 1601 to a [`SystemTime`].
+pub(crate) fn from_rtime(minutes: i64) -> SystemTime {
+    let since_1601 = Duration::from_secs(minutes.max(0) as u64 * 60);
+    let epoch = Duration::from_secs(FILETIME_UNIX_EPOCH_SECONDS);
+    if since_1601 >= epoch {
+        UNIX_EPOCH + (since_1601 - epoch)
+    } else {
+        UNIX_EPOCH - (epoch - since_1601)
+    }
+}
+
+/// Count the days from January 1, 1970 to the first day of `month` in `year`.
+fn days_from_civil(year: i64, month: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Get the year and month containing a number of days since January 1, 1970.
+fn civil_from_days(days: i64) -> (i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month)
+}
+
+/// Get the start of `month` in `year` in minutes since January 1, 1601.
+pub(crate) fn month_start(year: i64, month: i64) -> i64 {
+    (days_from_civil(year, month) - days_from_civil(1601, 1)) * MINUTES_PER_DAY
+}
+
+/// Get the year and month containing a time in minutes since January 1, 1601.
+pub(crate) fn month_of(minutes: i64) -> (i64, i64) {
+    civil_from_days(minutes.div_euclid(MINUTES_PER_DAY) + days_from_civil(1601, 1))
+}
+
+/// Decode the blocks in each month into sorted ranges of minutes since January 1, 1601, joining
+/// ranges which touch.
+fn decode(months: &[i32], blocks: &[&[u8]]) -> Result<Vec<Range<i64>>> {
+    if months.len() != blocks.len() {
+        return Err(Error::from(sys::MAPI_E_CORRUPT_DATA));
+    }
+
+    let mut ranges = Vec::new();
+    for (month, blocks) in months.iter().zip(blocks) {
+        let (year, month) = (i64::from(*month >> 4), i64::from(*month & 0xF));
+        if !(1..=12).contains(&month) || blocks.len() % 4 != 0 {
+            return Err(Error::from(sys::MAPI_E_CORRUPT_DATA));
+        }
+        let start_of_month = month_start(year, month);
+        for block in blocks.chunks_exact(4) {
+            let start = i64::from(u16::from_le_bytes([block[0], block[1]]));
+            let end = i64::from(u16::from_le_bytes([block[2], block[3]]));
+            if end < start {
+                return Err(Error::from(sys::MAPI_E_CORRUPT_DATA));
+            }
+            ranges.push(start_of_month + start..start_of_month + end);
+        }
+    }
+    Ok(join(ranges))
+}
+
+/// Encode ranges of minutes since January 1, 1601 into the months and blocks properties,
+/// splitting ranges which cross into another month.
+fn encode(ranges: impl Iterator<Item = Range<i64>>) -> (Vec<i32>, Vec<Vec<u8>>) {
+    let mut months: BTreeMap<i32, Vec<u8>> = BTreeMap::new();
+    for range in join(ranges.collect()) {
+        let mut start = range.start;
+        while start < range.end {
+            let (year, month) = month_of(start);
+            let start_of_month = month_start(year, month);
+            let next_month = if month == 12 {
+                month_start(year + 1, 1)
+            } else {
+                month_start(year, month + 1)
+            };
+            let end = range.end.min(next_month);
+            let Ok(key) = i32::try_from(year * 16 + month) else {
+                break;
+            };
+            let blocks = months.entry(key).or_default();
+            blocks.extend_from_slice(&((start - start_of_month) as u16).to_le_bytes());
+            blocks.extend_from_slice(&((end - start_of_month) as u16).to_le_bytes());
+            start = end;
+        }
+    }
+    months.into_iter().unzip()
+}
+
+/// Sort the ranges, drop empty ones, and join any which overlap or touch.
+fn join(mut ranges: Vec<Range<i64>>) -> Vec<Range<i64>> {
+    ranges.retain(|range| range.start < range.end);
+    ranges.sort_by_key(|range| range.start);
+    let mut joined: Vec<Range<i64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match joined.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => joined.push(range),
+        }
+    }
+    joined
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calendar_math() {
+        assert_eq!(days_from_civil(1970, 1), 0);
+        assert_eq!(month_start(1601, 1), 0);
+        assert_eq!(month_of(0), (1601, 1));
+        assert_eq!(
+            month_of(month_start(2024, 2) + 29 * MINUTES_PER_DAY - 1),
+            (2024, 2)
+        );
+        assert_eq!(month_of(month_start(2024, 3)), (2024, 3));
+        assert_eq!(civil_from_days(days_from_civil(2000, 12)), (2000, 12));
+    }
+
+    #[test]
+    fn rtime() {
+        let epoch = to_rtime(UNIX_EPOCH);
+        assert_eq!(epoch, month_start(1970, 1));
+        assert_eq!(from_rtime(epoch), UNIX_EPOCH);
+        assert_eq!(to_rtime(UNIX_EPOCH + Duration::from_secs(119)), epoch + 1);
+    }
+
+    #[test]
+    fn decode_blocks() {
+        let month = 2024 * 16 + 3;
+        let blocks = [60_u16, 120, 120, 180, 600, 660]
+            .iter()
+            .flat_map(|minutes| minutes.to_le_bytes())
+            .collect::<Vec<_>>();
+        let start = month_start(2024, 3);
+        assert_eq!(
+            decode(&[month], &[&blocks]).expect("decode failed"),
+            [start + 60..start + 180, start + 600..start + 660]
+        );
+    }
+
+    #[test]
+    fn decode_corrupt() {
+        let month = 2024 * 16 + 3;
+        for (months, blocks) in [
+            (&[month][..], &[&[0_u8; 3][..]][..]),
+            (&[month], &[]),
+            (&[2024 * 16 + 13], &[&[0; 4]]),
+            (&[month], &[&[2, 0, 1, 0]]),
+        ] {
+            assert_eq!(
+                decode(months, blocks).map_err(|err| err.code()),
+                Err(sys::MAPI_E_CORRUPT_DATA)
+            );
+        }
+    }
+
+    #[test]
+    fn encode_across_months() {
+        let end_of_january = month_start(2025, 2);
+        let range = end_of_january - 30..end_of_january + 90;
+        let (months, blocks) = encode([range.clone()].into_iter());
+        assert_eq!(months, [2025 * 16 + 1, 2025 * 16 + 2]);
+        let minutes = 31 * MINUTES_PER_DAY as u16;
+        assert_eq!(
+            blocks[0],
+            [(minutes - 30).to_le_bytes(), minutes.to_le_bytes()].concat()
+        );
+        assert_eq!(
+            blocks[1],
+            [0_u16.to_le_bytes(), 90_u16.to_le_bytes()].concat()
+        );
+
+        let blocks: Vec<_> = blocks.iter().map(Vec::as_slice).collect();
+        assert_eq!(decode(&months, &blocks).expect("decode failed"), [range]);
+    }
+
+    #[test]
+    fn join_ranges() {
+        let joined = join(vec![5..10, 0..5, 20..20, 8..12, 15..16]);
+        assert_eq!(joined, [0..12, 15..16]);
+        let (months, blocks) = encode([].into_iter());
+        assert!(months.is_empty() && blocks.is_empty());
+    }
+}
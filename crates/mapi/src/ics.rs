@@ -0,0 +1,670 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`SyncKind`], [`SyncFlags`], [`SyncState`], [`ImportSink`], [`ImportDeletion`], and
+//! [`ReadStateChange`].
+//!
+//! Incremental Change Synchronization (ICS) lets a client download only what changed in a folder
+//! since the last time it synchronized. The folder hands out a [`sys::IExchangeExportChanges`]
+//! synchronizer, which compares an opaque state blob from the previous run with the current
+//! contents, and reports each difference to an importer object. [`crate::Folder::export_changes`]
+//! drives the synchronizer with an importer that forwards each change to an [`ImportSink`], and
+//! keeps the state blob up to date in a [`SyncState`].
+
+use crate::{mapi_error::with_last_error, sys, PropValue, PropertyStream};
+use core::{ptr, slice};
+use std::{
+    io::{Read, Seek, SeekFrom, Write},
+    panic::{self, AssertUnwindSafe},
+    sync::{Mutex, PoisonError},
+};
+use windows::Win32::System::Com::IStream;
+use windows_core::*;
+
+/// `SYNC_W_PROGRESS`, which is missing from [`sys`]. [`sys::IExchangeExportChanges::Synchronize`]
+/// returns this until it has exported every change.
+const SYNC_W_PROGRESS: HRESULT = HRESULT(0x00040820);
+
+/// `SYNC_E_IGNORE`, which is missing from [`sys`]. An importer returns this to skip the contents
+/// of a changed message.
+const SYNC_E_IGNORE: HRESULT = HRESULT(0x80040801_u32 as _);
+
+/// Which synchronizer to open with [`crate::Folder::export_changes`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncKind {
+    /// [`sys::PR_CONTENTS_SYNCHRONIZER`] reports changes to the messages in the folder.
+    Contents,
+
+    /// [`sys::PR_HIERARCHY_SYNCHRONIZER`] reports changes to the subfolders of the folder.
+    Hierarchy,
+}
+
+impl From<SyncKind> for u32 {
+    fn from(value: SyncKind) -> Self {
+        match value {
+            SyncKind::Contents => sys::PR_CONTENTS_SYNCHRONIZER,
+            SyncKind::Hierarchy => sys::PR_HIERARCHY_SYNCHRONIZER,
+        }
+    }
+}
+
+/// Set of flags that can be passed to [`crate::Folder::export_changes`].
+#[derive(Default)]
+pub struct SyncFlags {
+    /// Pass [`sys::SYNC_UNICODE`].
+    pub unicode: bool,
+
+    /// Pass [`sys::SYNC_NORMAL`] to include normal messages in a [`SyncKind::Contents`] sync.
+    pub normal: bool,
+
+    /// Pass [`sys::SYNC_ASSOCIATED`] to include associated messages in a [`SyncKind::Contents`]
+    /// sync.
+    pub associated: bool,
+
+    /// Pass [`sys::SYNC_READ_STATE`] to report changes to the read state of messages.
+    pub read_state: bool,
+
+    /// Pass [`sys::SYNC_NO_DELETIONS`].
+    pub no_deletions: bool,
+
+    /// Pass [`sys::SYNC_NO_SOFT_DELETIONS`].
+    pub no_soft_deletions: bool,
+
+    /// Pass [`sys::SYNC_CATCHUP`] to bring the [`SyncState`] up to date without reporting any
+    /// changes.
+    pub catchup: bool,
+
+    /// Pass [`sys::SYNC_BEST_BODY`].
+    pub best_body: bool,
+}
+
+impl From<SyncFlags> for u32 {
+    fn from(value: SyncFlags) -> Self {
+        let unicode = if value.unicode { sys::SYNC_UNICODE } else { 0 };
+        let normal = if value.normal { sys::SYNC_NORMAL } else { 0 };
+        let associated = if value.associated {
+            sys::SYNC_ASSOCIATED
+        } else {
+            0
+        };
+        let read_state = if value.read_state {
+            sys::SYNC_READ_STATE
+        } else {
+            0
+        };
+        let no_deletions = if value.no_deletions {
+            sys::SYNC_NO_DELETIONS
+        } else {
+            0
+        };
+        let no_soft_deletions = if value.no_soft_deletions {
+            sys::SYNC_NO_SOFT_DELETIONS
+        } else {
+            0
+        };
+        let catchup = if value.catchup { sys::SYNC_CATCHUP } else { 0 };
+        let best_body = if value.best_body {
+            sys::SYNC_BEST_BODY
+        } else {
+            0
+        };
+
+        unicode
+            | normal
+            | associated
+            | read_state
+            | no_deletions
+            | no_soft_deletions
+            | catchup
+            | best_body
+    }
+}
+
+/// Opaque state blob which records how far a synchronizer has gotten. Start with an empty
+/// [`SyncState`] to export everything, and save the bytes after each
+/// [`crate::Folder::export_changes`] to pick up where it left off next time.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncState(Vec<u8>);
+
+impl SyncState {
+    /// Create an empty [`SyncState`] for the initial sync.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Check if this is the state for an initial sync.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Get the serialized state, e.g. to save it between sessions.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Take the serialized state out of the [`SyncState`].
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+impl From<Vec<u8>> for SyncState {
+    fn from(value: Vec<u8>) -> Self {
+        Self(value)
+    }
+}
+
+/// Messages or folders which were deleted, passed to [`ImportSink::message_deletion`] or
+/// [`ImportSink::folder_deletion`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImportDeletion<'a> {
+    /// [`sys::PR_SOURCE_KEY`] of each deleted item.
+    pub source_keys: Vec<&'a [u8]>,
+
+    /// [`sys::SYNC_SOFT_DELETE`] is set, so the items can still be recovered.
+    pub soft_delete: bool,
+
+    /// [`sys::SYNC_EXPIRY`] is set, so the items were removed by a retention policy.
+    pub expiry: bool,
+}
+
+impl<'a> ImportDeletion<'a> {
+    /// Convert the arguments of `ImportMessageDeletion` or `ImportFolderDeletion`.
+    ///
+    /// # Safety
+    ///
+    /// `source_keys` must be `null` or point to a valid [`sys::SBinaryArray`], which outlives the
+    /// result.
+    unsafe fn new(flags: u32, source_keys: *const sys::SBinaryArray) -> Self {
+        let source_keys = match source_keys.as_ref() {
+            Some(source_keys) if !source_keys.lpbin.is_null() => {
+                slice::from_raw_parts(source_keys.lpbin, source_keys.cValues as usize)
+                    .iter()
+                    .map(|bin| binary(bin.cb, bin.lpb))
+                    .collect()
+            }
+            _ => Default::default(),
+        };
+        Self {
+            source_keys,
+            soft_delete: flags & sys::SYNC_SOFT_DELETE != 0,
+            expiry: flags & sys::SYNC_EXPIRY != 0,
+        }
+    }
+}
+
+/// Change to the read state of a message, passed to [`ImportSink::read_state_change`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReadStateChange<'a> {
+    /// [`sys::PR_SOURCE_KEY`] of the message.
+    pub source_key: &'a [u8],
+
+    /// [`sys::MSGFLAG_READ`] is set.
+    pub read: bool,
+}
+
+/// Receive the changes reported by [`crate::Folder::export_changes`]. Each method has a default
+/// implementation which ignores the change, so a sink only needs to handle the ones it cares
+/// about. Returning an error stops the sync, and the [`SyncState`] is left unchanged.
+pub trait ImportSink {
+    /// A message was created or modified. `values` has the [`sys::PR_SOURCE_KEY`],
+    /// [`sys::PR_CHANGE_KEY`], [`sys::PR_ENTRYID`], and other header properties the store
+    /// includes, but not the contents, which can be read by opening the message. `new_message` is
+    /// set if the message was created since the last sync.
+    fn message_change(&mut self, values: &[PropValue], new_message: bool) -> Result<()> {
+        let _ = (values, new_message);
+        Ok(())
+    }
+
+    /// One or more messages were deleted.
+    fn message_deletion(&mut self, deletion: &ImportDeletion) -> Result<()> {
+        let _ = deletion;
+        Ok(())
+    }
+
+    /// The read state of one or more messages changed, which is only reported with
+    /// [`SyncFlags::read_state`].
+    fn read_state_change(&mut self, changes: &[ReadStateChange]) -> Result<()> {
+        let _ = changes;
+        Ok(())
+    }
+
+    /// A folder was created, modified, or moved. `values` has the [`sys::PR_SOURCE_KEY`],
+    /// [`sys::PR_PARENT_SOURCE_KEY`], [`sys::PR_DISPLAY_NAME_W`], and the other folder properties.
+    fn folder_change(&mut self, values: &[PropValue]) -> Result<()> {
+        let _ = values;
+        Ok(())
+    }
+
+    /// One or more folders were deleted.
+    fn folder_deletion(&mut self, deletion: &ImportDeletion) -> Result<()> {
+        let _ = deletion;
+        Ok(())
+    }
+}
+
+type Sink = Box<dyn ImportSink + Send>;
+
+/// Implementation of [`sys::IExchangeImportContentsChanges`] and
+/// [`sys::IExchangeImportHierarchyChanges`] which forwards each change to an [`ImportSink`].
+///
+/// Like [`crate::ProgressSink`], the synchronizer may call it on a different thread, so the sink
+/// must be [`Send`]. The [`ImportSink`] methods take `&mut self`, so unlike the other sinks, calls
+/// to it are serialized with a lock. If one of them panics, the panic is caught inside of the lock,
+/// so it is not poisoned, and the import returns [`sys::MAPI_E_CALL_FAILED`] to stop the sync.
+#[implement(
+    sys::IExchangeImportContentsChanges,
+    sys::IExchangeImportHierarchyChanges
+)]
+struct Importer(Mutex<Sink>);
+
+impl Importer {
+    fn create(sink: Sink) -> IUnknown {
+        Self(Mutex::new(sink)).into()
+    }
+
+    fn with_sink<F>(&self, callback: F) -> Result<()>
+    where
+        F: FnOnce(&mut dyn ImportSink) -> Result<()>,
+    {
+        let mut sink = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        panic::catch_unwind(AssertUnwindSafe(|| callback(sink.as_mut())))
+            .unwrap_or_else(|_| Err(Error::from(sys::MAPI_E_CALL_FAILED)))
+    }
+}
+
+impl sys::IExchangeImportContentsChanges_Impl for Importer_Impl {
+    fn GetLastError(&self, _: HRESULT, _: u32, _: *mut *mut sys::MAPIERROR) -> Result<()> {
+        Err(sys::MAPI_E_NO_SUPPORT.into())
+    }
+
+    fn Config(&self, _: Ref<'_, IStream>, _: u32) -> Result<()> {
+        Ok(())
+    }
+
+    fn UpdateState(&self, _: Ref<'_, IStream>) -> Result<()> {
+        Ok(())
+    }
+
+    fn ImportMessageChange(
+        &self,
+        cpvalchanges: u32,
+        ppvalchanges: *mut sys::SPropValue,
+        ulflags: u32,
+        _: OutRef<'_, sys::IMessage>,
+    ) -> Result<()> {
+        let values = unsafe { prop_values(cpvalchanges, ppvalchanges) };
+        self.with_sink(|sink| sink.message_change(&values, ulflags & sys::SYNC_NEW_MESSAGE != 0))?;
+
+        // Skip the contents, the sink can open the message itself if it needs them.
+        Err(SYNC_E_IGNORE.into())
+    }
+
+    fn ImportMessageDeletion(
+        &self,
+        ulflags: u32,
+        lpsrcentrylist: *mut sys::SBinaryArray,
+    ) -> Result<()> {
+        let deletion = unsafe { ImportDeletion::new(ulflags, lpsrcentrylist) };
+        self.with_sink(|sink| sink.message_deletion(&deletion))
+    }
+
+    fn ImportPerUserReadStateChange(
+        &self,
+        celements: u32,
+        lpreadstate: *mut sys::READSTATE,
+    ) -> Result<()> {
+        if lpreadstate.is_null() {
+            return Ok(());
+        }
+        let changes: Vec<_> = unsafe { slice::from_raw_parts(lpreadstate, celements as usize) }
+            .iter()
+            .map(|state| ReadStateChange {
+                source_key: unsafe { binary(state.cbSourceKey, state.pbSourceKey) },
+                read: state.ulFlags & sys::MSGFLAG_READ != 0,
+            })
+            .collect();
+        self.with_sink(|sink| sink.read_state_change(&changes))
+    }
+
+    fn ImportMessageMove(
+        &self,
+        _: u32,
+        _: *mut u8,
+        _: u32,
+        _: *mut u8,
+        _: u32,
+        _: *mut u8,
+        _: u32,
+        _: *mut u8,
+        _: u32,
+        _: *mut u8,
+    ) -> Result<()> {
+        // The synchronizer reports moves as a deletion and a change unless the importer asks for
+        // them, which this one does not.
+        Err(sys::MAPI_E_NO_SUPPORT.into())
+    }
+}
+
+impl sys::IExchangeImportHierarchyChanges_Impl for Importer_Impl {
+    fn GetLastError(&self, _: HRESULT, _: u32, _: *mut *mut sys::MAPIERROR) -> Result<()> {
+        Err(sys::MAPI_E_NO_SUPPORT.into())
+    }
+
+    fn Config(&self, _: Ref<'_, IStream>, _: u32) -> Result<()> {
+        Ok(())
+    }
+
+    fn UpdateState(&self, _: Ref<'_, IStream>) -> Result<()> {
+        Ok(())
+    }
+
+    fn ImportFolderChange(
+        &self,
+        cpvalchanges: u32,
+        ppvalchanges: *mut sys::SPropValue,
+    ) -> Result<()> {
+        let values = unsafe { prop_values(cpvalchanges, ppvalchanges) };
+        self.with_sink(|sink| sink.folder_change(&values))
+    }
+
+    fn ImportFolderDeletion(
+        &self,
+        ulflags: u32,
+        lpsrcentrylist: *mut sys::SBinaryArray,
+    ) -> Result<()> {
+        let deletion = unsafe { ImportDeletion::new(ulflags, lpsrcentrylist) };
+        self.with_sink(|sink| sink.folder_deletion(&deletion))
+    }
+}
+
+/// Convert the property values passed to `ImportMessageChange` or `ImportFolderChange`.
+///
+/// # Safety
+///
+/// `values` must be `null` or point to `count` valid [`sys::SPropValue`] entries, which outlive
+/// the result.
+unsafe fn prop_values<'a>(count: u32, values: *const sys::SPropValue) -> Vec<PropValue<'a>> {
+    if values.is_null() {
+        return Default::default();
+    }
+    slice::from_raw_parts(values, count as usize)
+        .iter()
+        .map(PropValue::from)
+        .collect()
+}
+
+/// Borrow a counted byte buffer, which may be `null` if it is empty.
+///
+/// # Safety
+///
+/// `data` must be `null` or point to `count` bytes, which outlive the result.
+unsafe fn binary<'a>(count: u32, data: *const u8) -> &'a [u8] {
+    if data.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(data, count as usize)
+    }
+}
+
+/// Run an [`sys::IExchangeExportChanges`] synchronizer to completion, reporting the changes to
+/// `sink`, and read the updated state back into `state`.
+pub(crate) fn synchronize(
+    exporter: &sys::IExchangeExportChanges,
+    state: &mut SyncState,
+    flags: SyncFlags,
+    sink: Sink,
+) -> Result<()> {
+    let mut stream = PropertyStream::memory()?;
+    stream.write_all(state.as_bytes())?;
+    stream.seek(SeekFrom::Start(0))?;
+
+    let importer = Importer::create(sink);
+    unsafe {
+        exporter
+            .Config(
+                &stream.stream,
+                flags.into(),
+                &importer,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                0,
+            )
+            .map_err(|error| with_last_error(exporter, error))?;
+    }
+
+    let mut steps = 0;
+    let mut progress = 0;
+    loop {
+        let result = unsafe {
+            (Interface::vtable(exporter).Synchronize)(
+                Interface::as_raw(exporter),
+                &mut steps,
+                &mut progress,
+            )
+        };
+        if result != SYNC_W_PROGRESS {
+            result
+                .ok()
+                .map_err(|error| with_last_error(exporter, error))?;
+            break;
+        }
+    }
+
+    // Replace the old state with the new one.
+    stream.set_size(0)?;
+    stream.seek(SeekFrom::Start(0))?;
+    unsafe {
+        exporter
+            .UpdateState(&stream.stream)
+            .map_err(|error| with_last_error(exporter, error))?;
+    }
+    stream.seek(SeekFrom::Start(0))?;
+    let mut updated = Vec::new();
+    stream.read_to_end(&mut updated)?;
+    *state = SyncState(updated);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PropTag, PropValueData};
+    use std::sync::mpsc;
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Event {
+        MessageChange(Vec<u8>, bool),
+        MessageDeletion(Vec<Vec<u8>>, bool),
+        ReadState(Vec<(Vec<u8>, bool)>),
+    }
+
+    struct TestSink(mpsc::Sender<Event>);
+
+    impl ImportSink for TestSink {
+        fn message_change(&mut self, values: &[PropValue], new_message: bool) -> Result<()> {
+            let source_key = values
+                .iter()
+                .find_map(|value| match value.value {
+                    PropValueData::Binary(bin) if value.tag == PropTag(sys::PR_SOURCE_KEY) => {
+                        Some(bin.to_vec())
+                    }
+                    _ => None,
+                })
+                .unwrap_or_default();
+            let _ = self.0.send(Event::MessageChange(source_key, new_message));
+            Ok(())
+        }
+
+        fn message_deletion(&mut self, deletion: &ImportDeletion) -> Result<()> {
+            let keys = deletion
+                .source_keys
+                .iter()
+                .map(|key| key.to_vec())
+                .collect();
+            let _ = self
+                .0
+                .send(Event::MessageDeletion(keys, deletion.soft_delete));
+            Ok(())
+        }
+
+        fn read_state_change(&mut self, changes: &[ReadStateChange]) -> Result<()> {
+            let changes = changes
+                .iter()
+                .map(|change| (change.source_key.to_vec(), change.read))
+                .collect();
+            let _ = self.0.send(Event::ReadState(changes));
+            Ok(())
+        }
+
+        fn folder_deletion(&mut self, _: &ImportDeletion) -> Result<()> {
+            Err(sys::MAPI_E_CALL_FAILED.into())
+        }
+    }
+
+    fn create_sink() -> (IUnknown, mpsc::Receiver<Event>) {
+        let (sender, receiver) = mpsc::channel();
+        (Importer::create(Box::new(TestSink(sender))), receiver)
+    }
+
+    struct PanicSink;
+
+    impl ImportSink for PanicSink {
+        fn message_change(&mut self, _: &[PropValue], _: bool) -> Result<()> {
+            panic!("sink panicked");
+        }
+    }
+
+    #[test]
+    fn catch_sink_panic() {
+        let importer = Importer::create(Box::new(PanicSink));
+        let importer: sys::IExchangeImportContentsChanges = importer.cast().expect("cast failed");
+
+        let mut values = [sys::SPropValue {
+            ulPropTag: sys::PR_SOURCE_KEY,
+            ..Default::default()
+        }];
+        for _ in 0..2 {
+            let mut message = None;
+            let result = unsafe {
+                importer.ImportMessageChange(
+                    values.len() as u32,
+                    values.as_mut_ptr(),
+                    0,
+                    &mut message,
+                )
+            };
+            assert_eq!(
+                result.map_err(|error| error.code()),
+                Err(sys::MAPI_E_CALL_FAILED)
+            );
+        }
+    }
+
+    #[test]
+    fn sync_flags() {
+        let flags: u32 = SyncFlags {
+            unicode: true,
+            normal: true,
+            read_state: true,
+            ..Default::default()
+        }
+        .into();
+        assert_eq!(
+            flags,
+            sys::SYNC_UNICODE | sys::SYNC_NORMAL | sys::SYNC_READ_STATE
+        );
+        assert_eq!(u32::from(SyncFlags::default()), 0);
+    }
+
+    #[test]
+    fn forward_contents_changes() {
+        let (importer, receiver) = create_sink();
+        let importer: sys::IExchangeImportContentsChanges = importer.cast().expect("cast failed");
+
+        let mut source_key = [1_u8, 2, 3];
+        let mut values = [sys::SPropValue {
+            ulPropTag: sys::PR_SOURCE_KEY,
+            Value: sys::__UPV {
+                bin: sys::SBinary {
+                    cb: source_key.len() as u32,
+                    lpb: source_key.as_mut_ptr(),
+                },
+            },
+            ..Default::default()
+        }];
+        let mut message = None;
+        let result = unsafe {
+            importer.ImportMessageChange(
+                values.len() as u32,
+                values.as_mut_ptr(),
+                sys::SYNC_NEW_MESSAGE,
+                &mut message,
+            )
+        };
+        assert_eq!(result.map_err(|error| error.code()), Err(SYNC_E_IGNORE));
+        assert!(message.is_none());
+        assert_eq!(
+            receiver.try_recv(),
+            Ok(Event::MessageChange(vec![1, 2, 3], true))
+        );
+
+        let mut other_key = [4_u8];
+        let mut bins = [
+            sys::SBinary {
+                cb: source_key.len() as u32,
+                lpb: source_key.as_mut_ptr(),
+            },
+            sys::SBinary {
+                cb: other_key.len() as u32,
+                lpb: other_key.as_mut_ptr(),
+            },
+        ];
+        let mut deleted = sys::SBinaryArray {
+            cValues: bins.len() as u32,
+            lpbin: bins.as_mut_ptr(),
+        };
+        unsafe {
+            importer
+                .ImportMessageDeletion(sys::SYNC_SOFT_DELETE, &mut deleted)
+                .expect("ImportMessageDeletion failed");
+        }
+        assert_eq!(
+            receiver.try_recv(),
+            Ok(Event::MessageDeletion(vec![vec![1, 2, 3], vec![4]], true))
+        );
+
+        let mut read_state = [sys::READSTATE {
+            cbSourceKey: other_key.len() as u32,
+            pbSourceKey: other_key.as_mut_ptr(),
+            ulFlags: sys::MSGFLAG_READ,
+        }];
+        unsafe {
+            importer
+                .ImportPerUserReadStateChange(read_state.len() as u32, read_state.as_mut_ptr())
+                .expect("ImportPerUserReadStateChange failed");
+        }
+        assert_eq!(
+            receiver.try_recv(),
+            Ok(Event::ReadState(vec![(vec![4], true)]))
+        );
+    }
+
+    #[test]
+    fn forward_hierarchy_errors() {
+        let (importer, receiver) = create_sink();
+        let importer: sys::IExchangeImportHierarchyChanges = importer.cast().expect("cast failed");
+        unsafe {
+            importer
+                .ImportFolderChange(0, ptr::null_mut())
+                .expect("ImportFolderChange failed");
+            let result = importer.ImportFolderDeletion(0, ptr::null_mut());
+            assert_eq!(
+                result.map_err(|error| error.code()),
+                Err(sys::MAPI_E_CALL_FAILED)
+            );
+        }
+        assert!(receiver.try_recv().is_err());
+    }
+}
@@ -0,0 +1,182 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`InstallationState`] and [`check_outlook_mapi_installation`].
+
+use crate::{runtime_info::file_version, FileVersion, MapiModuleSource};
+use std::{env, fs::File, io::Read, path::PathBuf};
+use windows::Win32::{Foundation::*, System::LibraryLoader::GetModuleHandleW};
+use windows_core::*;
+
+/// Number of bytes to read from the start of a DLL to find the machine type in the PE header.
+const PE_HEADER_PROBE: u64 = 4096;
+
+/// Where the MAPI DLL is installed and which architecture it was built for, returned from
+/// [`check_outlook_mapi_installation`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InstallationState {
+    /// Full path of the DLL.
+    pub path: PathBuf,
+
+    /// `FileVersion` of the DLL, if it has a version resource.
+    pub file_version: Option<FileVersion>,
+
+    /// Architecture of the DLL from its PE header, using the same names as
+    /// [`std::env::consts::ARCH`], e.g. `x86` or `x86_64`.
+    pub architecture: &'static str,
+
+    /// Whether it is Outlook's `olmapi32.dll`, and how it was found, or the `mapi32.dll` stub.
+    pub source: MapiModuleSource,
+}
+
+impl InstallationState {
+    /// Check if this is Outlook's `olmapi32.dll` rather than the `mapi32.dll` stub.
+    pub fn is_outlook(&self) -> bool {
+        !matches!(self.source, MapiModuleSource::Mapi32)
+    }
+
+    /// Check if the DLL can be loaded in this process, i.e. it was built for
+    /// [`std::env::consts::ARCH`].
+    pub fn matches_process(&self) -> bool {
+        self.architecture == env::consts::ARCH
+    }
+
+    /// Return an error describing the mismatch if [`InstallationState::matches_process`] is
+    /// `false`.
+    pub fn check_architecture(&self) -> Result<()> {
+        if self.matches_process() {
+            return Ok(());
+        }
+        Err(Error::new(
+            ERROR_BAD_EXE_FORMAT.to_hresult(),
+            format!(
+                "{architecture} MAPI at {path} cannot be loaded in this {process} process; \
+                 build for {architecture} or install the {process} version of Outlook",
+                architecture = self.architecture,
+                path = self.path.display(),
+                process = env::consts::ARCH,
+            ),
+        ))
+    }
+}
+
+/// Find the MAPI DLL which the bindings are going to load, and check that it matches the
+/// architecture of this process. Unlike [`crate::mapi_runtime_info`], this does not load
+/// Outlook's `olmapi32.dll`, so it can explain why loading it would fail.
+///
+/// If Outlook is not installed, this loads the `mapi32.dll` stub to describe it.
+pub fn check_outlook_mapi_installation() -> Result<InstallationState> {
+    let state = if unsafe { GetModuleHandleW(w!("olmapi32.dll")) }.is_ok() {
+        loaded_state()?
+    } else if let Some((path, source)) = outlook_mapi_sys::find_olmapi32() {
+        let architecture = dll_architecture(&path)?;
+        InstallationState {
+            file_version: file_version(&path),
+            path,
+            architecture,
+            source,
+        }
+    } else {
+        loaded_state()?
+    };
+    state.check_architecture()?;
+    Ok(state)
+}
+
+/// Describe the DLL which is already loaded with [`crate::mapi_runtime_info`].
+fn loaded_state() -> Result<InstallationState> {
+    let info = crate::mapi_runtime_info()?;
+    Ok(InstallationState {
+        path: info.path,
+        file_version: info.file_version,
+        architecture: info.architecture,
+        source: info.source,
+    })
+}
+
+/// Read the start of the file at `path` and get the architecture from the PE header.
+fn dll_architecture(path: &std::path::Path) -> Result<&'static str> {
+    let mut header = Vec::new();
+    File::open(path)
+        .and_then(|file| file.take(PE_HEADER_PROBE).read_to_end(&mut header))
+        .map_err(|err| Error::new(E_FAIL, format!("{}: {err}", path.display())))?;
+    pe_architecture(&header).ok_or_else(|| {
+        Error::new(
+            ERROR_BAD_EXE_FORMAT.to_hresult(),
+            format!("{} is not a recognized DLL", path.display()),
+        )
+    })
+}
+
+/// Parse the `IMAGE_FILE_HEADER::Machine` field from the start of a PE file, and map it to the
+/// name [`std::env::consts::ARCH`] uses for that architecture.
+fn pe_architecture(header: &[u8]) -> Option<&'static str> {
+    const IMAGE_FILE_MACHINE_I386: u16 = 0x014C;
+    const IMAGE_FILE_MACHINE_ARMNT: u16 = 0x01C4;
+    const IMAGE_FILE_MACHINE_AMD64: u16 = 0x8664;
+    const IMAGE_FILE_MACHINE_ARM64: u16 = 0xAA64;
+
+    if header.get(..2)? != b"MZ" {
+        return None;
+    }
+    let offset = u32::from_le_bytes(header.get(0x3C..0x40)?.try_into().ok()?) as usize;
+    if header.get(offset..offset.checked_add(4)?)? != b"PE\0\0" {
+        return None;
+    }
+    let machine = u16::from_le_bytes(header.get(offset + 4..offset + 6)?.try_into().ok()?);
+    match machine {
+        IMAGE_FILE_MACHINE_I386 => Some("x86"),
+        IMAGE_FILE_MACHINE_ARMNT => Some("arm"),
+        IMAGE_FILE_MACHINE_AMD64 => Some("x86_64"),
+        IMAGE_FILE_MACHINE_ARM64 => Some("aarch64"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pe_header(machine: u16) -> Vec<u8> {
+        let mut header = vec![0_u8; 0x86];
+        header[..2].copy_from_slice(b"MZ");
+        header[0x3C..0x40].copy_from_slice(&0x80_u32.to_le_bytes());
+        header[0x80..0x84].copy_from_slice(b"PE\0\0");
+        header[0x84..0x86].copy_from_slice(&machine.to_le_bytes());
+        header
+    }
+
+    #[test]
+    fn parse_machine() {
+        assert_eq!(pe_architecture(&pe_header(0x014C)), Some("x86"));
+        assert_eq!(pe_architecture(&pe_header(0x8664)), Some("x86_64"));
+        assert_eq!(pe_architecture(&pe_header(0xAA64)), Some("aarch64"));
+        assert_eq!(pe_architecture(&pe_header(0x1234)), None);
+
+        let mut truncated = pe_header(0x8664);
+        truncated.truncate(0x84);
+        assert_eq!(pe_architecture(&truncated), None);
+        assert_eq!(pe_architecture(b"not a dll"), None);
+    }
+
+    #[test]
+    fn bitness_mismatch() {
+        let other = if env::consts::ARCH == "x86" {
+            "x86_64"
+        } else {
+            "x86"
+        };
+        let mut state = InstallationState {
+            path: PathBuf::from(r"C:\Program Files\Microsoft Office\root\Office16\olmapi32.dll"),
+            file_version: None,
+            architecture: env::consts::ARCH,
+            source: MapiModuleSource::OutlookInstall("Office 2016 or later"),
+        };
+        assert!(state.is_outlook());
+        assert!(state.check_architecture().is_ok());
+
+        state.architecture = other;
+        let error = state.check_architecture().expect_err("should not match");
+        assert_eq!(error.code(), ERROR_BAD_EXE_FORMAT.to_hresult());
+    }
+}
@@ -14,23 +14,119 @@ pub mod sys {
     pub use outlook_mapi_sys::Microsoft::Office::Outlook::MAPI::Win32::*;
 }
 
+pub mod addr_book;
+pub mod advise;
+pub mod attachment;
+pub mod banner;
+pub mod bulk;
+pub mod code_page;
+pub mod column_tracker;
+pub mod columns;
+pub mod compose;
+pub mod contacts;
+pub mod conversation_index;
+pub mod display_table;
+pub mod entry_id;
+pub mod folder;
+pub mod forms;
+pub mod free_busy;
+pub mod ics;
+pub mod installation;
+pub mod limits;
+pub mod mapi_error;
 pub mod mapi_initialize;
 pub mod mapi_logon;
+pub mod mapi_prop;
 pub mod mapi_ptr;
+pub mod mapi_status;
+pub mod marshal;
+pub mod message;
+#[cfg(feature = "olmapi32")]
+pub mod mime;
+pub mod msg_export;
+pub mod msg_store;
+pub mod object_registry;
+pub mod open_policy;
+pub mod outbox;
+pub mod progress;
 pub mod prop_tag;
 pub mod prop_value;
+pub mod property_stream;
+pub mod recurrence;
+pub mod restriction;
+pub mod resume_token;
+pub mod retry;
 pub mod row;
 pub mod row_set;
+pub mod rtf;
+pub mod rules;
+pub mod runtime_info;
+pub mod service_logon;
+pub mod simple_mapi;
 pub mod sized_types;
+pub mod status;
+pub mod store_connection;
+pub mod stores;
+pub mod strings;
+pub mod table;
+pub mod tnef;
 
+pub use addr_book::*;
+pub use advise::*;
+pub use attachment::*;
+pub use banner::*;
+pub use bulk::*;
+pub use code_page::*;
+pub use column_tracker::*;
+pub use columns::ColumnSet;
+pub use compose::*;
+pub use contacts::*;
+pub use conversation_index::*;
+pub use display_table::*;
+pub use entry_id::*;
+pub use folder::*;
+pub use forms::*;
+pub use free_busy::*;
+pub use ics::*;
+pub use installation::*;
+pub use limits::*;
+pub use mapi_error::*;
 pub use mapi_initialize::*;
 pub use mapi_logon::*;
+pub use mapi_prop::*;
 pub use mapi_ptr::*;
+pub use mapi_status::*;
+pub use marshal::*;
+pub use message::*;
+#[cfg(feature = "olmapi32")]
+pub use mime::*;
+pub use msg_export::*;
+pub use msg_store::*;
+pub use object_registry::*;
+pub use open_policy::*;
+pub use outbox::*;
+pub use progress::*;
 pub use prop_tag::*;
 pub use prop_value::*;
+pub use property_stream::*;
+pub use recurrence::*;
+pub use restriction::*;
+pub use resume_token::*;
+pub use retry::*;
 pub use row::*;
 pub use row_set::*;
+pub use rtf::*;
+pub use rules::*;
+pub use runtime_info::*;
+pub use service_logon::*;
+pub use simple_mapi::*;
 pub use sized_types::*;
+pub use status::*;
+pub use store_connection::*;
+pub use stores::*;
+pub use strings::*;
+pub use table::*;
+pub use tnef::*;
 
 pub fn is_outlook_mapi_installed() -> bool {
     outlook_mapi_sys::ensure_olmapi32().is_ok()
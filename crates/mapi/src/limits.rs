@@ -0,0 +1,354 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`ReadLimits`] and [`LimitExceeded`].
+//!
+//! A single message can have a body or attachment which is hundreds of megabytes, and a folder
+//! can have hundreds of thousands of rows. Services which read arbitrary mailboxes can set
+//! [`ReadLimits`] once with [`ReadLimits::set_global`], or per table with
+//! [`crate::Table::set_read_limits`], instead of checking the size of every value at each call
+//! site.
+
+use crate::{sys, PropTag, Row, RowSet};
+use core::{fmt, slice};
+use std::sync::RwLock;
+use windows_core::*;
+
+/// Limits enforced by [`crate::MAPIProp::get_props`], [`crate::Table::query_rows`],
+/// [`crate::Table::query_all_rows`], and [`crate::Table::iter_rows`]. A limit of [`None`] means
+/// there is no limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReadLimits {
+    /// Maximum number of characters in a [`sys::PT_STRING8`] or [`sys::PT_UNICODE`] value, not
+    /// counting the `null` terminator. This also applies to each element of
+    /// [`sys::PT_MV_STRING8`] and [`sys::PT_MV_UNICODE`] values.
+    pub max_string_len: Option<usize>,
+
+    /// Maximum number of bytes in a [`sys::PT_BINARY`] value, or each element of a
+    /// [`sys::PT_MV_BINARY`] value.
+    pub max_binary_size: Option<usize>,
+
+    /// Maximum number of rows read from a table.
+    pub max_rows: Option<usize>,
+}
+
+static GLOBAL_LIMITS: RwLock<ReadLimits> = RwLock::new(ReadLimits::UNLIMITED);
+
+impl ReadLimits {
+    /// No limits, which is the default.
+    pub const UNLIMITED: Self = Self {
+        max_string_len: None,
+        max_binary_size: None,
+        max_rows: None,
+    };
+
+    /// Get the process-wide limits, which start out as [`ReadLimits::UNLIMITED`].
+    pub fn global() -> Self {
+        *GLOBAL_LIMITS
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Replace the process-wide limits. Tables with their own limits from
+    /// [`crate::Table::set_read_limits`] are not affected.
+    pub fn set_global(limits: Self) {
+        *GLOBAL_LIMITS
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = limits;
+    }
+
+    /// Check a single [`sys::SPropValue`] against [`ReadLimits::max_string_len`] and
+    /// [`ReadLimits::max_binary_size`] without copying it.
+    pub fn check_value(&self, value: &sys::SPropValue) -> core::result::Result<(), LimitExceeded> {
+        let tag = PropTag(value.ulPropTag);
+        let prop_type: u32 = tag.prop_type().remove_flags(sys::MV_INSTANCE).into();
+        unsafe {
+            match prop_type {
+                sys::PT_STRING8 if !value.Value.lpszA.is_null() => {
+                    self.check_string(tag, value.Value.lpszA.as_bytes().len())
+                }
+                sys::PT_UNICODE if !value.Value.lpszW.is_null() => {
+                    self.check_string(tag, value.Value.lpszW.len())
+                }
+                sys::PT_BINARY => self.check_binary(tag, value.Value.bin.cb as usize),
+                sys::PT_MV_STRING8 if !value.Value.MVszA.lppszA.is_null() => {
+                    let values = slice::from_raw_parts(
+                        value.Value.MVszA.lppszA,
+                        value.Value.MVszA.cValues as usize,
+                    );
+                    values
+                        .iter()
+                        .filter(|value| !value.is_null())
+                        .try_for_each(|value| self.check_string(tag, value.as_bytes().len()))
+                }
+                sys::PT_MV_UNICODE if !value.Value.MVszW.lppszW.is_null() => {
+                    let values = slice::from_raw_parts(
+                        value.Value.MVszW.lppszW,
+                        value.Value.MVszW.cValues as usize,
+                    );
+                    values
+                        .iter()
+                        .filter(|value| !value.is_null())
+                        .try_for_each(|value| self.check_string(tag, value.len()))
+                }
+                sys::PT_MV_BINARY if !value.Value.MVbin.lpbin.is_null() => {
+                    let values = slice::from_raw_parts(
+                        value.Value.MVbin.lpbin,
+                        value.Value.MVbin.cValues as usize,
+                    );
+                    values
+                        .iter()
+                        .try_for_each(|value| self.check_binary(tag, value.cb as usize))
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+
+    /// Check every value in a [`Row`].
+    pub fn check_row(&self, row: &Row) -> core::result::Result<(), LimitExceeded> {
+        row.values()
+            .iter()
+            .try_for_each(|value| self.check_value(value))
+    }
+
+    /// Check the number of rows in a [`RowSet`] against [`ReadLimits::max_rows`], and then check
+    /// every value in each row.
+    pub fn check_rows(&self, rows: &RowSet) -> core::result::Result<(), LimitExceeded> {
+        self.check_row_count(rows.len())?;
+        rows.iter().try_for_each(|row| {
+            row.values()
+                .iter()
+                .try_for_each(|value| self.check_value(value))
+        })
+    }
+
+    /// Check a number of rows against [`ReadLimits::max_rows`].
+    pub fn check_row_count(&self, count: usize) -> core::result::Result<(), LimitExceeded> {
+        match self.max_rows {
+            Some(max) if count > max => Err(LimitExceeded::RowCount { count, max }),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_string(
+        &self,
+        tag: PropTag,
+        len: usize,
+    ) -> core::result::Result<(), LimitExceeded> {
+        match self.max_string_len {
+            Some(max) if len > max => Err(LimitExceeded::StringLength { tag, len, max }),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_binary(
+        &self,
+        tag: PropTag,
+        size: usize,
+    ) -> core::result::Result<(), LimitExceeded> {
+        match self.max_binary_size {
+            Some(max) if size > max => Err(LimitExceeded::BinarySize { tag, size, max }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Errors returned when a value exceeds one of the [`ReadLimits`].
+///
+/// The methods which enforce the limits return a [`windows_core::Error`] like everything else,
+/// so this is converted to [`sys::MAPI_E_TOO_BIG`] with a message describing the limit. Call
+/// the `check_*` methods on [`ReadLimits`] directly to get the typed error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LimitExceeded {
+    /// A string value has more than [`ReadLimits::max_string_len`] characters.
+    StringLength {
+        /// Property which exceeded the limit.
+        tag: PropTag,
+
+        /// Number of characters in the string.
+        len: usize,
+
+        /// Value of [`ReadLimits::max_string_len`].
+        max: usize,
+    },
+
+    /// A binary value has more than [`ReadLimits::max_binary_size`] bytes.
+    BinarySize {
+        /// Property which exceeded the limit.
+        tag: PropTag,
+
+        /// Number of bytes in the value.
+        size: usize,
+
+        /// Value of [`ReadLimits::max_binary_size`].
+        max: usize,
+    },
+
+    /// A table returned more than [`ReadLimits::max_rows`] rows.
+    RowCount {
+        /// Number of rows, or at least one more than the limit if the table stopped early.
+        count: usize,
+
+        /// Value of [`ReadLimits::max_rows`].
+        max: usize,
+    },
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StringLength { tag, len, max } => write!(
+                f,
+                "string property 0x{:08X} has {len} characters, limit is {max}",
+                tag.0
+            ),
+            Self::BinarySize { tag, size, max } => write!(
+                f,
+                "binary property 0x{:08X} has {size} bytes, limit is {max}",
+                tag.0
+            ),
+            Self::RowCount { count, max } => {
+                write!(f, "table returned {count} rows, limit is {max}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+impl From<LimitExceeded> for Error {
+    fn from(value: LimitExceeded) -> Self {
+        Error::new(sys::MAPI_E_TOO_BIG, value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PropType;
+    use windows_core::{s, w, PCWSTR, PSTR, PWSTR};
+
+    fn mv_tag(prop_type: u32) -> PropTag {
+        PropTag(sys::PR_NULL).change_prop_type(PropType::new(prop_type as u16))
+    }
+
+    const LIMITS: ReadLimits = ReadLimits {
+        max_string_len: Some(5),
+        max_binary_size: Some(3),
+        max_rows: Some(2),
+    };
+
+    #[test]
+    fn unlimited() {
+        let value = sys::SPropValue {
+            ulPropTag: sys::PR_SUBJECT_W,
+            Value: sys::__UPV {
+                lpszW: PWSTR(w!("longer than five").0 as *mut _),
+            },
+            ..Default::default()
+        };
+        assert_eq!(ReadLimits::UNLIMITED.check_value(&value), Ok(()));
+        assert_eq!(ReadLimits::UNLIMITED.check_row_count(usize::MAX), Ok(()));
+    }
+
+    #[test]
+    fn unicode_string() {
+        let mut value = sys::SPropValue {
+            ulPropTag: sys::PR_SUBJECT_W,
+            Value: sys::__UPV {
+                lpszW: PWSTR(w!("fifty").0 as *mut _),
+            },
+            ..Default::default()
+        };
+        assert_eq!(LIMITS.check_value(&value), Ok(()));
+
+        value.Value.lpszW = PWSTR(w!("fifty-three").0 as *mut _);
+        assert_eq!(
+            LIMITS.check_value(&value),
+            Err(LimitExceeded::StringLength {
+                tag: PropTag(sys::PR_SUBJECT_W),
+                len: 11,
+                max: 5
+            })
+        );
+    }
+
+    #[test]
+    fn ansi_string_array() {
+        let mut values = [s!("one"), s!("fifty-four")];
+        let value = sys::SPropValue {
+            ulPropTag: mv_tag(sys::PT_MV_STRING8).0,
+            Value: sys::__UPV {
+                MVszA: sys::SLPSTRArray {
+                    cValues: values.len() as u32,
+                    lppszA: values.as_mut_ptr() as *mut PSTR,
+                },
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            LIMITS.check_value(&value),
+            Err(LimitExceeded::StringLength {
+                tag: mv_tag(sys::PT_MV_STRING8),
+                len: 10,
+                max: 5
+            })
+        );
+    }
+
+    #[test]
+    fn null_strings() {
+        let mut values = [PCWSTR::null()];
+        let mut value = sys::SPropValue {
+            ulPropTag: sys::PR_SUBJECT_W,
+            ..Default::default()
+        };
+        assert_eq!(LIMITS.check_value(&value), Ok(()));
+
+        value.ulPropTag = mv_tag(sys::PT_MV_UNICODE).0;
+        value.Value.MVszW = sys::SWStringArray {
+            cValues: values.len() as u32,
+            lppszW: values.as_mut_ptr() as *mut PWSTR,
+        };
+        assert_eq!(LIMITS.check_value(&value), Ok(()));
+    }
+
+    #[test]
+    fn binary() {
+        let mut bytes = [55_u8, 56, 57, 58];
+        let value = sys::SPropValue {
+            ulPropTag: sys::PR_ENTRYID,
+            Value: sys::__UPV {
+                bin: sys::SBinary {
+                    cb: bytes.len() as u32,
+                    lpb: bytes.as_mut_ptr(),
+                },
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            LIMITS.check_value(&value),
+            Err(LimitExceeded::BinarySize {
+                tag: PropTag(sys::PR_ENTRYID),
+                size: 4,
+                max: 3
+            })
+        );
+    }
+
+    #[test]
+    fn row_count() {
+        assert_eq!(LIMITS.check_row_count(2), Ok(()));
+        assert_eq!(
+            LIMITS.check_row_count(3),
+            Err(LimitExceeded::RowCount { count: 3, max: 2 })
+        );
+    }
+
+    #[test]
+    fn error_code() {
+        let err = Error::from(LimitExceeded::RowCount { count: 3, max: 2 });
+        assert_eq!(err.code(), sys::MAPI_E_TOO_BIG);
+    }
+}
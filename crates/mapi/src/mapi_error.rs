@@ -0,0 +1,205 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`MapiError`], [`LastError`], and [`last_error`].
+//!
+//! Most MAPI methods only return an `HRESULT`, but the provider usually has a better description
+//! of what went wrong, which it returns from `GetLastError` in a [`sys::MAPIERROR`]. The safe
+//! wrappers in this crate call [`last_error`] when a method fails and include the description in
+//! the [`Error`] message, so `MAPI_E_CALL_FAILED` comes with something actionable.
+
+use crate::{sys, CodePage, MAPIOutParam};
+use core::fmt;
+use windows_core::*;
+
+/// Details about a failure from a [`sys::MAPIERROR`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MapiError {
+    /// Error returned from the method which failed.
+    pub hresult: HRESULT,
+
+    /// [`sys::MAPIERROR::ulLowLevelError`], e.g. a Win32 or RPC error code.
+    pub low_level_error: u32,
+
+    /// [`sys::MAPIERROR::ulContext`], which identifies where the error happened in the component.
+    pub context: u32,
+
+    /// [`sys::MAPIERROR::lpszComponent`], e.g. the name of the provider.
+    pub component: Option<String>,
+
+    /// [`sys::MAPIERROR::lpszError`]
+    pub message: Option<String>,
+}
+
+impl MapiError {
+    /// Copy the strings from a [`sys::MAPIERROR`]. If `unicode` is `true`, it was returned with
+    /// [`sys::MAPI_UNICODE`], and the strings are actually `PCWSTR`. Otherwise they are decoded
+    /// with [`CodePage::ACP`].
+    ///
+    /// # Safety
+    ///
+    /// The string pointers in `error` must be `null` or point to `null` terminated strings of the
+    /// width indicated by `unicode`.
+    pub unsafe fn from_sys(hresult: HRESULT, error: &sys::MAPIERROR, unicode: bool) -> Self {
+        let text = |value: *mut i8| {
+            if value.is_null() {
+                None
+            } else if unicode {
+                PCWSTR(value as *const u16).to_string().ok()
+            } else {
+                CodePage::ACP
+                    .decode(PCSTR(value as *const u8).as_bytes())
+                    .ok()
+            }
+            .filter(|value| !value.is_empty())
+        };
+        Self {
+            hresult,
+            low_level_error: error.ulLowLevelError,
+            context: error.ulContext,
+            component: text(error.lpszComponent),
+            message: text(error.lpszError),
+        }
+    }
+}
+
+impl fmt::Display for MapiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(component) = &self.component {
+            write!(f, "{component}: ")?;
+        }
+        match &self.message {
+            Some(message) => write!(f, "{message}")?,
+            None => write!(f, "{}", self.hresult.message())?,
+        }
+        if self.low_level_error != 0 {
+            write!(f, " (low level error 0x{:08X})", self.low_level_error)?;
+        }
+        if self.context != 0 {
+            write!(f, " (context 0x{:08X})", self.context)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for MapiError {}
+
+impl From<MapiError> for Error {
+    fn from(value: MapiError) -> Self {
+        Error::new(value.hresult, value.to_string())
+    }
+}
+
+/// Interfaces with a `GetLastError` method, which [`last_error`] can call.
+pub trait LastError {
+    /// Call `GetLastError` on the interface.
+    ///
+    /// # Safety
+    ///
+    /// `error` must be a valid out-pointer, and the buffer it returns must be freed with
+    /// [`sys::MAPIFreeBuffer`].
+    unsafe fn get_last_error(
+        &self,
+        hresult: HRESULT,
+        flags: u32,
+        error: *mut *mut sys::MAPIERROR,
+    ) -> Result<()>;
+}
+
+macro_rules! impl_last_error {
+    ($($interface:ident),* $(,)?) => {
+        $(
+            impl LastError for sys::$interface {
+                unsafe fn get_last_error(
+                    &self,
+                    hresult: HRESULT,
+                    flags: u32,
+                    error: *mut *mut sys::MAPIERROR,
+                ) -> Result<()> {
+                    self.GetLastError(hresult, flags, error)
+                }
+            }
+        )*
+    };
+}
+
+impl_last_error!(
+    IMAPIProp,
+    IMAPITable,
+    IMAPISession,
+    IMsgServiceAdmin,
+    IProfAdmin,
+    IProviderAdmin,
+    IMAPIFormMgr,
+    IMAPIFormContainer,
+    IExchangeModifyTable,
+    IExchangeExportChanges,
+);
+
+/// Call `GetLastError` for `hresult`, first with [`sys::MAPI_UNICODE`], and then without it if the
+/// provider only supports ANSI strings. Returns [`None`] if the object has nothing to add.
+pub fn last_error<T>(object: &T, hresult: HRESULT) -> Option<MapiError>
+where
+    T: LastError + ?Sized,
+{
+    for (flags, unicode) in [(sys::MAPI_UNICODE, true), (0, false)] {
+        let mut error: MAPIOutParam<sys::MAPIERROR> = MAPIOutParam::new();
+        match unsafe { object.get_last_error(hresult, flags, error.as_mut_ptr()) } {
+            Ok(()) => {
+                let error = unsafe { error.as_mut() }?;
+                return Some(unsafe { MapiError::from_sys(hresult, error, unicode) });
+            }
+            Err(error) if error.code() == sys::MAPI_E_BAD_CHARWIDTH => continue,
+            Err(_) => return None,
+        }
+    }
+    None
+}
+
+/// Replace `error` with the [`MapiError`] from [`last_error`], if there is one with a message.
+pub(crate) fn with_last_error<T>(object: &T, error: Error) -> Error
+where
+    T: LastError + ?Sized,
+{
+    match last_error(object, error.code()) {
+        Some(mapi_error) if mapi_error.message.is_some() => mapi_error.into(),
+        _ => error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::to_pwstr_buffer;
+
+    #[test]
+    fn unicode_error() {
+        let message = to_pwstr_buffer("Network problems");
+        let component = to_pwstr_buffer("Exchange");
+        let error = sys::MAPIERROR {
+            lpszError: message.as_ptr() as *mut _,
+            lpszComponent: component.as_ptr() as *mut _,
+            ulLowLevelError: 0x6BA,
+            ..Default::default()
+        };
+        let error = unsafe { MapiError::from_sys(sys::MAPI_E_NETWORK_ERROR, &error, true) };
+        assert_eq!(error.message.as_deref(), Some("Network problems"));
+        assert_eq!(error.component.as_deref(), Some("Exchange"));
+        assert_eq!(
+            error.to_string(),
+            "Exchange: Network problems (low level error 0x000006BA)"
+        );
+
+        let error = Error::from(error);
+        assert_eq!(error.code(), sys::MAPI_E_NETWORK_ERROR);
+    }
+
+    #[test]
+    fn empty_error() {
+        let error =
+            unsafe { MapiError::from_sys(sys::MAPI_E_CALL_FAILED, &Default::default(), false) };
+        assert_eq!(error.message, None);
+        assert_eq!(error.component, None);
+        assert_eq!(error.context, 0);
+    }
+}
@@ -1,16 +1,21 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
-//! Define [`Initialize`] and [`InitializeFlags`].
+//! Define [`Initialize`], [`InitializeFlags`], [`InitializeConflict`], and [`InitEpoch`].
+
+pub use outlook_mapi_sys::{MapiModule, MapiModuleScope};
 
 use crate::sys;
-use core::ptr;
-use std::sync::Arc;
+use core::{cell::RefCell, fmt, ptr};
+use std::sync::{Arc, Mutex, Weak};
 use windows_core::*;
 
+#[cfg(feature = "init-guard")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 /// Set of flags that can be passed to [`sys::MAPIInitialize`] through the
 /// [`sys::MAPIINIT::ulFlags`] member.
-#[derive(Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct InitializeFlags {
     /// Pass [`sys::MAPI_MULTITHREAD_NOTIFICATIONS`].
     pub multithread_notifications: bool,
@@ -44,29 +49,445 @@ impl From<InitializeFlags> for u32 {
     }
 }
 
+impl InitializeFlags {
+    /// Check if the flags which affect the whole process match. [`sys::MAPI_NO_COINIT`] only
+    /// affects the calling thread, so it may be different for each call to
+    /// [`sys::MAPIInitialize`].
+    fn is_compatible(&self, other: &Self) -> bool {
+        self.multithread_notifications == other.multithread_notifications
+            && self.nt_service == other.nt_service
+    }
+}
+
+/// Error returned from [`Initialize::new`] or [`Initialize::acquire`] when MAPI is already
+/// initialized with different process-wide [`InitializeFlags`].
+///
+/// The first [`Initialize`] wins: [`sys::MAPIInitialize`] only applies
+/// [`sys::MAPI_MULTITHREAD_NOTIFICATIONS`] and [`sys::MAPI_NT_SERVICE`] the first time it is called
+/// in the process, so a later caller asking for something else would silently get the
+/// [`InitializeConflict::effective`] flags instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InitializeConflict {
+    /// Flags from the first [`Initialize`] which is still alive, i.e.
+    /// [`Initialize::effective_flags`].
+    pub effective: InitializeFlags,
+
+    /// Flags passed to [`Initialize::new`].
+    pub requested: InitializeFlags,
+}
+
+impl fmt::Display for InitializeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "MAPI is already initialized with {:?}, cannot initialize with {:?}",
+            self.effective, self.requested
+        )
+    }
+}
+
+impl std::error::Error for InitializeConflict {}
+
+impl From<InitializeConflict> for Error {
+    fn from(value: InitializeConflict) -> Self {
+        Error::new(sys::MAPI_E_INVALID_PARAMETER, value.to_string())
+    }
+}
+
 /// Call [`sys::MAPIInitialize`] in the constructor, and balance it with a call to
 /// [`sys::MAPIUninitialize`] in the destructor.
-pub struct Initialize();
+pub struct Initialize(Option<Arc<MapiModule>>);
 
 impl Initialize {
     /// Call [`sys::MAPIInitialize`] with the specified flags in [`InitializeFlags`].
+    ///
+    /// If another [`Initialize`] is still alive with different process-wide flags, this returns
+    /// [`sys::MAPI_E_INVALID_PARAMETER`] with an [`InitializeConflict`] message, without calling
+    /// [`sys::MAPIInitialize`]. Use [`Initialize::check_flags`] to get the typed error first. This
+    /// only knows about [`Initialize`] objects created by this crate.
     pub fn new(flags: InitializeFlags) -> Result<Arc<Self>> {
-        unsafe {
+        effective::acquire(flags)?;
+        let result = unsafe {
             sys::MAPIInitialize(ptr::from_mut(&mut sys::MAPIINIT {
                 ulVersion: sys::MAPI_INIT_VERSION,
                 ulFlags: flags.into(),
-            }) as *mut _)?;
+            }) as *mut _)
+        };
+        if let Err(err) = result {
+            effective::release();
+            return Err(err);
+        }
+
+        #[cfg(feature = "init-guard")]
+        guard::initialized();
+
+        Ok(Arc::new(Self(None)))
+    }
+
+    /// Call [`sys::MAPIInitialize`] in a separate MAPI DLL, e.g. to talk to two different MAPI
+    /// providers in one process. See [`MapiModule`] for how the exported functions are
+    /// resolved.
+    ///
+    /// Wrap every call which goes through this session in [`Initialize::enter`], including
+    /// dropping any buffers it returns. This does not take part in [`Initialize::effective_flags`]
+    /// or [`Initialize::acquire`], since the flags only apply to the process-wide DLL.
+    pub fn new_isolated(module: Arc<MapiModule>, flags: InitializeFlags) -> Result<Arc<Self>> {
+        {
+            let _scope = module.enter();
+            unsafe {
+                sys::MAPIInitialize(ptr::from_mut(&mut sys::MAPIINIT {
+                    ulVersion: sys::MAPI_INIT_VERSION,
+                    ulFlags: flags.into(),
+                }) as *mut _)?;
+            }
+        }
+
+        #[cfg(feature = "init-guard")]
+        guard::initialized();
+
+        Ok(Arc::new(Self(Some(module))))
+    }
+
+    /// Get the [`MapiModule`] passed to [`Initialize::new_isolated`], or [`None`] if this
+    /// uses the process-wide MAPI DLL.
+    pub fn module(&self) -> Option<&Arc<MapiModule>> {
+        self.0.as_ref()
+    }
+
+    /// Resolve exported MAPI functions from the [`Initialize::module`] on this thread until the
+    /// scope is dropped. This returns [`None`] if there is no separate module, in which case
+    /// nothing needs to be done.
+    pub fn enter(&self) -> Option<MapiModuleScope<'_>> {
+        self.0.as_ref().map(|module| module.enter())
+    }
+
+    /// Share a single [`Initialize`] with every other caller on the same thread which passes the
+    /// same `flags`, and only call [`sys::MAPIInitialize`] if there is not one alive already. Once
+    /// every holder has dropped its [`Arc`], [`sys::MAPIUninitialize`] is called once, so the
+    /// order in which they are dropped does not matter.
+    ///
+    /// [`sys::MAPIInitialize`] and the COM initialization it does are per-thread, so each thread
+    /// gets its own [`Initialize`]. Keep the [`Arc`] on the thread which acquired it, so the last
+    /// one is dropped, and [`sys::MAPIUninitialize`] is called, on the same thread.
+    ///
+    /// This fails like [`Initialize::new`] if the process-wide flags conflict with the
+    /// [`Initialize::effective_flags`]. Flags which only differ in
+    /// [`InitializeFlags::no_coinit`] get separate [`Initialize`] objects, since that one only
+    /// affects the calling thread.
+    pub fn acquire(flags: InitializeFlags) -> Result<Arc<Self>> {
+        thread_local! {
+            static SHARED: RefCell<shared::Cache<Initialize>> =
+                const { RefCell::new(shared::Cache::new()) };
+        }
+
+        if let Some(initialized) = SHARED.with(|shared| shared.borrow().get(flags)) {
+            return Ok(initialized);
         }
+        let initialized = Self::new(flags)?;
+        SHARED.with(|shared| shared.borrow_mut().insert(flags, &initialized));
+        Ok(initialized)
+    }
+
+    /// Get the [`InitializeFlags`] from the first [`Initialize`] which is still alive, or [`None`]
+    /// if MAPI is not initialized.
+    pub fn effective_flags() -> Option<InitializeFlags> {
+        effective::flags()
+    }
 
-        Ok(Arc::new(Self()))
+    /// Check if [`Initialize::new`] would succeed with these flags, or if they conflict with the
+    /// [`Initialize::effective_flags`].
+    pub fn check_flags(flags: InitializeFlags) -> core::result::Result<(), InitializeConflict> {
+        effective::check(flags)
     }
 }
 
 impl Drop for Initialize {
     /// Call [`sys::MAPIUninitialize`].
     fn drop(&mut self) {
+        #[cfg(feature = "init-guard")]
+        guard::uninitialized();
+
+        let _scope = match &self.0 {
+            Some(module) => Some(module.enter()),
+            None => {
+                effective::release();
+                None
+            }
+        };
+
         unsafe {
             sys::MAPIUninitialize();
         }
     }
 }
+
+/// Snapshot of the global initialization epoch, which is captured by the safe wrappers when they
+/// are created.
+///
+/// With the `init-guard` feature, the epoch is incremented every time the first [`Initialize`] is
+/// created after all of the previous ones were dropped. Calling into MAPI through a wrapper that
+/// was created in an earlier epoch, or when there is no [`Initialize`] at all, usually crashes
+/// somewhere inside of a provider DLL. [`InitEpoch::check`] turns that into a panic in debug
+/// builds, or [`sys::MAPI_E_NOT_INITIALIZED`] in release builds.
+///
+/// Without the `init-guard` feature, this is an empty type and [`InitEpoch::check`] always
+/// succeeds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InitEpoch {
+    #[cfg(feature = "init-guard")]
+    epoch: usize,
+}
+
+impl InitEpoch {
+    /// Capture the current epoch.
+    pub fn current() -> Self {
+        Self {
+            #[cfg(feature = "init-guard")]
+            epoch: guard::current(),
+        }
+    }
+
+    /// Make sure MAPI is still initialized in the same epoch that was captured in
+    /// [`InitEpoch::current`].
+    #[inline]
+    pub fn check(&self) -> Result<()> {
+        #[cfg(feature = "init-guard")]
+        {
+            let valid = guard::is_valid(self.epoch);
+            debug_assert!(
+                valid,
+                "MAPI was uninitialized before this object was released"
+            );
+            if !valid {
+                return Err(Error::from_hresult(sys::MAPI_E_NOT_INITIALIZED));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Track the first-wins [`InitializeFlags`] for the [`Initialize`] objects which are alive.
+mod effective {
+    use super::*;
+
+    /// Number of [`Initialize`] instances which have not been dropped, and the flags from the
+    /// first one.
+    static STATE: Mutex<(usize, Option<InitializeFlags>)> = Mutex::new((0, None));
+
+    fn lock() -> std::sync::MutexGuard<'static, (usize, Option<InitializeFlags>)> {
+        STATE
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn acquire(flags: InitializeFlags) -> core::result::Result<(), InitializeConflict> {
+        let mut state = lock();
+        let (count, effective) = &mut *state;
+        match effective {
+            Some(effective) if !effective.is_compatible(&flags) => {
+                return Err(InitializeConflict {
+                    effective: *effective,
+                    requested: flags,
+                });
+            }
+            Some(_) => {}
+            None => *effective = Some(flags),
+        }
+        *count += 1;
+        Ok(())
+    }
+
+    pub fn release() {
+        let mut state = lock();
+        let (count, effective) = &mut *state;
+        *count = count.saturating_sub(1);
+        if *count == 0 {
+            *effective = None;
+        }
+    }
+
+    pub fn flags() -> Option<InitializeFlags> {
+        lock().1
+    }
+
+    pub fn check(flags: InitializeFlags) -> core::result::Result<(), InitializeConflict> {
+        match lock().1 {
+            Some(effective) if !effective.is_compatible(&flags) => Err(InitializeConflict {
+                effective,
+                requested: flags,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Keep track of the [`Initialize`] objects shared by [`Initialize::acquire`] without keeping
+/// them alive.
+mod shared {
+    use super::*;
+
+    pub struct Cache<T> {
+        entries: Vec<(InitializeFlags, Weak<T>)>,
+    }
+
+    impl<T> Cache<T> {
+        pub const fn new() -> Self {
+            Self {
+                entries: Vec::new(),
+            }
+        }
+
+        /// Get the shared value for `flags` if it is still alive.
+        pub fn get(&self, flags: InitializeFlags) -> Option<Arc<T>> {
+            self.entries
+                .iter()
+                .find(|(entry, _)| *entry == flags)
+                .and_then(|(_, value)| value.upgrade())
+        }
+
+        /// Share `value` for `flags`, and forget any entries which have been dropped.
+        pub fn insert(&mut self, flags: InitializeFlags, value: &Arc<T>) {
+            self.entries
+                .retain(|(entry, value)| *entry != flags && value.strong_count() > 0);
+            self.entries.push((flags, Arc::downgrade(value)));
+        }
+    }
+}
+
+#[cfg(feature = "init-guard")]
+mod guard {
+    use super::*;
+
+    /// Number of [`Initialize`] instances which have not been dropped, and the current epoch.
+    static STATE: Mutex<(usize, usize)> = Mutex::new((0, 0));
+
+    /// Cached copy of the current epoch, or 0 if MAPI is not initialized. Epochs start at 1.
+    static ACTIVE_EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+    pub fn initialized() {
+        let mut state = STATE.lock().expect("init-guard poisoned");
+        let (count, epoch) = &mut *state;
+        if *count == 0 {
+            *epoch += 1;
+            ACTIVE_EPOCH.store(*epoch, Ordering::Release);
+        }
+        *count += 1;
+    }
+
+    pub fn uninitialized() {
+        let mut state = STATE.lock().expect("init-guard poisoned");
+        let (count, _) = &mut *state;
+        *count -= 1;
+        if *count == 0 {
+            ACTIVE_EPOCH.store(0, Ordering::Release);
+        }
+    }
+
+    pub fn current() -> usize {
+        ACTIVE_EPOCH.load(Ordering::Acquire)
+    }
+
+    pub fn is_valid(epoch: usize) -> bool {
+        epoch != 0 && epoch == current()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_flags_win() {
+        let multithread = InitializeFlags {
+            multithread_notifications: true,
+            ..Default::default()
+        };
+        let no_coinit = InitializeFlags {
+            multithread_notifications: true,
+            no_coinit: true,
+            ..Default::default()
+        };
+
+        assert_eq!(effective::flags(), None);
+        assert!(effective::acquire(multithread).is_ok());
+        assert_eq!(effective::flags(), Some(multithread));
+
+        // MAPI_NO_COINIT only affects the calling thread.
+        assert!(effective::check(no_coinit).is_ok());
+        assert!(effective::acquire(no_coinit).is_ok());
+        assert_eq!(effective::flags(), Some(multithread));
+
+        let conflict = InitializeConflict {
+            effective: multithread,
+            requested: Default::default(),
+        };
+        assert_eq!(effective::check(Default::default()), Err(conflict));
+        assert_eq!(effective::acquire(Default::default()), Err(conflict));
+        assert_eq!(Error::from(conflict).code(), sys::MAPI_E_INVALID_PARAMETER);
+
+        effective::release();
+        assert_eq!(effective::flags(), Some(multithread));
+        effective::release();
+        assert_eq!(effective::flags(), None);
+
+        assert!(effective::acquire(Default::default()).is_ok());
+        assert_eq!(effective::flags(), Some(Default::default()));
+        effective::release();
+    }
+
+    #[test]
+    fn shared_cache() {
+        let no_coinit = InitializeFlags {
+            no_coinit: true,
+            ..Default::default()
+        };
+        let mut cache = shared::Cache::new();
+        assert!(cache.get(Default::default()).is_none());
+
+        let first = Arc::new(1);
+        cache.insert(Default::default(), &first);
+        assert!(Arc::ptr_eq(
+            &cache.get(Default::default()).expect("missing entry"),
+            &first
+        ));
+        assert!(cache.get(no_coinit).is_none());
+
+        let second = Arc::new(2);
+        cache.insert(no_coinit, &second);
+        drop(first);
+        assert!(cache.get(Default::default()).is_none());
+        assert_eq!(cache.get(no_coinit).as_deref(), Some(&2));
+
+        let third = Arc::new(3);
+        cache.insert(Default::default(), &third);
+        assert_eq!(cache.get(Default::default()).as_deref(), Some(&3));
+    }
+
+    #[cfg(not(feature = "init-guard"))]
+    #[test]
+    fn check_without_guard() {
+        assert!(InitEpoch::current().check().is_ok());
+    }
+
+    #[cfg(feature = "init-guard")]
+    #[test]
+    fn epoch_changes_after_uninitialize() {
+        guard::initialized();
+        let first = InitEpoch::current();
+        assert!(first.check().is_ok());
+
+        guard::initialized();
+        guard::uninitialized();
+        assert!(guard::is_valid(first.epoch));
+
+        guard::uninitialized();
+        assert!(!guard::is_valid(first.epoch));
+
+        guard::initialized();
+        let second = InitEpoch::current();
+        assert!(!guard::is_valid(first.epoch));
+        assert!(second.check().is_ok());
+        guard::uninitialized();
+    }
+}
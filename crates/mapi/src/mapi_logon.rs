@@ -3,8 +3,13 @@
 
 //! Define [`Logon`] and [`LogonFlags`].
 
-use crate::{sys, Initialize};
-use std::{iter, ptr, sync::Arc};
+use crate::{
+    mapi_error::with_last_error, open_policy::probe_object, sys, to_pcstr_buffer, to_pwstr_buffer,
+    AddrBook, AdviseConnection, EntryId, EventMask, FlushDirection, InitEpoch, Initialize,
+    MarshalToThread, Marshaled, MsgStore, Notification, NotificationSink, OpenPolicy, StatusInfo,
+    StatusObject, StoreInfo, Table, FLUSH_POLL_INTERVAL, FLUSH_START_GRACE,
+};
+use std::{ptr, sync::Arc, time::Duration};
 use windows::Win32::Foundation::*;
 use windows_core::*;
 
@@ -44,7 +49,8 @@ pub struct LogonFlags {
     /// Pass [`sys::MAPI_TIMEOUT_SHORT`].
     pub timeout_short: bool,
 
-    /// Pass [`sys::MAPI_UNICODE`].
+    /// Pass [`sys::MAPI_UNICODE`]. [`Logon::new`] encodes the profile name and password as UTF-16
+    /// if this is set, or in the system ANSI code page if it is not.
     pub unicode: bool,
 
     /// Pass [`sys::MAPI_USE_DEFAULT`].
@@ -127,6 +133,36 @@ impl From<LogonFlags> for u32 {
     }
 }
 
+/// Profile name or password for [`sys::MAPILogonEx`], which is a `PSTR` or a `PWSTR` depending on
+/// [`LogonFlags::unicode`].
+enum LogonString {
+    Ansi(Vec<u8>),
+    Unicode(Vec<u16>),
+}
+
+impl LogonString {
+    fn new(value: Option<&str>, unicode: bool) -> Result<Option<Self>> {
+        value
+            .map(|value| {
+                Ok(if unicode {
+                    Self::Unicode(to_pwstr_buffer(value))
+                } else {
+                    Self::Ansi(to_pcstr_buffer(value)?)
+                })
+            })
+            .transpose()
+    }
+
+    /// Get the pointer to pass to [`sys::MAPILogonEx`], or `null` if there is no value.
+    fn as_mut_ptr(value: &mut Option<Self>) -> *mut i8 {
+        match value {
+            Some(Self::Ansi(value)) => value.as_mut_ptr() as *mut _,
+            Some(Self::Unicode(value)) => value.as_mut_ptr() as *mut _,
+            None => ptr::null_mut(),
+        }
+    }
+}
+
 /// Call [`sys::MAPILogonEx`] and hold on to the [`sys::IMAPISession`].
 ///
 /// This helper also holds onto an `Arc<Initialize>`, which ensures that there are balanced calls
@@ -136,6 +172,7 @@ pub struct Logon {
     /// Access the [`sys::IMAPISession`].
     pub session: sys::IMAPISession,
 
+    epoch: InitEpoch,
     _initialized: Arc<Initialize>,
 }
 
@@ -147,20 +184,13 @@ impl Logon {
         password: Option<&str>,
         flags: LogonFlags,
     ) -> Result<Self> {
-        let mut profile_name: Option<Vec<_>> =
-            profile_name.map(|value| value.bytes().chain(iter::once(0)).collect());
-        let profile_name = profile_name
-            .as_mut()
-            .map(|value| value.as_mut_ptr())
-            .unwrap_or(ptr::null_mut());
-        let mut password: Option<Vec<_>> =
-            password.map(|value| value.bytes().chain(iter::once(0)).collect());
-        let password = password
-            .as_mut()
-            .map(|value| value.as_mut_ptr())
-            .unwrap_or(ptr::null_mut());
+        let mut profile_name = LogonString::new(profile_name, flags.unicode)?;
+        let mut password = LogonString::new(password, flags.unicode)?;
+        let profile_name = LogonString::as_mut_ptr(&mut profile_name);
+        let password = LogonString::as_mut_ptr(&mut password);
 
         Ok(Self {
+            epoch: InitEpoch::current(),
             _initialized: initialized,
             session: unsafe {
                 let mut session = None;
@@ -176,4 +206,250 @@ impl Logon {
             .ok_or_else(|| Error::from(E_FAIL))?,
         })
     }
+
+    /// Make sure MAPI is still initialized in the same epoch as the session.
+    pub(crate) fn check(&self) -> Result<()> {
+        self.epoch.check()
+    }
+
+    /// Call [`sys::IMAPISession::OpenAddressBook`] with [`sys::AB_NO_DIALOG`].
+    pub fn open_addr_book(&self) -> Result<AddrBook> {
+        let mut addr_book = None;
+        unsafe {
+            self.session
+                .OpenAddressBook(0, ptr::null_mut(), sys::AB_NO_DIALOG, &mut addr_book)
+                .map_err(|error| with_last_error(&self.session, error))?;
+        }
+        let addr_book = addr_book.ok_or_else(|| Error::from(E_POINTER))?;
+        Ok(AddrBook::new(addr_book))
+    }
+
+    /// Call [`sys::IMAPISession::GetMsgStoresTable`] and read every row with
+    /// [`Table::query_all_rows`], returning a [`StoreInfo`] for each message store in the profile.
+    /// Rows without a [`sys::PR_ENTRYID`] are skipped.
+    pub fn message_stores(&self) -> Result<impl Iterator<Item = StoreInfo>> {
+        let table = Table::new(unsafe {
+            self.session
+                .GetMsgStoresTable(0)
+                .map_err(|error| with_last_error(&self.session, error))?
+        });
+        let rows = table.query_all_rows(&StoreInfo::COLUMNS, None, None)?;
+        let stores: Vec<_> = rows
+            .into_iter()
+            .filter_map(|row| StoreInfo::from_row(&row))
+            .collect();
+        Ok(stores.into_iter())
+    }
+
+    /// Call [`sys::IMAPISession::OpenMsgStore`] with [`sys::MAPI_BEST_ACCESS`],
+    /// [`sys::MAPI_DEFERRED_ERRORS`], [`sys::MDB_NO_DIALOG`], and [`sys::MDB_NO_MAIL`] to open a
+    /// message store, e.g. with the [`StoreInfo::entry_id`] from [`Logon::message_stores`].
+    ///
+    /// This is the same as [`Logon::open_msg_store_with_policy`] with
+    /// [`OpenPolicy::DeferredUnchecked`].
+    pub fn open_msg_store(&self, entry_id: &EntryId) -> Result<MsgStore> {
+        self.open_msg_store_with_policy(entry_id, OpenPolicy::DeferredUnchecked)
+    }
+
+    /// Same as [`Logon::open_msg_store`], but choose how to handle
+    /// [`sys::MAPI_DEFERRED_ERRORS`] with an [`OpenPolicy`].
+    pub fn open_msg_store_with_policy(
+        &self,
+        entry_id: &EntryId,
+        policy: OpenPolicy,
+    ) -> Result<MsgStore> {
+        let store: sys::IMsgStore = policy.open(
+            |flags| {
+                let mut store = None;
+                unsafe {
+                    self.session
+                        .OpenMsgStore(
+                            0,
+                            u32::try_from(entry_id.len())?,
+                            entry_id.as_ptr() as *mut _,
+                            ptr::null_mut(),
+                            sys::MAPI_BEST_ACCESS | sys::MDB_NO_DIALOG | sys::MDB_NO_MAIL | flags,
+                            &mut store,
+                        )
+                        .map_err(|error| with_last_error(&self.session, error))?;
+                }
+                store.ok_or_else(|| Error::from(E_POINTER))
+            },
+            |store| probe_object(store),
+        )?;
+        Ok(MsgStore::with_entry_id(store, entry_id))
+    }
+
+    /// Call [`sys::IMAPISession::GetStatusTable`] to get the session status table, which has a
+    /// row for the MAPI subsystem, the spooler, and each provider. Read the rows with the
+    /// [`StatusInfo::COLUMNS`] to parse them with [`StatusInfo::from_row`].
+    pub fn status_table(&self) -> Result<Table> {
+        self.check()?;
+        Ok(Table::new(unsafe {
+            self.session
+                .GetStatusTable(0)
+                .map_err(|error| with_last_error(&self.session, error))?
+        }))
+    }
+
+    /// Read every row in [`Logon::status_table`] and call [`sys::IMAPISession::OpenEntry`] to open
+    /// the [`sys::IMAPIStatus`] for each one. Rows without a [`sys::PR_ENTRYID`] are skipped, and so
+    /// are providers whose status object cannot be opened, e.g. because they are not loaded.
+    pub fn status_objects(&self) -> Result<Vec<StatusObject>> {
+        Ok(self
+            .status_rows()?
+            .into_iter()
+            .filter_map(|info| self.open_status(info).ok())
+            .collect())
+    }
+
+    /// Ask the spooler to flush the outbound and/or inbound queues of every transport with
+    /// [`sys::IMAPIStatus::FlushQueues`], and then poll the [`sys::PR_STATUS_CODE`] of the
+    /// spooler every [`FLUSH_POLL_INTERVAL`] until it is no longer busy in that `direction`. The
+    /// spooler must report that it is busy within [`FLUSH_START_GRACE`], or there is nothing to
+    /// wait for.
+    ///
+    /// Fails with [`sys::MAPI_E_NOT_FOUND`] if the profile does not have a spooler, or with
+    /// [`sys::MAPI_E_TIMEOUT`] if the spooler is still busy after `timeout`.
+    pub fn flush_queues(&self, direction: FlushDirection, timeout: Duration) -> Result<()> {
+        let spooler = self
+            .status_rows()?
+            .into_iter()
+            .find(|info| info.resource_type == sys::MAPI_SPOOLER)
+            .ok_or_else(|| Error::from(sys::MAPI_E_NOT_FOUND))?;
+        let spooler = self.open_status(spooler)?;
+        spooler.flush_queues(HWND::default(), None, direction.flags())?;
+
+        crate::status::wait_for_idle(timeout, FLUSH_POLL_INTERVAL, FLUSH_START_GRACE, || {
+            Ok(self
+                .status_rows()?
+                .into_iter()
+                .find(|info| info.resource_type == sys::MAPI_SPOOLER)
+                .is_some_and(|info| direction.is_busy(info.status_code)))
+        })
+    }
+
+    /// Read the [`StatusInfo`] for every row in [`Logon::status_table`].
+    fn status_rows(&self) -> Result<Vec<StatusInfo>> {
+        let rows = self
+            .status_table()?
+            .query_all_rows(&StatusInfo::COLUMNS, None, None)?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| StatusInfo::from_row(&row))
+            .collect())
+    }
+
+    /// Call [`sys::IMAPISession::OpenEntry`] to open the [`sys::IMAPIStatus`] for a row in
+    /// [`Logon::status_table`].
+    fn open_status(&self, info: StatusInfo) -> Result<StatusObject> {
+        let mut obj_type = 0;
+        let mut unknown = None;
+        unsafe {
+            self.session
+                .OpenEntry(
+                    u32::try_from(info.entry_id.len())?,
+                    info.entry_id.as_ptr() as *mut _,
+                    &sys::IMAPIStatus::IID as *const _ as *mut _,
+                    sys::MAPI_BEST_ACCESS,
+                    &mut obj_type,
+                    &mut unknown,
+                )
+                .map_err(|error| with_last_error(&self.session, error))?;
+        }
+        let status = unknown.ok_or_else(|| Error::from(E_POINTER))?.cast()?;
+        Ok(StatusObject::with_info(status, info))
+    }
+
+    /// Call [`sys::IMAPISession::Advise`] to register for [`sys::fnevCriticalError`] and
+    /// [`sys::fnevExtended`] notifications on the session, which MAPI sends when a provider loses
+    /// its connection or Outlook shuts down the session, e.g. with [`sys::MAPI_E_END_OF_SESSION`]
+    /// or [`sys::MAPI_E_NETWORK_ERROR`]. Outlook reports a store shutdown with an extended event
+    /// instead of an error, so those are passed to `callback` as [`sys::MAPI_E_END_OF_SESSION`].
+    ///
+    /// The `callback` receives the error, possibly on another thread, until the
+    /// [`AdviseConnection`] is dropped. Once it has been called, the session should be released
+    /// and a long-running service should logon again. Notifications which arrive after MAPI has
+    /// been uninitialized and initialized again belong to the old session, so they are ignored.
+    pub fn on_session_lost<F>(&self, callback: F) -> Result<AdviseConnection>
+    where
+        F: Fn(HRESULT) + Send + Sync + 'static,
+    {
+        let epoch = self.epoch;
+        epoch.check()?;
+        let sink = NotificationSink::create(move |notification| {
+            if epoch.check().is_err() {
+                return;
+            }
+            match notification {
+                Notification::CriticalError { error, .. } => callback(error),
+                Notification::Extended { .. } => callback(sys::MAPI_E_END_OF_SESSION),
+                _ => {}
+            }
+        });
+        let mut connection = 0;
+        unsafe {
+            self.session
+                .Advise(
+                    0,
+                    ptr::null_mut(),
+                    EventMask {
+                        critical_error: true,
+                        extended: true,
+                        ..Default::default()
+                    }
+                    .into(),
+                    &sink,
+                    &mut connection,
+                )
+                .map_err(|error| with_last_error(&self.session, error))?;
+        }
+        Ok(AdviseConnection::session(
+            self.session.clone(),
+            connection,
+            epoch,
+        ))
+    }
+}
+
+/// The [`Initialize`] is not moved to the other thread, since the last one must be dropped on the
+/// thread which called [`sys::MAPIInitialize`]. Pass the other thread's own `Arc<Initialize>`,
+/// e.g. from [`Initialize::acquire`], to [`Marshaled::unmarshal_with`] instead.
+impl MarshalToThread for Logon {
+    type State = ();
+    type Context = Arc<Initialize>;
+
+    fn marshal_to_thread(&self) -> Result<Marshaled<Self>> {
+        Marshaled::new(&self.session, ())
+    }
+
+    fn unmarshal(marshaled: Marshaled<Self>, initialized: Arc<Initialize>) -> Result<Self> {
+        let (session, ()) = marshaled.into_parts()?;
+        Ok(Self {
+            session,
+            epoch: InitEpoch::current(),
+            _initialized: initialized,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unicode_profile_name() {
+        let mut profile_name = LogonString::new(Some("Zoë's Profile"), true).expect("new failed");
+        let value = LogonString::as_mut_ptr(&mut profile_name);
+        assert_eq!(
+            unsafe { PCWSTR(value as *const u16).to_string() }.expect("invalid string"),
+            "Zoë's Profile"
+        );
+    }
+
+    #[test]
+    fn missing_profile_name() {
+        let mut profile_name = LogonString::new(None, true).expect("new failed");
+        assert!(LogonString::as_mut_ptr(&mut profile_name).is_null());
+    }
 }
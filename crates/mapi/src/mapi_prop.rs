@@ -0,0 +1,832 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`MAPIProp`], [`SaveChangesFlags`], [`CopyPropsFlags`], [`CheckedProps`],
+//! [`AllProps`], [`PropError`], and [`PropProblem`].
+
+use crate::{
+    mapi_error::with_last_error, prop_tag::prop_tag_array, sys, InitEpoch, MAPIOutParam,
+    ObjectKind, ObjectRegistration, OpenPropertyFlags, PropTag, PropType, PropValue, PropValueData,
+    PropertyReader, PropertyStream, ReadLimits, Row,
+};
+use core::{fmt, ptr};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use windows::Win32::{Foundation::*, System::Com::IStream};
+use windows_core::*;
+
+/// Set of flags that can be passed to [`sys::IMAPIProp::SaveChanges`].
+#[derive(Default)]
+pub struct SaveChangesFlags {
+    /// Pass [`sys::FORCE_SAVE`].
+    pub force_save: bool,
+
+    /// Pass [`sys::KEEP_OPEN_READONLY`].
+    pub keep_open_read_only: bool,
+
+    /// Pass [`sys::KEEP_OPEN_READWRITE`].
+    pub keep_open_read_write: bool,
+
+    /// Pass [`sys::MAPI_DEFERRED_ERRORS`].
+    pub deferred_errors: bool,
+}
+
+impl From<SaveChangesFlags> for u32 {
+    fn from(value: SaveChangesFlags) -> Self {
+        let force_save = if value.force_save { sys::FORCE_SAVE } else { 0 };
+        let keep_open_read_only = if value.keep_open_read_only {
+            sys::KEEP_OPEN_READONLY
+        } else {
+            0
+        };
+        let keep_open_read_write = if value.keep_open_read_write {
+            sys::KEEP_OPEN_READWRITE
+        } else {
+            0
+        };
+        let deferred_errors = if value.deferred_errors {
+            sys::MAPI_DEFERRED_ERRORS
+        } else {
+            0
+        };
+
+        force_save | keep_open_read_only | keep_open_read_write | deferred_errors
+    }
+}
+
+/// Set of flags that can be passed to [`MAPIProp::copy_to`] or [`MAPIProp::copy_props`].
+#[derive(Default)]
+pub struct CopyPropsFlags {
+    /// Pass [`sys::MAPI_MOVE`] to delete the properties from the source after they are copied.
+    pub move_props: bool,
+
+    /// Pass [`sys::MAPI_NOREPLACE`] to keep properties which are already set on the destination.
+    pub no_replace: bool,
+
+    /// Pass [`sys::MAPI_DECLINE_OK`].
+    pub decline_ok: bool,
+}
+
+impl From<CopyPropsFlags> for u32 {
+    fn from(value: CopyPropsFlags) -> Self {
+        let move_props = if value.move_props { sys::MAPI_MOVE } else { 0 };
+        let no_replace = if value.no_replace {
+            sys::MAPI_NOREPLACE
+        } else {
+            0
+        };
+        let decline_ok = if value.decline_ok {
+            sys::MAPI_DECLINE_OK
+        } else {
+            0
+        };
+
+        move_props | no_replace | decline_ok
+    }
+}
+
+/// Common property operations for any of the safe wrappers around an interface which inherits from
+/// [`sys::IMAPIProp`].
+pub trait MAPIProp {
+    /// Access the [`sys::IMAPIProp`] interface.
+    fn mapi_prop(&self) -> &sys::IMAPIProp;
+
+    /// Get the [`InitEpoch`] captured when the wrapper was created.
+    fn init_epoch(&self) -> InitEpoch;
+
+    /// Get the IID of the interface the wrapper holds, which [`MAPIProp::copy_to`] and
+    /// [`MAPIProp::copy_props`] pass along with the destination, so the provider knows what kind of
+    /// object it is copying to.
+    fn interface_id(&self) -> GUID {
+        sys::IMAPIProp::IID
+    }
+
+    /// Get the [`ObjectRegistration`] of the wrapper, so objects opened through it are counted
+    /// under the same store in the [`crate::ObjectRegistry`].
+    fn registration(&self) -> Option<&ObjectRegistration> {
+        None
+    }
+
+    /// Call [`sys::IMAPIProp::GetProps`] with [`sys::MAPI_UNICODE`]. The [`Row`] owns the returned
+    /// [`sys::SPropValue`] array, and [`Row::iter`] yields a [`PropValue`] for each of the `tags`.
+    ///
+    /// Properties which could not be retrieved are still included, but the value will be
+    /// [`crate::PropValueData::Error`], e.g. [`sys::MAPI_E_NOT_FOUND`].
+    ///
+    /// The values are checked against the [`ReadLimits::global`] limits.
+    fn get_props(&self, tags: &[PropTag]) -> Result<Row> {
+        self.get_props_with_limits(tags, &ReadLimits::global())
+    }
+
+    /// Same as [`MAPIProp::get_props`], but check the values against `limits` instead of the
+    /// [`ReadLimits::global`] limits. If any of them is too big, this returns
+    /// [`sys::MAPI_E_TOO_BIG`] with a [`crate::LimitExceeded`] message.
+    fn get_props_with_limits(&self, tags: &[PropTag], limits: &ReadLimits) -> Result<Row> {
+        check(self)?;
+        let mut tags = prop_tag_array(tags)?;
+        let mut row = sys::SRow::default();
+        unsafe {
+            self.mapi_prop()
+                .GetProps(
+                    tags.as_mut_ptr() as *mut _,
+                    sys::MAPI_UNICODE,
+                    &mut row.cValues,
+                    &mut row.lpProps,
+                )
+                .map_err(|error| with_last_error(self.mapi_prop(), error))?;
+        }
+        let row = Row::new(&mut row);
+        limits.check_row(&row)?;
+        Ok(row)
+    }
+
+    /// Call [`MAPIProp::get_props`], and sort the values into the ones which were returned and
+    /// the ones which failed with a [`PropError`].
+    ///
+    /// [`sys::IMAPIProp::GetProps`] returns [`sys::MAPI_W_ERRORS_RETURNED`] if any of the
+    /// properties failed, which is easy to mistake for success. Values which are too big to
+    /// return, i.e. [`sys::MAPI_E_NOT_ENOUGH_MEMORY`], are read again with
+    /// [`MAPIProp::open_property_stream`] if they are [`sys::PT_BINARY`], [`sys::PT_STRING8`], or
+    /// [`sys::PT_UNICODE`]. Strings read that way are always returned as [`sys::PT_UNICODE`]. If
+    /// the stream cannot be opened, the value is still [`PropError::TooBig`].
+    fn get_props_checked(&self, tags: &[PropTag]) -> Result<CheckedProps> {
+        let limits = ReadLimits::global();
+        let row = self.get_props_with_limits(tags, &limits)?;
+        let mut streamed = Vec::with_capacity(tags.len());
+        for (tag, value) in tags.iter().zip(row.iter()) {
+            streamed.push(match check_prop(*tag, value) {
+                Err(PropError::TooBig { tag }) => read_streamed(self, tag, &limits)?,
+                _ => None,
+            });
+        }
+        Ok(CheckedProps {
+            tags: tags.to_vec(),
+            row,
+            streamed,
+        })
+    }
+
+    /// Call [`sys::IMAPIProp::GetPropList`] with [`sys::MAPI_UNICODE`] to get the tags of every
+    /// property which is set on the object.
+    fn get_prop_list(&self) -> Result<Vec<PropTag>> {
+        check(self)?;
+        let mut tags: MAPIOutParam<sys::SPropTagArray> = MAPIOutParam::new();
+        unsafe {
+            self.mapi_prop()
+                .GetPropList(sys::MAPI_UNICODE, tags.as_mut_ptr())
+                .map_err(|error| with_last_error(self.mapi_prop(), error))?;
+        }
+        Ok(match unsafe { tags.as_mut() } {
+            Some(tags) => unsafe { read_tags(tags) },
+            None => Vec::new(),
+        })
+    }
+
+    /// Call [`MAPIProp::get_prop_list`], and then [`MAPIProp::get_props`] in batches of
+    /// [`ALL_PROPS_BATCH_SIZE`] tags, to read every property on the object. Like
+    /// [`MAPIProp::get_props`], values which could not be retrieved are returned as
+    /// [`crate::PropValueData::Error`], e.g. [`sys::MAPI_E_NOT_ENOUGH_MEMORY`] for large values.
+    fn all_props(&self) -> Result<AllProps> {
+        let tags = self.get_prop_list()?;
+        let rows = tags
+            .chunks(ALL_PROPS_BATCH_SIZE)
+            .map(|tags| self.get_props(tags))
+            .collect::<Result<_>>()?;
+        Ok(AllProps { rows })
+    }
+
+    /// Call [`sys::IMAPIProp::SetProps`]. If any of the properties could not be set, this will
+    /// return the error for the first one in the [`sys::SPropProblemArray`].
+    fn set_props(&self, values: &[PropValue]) -> Result<()> {
+        check_problems(self.set_props_with_problems(values)?)
+    }
+
+    /// Same as [`MAPIProp::set_props`], but return a [`PropProblem`] for each of the properties
+    /// which could not be set, instead of failing on the first one.
+    fn set_props_with_problems(&self, values: &[PropValue]) -> Result<Vec<PropProblem>> {
+        check(self)?;
+        let mut values: Vec<_> = values.iter().map(sys::SPropValue::from).collect();
+        let mut problems = MAPIOutParam::new();
+        unsafe {
+            self.mapi_prop()
+                .SetProps(
+                    u32::try_from(values.len())?,
+                    values.as_mut_ptr(),
+                    problems.as_mut_ptr(),
+                )
+                .map_err(|error| with_last_error(self.mapi_prop(), error))?;
+        }
+        Ok(collect_problems(problems))
+    }
+
+    /// Call [`sys::IMAPIProp::DeleteProps`]. If any of the properties could not be deleted, this
+    /// will return the error for the first one in the [`sys::SPropProblemArray`].
+    fn delete_props(&self, tags: &[PropTag]) -> Result<()> {
+        check_problems(self.delete_props_with_problems(tags)?)
+    }
+
+    /// Same as [`MAPIProp::delete_props`], but return a [`PropProblem`] for each of the
+    /// properties which could not be deleted, instead of failing on the first one.
+    fn delete_props_with_problems(&self, tags: &[PropTag]) -> Result<Vec<PropProblem>> {
+        check(self)?;
+        let mut tags = prop_tag_array(tags)?;
+        let mut problems = MAPIOutParam::new();
+        unsafe {
+            self.mapi_prop()
+                .DeleteProps(tags.as_mut_ptr() as *mut _, problems.as_mut_ptr())
+                .map_err(|error| with_last_error(self.mapi_prop(), error))?;
+        }
+        Ok(collect_problems(problems))
+    }
+
+    /// Call [`sys::IMAPIProp::CopyTo`] to copy all of the properties of this object to the
+    /// `destination`, except for the `excluded` properties. For a [`crate::Message`], this
+    /// includes the recipients and attachments unless [`sys::PR_MESSAGE_RECIPIENTS`] or
+    /// [`sys::PR_MESSAGE_ATTACHMENTS`] are excluded.
+    ///
+    /// Properties which could not be copied are returned as [`PropProblem`] entries. If the whole
+    /// copy fails, the error includes the [`crate::MapiError`] from [`crate::last_error`]. Call
+    /// [`MAPIProp::save_changes`] on the `destination` to keep the copy.
+    fn copy_to(
+        &self,
+        destination: &dyn MAPIProp,
+        excluded: &[PropTag],
+        flags: CopyPropsFlags,
+    ) -> Result<Vec<PropProblem>> {
+        check(self)?;
+        check(destination)?;
+        let mut excluded = prop_tag_array(excluded)?;
+        let mut interface = destination.interface_id();
+        let mut problems = MAPIOutParam::new();
+        unsafe {
+            self.mapi_prop()
+                .CopyTo(
+                    0,
+                    ptr::null_mut(),
+                    excluded.as_mut_ptr() as *mut _,
+                    0,
+                    None::<&sys::IMAPIProgress>,
+                    &mut interface,
+                    destination.mapi_prop().as_raw(),
+                    flags.into(),
+                    problems.as_mut_ptr(),
+                )
+                .map_err(|error| with_last_error(self.mapi_prop(), error))?;
+        }
+        Ok(collect_problems(problems))
+    }
+
+    /// Call [`sys::IMAPIProp::CopyProps`] to copy only the `included` properties of this object
+    /// to the `destination`. This returns the same [`PropProblem`] entries and errors as
+    /// [`MAPIProp::copy_to`].
+    fn copy_props(
+        &self,
+        destination: &dyn MAPIProp,
+        included: &[PropTag],
+        flags: CopyPropsFlags,
+    ) -> Result<Vec<PropProblem>> {
+        check(self)?;
+        check(destination)?;
+        let mut included = prop_tag_array(included)?;
+        let mut interface = destination.interface_id();
+        let mut problems = MAPIOutParam::new();
+        unsafe {
+            self.mapi_prop()
+                .CopyProps(
+                    included.as_mut_ptr() as *mut _,
+                    0,
+                    None::<&sys::IMAPIProgress>,
+                    &mut interface,
+                    destination.mapi_prop().as_raw(),
+                    flags.into(),
+                    problems.as_mut_ptr(),
+                )
+                .map_err(|error| with_last_error(self.mapi_prop(), error))?;
+        }
+        Ok(collect_problems(problems))
+    }
+
+    /// Call [`sys::IMAPIProp::OpenProperty`] to open a [`sys::PT_BINARY`], [`sys::PT_STRING8`],
+    /// or [`sys::PT_UNICODE`] property as an [`IStream`] wrapped in a [`PropertyStream`]. This
+    /// works for values which are too large to return from [`MAPIProp::get_props`], e.g.
+    /// [`sys::PR_ATTACH_DATA_BIN`] or [`sys::PR_HTML`].
+    fn open_property_stream(
+        &self,
+        tag: PropTag,
+        flags: OpenPropertyFlags,
+    ) -> Result<PropertyStream> {
+        check(self)?;
+        let mut unknown = None;
+        unsafe {
+            self.mapi_prop()
+                .OpenProperty(
+                    tag.0,
+                    &IStream::IID as *const _ as *mut _,
+                    0,
+                    flags.into(),
+                    &mut unknown,
+                )
+                .map_err(|error| with_last_error(self.mapi_prop(), error))?;
+        }
+        let unknown = unknown.ok_or_else(|| Error::from(E_POINTER))?;
+        let stream = unknown.cast()?;
+        Ok(match self.registration() {
+            Some(registration) => PropertyStream::with_registration(
+                stream,
+                registration.child(ObjectKind::PropertyStream),
+            ),
+            None => PropertyStream::new(stream),
+        })
+    }
+
+    /// Read the whole value of a [`sys::PT_BINARY`], [`sys::PT_STRING8`], or [`sys::PT_UNICODE`]
+    /// property with [`MAPIProp::stream_property`], e.g. a [`sys::PR_BODY_W`] or [`sys::PR_HTML`]
+    /// which is too big for [`MAPIProp::get_props`]. Strings are returned as the raw bytes in the
+    /// stream, without a terminating NUL. The size is checked against [`ReadLimits::global`]
+    /// before reading it.
+    fn read_stream_property(&self, tag: PropTag) -> Result<Vec<u8>> {
+        let mut reader = self.stream_property(tag, PropertyReader::DEFAULT_CHUNK_SIZE)?;
+        read_checked(&mut reader, tag, &ReadLimits::global())
+    }
+
+    /// Open a property with [`MAPIProp::open_property_stream`] and wrap it in a
+    /// [`PropertyReader`], which reads at most `chunk_size` bytes with each call to
+    /// [`IStream::Read`]. Use this to process a large body without holding all of it in memory.
+    fn stream_property(&self, tag: PropTag, chunk_size: usize) -> Result<PropertyReader> {
+        let stream = self.open_property_stream(tag, Default::default())?;
+        Ok(PropertyReader::new(stream, chunk_size))
+    }
+
+    /// Replace the value of a [`sys::PT_BINARY`], [`sys::PT_STRING8`], or [`sys::PT_UNICODE`]
+    /// property with everything read from `value`, e.g. a large [`sys::PR_HTML`] body or
+    /// [`sys::PR_ATTACH_DATA_BIN`]. The property is opened with [`sys::MAPI_CREATE`] and
+    /// [`sys::MAPI_MODIFY`] and written [`PropertyReader::DEFAULT_CHUNK_SIZE`] bytes at a time,
+    /// so the value never needs to be in one contiguous buffer. The stream is committed and then
+    /// [`MAPIProp::save_changes`] is called. Returns the number of bytes written.
+    fn write_stream_property(&self, tag: PropTag, value: &mut dyn Read) -> Result<u64> {
+        let mut stream = self.open_property_stream(
+            tag,
+            OpenPropertyFlags {
+                create: true,
+                modify: true,
+                ..Default::default()
+            },
+        )?;
+        let written = copy_chunked(value, &mut stream, PropertyReader::DEFAULT_CHUNK_SIZE)?;
+        stream.flush()?;
+        drop(stream);
+        self.save_changes(Default::default())?;
+        Ok(written)
+    }
+
+    /// Call [`sys::IMAPIProp::SaveChanges`] to commit any pending changes.
+    ///
+    /// The methods on this trait all add the [`crate::MapiError`] from [`crate::last_error`] to
+    /// the [`Error`] if the call fails.
+    fn save_changes(&self, flags: SaveChangesFlags) -> Result<()> {
+        check(self)?;
+        unsafe {
+            self.mapi_prop()
+                .SaveChanges(flags.into())
+                .map_err(|error| with_last_error(self.mapi_prop(), error))
+        }
+    }
+}
+
+/// Make sure MAPI is still initialized and the store the object belongs to is still connected.
+fn check<P: MAPIProp + ?Sized>(prop: &P) -> Result<()> {
+    prop.init_epoch().check()?;
+    if let Some(registration) = prop.registration() {
+        registration.check_connected()?;
+    }
+    Ok(())
+}
+
+/// Number of tags [`MAPIProp::all_props`] passes to each [`sys::IMAPIProp::GetProps`] call, so a
+/// single call does not need to return every property at once.
+pub const ALL_PROPS_BATCH_SIZE: usize = 64;
+
+/// Values returned from [`MAPIProp::all_props`].
+pub struct AllProps {
+    rows: Vec<Row>,
+}
+
+impl AllProps {
+    /// Test for an object without any properties.
+    pub fn is_empty(&self) -> bool {
+        self.rows.iter().all(Row::is_empty)
+    }
+
+    /// Get the number of properties.
+    pub fn len(&self) -> usize {
+        self.rows.iter().map(Row::len).sum()
+    }
+
+    /// Iterate over the [`PropValue`] of every property, in the order returned by
+    /// [`sys::IMAPIProp::GetPropList`].
+    pub fn iter(&self) -> impl Iterator<Item = PropValue<'_>> {
+        self.rows.iter().flat_map(Row::iter)
+    }
+}
+
+/// Values returned from [`MAPIProp::get_props_checked`].
+pub struct CheckedProps {
+    tags: Vec<PropTag>,
+    row: Row,
+    streamed: Vec<Option<StreamedValue>>,
+}
+
+impl CheckedProps {
+    /// Get a [`PropValue`] or a [`PropError`] for each of the tags which were requested, in the
+    /// same order.
+    pub fn results(&self) -> Vec<core::result::Result<PropValue<'_>, PropError>> {
+        self.tags
+            .iter()
+            .zip(self.row.iter())
+            .zip(self.streamed.iter())
+            .map(|((tag, value), streamed)| match streamed {
+                Some(StreamedValue::Binary(value)) => Ok(PropValue {
+                    tag: *tag,
+                    value: PropValueData::Binary(value),
+                }),
+                Some(StreamedValue::Unicode(value)) => Ok(PropValue {
+                    tag: tag.change_prop_type(PropType::new(sys::PT_UNICODE as u16)),
+                    value: PropValueData::Unicode(value.clone()),
+                }),
+                None => check_prop(*tag, value),
+            })
+            .collect()
+    }
+}
+
+/// Value which was too big for [`sys::IMAPIProp::GetProps`], read from a [`PropertyStream`].
+enum StreamedValue {
+    Binary(Vec<u8>),
+    Unicode(Vec<u16>),
+}
+
+/// Error for a single property returned from [`CheckedProps::results`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PropError {
+    /// The property is not set, i.e. [`sys::MAPI_E_NOT_FOUND`].
+    NotFound {
+        /// Property which was requested.
+        tag: PropTag,
+    },
+
+    /// The value is too big to return from [`sys::IMAPIProp::GetProps`], i.e.
+    /// [`sys::MAPI_E_NOT_ENOUGH_MEMORY`], and it could not be read with
+    /// [`MAPIProp::open_property_stream`].
+    TooBig {
+        /// Property which was requested.
+        tag: PropTag,
+    },
+
+    /// Any other error.
+    Failed {
+        /// Property which was requested.
+        tag: PropTag,
+
+        /// Error returned in the [`sys::PT_ERROR`] value.
+        error: HRESULT,
+    },
+}
+
+impl PropError {
+    /// Get the property which was requested.
+    pub fn tag(&self) -> PropTag {
+        match self {
+            Self::NotFound { tag } | Self::TooBig { tag } | Self::Failed { tag, .. } => *tag,
+        }
+    }
+
+    /// Get the error returned in the [`sys::PT_ERROR`] value.
+    pub fn error(&self) -> HRESULT {
+        match self {
+            Self::NotFound { .. } => sys::MAPI_E_NOT_FOUND,
+            Self::TooBig { .. } => sys::MAPI_E_NOT_ENOUGH_MEMORY,
+            Self::Failed { error, .. } => *error,
+        }
+    }
+}
+
+impl fmt::Display for PropError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound { tag } => write!(f, "property 0x{:08X} is not set", tag.0),
+            Self::TooBig { tag } => write!(f, "property 0x{:08X} is too big to read", tag.0),
+            Self::Failed { tag, error } => {
+                write!(f, "property 0x{:08X} failed with 0x{:08X}", tag.0, error.0)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PropError {}
+
+impl From<PropError> for Error {
+    fn from(value: PropError) -> Self {
+        Error::new(value.error(), value.to_string())
+    }
+}
+
+/// Sort a value returned from [`sys::IMAPIProp::GetProps`] into a [`PropValue`] or a
+/// [`PropError`] for the `requested` tag.
+fn check_prop(requested: PropTag, value: PropValue) -> core::result::Result<PropValue, PropError> {
+    let tag = requested;
+    match value.value {
+        PropValueData::Error(error) if error == sys::MAPI_E_NOT_FOUND => {
+            Err(PropError::NotFound { tag })
+        }
+        PropValueData::Error(error) if error == sys::MAPI_E_NOT_ENOUGH_MEMORY => {
+            Err(PropError::TooBig { tag })
+        }
+        PropValueData::Error(error) => Err(PropError::Failed { tag, error }),
+        _ => Ok(value),
+    }
+}
+
+/// Read a value which was too big for [`sys::IMAPIProp::GetProps`] with
+/// [`MAPIProp::open_property_stream`]. The size is checked against the [`ReadLimits`] before
+/// reading it.
+fn read_streamed<P: MAPIProp + ?Sized>(
+    prop: &P,
+    tag: PropTag,
+    limits: &ReadLimits,
+) -> Result<Option<StreamedValue>> {
+    let prop_type: u32 = tag.prop_type().into();
+    let tag = match prop_type {
+        sys::PT_BINARY => tag,
+        sys::PT_STRING8 | sys::PT_UNICODE => {
+            tag.change_prop_type(PropType::new(sys::PT_UNICODE as u16))
+        }
+        _ => return Ok(None),
+    };
+    let Ok(mut reader) = prop.stream_property(tag, PropertyReader::DEFAULT_CHUNK_SIZE) else {
+        return Ok(None);
+    };
+    let value = read_checked(&mut reader, tag, limits)?;
+    Ok(Some(if prop_type == sys::PT_BINARY {
+        StreamedValue::Binary(value)
+    } else {
+        StreamedValue::Unicode(
+            value
+                .chunks_exact(2)
+                .map(|ch| u16::from_le_bytes([ch[0], ch[1]]))
+                .chain(core::iter::once(0))
+                .collect(),
+        )
+    }))
+}
+
+/// Read the rest of a [`PropertyReader`] after checking the size of the stream against the
+/// [`ReadLimits`] for a [`sys::PT_BINARY`] or [`sys::PT_UNICODE`] value.
+fn read_checked(reader: &mut PropertyReader, tag: PropTag, limits: &ReadLimits) -> Result<Vec<u8>> {
+    let stream = reader.get_mut();
+    let size = stream.seek(SeekFrom::End(0))?;
+    let size = usize::try_from(size)?;
+    match u32::from(tag.prop_type()) {
+        sys::PT_STRING8 => limits.check_string(tag, size)?,
+        sys::PT_UNICODE => limits.check_string(tag, size / 2)?,
+        _ => limits.check_binary(tag, size)?,
+    }
+    stream.seek(SeekFrom::Start(0))?;
+    let mut value = Vec::with_capacity(size);
+    reader.read_to_end(&mut value)?;
+    Ok(value)
+}
+
+/// Copy everything from `reader` to `writer` through a buffer of `chunk_size` bytes.
+fn copy_chunked(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    chunk_size: usize,
+) -> io::Result<u64> {
+    let mut buffer = vec![0_u8; chunk_size.max(1)];
+    let mut written = 0;
+    loop {
+        let count = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(written),
+            Ok(count) => count,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+        writer.write_all(&buffer[..count])?;
+        written += count as u64;
+    }
+}
+
+/// Entry in a [`sys::SPropProblemArray`] returned from [`MAPIProp::set_props_with_problems`] or
+/// [`MAPIProp::delete_props_with_problems`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PropProblem {
+    /// Index of the property in the values or tags which were passed in.
+    pub index: usize,
+
+    /// Property which could not be set or deleted.
+    pub tag: PropTag,
+
+    /// What went wrong.
+    pub scode: HRESULT,
+}
+
+impl From<&sys::SPropProblem> for PropProblem {
+    fn from(value: &sys::SPropProblem) -> Self {
+        Self {
+            index: value.ulIndex as usize,
+            tag: PropTag(value.ulPropTag),
+            scode: HRESULT(value.scode),
+        }
+    }
+}
+
+impl From<PropProblem> for Error {
+    fn from(value: PropProblem) -> Self {
+        Error::new(value.scode, format!("property 0x{:08X}", value.tag.0))
+    }
+}
+
+/// Copy the entries in a [`sys::SPropProblemArray`] returned from MAPI, and free it.
+fn collect_problems(mut problems: MAPIOutParam<sys::SPropProblemArray>) -> Vec<PropProblem> {
+    match unsafe { problems.as_mut() } {
+        Some(problems) => unsafe { read_problems(problems) },
+        None => Vec::new(),
+    }
+}
+
+/// Read [`sys::SPropProblemArray::cProblem`] entries from a [`sys::SPropProblemArray`].
+///
+/// # Safety
+///
+/// The allocation must be at least [`crate::CbSPropProblemArray`] bytes, e.g. a buffer returned
+/// from MAPI or a [`crate::PropProblemArrayBuf`].
+unsafe fn read_problems(problems: &sys::SPropProblemArray) -> Vec<PropProblem> {
+    let entries = problems.aProblem.as_ptr();
+    (0..problems.cProblem as usize)
+        .map(|index| PropProblem::from(&ptr::read_unaligned(entries.add(index))))
+        .collect()
+}
+
+/// Read [`sys::SPropTagArray::cValues`] entries from a [`sys::SPropTagArray`].
+///
+/// # Safety
+///
+/// The allocation must be at least [`crate::CbSPropTagArray`] bytes, e.g. a buffer returned from
+/// MAPI or a [`crate::PropTagArrayBuf`].
+unsafe fn read_tags(tags: &sys::SPropTagArray) -> Vec<PropTag> {
+    let entries = tags.aulPropTag.as_ptr();
+    (0..tags.cValues as usize)
+        .map(|index| PropTag(ptr::read_unaligned(entries.add(index))))
+        .collect()
+}
+
+/// Convert the first [`PropProblem`] into an [`Error`].
+fn check_problems(problems: Vec<PropProblem>) -> Result<()> {
+    match problems.into_iter().next() {
+        Some(problem) => Err(problem.into()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_changes_flags() {
+        assert_eq!(u32::from(SaveChangesFlags::default()), 0);
+        assert_eq!(
+            u32::from(SaveChangesFlags {
+                keep_open_read_write: true,
+                ..Default::default()
+            }),
+            sys::KEEP_OPEN_READWRITE
+        );
+    }
+
+    #[test]
+    fn copy_props_flags() {
+        assert_eq!(u32::from(CopyPropsFlags::default()), 0);
+        assert_eq!(
+            u32::from(CopyPropsFlags {
+                move_props: true,
+                no_replace: true,
+                ..Default::default()
+            }),
+            sys::MAPI_MOVE | sys::MAPI_NOREPLACE
+        );
+    }
+
+    #[test]
+    fn copy_in_chunks() {
+        struct Chunks(Vec<usize>);
+
+        impl Write for Chunks {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.push(buf.len());
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut writer = Chunks(Vec::new());
+        let written = copy_chunked(&mut &[1_u8; 10][..], &mut writer, 4).expect("copy failed");
+        assert_eq!(written, 10);
+        assert_eq!(writer.0, [4, 4, 2]);
+    }
+
+    #[test]
+    fn read_prop_list() {
+        let tags = crate::PropTagArrayBuf::from([sys::PR_SUBJECT_W, sys::PR_BODY_W]);
+        let tags = unsafe { read_tags(&*tags.as_ptr()) };
+        assert_eq!(tags, [PropTag(sys::PR_SUBJECT_W), PropTag(sys::PR_BODY_W)]);
+    }
+
+    #[test]
+    fn checked_props() {
+        let tag = PropTag(sys::PR_SUBJECT_W);
+        let error = |error| PropValue {
+            tag: tag.change_prop_type(PropType::new(sys::PT_ERROR as u16)),
+            value: PropValueData::Error(error),
+        };
+
+        assert_eq!(
+            check_prop(tag, error(sys::MAPI_E_NOT_FOUND)).err(),
+            Some(PropError::NotFound { tag })
+        );
+        assert_eq!(
+            check_prop(tag, error(sys::MAPI_E_NOT_ENOUGH_MEMORY)).err(),
+            Some(PropError::TooBig { tag })
+        );
+        assert_eq!(
+            check_prop(tag, error(sys::MAPI_E_NO_ACCESS)).err(),
+            Some(PropError::Failed {
+                tag,
+                error: sys::MAPI_E_NO_ACCESS
+            })
+        );
+
+        let value = check_prop(
+            tag,
+            PropValue {
+                tag,
+                value: PropValueData::Long(1),
+            },
+        )
+        .ok()
+        .map(|value| value.value);
+        assert!(matches!(value, Some(PropValueData::Long(1))));
+    }
+
+    #[test]
+    fn prop_problems() {
+        let problems = crate::PropProblemArrayBuf {
+            aProblem: [
+                sys::SPropProblem {
+                    ulIndex: 0,
+                    ulPropTag: sys::PR_SUBJECT_W,
+                    scode: sys::MAPI_E_NO_ACCESS.0,
+                },
+                sys::SPropProblem {
+                    ulIndex: 3,
+                    ulPropTag: sys::PR_BODY_W,
+                    scode: sys::MAPI_E_COMPUTED.0,
+                },
+            ],
+            ..Default::default()
+        };
+        let problems = unsafe { read_problems(&*problems.as_ptr()) };
+        assert_eq!(
+            problems,
+            [
+                PropProblem {
+                    index: 0,
+                    tag: PropTag(sys::PR_SUBJECT_W),
+                    scode: sys::MAPI_E_NO_ACCESS,
+                },
+                PropProblem {
+                    index: 3,
+                    tag: PropTag(sys::PR_BODY_W),
+                    scode: sys::MAPI_E_COMPUTED,
+                },
+            ]
+        );
+        assert_eq!(
+            check_problems(problems).unwrap_err().code(),
+            sys::MAPI_E_NO_ACCESS
+        );
+        assert!(check_problems(Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn prop_error() {
+        let error = PropError::Failed {
+            tag: PropTag(sys::PR_SUBJECT_W),
+            error: sys::MAPI_E_NO_ACCESS,
+        };
+        assert_eq!(error.tag(), PropTag(sys::PR_SUBJECT_W));
+        assert_eq!(Error::from(error).code(), sys::MAPI_E_NO_ACCESS);
+    }
+}
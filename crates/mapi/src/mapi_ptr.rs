@@ -1,7 +1,8 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
-//! Define [`MAPIUninit`], [`MAPIBuffer`], and [`MAPIOutParam`].
+//! Define [`MAPIUninit`], [`MAPIBuffer`], [`MapiArena`], [`MAPIOutParam`], and the
+//! [`OutParamDeleter`] implementations for it.
 //!
 //! Smart pointer types for memory allocated with [`sys::MAPIAllocateBuffer`], which must be freed
 //! with [`sys::MAPIFreeBuffer`], or [`sys::MAPIAllocateMore`], which is chained to another
@@ -14,8 +15,8 @@ use core::{
     mem::{self, MaybeUninit},
     ptr, slice,
 };
-use windows::Win32::Foundation::E_OUTOFMEMORY;
-use windows_core::{Error, HRESULT};
+use windows::Win32::Foundation::{E_BOUNDS, E_OUTOFMEMORY, E_POINTER};
+use windows_core::{Error, IUnknown, Interface, HRESULT};
 
 /// Errors which can be returned from this module.
 #[derive(Debug)]
@@ -39,6 +40,16 @@ pub enum MAPIAllocError {
     AllocationFailed(Error),
 }
 
+impl From<MAPIAllocError> for Error {
+    fn from(value: MAPIAllocError) -> Self {
+        match value {
+            MAPIAllocError::SizeOverflow(_) => Error::from_hresult(E_OUTOFMEMORY),
+            MAPIAllocError::OutOfBoundsAccess => Error::from_hresult(E_BOUNDS),
+            MAPIAllocError::AllocationFailed(err) => err,
+        }
+    }
+}
+
 enum Buffer<T>
 where
     T: Sized,
@@ -68,11 +79,11 @@ where
     T: Sized,
 {
     fn new(count: usize) -> Result<Self, MAPIAllocError> {
-        let byte_count = count * mem::size_of::<T>();
+        let byte_count = Self::byte_count(count)?;
         Ok(Self::Root {
             buffer: unsafe {
                 let mut alloc = ptr::null_mut();
-                HRESULT::from_win32(sys::MAPIAllocateBuffer(
+                HRESULT::from_win32(backend::allocate_buffer(
                     u32::try_from(byte_count)
                         .map_err(|_| MAPIAllocError::SizeOverflow(byte_count))?,
                     &mut alloc,
@@ -90,22 +101,37 @@ where
         })
     }
 
+    /// Get the size of `count` elements of `T`, or [`MAPIAllocError::SizeOverflow`] with the
+    /// `count` if that does not fit in a `usize`.
+    fn byte_count(count: usize) -> Result<usize, MAPIAllocError> {
+        count
+            .checked_mul(mem::size_of::<T>())
+            .ok_or(MAPIAllocError::SizeOverflow(count))
+    }
+
     fn chain<P>(&self, count: usize) -> Result<Allocation<'a, P>, MAPIAllocError>
     where
         P: Sized,
     {
-        let root = match self {
+        Allocation::chain_to(self.root(), count)
+    }
+
+    fn root(&self) -> *mut ffi::c_void {
+        match self {
             Self::Root { buffer, .. } => match buffer {
                 Buffer::Uninit(alloc) => *alloc as *mut _,
                 Buffer::Ready(alloc) => *alloc as *mut _,
             },
             Self::More { root, .. } => *root,
-        };
-        let byte_count = count * mem::size_of::<T>();
-        Ok(Allocation::More {
+        }
+    }
+
+    fn chain_to(root: *mut ffi::c_void, count: usize) -> Result<Self, MAPIAllocError> {
+        let byte_count = Self::byte_count(count)?;
+        Ok(Self::More {
             buffer: unsafe {
                 let mut alloc = ptr::null_mut();
-                HRESULT::from_win32(sys::MAPIAllocateMore(
+                HRESULT::from_win32(backend::allocate_more(
                     u32::try_from(byte_count)
                         .map_err(|_| MAPIAllocError::SizeOverflow(byte_count))?,
                     root,
@@ -139,7 +165,7 @@ where
             Self::Root {
                 buffer: Buffer::Uninit(alloc),
                 byte_count,
-            } if byte_count >= mem::size_of::<T>() => Ok(Allocation::Root {
+            } if byte_count >= mem::size_of::<P>() => Ok(Allocation::Root {
                 buffer: Buffer::Uninit(alloc as *mut _),
                 byte_count,
             }),
@@ -148,7 +174,7 @@ where
                 byte_count,
                 root,
                 ..
-            } if byte_count >= mem::size_of::<T>() => Ok(Allocation::More {
+            } if byte_count >= mem::size_of::<P>() => Ok(Allocation::More {
                 buffer: Buffer::Uninit(alloc as *mut _),
                 byte_count,
                 root,
@@ -261,6 +287,42 @@ where
             _ => Err(MAPIAllocError::OutOfBoundsAccess),
         }
     }
+
+    fn ready_slice(&self, count: usize) -> Result<(*mut T, usize), MAPIAllocError> {
+        let (alloc, byte_count) = match self {
+            Self::Root {
+                buffer: Buffer::Uninit(_),
+                ..
+            }
+            | Self::More {
+                buffer: Buffer::Uninit(_),
+                ..
+            } => unreachable!(),
+            Self::Root {
+                buffer: Buffer::Ready(alloc),
+                byte_count,
+            }
+            | Self::More {
+                buffer: Buffer::Ready(alloc),
+                byte_count,
+                ..
+            } => (*alloc, *byte_count),
+        };
+        match count.checked_mul(mem::size_of::<T>()) {
+            Some(size) if size <= byte_count => Ok((alloc, count)),
+            _ => Err(MAPIAllocError::OutOfBoundsAccess),
+        }
+    }
+
+    fn as_slice(&self, count: usize) -> Result<&[T], MAPIAllocError> {
+        let (alloc, count) = self.ready_slice(count)?;
+        Ok(unsafe { slice::from_raw_parts(alloc, count) })
+    }
+
+    fn as_mut_slice(&mut self, count: usize) -> Result<&mut [T], MAPIAllocError> {
+        let (alloc, count) = self.ready_slice(count)?;
+        Ok(unsafe { slice::from_raw_parts_mut(alloc, count) })
+    }
 }
 
 impl<T> Drop for Allocation<'_, T> {
@@ -271,11 +333,8 @@ impl<T> Drop for Allocation<'_, T> {
                 Buffer::Ready(alloc) => alloc,
             };
             if !alloc.is_null() {
-                #[cfg(test)]
-                unreachable!();
-                #[cfg(not(test))]
                 unsafe {
-                    sys::MAPIFreeBuffer(alloc as *mut _);
+                    backend::free_buffer(alloc as *mut _);
                 }
             }
         }
@@ -413,24 +472,203 @@ impl<'a, T> MAPIBuffer<'a, T> {
     pub fn as_mut(&mut self) -> Result<&mut T, MAPIAllocError> {
         self.0.as_mut()
     }
+
+    /// Access the first `count` elements of type `T` once they have been initialized with
+    /// [`MAPIUninit::assume_init`]. Returns [`MAPIAllocError::OutOfBoundsAccess`] if the
+    /// allocation is not big enough for `count` elements.
+    pub fn as_slice(&self, count: usize) -> Result<&[T], MAPIAllocError> {
+        self.0.as_slice(count)
+    }
+
+    /// Mutable version of [`MAPIBuffer::as_slice`].
+    pub fn as_mut_slice(&mut self, count: usize) -> Result<&mut [T], MAPIAllocError> {
+        self.0.as_mut_slice(count)
+    }
+}
+
+/// Arena of allocations chained with [`sys::MAPIAllocateMore`] to a single root allocation, for
+/// building nested structures like a [`sys::SRestriction`] tree or a [`sys::SPropValue`] array
+/// which point to more allocations.
+///
+/// Everything allocated from the arena is initialized, and it is not freed until the root
+/// allocation is freed, so the references it returns are tied to the lifetime of the arena. The
+/// arena never runs destructors, so it only allocates [`Copy`] types.
+pub struct MapiArena<'a> {
+    root: *mut ffi::c_void,
+    /// Root allocation from [`MapiArena::new`], which is only held to free it on drop.
+    _owned: Option<Allocation<'static, u8>>,
+    phantom: PhantomData<&'a ()>,
+}
+
+impl MapiArena<'static> {
+    /// Create an arena with its own root allocation from [`sys::MAPIAllocateBuffer`]. Everything
+    /// allocated from the arena is freed with a single call to [`sys::MAPIFreeBuffer`] when it is
+    /// dropped.
+    pub fn new() -> Result<Self, MAPIAllocError> {
+        let owned = Allocation::new(1)?;
+        Ok(Self {
+            root: owned.root(),
+            _owned: Some(owned),
+            phantom: PhantomData,
+        })
+    }
+}
+
+impl<'a> MapiArena<'a> {
+    /// Create an arena which chains its allocations to an existing [`MAPIUninit`], e.g. the root
+    /// of a structure which is filled in with pointers to the arena. The allocations are freed
+    /// along with `root`.
+    pub fn chained<T>(root: &'a MAPIUninit<'_, T>) -> Self {
+        Self {
+            root: root.0.root(),
+            _owned: None,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Allocate `count` elements of type `T` with [`sys::MAPIAllocateMore`], initialized with
+    /// [`Default::default`]. An empty slice does not allocate anything.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc<T>(&self, count: usize) -> Result<&mut [T], MAPIAllocError>
+    where
+        T: Copy + Default,
+    {
+        let alloc = self.alloc_raw::<T>(count)?;
+        unsafe {
+            for index in 0..count {
+                alloc.add(index).write(T::default());
+            }
+            Ok(slice::from_raw_parts_mut(alloc, count))
+        }
+    }
+
+    /// Allocate a copy of `data` with [`sys::MAPIAllocateMore`].
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_copy<T>(&self, data: &[T]) -> Result<&mut [T], MAPIAllocError>
+    where
+        T: Copy,
+    {
+        let alloc = self.alloc_raw::<T>(data.len())?;
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), alloc, data.len());
+            Ok(slice::from_raw_parts_mut(alloc, data.len()))
+        }
+    }
+
+    /// Allocate a copy of a byte buffer, e.g. for [`sys::SBinary::lpb`].
+    pub fn alloc_bytes(&self, data: &[u8]) -> Result<&mut [u8], MAPIAllocError> {
+        self.alloc_copy(data)
+    }
+
+    /// Allocate a `null` terminated UTF-16 copy of `value`, e.g. for a [`sys::PT_UNICODE`]
+    /// property. The slice includes the `null` terminator.
+    pub fn alloc_str_w(&self, value: &str) -> Result<&mut [u16], MAPIAllocError> {
+        let alloc = self.alloc(value.encode_utf16().count() + 1)?;
+        for (element, ch) in alloc.iter_mut().zip(value.encode_utf16()) {
+            *element = ch;
+        }
+        Ok(alloc)
+    }
+
+    /// Allocate room for `count` elements of type `T`, or return a dangling pointer if `count` is
+    /// 0. Each call returns a separate allocation which lives until the root allocation is freed,
+    /// so the references built on it never alias each other.
+    fn alloc_raw<T>(&self, count: usize) -> Result<*mut T, MAPIAllocError> {
+        if count == 0 {
+            return Ok(ptr::NonNull::dangling().as_ptr());
+        }
+        match Allocation::<T>::chain_to(self.root, count)? {
+            Allocation::More {
+                buffer: Buffer::Uninit(alloc),
+                ..
+            } => Ok(alloc as *mut T),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Free the buffer or object returned in a [`MAPIOutParam`] when it is dropped.
+pub trait OutParamDeleter<T>
+where
+    T: Sized,
+{
+    /// Free a non-`null` pointer returned from the MAPI API.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must have been returned from an API which expects the caller to free it this
+    /// way, and it must not be used again.
+    unsafe fn delete(value: *mut T);
+}
+
+/// Free the out-param with [`sys::MAPIFreeBuffer`], which is what most MAPI APIs expect.
+pub struct MapiFree;
+
+impl<T> OutParamDeleter<T> for MapiFree
+where
+    T: Sized,
+{
+    unsafe fn delete(value: *mut T) {
+        backend::free_buffer(value as *mut _);
+    }
+}
+
+/// Free a [`sys::SRowSet`] out-param with [`sys::FreeProws`], which also frees each row, e.g. from
+/// [`sys::HrQueryAllRows`].
+pub struct FreePRows;
+
+impl OutParamDeleter<sys::SRowSet> for FreePRows {
+    unsafe fn delete(value: *mut sys::SRowSet) {
+        sys::FreeProws(value);
+    }
+}
+
+/// Call `Release` on a COM interface out-param, e.g. from a method which returns a `*mut c_void`
+/// for a requested IID.
+pub struct ComRelease;
+
+impl OutParamDeleter<ffi::c_void> for ComRelease {
+    unsafe fn delete(value: *mut ffi::c_void) {
+        drop(IUnknown::from_raw(value));
+    }
 }
 
 /// Hold an out-pointer for MAPI APIs which perform their own buffer allocations. This version does
 /// not perform any validation of the buffer size, so the typed accessors are inherently unsafe.
-pub struct MAPIOutParam<T>(*mut T)
+///
+/// The pointer is freed with the [`OutParamDeleter`] in `D` when this is dropped, which is
+/// [`sys::MAPIFreeBuffer`] by default.
+pub struct MAPIOutParam<T, D = MapiFree>(*mut T, PhantomData<D>)
 where
-    T: Sized;
+    T: Sized,
+    D: OutParamDeleter<T>;
 
-impl<T> MAPIOutParam<T>
+impl<T, D> MAPIOutParam<T, D>
 where
     T: Sized,
+    D: OutParamDeleter<T>,
 {
+    /// Create an empty out-param. If the API fills it in, the pointer is freed with `D`.
+    pub fn new() -> Self {
+        Self(ptr::null_mut(), PhantomData)
+    }
+
     /// Get a `*mut *mut T` suitable for use with a MAPI API that fills in an out-pointer
     /// with a newly allocated buffer.
     pub fn as_mut_ptr(&mut self) -> *mut *mut T {
         &mut self.0
     }
 
+    /// Borrow a single element of type `T`.
+    ///
+    /// # Safety
+    ///
+    /// This version does not perform any validation of the buffer size, so the typed accessors are
+    /// inherently unsafe. The only thing it handles is a `null` check.
+    pub unsafe fn as_ref(&self) -> Option<&T> {
+        self.0.as_ref()
+    }
+
     /// Access a single element of type `T`.
     ///
     /// # Safety
@@ -456,31 +694,180 @@ where
     }
 }
 
+impl MAPIOutParam<ffi::c_void, ComRelease> {
+    /// Query the COM interface returned in the out-param for `I`. Returns [`E_POINTER`] if it is
+    /// `null`.
+    ///
+    /// # Safety
+    ///
+    /// The out-param must hold a COM interface pointer.
+    pub unsafe fn cast<I>(&self) -> Result<I, Error>
+    where
+        I: Interface,
+    {
+        IUnknown::from_raw_borrowed(&self.0)
+            .ok_or_else(|| Error::from_hresult(E_POINTER))?
+            .cast()
+    }
+}
+
 impl<T> Default for MAPIOutParam<T>
 where
     T: Sized,
 {
+    /// Create an empty out-param which is freed with [`sys::MAPIFreeBuffer`]. Use
+    /// [`MAPIOutParam::new`] for any other [`OutParamDeleter`].
     fn default() -> Self {
-        Self(ptr::null_mut())
+        Self::new()
     }
 }
 
-impl<T> Drop for MAPIOutParam<T>
+impl<T, D> Drop for MAPIOutParam<T, D>
 where
     T: Sized,
+    D: OutParamDeleter<T>,
 {
     fn drop(&mut self) {
         if !self.0.is_null() {
-            #[cfg(test)]
-            unreachable!();
-            #[cfg(not(test))]
             unsafe {
-                sys::MAPIFreeBuffer(self.0 as *mut _);
+                D::delete(self.0);
             }
         }
     }
 }
 
+/// Allocator backend for [`MAPIUninit`], [`MAPIBuffer`], and [`MAPIOutParam`].
+///
+/// Normally this just forwards to [`sys::MAPIAllocateBuffer`], [`sys::MAPIAllocateMore`], and
+/// [`sys::MAPIFreeBuffer`]. Unit tests and fuzz targets can't depend on MAPI being installed, so
+/// they use a simple heap with the same chaining semantics instead.
+#[cfg(not(any(test, fuzzing)))]
+pub(crate) mod backend {
+    use super::sys;
+    use core::ffi;
+
+    pub unsafe fn allocate_buffer(byte_count: u32, alloc: *mut *mut ffi::c_void) -> i32 {
+        sys::MAPIAllocateBuffer(byte_count, alloc)
+    }
+
+    pub unsafe fn allocate_more(
+        byte_count: u32,
+        root: *mut ffi::c_void,
+        alloc: *mut *mut ffi::c_void,
+    ) -> i32 {
+        sys::MAPIAllocateMore(byte_count, root, alloc)
+    }
+
+    pub unsafe fn free_buffer(alloc: *mut ffi::c_void) {
+        sys::MAPIFreeBuffer(alloc);
+    }
+}
+
+#[cfg(any(test, fuzzing))]
+pub(crate) mod backend {
+    use super::sys;
+    use core::{ffi, mem};
+    use std::{
+        alloc::{self, Layout},
+        collections::HashMap,
+        sync::Mutex,
+    };
+    use windows::Win32::Foundation::{E_INVALIDARG, E_OUTOFMEMORY};
+
+    /// MAPI allocations are aligned for any of the types in a [`sys::SPropValue`].
+    const ALIGNMENT: usize = mem::align_of::<sys::SPropValue>();
+
+    /// Allocation chained to a root allocation with [`allocate_more`]. This keeps the original
+    /// pointer instead of just the address, so freeing it doesn't lose the provenance when the
+    /// tests run under Miri.
+    struct Chained(*mut u8, Layout);
+
+    // SAFETY: The pointer is only dereferenced to free the allocation while holding the lock.
+    unsafe impl Send for Chained {}
+
+    /// Map the address of each root allocation to its [`Layout`] and the list of chained
+    /// allocations.
+    type Heap = HashMap<usize, (Layout, Vec<Chained>)>;
+
+    static HEAP: Mutex<Option<Heap>> = Mutex::new(None);
+
+    fn allocate(byte_count: u32) -> Option<(*mut u8, Layout)> {
+        let layout = Layout::from_size_align(byte_count.max(1) as usize, ALIGNMENT).ok()?;
+        let alloc = unsafe { alloc::alloc_zeroed(layout) };
+        (!alloc.is_null()).then_some((alloc, layout))
+    }
+
+    pub unsafe fn allocate_buffer(byte_count: u32, alloc: *mut *mut ffi::c_void) -> i32 {
+        let Some((buffer, layout)) = allocate(byte_count) else {
+            return E_OUTOFMEMORY.0;
+        };
+        let mut heap = HEAP.lock().expect("heap poisoned");
+        heap.get_or_insert_with(Default::default)
+            .insert(buffer as usize, (layout, vec![]));
+        *alloc = buffer as *mut _;
+        0
+    }
+
+    pub unsafe fn allocate_more(
+        byte_count: u32,
+        root: *mut ffi::c_void,
+        alloc: *mut *mut ffi::c_void,
+    ) -> i32 {
+        let mut heap = HEAP.lock().expect("heap poisoned");
+        let Some((_, chain)) = heap
+            .get_or_insert_with(Default::default)
+            .get_mut(&(root as usize))
+        else {
+            return E_INVALIDARG.0;
+        };
+        let Some((buffer, layout)) = allocate(byte_count) else {
+            return E_OUTOFMEMORY.0;
+        };
+        chain.push(Chained(buffer, layout));
+        *alloc = buffer as *mut _;
+        0
+    }
+
+    pub unsafe fn free_buffer(alloc: *mut ffi::c_void) {
+        let mut heap = HEAP.lock().expect("heap poisoned");
+        let (layout, chain) = heap
+            .get_or_insert_with(Default::default)
+            .remove(&(alloc as usize))
+            .expect("not a root allocation");
+        for Chained(buffer, layout) in chain {
+            alloc::dealloc(buffer, layout);
+        }
+        alloc::dealloc(alloc as *mut _, layout);
+    }
+
+    /// Test if a root allocation has not been freed yet.
+    #[cfg(test)]
+    pub fn is_allocated(alloc: *mut ffi::c_void) -> bool {
+        HEAP.lock()
+            .expect("heap poisoned")
+            .as_ref()
+            .map(|heap| heap.contains_key(&(alloc as usize)))
+            .unwrap_or_default()
+    }
+
+    /// Free a list where each entry owns a separate [`sys::SPropValue`] array, like a
+    /// [`sys::SRowSet`] or [`sys::ADRLIST`], from the test heap instead of calling
+    /// [`sys::FreeProws`] or [`sys::FreePadrlist`]. The `owner` would call one of those when it
+    /// is dropped, so it is forgotten.
+    #[cfg(test)]
+    pub unsafe fn free_list<T>(
+        owner: T,
+        list: *mut ffi::c_void,
+        props: impl IntoIterator<Item = *mut sys::SPropValue>,
+    ) {
+        for props in props.into_iter().filter(|props| !props.is_null()) {
+            free_buffer(props as *mut _);
+        }
+        free_buffer(list);
+        mem::forget(owner);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -488,7 +875,7 @@ mod tests {
 
     use mem::ManuallyDrop;
 
-    SizedSPropTagArray! { TestTags[2] }
+    type TestTags = PropTagArrayBuf<2>;
 
     const TEST_TAGS: TestTags = TestTags {
         cValues: 2,
@@ -507,11 +894,12 @@ mod tests {
 
     #[test]
     fn buffer_into() {
-        let mut buffer: [MaybeUninit<u8>; mem::size_of::<TestTags>()] =
-            [MaybeUninit::uninit(); CbNewSPropTagArray(2)];
+        // Use a buffer of `u32` so the `u8` allocation is still aligned for `TestTags`.
+        let mut buffer: [MaybeUninit<u32>; CbNewSPropTagArray(2) / mem::size_of::<u32>()] =
+            [MaybeUninit::uninit(); CbNewSPropTagArray(2) / mem::size_of::<u32>()];
         let mut mapi_buffer = ManuallyDrop::new(MAPIUninit(Allocation::Root {
-            buffer: Buffer::Uninit(buffer.as_mut_ptr()),
-            byte_count: buffer.len(),
+            buffer: Buffer::Uninit(buffer.as_mut_ptr() as *mut MaybeUninit<u8>),
+            byte_count: mem::size_of_val(&buffer),
         }));
         assert!(mapi_buffer.uninit().is_ok());
         let mut mapi_buffer = ManuallyDrop::new(
@@ -576,3 +964,311 @@ mod tests {
         assert_eq!(TEST_TAGS.aulPropTag, test_tags.aulPropTag);
     }
 }
+
+/// Soundness tests which go through the allocator backend instead of stack buffers, so the
+/// pointer arithmetic, chaining, and [`MAPIUninit::assume_init`] paths are exercised with real
+/// heap allocations. Run them under Miri to catch undefined behavior:
+///
+/// ```text
+/// cargo +nightly miri test -p outlook-mapi mapi_ptr
+/// ```
+#[cfg(test)]
+mod soundness_tests {
+    use super::*;
+    use crate::*;
+
+    type TestTags = PropTagArrayBuf<2>;
+
+    /// Get the address of the root allocation, which is the key in the test backend heap.
+    fn root_of<T>(alloc: &Allocation<'_, T>) -> *mut ffi::c_void {
+        match alloc {
+            Allocation::Root {
+                buffer: Buffer::Uninit(alloc),
+                ..
+            } => *alloc as *mut _,
+            Allocation::Root {
+                buffer: Buffer::Ready(alloc),
+                ..
+            } => *alloc as *mut _,
+            Allocation::More { root, .. } => *root,
+        }
+    }
+
+    #[test]
+    fn root_freed_on_drop() {
+        let buffer = MAPIUninit::<u32>::new(4).expect("new failed");
+        let root = root_of(&buffer.0);
+        assert!(backend::is_allocated(root));
+        drop(buffer);
+        assert!(!backend::is_allocated(root));
+    }
+
+    #[test]
+    fn chained_freed_with_root() {
+        let buffer = MAPIUninit::<u32>::new(1).expect("new failed");
+        let root = root_of(&buffer.0);
+
+        let mut first = buffer.chain::<u64>(2).expect("chain failed");
+        assert_eq!(root_of(&first.0), root);
+        first.uninit().expect("uninit failed").write(u64::MAX);
+
+        let mut second = first.chain::<u16>(3).expect("chain failed");
+        assert_eq!(root_of(&second.0), root);
+        second.uninit().expect("uninit failed").write(u16::MAX);
+
+        // Dropping the chained allocations must not free anything.
+        drop(second);
+        drop(first);
+        assert!(backend::is_allocated(root));
+
+        drop(buffer);
+        assert!(!backend::is_allocated(root));
+    }
+
+    #[test]
+    fn chain_from_buffer() {
+        let mut buffer = MAPIUninit::<u32>::new(1).expect("new failed");
+        buffer.uninit().expect("uninit failed").write(1);
+        let buffer = unsafe { buffer.assume_init() };
+        let root = root_of(&buffer.0);
+
+        let mut chained = buffer.chain::<u32>(1).expect("chain failed");
+        assert_eq!(root_of(&chained.0), root);
+        chained.uninit().expect("uninit failed").write(2);
+        let mut chained = unsafe { chained.assume_init() };
+        assert_eq!(*chained.as_mut().expect("as_mut failed"), 2);
+
+        drop(buffer);
+        assert!(!backend::is_allocated(root));
+    }
+
+    #[test]
+    fn arena_freed_on_drop() {
+        let arena = MapiArena::new().expect("new failed");
+        let root = arena.root;
+        assert!(backend::is_allocated(root));
+
+        let values = arena.alloc::<u64>(3).expect("alloc failed");
+        assert_eq!(values, [0; 3]);
+        values[1] = u64::MAX;
+        let bytes = arena.alloc_bytes(b"abc").expect("alloc_bytes failed");
+        let text = arena.alloc_str_w("Zoë").expect("alloc_str_w failed");
+        assert_eq!(values, [0, u64::MAX, 0]);
+        assert_eq!(bytes, b"abc");
+        assert_eq!(text, ['Z' as u16, 'o' as u16, 'ë' as u16, 0]);
+        assert!(arena.alloc::<u32>(0).expect("alloc failed").is_empty());
+
+        drop(arena);
+        assert!(!backend::is_allocated(root));
+    }
+
+    #[test]
+    fn arena_chained_to_buffer() {
+        let buffer = MAPIUninit::<u32>::new(1).expect("new failed");
+        let root = root_of(&buffer.0);
+        {
+            let arena = MapiArena::chained(&buffer);
+            assert_eq!(arena.root, root);
+            arena.alloc_copy(&[1_u16, 2]).expect("alloc_copy failed");
+        }
+        assert!(backend::is_allocated(root));
+
+        drop(buffer);
+        assert!(!backend::is_allocated(root));
+    }
+
+    #[test]
+    fn iter_writes_every_element() {
+        const COUNT: usize = 5;
+
+        let buffer = MAPIUninit::<u64>::new(COUNT).expect("new failed");
+        let root = root_of(&buffer.0);
+        let mut count = 0;
+        for (idx, mut element) in buffer.iter().enumerate() {
+            assert_eq!(root_of(&element.0), root);
+            element
+                .uninit()
+                .expect("uninit failed")
+                .write(idx as u64 + 1);
+            let mut element = unsafe { element.assume_init() };
+            assert_eq!(*element.as_mut().expect("as_mut failed"), idx as u64 + 1);
+            count += 1;
+        }
+        assert_eq!(count, COUNT);
+
+        let mut buffer = unsafe { buffer.assume_init() };
+        assert_eq!(*buffer.as_mut().expect("as_mut failed"), 1);
+    }
+
+    #[test]
+    fn into_sized_type() {
+        let buffer = MAPIUninit::<u8>::new(CbNewSPropTagArray(2)).expect("new failed");
+        let root = root_of(&buffer.0);
+
+        let mut tags = buffer.into::<TestTags>().expect("into failed");
+        assert_eq!(root_of(&tags.0), root);
+        tags.uninit().expect("uninit failed").write(TestTags {
+            cValues: 2,
+            aulPropTag: [sys::PR_INSTANCE_KEY, sys::PR_SUBJECT_W],
+        });
+        let mut tags = unsafe { tags.assume_init() };
+        let tags = tags.as_mut().expect("as_mut failed");
+        assert_eq!(tags.cValues, 2);
+        assert_eq!(tags.aulPropTag, [sys::PR_INSTANCE_KEY, sys::PR_SUBJECT_W]);
+    }
+
+    #[test]
+    fn into_too_small() {
+        let buffer = MAPIUninit::<u8>::new(CbNewSPropTagArray(2) - 1).expect("new failed");
+        let root = root_of(&buffer.0);
+        assert!(matches!(
+            buffer.into::<TestTags>(),
+            Err(MAPIAllocError::OutOfBoundsAccess)
+        ));
+
+        // The original allocation is dropped with the error.
+        assert!(!backend::is_allocated(root));
+    }
+
+    #[test]
+    fn empty_allocation() {
+        let mut buffer = MAPIUninit::<u32>::new(0).expect("new failed");
+        assert!(matches!(
+            buffer.uninit(),
+            Err(MAPIAllocError::OutOfBoundsAccess)
+        ));
+        assert!(buffer.iter().next().is_none());
+
+        let mut chained = buffer.chain::<u32>(0).expect("chain failed");
+        assert!(matches!(
+            chained.uninit(),
+            Err(MAPIAllocError::OutOfBoundsAccess)
+        ));
+    }
+
+    #[test]
+    fn size_overflow() {
+        let count = u32::MAX as usize;
+        assert!(matches!(
+            MAPIUninit::<u64>::new(count),
+            Err(MAPIAllocError::SizeOverflow(_))
+        ));
+
+        let buffer = MAPIUninit::<u8>::new(1).expect("new failed");
+        assert!(matches!(
+            buffer.chain::<u64>(count),
+            Err(MAPIAllocError::SizeOverflow(_))
+        ));
+
+        // This wraps around to a small size if the multiplication is not checked.
+        let count = usize::MAX / mem::size_of::<u64>() + 2;
+        assert!(matches!(
+            MAPIUninit::<u64>::new(count),
+            Err(MAPIAllocError::SizeOverflow(_))
+        ));
+        assert!(matches!(
+            buffer.chain::<u64>(count),
+            Err(MAPIAllocError::SizeOverflow(_))
+        ));
+        let arena = MapiArena::chained(&buffer);
+        assert!(matches!(
+            arena.alloc::<u64>(count),
+            Err(MAPIAllocError::SizeOverflow(_))
+        ));
+    }
+
+    #[test]
+    fn out_param() {
+        let mut empty = MAPIOutParam::<u32>::new();
+        assert!(unsafe { empty.as_mut() }.is_none());
+        assert!(unsafe { empty.as_mut_slice(1) }.is_none());
+
+        let mut out_param = MAPIOutParam::<u32>::new();
+        unsafe {
+            assert_eq!(
+                backend::allocate_buffer(
+                    (3 * mem::size_of::<u32>()) as u32,
+                    out_param.as_mut_ptr() as *mut _,
+                ),
+                0
+            );
+        }
+        let root = unsafe { *out_param.as_mut_ptr() } as *mut ffi::c_void;
+        let values = unsafe { out_param.as_mut_slice(3) }.expect("missing buffer");
+        values.copy_from_slice(&[1, 2, 3]);
+        assert_eq!(unsafe { out_param.as_mut() }.copied(), Some(1));
+
+        drop(out_param);
+        assert!(!backend::is_allocated(root));
+    }
+
+    #[test]
+    fn out_param_release() {
+        let owner = std::sync::Arc::new(());
+        let callback = owner.clone();
+        let sink = ProgressSink::create(move |_| {
+            let _ = &callback;
+        });
+        assert_eq!(std::sync::Arc::strong_count(&owner), 2);
+
+        let mut out_param = MAPIOutParam::<ffi::c_void, ComRelease>::new();
+        unsafe {
+            *out_param.as_mut_ptr() = sink.into_raw();
+            let sink: sys::IMAPIProgress = out_param.cast().expect("cast failed");
+            sink.Progress(1, 0, 0).expect("Progress failed");
+        }
+        assert_eq!(std::sync::Arc::strong_count(&owner), 2);
+
+        drop(out_param);
+        assert_eq!(std::sync::Arc::strong_count(&owner), 1);
+    }
+
+    #[test]
+    fn buffer_slices() {
+        const COUNT: usize = 3;
+
+        let buffer = MAPIUninit::<u32>::new(COUNT).expect("new failed");
+        for (idx, mut element) in buffer.iter().enumerate() {
+            element
+                .uninit()
+                .expect("uninit failed")
+                .write(idx as u32 + 59);
+        }
+        let mut buffer = unsafe { buffer.assume_init() };
+
+        assert_eq!(
+            buffer.as_slice(COUNT).expect("as_slice failed"),
+            [59, 60, 61]
+        );
+        assert_eq!(buffer.as_slice(0).expect("as_slice failed"), [0_u32; 0]);
+        buffer.as_mut_slice(COUNT).expect("as_mut_slice failed")[COUNT - 1] = 62;
+        assert_eq!(
+            buffer.as_slice(COUNT).expect("as_slice failed"),
+            [59, 60, 62]
+        );
+
+        assert!(matches!(
+            buffer.as_slice(COUNT + 1),
+            Err(MAPIAllocError::OutOfBoundsAccess)
+        ));
+        assert!(matches!(
+            buffer.as_mut_slice(usize::MAX),
+            Err(MAPIAllocError::OutOfBoundsAccess)
+        ));
+    }
+
+    #[test]
+    fn chained_slices() {
+        let buffer = MAPIUninit::<u8>::new(1).expect("new failed");
+        let chained = buffer.chain::<u16>(2).expect("chain failed");
+        for mut element in chained.iter() {
+            element.uninit().expect("uninit failed").write(63);
+        }
+        let chained = unsafe { chained.assume_init() };
+        assert_eq!(chained.as_slice(2).expect("as_slice failed"), [63, 63]);
+        assert!(matches!(
+            chained.as_slice(3),
+            Err(MAPIAllocError::OutOfBoundsAccess)
+        ));
+    }
+}
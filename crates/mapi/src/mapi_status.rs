@@ -0,0 +1,225 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`MapiStatus`].
+
+use crate::sys;
+use core::fmt;
+use windows_core::*;
+
+macro_rules! mapi_status {
+    ($($variant:ident => $value:ident,)*) => {
+        /// Named variants for the `MAPI_E_*` and `MAPI_W_*` [`HRESULT`] values in [`sys`], so
+        /// error handling code can `match` on them. Convert from an [`HRESULT`] or an [`Error`],
+        /// and anything else, e.g. a generic COM error, is [`MapiStatus::Other`].
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum MapiStatus {
+            $(
+                #[doc = concat!("[`sys::", stringify!($value), "`]")]
+                $variant,
+            )*
+
+            /// Any other [`HRESULT`].
+            Other(HRESULT),
+        }
+
+        impl MapiStatus {
+            #[cfg(test)]
+            const ALL: &'static [Self] = &[$(Self::$variant,)*];
+
+            /// Get the [`HRESULT`] value.
+            pub fn hresult(self) -> HRESULT {
+                match self {
+                    $(Self::$variant => sys::$value,)*
+                    Self::Other(hresult) => hresult,
+                }
+            }
+
+            /// Get the name of the constant in [`sys`], e.g. `MAPI_E_NOT_FOUND`.
+            pub fn name(self) -> Option<&'static str> {
+                match self {
+                    $(Self::$variant => Some(stringify!($value)),)*
+                    Self::Other(_) => None,
+                }
+            }
+        }
+
+        impl From<HRESULT> for MapiStatus {
+            fn from(value: HRESULT) -> Self {
+                $(
+                    if value == sys::$value {
+                        return Self::$variant;
+                    }
+                )*
+                Self::Other(value)
+            }
+        }
+    };
+}
+
+mapi_status!(
+    AccountDisabled => MAPI_E_ACCOUNT_DISABLED,
+    AmbiguousRecip => MAPI_E_AMBIGUOUS_RECIP,
+    BadCharWidth => MAPI_E_BAD_CHARWIDTH,
+    BadColumn => MAPI_E_BAD_COLUMN,
+    BadValue => MAPI_E_BAD_VALUE,
+    Busy => MAPI_E_BUSY,
+    CallFailed => MAPI_E_CALL_FAILED,
+    Cancel => MAPI_E_CANCEL,
+    Collision => MAPI_E_COLLISION,
+    Computed => MAPI_E_COMPUTED,
+    CorruptData => MAPI_E_CORRUPT_DATA,
+    CorruptStore => MAPI_E_CORRUPT_STORE,
+    DeclineCopy => MAPI_E_DECLINE_COPY,
+    DiskError => MAPI_E_DISK_ERROR,
+    EndOfSession => MAPI_E_END_OF_SESSION,
+    ExtendedError => MAPI_E_EXTENDED_ERROR,
+    FailOneProvider => MAPI_E_FAILONEPROVIDER,
+    FolderCycle => MAPI_E_FOLDER_CYCLE,
+    HasFolders => MAPI_E_HAS_FOLDERS,
+    HasMessages => MAPI_E_HAS_MESSAGES,
+    InterfaceNotSupported => MAPI_E_INTERFACE_NOT_SUPPORTED,
+    InvalidAccessTime => MAPI_E_INVALID_ACCESS_TIME,
+    InvalidBookmark => MAPI_E_INVALID_BOOKMARK,
+    InvalidEntryId => MAPI_E_INVALID_ENTRYID,
+    InvalidObject => MAPI_E_INVALID_OBJECT,
+    InvalidParameter => MAPI_E_INVALID_PARAMETER,
+    InvalidType => MAPI_E_INVALID_TYPE,
+    InvalidWorkstationAccount => MAPI_E_INVALID_WORKSTATION_ACCOUNT,
+    LogonFailed => MAPI_E_LOGON_FAILED,
+    MissingRequiredColumn => MAPI_E_MISSING_REQUIRED_COLUMN,
+    NetworkError => MAPI_E_NETWORK_ERROR,
+    NonStandard => MAPI_E_NON_STANDARD,
+    NotEnoughDisk => MAPI_E_NOT_ENOUGH_DISK,
+    NotEnoughMemory => MAPI_E_NOT_ENOUGH_MEMORY,
+    NotEnoughResources => MAPI_E_NOT_ENOUGH_RESOURCES,
+    NotFound => MAPI_E_NOT_FOUND,
+    NotInitialized => MAPI_E_NOT_INITIALIZED,
+    NotInQueue => MAPI_E_NOT_IN_QUEUE,
+    NotMe => MAPI_E_NOT_ME,
+    NoAccess => MAPI_E_NO_ACCESS,
+    NoRecipients => MAPI_E_NO_RECIPIENTS,
+    NoSupport => MAPI_E_NO_SUPPORT,
+    NoSuppress => MAPI_E_NO_SUPPRESS,
+    ObjectChanged => MAPI_E_OBJECT_CHANGED,
+    ObjectDeleted => MAPI_E_OBJECT_DELETED,
+    PasswordChangeRequired => MAPI_E_PASSWORD_CHANGE_REQUIRED,
+    PasswordExpired => MAPI_E_PASSWORD_EXPIRED,
+    SessionLimit => MAPI_E_SESSION_LIMIT,
+    StringTooLong => MAPI_E_STRING_TOO_LONG,
+    Submitted => MAPI_E_SUBMITTED,
+    TableEmpty => MAPI_E_TABLE_EMPTY,
+    TableTooBig => MAPI_E_TABLE_TOO_BIG,
+    Timeout => MAPI_E_TIMEOUT,
+    TooBig => MAPI_E_TOO_BIG,
+    TooComplex => MAPI_E_TOO_COMPLEX,
+    TypeNoSupport => MAPI_E_TYPE_NO_SUPPORT,
+    UnableToAbort => MAPI_E_UNABLE_TO_ABORT,
+    UnableToComplete => MAPI_E_UNABLE_TO_COMPLETE,
+    Unconfigured => MAPI_E_UNCONFIGURED,
+    UnexpectedId => MAPI_E_UNEXPECTED_ID,
+    UnexpectedType => MAPI_E_UNEXPECTED_TYPE,
+    UnknownCpid => MAPI_E_UNKNOWN_CPID,
+    UnknownEntryId => MAPI_E_UNKNOWN_ENTRYID,
+    UnknownFlags => MAPI_E_UNKNOWN_FLAGS,
+    UnknownLcid => MAPI_E_UNKNOWN_LCID,
+    UserCancel => MAPI_E_USER_CANCEL,
+    Version => MAPI_E_VERSION,
+    Wait => MAPI_E_WAIT,
+    WarnApproxCount => MAPI_W_APPROX_COUNT,
+    WarnCancelMessage => MAPI_W_CANCEL_MESSAGE,
+    WarnErrorsReturned => MAPI_W_ERRORS_RETURNED,
+    WarnNoService => MAPI_W_NO_SERVICE,
+    WarnPartialCompletion => MAPI_W_PARTIAL_COMPLETION,
+    WarnPositionChanged => MAPI_W_POSITION_CHANGED,
+);
+
+impl MapiStatus {
+    /// Check for a failure which might succeed if the same call is retried later, i.e.
+    /// [`sys::MAPI_E_NETWORK_ERROR`], [`sys::MAPI_E_TIMEOUT`], [`sys::MAPI_E_BUSY`], or
+    /// [`sys::MAPI_E_WAIT`].
+    pub fn is_transient(self) -> bool {
+        matches!(
+            self,
+            Self::NetworkError | Self::Timeout | Self::Busy | Self::Wait
+        )
+    }
+
+    /// Check for a failure which means the object is not there, i.e. [`sys::MAPI_E_NOT_FOUND`],
+    /// [`sys::MAPI_E_OBJECT_DELETED`], or [`sys::MAPI_E_UNKNOWN_ENTRYID`].
+    pub fn is_not_found(self) -> bool {
+        matches!(
+            self,
+            Self::NotFound | Self::ObjectDeleted | Self::UnknownEntryId
+        )
+    }
+
+    /// Check for a `MAPI_W_*` warning, which is a success code with more information.
+    pub fn is_warning(self) -> bool {
+        self.hresult().is_ok() && self.name().is_some()
+    }
+}
+
+impl From<&Error> for MapiStatus {
+    fn from(value: &Error) -> Self {
+        value.code().into()
+    }
+}
+
+impl From<MapiStatus> for HRESULT {
+    fn from(value: MapiStatus) -> Self {
+        value.hresult()
+    }
+}
+
+impl From<MapiStatus> for Error {
+    fn from(value: MapiStatus) -> Self {
+        value.hresult().into()
+    }
+}
+
+impl fmt::Display for MapiStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{name}"),
+            None => write!(f, "0x{:08X}", self.hresult().0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windows::Win32::Foundation::*;
+
+    #[test]
+    fn round_trip() {
+        for status in MapiStatus::ALL {
+            assert_eq!(MapiStatus::from(status.hresult()), *status, "{status}");
+        }
+        assert_eq!(
+            MapiStatus::from(E_UNEXPECTED),
+            MapiStatus::Other(E_UNEXPECTED)
+        );
+        assert_eq!(
+            MapiStatus::from(&Error::from(sys::MAPI_E_TIMEOUT)),
+            MapiStatus::Timeout
+        );
+    }
+
+    #[test]
+    fn classify() {
+        assert!(MapiStatus::from(sys::MAPI_E_NETWORK_ERROR).is_transient());
+        assert!(!MapiStatus::from(sys::MAPI_E_NOT_FOUND).is_transient());
+        assert!(MapiStatus::from(sys::MAPI_E_UNKNOWN_ENTRYID).is_not_found());
+        assert!(MapiStatus::from(sys::MAPI_W_ERRORS_RETURNED).is_warning());
+        assert!(!MapiStatus::from(sys::MAPI_E_CALL_FAILED).is_warning());
+        assert!(!MapiStatus::Other(S_OK).is_warning());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(MapiStatus::NotFound.to_string(), "MAPI_E_NOT_FOUND");
+        assert_eq!(MapiStatus::Other(HRESULT(1)).to_string(), "0x00000001");
+    }
+}
@@ -0,0 +1,226 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`MarshalToThread`] and [`Marshaled`].
+//!
+//! # Thread safety
+//!
+//! The wrappers around MAPI interfaces, e.g. [`crate::Logon`], [`crate::MsgStore`],
+//! [`crate::Folder`], [`crate::Message`], [`crate::Table`], and [`crate::AddrBook`], are neither
+//! [`Send`] nor [`Sync`]. MAPI objects belong to the thread (and COM apartment) which opened
+//! them, so the interface pointers can only be used there. To use one of them on another
+//! thread, call [`MarshalToThread::marshal_to_thread`], move the [`Marshaled`] value to the
+//! other thread, and call [`Marshaled::unmarshal`] there to get a wrapper around the same
+//! object. [`crate::Logon`] also needs the [`crate::Initialize`] from the other thread, so call
+//! [`Marshaled::unmarshal_with`] there instead.
+//!
+//! [`Marshaled`] is both [`Send`] and [`Sync`], and the wrapper returned from
+//! [`Marshaled::unmarshal`] is again neither [`Send`] nor [`Sync`]. Plain data which does not
+//! hold an interface pointer, e.g. [`crate::Row`], [`crate::RowSet`], [`crate::EntryId`], and
+//! [`crate::OwnedPropValue`], is [`Send`], so it can be passed between threads directly.
+//!
+//! Only moving objects between two threads in the multithreaded apartment (MTA) is supported,
+//! e.g. threads which call `CoInitializeEx` with `COINIT_MULTITHREADED` before
+//! [`crate::Initialize::new`]. MAPI does not register a proxy/stub for its interfaces, so the
+//! Global Interface Table cannot marshal them into or out of a single-threaded apartment (STA).
+//! Between two MTA threads, [`Marshaled::unmarshal`] returns the same interface pointer rather
+//! than a proxy.
+
+use core::{marker::PhantomData, ptr};
+use windows::Win32::{Foundation::*, System::Com::*};
+use windows_core::*;
+
+/// `CLSID_StdGlobalInterfaceTable`, which is not included in the `windows` crate.
+const CLSID_STD_GLOBAL_INTERFACE_TABLE: GUID =
+    GUID::from_u128(0x00000323_0000_0000_c000_000000000046);
+
+/// Wrappers which can be moved to another thread with the COM Global Interface Table.
+pub trait MarshalToThread: Sized {
+    /// Everything besides the interface which is needed to rebuild the wrapper on the other
+    /// thread.
+    type State: Send;
+
+    /// Anything from the current thread which is needed to rebuild the wrapper, e.g. the
+    /// `Arc<Initialize>` for [`crate::Logon`]. This is `()` for most wrappers.
+    type Context;
+
+    /// Call [`IGlobalInterfaceTable::RegisterInterfaceInGlobal`] to make the interface
+    /// available to other threads. COM must be initialized on this thread, e.g. by
+    /// [`crate::Initialize::new`].
+    fn marshal_to_thread(&self) -> Result<Marshaled<Self>>;
+
+    /// Rebuild the wrapper from a [`Marshaled`] value on the current thread. Call
+    /// [`Marshaled::unmarshal`] or [`Marshaled::unmarshal_with`] instead.
+    fn unmarshal(marshaled: Marshaled<Self>, context: Self::Context) -> Result<Self>;
+}
+
+/// Cookie from [`IGlobalInterfaceTable::RegisterInterfaceInGlobal`], along with the rest of the
+/// [`MarshalToThread::State`]. This is [`Send`], so it can be moved to another thread and turned
+/// back into the wrapper with [`Marshaled::unmarshal`].
+///
+/// If it is dropped without calling [`Marshaled::unmarshal`], the interface is revoked from the
+/// Global Interface Table.
+pub struct Marshaled<T>
+where
+    T: MarshalToThread,
+{
+    cookie: u32,
+    state: Option<T::State>,
+    _wrapper: PhantomData<fn() -> T>,
+}
+
+impl<T> Marshaled<T>
+where
+    T: MarshalToThread,
+{
+    /// Register `interface` in the Global Interface Table and keep the `state` for
+    /// [`MarshalToThread::unmarshal`].
+    pub(crate) fn new<I>(interface: &I, state: T::State) -> Result<Self>
+    where
+        I: Interface,
+    {
+        let unknown: IUnknown = interface.cast()?;
+        let cookie =
+            unsafe { global_interface_table()?.RegisterInterfaceInGlobal(&unknown, &I::IID)? };
+        Ok(Self {
+            cookie,
+            state: Some(state),
+            _wrapper: PhantomData,
+        })
+    }
+
+    /// Call [`IGlobalInterfaceTable::GetInterfaceFromGlobal`] to get the interface on the
+    /// current thread, and return it with the [`MarshalToThread::State`].
+    pub(crate) fn into_parts<I>(mut self) -> Result<(I, T::State)>
+    where
+        I: Interface,
+    {
+        let mut interface = ptr::null_mut();
+        unsafe {
+            global_interface_table()?.GetInterfaceFromGlobal(
+                self.cookie,
+                &I::IID,
+                &mut interface,
+            )?;
+        }
+        if interface.is_null() {
+            return Err(Error::from(E_POINTER));
+        }
+        let interface = unsafe { I::from_raw(interface) };
+        let state = self.state.take().ok_or_else(|| Error::from(E_UNEXPECTED))?;
+        Ok((interface, state))
+    }
+
+    /// Get the wrapper back on the current thread. COM must be initialized on this thread,
+    /// e.g. by [`crate::Initialize::new`].
+    pub fn unmarshal(self) -> Result<T>
+    where
+        T: MarshalToThread<Context = ()>,
+    {
+        T::unmarshal(self, ())
+    }
+
+    /// Get the wrapper back on the current thread, passing along the
+    /// [`MarshalToThread::Context`] from this thread.
+    pub fn unmarshal_with(self, context: T::Context) -> Result<T> {
+        T::unmarshal(self, context)
+    }
+}
+
+// SAFETY: There are no methods which take `&self`, so a shared reference cannot be used to
+// touch the cookie or the state from more than one thread.
+unsafe impl<T> Sync for Marshaled<T> where T: MarshalToThread {}
+
+impl<T> Drop for Marshaled<T>
+where
+    T: MarshalToThread,
+{
+    fn drop(&mut self) {
+        if let Ok(table) = global_interface_table() {
+            let _ = unsafe { table.RevokeInterfaceFromGlobal(self.cookie) };
+        }
+    }
+}
+
+/// Call [`CoCreateInstance`] to get the process-wide Global Interface Table.
+fn global_interface_table() -> Result<IGlobalInterfaceTable> {
+    unsafe {
+        CoCreateInstance(
+            &CLSID_STD_GLOBAL_INTERFACE_TABLE,
+            None,
+            CLSCTX_INPROC_SERVER,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use std::sync::Arc;
+
+    /// Only compiles if `T` is [`Send`].
+    fn assert_send<T: Send>() {}
+
+    /// Only compiles if `T` is [`Sync`].
+    fn assert_sync<T: Sync>() {}
+
+    /// The call to `check` is ambiguous if `T` implements [`Send`], so this only compiles for
+    /// types which do not.
+    trait AmbiguousIfSend<A> {
+        fn check() {}
+    }
+    impl<T: ?Sized> AmbiguousIfSend<()> for T {}
+    impl<T: ?Sized + Send> AmbiguousIfSend<u8> for T {}
+
+    /// Same as [`AmbiguousIfSend`] for [`Sync`].
+    trait AmbiguousIfSync<A> {
+        fn check() {}
+    }
+    impl<T: ?Sized> AmbiguousIfSync<()> for T {}
+    impl<T: ?Sized + Sync> AmbiguousIfSync<u8> for T {}
+
+    macro_rules! assert_not_send_sync {
+        ($($wrapper:ty),*) => {
+            $(
+                <$wrapper as AmbiguousIfSend<_>>::check();
+                <$wrapper as AmbiguousIfSync<_>>::check();
+            )*
+        };
+    }
+
+    #[test]
+    fn wrappers_stay_on_thread() {
+        assert_not_send_sync!(
+            Logon,
+            MsgStore,
+            Folder,
+            Message,
+            Attachment,
+            Table,
+            AddrBook,
+            StatusObject
+        );
+    }
+
+    #[test]
+    fn marshaled_send() {
+        assert_send::<Marshaled<Logon>>();
+        assert_send::<Marshaled<MsgStore>>();
+        assert_send::<Marshaled<Folder>>();
+        assert_send::<Marshaled<Message>>();
+        assert_send::<Marshaled<AddrBook>>();
+        assert_send::<Row>();
+        assert_send::<RowSet>();
+        assert_send::<EntryId>();
+        assert_sync::<Marshaled<Logon>>();
+        assert_sync::<Marshaled<MsgStore>>();
+        assert_sync::<Marshaled<Folder>>();
+    }
+
+    #[test]
+    fn logon_initialize_stays_on_thread() {
+        let _: <Logon as MarshalToThread>::State = ();
+        let _: fn(Marshaled<Logon>, Arc<Initialize>) -> Result<Logon> = Marshaled::unmarshal_with;
+    }
+}
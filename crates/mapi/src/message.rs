@@ -0,0 +1,1012 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`Message`], [`HtmlBody`], [`Body`], [`MessageFlags`], [`MessageClass`], [`Recipient`],
+//! [`RecipOp`], and [`SubmitFlags`].
+
+use crate::{
+    addr_book::AdrList, mapi_error::with_last_error, prop_tag::prop_tag_array, retry::with_retry,
+    sys, to_pwstr_buffer, Attachment, BodyFormat, CodePage, EntryId, InitEpoch, MAPIProp,
+    MarshalToThread, Marshaled, ObjectKind, ObjectRegistration, OpenPropertyFlags, PropTag,
+    PropValue, PropValueData, RecipientKind, ResolvedRecipient, RetryPolicy, RtfBody, Table,
+    TableFlags,
+};
+use core::{cell::Cell, fmt, ptr};
+use std::io::{Read, Write};
+use windows::Win32::Foundation::*;
+use windows_core::*;
+
+/// HTML content passed to [`Message::set_html_body`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HtmlBody<'a> {
+    /// HTML which is already encoded in the code page passed to [`Message::set_html_body`].
+    Bytes(&'a [u8]),
+
+    /// HTML which should be encoded in the code page passed to [`Message::set_html_body`].
+    Text(&'a str),
+}
+
+impl<'a> From<&'a [u8]> for HtmlBody<'a> {
+    fn from(value: &'a [u8]) -> Self {
+        Self::Bytes(value)
+    }
+}
+
+impl<'a> From<&'a str> for HtmlBody<'a> {
+    fn from(value: &'a str) -> Self {
+        Self::Text(value)
+    }
+}
+
+/// Body of a message in the best available format, returned from [`Message::body`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Body {
+    /// Decoded [`sys::PR_BODY_W`].
+    PlainText(String),
+
+    /// [`sys::PR_HTML`], encoded in the [`sys::PR_INTERNET_CPID`] code page.
+    Html(Vec<u8>),
+
+    /// Decompressed [`sys::PR_RTF_COMPRESSED`].
+    Rtf(Vec<u8>),
+}
+
+impl Body {
+    /// Get the [`BodyFormat`] of this [`Body`].
+    pub fn format(&self) -> BodyFormat {
+        match self {
+            Self::PlainText(_) => BodyFormat::PlainText,
+            Self::Html(_) => BodyFormat::Html,
+            Self::Rtf(_) => BodyFormat::Rtf,
+        }
+    }
+}
+
+/// Status of a message, decoded from the [`sys::PR_MESSAGE_FLAGS`] bits returned by
+/// [`Message::flags`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MessageFlags(pub u32);
+
+impl MessageFlags {
+    /// Test if all of the bits in `mask` are set.
+    pub fn contains(self, mask: u32) -> bool {
+        self.0 & mask == mask
+    }
+
+    /// [`sys::MSGFLAG_READ`]: The message has been read.
+    pub fn is_read(self) -> bool {
+        self.contains(sys::MSGFLAG_READ)
+    }
+
+    /// [`sys::MSGFLAG_UNMODIFIED`]: The message has not been modified since it was delivered.
+    pub fn is_unmodified(self) -> bool {
+        self.contains(sys::MSGFLAG_UNMODIFIED)
+    }
+
+    /// [`sys::MSGFLAG_SUBMIT`]: The message has been submitted, but not sent yet.
+    pub fn is_submitted(self) -> bool {
+        self.contains(sys::MSGFLAG_SUBMIT)
+    }
+
+    /// [`sys::MSGFLAG_UNSENT`]: The message is a draft which can be sent with
+    /// [`Message::submit`].
+    pub fn is_unsent(self) -> bool {
+        self.contains(sys::MSGFLAG_UNSENT)
+    }
+
+    /// [`sys::MSGFLAG_HASATTACH`]: The message has at least one attachment.
+    pub fn has_attachments(self) -> bool {
+        self.contains(sys::MSGFLAG_HASATTACH)
+    }
+
+    /// [`sys::MSGFLAG_FROMME`]: The message was sent by the owner of the mailbox.
+    pub fn is_from_me(self) -> bool {
+        self.contains(sys::MSGFLAG_FROMME)
+    }
+
+    /// [`sys::MSGFLAG_ASSOCIATED`]: The message is in the associated contents table of the
+    /// folder, e.g. a view or a rule.
+    pub fn is_associated(self) -> bool {
+        self.contains(sys::MSGFLAG_ASSOCIATED)
+    }
+
+    /// [`sys::MSGFLAG_RESEND`]: The message is a resend of a message which failed to send.
+    pub fn is_resend(self) -> bool {
+        self.contains(sys::MSGFLAG_RESEND)
+    }
+
+    /// [`sys::MSGFLAG_RN_PENDING`]: A read receipt will be sent when the message is read.
+    pub fn is_read_receipt_pending(self) -> bool {
+        self.contains(sys::MSGFLAG_RN_PENDING)
+    }
+
+    /// [`sys::MSGFLAG_NRN_PENDING`]: A non-read receipt will be sent if the message is deleted
+    /// without being read.
+    pub fn is_non_read_receipt_pending(self) -> bool {
+        self.contains(sys::MSGFLAG_NRN_PENDING)
+    }
+}
+
+impl From<u32> for MessageFlags {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+/// [`sys::PR_MESSAGE_CLASS_W`] of a message, returned from [`Message::message_class`].
+///
+/// Message classes are hierarchical and case-insensitive, so `IPM.Note.SMIME` is still an
+/// `IPM.Note` as far as [`MessageClass::is`] is concerned.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MessageClass(pub String);
+
+impl MessageClass {
+    /// Get the message class as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Test if this is `class`, or a subclass of it, ignoring case.
+    pub fn is(&self, class: &str) -> bool {
+        let value = self.0.as_bytes();
+        let class = class.as_bytes();
+        value.len() >= class.len()
+            && value[..class.len()].eq_ignore_ascii_case(class)
+            && (value.len() == class.len() || value[class.len()] == b'.')
+    }
+
+    /// Test for an `IPM.Note`, i.e. an email message.
+    pub fn is_ipm_note(&self) -> bool {
+        self.is("IPM.Note")
+    }
+
+    /// Test for an `IPM.Schedule.Meeting.Request`.
+    pub fn is_meeting_request(&self) -> bool {
+        self.is("IPM.Schedule.Meeting.Request")
+    }
+
+    /// Test for an `IPM.Appointment`, i.e. a calendar item.
+    pub fn is_appointment(&self) -> bool {
+        self.is("IPM.Appointment")
+    }
+
+    /// Test for an `IPM.Contact`.
+    pub fn is_contact(&self) -> bool {
+        self.is("IPM.Contact")
+    }
+}
+
+impl From<&str> for MessageClass {
+    fn from(value: &str) -> Self {
+        Self(String::from(value))
+    }
+}
+
+impl fmt::Display for MessageClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Set of flags that can be passed to [`Message::submit`].
+#[derive(Default)]
+pub struct SubmitFlags {
+    /// Pass [`sys::FORCE_SUBMIT`] to submit the message even if the transport is not available
+    /// right now.
+    pub force: bool,
+}
+
+impl From<SubmitFlags> for u32 {
+    fn from(value: SubmitFlags) -> Self {
+        if value.force {
+            sys::FORCE_SUBMIT
+        } else {
+            0
+        }
+    }
+}
+
+/// Operation passed to [`Message::modify_recipients`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecipOp {
+    /// [`sys::MODRECIP_ADD`]
+    Add,
+
+    /// [`sys::MODRECIP_MODIFY`]
+    Modify,
+
+    /// [`sys::MODRECIP_REMOVE`]
+    Remove,
+}
+
+impl From<RecipOp> for u32 {
+    fn from(value: RecipOp) -> Self {
+        match value {
+            RecipOp::Add => sys::MODRECIP_ADD,
+            RecipOp::Modify => sys::MODRECIP_MODIFY,
+            RecipOp::Remove => sys::MODRECIP_REMOVE,
+        }
+    }
+}
+
+/// Row in the recipient table of a [`Message`], read with [`Message::recipients`] or passed to
+/// [`Message::modify_recipients`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Recipient {
+    /// [`sys::PR_RECIPIENT_TYPE`]
+    pub kind: RecipientKind,
+
+    /// [`sys::PR_ROWID`], which identifies the row for [`RecipOp::Modify`] and
+    /// [`RecipOp::Remove`]. It is ignored for [`RecipOp::Add`], since the message assigns a new
+    /// one.
+    pub row_id: Option<u32>,
+
+    /// [`sys::PR_DISPLAY_NAME_W`]
+    pub display_name: Option<String>,
+
+    /// [`sys::PR_EMAIL_ADDRESS_W`]
+    pub email_address: Option<String>,
+
+    /// [`sys::PR_ADDRTYPE_W`], e.g. `SMTP` or `EX`. If it is missing, the
+    /// [`Recipient::email_address`] is sent as `SMTP`.
+    pub address_type: Option<String>,
+
+    /// [`sys::PR_ENTRYID`]
+    pub entry_id: Option<EntryId>,
+}
+
+impl Recipient {
+    /// Properties needed by [`Recipient::from_props`].
+    pub const PROPS: [PropTag; 6] = [
+        PropTag(sys::PR_ROWID),
+        PropTag(sys::PR_RECIPIENT_TYPE),
+        PropTag(sys::PR_DISPLAY_NAME_W),
+        PropTag(sys::PR_EMAIL_ADDRESS_W),
+        PropTag(sys::PR_ADDRTYPE_W),
+        PropTag(sys::PR_ENTRYID),
+    ];
+
+    /// Pick out the recipient properties from a row in the recipient table.
+    pub fn from_props<'a, I>(values: I) -> Self
+    where
+        I: IntoIterator<Item = PropValue<'a>>,
+    {
+        let values: Vec<_> = values.into_iter().collect();
+        let mut kind = None;
+        let mut row_id = None;
+        for value in values.iter() {
+            match (value.tag.0, &value.value) {
+                (sys::PR_ROWID, PropValueData::Long(value)) => row_id = Some(*value as u32),
+                (sys::PR_RECIPIENT_TYPE, PropValueData::Long(value)) => {
+                    kind = recipient_kind(*value as u32)
+                }
+                _ => {}
+            }
+        }
+        let address = ResolvedRecipient::from_props(values);
+        Self {
+            kind: kind.unwrap_or_default(),
+            row_id,
+            display_name: address.display_name,
+            email_address: address.email_address,
+            address_type: address.address_type,
+            entry_id: address.entry_id,
+        }
+    }
+}
+
+impl From<&Recipient> for ResolvedRecipient {
+    fn from(value: &Recipient) -> Self {
+        Self {
+            display_name: value.display_name.clone(),
+            entry_id: value.entry_id.clone(),
+            address_type: value.address_type.clone(),
+            email_address: value.email_address.clone(),
+            smtp_address: None,
+        }
+    }
+}
+
+/// Hold on to a [`sys::IMessage`] and expose the operations needed to read and write messages
+/// without `unsafe`.
+pub struct Message {
+    /// Access the [`sys::IMessage`].
+    pub message: sys::IMessage,
+
+    epoch: InitEpoch,
+    registration: ObjectRegistration,
+
+    /// Policy from [`Message::set_retry_policy`].
+    retry: Cell<Option<RetryPolicy>>,
+}
+
+impl Message {
+    /// Wrap a [`sys::IMessage`] returned from one of the [`sys`] interface methods.
+    pub fn new(message: sys::IMessage) -> Self {
+        Self::with_registration(message, ObjectRegistration::new(ObjectKind::Message))
+    }
+
+    /// Wrap a [`sys::IMessage`] opened through another wrapper, so it belongs to the same store.
+    pub(crate) fn with_registration(
+        message: sys::IMessage,
+        registration: ObjectRegistration,
+    ) -> Self {
+        Self {
+            message,
+            epoch: InitEpoch::current(),
+            registration,
+            retry: Default::default(),
+        }
+    }
+
+    /// Make sure MAPI is still initialized and the store is still connected.
+    fn check(&self) -> Result<()> {
+        self.epoch.check()?;
+        Ok(self.registration.check_connected()?)
+    }
+
+    /// Retry the calls which open attachment and recipient tables or attachments if they fail
+    /// with a transient error, see [`RetryPolicy`]. Tables opened from this message start out
+    /// with the same policy. Pass [`None`] to stop retrying.
+    pub fn set_retry_policy(&self, policy: Option<RetryPolicy>) {
+        self.retry.set(policy);
+    }
+
+    /// Get the policy from [`Message::set_retry_policy`].
+    pub fn retry_policy(&self) -> Option<RetryPolicy> {
+        self.retry.get()
+    }
+
+    /// Call [`sys::IMessage::GetAttachmentTable`] to list the attachments on this message.
+    pub fn get_attachment_table(&self, flags: TableFlags) -> Result<Table> {
+        self.check()?;
+        let flags = flags.into();
+        let table = with_retry(self.retry_policy(), || unsafe {
+            self.message.GetAttachmentTable(flags)
+        })?;
+        let table = Table::with_registration(table, self.registration.child(ObjectKind::Table));
+        table.set_retry_policy(self.retry_policy());
+        Ok(table)
+    }
+
+    /// Call [`sys::IMessage::GetRecipientTable`] to list the recipients on this message.
+    pub fn get_recipient_table(&self, flags: TableFlags) -> Result<Table> {
+        self.check()?;
+        let flags = flags.into();
+        let table = with_retry(self.retry_policy(), || unsafe {
+            self.message.GetRecipientTable(flags)
+        })?;
+        let table = Table::with_registration(table, self.registration.child(ObjectKind::Table));
+        table.set_retry_policy(self.retry_policy());
+        Ok(table)
+    }
+
+    /// Call [`sys::IMessage::OpenAttach`] with [`sys::MAPI_BEST_ACCESS`] to open an attachment
+    /// using its [`sys::PR_ATTACH_NUM`].
+    pub fn open_attachment(&self, attach_num: u32) -> Result<Attachment> {
+        self.check()?;
+        let attach = with_retry(self.retry_policy(), || {
+            let mut attach = None;
+            unsafe {
+                self.message.OpenAttach(
+                    attach_num,
+                    ptr::null_mut(),
+                    sys::MAPI_BEST_ACCESS,
+                    &mut attach,
+                )?;
+            }
+            attach.ok_or_else(|| Error::from(E_POINTER))
+        })?;
+        Ok(Attachment::with_registration(
+            attach,
+            self.registration.child(ObjectKind::Attachment),
+        ))
+    }
+
+    /// Call [`sys::IMessage::CreateAttach`] to add a new attachment to this message. Set the
+    /// attachment properties, call [`MAPIProp::save_changes`] on the [`Attachment`], and then on
+    /// the message to keep it.
+    pub fn create_attachment(&self) -> Result<Attachment> {
+        self.check()?;
+        let mut attach_num = 0;
+        let mut attach = None;
+        unsafe {
+            self.message
+                .CreateAttach(ptr::null_mut(), 0, &mut attach_num, &mut attach)?;
+        }
+        let attach = attach.ok_or_else(|| Error::from(E_POINTER))?;
+        Ok(Attachment::with_registration(
+            attach,
+            self.registration.child(ObjectKind::Attachment),
+        ))
+    }
+
+    /// Call [`sys::IMessage::DeleteAttach`] to remove an attachment using its
+    /// [`sys::PR_ATTACH_NUM`]. Call [`MAPIProp::save_changes`] to keep the changes.
+    pub fn delete_attachment(&self, attach_num: u32) -> Result<()> {
+        self.check()?;
+        unsafe {
+            self.message
+                .DeleteAttach(attach_num, 0, None::<&sys::IMAPIProgress>, 0)
+        }
+    }
+
+    /// Call [`sys::IMessage::ModifyRecipients`] to replace all of the recipients on this message.
+    /// Each [`ResolvedRecipient`] should have at least a [`ResolvedRecipient::display_name`] and
+    /// an [`ResolvedRecipient::email_address`] or [`ResolvedRecipient::smtp_address`]. If the
+    /// [`ResolvedRecipient::address_type`] is missing, it is sent as `SMTP`. Call
+    /// [`MAPIProp::save_changes`] to keep the changes.
+    pub fn set_recipients<'a, I>(&self, recipients: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (RecipientKind, &'a ResolvedRecipient)>,
+    {
+        self.check()?;
+        let recipients: Vec<_> = recipients.into_iter().collect();
+        let entries: Vec<_> = recipients
+            .iter()
+            .map(|(kind, recipient)| recipient_props(*kind, recipient))
+            .collect();
+        let mut adr_list = AdrList::from_entries(&entries)?;
+        unsafe { self.message.ModifyRecipients(0, adr_list.as_mut_ptr()) }
+    }
+
+    /// Read every row of the [`Message::get_recipient_table`] as a [`Recipient`], including the
+    /// [`Recipient::row_id`] needed for [`Message::modify_recipients`].
+    pub fn recipients(&self) -> Result<Vec<Recipient>> {
+        let rows = self
+            .get_recipient_table(Default::default())?
+            .query_all_rows(&Recipient::PROPS, None, None)?;
+        Ok(rows
+            .into_iter()
+            .map(|row| Recipient::from_props(row.iter()))
+            .collect())
+    }
+
+    /// Call [`sys::IMessage::ModifyRecipients`] to add, modify, or remove the `recipients`.
+    ///
+    /// [`RecipOp::Modify`] and [`RecipOp::Remove`] find the rows by [`Recipient::row_id`], so
+    /// every recipient needs one, e.g. from [`Message::recipients`], or this returns
+    /// [`sys::MAPI_E_INVALID_PARAMETER`]. [`RecipOp::Remove`] only passes the row ID. Call
+    /// [`MAPIProp::save_changes`] to keep the changes.
+    pub fn modify_recipients(&self, op: RecipOp, recipients: &[Recipient]) -> Result<()> {
+        self.check()?;
+        let addresses: Vec<_> = recipients.iter().map(ResolvedRecipient::from).collect();
+        let entries = recipients
+            .iter()
+            .zip(addresses.iter())
+            .map(|(recipient, address)| recipient_row(op, recipient, address))
+            .collect::<Result<Vec<_>>>()?;
+        let mut adr_list = AdrList::from_entries(&entries)?;
+        unsafe {
+            self.message
+                .ModifyRecipients(op.into(), adr_list.as_mut_ptr())
+        }
+    }
+
+    /// Call [`sys::IMessage::SubmitMessage`] to save the message and hand it to the spooler to
+    /// send. The message cannot be modified after this.
+    ///
+    /// The message needs recipients, e.g. from [`Message::set_recipients`], and
+    /// [`sys::MSGFLAG_UNSENT`] in [`sys::PR_MESSAGE_FLAGS`]. Messages created with
+    /// [`crate::Outbox::create_message`] already have the flag set.
+    pub fn submit(&self, flags: SubmitFlags) -> Result<()> {
+        self.check()?;
+        unsafe {
+            self.message
+                .SubmitMessage(flags.into())
+                .map_err(|error| with_last_error(self.mapi_prop(), error))
+        }
+    }
+
+    /// Read [`sys::PR_MESSAGE_FLAGS`] and decode it as [`MessageFlags`].
+    pub fn flags(&self) -> Result<MessageFlags> {
+        let props = self.get_props(&[PropTag(sys::PR_MESSAGE_FLAGS)])?;
+        let value = props.iter().next().map(|value| value.value);
+        match value {
+            Some(PropValueData::Long(value)) => Ok(MessageFlags(value as u32)),
+            Some(PropValueData::Error(error)) => Err(Error::from(error)),
+            _ => Err(Error::from(sys::MAPI_E_NOT_FOUND)),
+        }
+    }
+
+    /// Read [`sys::PR_MESSAGE_CLASS_W`] as a [`MessageClass`].
+    pub fn message_class(&self) -> Result<MessageClass> {
+        let props = self.get_props(&[PropTag(sys::PR_MESSAGE_CLASS_W)])?;
+        let value = props.iter().next().map(|value| value.value);
+        match value {
+            Some(PropValueData::Error(error)) => Err(Error::from(error)),
+            Some(value) => value
+                .as_string()
+                .map(MessageClass)
+                .ok_or_else(|| Error::from(sys::MAPI_E_NOT_FOUND)),
+            None => Err(Error::from(sys::MAPI_E_NOT_FOUND)),
+        }
+    }
+
+    /// Read the [`sys::PR_ATTACH_NUM`] of every attachment from the
+    /// [`Message::get_attachment_table`], and return an iterator which opens each of them with
+    /// [`Message::open_attachment`].
+    pub fn attachments(&self) -> Result<impl Iterator<Item = Result<Attachment>> + '_> {
+        let rows = self
+            .get_attachment_table(Default::default())?
+            .query_all_rows(&[PropTag(sys::PR_ATTACH_NUM)], None, None)?;
+        let attach_nums: Vec<_> = rows
+            .into_iter()
+            .filter_map(|row| match row.iter().next()?.value {
+                PropValueData::Long(attach_num) => Some(attach_num as u32),
+                _ => None,
+            })
+            .collect();
+        Ok(attach_nums
+            .into_iter()
+            .map(|attach_num| self.open_attachment(attach_num)))
+    }
+
+    /// Open [`sys::PR_RTF_COMPRESSED`] and wrap it in an [`RtfBody`], which reads the
+    /// decompressed RTF.
+    ///
+    /// If [`sys::PR_RTF_IN_SYNC`] is missing or `false`, the plain text body was changed without
+    /// updating the RTF, so this calls [`sys::RTFSync`] with [`sys::RTF_SYNC_BODY_CHANGED`] first.
+    /// That only updates the open message. Call [`MAPIProp::save_changes`] to keep the changes.
+    /// If the sync fails, e.g. because the message was opened read-only, this reads the stored RTF
+    /// anyway.
+    pub fn open_rtf_body(&self) -> Result<RtfBody> {
+        self.check()?;
+        let props = self.get_props(&[PropTag(sys::PR_RTF_IN_SYNC)])?;
+        let in_sync = props.iter().next().is_some_and(
+            |value| matches!(value.value, PropValueData::Boolean(value) if value != 0),
+        );
+        read_synced_rtf(
+            in_sync,
+            || unsafe { sys::RTFSync(&self.message, sys::RTF_SYNC_BODY_CHANGED) },
+            || {
+                let compressed =
+                    self.open_property_stream(PropTag(sys::PR_RTF_COMPRESSED), Default::default())?;
+                RtfBody::wrap_compressed(&compressed)
+            },
+        )
+    }
+
+    /// Read the body of the message in its native [`BodyFormat`], which preserves the most
+    /// formatting. If the native format is missing, fall back to the other formats, preferring
+    /// [`Body::Html`] and [`Body::Rtf`] over [`Body::PlainText`]. A message without any body
+    /// returns an empty [`Body::PlainText`].
+    ///
+    /// Every format is read through a [`crate::PropertyStream`], so there is no limit on the size,
+    /// and [`Body::Rtf`] is read with [`Message::open_rtf_body`], which syncs the RTF first if
+    /// [`sys::PR_RTF_IN_SYNC`] says it is stale.
+    pub fn body(&self) -> Result<Body> {
+        self.check()?;
+        let format = BodyFormat::from_props(self.get_props(&BodyFormat::PROPS)?.iter());
+        let formats = match format {
+            BodyFormat::PlainText => [BodyFormat::PlainText, BodyFormat::Html, BodyFormat::Rtf],
+            BodyFormat::Rtf => [BodyFormat::Rtf, BodyFormat::Html, BodyFormat::PlainText],
+            BodyFormat::Html => [BodyFormat::Html, BodyFormat::Rtf, BodyFormat::PlainText],
+        };
+        for format in formats {
+            match self.read_body(format) {
+                Ok(body) => return Ok(body),
+                Err(error) if error.code() == sys::MAPI_E_NOT_FOUND => continue,
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(Body::PlainText(String::new()))
+    }
+
+    /// Read the body in a specific [`BodyFormat`], or fail with [`sys::MAPI_E_NOT_FOUND`].
+    fn read_body(&self, format: BodyFormat) -> Result<Body> {
+        let mut value = Vec::new();
+        match format {
+            BodyFormat::PlainText => {
+                self.open_property_stream(PropTag(sys::PR_BODY_W), Default::default())?
+                    .read_to_end(&mut value)?;
+                decode_unicode_body(&value).map(Body::PlainText)
+            }
+            BodyFormat::Rtf => {
+                self.open_rtf_body()?.read_to_end(&mut value)?;
+                Ok(Body::Rtf(value))
+            }
+            BodyFormat::Html => {
+                self.open_property_stream(PropTag(sys::PR_HTML), Default::default())?
+                    .read_to_end(&mut value)?;
+                Ok(Body::Html(value))
+            }
+        }
+    }
+
+    /// Replace the body of the message with HTML in the specified `code_page`.
+    ///
+    /// The HTML is written to [`sys::PR_HTML`] through a [`crate::PropertyStream`], so there is no
+    /// limit on the size. This also sets [`sys::PR_INTERNET_CPID`] to match the HTML, sets
+    /// [`sys::PR_MSG_EDITOR_FORMAT`] to [`sys::EDITOR_FORMAT_HTML`], and deletes the
+    /// [`sys::PR_BODY_W`] and [`sys::PR_RTF_COMPRESSED`] properties, so the store regenerates
+    /// the other body formats from the HTML instead of showing a stale body. Call
+    /// [`MAPIProp::save_changes`] to keep the changes.
+    pub fn set_html_body<'a, H>(&self, html: H, code_page: CodePage) -> Result<()>
+    where
+        H: Into<HtmlBody<'a>>,
+    {
+        self.check()?;
+        let encoded;
+        let html = match html.into() {
+            HtmlBody::Bytes(html) => html,
+            HtmlBody::Text(html) => {
+                encoded = code_page.encode(html)?;
+                encoded.as_slice()
+            }
+        };
+
+        self.write_html_body(html)?;
+        self.set_props(&[
+            PropValue {
+                tag: PropTag(sys::PR_INTERNET_CPID),
+                value: PropValueData::Long(u32::from(code_page) as i32),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_MSG_EDITOR_FORMAT),
+                value: PropValueData::Long(sys::EDITOR_FORMAT_HTML as i32),
+            },
+        ])
+    }
+
+    /// Replace the RTF body of the message.
+    ///
+    /// The RTF is compressed into [`sys::PR_RTF_COMPRESSED`] with
+    /// [`RtfBody::wrap_compressed_for_write`]. This deletes [`sys::PR_HTML`], which would be
+    /// stale, and then calls [`sys::RTFSync`] with
+    /// [`sys::RTF_SYNC_RTF_CHANGED`] to update [`sys::PR_BODY_W`] and the `PR_RTF_SYNC_*`
+    /// properties, so the other body formats match the RTF. Writing [`sys::PR_RTF_COMPRESSED`] with
+    /// [`MAPIProp::set_props`] skips all of that, which is how messages end up showing a different
+    /// body depending on the client. Call [`MAPIProp::save_changes`] to keep the changes.
+    pub fn set_rtf_body(&self, rtf: &[u8]) -> Result<()> {
+        self.check()?;
+        let mut compressed = self.open_property_stream(
+            PropTag(sys::PR_RTF_COMPRESSED),
+            OpenPropertyFlags {
+                create: true,
+                modify: true,
+                ..Default::default()
+            },
+        )?;
+        let mut body = RtfBody::wrap_compressed_for_write(&compressed)?;
+        body.write_all(rtf)?;
+        body.flush()?;
+        drop(body);
+        compressed.flush()?;
+
+        self.delete_stale_bodies(&[PropTag(sys::PR_HTML)])?;
+        // The RTF was just written, so it does not matter if this changed anything else.
+        let _updated = unsafe { sys::RTFSync(&self.message, sys::RTF_SYNC_RTF_CHANGED)? };
+        Ok(())
+    }
+
+    /// Delete [`sys::PR_BODY_W`] and [`sys::PR_RTF_COMPRESSED`], and write the HTML to
+    /// [`sys::PR_HTML`] through a [`crate::PropertyStream`].
+    pub(crate) fn write_html_body(&self, html: &[u8]) -> Result<()> {
+        self.delete_stale_bodies(&[PropTag(sys::PR_BODY_W), PropTag(sys::PR_RTF_COMPRESSED)])?;
+
+        let mut stream = self.open_property_stream(
+            PropTag(sys::PR_HTML),
+            OpenPropertyFlags {
+                create: true,
+                modify: true,
+                ..Default::default()
+            },
+        )?;
+        stream.write_all(html)?;
+        stream.flush()?;
+        Ok(())
+    }
+
+    /// Delete body properties which no longer match the body that is being written, so the store
+    /// regenerates them.
+    pub(crate) fn delete_stale_bodies(&self, tags: &[PropTag]) -> Result<()> {
+        self.check()?;
+        // Missing properties show up in the problem array, which we can ignore.
+        let mut stale = prop_tag_array(tags)?;
+        unsafe {
+            self.message
+                .DeleteProps(stale.as_mut_ptr() as *mut _, ptr::null_mut())?;
+        }
+        Ok(())
+    }
+}
+
+impl MAPIProp for Message {
+    fn mapi_prop(&self) -> &sys::IMAPIProp {
+        &self.message
+    }
+
+    fn interface_id(&self) -> GUID {
+        sys::IMessage::IID
+    }
+
+    fn init_epoch(&self) -> InitEpoch {
+        self.epoch
+    }
+
+    fn registration(&self) -> Option<&ObjectRegistration> {
+        Some(&self.registration)
+    }
+}
+
+impl From<sys::IMessage> for Message {
+    fn from(value: sys::IMessage) -> Self {
+        Self::new(value)
+    }
+}
+
+impl MarshalToThread for Message {
+    type State = (ObjectRegistration, Option<RetryPolicy>);
+    type Context = ();
+
+    fn marshal_to_thread(&self) -> Result<Marshaled<Self>> {
+        Marshaled::new(
+            &self.message,
+            (
+                self.registration.child(ObjectKind::Message),
+                self.retry_policy(),
+            ),
+        )
+    }
+
+    fn unmarshal(marshaled: Marshaled<Self>, _context: ()) -> Result<Self> {
+        let (message, (registration, retry)) = marshaled.into_parts()?;
+        let message = Self::with_registration(message, registration);
+        message.set_retry_policy(retry);
+        Ok(message)
+    }
+}
+
+/// Decode the little-endian UTF-16 bytes from a [`sys::PR_BODY_W`] stream, which may include a
+/// `null` terminator.
+pub(crate) fn decode_unicode_body(value: &[u8]) -> Result<String> {
+    let value: Vec<_> = value
+        .chunks_exact(2)
+        .map(|ch| u16::from_le_bytes([ch[0], ch[1]]))
+        .collect();
+    let value = String::from_utf16(&value).map_err(|_| Error::from(sys::MAPI_E_CORRUPT_DATA))?;
+    Ok(value.trim_end_matches('\0').to_string())
+}
+
+/// Convert a [`ResolvedRecipient`] to the properties [`sys::IMessage::ModifyRecipients`] expects.
+fn recipient_props(kind: RecipientKind, recipient: &ResolvedRecipient) -> Vec<PropValue> {
+    let unicode = |tag: u32, value: &str| PropValue {
+        tag: PropTag(tag),
+        value: PropValueData::Unicode(to_pwstr_buffer(value)),
+    };
+    let email_address = recipient
+        .email_address
+        .as_deref()
+        .or(recipient.smtp_address.as_deref());
+
+    let mut props = vec![PropValue {
+        tag: PropTag(sys::PR_RECIPIENT_TYPE),
+        value: PropValueData::Long(u32::from(kind) as i32),
+    }];
+    if let Some(display_name) = recipient.display_name.as_deref().or(email_address) {
+        props.push(unicode(sys::PR_DISPLAY_NAME_W, display_name));
+    }
+    if let Some(email_address) = email_address {
+        let address_type = recipient.address_type.as_deref().unwrap_or("SMTP");
+        props.push(unicode(sys::PR_ADDRTYPE_W, address_type));
+        props.push(unicode(sys::PR_EMAIL_ADDRESS_W, email_address));
+    }
+    if let Some(smtp_address) = recipient.smtp_address.as_deref() {
+        props.push(unicode(sys::PR_SMTP_ADDRESS_W, smtp_address));
+    }
+    if let Some(entry_id) = recipient.entry_id.as_ref() {
+        props.push(PropValue {
+            tag: PropTag(sys::PR_ENTRYID),
+            value: PropValueData::Binary(entry_id.as_bytes()),
+        });
+    }
+    props
+}
+
+/// Build the properties for one row passed to [`Message::modify_recipients`].
+fn recipient_row<'a>(
+    op: RecipOp,
+    recipient: &Recipient,
+    address: &'a ResolvedRecipient,
+) -> Result<Vec<PropValue<'a>>> {
+    let row_id = recipient.row_id.map(|row_id| PropValue {
+        tag: PropTag(sys::PR_ROWID),
+        value: PropValueData::Long(row_id as i32),
+    });
+    match (op, row_id) {
+        (RecipOp::Add, _) => Ok(recipient_props(recipient.kind, address)),
+        (RecipOp::Modify, Some(row_id)) => {
+            let mut props = recipient_props(recipient.kind, address);
+            props.insert(0, row_id);
+            Ok(props)
+        }
+        (RecipOp::Remove, Some(row_id)) => Ok(vec![row_id]),
+        (_, None) => Err(Error::from(sys::MAPI_E_INVALID_PARAMETER)),
+    }
+}
+
+/// Convert a [`sys::PR_RECIPIENT_TYPE`] value to a [`RecipientKind`], ignoring flags like
+/// [`sys::MAPI_SUBMITTED`] and [`sys::MAPI_P1`].
+fn recipient_kind(value: u32) -> Option<RecipientKind> {
+    match value & !(sys::MAPI_SUBMITTED | sys::MAPI_P1) {
+        sys::MAPI_TO => Some(RecipientKind::To),
+        sys::MAPI_CC => Some(RecipientKind::Cc),
+        sys::MAPI_BCC => Some(RecipientKind::Bcc),
+        _ => None,
+    }
+}
+
+/// Call `sync` if the RTF is not `in_sync`, and then `open` the stored [`sys::PR_RTF_COMPRESSED`].
+/// Syncing is only best-effort, since a stale RTF body is still better than none.
+fn read_synced_rtf<S, T>(
+    in_sync: bool,
+    sync: impl FnOnce() -> Result<S>,
+    open: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    if !in_sync {
+        // We read from the message either way, so we don't need to know if it changed.
+        let _ = sync();
+    }
+    open()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_flags() {
+        let flags = MessageFlags::from(sys::MSGFLAG_READ | sys::MSGFLAG_HASATTACH);
+        assert!(flags.is_read());
+        assert!(flags.has_attachments());
+        assert!(!flags.is_unsent());
+        assert!(!flags.is_associated());
+        assert!(!MessageFlags::default().is_read());
+    }
+
+    #[test]
+    fn message_class() {
+        assert!(MessageClass::from("IPM.Note").is_ipm_note());
+        assert!(MessageClass::from("ipm.note.SMIME").is_ipm_note());
+        assert!(!MessageClass::from("IPM.Notes").is_ipm_note());
+        assert!(!MessageClass::from("IPM").is_ipm_note());
+        assert!(MessageClass::from("IPM.Schedule.Meeting.Request").is_meeting_request());
+        assert!(!MessageClass::from("IPM.Schedule.Meeting.Canceled").is_meeting_request());
+        assert!(MessageClass::from("IPM.Appointment").is("IPM"));
+        assert_eq!(MessageClass::from("IPM.Contact").to_string(), "IPM.Contact");
+    }
+
+    #[test]
+    fn rtf_sync_failure() {
+        let mut synced = false;
+        let rtf = read_synced_rtf(
+            false,
+            || -> Result<()> {
+                synced = true;
+                Err(Error::from(sys::MAPI_E_NO_ACCESS))
+            },
+            || Ok(b"{\\rtf1 stored}".to_vec()),
+        )
+        .expect("read failed");
+        assert!(synced);
+        assert_eq!(rtf, b"{\\rtf1 stored}");
+
+        let rtf = read_synced_rtf(
+            true,
+            || -> Result<()> { panic!("sync should be skipped") },
+            || Ok(b"{\\rtf1 stored}".to_vec()),
+        )
+        .expect("read failed");
+        assert_eq!(rtf, b"{\\rtf1 stored}");
+    }
+
+    #[test]
+    fn unicode_body() {
+        let value: Vec<_> = "Zoë\r\n\0"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        assert_eq!(
+            decode_unicode_body(&value).expect("decode failed"),
+            "Zoë\r\n"
+        );
+        assert!(decode_unicode_body(&[0x00, 0xD8]).is_err());
+        assert_eq!(
+            Body::PlainText(String::new()).format(),
+            BodyFormat::PlainText
+        );
+    }
+
+    #[test]
+    fn recipient_rows() {
+        let recipient = Recipient {
+            kind: RecipientKind::Bcc,
+            row_id: Some(3),
+            email_address: Some(String::from("zoe@example.com")),
+            ..Default::default()
+        };
+        let address = ResolvedRecipient::from(&recipient);
+
+        let row = recipient_row(RecipOp::Remove, &recipient, &address).expect("remove failed");
+        assert_eq!(row.len(), 1);
+        assert_eq!(row[0].tag, PropTag(sys::PR_ROWID));
+        assert!(matches!(row[0].value, PropValueData::Long(3)));
+
+        let row = recipient_row(RecipOp::Modify, &recipient, &address).expect("modify failed");
+        assert_eq!(row[0].tag, PropTag(sys::PR_ROWID));
+        assert!(row
+            .iter()
+            .any(|value| value.tag == PropTag(sys::PR_ADDRTYPE_W)
+                && value.value.as_string().as_deref() == Some("SMTP")));
+
+        let row = recipient_row(RecipOp::Add, &recipient, &address).expect("add failed");
+        assert!(row.iter().all(|value| value.tag != PropTag(sys::PR_ROWID)));
+
+        let recipient = Recipient {
+            row_id: None,
+            ..recipient
+        };
+        assert!(matches!(
+            recipient_row(RecipOp::Remove, &recipient, &address),
+            Err(error) if error.code() == sys::MAPI_E_INVALID_PARAMETER
+        ));
+    }
+
+    #[test]
+    fn recipient_from_props() {
+        let display_name = to_pwstr_buffer("Zoë");
+        let values = vec![
+            PropValue {
+                tag: PropTag(sys::PR_ROWID),
+                value: PropValueData::Long(7),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_RECIPIENT_TYPE),
+                value: PropValueData::Long((sys::MAPI_CC | sys::MAPI_SUBMITTED) as i32),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_DISPLAY_NAME_W),
+                value: PropValueData::Unicode(display_name),
+            },
+        ];
+        let recipient = Recipient::from_props(values);
+        assert_eq!(recipient.kind, RecipientKind::Cc);
+        assert_eq!(recipient.row_id, Some(7));
+        assert_eq!(recipient.display_name.as_deref(), Some("Zoë"));
+        assert_eq!(recipient.email_address, None);
+    }
+
+    #[test]
+    fn smtp_recipient_props() {
+        let recipient = ResolvedRecipient {
+            smtp_address: Some(String::from("zoe@example.com")),
+            ..Default::default()
+        };
+        let props = recipient_props(RecipientKind::Cc, &recipient);
+        let find = |tag: u32| {
+            props
+                .iter()
+                .find(|value| value.tag == PropTag(tag))
+                .map(|value| &value.value)
+        };
+
+        assert!(matches!(
+            find(sys::PR_RECIPIENT_TYPE),
+            Some(PropValueData::Long(kind)) if *kind as u32 == sys::MAPI_CC
+        ));
+        assert_eq!(
+            find(sys::PR_DISPLAY_NAME_W).and_then(PropValueData::as_string),
+            Some(String::from("zoe@example.com"))
+        );
+        assert_eq!(
+            find(sys::PR_ADDRTYPE_W).and_then(PropValueData::as_string),
+            Some(String::from("SMTP"))
+        );
+        assert_eq!(
+            find(sys::PR_EMAIL_ADDRESS_W).and_then(PropValueData::as_string),
+            Some(String::from("zoe@example.com"))
+        );
+        assert!(find(sys::PR_ENTRYID).is_none());
+    }
+}
@@ -0,0 +1,346 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`MimeConverter`], [`MimeFlags`], [`MimeEncoding`], and [`MimeSaveFormat`].
+//!
+//! Outlook registers an `IConverterSession` class which converts a MAPI message to an RFC 5322
+//! MIME stream, e.g. an `.eml` file, and back. It is part of the Outlook MAPI implementation, so
+//! this module is only available with the `olmapi32` feature, and [`MimeConverter::new`] fails if
+//! Outlook is not installed.
+//!
+//! The generated bindings do not include `IConverterSession`, so it is declared here with the
+//! layout from the Outlook MAPI reference.
+
+use crate::{AddrBook, MAPIProp, Message, PropertyStream};
+use core::ptr;
+use std::io::{Read, Seek, SeekFrom, Write};
+use windows::Win32::{Foundation::BOOL, System::Com::*};
+use windows_core::*;
+
+/// `CLSID_IConverterSession`, which is missing from [`crate::sys`].
+const CLSID_CONVERTER_SESSION: GUID = GUID::from_u128(0x4e3a7680_b77a_11d0_9da5_00c04fd65685);
+
+/// `CCSF_SMTP`, which is missing from [`crate::sys`]. Both directions require it.
+const CCSF_SMTP: u32 = 0x0002;
+
+/// `CCSF_NOHEADERS`, which is missing from [`crate::sys`].
+const CCSF_NOHEADERS: u32 = 0x0004;
+
+/// `CCSF_USE_TNEF`, which is missing from [`crate::sys`].
+const CCSF_USE_TNEF: u32 = 0x0010;
+
+/// `CCSF_INCLUDE_BCC`, which is missing from [`crate::sys`].
+const CCSF_INCLUDE_BCC: u32 = 0x0020;
+
+/// `CCSF_8BITHEADERS`, which is missing from [`crate::sys`].
+const CCSF_8BITHEADERS: u32 = 0x0040;
+
+/// `CCSF_USE_RTF`, which is missing from [`crate::sys`].
+const CCSF_USE_RTF: u32 = 0x0080;
+
+/// `CCSF_PLAIN_TEXT_ONLY`, which is missing from [`crate::sys`].
+const CCSF_PLAIN_TEXT_ONLY: u32 = 0x1000;
+
+/// `CCSF_NO_MSGID`, which is missing from [`crate::sys`].
+const CCSF_NO_MSGID: u32 = 0x4000;
+
+/// `CCSF_EMBEDDED_MESSAGE`, which is missing from [`crate::sys`].
+const CCSF_EMBEDDED_MESSAGE: u32 = 0x8000;
+
+/// `CCSF_PRESERVE_SOURCE`, which is missing from [`crate::sys`].
+const CCSF_PRESERVE_SOURCE: u32 = 0x0004_0000;
+
+mod iconverter_session {
+    #![allow(dead_code, non_snake_case)]
+
+    use core::ffi::c_void;
+    use windows::Win32::Foundation::BOOL;
+    use windows_core::*;
+
+    /// `IConverterSession` from the Outlook MAPI reference. The placeholder methods keep the
+    /// vtable layout, but they are not implemented.
+    #[windows_interface::interface("4b401570-b77b-11d0-9da5-00c04fd65685")]
+    pub unsafe trait IConverterSession: IUnknown {
+        pub fn SetAdrBook(&self, addr_book: *mut c_void) -> HRESULT;
+        pub fn SetEncoding(&self, encoding: i32) -> HRESULT;
+        pub fn PlaceHolder1(&self) -> HRESULT;
+        pub fn MIMEToMAPI(
+            &self,
+            stream: *mut c_void,
+            message: *mut c_void,
+            src_srv: PCSTR,
+            flags: u32,
+        ) -> HRESULT;
+        pub fn MAPIToMIMEStm(
+            &self,
+            message: *mut c_void,
+            stream: *mut c_void,
+            flags: u32,
+        ) -> HRESULT;
+        pub fn PlaceHolder2(&self) -> HRESULT;
+        pub fn PlaceHolder3(&self) -> HRESULT;
+        pub fn PlaceHolder4(&self) -> HRESULT;
+        pub fn SetTextWrapping(&self, wrap_text: BOOL, wrap_width: u32) -> HRESULT;
+        pub fn SetSaveFormat(&self, save_format: i32) -> HRESULT;
+        pub fn PlaceHolder5(&self) -> HRESULT;
+        pub fn SetCharset(&self, apply: BOOL, charset: *mut c_void, apply_type: i32) -> HRESULT;
+    }
+}
+
+use iconverter_session::IConverterSession;
+
+/// Set of flags that can be passed to [`MimeConverter::message_to_mime`] or
+/// [`MimeConverter::mime_to_message`]. `CCSF_SMTP` is always passed.
+#[derive(Default)]
+pub struct MimeFlags {
+    /// Pass `CCSF_NOHEADERS` to leave out the message headers.
+    pub no_headers: bool,
+
+    /// Pass `CCSF_USE_TNEF` to encode the MAPI properties in a TNEF attachment.
+    pub use_tnef: bool,
+
+    /// Pass `CCSF_INCLUDE_BCC` to write the `Bcc` recipients to the headers.
+    pub include_bcc: bool,
+
+    /// Pass `CCSF_8BITHEADERS` to allow 8-bit characters in the headers instead of encoding them.
+    pub eight_bit_headers: bool,
+
+    /// Pass `CCSF_USE_RTF` to convert an HTML body to RTF in the MAPI message.
+    pub use_rtf: bool,
+
+    /// Pass `CCSF_PLAIN_TEXT_ONLY` to only write the plain text body.
+    pub plain_text_only: bool,
+
+    /// Pass `CCSF_NO_MSGID` to leave out the `Message-ID` header.
+    pub no_message_id: bool,
+
+    /// Pass `CCSF_EMBEDDED_MESSAGE` if the message is an embedded message, e.g. from
+    /// [`crate::Attachment::open_embedded_message`], so the sent and unsent state is preserved.
+    pub embedded_message: bool,
+
+    /// Pass `CCSF_PRESERVE_SOURCE` to keep the original MIME stream when converting to MAPI.
+    pub preserve_source: bool,
+}
+
+impl From<MimeFlags> for u32 {
+    fn from(value: MimeFlags) -> Self {
+        let no_headers = if value.no_headers { CCSF_NOHEADERS } else { 0 };
+        let use_tnef = if value.use_tnef { CCSF_USE_TNEF } else { 0 };
+        let include_bcc = if value.include_bcc {
+            CCSF_INCLUDE_BCC
+        } else {
+            0
+        };
+        let eight_bit_headers = if value.eight_bit_headers {
+            CCSF_8BITHEADERS
+        } else {
+            0
+        };
+        let use_rtf = if value.use_rtf { CCSF_USE_RTF } else { 0 };
+        let plain_text_only = if value.plain_text_only {
+            CCSF_PLAIN_TEXT_ONLY
+        } else {
+            0
+        };
+        let no_message_id = if value.no_message_id {
+            CCSF_NO_MSGID
+        } else {
+            0
+        };
+        let embedded_message = if value.embedded_message {
+            CCSF_EMBEDDED_MESSAGE
+        } else {
+            0
+        };
+        let preserve_source = if value.preserve_source {
+            CCSF_PRESERVE_SOURCE
+        } else {
+            0
+        };
+
+        CCSF_SMTP
+            | no_headers
+            | use_tnef
+            | include_bcc
+            | eight_bit_headers
+            | use_rtf
+            | plain_text_only
+            | no_message_id
+            | embedded_message
+            | preserve_source
+    }
+}
+
+/// Content transfer encoding for the body parts, passed to `IConverterSession::SetEncoding`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MimeEncoding {
+    /// `IET_BASE64`
+    Base64,
+
+    /// `IET_UUENCODE`
+    UuEncode,
+
+    /// `IET_QP`, quoted-printable.
+    QuotedPrintable,
+
+    /// `IET_7BIT`
+    SevenBit,
+
+    /// `IET_8BIT`
+    EightBit,
+}
+
+impl From<MimeEncoding> for i32 {
+    fn from(value: MimeEncoding) -> Self {
+        match value {
+            MimeEncoding::Base64 => 1,
+            MimeEncoding::UuEncode => 2,
+            MimeEncoding::QuotedPrintable => 3,
+            MimeEncoding::SevenBit => 4,
+            MimeEncoding::EightBit => 5,
+        }
+    }
+}
+
+/// MIME format to write, passed to `IConverterSession::SetSaveFormat`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MimeSaveFormat {
+    /// `SAVE_RFC822`, which encodes attachments with uuencode.
+    Rfc822,
+
+    /// `SAVE_RFC1521`, which writes a multipart MIME message.
+    #[default]
+    Rfc1521,
+}
+
+impl From<MimeSaveFormat> for i32 {
+    fn from(value: MimeSaveFormat) -> Self {
+        match value {
+            MimeSaveFormat::Rfc822 => 0,
+            MimeSaveFormat::Rfc1521 => 1,
+        }
+    }
+}
+
+/// Wrapper for an `IConverterSession` object, which converts a [`Message`] to MIME and back.
+///
+/// The settings are sticky, so call [`MimeConverter::set_encoding`] and the other setters before
+/// each conversion that needs something other than the defaults.
+pub struct MimeConverter {
+    session: IConverterSession,
+}
+
+impl MimeConverter {
+    /// Call [`CoCreateInstance`] to create the `IConverterSession` object which Outlook
+    /// registers. COM must already be initialized on this thread.
+    pub fn new() -> Result<Self> {
+        let session =
+            unsafe { CoCreateInstance(&CLSID_CONVERTER_SESSION, None, CLSCTX_INPROC_SERVER)? };
+        Ok(Self { session })
+    }
+
+    /// Call `IConverterSession::SetAdrBook` so recipients can be resolved with the
+    /// [`AddrBook`] while converting.
+    pub fn set_addr_book(&self, addr_book: &AddrBook) -> Result<()> {
+        unsafe { self.session.SetAdrBook(addr_book.addr_book.as_raw()).ok() }
+    }
+
+    /// Call `IConverterSession::SetEncoding` to choose the content transfer encoding.
+    pub fn set_encoding(&self, encoding: MimeEncoding) -> Result<()> {
+        unsafe { self.session.SetEncoding(encoding.into()).ok() }
+    }
+
+    /// Call `IConverterSession::SetSaveFormat` to choose the MIME format to write.
+    pub fn set_save_format(&self, save_format: MimeSaveFormat) -> Result<()> {
+        unsafe { self.session.SetSaveFormat(save_format.into()).ok() }
+    }
+
+    /// Call `IConverterSession::SetTextWrapping` to wrap text body lines at `width` characters,
+    /// or to turn off wrapping if it is [`None`].
+    pub fn set_text_wrapping(&self, width: Option<u32>) -> Result<()> {
+        unsafe {
+            self.session
+                .SetTextWrapping(BOOL::from(width.is_some()), width.unwrap_or_default())
+                .ok()
+        }
+    }
+
+    /// Call `IConverterSession::MAPIToMIMEStm` to write `message` to `stream` as MIME.
+    pub fn message_to_mime(
+        &self,
+        message: &Message,
+        stream: &IStream,
+        flags: MimeFlags,
+    ) -> Result<()> {
+        message.init_epoch().check()?;
+        unsafe {
+            self.session
+                .MAPIToMIMEStm(message.message.as_raw(), stream.as_raw(), flags.into())
+                .ok()
+        }
+    }
+
+    /// Convert `message` to MIME in memory, e.g. to save it as an `.eml` file.
+    pub fn message_to_eml(&self, message: &Message, flags: MimeFlags) -> Result<Vec<u8>> {
+        let mut stream = PropertyStream::memory()?;
+        self.message_to_mime(message, &stream.stream, flags)?;
+        stream.seek(SeekFrom::Start(0))?;
+        let mut eml = Vec::new();
+        stream.read_to_end(&mut eml)?;
+        Ok(eml)
+    }
+
+    /// Call `IConverterSession::MIMEToMAPI` to read the MIME message in `stream` into `message`.
+    /// Call [`crate::MAPIProp::save_changes`] on the `message` afterwards to keep it.
+    pub fn mime_to_message(
+        &self,
+        stream: &IStream,
+        message: &Message,
+        flags: MimeFlags,
+    ) -> Result<()> {
+        message.init_epoch().check()?;
+        unsafe {
+            self.session
+                .MIMEToMAPI(
+                    stream.as_raw(),
+                    message.message.as_raw(),
+                    PCSTR(ptr::null()),
+                    flags.into(),
+                )
+                .ok()
+        }
+    }
+
+    /// Read the MIME message in `eml`, e.g. the contents of an `.eml` file, into `message`. See
+    /// [`MimeConverter::mime_to_message`].
+    pub fn eml_to_message(&self, eml: &[u8], message: &Message, flags: MimeFlags) -> Result<()> {
+        let mut stream = PropertyStream::memory()?;
+        stream.write_all(eml)?;
+        stream.seek(SeekFrom::Start(0))?;
+        self.mime_to_message(&stream.stream, message, flags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mime_flags() {
+        assert_eq!(u32::from(MimeFlags::default()), CCSF_SMTP);
+        assert_eq!(
+            u32::from(MimeFlags {
+                no_headers: true,
+                include_bcc: true,
+                ..Default::default()
+            }),
+            CCSF_SMTP | CCSF_NOHEADERS | CCSF_INCLUDE_BCC
+        );
+    }
+
+    #[test]
+    fn save_format() {
+        assert_eq!(i32::from(MimeSaveFormat::default()), 1);
+        assert_eq!(i32::from(MimeEncoding::QuotedPrintable), 3);
+    }
+}
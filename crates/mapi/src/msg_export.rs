@@ -0,0 +1,240 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`export_msg`], [`export_msg_to_storage`], and [`MsgExportOptions`].
+
+use crate::{mapi_ptr::backend, sys, MAPIProp, Message, PropProblem, PropTag};
+use core::{ffi::c_void, ptr};
+use std::path::Path;
+use windows::Win32::{
+    Foundation::E_POINTER,
+    Storage::Imapi::LPMSGSESS,
+    System::Com::{
+        StructuredStorage::{IStorage, StgCreateDocfile, StgOpenStorage, WriteClassStg},
+        STGC_DEFAULT, STGM_CREATE, STGM_READ, STGM_READWRITE, STGM_SHARE_DENY_WRITE,
+        STGM_SHARE_EXCLUSIVE, STGM_TRANSACTED,
+    },
+};
+use windows_core::*;
+
+/// `CLSID_MailMessage`, which is missing from [`sys`]. Outlook checks for this class on the root
+/// storage of a `.msg` file.
+const CLSID_MAIL_MESSAGE: GUID = GUID::from_u128(0x00020d0b_0000_0000_c000_000000000046);
+
+/// Properties which only make sense in the source store, or which the store computes, and should
+/// not be copied to or from a `.msg` file.
+pub(crate) const EXCLUDED_PROPS: [PropTag; 10] = [
+    PropTag(sys::PR_ENTRYID),
+    PropTag(sys::PR_PARENT_ENTRYID),
+    PropTag(sys::PR_RECORD_KEY),
+    PropTag(sys::PR_STORE_ENTRYID),
+    PropTag(sys::PR_STORE_RECORD_KEY),
+    PropTag(sys::PR_REPLICA_VERSION),
+    PropTag(sys::PR_MESSAGE_SIZE),
+    PropTag(sys::PR_DISPLAY_TO_W),
+    PropTag(sys::PR_DISPLAY_CC_W),
+    PropTag(sys::PR_DISPLAY_BCC_W),
+];
+
+/// Options for [`export_msg`] and [`export_msg_to_storage`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MsgExportOptions {
+    /// Write a Unicode `.msg` file, which is what Outlook 2003 and later save. If `false`, string
+    /// properties are written in the ANSI format, which older readers expect.
+    pub unicode: bool,
+}
+
+impl Default for MsgExportOptions {
+    fn default() -> Self {
+        Self { unicode: true }
+    }
+}
+
+/// Create a new `.msg` file at `path`, replacing any existing file, and copy the properties,
+/// recipients, and attachments of `message` into it. See [`export_msg_to_storage`].
+pub fn export_msg<P>(
+    message: &Message,
+    path: P,
+    options: &MsgExportOptions,
+) -> Result<Vec<PropProblem>>
+where
+    P: AsRef<Path>,
+{
+    let path = HSTRING::from(path.as_ref());
+    let storage = unsafe {
+        StgCreateDocfile(
+            &path,
+            STGM_CREATE | STGM_READWRITE | STGM_SHARE_EXCLUSIVE | STGM_TRANSACTED,
+            None,
+        )?
+    };
+    export_msg_to_storage(message, &storage, options)
+}
+
+/// Write `message` to an empty `storage` in the `.msg` format, e.g. to embed it in another
+/// compound file, and commit the `storage`.
+///
+/// Properties which could not be copied are returned as [`PropProblem`] entries, the same as
+/// [`MAPIProp::copy_to`].
+pub fn export_msg_to_storage(
+    message: &Message,
+    storage: &IStorage,
+    options: &MsgExportOptions,
+) -> Result<Vec<PropProblem>> {
+    message.init_epoch().check()?;
+    unsafe {
+        WriteClassStg(storage, &CLSID_MAIL_MESSAGE)?;
+    }
+
+    let destination = MsgFile::open(storage, options.unicode)?;
+    let problems = message.copy_to(&destination.message, &EXCLUDED_PROPS, Default::default())?;
+    destination.message.save_changes(Default::default())?;
+    drop(destination);
+    unsafe {
+        storage.Commit(STGC_DEFAULT.0 as u32)?;
+    }
+    Ok(problems)
+}
+
+/// Open an existing `.msg` file at `path` for reading.
+pub(crate) fn open_msg_storage(path: &Path) -> Result<IStorage> {
+    let path = HSTRING::from(path);
+    unsafe {
+        StgOpenStorage(
+            &path,
+            None,
+            STGM_READ | STGM_SHARE_DENY_WRITE | STGM_TRANSACTED,
+            None,
+            0,
+        )
+    }
+}
+
+/// [`Message`] on top of an [`IStorage`] in the `.msg` format, along with the [`MsgSession`]
+/// which has to outlive it. The fields are dropped in order, so the message is released before
+/// the session is closed.
+pub(crate) struct MsgFile {
+    pub message: Message,
+    _session: MsgSession,
+}
+
+impl MsgFile {
+    /// Call [`sys::OpenIMsgOnIStg`] in a new [`MsgSession`]. The `unicode` flag only matters
+    /// when writing to an empty `storage`.
+    pub fn open(storage: &IStorage, unicode: bool) -> Result<Self> {
+        let session = MsgSession::open()?;
+        let message = Message::new(session.open_message(storage, unicode)?);
+        Ok(Self {
+            message,
+            _session: session,
+        })
+    }
+}
+
+/// Hold on to the [`LPMSGSESS`] from [`sys::OpenIMsgSession`], and call
+/// [`sys::CloseIMsgSession`] when it is dropped. Any messages opened in the session must be
+/// released first.
+struct MsgSession(LPMSGSESS);
+
+impl MsgSession {
+    fn open() -> Result<Self> {
+        let mut session = LPMSGSESS::default();
+        unsafe {
+            let malloc = sys::MAPIGetDefaultMalloc().ok_or_else(|| Error::from(E_POINTER))?;
+            HRESULT(sys::OpenIMsgSession(&malloc, 0, &mut session)).ok()?;
+        }
+        Ok(Self(session))
+    }
+
+    /// Call [`sys::OpenIMsgOnIStg`] to create a [`sys::IMessage`] which reads from and writes to
+    /// `storage`. If `unicode` is set, this also sets [`sys::STORE_UNICODE_OK`] in
+    /// [`sys::PR_STORE_SUPPORT_MASK`], so [`MAPIProp::copy_to`] keeps the Unicode strings instead
+    /// of converting them to ANSI.
+    fn open_message(&self, storage: &IStorage, unicode: bool) -> Result<sys::IMessage> {
+        let flags = if unicode { sys::MAPI_UNICODE } else { 0 };
+        let mut message = None;
+        unsafe {
+            let malloc = sys::MAPIGetDefaultMalloc().ok_or_else(|| Error::from(E_POINTER))?;
+            HRESULT(sys::OpenIMsgOnIStg(
+                self.0,
+                Some(allocate_buffer),
+                Some(allocate_more),
+                Some(free_buffer),
+                &malloc,
+                ptr::null_mut(),
+                storage,
+                ptr::null_mut(),
+                0,
+                flags,
+                &mut message,
+            ))
+            .ok()?;
+        }
+        let message = message.ok_or_else(|| Error::from(E_POINTER))?;
+        if unicode {
+            let mut support_mask = sys::SPropValue {
+                ulPropTag: sys::PR_STORE_SUPPORT_MASK,
+                Value: sys::__UPV {
+                    l: sys::STORE_UNICODE_OK as i32,
+                },
+                ..Default::default()
+            };
+            unsafe {
+                sys::HrSetOneProp(&*message, &mut support_mask)?;
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl Drop for MsgSession {
+    fn drop(&mut self) {
+        unsafe {
+            sys::CloseIMsgSession(self.0);
+        }
+    }
+}
+
+/// [`sys::LPALLOCATEBUFFER`] callback for [`sys::OpenIMsgOnIStg`].
+unsafe extern "system" fn allocate_buffer(byte_count: u32, alloc: *mut *mut c_void) -> i32 {
+    backend::allocate_buffer(byte_count, alloc)
+}
+
+/// [`sys::LPALLOCATEMORE`] callback for [`sys::OpenIMsgOnIStg`].
+unsafe extern "system" fn allocate_more(
+    byte_count: u32,
+    root: *mut c_void,
+    alloc: *mut *mut c_void,
+) -> i32 {
+    backend::allocate_more(byte_count, root, alloc)
+}
+
+/// [`sys::LPFREEBUFFER`] callback for [`sys::OpenIMsgOnIStg`].
+unsafe extern "system" fn free_buffer(alloc: *mut c_void) -> u32 {
+    backend::free_buffer(alloc);
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocator_callbacks() {
+        let mut root = ptr::null_mut();
+        let mut chained = ptr::null_mut();
+        unsafe {
+            assert_eq!(allocate_buffer(16, &mut root), 0);
+            assert_eq!(allocate_more(32, root, &mut chained), 0);
+            assert!(backend::is_allocated(root));
+            assert!(!chained.is_null());
+            assert_eq!(free_buffer(root), 0);
+        }
+        assert!(!backend::is_allocated(root));
+    }
+
+    #[test]
+    fn default_options() {
+        assert!(MsgExportOptions::default().unicode);
+    }
+}
@@ -0,0 +1,897 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`MsgStore`], [`SpecialFolder`], [`StoreCapabilities`], and [`QuotaInfo`].
+
+use crate::{
+    mapi_error::with_last_error, open_policy::probe_object, sys, to_pwstr_buffer, AdviseConnection,
+    CompareEntryIds, EntryId, EventMask, Folder, InitEpoch, Logon, MAPIOutParam, MAPIProp,
+    MarshalToThread, Marshaled, Message, Notification, NotificationSink, ObjectKind,
+    ObjectRegistration, OpenPolicy, Outbox, PropTag, PropValue, PropValueData, RelOp, Restriction,
+    StoreDisconnected, TableFlags,
+};
+use core::{ptr, slice};
+use std::sync::OnceLock;
+use windows::Win32::Foundation::*;
+use windows_core::*;
+
+/// Well-known folders which can be opened with [`MsgStore::special_folder`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SpecialFolder {
+    /// Root of the folders visible to the user, from [`sys::PR_IPM_SUBTREE_ENTRYID`].
+    IpmSubtree,
+
+    /// Default receive folder, from [`sys::IMsgStore::GetReceiveFolder`].
+    Inbox,
+
+    /// From [`sys::PR_IPM_OUTBOX_ENTRYID`].
+    Outbox,
+
+    /// From [`sys::PR_IPM_SENTMAIL_ENTRYID`].
+    SentItems,
+
+    /// From [`sys::PR_IPM_WASTEBASKET_ENTRYID`].
+    DeletedItems,
+
+    /// From [`sys::PR_IPM_APPOINTMENT_ENTRYID`] on the [`SpecialFolder::Inbox`].
+    Calendar,
+
+    /// From [`sys::PR_IPM_CONTACT_ENTRYID`] on the [`SpecialFolder::Inbox`].
+    Contacts,
+
+    /// From [`sys::PR_IPM_DRAFTS_ENTRYID`] on the [`SpecialFolder::Inbox`].
+    Drafts,
+
+    /// From [`sys::PR_IPM_TASK_ENTRYID`] on the [`SpecialFolder::Inbox`].
+    Tasks,
+
+    /// Root of the search folders, from [`sys::PR_FINDER_ENTRYID`]. Create persistent searches
+    /// in it with [`crate::Folder::create_search_folder`].
+    SearchRoot,
+}
+
+/// Capabilities of a store, decoded from the [`sys::PR_STORE_SUPPORT_MASK`] bits returned by
+/// [`MsgStore::capabilities`].
+///
+/// Exchange mailboxes, PST files, and delegate or public folder stores each support a different
+/// subset of MAPI, so code which should work with any of them can check these before relying on
+/// a feature like [`sys::IMAPIFolder::SetSearchCriteria`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct StoreCapabilities(pub u32);
+
+impl StoreCapabilities {
+    /// Test if all of the bits in `mask` are set.
+    pub fn contains(self, mask: u32) -> bool {
+        self.0 & mask == mask
+    }
+
+    /// [`sys::STORE_UNICODE_OK`]: The store supports [`sys::PT_UNICODE`] properties.
+    pub fn supports_unicode(self) -> bool {
+        self.contains(sys::STORE_UNICODE_OK)
+    }
+
+    /// [`sys::STORE_SEARCH_OK`]: The store supports search folders.
+    pub fn supports_search(self) -> bool {
+        self.contains(sys::STORE_SEARCH_OK)
+    }
+
+    /// [`sys::STORE_READONLY`]: All of the objects in the store are read-only.
+    pub fn is_read_only(self) -> bool {
+        self.contains(sys::STORE_READONLY)
+    }
+
+    /// [`sys::STORE_MODIFY_OK`]: Existing messages can be modified.
+    pub fn supports_modify(self) -> bool {
+        self.contains(sys::STORE_MODIFY_OK)
+    }
+
+    /// [`sys::STORE_CREATE_OK`]: New messages can be created.
+    pub fn supports_create(self) -> bool {
+        self.contains(sys::STORE_CREATE_OK)
+    }
+
+    /// [`sys::STORE_ATTACH_OK`]: Messages can have attachments.
+    pub fn supports_attachments(self) -> bool {
+        self.contains(sys::STORE_ATTACH_OK)
+    }
+
+    /// [`sys::STORE_SUBMIT_OK`]: Messages can be sent with [`crate::Message::submit`].
+    pub fn supports_submit(self) -> bool {
+        self.contains(sys::STORE_SUBMIT_OK)
+    }
+
+    /// [`sys::STORE_NOTIFY_OK`]: The store sends notifications to [`MsgStore::advise`].
+    pub fn supports_notify(self) -> bool {
+        self.contains(sys::STORE_NOTIFY_OK)
+    }
+
+    /// [`sys::STORE_MV_PROPS_OK`]: The store supports multi-value properties.
+    pub fn supports_multi_value(self) -> bool {
+        self.contains(sys::STORE_MV_PROPS_OK)
+    }
+
+    /// [`sys::STORE_RTF_OK`]: The store keeps [`sys::PR_BODY_W`] and
+    /// [`sys::PR_RTF_COMPRESSED`] in sync itself.
+    pub fn supports_rtf(self) -> bool {
+        self.contains(sys::STORE_RTF_OK)
+    }
+
+    /// [`sys::STORE_RESTRICTION_OK`]: Tables support [`crate::Table::restrict`].
+    pub fn supports_restriction(self) -> bool {
+        self.contains(sys::STORE_RESTRICTION_OK)
+    }
+
+    /// [`sys::STORE_SORT_OK`]: Tables support [`crate::Table::sort`].
+    pub fn supports_sort(self) -> bool {
+        self.contains(sys::STORE_SORT_OK)
+    }
+
+    /// [`sys::STORE_PUBLIC_FOLDERS`]: This is a public folder store.
+    pub fn is_public_folders(self) -> bool {
+        self.contains(sys::STORE_PUBLIC_FOLDERS)
+    }
+
+    /// [`sys::STORE_ENTRYID_UNIQUE`]: Entry IDs are never reused, even after the object is
+    /// deleted.
+    pub fn has_unique_entry_ids(self) -> bool {
+        self.contains(sys::STORE_ENTRYID_UNIQUE)
+    }
+}
+
+impl From<u32> for StoreCapabilities {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+/// Size of a mailbox and its quotas, returned from [`MsgStore::quota_info`]. The quotas are in
+/// kilobytes, like the properties they come from, and [`None`] means the store does not set
+/// that quota.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QuotaInfo {
+    /// Total size of the store in bytes, from [`sys::PR_MESSAGE_SIZE_EXTENDED`], or
+    /// [`sys::PR_MESSAGE_SIZE`] if the store does not support the 64-bit property.
+    pub mailbox_size: Option<u64>,
+
+    /// [`sys::PR_STORAGE_QUOTA_LIMIT`], where the user starts getting warnings.
+    pub storage_quota_limit: Option<u32>,
+
+    /// [`sys::PR_PROHIBIT_SEND_QUOTA`], where the user can no longer send mail.
+    pub prohibit_send_quota: Option<u32>,
+
+    /// [`sys::PR_PROHIBIT_RECEIVE_QUOTA`], where the mailbox stops accepting new mail.
+    pub prohibit_receive_quota: Option<u32>,
+}
+
+impl QuotaInfo {
+    /// Properties which [`QuotaInfo::from_props`] expects on the store.
+    pub const PROPS: [PropTag; 5] = [
+        PropTag(sys::PR_MESSAGE_SIZE_EXTENDED),
+        PropTag(sys::PR_MESSAGE_SIZE),
+        PropTag(sys::PR_STORAGE_QUOTA_LIMIT),
+        PropTag(sys::PR_PROHIBIT_SEND_QUOTA),
+        PropTag(sys::PR_PROHIBIT_RECEIVE_QUOTA),
+    ];
+
+    /// Read the [`QuotaInfo::PROPS`] from any set of properties. Missing properties and errors
+    /// are left as [`None`].
+    pub fn from_props<'a>(values: impl IntoIterator<Item = PropValue<'a>>) -> Self {
+        let mut info = Self::default();
+        let mut small_size = None;
+        for value in values {
+            match (value.tag.0, value.value) {
+                (sys::PR_MESSAGE_SIZE_EXTENDED, PropValueData::LargeInteger(size)) => {
+                    info.mailbox_size = u64::try_from(size).ok()
+                }
+                (sys::PR_MESSAGE_SIZE, PropValueData::Long(size)) => {
+                    small_size = Some(u64::from(size as u32))
+                }
+                (sys::PR_STORAGE_QUOTA_LIMIT, PropValueData::Long(quota)) => {
+                    info.storage_quota_limit = Some(quota as u32)
+                }
+                (sys::PR_PROHIBIT_SEND_QUOTA, PropValueData::Long(quota)) => {
+                    info.prohibit_send_quota = Some(quota as u32)
+                }
+                (sys::PR_PROHIBIT_RECEIVE_QUOTA, PropValueData::Long(quota)) => {
+                    info.prohibit_receive_quota = Some(quota as u32)
+                }
+                _ => {}
+            }
+        }
+        info.mailbox_size = info.mailbox_size.or(small_size);
+        info
+    }
+
+    /// Check if the mailbox has reached [`QuotaInfo::storage_quota_limit`].
+    pub fn is_over_warning(&self) -> bool {
+        self.exceeds(self.storage_quota_limit)
+    }
+
+    /// Check if the mailbox has reached [`QuotaInfo::prohibit_send_quota`].
+    pub fn is_send_prohibited(&self) -> bool {
+        self.exceeds(self.prohibit_send_quota)
+    }
+
+    /// Check if the mailbox has reached [`QuotaInfo::prohibit_receive_quota`].
+    pub fn is_receive_prohibited(&self) -> bool {
+        self.exceeds(self.prohibit_receive_quota)
+    }
+
+    /// Compare [`QuotaInfo::mailbox_size`] to a quota in kilobytes.
+    fn exceeds(&self, quota: Option<u32>) -> bool {
+        match (self.mailbox_size, quota) {
+            (Some(size), Some(quota)) => size >= u64::from(quota) * 1024,
+            _ => false,
+        }
+    }
+}
+
+/// Where to look for the entry ID of a [`SpecialFolder`].
+#[derive(Debug, PartialEq, Eq)]
+enum FolderLocation {
+    /// Call [`sys::IMsgStore::GetReceiveFolder`].
+    ReceiveFolder,
+
+    /// Read a property on the [`MsgStore`].
+    StoreProp(u32),
+
+    /// Read a property on the [`SpecialFolder::Inbox`], or the root folder of the store if the
+    /// inbox doesn't have it.
+    InboxProp(u32),
+}
+
+impl From<SpecialFolder> for FolderLocation {
+    fn from(value: SpecialFolder) -> Self {
+        match value {
+            SpecialFolder::IpmSubtree => Self::StoreProp(sys::PR_IPM_SUBTREE_ENTRYID),
+            SpecialFolder::Inbox => Self::ReceiveFolder,
+            SpecialFolder::Outbox => Self::StoreProp(sys::PR_IPM_OUTBOX_ENTRYID),
+            SpecialFolder::SentItems => Self::StoreProp(sys::PR_IPM_SENTMAIL_ENTRYID),
+            SpecialFolder::DeletedItems => Self::StoreProp(sys::PR_IPM_WASTEBASKET_ENTRYID),
+            SpecialFolder::Calendar => Self::InboxProp(sys::PR_IPM_APPOINTMENT_ENTRYID),
+            SpecialFolder::Contacts => Self::InboxProp(sys::PR_IPM_CONTACT_ENTRYID),
+            SpecialFolder::Drafts => Self::InboxProp(sys::PR_IPM_DRAFTS_ENTRYID),
+            SpecialFolder::Tasks => Self::InboxProp(sys::PR_IPM_TASK_ENTRYID),
+            SpecialFolder::SearchRoot => Self::StoreProp(sys::PR_FINDER_ENTRYID),
+        }
+    }
+}
+
+/// Hold on to a [`sys::IMsgStore`] and expose the store level operations without `unsafe`.
+pub struct MsgStore {
+    /// Access the [`sys::IMsgStore`].
+    pub store: sys::IMsgStore,
+
+    epoch: InitEpoch,
+    registration: ObjectRegistration,
+
+    /// Cached [`sys::PR_ENTRYID`] of the store, which [`MsgStore::reopen`] needs after the store
+    /// is disconnected.
+    entry_id: OnceLock<EntryId>,
+}
+
+impl MsgStore {
+    /// Wrap a [`sys::IMsgStore`] returned from one of the [`sys`] interface methods.
+    pub fn new(store: sys::IMsgStore) -> Self {
+        Self {
+            store,
+            epoch: InitEpoch::current(),
+            registration: ObjectRegistration::new_store(),
+            entry_id: OnceLock::new(),
+        }
+    }
+
+    /// Wrap a [`sys::IMsgStore`] which was opened with its [`sys::PR_ENTRYID`].
+    pub(crate) fn with_entry_id(store: sys::IMsgStore, entry_id: &EntryId) -> Self {
+        let store = Self::new(store);
+        let _ = store.entry_id.set(entry_id.clone());
+        store
+    }
+
+    /// Make sure MAPI is still initialized and the store is still connected.
+    fn check(&self) -> Result<()> {
+        self.epoch.check()?;
+        Ok(self.registration.check_connected()?)
+    }
+
+    /// Open the store with [`sys::PR_DEFAULT_STORE`] set in the session's message store table,
+    /// using [`Logon::message_stores`] and [`Logon::open_msg_store`].
+    pub fn default_store(logon: &Logon) -> Result<Self> {
+        let default = logon
+            .message_stores()?
+            .find(|store| store.is_default)
+            .ok_or_else(|| Error::from(sys::MAPI_E_NOT_FOUND))?;
+        logon.open_msg_store(&default.entry_id)
+    }
+
+    /// Call [`sys::IMsgStore::Advise`] to register for notifications about any object in the
+    /// store. Each [`Notification`] is passed to `callback`, possibly on another thread, until the
+    /// [`AdviseConnection`] is dropped. See [`NotificationSink`] for how `callback` is called.
+    pub fn advise<F>(&self, event_mask: EventMask, callback: F) -> Result<AdviseConnection>
+    where
+        F: Fn(Notification) + Send + Sync + 'static,
+    {
+        self.check()?;
+        let sink = NotificationSink::create(callback);
+        let mut connection = 0;
+        unsafe {
+            self.store
+                .Advise(
+                    0,
+                    ptr::null_mut(),
+                    event_mask.into(),
+                    &sink,
+                    &mut connection,
+                )
+                .map_err(|error| with_last_error(self.mapi_prop(), error))?;
+        }
+        Ok(AdviseConnection::msg_store(
+            self.store.clone(),
+            connection,
+            self.epoch,
+        ))
+    }
+
+    /// Call [`MsgStore::advise`] to register for [`sys::fnevCriticalError`] notifications, and
+    /// disconnect the store when one of them has a [`StoreDisconnected::is_disconnect_error`].
+    ///
+    /// Once the store is disconnected, this [`MsgStore`] and every [`Folder`], [`Message`],
+    /// [`crate::Table`], etc. opened from it return [`sys::MAPI_E_END_OF_SESSION`] with a
+    /// [`StoreDisconnected`] message instead of calling into the provider. Use
+    /// [`MsgStore::reopen`] to get a new connection to the store. The notifications stop when the
+    /// [`AdviseConnection`] is dropped.
+    pub fn watch_disconnect(&self) -> Result<AdviseConnection> {
+        self.check()?;
+
+        // Cache the entry ID now, since it can't be read after the store is disconnected.
+        self.entry_id()?;
+
+        let connection = self.registration.connection().cloned();
+        self.advise(
+            EventMask {
+                critical_error: true,
+                ..Default::default()
+            },
+            move |notification| match (&connection, notification) {
+                (Some(connection), Notification::CriticalError { error, .. })
+                    if StoreDisconnected::is_disconnect_error(error) =>
+                {
+                    connection.disconnect(error)
+                }
+                _ => {}
+            },
+        )
+    }
+
+    /// Return [`StoreDisconnected`] if [`MsgStore::watch_disconnect`] has seen a notification
+    /// which disconnected the store.
+    pub fn check_connected(&self) -> core::result::Result<(), StoreDisconnected> {
+        self.registration.check_connected()
+    }
+
+    /// Get the [`sys::PR_ENTRYID`] of the store, which is cached after the first call.
+    pub fn entry_id(&self) -> Result<EntryId> {
+        if let Some(entry_id) = self.entry_id.get() {
+            return Ok(entry_id.clone());
+        }
+        let entry_id = entry_id_prop(self, sys::PR_ENTRYID)?;
+        Ok(self.entry_id.get_or_init(|| entry_id).clone())
+    }
+
+    /// Read [`sys::PR_STORE_SUPPORT_MASK`] and decode it as [`StoreCapabilities`]. Returns
+    /// [`sys::MAPI_E_NOT_FOUND`] if the store does not set it.
+    pub fn capabilities(&self) -> Result<StoreCapabilities> {
+        let props = self.get_props(&[PropTag(sys::PR_STORE_SUPPORT_MASK)])?;
+        let value = props.iter().next().map(|value| value.value);
+        match value {
+            Some(PropValueData::Long(value)) => Ok(StoreCapabilities(value as u32)),
+            Some(PropValueData::Error(error)) => Err(Error::from(error)),
+            _ => Err(Error::from(sys::MAPI_E_NOT_FOUND)),
+        }
+    }
+
+    /// Read the mailbox size and quota properties into a [`QuotaInfo`]. Stores which do not
+    /// enforce quotas, e.g. PST files, just leave them as [`None`].
+    pub fn quota_info(&self) -> Result<QuotaInfo> {
+        let props = self.get_props(&QuotaInfo::PROPS)?;
+        Ok(QuotaInfo::from_props(props.iter()))
+    }
+
+    /// Replace the [`sys::IMsgStore`] with a new one from [`Logon::open_msg_store`], e.g. after
+    /// the store was disconnected.
+    ///
+    /// Only the [`MsgStore`] itself is reopened. Everything opened from the old store stays
+    /// disconnected, since the objects it wraps are dead, so open them again from this store by
+    /// their entry IDs. Call [`MsgStore::watch_disconnect`] again to watch the new connection.
+    /// This returns [`sys::MAPI_E_END_OF_SESSION`] if the store was disconnected before its
+    /// [`MsgStore::entry_id`] was cached.
+    pub fn reopen(&mut self, logon: &Logon) -> Result<()> {
+        let entry_id = self.entry_id()?;
+        *self = logon.open_msg_store(&entry_id)?;
+        Ok(())
+    }
+
+    /// Call [`sys::IMsgStore::OpenEntry`] with [`sys::MAPI_BEST_ACCESS`] to open a folder using its
+    /// [`sys::PR_ENTRYID`].
+    ///
+    /// If the entry ID refers to something other than a folder, this will return
+    /// [`E_NOINTERFACE`].
+    pub fn open_folder(&self, entry_id: &EntryId) -> Result<Folder> {
+        self.open_folder_with_policy(entry_id, OpenPolicy::Immediate)
+    }
+
+    /// Same as [`MsgStore::open_folder`], but choose how to handle
+    /// [`sys::MAPI_DEFERRED_ERRORS`] with an [`OpenPolicy`].
+    pub fn open_folder_with_policy(
+        &self,
+        entry_id: &EntryId,
+        policy: OpenPolicy,
+    ) -> Result<Folder> {
+        let unknown = self.open_entry(entry_id, sys::MAPI_FOLDER, policy)?;
+        Ok(Folder::with_registration(
+            unknown.cast()?,
+            self.registration.child(ObjectKind::Folder),
+        ))
+    }
+
+    /// Call [`sys::IMsgStore::OpenEntry`] with [`sys::MAPI_BEST_ACCESS`] to open a message using
+    /// its [`sys::PR_ENTRYID`].
+    ///
+    /// If the entry ID refers to something other than a message, this will return
+    /// [`E_NOINTERFACE`].
+    pub fn open_message(&self, entry_id: &EntryId) -> Result<Message> {
+        self.open_message_with_policy(entry_id, OpenPolicy::Immediate)
+    }
+
+    /// Same as [`MsgStore::open_message`], but choose how to handle
+    /// [`sys::MAPI_DEFERRED_ERRORS`] with an [`OpenPolicy`].
+    pub fn open_message_with_policy(
+        &self,
+        entry_id: &EntryId,
+        policy: OpenPolicy,
+    ) -> Result<Message> {
+        let unknown = self.open_entry(entry_id, sys::MAPI_MESSAGE, policy)?;
+        Ok(Message::with_registration(
+            unknown.cast()?,
+            self.registration.child(ObjectKind::Message),
+        ))
+    }
+
+    /// Open the root folder of the store, which is the parent of the
+    /// [`SpecialFolder::IpmSubtree`] and the other folders which are not visible to the user.
+    pub fn root_folder(&self) -> Result<Folder> {
+        self.open_folder(&EntryId::default())
+    }
+
+    /// Open the local free/busy message, using the second entry ID in
+    /// `PidTagFreeBusyEntryIds` on the [`MsgStore::root_folder`]. Read and publish the free/busy
+    /// data on it with [`crate::FreeBusyData::read`] and [`crate::FreeBusyData::publish`]. Stores without one,
+    /// e.g. a PST file, return [`sys::MAPI_E_NOT_FOUND`].
+    pub fn local_free_busy_message(&self) -> Result<Message> {
+        /// `PidTagFreeBusyEntryIds`, which is missing from [`sys`].
+        const PR_FREEBUSY_ENTRYIDS: u32 = 0x36E4_1102;
+
+        let props = self
+            .root_folder()?
+            .get_props(&[PropTag(PR_FREEBUSY_ENTRYIDS)])?;
+        let entry_id = props
+            .iter()
+            .next()
+            .and_then(|value| match value.value {
+                PropValueData::BinaryArray(entry_ids) => entry_ids
+                    .get(1)
+                    .filter(|entry_id| entry_id.cb > 0 && !entry_id.lpb.is_null())
+                    .map(|entry_id| unsafe {
+                        EntryId::from(slice::from_raw_parts(entry_id.lpb, entry_id.cb as usize))
+                    }),
+                _ => None,
+            })
+            .ok_or_else(|| Error::from(sys::MAPI_E_NOT_FOUND))?;
+        self.open_message(&entry_id)
+    }
+
+    /// Open the default receive folder, i.e. [`SpecialFolder::Inbox`].
+    pub fn inbox(&self) -> Result<Folder> {
+        self.special_folder(SpecialFolder::Inbox)
+    }
+
+    /// Open the [`SpecialFolder::Outbox`] as an [`Outbox`], which creates messages that are ready
+    /// to send with [`Message::submit`].
+    pub fn outbox(&self) -> Result<Outbox> {
+        Outbox::open(self)
+    }
+
+    /// Look up the entry ID of a [`SpecialFolder`] with [`MsgStore::special_folder_id`] and open
+    /// it with [`MsgStore::open_folder`].
+    pub fn special_folder(&self, folder: SpecialFolder) -> Result<Folder> {
+        self.open_folder(&self.special_folder_id(folder)?)
+    }
+
+    /// Get the [`sys::PR_ENTRYID`] of a [`SpecialFolder`].
+    ///
+    /// The [`SpecialFolder::Inbox`] comes from [`sys::IMsgStore::GetReceiveFolder`] with the
+    /// default message class. Most of the others are properties on the store, but the entry IDs
+    /// for folders like the [`SpecialFolder::Calendar`] are properties on the inbox, or on the
+    /// root folder of the store if the inbox doesn't have them. If the folder doesn't exist in
+    /// this store, e.g. a PST file without a calendar, this will return
+    /// [`sys::MAPI_E_NOT_FOUND`].
+    pub fn special_folder_id(&self, folder: SpecialFolder) -> Result<EntryId> {
+        self.check()?;
+        match FolderLocation::from(folder) {
+            FolderLocation::ReceiveFolder => self.receive_folder_id(),
+            FolderLocation::StoreProp(tag) => entry_id_prop(self, tag),
+            FolderLocation::InboxProp(tag) => {
+                match entry_id_prop(&self.inbox()?, tag) {
+                    Err(err) if err.code() == sys::MAPI_E_NOT_FOUND => {}
+                    result => return result,
+                }
+                entry_id_prop(&self.root_folder()?, tag)
+            }
+        }
+    }
+
+    /// Find every message in the IPM subtree of the store with a matching
+    /// [`sys::PR_INTERNET_MESSAGE_ID_W`], e.g. the `Message-ID` header of an SMTP message.
+    ///
+    /// This restricts the contents table of the root of the IPM subtree and each of the folders
+    /// under it. The same message may have been copied to more than one folder, so there may be
+    /// more than one match. Search folders are skipped, since their contents are already in
+    /// another folder, and so are subfolders which cannot be opened or searched, e.g. because
+    /// the user does not have access to them.
+    pub fn find_by_internet_message_id(&self, id: &str) -> Result<Vec<Message>> {
+        let root = self.special_folder(SpecialFolder::IpmSubtree)?;
+
+        let restriction = Restriction::And(vec![
+            Restriction::Exist(PropTag(sys::PR_INTERNET_MESSAGE_ID_W)),
+            Restriction::Property {
+                relop: RelOp::Equal,
+                value: PropValue {
+                    tag: PropTag(sys::PR_INTERNET_MESSAGE_ID_W),
+                    value: PropValueData::Unicode(to_pwstr_buffer(id)),
+                },
+            },
+        ]);
+        let mut entry_ids = find_entry_ids(&root, &restriction)?;
+
+        let subfolders = root
+            .open_hierarchy_table(TableFlags {
+                convenient_depth: true,
+                ..Default::default()
+            })?
+            .query_all_rows(
+                &[PropTag(sys::PR_ENTRYID), PropTag(sys::PR_FOLDER_TYPE)],
+                None,
+                None,
+            )?;
+        for row in subfolders.iter() {
+            let Some(folder_id) = searchable_folder_id(row.iter()) else {
+                continue;
+            };
+            let Ok(found) = root
+                .open_subfolder(&folder_id)
+                .and_then(|folder| find_entry_ids(&folder, &restriction))
+            else {
+                continue;
+            };
+            entry_ids.extend(found);
+        }
+
+        entry_ids
+            .iter()
+            .map(|entry_id| self.open_message(entry_id))
+            .collect()
+    }
+
+    fn receive_folder_id(&self) -> Result<EntryId> {
+        let mut count = 0;
+        let mut entry_id = MAPIOutParam::<u8>::new();
+        unsafe {
+            self.store
+                .GetReceiveFolder(
+                    ptr::null_mut(),
+                    0,
+                    &mut count,
+                    entry_id.as_mut_ptr() as *mut *mut sys::ENTRYID,
+                    ptr::null_mut(),
+                )
+                .map_err(|error| with_last_error(self.mapi_prop(), error))?;
+            let entry_id = entry_id
+                .as_mut_slice(count as usize)
+                .ok_or_else(|| Error::from(sys::MAPI_E_NOT_FOUND))?;
+            Ok(EntryId::from(&*entry_id))
+        }
+    }
+
+    fn open_entry(
+        &self,
+        entry_id: &EntryId,
+        expected_type: u32,
+        policy: OpenPolicy,
+    ) -> Result<IUnknown> {
+        self.check()?;
+        policy.open(
+            |flags| {
+                let mut obj_type = 0;
+                let mut unknown = None;
+                unsafe {
+                    self.store
+                        .OpenEntry(
+                            u32::try_from(entry_id.len())?,
+                            entry_id.as_ptr() as *mut _,
+                            ptr::null_mut(),
+                            sys::MAPI_BEST_ACCESS | flags,
+                            &mut obj_type,
+                            &mut unknown,
+                        )
+                        .map_err(|error| with_last_error(self.mapi_prop(), error))?;
+                }
+                if obj_type != expected_type {
+                    return Err(Error::from(E_NOINTERFACE));
+                }
+                unknown.ok_or_else(|| Error::from(E_POINTER))
+            },
+            |unknown| probe_object(&unknown.cast()?),
+        )
+    }
+}
+
+/// Read a [`sys::PT_BINARY`] entry ID property, e.g. [`sys::PR_IPM_SUBTREE_ENTRYID`]. A missing
+/// property returns the error in its [`PropValueData::Error`] value, usually
+/// [`sys::MAPI_E_NOT_FOUND`].
+fn entry_id_prop<P: MAPIProp>(prop: &P, tag: u32) -> Result<EntryId> {
+    let props = prop.get_props(&[PropTag(tag)])?;
+    let value = props
+        .iter()
+        .next()
+        .ok_or_else(|| Error::from(sys::MAPI_E_NOT_FOUND))?;
+    match value.value {
+        PropValueData::Error(err) => Err(Error::from(err)),
+        _ => EntryId::try_from(&value),
+    }
+}
+
+/// Get the [`sys::PR_ENTRYID`] of every message in the `folder` which matches the `restriction`.
+/// Get the [`sys::PR_ENTRYID`] from a row in the hierarchy table, unless the
+/// [`sys::PR_FOLDER_TYPE`] is [`sys::FOLDER_SEARCH`].
+fn searchable_folder_id<'a>(values: impl Iterator<Item = PropValue<'a>>) -> Option<EntryId> {
+    let mut entry_id = None;
+    for value in values {
+        match (value.tag, &value.value) {
+            (PropTag(sys::PR_FOLDER_TYPE), PropValueData::Long(folder_type))
+                if *folder_type as u32 == sys::FOLDER_SEARCH =>
+            {
+                return None;
+            }
+            (PropTag(sys::PR_ENTRYID), _) => entry_id = EntryId::try_from(&value).ok(),
+            _ => {}
+        }
+    }
+    entry_id
+}
+
+fn find_entry_ids(folder: &Folder, restriction: &Restriction) -> Result<Vec<EntryId>> {
+    let rows = folder
+        .open_contents_table(Default::default())?
+        .query_all_rows(&[PropTag(sys::PR_ENTRYID)], Some(restriction), None)?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            row.iter()
+                .next()
+                .and_then(|value| EntryId::try_from(&value).ok())
+        })
+        .collect())
+}
+
+impl MAPIProp for MsgStore {
+    fn mapi_prop(&self) -> &sys::IMAPIProp {
+        &self.store
+    }
+
+    fn interface_id(&self) -> GUID {
+        sys::IMsgStore::IID
+    }
+
+    fn init_epoch(&self) -> InitEpoch {
+        self.epoch
+    }
+
+    fn registration(&self) -> Option<&ObjectRegistration> {
+        Some(&self.registration)
+    }
+}
+
+impl CompareEntryIds for MsgStore {
+    /// Call [`sys::IMsgStore::CompareEntryIDs`], which only compares entry IDs from this store,
+    /// e.g. when the store was opened without a [`Logon`].
+    fn compare_entry_ids(&self, left: &EntryId, right: &EntryId) -> Result<bool> {
+        self.check()?;
+        let mut result = 0;
+        unsafe {
+            self.store
+                .CompareEntryIDs(
+                    u32::try_from(left.len())?,
+                    left.as_ptr() as *mut _,
+                    u32::try_from(right.len())?,
+                    right.as_ptr() as *mut _,
+                    0,
+                    &mut result,
+                )
+                .map_err(|error| with_last_error(self.mapi_prop(), error))?;
+        }
+        Ok(result != 0)
+    }
+}
+
+impl From<sys::IMsgStore> for MsgStore {
+    fn from(value: sys::IMsgStore) -> Self {
+        Self::new(value)
+    }
+}
+
+impl MarshalToThread for MsgStore {
+    type State = (ObjectRegistration, Option<EntryId>);
+    type Context = ();
+
+    /// The [`MsgStore`] on the other thread gets a child of this one's [`ObjectRegistration`],
+    /// so it shares the disconnected state: once a [`MsgStore::watch_disconnect`] sink on either
+    /// thread sees the store disconnect, both of them return [`StoreDisconnected`]. The sink
+    /// itself is not shared, and [`MsgStore::reopen`] only reconnects the [`MsgStore`] it is
+    /// called on.
+    fn marshal_to_thread(&self) -> Result<Marshaled<Self>> {
+        Marshaled::new(
+            &self.store,
+            (
+                self.registration.child(ObjectKind::MsgStore),
+                self.entry_id.get().cloned(),
+            ),
+        )
+    }
+
+    fn unmarshal(marshaled: Marshaled<Self>, _context: ()) -> Result<Self> {
+        let (store, (registration, entry_id)) = marshaled.into_parts()?;
+        let store = Self {
+            store,
+            epoch: InitEpoch::current(),
+            registration,
+            entry_id: OnceLock::new(),
+        };
+        if let Some(entry_id) = entry_id {
+            let _ = store.entry_id.set(entry_id);
+        }
+        Ok(store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quota_info() {
+        let values = [
+            PropValue {
+                tag: PropTag(sys::PR_MESSAGE_SIZE_EXTENDED),
+                value: PropValueData::Error(sys::MAPI_E_NOT_FOUND),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_MESSAGE_SIZE),
+                value: PropValueData::Long(3 * 1024 * 1024),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_STORAGE_QUOTA_LIMIT),
+                value: PropValueData::Long(2 * 1024),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_PROHIBIT_SEND_QUOTA),
+                value: PropValueData::Long(4 * 1024),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_PROHIBIT_RECEIVE_QUOTA),
+                value: PropValueData::Error(sys::MAPI_E_NOT_FOUND),
+            },
+        ];
+        let info = QuotaInfo::from_props(values);
+        assert_eq!(
+            info,
+            QuotaInfo {
+                mailbox_size: Some(3 * 1024 * 1024),
+                storage_quota_limit: Some(2 * 1024),
+                prohibit_send_quota: Some(4 * 1024),
+                prohibit_receive_quota: None,
+            }
+        );
+        assert!(info.is_over_warning());
+        assert!(!info.is_send_prohibited());
+        assert!(!info.is_receive_prohibited());
+
+        let info = QuotaInfo::from_props([
+            PropValue {
+                tag: PropTag(sys::PR_MESSAGE_SIZE_EXTENDED),
+                value: PropValueData::LargeInteger(5 << 32),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_MESSAGE_SIZE),
+                value: PropValueData::Long(1),
+            },
+        ]);
+        assert_eq!(info.mailbox_size, Some(5 << 32));
+        assert!(!info.is_send_prohibited());
+    }
+
+    #[test]
+    fn store_props() {
+        assert_eq!(
+            FolderLocation::from(SpecialFolder::IpmSubtree),
+            FolderLocation::StoreProp(sys::PR_IPM_SUBTREE_ENTRYID)
+        );
+        assert_eq!(
+            FolderLocation::from(SpecialFolder::SentItems),
+            FolderLocation::StoreProp(sys::PR_IPM_SENTMAIL_ENTRYID)
+        );
+        assert_eq!(
+            FolderLocation::from(SpecialFolder::DeletedItems),
+            FolderLocation::StoreProp(sys::PR_IPM_WASTEBASKET_ENTRYID)
+        );
+        assert_eq!(
+            FolderLocation::from(SpecialFolder::SearchRoot),
+            FolderLocation::StoreProp(sys::PR_FINDER_ENTRYID)
+        );
+    }
+
+    #[test]
+    fn store_capabilities() {
+        let capabilities = StoreCapabilities::from(
+            sys::STORE_UNICODE_OK | sys::STORE_SEARCH_OK | sys::STORE_MODIFY_OK,
+        );
+        assert!(capabilities.supports_unicode());
+        assert!(capabilities.supports_search());
+        assert!(capabilities.supports_modify());
+        assert!(!capabilities.is_read_only());
+        assert!(!capabilities.supports_submit());
+        assert!(capabilities.contains(sys::STORE_UNICODE_OK | sys::STORE_SEARCH_OK));
+        assert!(!capabilities.contains(sys::STORE_UNICODE_OK | sys::STORE_READONLY));
+        assert!(!StoreCapabilities::default().supports_unicode());
+    }
+
+    #[test]
+    fn inbox_props() {
+        assert_eq!(
+            FolderLocation::from(SpecialFolder::Inbox),
+            FolderLocation::ReceiveFolder
+        );
+        assert_eq!(
+            FolderLocation::from(SpecialFolder::Calendar),
+            FolderLocation::InboxProp(sys::PR_IPM_APPOINTMENT_ENTRYID)
+        );
+        assert_eq!(
+            FolderLocation::from(SpecialFolder::Tasks),
+            FolderLocation::InboxProp(sys::PR_IPM_TASK_ENTRYID)
+        );
+    }
+
+    #[test]
+    fn skip_search_folders() {
+        let entry_id = [0x1_u8, 0x2];
+        let folder = |folder_type: u32| {
+            [
+                PropValue {
+                    tag: PropTag(sys::PR_ENTRYID),
+                    value: PropValueData::Binary(&entry_id),
+                },
+                PropValue {
+                    tag: PropTag(sys::PR_FOLDER_TYPE),
+                    value: PropValueData::Long(folder_type as i32),
+                },
+            ]
+        };
+        assert_eq!(
+            searchable_folder_id(folder(sys::FOLDER_GENERIC).into_iter()),
+            Some(EntryId::from(&entry_id[..]))
+        );
+        assert_eq!(
+            searchable_folder_id(folder(sys::FOLDER_SEARCH).into_iter()),
+            None
+        );
+    }
+}
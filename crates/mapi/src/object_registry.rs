@@ -0,0 +1,407 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`ObjectRegistry`], [`ObjectRegistration`], [`ObjectKind`], [`ObjectId`], and
+//! [`LiveObject`].
+//!
+//! Every [`crate::Message`], [`crate::Table`], etc. holds a reference which keeps its message store
+//! loaded, so a single leaked wrapper can make the store, and eventually
+//! [`crate::sys::MAPIUninitialize`], hang at shutdown. With the `object-registry` feature, each of the
+//! safe wrappers registers itself when it is created and removes itself when it is dropped, and
+//! [`ObjectRegistry::dump_leaks`] describes whatever is still alive, grouped by the
+//! [`crate::MsgStore`] it was opened from.
+//!
+//! Without the `object-registry` feature, [`ObjectRegistration`] is an empty type and the registry
+//! is always empty.
+
+use crate::{store_connection::StoreConnection, StoreDisconnected};
+use core::fmt::{self, Write};
+use std::{backtrace::Backtrace, collections::BTreeMap, sync::Arc};
+
+#[cfg(feature = "object-registry")]
+use std::{
+    backtrace::BacktraceStatus,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+/// Type of safe wrapper tracked by the [`ObjectRegistry`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ObjectKind {
+    /// [`crate::MsgStore`].
+    MsgStore,
+
+    /// [`crate::Folder`].
+    Folder,
+
+    /// [`crate::Message`].
+    Message,
+
+    /// [`crate::Attachment`].
+    Attachment,
+
+    /// [`crate::Table`].
+    Table,
+
+    /// [`crate::PropertyStream`].
+    PropertyStream,
+
+    /// [`crate::RulesTable`].
+    RulesTable,
+
+    /// [`crate::StatusObject`].
+    StatusObject,
+}
+
+/// Unique identifier assigned to each registered object. The [`ObjectId`] of a
+/// [`crate::MsgStore`] also identifies the store in [`LiveObject::store`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObjectId(u64);
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Snapshot of a registered object which has not been dropped yet, returned from
+/// [`ObjectRegistry::live_objects`].
+#[derive(Clone, Debug)]
+pub struct LiveObject {
+    /// Identifier of the object.
+    pub id: ObjectId,
+
+    /// Type of the object.
+    pub kind: ObjectKind,
+
+    /// Identifier of the [`crate::MsgStore`] the object was opened from, or [`None`] if it was
+    /// wrapped directly with one of the `new` constructors.
+    pub store: Option<ObjectId>,
+
+    /// Where the object was created. This is only captured in debug builds with
+    /// `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` set, see [`Backtrace::capture`].
+    pub backtrace: Option<Arc<Backtrace>>,
+}
+
+/// Registration held by each of the safe wrappers, which links it to the [`crate::MsgStore`] it
+/// was opened from, and removes the object from the [`ObjectRegistry`] when the wrapper is
+/// dropped.
+#[derive(Debug)]
+pub struct ObjectRegistration {
+    #[cfg(feature = "object-registry")]
+    id: ObjectId,
+
+    #[cfg(feature = "object-registry")]
+    store: Option<ObjectId>,
+
+    /// Shared with the [`crate::MsgStore`] and everything else opened from it, see
+    /// [`crate::MsgStore::watch_disconnect`].
+    connection: Option<Arc<StoreConnection>>,
+}
+
+impl ObjectRegistration {
+    /// Register a new [`crate::MsgStore`], which is the store for itself.
+    pub(crate) fn new_store() -> Self {
+        let connection = Some(Default::default());
+
+        #[cfg(feature = "object-registry")]
+        {
+            let id = registry::next_id();
+            registry::insert(id, ObjectKind::MsgStore, Some(id));
+            Self {
+                id,
+                store: Some(id),
+                connection,
+            }
+        }
+
+        #[cfg(not(feature = "object-registry"))]
+        Self { connection }
+    }
+
+    /// Register an object which was not opened from a registered [`crate::MsgStore`].
+    pub(crate) fn new(kind: ObjectKind) -> Self {
+        Self::with_store(kind, None, None)
+    }
+
+    /// Register an object which was opened from the object holding this registration, so it
+    /// belongs to the same [`crate::MsgStore`].
+    pub(crate) fn child(&self, kind: ObjectKind) -> Self {
+        Self::with_store(kind, self.store(), self.connection.clone())
+    }
+
+    #[cfg_attr(not(feature = "object-registry"), allow(unused_variables))]
+    fn with_store(
+        kind: ObjectKind,
+        store: Option<ObjectId>,
+        connection: Option<Arc<StoreConnection>>,
+    ) -> Self {
+        #[cfg(feature = "object-registry")]
+        {
+            let id = registry::next_id();
+            registry::insert(id, kind, store);
+            Self {
+                id,
+                store,
+                connection,
+            }
+        }
+
+        #[cfg(not(feature = "object-registry"))]
+        Self { connection }
+    }
+
+    /// Get the [`ObjectId`] of the registered object, or [`None`] without the `object-registry`
+    /// feature.
+    pub fn id(&self) -> Option<ObjectId> {
+        #[cfg(feature = "object-registry")]
+        return Some(self.id);
+
+        #[cfg(not(feature = "object-registry"))]
+        None
+    }
+
+    /// Get the [`ObjectId`] of the [`crate::MsgStore`] the object belongs to, if any.
+    pub fn store(&self) -> Option<ObjectId> {
+        #[cfg(feature = "object-registry")]
+        return self.store;
+
+        #[cfg(not(feature = "object-registry"))]
+        None
+    }
+
+    /// Return [`StoreDisconnected`] if the [`crate::MsgStore`] the object belongs to was
+    /// disconnected. Objects which were not opened from a [`crate::MsgStore`] are always
+    /// connected.
+    pub fn check_connected(&self) -> core::result::Result<(), StoreDisconnected> {
+        match &self.connection {
+            Some(connection) => connection.check(),
+            None => Ok(()),
+        }
+    }
+
+    /// Get the [`StoreConnection`] shared with the [`crate::MsgStore`], if any.
+    pub(crate) fn connection(&self) -> Option<&Arc<StoreConnection>> {
+        self.connection.as_ref()
+    }
+}
+
+impl Drop for ObjectRegistration {
+    fn drop(&mut self) {
+        #[cfg(feature = "object-registry")]
+        registry::remove(self.id);
+    }
+}
+
+/// Query the objects registered with the `object-registry` feature.
+pub struct ObjectRegistry;
+
+impl ObjectRegistry {
+    /// Check if the crate was built with the `object-registry` feature.
+    pub const fn is_enabled() -> bool {
+        cfg!(feature = "object-registry")
+    }
+
+    /// Get a snapshot of every registered object which has not been dropped yet, in the order
+    /// they were created.
+    pub fn live_objects() -> Vec<LiveObject> {
+        #[cfg(feature = "object-registry")]
+        return registry::snapshot();
+
+        #[cfg(not(feature = "object-registry"))]
+        Vec::new()
+    }
+
+    /// Count the [`ObjectRegistry::live_objects`] by [`LiveObject::store`] and [`ObjectKind`].
+    pub fn counts_by_store() -> BTreeMap<Option<ObjectId>, BTreeMap<ObjectKind, usize>> {
+        count_by_store(&Self::live_objects())
+    }
+
+    /// Describe every registered object which has not been dropped yet, grouped by store, with
+    /// the creation [`LiveObject::backtrace`] of each one if it was captured. Returns an empty
+    /// string if nothing is alive, e.g. right before dropping the last [`crate::Initialize`].
+    pub fn dump_leaks() -> String {
+        format_leaks(&Self::live_objects())
+    }
+}
+
+fn count_by_store(
+    objects: &[LiveObject],
+) -> BTreeMap<Option<ObjectId>, BTreeMap<ObjectKind, usize>> {
+    let mut counts: BTreeMap<_, BTreeMap<_, _>> = BTreeMap::new();
+    for object in objects {
+        *counts
+            .entry(object.store)
+            .or_default()
+            .entry(object.kind)
+            .or_default() += 1;
+    }
+    counts
+}
+
+fn format_leaks(objects: &[LiveObject]) -> String {
+    let mut report = String::new();
+    for (store, counts) in count_by_store(objects) {
+        let counts: Vec<_> = counts
+            .into_iter()
+            .map(|(kind, count)| format!("{count} {kind:?}"))
+            .collect();
+        let _ = match store {
+            Some(store) => writeln!(report, "store {store}: {}", counts.join(", ")),
+            None => writeln!(report, "no store: {}", counts.join(", ")),
+        };
+
+        for object in objects.iter().filter(|object| object.store == store) {
+            let _ = writeln!(report, "  {:?} {}", object.kind, object.id);
+            if let Some(backtrace) = object.backtrace.as_deref() {
+                for line in backtrace.to_string().lines() {
+                    let _ = writeln!(report, "    {line}");
+                }
+            }
+        }
+    }
+    report
+}
+
+#[cfg(feature = "object-registry")]
+mod registry {
+    use super::*;
+
+    struct Entry {
+        kind: ObjectKind,
+        store: Option<ObjectId>,
+        backtrace: Option<Arc<Backtrace>>,
+    }
+
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+    static OBJECTS: Mutex<BTreeMap<ObjectId, Entry>> = Mutex::new(BTreeMap::new());
+
+    fn lock() -> std::sync::MutexGuard<'static, BTreeMap<ObjectId, Entry>> {
+        OBJECTS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn next_id() -> ObjectId {
+        ObjectId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn insert(id: ObjectId, kind: ObjectKind, store: Option<ObjectId>) {
+        let backtrace = if cfg!(debug_assertions) {
+            let backtrace = Backtrace::capture();
+            (backtrace.status() == BacktraceStatus::Captured).then(|| Arc::new(backtrace))
+        } else {
+            None
+        };
+        lock().insert(
+            id,
+            Entry {
+                kind,
+                store,
+                backtrace,
+            },
+        );
+    }
+
+    pub fn remove(id: ObjectId) {
+        lock().remove(&id);
+    }
+
+    pub fn snapshot() -> Vec<LiveObject> {
+        lock()
+            .iter()
+            .map(|(id, entry)| LiveObject {
+                id: *id,
+                kind: entry.kind,
+                store: entry.store,
+                backtrace: entry.backtrace.clone(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(id: u64, kind: ObjectKind, store: Option<u64>) -> LiveObject {
+        LiveObject {
+            id: ObjectId(id),
+            kind,
+            store: store.map(ObjectId),
+            backtrace: None,
+        }
+    }
+
+    #[test]
+    fn format_by_store() {
+        let objects = [
+            object(1, ObjectKind::MsgStore, Some(1)),
+            object(2, ObjectKind::Table, None),
+            object(3, ObjectKind::Message, Some(1)),
+            object(4, ObjectKind::Message, Some(1)),
+        ];
+        let counts = count_by_store(&objects);
+        assert_eq!(counts[&Some(ObjectId(1))][&ObjectKind::Message], 2);
+        assert_eq!(counts[&None][&ObjectKind::Table], 1);
+        assert_eq!(
+            format_leaks(&objects),
+            "no store: 1 Table\n  Table #2\nstore #1: 1 MsgStore, 2 Message\n  MsgStore #1\n  Message #3\n  Message #4\n"
+        );
+        assert_eq!(format_leaks(&[]), "");
+    }
+
+    #[cfg(feature = "object-registry")]
+    #[test]
+    fn register_and_drop() {
+        let store = ObjectRegistration::new_store();
+        let store_id = store.id().expect("missing id");
+        assert_eq!(store.store(), Some(store_id));
+
+        let message = store.child(ObjectKind::Message);
+        let table = message.child(ObjectKind::Table);
+        assert_eq!(table.store(), Some(store_id));
+        let unattributed = ObjectRegistration::new(ObjectKind::Table);
+        assert_eq!(unattributed.store(), None);
+
+        let live = |id: Option<ObjectId>| {
+            ObjectRegistry::live_objects()
+                .iter()
+                .any(|object| Some(object.id) == id)
+        };
+        assert!(live(table.id()));
+        assert_eq!(
+            ObjectRegistry::counts_by_store()[&Some(store_id)],
+            BTreeMap::from([
+                (ObjectKind::MsgStore, 1),
+                (ObjectKind::Message, 1),
+                (ObjectKind::Table, 1)
+            ])
+        );
+
+        let table_id = table.id();
+        drop(table);
+        assert!(!live(table_id));
+        assert!(
+            ObjectRegistry::dump_leaks().contains(&format!("Message {}", message.id().unwrap()))
+        );
+
+        drop(message);
+        drop(store);
+        assert!(!ObjectRegistry::counts_by_store().contains_key(&Some(store_id)));
+    }
+
+    #[cfg(not(feature = "object-registry"))]
+    #[test]
+    fn disabled() {
+        let store = ObjectRegistration::new_store();
+        let _message = store.child(ObjectKind::Message);
+        assert!(!ObjectRegistry::is_enabled());
+        assert_eq!(store.id(), None);
+        assert!(ObjectRegistry::live_objects().is_empty());
+        assert_eq!(ObjectRegistry::dump_leaks(), "");
+    }
+}
@@ -0,0 +1,122 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`OpenPolicy`].
+
+use crate::{mapi_error::with_last_error, prop_tag::prop_tag_array, sys, PropTag, Row};
+use windows_core::*;
+
+/// Choose how the `_with_policy` open helpers, e.g. [`crate::Folder::open_message_with_policy`],
+/// handle [`sys::MAPI_DEFERRED_ERRORS`].
+///
+/// With deferred errors, a provider may return success from `OpenEntry` or `OpenMsgStore`
+/// without contacting the server, and report a failure on the next call to the object instead.
+/// That is faster, but it means a missing or ghosted object, e.g. a public folder which is not
+/// replicated to this server, fails in some later, unrelated call.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OpenPolicy {
+    /// Do not pass [`sys::MAPI_DEFERRED_ERRORS`], so every error is returned by the open call.
+    #[default]
+    Immediate,
+
+    /// Pass [`sys::MAPI_DEFERRED_ERRORS`], then flush any deferred error before returning by
+    /// reading [`sys::PR_OBJECT_TYPE`]. If that fails, open the object again without
+    /// [`sys::MAPI_DEFERRED_ERRORS`], so the error (if any) comes from the open call.
+    Deferred,
+
+    /// Pass [`sys::MAPI_DEFERRED_ERRORS`] and return the object without checking it. Errors show
+    /// up on whichever call uses the object first.
+    DeferredUnchecked,
+}
+
+impl OpenPolicy {
+    /// Get the flags to pass to the first open call for this policy.
+    pub fn flags(self) -> u32 {
+        match self {
+            Self::Immediate => 0,
+            Self::Deferred | Self::DeferredUnchecked => sys::MAPI_DEFERRED_ERRORS,
+        }
+    }
+
+    /// Call `open` with the [`OpenPolicy::flags`], and for [`OpenPolicy::Deferred`], call `probe`
+    /// on the result to flush any deferred error. If `probe` fails, call `open` again with
+    /// immediate errors and return that result instead.
+    pub(crate) fn open<T, O, P>(self, mut open: O, probe: P) -> Result<T>
+    where
+        O: FnMut(u32) -> Result<T>,
+        P: FnOnce(&T) -> Result<()>,
+    {
+        let opened = open(self.flags())?;
+        match self {
+            Self::Deferred if probe(&opened).is_err() => {
+                drop(opened);
+                open(Self::Immediate.flags())
+            }
+            _ => Ok(opened),
+        }
+    }
+}
+
+/// Read [`sys::PR_OBJECT_TYPE`] to find out if a deferred open actually succeeded. The value
+/// itself does not matter, only whether [`sys::IMAPIProp::GetProps`] fails.
+pub(crate) fn probe_object(prop: &sys::IMAPIProp) -> Result<()> {
+    let mut tags = prop_tag_array(&[PropTag(sys::PR_OBJECT_TYPE)])?;
+    let mut row = sys::SRow::default();
+    unsafe {
+        prop.GetProps(
+            tags.as_mut_ptr() as *mut _,
+            0,
+            &mut row.cValues,
+            &mut row.lpProps,
+        )
+        .map_err(|error| with_last_error(prop, error))?;
+    }
+    drop(Row::new(&mut row));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn open_log(policy: OpenPolicy, probe_result: Result<()>) -> (Result<u32>, Vec<u32>) {
+        let calls = RefCell::new(Vec::new());
+        let result = policy.open(
+            |flags| {
+                calls.borrow_mut().push(flags);
+                Ok(flags)
+            },
+            |_| probe_result,
+        );
+        (result, calls.into_inner())
+    }
+
+    #[test]
+    fn immediate() {
+        let (result, calls) = open_log(OpenPolicy::Immediate, Err(sys::MAPI_E_CALL_FAILED.into()));
+        assert_eq!(result.expect("open failed"), 0);
+        assert_eq!(calls, [0]);
+    }
+
+    #[test]
+    fn deferred_flushed() {
+        let (result, calls) = open_log(OpenPolicy::Deferred, Ok(()));
+        assert_eq!(result.expect("open failed"), sys::MAPI_DEFERRED_ERRORS);
+        assert_eq!(calls, [sys::MAPI_DEFERRED_ERRORS]);
+    }
+
+    #[test]
+    fn deferred_retried() {
+        let (result, calls) = open_log(OpenPolicy::Deferred, Err(sys::MAPI_E_NOT_FOUND.into()));
+        assert_eq!(result.expect("open failed"), 0);
+        assert_eq!(calls, [sys::MAPI_DEFERRED_ERRORS, 0]);
+
+        let (result, calls) = open_log(
+            OpenPolicy::DeferredUnchecked,
+            Err(sys::MAPI_E_NOT_FOUND.into()),
+        );
+        assert_eq!(result.expect("open failed"), sys::MAPI_DEFERRED_ERRORS);
+        assert_eq!(calls, [sys::MAPI_DEFERRED_ERRORS]);
+    }
+}
@@ -0,0 +1,95 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`Outbox`].
+//!
+//! Sending a message with extended MAPI takes more than [`Message::submit`]. The message should
+//! be created in the outbox, marked as unsent, and told where to put the copy of the sent message
+//! before the spooler picks it up. [`Outbox::create_message`] takes care of that, so the caller
+//! only needs to fill in the content and recipients.
+
+use crate::{
+    sys, EntryId, Folder, MAPIProp, Message, MsgStore, PropTag, PropValue, PropValueData,
+    SpecialFolder,
+};
+use windows_core::*;
+
+/// The [`SpecialFolder::Outbox`] of a [`MsgStore`], opened with [`MsgStore::outbox`].
+pub struct Outbox {
+    /// Access the outbox [`Folder`].
+    pub folder: Folder,
+
+    /// [`sys::PR_ENTRYID`] of the [`SpecialFolder::SentItems`], if the store has one.
+    sent_items: Option<EntryId>,
+}
+
+impl Outbox {
+    /// Open the [`SpecialFolder::Outbox`] and look up the [`SpecialFolder::SentItems`] in the
+    /// `store`.
+    pub fn open(store: &MsgStore) -> Result<Self> {
+        let folder = store.special_folder(SpecialFolder::Outbox)?;
+        let sent_items = match store.special_folder_id(SpecialFolder::SentItems) {
+            Ok(entry_id) => Some(entry_id),
+            Err(err) if err.code() == sys::MAPI_E_NOT_FOUND => None,
+            Err(err) => return Err(err),
+        };
+        Ok(Self { folder, sent_items })
+    }
+
+    /// Create a new message in the outbox, ready to send with [`Message::submit`] once it has a
+    /// subject, body, and recipients.
+    ///
+    /// The message has [`sys::MSGFLAG_UNSENT`] and [`sys::MSGFLAG_FROMME`] set in
+    /// [`sys::PR_MESSAGE_FLAGS`]. After it is sent, the spooler moves it to the
+    /// [`SpecialFolder::SentItems`] with [`sys::PR_SENTMAIL_ENTRYID`], or deletes it with
+    /// [`sys::PR_DELETE_AFTER_SUBMIT`] if the store does not have that folder.
+    pub fn create_message(&self) -> Result<Message> {
+        let message = self.folder.create_message()?;
+        message.set_props(&outbox_props(self.sent_items.as_ref()))?;
+        Ok(message)
+    }
+}
+
+/// Properties which [`Outbox::create_message`] sets on each new message.
+fn outbox_props(sent_items: Option<&EntryId>) -> Vec<PropValue> {
+    let mut props = vec![PropValue {
+        tag: PropTag(sys::PR_MESSAGE_FLAGS),
+        value: PropValueData::Long((sys::MSGFLAG_UNSENT | sys::MSGFLAG_FROMME) as i32),
+    }];
+    props.push(match sent_items {
+        Some(entry_id) => PropValue {
+            tag: PropTag(sys::PR_SENTMAIL_ENTRYID),
+            value: PropValueData::Binary(entry_id.as_bytes()),
+        },
+        None => PropValue {
+            tag: PropTag(sys::PR_DELETE_AFTER_SUBMIT),
+            value: PropValueData::Boolean(1),
+        },
+    });
+    props
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sent_items_props() {
+        let entry_id = EntryId::from([0x0_u8, 0x0, 0x0, 0x0, 0x1].as_slice());
+        let props = outbox_props(Some(&entry_id));
+        assert!(matches!(
+            props[0].value,
+            PropValueData::Long(flags) if flags as u32 == sys::MSGFLAG_UNSENT | sys::MSGFLAG_FROMME
+        ));
+        assert_eq!(props[1].tag, PropTag(sys::PR_SENTMAIL_ENTRYID));
+        assert_eq!(props[1].value.as_bytes(), Some(entry_id.as_bytes()));
+    }
+
+    #[test]
+    fn delete_after_submit_props() {
+        let props = outbox_props(None);
+        assert_eq!(props.len(), 2);
+        assert_eq!(props[1].tag, PropTag(sys::PR_DELETE_AFTER_SUBMIT));
+        assert!(matches!(props[1].value, PropValueData::Boolean(1)));
+    }
+}
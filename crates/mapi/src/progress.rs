@@ -0,0 +1,252 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`Progress`] and [`ProgressSink`].
+//!
+//! Long running operations, like [`sys::IMAPIFolder::CopyMessages`] or
+//! [`sys::IMAPIFolder::EmptyFolder`], take an optional [`sys::IMAPIProgress`] which the provider
+//! calls as it works. [`ProgressSink`] implements that interface and hands each update to a Rust
+//! closure, e.g. to drive a progress bar.
+
+use crate::{call_sink, sys};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use windows::Win32::Foundation::E_INVALIDARG;
+use windows_core::*;
+
+/// Progress update passed to the [`ProgressSink`] callback.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Current progress, between [`Progress::min`] and [`Progress::max`].
+    pub value: u32,
+
+    /// Number of items processed so far, or 0 if the provider does not count items.
+    pub count: u32,
+
+    /// Total number of items, or 0 if the provider does not count items.
+    pub total: u32,
+
+    /// Lower limit of [`Progress::value`], from [`sys::IMAPIProgress::SetLimits`].
+    pub min: u32,
+
+    /// Upper limit of [`Progress::value`], from [`sys::IMAPIProgress::SetLimits`].
+    pub max: u32,
+
+    /// [`sys::MAPI_TOP_LEVEL`] is set, so the limits apply to the whole operation rather than to
+    /// one of the subobjects it is processing.
+    pub top_level: bool,
+}
+
+impl Progress {
+    /// Get the [`Progress::value`] as a fraction of the range between [`Progress::min`] and
+    /// [`Progress::max`], clamped to `0.0..=1.0`.
+    pub fn fraction(&self) -> f64 {
+        if self.max <= self.min {
+            return 0.0;
+        }
+        let value = self.value.clamp(self.min, self.max) - self.min;
+        f64::from(value) / f64::from(self.max - self.min)
+    }
+}
+
+/// Limits stored by [`sys::IMAPIProgress::SetLimits`].
+struct Limits {
+    min: u32,
+    max: u32,
+    flags: u32,
+}
+
+impl Default for Limits {
+    /// Start with the values MAPI recommends for a new progress object: a range of 1 to 1000, and
+    /// [`sys::MAPI_TOP_LEVEL`].
+    fn default() -> Self {
+        Self {
+            min: 1,
+            max: 1000,
+            flags: sys::MAPI_TOP_LEVEL,
+        }
+    }
+}
+
+type Callback = Arc<dyn Fn(Progress) + Send + Sync>;
+
+/// Implementation of [`sys::IMAPIProgress`] which keeps track of the limits and passes each
+/// [`sys::IMAPIProgress::Progress`] call to a Rust closure as a [`Progress`].
+///
+/// Like [`crate::NotificationSink`], the provider may call it on a different thread, so the closure
+/// must be [`Send`] and [`Sync`], and it is not called under a lock. If it panics, the panic is
+/// caught and [`sys::IMAPIProgress::Progress`] returns [`sys::MAPI_E_CALL_FAILED`].
+#[implement(sys::IMAPIProgress)]
+pub struct ProgressSink {
+    limits: Mutex<Limits>,
+    callback: Callback,
+}
+
+impl ProgressSink {
+    /// Create a [`sys::IMAPIProgress`] which calls `callback` with each [`Progress`] update.
+    pub fn create<F>(callback: F) -> sys::IMAPIProgress
+    where
+        F: Fn(Progress) + Send + Sync + 'static,
+    {
+        Self {
+            limits: Default::default(),
+            callback: Arc::new(callback),
+        }
+        .into()
+    }
+
+    /// Lock the [`Limits`]. They are only ever assigned whole values, so they are still valid
+    /// if another thread panicked while holding the lock.
+    fn limits(&self) -> MutexGuard<'_, Limits> {
+        self.limits.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl sys::IMAPIProgress_Impl for ProgressSink_Impl {
+    fn Progress(&self, ulvalue: u32, ulcount: u32, ultotal: u32) -> Result<()> {
+        let progress = {
+            let limits = self.limits();
+            Progress {
+                value: ulvalue,
+                count: ulcount,
+                total: ultotal,
+                min: limits.min,
+                max: limits.max,
+                top_level: limits.flags & sys::MAPI_TOP_LEVEL != 0,
+            }
+        };
+        let callback = Arc::clone(&self.callback);
+        if call_sink(&*callback, progress) {
+            Ok(())
+        } else {
+            Err(Error::from(sys::MAPI_E_CALL_FAILED))
+        }
+    }
+
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn GetFlags(&self, lpulflags: *mut u32) -> Result<()> {
+        let flags = self.limits().flags;
+        unsafe { write_out(lpulflags, flags) }
+    }
+
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn GetMax(&self, lpulmax: *mut u32) -> Result<()> {
+        let max = self.limits().max;
+        unsafe { write_out(lpulmax, max) }
+    }
+
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn GetMin(&self, lpulmin: *mut u32) -> Result<()> {
+        let min = self.limits().min;
+        unsafe { write_out(lpulmin, min) }
+    }
+
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn SetLimits(&self, lpulmin: *mut u32, lpulmax: *mut u32, lpulflags: *mut u32) -> Result<()> {
+        let mut limits = self.limits();
+        unsafe {
+            if let Some(min) = lpulmin.as_ref() {
+                limits.min = *min;
+            }
+            if let Some(max) = lpulmax.as_ref() {
+                limits.max = *max;
+            }
+            if let Some(flags) = lpulflags.as_ref() {
+                limits.flags = *flags;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write a value to an out-param, or return [`E_INVALIDARG`] if the pointer is `null`.
+///
+/// # Safety
+///
+/// The `out` pointer must be `null` or valid for writes.
+unsafe fn write_out(out: *mut u32, value: u32) -> Result<()> {
+    match out.as_mut() {
+        Some(out) => {
+            *out = value;
+            Ok(())
+        }
+        None => Err(Error::from(E_INVALIDARG)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ptr;
+    use std::sync::mpsc;
+
+    #[test]
+    fn forward_progress() {
+        let (sender, receiver) = mpsc::channel();
+        let sink = ProgressSink::create(move |progress| {
+            let _ = sender.send(progress);
+        });
+
+        let mut min = 0;
+        let mut max = 0;
+        let mut flags = 0;
+        unsafe {
+            sink.GetMin(&mut min).expect("GetMin failed");
+            sink.GetMax(&mut max).expect("GetMax failed");
+            sink.GetFlags(&mut flags).expect("GetFlags failed");
+        }
+        assert_eq!((min, max, flags), (1, 1000, sys::MAPI_TOP_LEVEL));
+
+        let (mut min, mut max, mut flags) = (0, 10, 0);
+        unsafe {
+            sink.SetLimits(&mut min, &mut max, &mut flags)
+                .expect("SetLimits failed");
+            sink.Progress(5, 1, 2).expect("Progress failed");
+        }
+        let progress = receiver.recv().expect("missing progress");
+        assert_eq!(
+            progress,
+            Progress {
+                value: 5,
+                count: 1,
+                total: 2,
+                min: 0,
+                max: 10,
+                top_level: false,
+            }
+        );
+        assert_eq!(progress.fraction(), 0.5);
+    }
+
+    #[test]
+    fn null_limits() {
+        let sink = ProgressSink::create(|_| {});
+        let mut max = 50;
+        unsafe {
+            sink.SetLimits(ptr::null_mut(), &mut max, ptr::null_mut())
+                .expect("SetLimits failed");
+            assert!(sink.GetMin(ptr::null_mut()).is_err());
+        }
+
+        let mut min = 0;
+        let mut flags = 0;
+        unsafe {
+            sink.GetMin(&mut min).expect("GetMin failed");
+            sink.GetMax(&mut max).expect("GetMax failed");
+            sink.GetFlags(&mut flags).expect("GetFlags failed");
+        }
+        assert_eq!((min, max, flags), (1, 50, sys::MAPI_TOP_LEVEL));
+    }
+
+    #[test]
+    fn catch_panic() {
+        let sink = ProgressSink::create(|progress| {
+            assert_ne!(progress.value, 5, "callback panicked");
+        });
+        unsafe {
+            sink.Progress(1, 0, 0).expect("Progress failed");
+            let error = sink.Progress(5, 0, 0).expect_err("Progress should fail");
+            assert_eq!(error.code(), sys::MAPI_E_CALL_FAILED);
+            sink.Progress(10, 0, 0).expect("Progress failed");
+        }
+    }
+}
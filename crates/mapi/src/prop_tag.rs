@@ -4,13 +4,14 @@
 //! Define [`PropTag`] and [`PropType`].
 
 use crate::sys;
+use windows_core::*;
 
 pub const PROP_ID_MASK: u32 = 0xFFFF_0000;
 pub const PROP_TYPE_MASK: u32 = 0xFFFF;
 
 /// Simple wrapper for a MAPI `PROP_TAG`.
 #[repr(transparent)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct PropTag(pub u32);
 
 impl PropTag {
@@ -106,3 +107,13 @@ impl From<PropType> for u32 {
         value.0 as u32
     }
 }
+
+/// Build a buffer with the same layout as a [`sys::SPropTagArray`] for a list of tags which is only
+/// known at runtime. The first element is [`sys::SPropTagArray::cValues`], followed by each of the
+/// elements in [`sys::SPropTagArray::aulPropTag`].
+pub(crate) fn prop_tag_array(tags: &[PropTag]) -> Result<Vec<u32>> {
+    let count = u32::try_from(tags.len())?;
+    Ok(std::iter::once(count)
+        .chain(tags.iter().map(|tag| tag.0))
+        .collect())
+}
@@ -1,10 +1,12 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
-//! Define [`PropValue`] and [`PropValueData`].
+//! Define [`PropValue`], [`PropValueData`], [`PropValueBuilder`], and [`OwnedPropValue`].
 
-use crate::{sys, PropTag};
-use core::{ffi, ptr, slice};
+use crate::{sys, to_pwstr_buffer, CodePage, MAPIBuffer, MAPIUninit, MapiArena, PropTag, PropType};
+use core::{ffi, mem, ptr, slice};
+use std::borrow::Cow;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use windows::Win32::{
     Foundation::{E_INVALIDARG, E_POINTER, FILETIME},
     System::Com::CY,
@@ -18,6 +20,10 @@ pub struct PropValue<'a> {
 }
 
 /// Enum with values from the original [`sys::SPropValue::Value`] union.
+///
+/// Multi-value arrays borrow from the [`sys::SPropValue`] without copying, unless the array
+/// pointer is not aligned for the element type, in which case the elements are copied into a
+/// [`Cow::Owned`] array.
 pub enum PropValueData<'a> {
     /// [`sys::PT_NULL`]
     Null,
@@ -74,31 +80,31 @@ pub enum PropValueData<'a> {
     FloatArray(&'a [f32]),
 
     /// [`sys::PT_MV_DOUBLE`]
-    DoubleArray(Vec<f64>),
+    DoubleArray(Cow<'a, [f64]>),
 
     /// [`sys::PT_MV_CURRENCY`]
-    CurrencyArray(Vec<CY>),
+    CurrencyArray(Cow<'a, [CY]>),
 
     /// [`sys::PT_MV_APPTIME`]
-    AppTimeArray(Vec<f64>),
+    AppTimeArray(Cow<'a, [f64]>),
 
     /// [`sys::PT_MV_SYSTIME`]
-    FileTimeArray(Vec<FILETIME>),
+    FileTimeArray(Cow<'a, [FILETIME]>),
 
     /// [`sys::PT_MV_BINARY`]
-    BinaryArray(Vec<sys::SBinary>),
+    BinaryArray(Cow<'a, [sys::SBinary]>),
 
     /// [`sys::PT_MV_STRING8`]
-    AnsiStringArray(Vec<PCSTR>),
+    AnsiStringArray(Cow<'a, [PCSTR]>),
 
     /// [`sys::PT_MV_UNICODE`]
-    UnicodeArray(Vec<PCWSTR>),
+    UnicodeArray(Cow<'a, [PCWSTR]>),
 
     /// [`sys::PT_MV_CLSID`]
-    GuidArray(Vec<GUID>),
+    GuidArray(Cow<'a, [GUID]>),
 
     /// [`sys::PT_MV_LONGLONG`]
-    LargeIntegerArray(Vec<i64>),
+    LargeIntegerArray(Cow<'a, [i64]>),
 
     /// [`sys::PT_ERROR`]
     Error(HRESULT),
@@ -107,6 +113,156 @@ pub enum PropValueData<'a> {
     Object(i32),
 }
 
+/// Number of seconds between the [`FILETIME`] epoch (1601-01-01) and [`UNIX_EPOCH`] (1970-01-01).
+pub(crate) const FILETIME_UNIX_EPOCH_SECONDS: u64 = 11_644_473_600;
+
+/// Number of [`FILETIME`] ticks per second. Each tick is 100 nanoseconds.
+const FILETIME_TICKS_PER_SECOND: u64 = 10_000_000;
+
+impl PropValueData<'_> {
+    /// Convert a [`PropValueData::Unicode`] value to a [`String`].
+    ///
+    /// Returns [`None`] for any other variant, or if the string is not valid UTF-16. A
+    /// [`PropValueData::AnsiString`] only holds a raw pointer, so decode it with
+    /// [`PropValueData::as_string_in`] instead.
+    pub fn as_string(&self) -> Option<String> {
+        match self {
+            Self::Unicode(value) => String::from_utf16(trim_nul(value)).ok(),
+            _ => None,
+        }
+    }
+
+    /// Convert a [`PropValueData::Unicode`] or [`PropValueData::AnsiString`] value to a
+    /// [`String`], decoding `PT_STRING8` values with the specified [`CodePage`].
+    ///
+    /// Returns [`None`] for any other variant, or if the string is not valid in its encoding.
+    ///
+    /// # Safety
+    ///
+    /// A [`PropValueData::AnsiString`] value must be a valid, `null` terminated string pointer,
+    /// e.g. one which was converted from a [`sys::SPropValue`] that MAPI returned and which has
+    /// not been freed yet.
+    pub unsafe fn as_string_in(&self, code_page: CodePage) -> Option<String> {
+        match self {
+            Self::AnsiString(_) => code_page.decode(self.as_ansi_bytes()?).ok(),
+            _ => self.as_string(),
+        }
+    }
+
+    /// Convert a [`PropValueData::Unicode`] value to a [`String`], replacing anything which is
+    /// not valid with [`char::REPLACEMENT_CHARACTER`]. Returns [`None`] for any other variant.
+    pub fn as_string_lossy(&self) -> Option<String> {
+        match self {
+            Self::Unicode(value) => Some(String::from_utf16_lossy(trim_nul(value))),
+            _ => None,
+        }
+    }
+
+    /// Get the bytes in a [`PropValueData::Binary`] value.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Binary(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Get the bytes without a `null` terminator in a [`PropValueData::AnsiString`] value.
+    ///
+    /// # Safety
+    ///
+    /// The same as [`PropValueData::as_string_in`].
+    pub unsafe fn as_ansi_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::AnsiString(value) if !value.is_null() => Some(value.as_bytes()),
+            _ => None,
+        }
+    }
+
+    /// Convert a [`PropValueData::FileTime`] value to a [`SystemTime`].
+    pub fn as_filetime(&self) -> Option<SystemTime> {
+        let Self::FileTime(value) = self else {
+            return None;
+        };
+        filetime_to_system_time(*value)
+    }
+
+    /// Convert a [`PropValueData::FileTime`] value to a [`chrono::DateTime`] in UTC.
+    #[cfg(feature = "chrono")]
+    pub fn as_date_time(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.as_filetime().map(chrono::DateTime::from)
+    }
+}
+
+impl PropValueData<'static> {
+    /// Build a [`PropValueData::FileTime`] value from a [`SystemTime`], e.g. for a
+    /// [`sys::PT_SYSTIME`] property passed to [`crate::MAPIProp::set_props`]. Returns [`None`] if
+    /// the time is before the [`FILETIME`] epoch (1601-01-01) or does not fit in a [`FILETIME`].
+    pub fn from_system_time(value: SystemTime) -> Option<Self> {
+        system_time_to_filetime(value).map(Self::FileTime)
+    }
+
+    /// Build a [`PropValueData::FileTime`] value from a [`chrono::DateTime`]. Returns [`None`] if
+    /// the time is before the [`FILETIME`] epoch (1601-01-01) or does not fit in a [`FILETIME`].
+    #[cfg(feature = "chrono")]
+    pub fn from_date_time<Tz: chrono::TimeZone>(value: &chrono::DateTime<Tz>) -> Option<Self> {
+        Self::from_system_time(value.with_timezone(&chrono::Utc).into())
+    }
+}
+
+/// Convert a [`FILETIME`], which counts 100 nanosecond ticks since 1601-01-01, to a
+/// [`SystemTime`].
+pub(crate) fn filetime_to_system_time(value: FILETIME) -> Option<SystemTime> {
+    let ticks = (u64::from(value.dwHighDateTime) << 32) | u64::from(value.dwLowDateTime);
+    let since_1601 = Duration::new(
+        ticks / FILETIME_TICKS_PER_SECOND,
+        ((ticks % FILETIME_TICKS_PER_SECOND) * 100) as u32,
+    );
+    let epoch = Duration::from_secs(FILETIME_UNIX_EPOCH_SECONDS);
+    if since_1601 >= epoch {
+        UNIX_EPOCH.checked_add(since_1601 - epoch)
+    } else {
+        UNIX_EPOCH.checked_sub(epoch - since_1601)
+    }
+}
+
+/// Convert a [`SystemTime`] to a [`FILETIME`], truncating to 100 nanosecond ticks.
+pub(crate) fn system_time_to_filetime(value: SystemTime) -> Option<FILETIME> {
+    let epoch = UNIX_EPOCH.checked_sub(Duration::from_secs(FILETIME_UNIX_EPOCH_SECONDS))?;
+    let since_1601 = value.duration_since(epoch).ok()?;
+    let ticks = since_1601
+        .as_secs()
+        .checked_mul(FILETIME_TICKS_PER_SECOND)?
+        .checked_add(u64::from(since_1601.subsec_nanos() / 100))?;
+    Some(FILETIME {
+        dwLowDateTime: ticks as u32,
+        dwHighDateTime: (ticks >> 32) as u32,
+    })
+}
+
+/// Borrow `count` elements starting at `first` if the pointer is aligned for `T`, or copy them
+/// with [`ptr::read_unaligned`] if it is not.
+///
+/// # Safety
+///
+/// `first` must point to `count` readable elements which outlive `'a`.
+unsafe fn borrow_or_copy<'a, T: Clone>(first: *const T, count: usize) -> Cow<'a, [T]> {
+    if first as usize % mem::align_of::<T>() == 0 {
+        Cow::Borrowed(slice::from_raw_parts(first, count))
+    } else {
+        Cow::Owned(
+            (0..count)
+                .map(|idx| ptr::read_unaligned(first.add(idx)))
+                .collect(),
+        )
+    }
+}
+
+/// Strip everything from the first `null` character in a wide string buffer.
+fn trim_nul(value: &[u16]) -> &[u16] {
+    let len = value.iter().position(|ch| *ch == 0).unwrap_or(value.len());
+    &value[..len]
+}
+
 impl<'a> From<&'a sys::SPropValue> for PropValue<'a> {
     /// Convert a [`sys::SPropValue`] reference into a friendlier [`PropValue`] type, which often
     /// supports safe access to the [`sys::SPropValue::Value`] union.
@@ -201,117 +357,90 @@ impl<'a> From<&'a sys::SPropValue> for PropValue<'a> {
                     if value.Value.MVdbl.lpdbl.is_null() {
                         PropValueData::Error(E_POINTER)
                     } else {
-                        let count = value.Value.MVdbl.cValues as usize;
-                        let first = value.Value.MVdbl.lpdbl;
-                        let mut values = Vec::with_capacity(count);
-                        for idx in 0..count {
-                            values.push(ptr::read_unaligned(first.add(idx)))
-                        }
-                        PropValueData::DoubleArray(values)
+                        PropValueData::DoubleArray(borrow_or_copy(
+                            value.Value.MVdbl.lpdbl,
+                            value.Value.MVdbl.cValues as usize,
+                        ))
                     }
                 }
                 sys::PT_MV_CURRENCY => {
                     if value.Value.MVcur.lpcur.is_null() {
                         PropValueData::Error(E_POINTER)
                     } else {
-                        let count = value.Value.MVcur.cValues as usize;
-                        let first = value.Value.MVcur.lpcur;
-                        let mut values = Vec::with_capacity(count);
-                        for idx in 0..count {
-                            values.push(ptr::read_unaligned(first.add(idx)))
-                        }
-                        PropValueData::CurrencyArray(values)
+                        PropValueData::CurrencyArray(borrow_or_copy(
+                            value.Value.MVcur.lpcur,
+                            value.Value.MVcur.cValues as usize,
+                        ))
                     }
                 }
                 sys::PT_MV_APPTIME => {
                     if value.Value.MVat.lpat.is_null() {
                         PropValueData::Error(E_POINTER)
                     } else {
-                        let count = value.Value.MVat.cValues as usize;
-                        let first = value.Value.MVat.lpat;
-                        let mut values = Vec::with_capacity(count);
-                        for idx in 0..count {
-                            values.push(ptr::read_unaligned(first.add(idx)))
-                        }
-                        PropValueData::AppTimeArray(values)
+                        PropValueData::AppTimeArray(borrow_or_copy(
+                            value.Value.MVat.lpat,
+                            value.Value.MVat.cValues as usize,
+                        ))
                     }
                 }
                 sys::PT_MV_SYSTIME => {
                     if value.Value.MVft.lpft.is_null() {
                         PropValueData::Error(E_POINTER)
                     } else {
-                        let count = value.Value.MVft.cValues as usize;
-                        let first = value.Value.MVft.lpft;
-                        let mut values = Vec::with_capacity(count);
-                        for idx in 0..count {
-                            values.push(ptr::read_unaligned(first.add(idx)))
-                        }
-                        PropValueData::FileTimeArray(values)
+                        PropValueData::FileTimeArray(borrow_or_copy(
+                            value.Value.MVft.lpft,
+                            value.Value.MVft.cValues as usize,
+                        ))
                     }
                 }
                 sys::PT_MV_BINARY => {
                     if value.Value.MVbin.lpbin.is_null() {
                         PropValueData::Error(E_POINTER)
                     } else {
-                        let count = value.Value.MVbin.cValues as usize;
-                        let first = value.Value.MVbin.lpbin;
-                        let mut values = Vec::with_capacity(count);
-                        for idx in 0..count {
-                            values.push(ptr::read_unaligned(first.add(idx)))
-                        }
-                        PropValueData::BinaryArray(values)
+                        PropValueData::BinaryArray(borrow_or_copy(
+                            value.Value.MVbin.lpbin,
+                            value.Value.MVbin.cValues as usize,
+                        ))
                     }
                 }
                 sys::PT_MV_STRING8 => {
                     if value.Value.MVszA.lppszA.is_null() {
                         PropValueData::Error(E_POINTER)
                     } else {
-                        let count = value.Value.MVszA.cValues as usize;
-                        let first = value.Value.MVszA.lppszA;
-                        let mut values = Vec::with_capacity(count);
-                        for idx in 0..count {
-                            values.push(PCSTR(ptr::read_unaligned(first.add(idx)).0))
-                        }
-                        PropValueData::AnsiStringArray(values)
+                        PropValueData::AnsiStringArray(borrow_or_copy(
+                            value.Value.MVszA.lppszA as *const PCSTR,
+                            value.Value.MVszA.cValues as usize,
+                        ))
                     }
                 }
                 sys::PT_MV_UNICODE => {
                     if value.Value.MVszW.lppszW.is_null() {
                         PropValueData::Error(E_POINTER)
                     } else {
-                        let count = value.Value.MVszW.cValues as usize;
-                        let first = value.Value.MVszW.lppszW;
-                        let mut values = Vec::with_capacity(count);
-                        for idx in 0..count {
-                            values.push(PCWSTR(ptr::read_unaligned(first.add(idx)).0))
-                        }
-                        PropValueData::UnicodeArray(values)
+                        PropValueData::UnicodeArray(borrow_or_copy(
+                            value.Value.MVszW.lppszW as *const PCWSTR,
+                            value.Value.MVszW.cValues as usize,
+                        ))
                     }
                 }
                 sys::PT_MV_CLSID => {
                     if value.Value.MVguid.lpguid.is_null() {
                         PropValueData::Error(E_POINTER)
                     } else {
-                        let count = value.Value.MVguid.cValues as usize;
-                        let first = value.Value.MVguid.lpguid;
-                        let mut values = Vec::with_capacity(count);
-                        for idx in 0..count {
-                            values.push(ptr::read_unaligned(first.add(idx)))
-                        }
-                        PropValueData::GuidArray(values)
+                        PropValueData::GuidArray(borrow_or_copy(
+                            value.Value.MVguid.lpguid,
+                            value.Value.MVguid.cValues as usize,
+                        ))
                     }
                 }
                 sys::PT_MV_LONGLONG => {
                     if value.Value.MVli.lpli.is_null() {
                         PropValueData::Error(E_POINTER)
                     } else {
-                        let count = value.Value.MVli.cValues as usize;
-                        let first = value.Value.MVli.lpli;
-                        let mut values = Vec::with_capacity(count);
-                        for idx in 0..count {
-                            values.push(ptr::read_unaligned(first.add(idx)))
-                        }
-                        PropValueData::LargeIntegerArray(values)
+                        PropValueData::LargeIntegerArray(borrow_or_copy(
+                            value.Value.MVli.lpli,
+                            value.Value.MVli.cValues as usize,
+                        ))
                     }
                 }
                 sys::PT_ERROR => PropValueData::Error(HRESULT(value.Value.err)),
@@ -323,11 +452,670 @@ impl<'a> From<&'a sys::SPropValue> for PropValue<'a> {
     }
 }
 
+impl From<&PropValue<'_>> for sys::SPropValue {
+    /// Convert a [`PropValue`] reference back into a [`sys::SPropValue`], e.g. to pass to
+    /// [`sys::IMAPIProp::SetProps`]. Any pointers in the result borrow from the [`PropValue`], so
+    /// the [`sys::SPropValue`] must not be used after the [`PropValue`] is dropped or modified.
+    ///
+    /// [`PropValueData::Unicode`] values must include a `null` terminator, like the ones returned
+    /// from `From<&sys::SPropValue>`.
+    fn from(value: &PropValue<'_>) -> Self {
+        let mut result = sys::SPropValue {
+            ulPropTag: value.tag.0,
+            ..Default::default()
+        };
+        match &value.value {
+            PropValueData::Null => {}
+            PropValueData::Short(data) => result.Value.i = *data,
+            PropValueData::Long(data) => result.Value.l = *data,
+            PropValueData::Pointer(data) => result.Value.lpv = *data,
+            PropValueData::Float(data) => result.Value.flt = *data,
+            PropValueData::Double(data) => result.Value.dbl = *data,
+            PropValueData::Boolean(data) => result.Value.b = *data,
+            PropValueData::Currency(data) => result.Value.cur.int64 = *data,
+            PropValueData::AppTime(data) => result.Value.at = *data,
+            PropValueData::FileTime(data) => result.Value.ft = *data,
+            PropValueData::AnsiString(data) => result.Value.lpszA = PSTR(data.0 as *mut _),
+            PropValueData::Binary(data) => {
+                result.Value.bin = sys::SBinary {
+                    cb: data.len() as u32,
+                    lpb: data.as_ptr() as *mut _,
+                }
+            }
+            PropValueData::Unicode(data) => result.Value.lpszW = PWSTR(data.as_ptr() as *mut _),
+            PropValueData::Guid(data) => result.Value.lpguid = ptr::from_ref(data) as *mut _,
+            PropValueData::LargeInteger(data) => result.Value.li = *data,
+            PropValueData::ShortArray(data) => {
+                result.Value.MVi = sys::SShortArray {
+                    cValues: data.len() as u32,
+                    lpi: data.as_ptr() as *mut _,
+                }
+            }
+            PropValueData::LongArray(data) => {
+                result.Value.MVl = sys::SLongArray {
+                    cValues: data.len() as u32,
+                    lpl: data.as_ptr() as *mut _,
+                }
+            }
+            PropValueData::FloatArray(data) => {
+                result.Value.MVflt = sys::SRealArray {
+                    cValues: data.len() as u32,
+                    lpflt: data.as_ptr() as *mut _,
+                }
+            }
+            PropValueData::DoubleArray(data) => {
+                result.Value.MVdbl = sys::SDoubleArray {
+                    cValues: data.len() as u32,
+                    lpdbl: data.as_ptr() as *mut _,
+                }
+            }
+            PropValueData::CurrencyArray(data) => {
+                result.Value.MVcur = sys::SCurrencyArray {
+                    cValues: data.len() as u32,
+                    lpcur: data.as_ptr() as *mut _,
+                }
+            }
+            PropValueData::AppTimeArray(data) => {
+                result.Value.MVat = sys::SAppTimeArray {
+                    cValues: data.len() as u32,
+                    lpat: data.as_ptr() as *mut _,
+                }
+            }
+            PropValueData::FileTimeArray(data) => {
+                result.Value.MVft = sys::SDateTimeArray {
+                    cValues: data.len() as u32,
+                    lpft: data.as_ptr() as *mut _,
+                }
+            }
+            PropValueData::BinaryArray(data) => {
+                result.Value.MVbin = sys::SBinaryArray {
+                    cValues: data.len() as u32,
+                    lpbin: data.as_ptr() as *mut _,
+                }
+            }
+            PropValueData::AnsiStringArray(data) => {
+                result.Value.MVszA = sys::SLPSTRArray {
+                    cValues: data.len() as u32,
+                    lppszA: data.as_ptr() as *mut _,
+                }
+            }
+            PropValueData::UnicodeArray(data) => {
+                result.Value.MVszW = sys::SWStringArray {
+                    cValues: data.len() as u32,
+                    lppszW: data.as_ptr() as *mut _,
+                }
+            }
+            PropValueData::GuidArray(data) => {
+                result.Value.MVguid = sys::SGuidArray {
+                    cValues: data.len() as u32,
+                    lpguid: data.as_ptr() as *mut _,
+                }
+            }
+            PropValueData::LargeIntegerArray(data) => {
+                result.Value.MVli = sys::SLargeIntegerArray {
+                    cValues: data.len() as u32,
+                    lpli: data.as_ptr() as *mut _,
+                }
+            }
+            PropValueData::Error(data) => result.Value.err = data.0,
+            PropValueData::Object(data) => result.Value.x = *data,
+        }
+        result
+    }
+}
+
+/// Accumulate a list of [`PropValue`] entries and then copy them into an [`OwnedPropValue`], with
+/// all of the strings, binary data, and multi-value arrays in allocations chained to the
+/// [`sys::SPropValue`] array. Unlike the [`sys::SPropValue`] from `From<&PropValue>`, the result does
+/// not borrow from the input, so it can be kept around and passed to any MAPI function which takes
+/// an `LPSPropValue`.
+///
+/// [`PropValueBuilder::string`] converts Rust strings to [`sys::PT_UNICODE`] or
+/// [`sys::PT_STRING8`] values depending on the [`PropTag::prop_type`]. `PT_STRING8` values are
+/// encoded with the [`CodePage`] passed to [`PropValueBuilder::code_page`], or else the
+/// [`sys::PR_INTERNET_CPID`] or [`sys::PR_MESSAGE_CODEPAGE`] value already in the builder. There is
+/// no default, since guessing wrong would silently mangle any accented characters.
+#[derive(Default)]
+pub struct PropValueBuilder<'a> {
+    code_page: Option<CodePage>,
+    values: Vec<PropValue<'a>>,
+    strings: Vec<Vec<u8>>,
+}
+
+impl<'a> PropValueBuilder<'a> {
+    /// Create an empty [`PropValueBuilder`].
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the [`CodePage`] used to encode [`sys::PT_STRING8`] values in
+    /// [`PropValueBuilder::string`], e.g. from [`CodePage::from_prop_value`] on the message or
+    /// store.
+    pub fn code_page(mut self, code_page: CodePage) -> Self {
+        self.code_page = Some(code_page);
+        self
+    }
+
+    /// Add a [`PropValue`] to the list. Use [`PropValueBuilder::string`] for strings, which does
+    /// not need the raw pointers.
+    ///
+    /// # Safety
+    ///
+    /// [`PropValueData::AnsiString`], [`PropValueData::AnsiStringArray`],
+    /// [`PropValueData::UnicodeArray`], and [`PropValueData::BinaryArray`] hold raw pointers, which
+    /// [`PropValueBuilder::build`] dereferences to copy the data. Every string pointer must be
+    /// `null` terminated, every [`sys::SBinary`] must point to `cb` readable bytes (or be `null`
+    /// with `cb` set to 0), and all of them must still be valid when [`PropValueBuilder::build`]
+    /// is called.
+    pub unsafe fn value(mut self, value: PropValue<'a>) -> Self {
+        self.values.push(value);
+        self
+    }
+
+    /// Add a string value. If the `tag` has the type [`sys::PT_STRING8`], the string is encoded
+    /// with the [`CodePage`] for this builder, otherwise it is converted to [`sys::PT_UNICODE`].
+    ///
+    /// A `PT_STRING8` value fails with [`E_INVALIDARG`] if there is no [`CodePage`], see
+    /// [`PropValueBuilder`].
+    pub fn string(mut self, tag: PropTag, value: &str) -> Result<Self> {
+        if u32::from(tag.prop_type()) == sys::PT_STRING8 {
+            let code_page = self
+                .code_page
+                .or_else(|| self.values.iter().find_map(CodePage::from_prop_value))
+                .ok_or_else(|| Error::new(E_INVALIDARG, "PT_STRING8 values need a code page"))?;
+            let value = code_page.encode_with_nul(value)?;
+            let data = PropValueData::AnsiString(PCSTR::from_raw(value.as_ptr()));
+            self.strings.push(value);
+            self.values.push(PropValue { tag, value: data });
+        } else {
+            let tag = tag.change_prop_type(PropType::new(sys::PT_UNICODE as u16));
+            let value = to_pwstr_buffer(value);
+            self.values.push(PropValue {
+                tag,
+                value: PropValueData::Unicode(value),
+            });
+        }
+        Ok(self)
+    }
+
+    /// Allocate the [`sys::SPropValue`] array with [`sys::MAPIAllocateBuffer`] and copy everything
+    /// it points to into allocations chained with [`sys::MAPIAllocateMore`].
+    ///
+    /// This is safe because raw pointers can only be added with the `unsafe`
+    /// [`PropValueBuilder::value`], whose caller guarantees they are still valid here.
+    pub fn build(self) -> Result<OwnedPropValue> {
+        let count = self.values.len();
+        if count == 0 {
+            return Ok(OwnedPropValue {
+                count,
+                buffer: None,
+            });
+        }
+
+        let buffer = MAPIUninit::<sys::SPropValue>::new(count)?;
+        {
+            let arena = MapiArena::chained(&buffer);
+            for (mut element, value) in buffer.iter().zip(self.values.iter()) {
+                // SAFETY: Raw pointers were checked by the caller of `PropValueBuilder::value`.
+                element
+                    .uninit()?
+                    .write(unsafe { chain_prop_value(&arena, value) }?);
+            }
+        }
+
+        Ok(OwnedPropValue {
+            count,
+            buffer: Some(unsafe { buffer.assume_init() }),
+        })
+    }
+}
+
+impl PropValue<'_> {
+    /// Make a deep copy of the value in `arena`, like `PropCopyMore`. Strings, binary values, and
+    /// multi-value arrays are all copied into allocations from the arena, so the result does not
+    /// borrow anything from `self`, e.g. to add values read from a [`crate::Row`] to a
+    /// [`sys::SPropValue`] array for [`sys::IMAPIProp::SetProps`].
+    ///
+    /// # Safety
+    ///
+    /// Raw pointers in the value must be valid, see [`PropValueBuilder::value`]. Values borrowed
+    /// from a [`sys::SPropValue`] which MAPI returned, e.g. in a [`crate::Row`], always are.
+    pub unsafe fn copy_to_arena<'b>(&self, arena: &'b MapiArena) -> Result<&'b sys::SPropValue> {
+        let value = chain_prop_value(arena, self)?;
+        Ok(&arena.alloc_copy(&[value])?[0])
+    }
+}
+
+/// Convert a [`PropValue`] to a [`sys::SPropValue`], copying everything it points to into
+/// allocations from `arena`.
+///
+/// # Safety
+///
+/// Raw pointers in `value` must be valid, see [`PropValueBuilder::value`]. Values borrowed from a
+/// [`sys::SPropValue`] which MAPI returned always are.
+pub(crate) unsafe fn chain_prop_value(
+    arena: &MapiArena,
+    value: &PropValue,
+) -> Result<sys::SPropValue> {
+    let mut result = sys::SPropValue::from(value);
+    unsafe {
+        match &value.value {
+            PropValueData::AnsiString(data) => {
+                if data.is_null() {
+                    return Err(Error::from_hresult(E_POINTER));
+                }
+                result.Value.lpszA = PSTR(copy_string(arena, data.as_bytes())?);
+            }
+            PropValueData::Binary(data) => {
+                result.Value.bin.lpb = chain_copy(arena, data)?;
+            }
+            PropValueData::Unicode(data) => {
+                let data = data.strip_suffix(&[0]).unwrap_or(data);
+                result.Value.lpszW = PWSTR(copy_string(arena, data)?);
+            }
+            PropValueData::Guid(data) => {
+                result.Value.lpguid = chain_copy(arena, slice::from_ref(data))?;
+            }
+            PropValueData::ShortArray(data) => {
+                result.Value.MVi.lpi = chain_copy(arena, data)?;
+            }
+            PropValueData::LongArray(data) => {
+                result.Value.MVl.lpl = chain_copy(arena, data)?;
+            }
+            PropValueData::FloatArray(data) => {
+                result.Value.MVflt.lpflt = chain_copy(arena, data)?;
+            }
+            PropValueData::DoubleArray(data) => {
+                result.Value.MVdbl.lpdbl = chain_copy(arena, data)?;
+            }
+            PropValueData::CurrencyArray(data) => {
+                result.Value.MVcur.lpcur = chain_copy(arena, data)?;
+            }
+            PropValueData::AppTimeArray(data) => {
+                result.Value.MVat.lpat = chain_copy(arena, data)?;
+            }
+            PropValueData::FileTimeArray(data) => {
+                result.Value.MVft.lpft = chain_copy(arena, data)?;
+            }
+            PropValueData::BinaryArray(data) => {
+                let data = data
+                    .iter()
+                    .map(|value| {
+                        let bytes = if value.lpb.is_null() {
+                            &[]
+                        } else {
+                            slice::from_raw_parts(value.lpb, value.cb as usize)
+                        };
+                        Ok(sys::SBinary {
+                            cb: u32::try_from(bytes.len())?,
+                            lpb: chain_copy(arena, bytes)?,
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                result.Value.MVbin.lpbin = chain_copy(arena, &data)?;
+            }
+            PropValueData::AnsiStringArray(data) => {
+                let data = data
+                    .iter()
+                    .map(|value| {
+                        if value.is_null() {
+                            return Err(Error::from_hresult(E_POINTER));
+                        }
+                        Ok(PSTR(copy_string(arena, value.as_bytes())?))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                result.Value.MVszA.lppszA = chain_copy(arena, &data)?;
+            }
+            PropValueData::UnicodeArray(data) => {
+                let data = data
+                    .iter()
+                    .map(|value| {
+                        if value.is_null() {
+                            return Err(Error::from_hresult(E_POINTER));
+                        }
+                        Ok(PWSTR(copy_string(arena, value.as_wide())?))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                result.Value.MVszW.lppszW = chain_copy(arena, &data)?;
+            }
+            PropValueData::GuidArray(data) => {
+                result.Value.MVguid.lpguid = chain_copy(arena, data)?;
+            }
+            PropValueData::LargeIntegerArray(data) => {
+                result.Value.MVli.lpli = chain_copy(arena, data)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(result)
+}
+
+/// Copy a slice into an allocation from `arena`. Empty slices are represented with a `null`
+/// pointer.
+pub(crate) fn chain_copy<T>(arena: &MapiArena, data: &[T]) -> Result<*mut T>
+where
+    T: Copy,
+{
+    if data.is_empty() {
+        return Ok(ptr::null_mut());
+    }
+    Ok(arena.alloc_copy(data)?.as_mut_ptr())
+}
+
+/// Copy a string without a `null` terminator into an allocation from `arena`, and append a `null`
+/// terminator.
+fn copy_string<T>(arena: &MapiArena, data: &[T]) -> Result<*mut T>
+where
+    T: Copy + Default,
+{
+    let alloc = arena.alloc::<T>(data.len() + 1)?;
+    alloc[..data.len()].copy_from_slice(data);
+    Ok(alloc.as_mut_ptr())
+}
+
+/// Array of [`sys::SPropValue`] built with [`PropValueBuilder`]. Everything is allocated with
+/// [`sys::MAPIAllocateBuffer`] and [`sys::MAPIAllocateMore`], so it is freed with a single call to
+/// [`sys::MAPIFreeBuffer`] when this is dropped.
+///
+/// [`OwnedPropValue`] is [`Send`], so it can be built on one thread and handed off to another, like
+/// [`crate::Row`] and [`crate::RowSet`].
+pub struct OwnedPropValue {
+    count: usize,
+    buffer: Option<MAPIBuffer<'static, sys::SPropValue>>,
+}
+
+// SAFETY: The buffer and everything chained to it are only reachable through this value, and
+// MAPI memory can be freed with [`sys::MAPIFreeBuffer`] on any thread.
+unsafe impl Send for OwnedPropValue {}
+
+impl OwnedPropValue {
+    /// Test for an empty [`sys::SPropValue`] array.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Get the number of [`sys::SPropValue`] elements in the array.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Get a pointer to the [`sys::SPropValue`] array, e.g. to pass to
+    /// [`sys::IMAPIProp::SetProps`]. The pointer is `null` if the array is empty.
+    pub fn as_mut_ptr(&mut self) -> *mut sys::SPropValue {
+        self.buffer
+            .as_mut()
+            .and_then(|buffer| buffer.as_mut().ok())
+            .map(ptr::from_mut)
+            .unwrap_or(ptr::null_mut())
+    }
+
+    /// Give up ownership of the [`sys::SPropValue`] array, e.g. to store it in a [`sys::SRow`] or
+    /// [`sys::ADRENTRY`] which will be freed with [`sys::FreeProws`] or [`sys::FreePadrlist`].
+    /// Returns the number of elements and a pointer which is `null` if the array is empty.
+    pub(crate) fn into_raw(mut self) -> (usize, *mut sys::SPropValue) {
+        let count = self.count;
+        let props = self.as_mut_ptr();
+        mem::forget(self);
+        (count, props)
+    }
+
+    /// Iterate over the [`sys::SPropValue`] elements in the array.
+    pub fn iter(&mut self) -> impl Iterator<Item = PropValue<'_>> {
+        let data = self
+            .buffer
+            .as_ref()
+            .and_then(|buffer| buffer.as_slice(self.count).ok())
+            .unwrap_or_default();
+        data.iter().map(PropValue::from)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for PropValue<'_> {
+    /// Serialize the [`PropTag`] as a `u32` and the [`PropValueData`] in its converted form.
+    fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        // SAFETY: Untrusted values are serialized without following their raw pointers.
+        unsafe { serialize::prop_value(self, false, serializer) }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for PropValueData<'_> {
+    /// Serialize the value with the same variant names, converted to portable types:
+    ///
+    /// - [`PropValueData::Unicode`] strings are decoded like [`PropValueData::as_string_lossy`].
+    /// - [`FILETIME`] values are the number of 100 nanosecond ticks since 1601-01-01.
+    /// - [`GUID`] values are formatted as strings.
+    /// - [`PropValueData::Boolean`] is a `bool`, and [`HRESULT`] values are a `u32`.
+    /// - [`PropValueData::Pointer`] is serialized without the address, which is meaningless outside
+    ///   of this process.
+    /// - [`PropValueData::AnsiString`], [`PropValueData::BinaryArray`],
+    ///   [`PropValueData::AnsiStringArray`], and [`PropValueData::UnicodeArray`] only hold raw
+    ///   pointers, so they are serialized as `null`. Wrap values which MAPI returned in a
+    ///   [`TrustedPropValue`] to decode them, the same as [`crate::Row`] does.
+    fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        // SAFETY: Untrusted values are serialized without following their raw pointers.
+        unsafe { serialize::value(self, false) }.serialize(serializer)
+    }
+}
+
+/// Serialize a [`PropValue`] which came from MAPI, decoding the variants which hold raw pointers
+/// instead of serializing them as `null`. [`crate::Row`] and [`crate::RowSet`] serialize their
+/// values this way.
+#[cfg(feature = "serde")]
+pub struct TrustedPropValue<'a>(PropValue<'a>);
+
+#[cfg(feature = "serde")]
+impl<'a> TrustedPropValue<'a> {
+    /// Wrap a [`PropValue`] for serialization.
+    ///
+    /// # Safety
+    ///
+    /// Any raw pointers in the `value` must be valid, e.g. because it was converted from a
+    /// [`sys::SPropValue`] that MAPI returned and which has not been freed yet.
+    pub unsafe fn new(value: PropValue<'a>) -> Self {
+        Self(value)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for TrustedPropValue<'_> {
+    /// Serialize the [`PropTag`] as a `u32` and the [`PropValueData`] in its converted form,
+    /// decoding strings and binaries from their raw pointers.
+    fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        // SAFETY: The pointers were checked by the caller of `TrustedPropValue::new`.
+        unsafe { serialize::prop_value(&self.0, true, serializer) }
+    }
+}
+
+/// Converted forms of [`PropValueData`] for [`serde::Serialize`].
+#[cfg(feature = "serde")]
+mod serialize {
+    use super::{trim_nul, PropValue, PropValueData};
+    use crate::CodePage;
+    use core::slice;
+    use windows::Win32::Foundation::FILETIME;
+    use windows_core::PCSTR;
+
+    /// Serialize a [`PropValue`] as a struct with the `tag` and the converted `value`.
+    ///
+    /// # Safety
+    ///
+    /// If `trusted` is `true`, the raw pointers in the value must be valid.
+    pub unsafe fn prop_value<S>(
+        value: &PropValue,
+        trusted: bool,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeStruct;
+
+        let mut result = serializer.serialize_struct("PropValue", 2)?;
+        result.serialize_field("tag", &u32::from(value.tag))?;
+        result.serialize_field("value", &self::value(&value.value, trusted))?;
+        result.end()
+    }
+
+    /// Convert a [`PropValueData`] to its serializable form. Variants which only hold raw
+    /// pointers are converted to [`None`] unless the value is `trusted`.
+    ///
+    /// # Safety
+    ///
+    /// If `trusted` is `true`, the raw pointers in the value must be valid.
+    pub unsafe fn value<'a>(value: &'a PropValueData, trusted: bool) -> Value<'a> {
+        match value {
+            PropValueData::Null => Value::Null,
+            PropValueData::Short(value) => Value::Short(*value),
+            PropValueData::Long(value) => Value::Long(*value),
+            PropValueData::Pointer(_) => Value::Pointer,
+            PropValueData::Float(value) => Value::Float(*value),
+            PropValueData::Double(value) => Value::Double(*value),
+            PropValueData::Boolean(value) => Value::Boolean(*value != 0),
+            PropValueData::Currency(value) => Value::Currency(*value),
+            PropValueData::AppTime(value) => Value::AppTime(*value),
+            PropValueData::FileTime(value) => Value::FileTime(filetime_ticks(value)),
+            PropValueData::AnsiString(value) => {
+                Value::AnsiString(trusted.then(|| ansi_string_lossy(*value)))
+            }
+            PropValueData::Binary(value) => Value::Binary(Bytes(value)),
+            PropValueData::Unicode(value) => {
+                Value::Unicode(String::from_utf16_lossy(trim_nul(value)))
+            }
+            PropValueData::Guid(value) => Value::Guid(format!("{value:?}")),
+            PropValueData::LargeInteger(value) => Value::LargeInteger(*value),
+            PropValueData::ShortArray(values) => Value::ShortArray(values),
+            PropValueData::LongArray(values) => Value::LongArray(values),
+            PropValueData::FloatArray(values) => Value::FloatArray(values),
+            PropValueData::DoubleArray(values) => Value::DoubleArray(values),
+            PropValueData::CurrencyArray(values) => {
+                Value::CurrencyArray(values.iter().map(|value| value.int64).collect())
+            }
+            PropValueData::AppTimeArray(values) => Value::AppTimeArray(values),
+            PropValueData::FileTimeArray(values) => {
+                Value::FileTimeArray(values.iter().map(filetime_ticks).collect())
+            }
+            PropValueData::BinaryArray(values) => Value::BinaryArray(trusted.then(|| {
+                values
+                    .iter()
+                    .map(|value| {
+                        Bytes(if value.lpb.is_null() {
+                            &[]
+                        } else {
+                            slice::from_raw_parts(value.lpb, value.cb as usize)
+                        })
+                    })
+                    .collect()
+            })),
+            PropValueData::AnsiStringArray(values) => Value::AnsiStringArray(trusted.then(|| {
+                values
+                    .iter()
+                    .map(|value| ansi_string_lossy(*value))
+                    .collect()
+            })),
+            PropValueData::UnicodeArray(values) => Value::UnicodeArray(trusted.then(|| {
+                values
+                    .iter()
+                    .map(|value| {
+                        if value.is_null() {
+                            String::new()
+                        } else {
+                            String::from_utf16_lossy(value.as_wide())
+                        }
+                    })
+                    .collect()
+            })),
+            PropValueData::GuidArray(values) => {
+                Value::GuidArray(values.iter().map(|value| format!("{value:?}")).collect())
+            }
+            PropValueData::LargeIntegerArray(values) => Value::LargeIntegerArray(values),
+            PropValueData::Error(value) => Value::Error(value.0 as u32),
+            PropValueData::Object(value) => Value::Object(*value),
+        }
+    }
+
+    #[derive(serde::Serialize)]
+    #[serde(rename = "PropValueData")]
+    pub enum Value<'a> {
+        Null,
+        Short(i16),
+        Long(i32),
+        Pointer,
+        Float(f32),
+        Double(f64),
+        Boolean(bool),
+        Currency(i64),
+        AppTime(f64),
+        FileTime(u64),
+        AnsiString(Option<String>),
+        Binary(Bytes<'a>),
+        Unicode(String),
+        Guid(String),
+        LargeInteger(i64),
+        ShortArray(&'a [i16]),
+        LongArray(&'a [i32]),
+        FloatArray(&'a [f32]),
+        DoubleArray(&'a [f64]),
+        CurrencyArray(Vec<i64>),
+        AppTimeArray(&'a [f64]),
+        FileTimeArray(Vec<u64>),
+        BinaryArray(Option<Vec<Bytes<'a>>>),
+        AnsiStringArray(Option<Vec<String>>),
+        UnicodeArray(Option<Vec<String>>),
+        GuidArray(Vec<String>),
+        LargeIntegerArray(&'a [i64]),
+        Error(u32),
+        Object(i32),
+    }
+
+    /// Decode a [`PropValueData::AnsiString`] with [`CodePage::ACP`], or as UTF-8 with
+    /// replacement characters if that fails.
+    ///
+    /// # Safety
+    ///
+    /// The `value` must be `null` or a valid, `null` terminated string pointer.
+    unsafe fn ansi_string_lossy(value: PCSTR) -> String {
+        if value.is_null() {
+            return String::new();
+        }
+        let bytes = value.as_bytes();
+        CodePage::ACP
+            .decode(bytes)
+            .unwrap_or_else(|_| String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    /// Serialize a byte slice with [`serde::Serializer::serialize_bytes`] instead of as a sequence.
+    pub struct Bytes<'a>(pub &'a [u8]);
+
+    impl serde::Serialize for Bytes<'_> {
+        fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            serializer.serialize_bytes(self.0)
+        }
+    }
+
+    fn filetime_ticks(value: &FILETIME) -> u64 {
+        (u64::from(value.dwHighDateTime) << 32) | u64::from(value.dwLowDateTime)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use crate::{sys, PropTag, PropType};
+    use crate::{mapi_ptr, sys, PropTag, PropType};
     use core::{iter, mem, ptr};
     use windows_core::{s, w};
 
@@ -637,7 +1425,52 @@ mod tests {
         let PropValueData::DoubleArray(values) = value.value else {
             panic!("wrong type")
         };
-        assert!(matches!(values.as_slice(), [17.0, 18.0]));
+        assert!(matches!(values.as_ref(), [17.0, 18.0]));
+    }
+
+    #[test]
+    fn test_array_borrowed() {
+        let expected = [17.0_f64, 18.0];
+        let mut value = sys::SPropValue {
+            ulPropTag: u32::from(
+                PropTag(sys::PR_NULL).change_prop_type(PropType::new(sys::PT_MV_DOUBLE as u16)),
+            ),
+            ..Default::default()
+        };
+        value.Value.MVdbl.cValues = expected.len() as u32;
+        value.Value.MVdbl.lpdbl = expected.as_ptr() as *mut _;
+        let value = PropValue::from(&value);
+        let PropValueData::DoubleArray(Cow::Borrowed(values)) = value.value else {
+            panic!("not borrowed")
+        };
+        assert_eq!(values.as_ptr(), expected.as_ptr());
+    }
+
+    #[test]
+    fn test_unaligned_array_copied() {
+        let expected = [17.0_f64, 18.0];
+        let mut buffer = [0_u64; 3];
+        let unaligned = unsafe { (buffer.as_mut_ptr() as *mut u8).add(1) };
+        unsafe {
+            ptr::copy_nonoverlapping(
+                expected.as_ptr() as *const u8,
+                unaligned,
+                mem::size_of_val(&expected),
+            );
+        }
+        let mut value = sys::SPropValue {
+            ulPropTag: u32::from(
+                PropTag(sys::PR_NULL).change_prop_type(PropType::new(sys::PT_MV_DOUBLE as u16)),
+            ),
+            ..Default::default()
+        };
+        value.Value.MVdbl.cValues = expected.len() as u32;
+        value.Value.MVdbl.lpdbl = unaligned as *mut _;
+        let value = PropValue::from(&value);
+        let PropValueData::DoubleArray(Cow::Owned(values)) = value.value else {
+            panic!("not copied")
+        };
+        assert_eq!(values, expected);
     }
 
     #[test]
@@ -658,7 +1491,7 @@ mod tests {
         };
         unsafe {
             assert!(matches!(
-                values.as_slice(),
+                values.as_ref(),
                 [CY { int64: 18 }, CY { int64: 19 }]
             ));
         }
@@ -680,7 +1513,7 @@ mod tests {
         let PropValueData::AppTimeArray(values) = value.value else {
             panic!("wrong type")
         };
-        assert!(matches!(values.as_slice(), [19.0, 20.0]));
+        assert!(matches!(values.as_ref(), [19.0, 20.0]));
     }
 
     #[test]
@@ -709,7 +1542,7 @@ mod tests {
             panic!("wrong type")
         };
         assert!(matches!(
-            values.as_slice(),
+            values.as_ref(),
             [
                 FILETIME {
                     dwHighDateTime: 20,
@@ -750,7 +1583,7 @@ mod tests {
             panic!("wrong type")
         };
         assert!(matches!(
-            values.as_slice(),
+            values.as_ref(),
             [actual1, actual2]
                 if actual1.cb == expected[0].cb && actual1.lpb == expected[0].lpb
                     && actual2.cb == expected[1].cb && actual2.lpb == expected[1].lpb
@@ -774,7 +1607,7 @@ mod tests {
             panic!("wrong type")
         };
         assert!(matches!(
-            values.as_slice(),
+            values.as_ref(),
             [actual1, actual2]
                 if actual1.0 == expected[0].0 && actual2.0 == expected[1].0
         ));
@@ -797,7 +1630,7 @@ mod tests {
             panic!("wrong type")
         };
         assert!(matches!(
-            values.as_slice(),
+            values.as_ref(),
             [actual1, actual2]
                 if actual1.0 == expected[0].0 && actual2.0 == expected[1].0
         ));
@@ -837,7 +1670,7 @@ mod tests {
             panic!("wrong type")
         };
         assert!(matches!(
-            values.as_slice(),
+            values.as_ref(),
             [
                 GUID { data1: 32, .. },
                 GUID { data2: 33, .. },
@@ -866,7 +1699,7 @@ mod tests {
         let PropValueData::LargeIntegerArray(values) = value.value else {
             panic!("wrong type")
         };
-        assert!(matches!(values.as_slice(), [36, 37]));
+        assert!(matches!(values.as_ref(), [36, 37]));
     }
 
     #[test]
@@ -896,4 +1729,478 @@ mod tests {
         assert_eq!(u32::from(value.tag.prop_type()), sys::PT_OBJECT);
         assert!(matches!(value.value, PropValueData::Object(39)));
     }
+
+    #[test]
+    fn test_long_to_sprop_value() {
+        let value = PropValue {
+            tag: PropTag(sys::PR_ATTACH_METHOD),
+            value: PropValueData::Long(sys::ATTACH_BY_VALUE as i32),
+        };
+        let value = sys::SPropValue::from(&value);
+        assert_eq!(value.ulPropTag, sys::PR_ATTACH_METHOD);
+        assert_eq!(unsafe { value.Value.l }, sys::ATTACH_BY_VALUE as i32);
+    }
+
+    #[test]
+    fn test_unicode_to_sprop_value() {
+        let expected = to_pwstr_buffer("forty");
+        let value = PropValue {
+            tag: PropTag(sys::PR_SUBJECT_W),
+            value: PropValueData::Unicode(expected.clone()),
+        };
+        let value = sys::SPropValue::from(&value);
+        let value = PropValue::from(&value);
+        let PropValueData::Unicode(actual) = value.value else {
+            panic!("wrong type");
+        };
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_binary_array_to_sprop_value() {
+        let first = [41_u8, 42];
+        let second = [43_u8];
+        let expected = vec![
+            sys::SBinary {
+                cb: first.len() as u32,
+                lpb: first.as_ptr() as *mut _,
+            },
+            sys::SBinary {
+                cb: second.len() as u32,
+                lpb: second.as_ptr() as *mut _,
+            },
+        ];
+        let value = PropValue {
+            tag: PropTag(sys::PR_NULL).change_prop_type(PropType::new(sys::PT_MV_BINARY as u16)),
+            value: PropValueData::BinaryArray(expected.into()),
+        };
+        let value = sys::SPropValue::from(&value);
+        let value = PropValue::from(&value);
+        let PropValueData::BinaryArray(actual) = value.value else {
+            panic!("wrong type");
+        };
+        assert_eq!(actual.len(), 2);
+        assert_eq!(actual[0].lpb, first.as_ptr() as *mut _);
+        assert_eq!(actual[1].cb, 1);
+    }
+
+    #[test]
+    fn test_builder_empty() {
+        let mut values = PropValueBuilder::new().build().expect("build failed");
+        assert!(values.is_empty());
+        assert!(values.as_mut_ptr().is_null());
+        assert_eq!(values.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_builder_strings() {
+        let mut values = PropValueBuilder::new()
+            .code_page(CodePage::WINDOWS_1252)
+            .string(PropTag(sys::PR_SUBJECT_A), "Crème brûlée")
+            .expect("string failed")
+            .string(PropTag(sys::PR_BODY_W), "forty-four")
+            .expect("string failed")
+            .build()
+            .expect("build failed");
+        assert_eq!(values.len(), 2);
+        let root = values.as_mut_ptr();
+        let mut values = values.iter();
+
+        let Some(PropValue {
+            tag,
+            value: PropValueData::AnsiString(actual),
+        }) = values.next()
+        else {
+            panic!("wrong type");
+        };
+        assert_eq!(tag.0, sys::PR_SUBJECT_A);
+        assert_ne!(actual.as_ptr(), root as *const _);
+        let actual = unsafe { CodePage::WINDOWS_1252.decode_pcstr(actual) }.expect("decode failed");
+        assert_eq!(actual, "Crème brûlée");
+
+        let Some(PropValue {
+            tag,
+            value: PropValueData::Unicode(actual),
+        }) = values.next()
+        else {
+            panic!("wrong type");
+        };
+        assert_eq!(tag.0, sys::PR_BODY_W);
+        let expected = to_pwstr_buffer("forty-four");
+        assert_eq!(actual, expected);
+        assert!(values.next().is_none());
+    }
+
+    #[test]
+    fn test_builder_message_code_page() {
+        // SAFETY: `PropValueData::Long` does not hold any pointers.
+        let builder = unsafe {
+            PropValueBuilder::new().value(PropValue {
+                tag: PropTag(sys::PR_MESSAGE_CODEPAGE),
+                value: PropValueData::Long(1252),
+            })
+        };
+        let mut values = builder
+            .string(PropTag(sys::PR_SUBJECT_A), "Crème brûlée")
+            .expect("string failed")
+            .build()
+            .expect("build failed");
+        let subject = values
+            .iter()
+            .find(|value| value.tag.0 == sys::PR_SUBJECT_A)
+            .expect("missing subject");
+        assert_eq!(
+            unsafe { subject.value.as_ansi_bytes() },
+            Some(b"Cr\xE8me br\xFBl\xE9e".as_slice())
+        );
+        assert_eq!(
+            unsafe { subject.value.as_string_in(CodePage::WINDOWS_1252) }.as_deref(),
+            Some("Crème brûlée")
+        );
+    }
+
+    #[test]
+    fn test_builder_requires_code_page() {
+        let Err(error) = PropValueBuilder::new().string(PropTag(sys::PR_SUBJECT_A), "Crème brûlée")
+        else {
+            panic!("string should fail without a code page");
+        };
+        assert_eq!(error.code(), E_INVALIDARG);
+    }
+
+    #[test]
+    fn test_builder_copies_data() {
+        let binary = vec![45_u8, 46, 47];
+        let longs = vec![48, 49];
+        let ansi = [s!("fifty"), s!("fifty-one")];
+        // SAFETY: The string literals are `null` terminated and outlive the call to `build`.
+        let mut values = unsafe {
+            PropValueBuilder::new()
+                .value(PropValue {
+                    tag: PropTag(sys::PR_ENTRYID),
+                    value: PropValueData::Binary(&binary),
+                })
+                .value(PropValue {
+                    tag: PropTag(sys::PR_NULL)
+                        .change_prop_type(PropType::new(sys::PT_MV_LONG as u16)),
+                    value: PropValueData::LongArray(&longs),
+                })
+                .value(PropValue {
+                    tag: PropTag(sys::PR_NULL)
+                        .change_prop_type(PropType::new(sys::PT_MV_STRING8 as u16)),
+                    value: PropValueData::AnsiStringArray(ansi.to_vec().into()),
+                })
+        }
+        .build()
+        .expect("build failed");
+        let root = values.as_mut_ptr();
+        assert!(mapi_ptr::backend::is_allocated(root as *mut _));
+
+        let mut values = values.iter();
+        let Some(PropValue {
+            value: PropValueData::Binary(actual),
+            ..
+        }) = values.next()
+        else {
+            panic!("wrong type");
+        };
+        assert_eq!(actual, binary.as_slice());
+        assert_ne!(actual.as_ptr(), binary.as_ptr());
+
+        let Some(PropValue {
+            value: PropValueData::LongArray(actual),
+            ..
+        }) = values.next()
+        else {
+            panic!("wrong type");
+        };
+        assert_eq!(actual, longs.as_slice());
+        assert_ne!(actual.as_ptr(), longs.as_ptr());
+
+        let Some(PropValue {
+            value: PropValueData::AnsiStringArray(actual),
+            ..
+        }) = values.next()
+        else {
+            panic!("wrong type");
+        };
+        assert_eq!(actual.len(), 2);
+        for (actual, expected) in actual.iter().zip(ansi) {
+            assert_ne!(actual.as_ptr(), expected.as_ptr());
+            assert_eq!(unsafe { actual.as_bytes() }, unsafe { expected.as_bytes() });
+        }
+    }
+
+    #[test]
+    fn test_builder_frees_chain() {
+        let binary = [52_u8; 16];
+        // SAFETY: `PropValueData::Binary` borrows a slice instead of holding a raw pointer.
+        let mut values = unsafe {
+            PropValueBuilder::new().value(PropValue {
+                tag: PropTag(sys::PR_ENTRYID),
+                value: PropValueData::Binary(&binary),
+            })
+        }
+        .build()
+        .expect("build failed");
+        let root = values.as_mut_ptr();
+        assert!(mapi_ptr::backend::is_allocated(root as *mut _));
+        drop(values);
+        assert!(!mapi_ptr::backend::is_allocated(root as *mut _));
+    }
+
+    #[test]
+    fn test_copy_to_arena() {
+        let arena = MapiArena::new().expect("new failed");
+        let copy = {
+            let subject = to_pwstr_buffer("fifty-four");
+            let first = [1_u8, 2];
+            let second = [3_u8];
+            let entry_ids = [
+                sys::SBinary {
+                    cb: first.len() as u32,
+                    lpb: first.as_ptr() as *mut _,
+                },
+                sys::SBinary {
+                    cb: second.len() as u32,
+                    lpb: second.as_ptr() as *mut _,
+                },
+            ];
+            let subject = PropValue {
+                tag: PropTag(sys::PR_SUBJECT_W),
+                value: PropValueData::Unicode(subject),
+            };
+            let entry_ids = PropValue {
+                tag: PropTag(sys::PR_NULL)
+                    .change_prop_type(PropType::new(sys::PT_MV_BINARY as u16)),
+                value: PropValueData::BinaryArray(Cow::Borrowed(&entry_ids)),
+            };
+            // SAFETY: The `SBinary` values point to the arrays above, which are still alive.
+            unsafe {
+                [
+                    subject.copy_to_arena(&arena).expect("copy_to_arena failed"),
+                    entry_ids
+                        .copy_to_arena(&arena)
+                        .expect("copy_to_arena failed"),
+                ]
+            }
+        };
+
+        assert!(matches!(
+            PropValue::from(copy[0]).value.as_string().as_deref(),
+            Some("fifty-four")
+        ));
+        let PropValueData::BinaryArray(entry_ids) = PropValue::from(copy[1]).value else {
+            panic!("wrong type");
+        };
+        let entry_ids: Vec<_> = entry_ids
+            .iter()
+            .map(|value| unsafe { slice::from_raw_parts(value.lpb, value.cb as usize) })
+            .collect();
+        assert_eq!(entry_ids, [&[1_u8, 2][..], &[3]]);
+    }
+
+    #[test]
+    fn test_copy_null_binary() {
+        let arena = MapiArena::new().expect("new failed");
+        let entry_ids = [sys::SBinary {
+            cb: 10,
+            lpb: ptr::null_mut(),
+        }];
+        let value = PropValue {
+            tag: PropTag(sys::PR_NULL).change_prop_type(PropType::new(sys::PT_MV_BINARY as u16)),
+            value: PropValueData::BinaryArray(Cow::Borrowed(&entry_ids)),
+        };
+        // SAFETY: A `null` `lpb` is never dereferenced.
+        let copy = unsafe { value.copy_to_arena(&arena) }.expect("copy_to_arena failed");
+        let PropValueData::BinaryArray(entry_ids) = PropValue::from(copy).value else {
+            panic!("wrong type");
+        };
+        assert_eq!(entry_ids.len(), 1);
+        assert_eq!(entry_ids[0].cb, 0);
+        assert!(entry_ids[0].lpb.is_null());
+    }
+
+    #[test]
+    fn test_as_string_unicode() {
+        let value = PropValueData::Unicode(to_pwstr_buffer("fifty-three"));
+        assert_eq!(value.as_string().as_deref(), Some("fifty-three"));
+        assert_eq!(value.as_string_lossy().as_deref(), Some("fifty-three"));
+        assert!(value.as_bytes().is_none());
+    }
+
+    #[test]
+    fn test_as_string_invalid_unicode() {
+        let value = PropValueData::Unicode(vec![0xD800, 0x41, 0]);
+        assert!(value.as_string().is_none());
+        assert_eq!(value.as_string_lossy().as_deref(), Some("\u{FFFD}A"));
+    }
+
+    #[test]
+    fn test_as_string_ansi() {
+        let value = PropValueData::AnsiString(s!("fifty-four"));
+        assert!(value.as_string().is_none());
+        assert!(value.as_bytes().is_none());
+        assert_eq!(
+            unsafe { value.as_string_in(CodePage::ACP) }.as_deref(),
+            Some("fifty-four")
+        );
+        assert_eq!(
+            unsafe { value.as_ansi_bytes() },
+            Some(b"fifty-four".as_slice())
+        );
+        let value = PropValueData::AnsiString(PCSTR::null());
+        assert!(unsafe { value.as_string_in(CodePage::ACP) }.is_none());
+    }
+
+    #[test]
+    fn test_as_bytes_binary() {
+        let data = [55_u8, 56];
+        let value = PropValueData::Binary(&data);
+        assert_eq!(value.as_bytes(), Some(data.as_slice()));
+        assert!(value.as_string().is_none());
+    }
+
+    #[test]
+    fn test_as_filetime() {
+        // 2000-01-01T00:00:00Z
+        let ticks: u64 = 125_911_584_000_000_000;
+        let value = PropValueData::FileTime(FILETIME {
+            dwLowDateTime: ticks as u32,
+            dwHighDateTime: (ticks >> 32) as u32,
+        });
+        let expected = UNIX_EPOCH + Duration::from_secs(946_684_800);
+        assert_eq!(value.as_filetime(), Some(expected));
+        assert!(PropValueData::Long(57).as_filetime().is_none());
+    }
+
+    #[test]
+    fn test_as_filetime_before_unix_epoch() {
+        let value = PropValueData::FileTime(FILETIME::default());
+        let expected = UNIX_EPOCH.checked_sub(Duration::from_secs(FILETIME_UNIX_EPOCH_SECONDS));
+        assert_eq!(value.as_filetime(), expected);
+    }
+
+    #[test]
+    fn test_from_system_time() {
+        // 2000-01-01T00:00:00.1234567Z
+        let time = UNIX_EPOCH + Duration::new(946_684_800, 123_456_789);
+        let Some(PropValueData::FileTime(value)) = PropValueData::from_system_time(time) else {
+            panic!("expected a FileTime value");
+        };
+        let ticks = (u64::from(value.dwHighDateTime) << 32) | u64::from(value.dwLowDateTime);
+        assert_eq!(ticks, 125_911_584_001_234_567);
+        assert_eq!(
+            PropValueData::FileTime(value).as_filetime(),
+            Some(UNIX_EPOCH + Duration::new(946_684_800, 123_456_700))
+        );
+
+        let before_1601 = UNIX_EPOCH
+            .checked_sub(Duration::from_secs(FILETIME_UNIX_EPOCH_SECONDS + 1))
+            .expect("SystemTime should go back to 1601");
+        assert!(PropValueData::from_system_time(before_1601).is_none());
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_date_time() {
+        use chrono::{TimeZone, Utc};
+
+        let date_time = Utc
+            .with_ymd_and_hms(2000, 1, 1, 0, 0, 0)
+            .single()
+            .expect("ambiguous date");
+        let value = PropValueData::from_date_time(&date_time).expect("valid date");
+        assert_eq!(value.as_date_time(), Some(date_time));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize() {
+        let subject = to_pwstr_buffer("Hello");
+        let values = [
+            PropValue {
+                tag: PropTag(sys::PR_SUBJECT_W),
+                value: PropValueData::Unicode(subject),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_READ),
+                value: PropValueData::Boolean(1),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_ENTRYID),
+                value: PropValueData::Binary(&[1, 2]),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_MESSAGE_DELIVERY_TIME),
+                value: PropValueData::FileTime(FILETIME {
+                    dwLowDateTime: 1,
+                    dwHighDateTime: 1,
+                }),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_BODY_W),
+                value: PropValueData::Error(sys::MAPI_E_NOT_FOUND),
+            },
+        ];
+        let json = serde_json::to_value(values).expect("serialize failed");
+        assert_eq!(
+            json,
+            serde_json::json!([
+                { "tag": sys::PR_SUBJECT_W, "value": { "Unicode": "Hello" } },
+                { "tag": sys::PR_READ, "value": { "Boolean": true } },
+                { "tag": sys::PR_ENTRYID, "value": { "Binary": [1, 2] } },
+                { "tag": sys::PR_MESSAGE_DELIVERY_TIME, "value": { "FileTime": 0x1_0000_0001_u64 } },
+                { "tag": sys::PR_BODY_W, "value": { "Error": 0x8004010F_u32 } },
+            ])
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize_raw_pointers() {
+        let value = PropValue {
+            tag: PropTag(sys::PR_SUBJECT_A),
+            value: PropValueData::AnsiString(s!("Hello")),
+        };
+        let json = serde_json::to_value(&value).expect("serialize failed");
+        assert_eq!(
+            json,
+            serde_json::json!({ "tag": sys::PR_SUBJECT_A, "value": { "AnsiString": null } })
+        );
+
+        let value = unsafe { TrustedPropValue::new(value) };
+        let json = serde_json::to_value(&value).expect("serialize failed");
+        assert_eq!(
+            json,
+            serde_json::json!({ "tag": sys::PR_SUBJECT_A, "value": { "AnsiString": "Hello" } })
+        );
+
+        let bogus = PropValueData::UnicodeArray(Cow::Owned(vec![PCWSTR(ptr::dangling())]));
+        let json = serde_json::to_value(&bogus).expect("serialize failed");
+        assert_eq!(json, serde_json::json!({ "UnicodeArray": null }));
+    }
+
+    #[test]
+    fn test_builder_send() {
+        let mut values = PropValueBuilder::new()
+            .string(PropTag(sys::PR_SUBJECT_W), "fifty-two")
+            .expect("string failed")
+            .build()
+            .expect("build failed");
+        let root = values.as_mut_ptr() as usize;
+        let subject = std::thread::spawn(move || {
+            let mut values = values;
+            let subject = values
+                .iter()
+                .next()
+                .and_then(|value| value.value.as_string());
+            drop(values);
+            subject
+        })
+        .join()
+        .expect("thread panicked");
+        assert_eq!(subject.as_deref(), Some("fifty-two"));
+        assert!(!mapi_ptr::backend::is_allocated(root as *mut _));
+    }
 }
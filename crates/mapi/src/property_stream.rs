@@ -0,0 +1,328 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`PropertyStream`] and [`PropertyReader`].
+
+use crate::{InitEpoch, ObjectKind, ObjectRegistration};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use windows::Win32::{
+    Foundation::HGLOBAL,
+    System::Com::{
+        IStream, StructuredStorage::CreateStreamOnHGlobal, STGC_DEFAULT, STREAM_SEEK,
+        STREAM_SEEK_CUR, STREAM_SEEK_END, STREAM_SEEK_SET,
+    },
+};
+use windows_core::*;
+
+/// Hold on to an [`IStream`], e.g. from [`crate::MAPIProp::open_property_stream`], and implement
+/// [`Read`], [`Write`], and [`Seek`] on top of it.
+///
+/// [`Write::flush`] calls [`IStream::Commit`], which MAPI requires before the new contents of a
+/// property stream are visible in the object. You still need to call
+/// [`crate::MAPIProp::save_changes`] on the object to keep the changes.
+pub struct PropertyStream {
+    /// Access the [`IStream`].
+    pub stream: IStream,
+
+    epoch: InitEpoch,
+    registration: ObjectRegistration,
+}
+
+impl PropertyStream {
+    /// Wrap an [`IStream`] returned from one of the [`crate::sys`] interface methods.
+    pub fn new(stream: IStream) -> Self {
+        Self::with_registration(stream, ObjectRegistration::new(ObjectKind::PropertyStream))
+    }
+
+    /// Wrap a [`IStream`] opened through another wrapper, so it belongs to the same store.
+    pub(crate) fn with_registration(stream: IStream, registration: ObjectRegistration) -> Self {
+        Self {
+            stream,
+            epoch: InitEpoch::current(),
+            registration,
+        }
+    }
+
+    /// Create an [`IStream`] on a growable memory buffer with [`CreateStreamOnHGlobal`], e.g. to
+    /// hand a buffer to an API which only reads from or writes to streams.
+    pub(crate) fn memory() -> Result<Self> {
+        let stream = unsafe { CreateStreamOnHGlobal(HGLOBAL::default(), true)? };
+        Ok(Self::new(stream))
+    }
+
+    /// Get the [`ObjectRegistration`] which tracks this stream in the [`crate::ObjectRegistry`].
+    pub fn registration(&self) -> &ObjectRegistration {
+        &self.registration
+    }
+
+    /// Make sure MAPI is still initialized and the store is still connected.
+    fn check(&self) -> Result<()> {
+        self.epoch.check()?;
+        Ok(self.registration.check_connected()?)
+    }
+
+    /// Call [`IStream::Commit`] to make the changes visible in the object which opened the stream.
+    pub fn commit(&self) -> Result<()> {
+        self.check()?;
+        unsafe { self.stream.Commit(STGC_DEFAULT) }
+    }
+
+    /// Call [`IStream::SetSize`], e.g. to truncate the stream before writing a shorter value.
+    pub fn set_size(&self, size: u64) -> Result<()> {
+        self.check()?;
+        unsafe { self.stream.SetSize(size) }
+    }
+}
+
+impl Read for PropertyStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.check()?;
+        let count = u32::try_from(buf.len()).unwrap_or(u32::MAX);
+        let mut read = 0;
+        unsafe {
+            self.stream
+                .Read(buf.as_mut_ptr() as *mut _, count, Some(&mut read))
+                .ok()?;
+        }
+        Ok(read as usize)
+    }
+}
+
+impl Write for PropertyStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check()?;
+        let count = u32::try_from(buf.len()).unwrap_or(u32::MAX);
+        let mut written = 0;
+        unsafe {
+            self.stream
+                .Write(buf.as_ptr() as *const _, count, Some(&mut written))
+                .ok()?;
+        }
+        Ok(written as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(self.commit()?)
+    }
+}
+
+impl Seek for PropertyStream {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.check()?;
+        let (offset, origin): (i64, STREAM_SEEK) = match pos {
+            SeekFrom::Start(offset) => (
+                i64::try_from(offset).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?,
+                STREAM_SEEK_SET,
+            ),
+            SeekFrom::Current(offset) => (offset, STREAM_SEEK_CUR),
+            SeekFrom::End(offset) => (offset, STREAM_SEEK_END),
+        };
+        let mut position = 0;
+        unsafe {
+            self.stream.Seek(offset, origin, Some(&mut position))?;
+        }
+        Ok(position)
+    }
+}
+
+impl From<IStream> for PropertyStream {
+    fn from(value: IStream) -> Self {
+        Self::new(value)
+    }
+}
+
+/// Read a [`PropertyStream`] without asking [`IStream::Read`] for more than `chunk_size` bytes
+/// at a time, e.g. from [`crate::MAPIProp::stream_property`].
+///
+/// Some providers fail or allocate a buffer for the whole request when a single read asks for a
+/// very large body, so this keeps each call bounded no matter how big the buffer passed to
+/// [`Read::read`] is.
+pub struct PropertyReader {
+    stream: PropertyStream,
+    chunk_size: usize,
+}
+
+impl PropertyReader {
+    /// Number of bytes [`crate::MAPIProp::read_stream_property`] reads with each call.
+    pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+    /// Wrap a [`PropertyStream`] and read at most `chunk_size` bytes with each call. A
+    /// `chunk_size` of 0 is treated as 1.
+    pub fn new(stream: PropertyStream, chunk_size: usize) -> Self {
+        Self {
+            stream,
+            chunk_size: chunk_size.max(1),
+        }
+    }
+
+    /// Get the maximum number of bytes read with each call to [`IStream::Read`].
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Access the [`PropertyStream`], e.g. to [`Seek`] in it.
+    pub fn get_mut(&mut self) -> &mut PropertyStream {
+        &mut self.stream
+    }
+
+    /// Release the [`PropertyStream`].
+    pub fn into_inner(self) -> PropertyStream {
+        self.stream
+    }
+}
+
+impl Read for PropertyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.chunk_size);
+        self.stream.read(&mut buf[..len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use windows::Win32::{
+        Foundation::*,
+        System::Com::{ISequentialStream_Impl, IStream_Impl, LOCKTYPE, STATFLAG, STATSTG, STGC},
+    };
+
+    /// Minimal in-memory [`IStream`] for testing the adapters.
+    #[implement(IStream)]
+    struct MemoryStream(Mutex<(Vec<u8>, usize)>);
+
+    impl ISequentialStream_Impl for MemoryStream_Impl {
+        fn Read(&self, pv: *mut core::ffi::c_void, cb: u32, pcbread: *mut u32) -> HRESULT {
+            let mut state = self.0.lock().unwrap();
+            let (data, position) = &mut *state;
+            let start = (*position).min(data.len());
+            let count = (cb as usize).min(data.len() - start);
+            unsafe {
+                core::ptr::copy_nonoverlapping(data[start..].as_ptr(), pv as *mut u8, count);
+                if !pcbread.is_null() {
+                    *pcbread = count as u32;
+                }
+            }
+            *position = start + count;
+            S_OK
+        }
+
+        fn Write(&self, pv: *const core::ffi::c_void, cb: u32, pcbwritten: *mut u32) -> HRESULT {
+            let mut state = self.0.lock().unwrap();
+            let (data, position) = &mut *state;
+            let bytes = unsafe { core::slice::from_raw_parts(pv as *const u8, cb as usize) };
+            let end = *position + bytes.len();
+            if data.len() < end {
+                data.resize(end, 0);
+            }
+            data[*position..end].copy_from_slice(bytes);
+            *position = end;
+            if !pcbwritten.is_null() {
+                unsafe {
+                    *pcbwritten = cb;
+                }
+            }
+            S_OK
+        }
+    }
+
+    impl IStream_Impl for MemoryStream_Impl {
+        fn Seek(
+            &self,
+            dlibmove: i64,
+            dworigin: STREAM_SEEK,
+            plibnewposition: *mut u64,
+        ) -> Result<()> {
+            let mut state = self.0.lock().unwrap();
+            let (data, position) = &mut *state;
+            let base = match dworigin {
+                STREAM_SEEK_SET => 0,
+                STREAM_SEEK_CUR => *position as i64,
+                STREAM_SEEK_END => data.len() as i64,
+                _ => return Err(Error::from(STG_E_INVALIDFUNCTION)),
+            };
+            let new_position = base + dlibmove;
+            if new_position < 0 {
+                return Err(Error::from(STG_E_INVALIDFUNCTION));
+            }
+            *position = new_position as usize;
+            if !plibnewposition.is_null() {
+                unsafe {
+                    *plibnewposition = new_position as u64;
+                }
+            }
+            Ok(())
+        }
+
+        fn SetSize(&self, libnewsize: u64) -> Result<()> {
+            self.0.lock().unwrap().0.resize(libnewsize as usize, 0);
+            Ok(())
+        }
+
+        fn CopyTo(&self, _: Ref<'_, IStream>, _: u64, _: *mut u64, _: *mut u64) -> Result<()> {
+            Err(Error::from(E_NOTIMPL))
+        }
+
+        fn Commit(&self, _: &STGC) -> Result<()> {
+            Ok(())
+        }
+
+        fn Revert(&self) -> Result<()> {
+            Err(Error::from(E_NOTIMPL))
+        }
+
+        fn LockRegion(&self, _: u64, _: u64, _: &LOCKTYPE) -> Result<()> {
+            Err(Error::from(E_NOTIMPL))
+        }
+
+        fn UnlockRegion(&self, _: u64, _: u64, _: u32) -> Result<()> {
+            Err(Error::from(E_NOTIMPL))
+        }
+
+        fn Stat(&self, _: *mut STATSTG, _: &STATFLAG) -> Result<()> {
+            Err(Error::from(E_NOTIMPL))
+        }
+
+        fn Clone(&self) -> Result<IStream> {
+            Err(Error::from(E_NOTIMPL))
+        }
+    }
+
+    #[test]
+    fn read_write_seek() {
+        let mut stream = PropertyStream::new(MemoryStream(Mutex::new((Vec::new(), 0))).into());
+        stream.write_all(b"Hello, world!").expect("write failed");
+        stream.flush().expect("flush failed");
+
+        assert_eq!(stream.seek(SeekFrom::Start(7)).expect("seek failed"), 7);
+        let mut buffer = String::new();
+        stream.read_to_string(&mut buffer).expect("read failed");
+        assert_eq!(buffer, "world!");
+
+        assert_eq!(stream.seek(SeekFrom::End(-6)).expect("seek failed"), 7);
+        stream.write_all(b"MAPI!!").expect("write failed");
+        stream.rewind().expect("rewind failed");
+        let mut buffer = Vec::new();
+        stream.read_to_end(&mut buffer).expect("read failed");
+        assert_eq!(buffer, b"Hello, MAPI!!");
+
+        assert!(stream.seek(SeekFrom::Current(-100)).is_err());
+    }
+
+    #[test]
+    fn read_in_chunks() {
+        let stream = MemoryStream(Mutex::new((b"Hello, world!".to_vec(), 0)));
+        let mut reader = PropertyReader::new(PropertyStream::new(stream.into()), 4);
+        assert_eq!(reader.chunk_size(), 4);
+
+        let mut buffer = [0_u8; 64];
+        assert_eq!(reader.read(&mut buffer).expect("read failed"), 4);
+        assert_eq!(&buffer[..4], b"Hell");
+
+        let mut buffer = Vec::new();
+        reader.read_to_end(&mut buffer).expect("read failed");
+        assert_eq!(buffer, b"o, world!");
+
+        assert_eq!(PropertyReader::new(reader.into_inner(), 0).chunk_size(), 1);
+    }
+}
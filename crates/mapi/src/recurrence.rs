@@ -0,0 +1,1036 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`Recurrence`], [`RecurFrequency`], [`RecurPattern`], [`RecurEnd`], [`DaysOfWeek`],
+//! [`RecurrenceException`], [`Occurrence`], and [`Occurrences`].
+//!
+//! A recurring appointment stores its pattern in the `PidLidAppointmentRecur` named property, as
+//! the binary `AppointmentRecurrencePattern` structure described in [MS-OXOCAL]. It starts with
+//! the `RecurrencePattern` shared with recurring tasks, followed by the time of day and the
+//! details of each modified instance. All of the dates and times in the blob are in minutes since
+//! January 1, 1601, in the time zone of the appointment, so the [`SystemTime`] values here are
+//! local times which should be read as if they were UTC.
+//!
+//! [MS-OXOCAL]: https://learn.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxocal
+
+use crate::{
+    banner,
+    free_busy::{from_rtime, month_of, month_start, to_rtime, MINUTES_PER_DAY},
+    sys, CodePage, MAPIOutParam, MAPIProp, Message, PropTag, PropType,
+};
+use core::{ops::Range, ptr};
+use std::{collections::VecDeque, time::SystemTime};
+use windows::Win32::Foundation::*;
+use windows_core::*;
+
+/// `PidLidAppointmentRecur` in the [`sys::PSETID_Appointment`] property set.
+const LID_APPOINTMENT_RECUR: i32 = 0x8216;
+
+const RECUR_FREQUENCY_DAILY: u16 = 0x200A;
+const RECUR_FREQUENCY_WEEKLY: u16 = 0x200B;
+const RECUR_FREQUENCY_MONTHLY: u16 = 0x200C;
+const RECUR_FREQUENCY_YEARLY: u16 = 0x200D;
+
+const PATTERN_TYPE_DAY: u16 = 0x0000;
+const PATTERN_TYPE_WEEK: u16 = 0x0001;
+const PATTERN_TYPE_MONTH: u16 = 0x0002;
+const PATTERN_TYPE_MONTH_NTH: u16 = 0x0003;
+const PATTERN_TYPE_MONTH_END: u16 = 0x0004;
+const PATTERN_TYPE_HJ_MONTH: u16 = 0x000A;
+const PATTERN_TYPE_HJ_MONTH_NTH: u16 = 0x000B;
+const PATTERN_TYPE_HJ_MONTH_END: u16 = 0x000C;
+
+const END_AFTER_DATE: u32 = 0x2021;
+const END_AFTER_N_OCCURRENCES: u32 = 0x2022;
+const END_NEVER: u32 = 0x2023;
+const END_NEVER_ALT: u32 = 0xFFFF_FFFF;
+
+/// `CAL_DEFAULT`, which is the same as `CAL_GREGORIAN` for expanding occurrences.
+const CALENDAR_DEFAULT: u16 = 0x0000;
+
+/// `CAL_GREGORIAN`
+const CALENDAR_GREGORIAN: u16 = 0x0001;
+
+const ARO_SUBJECT: u16 = 0x0001;
+const ARO_MEETINGTYPE: u16 = 0x0002;
+const ARO_REMINDERDELTA: u16 = 0x0004;
+const ARO_REMINDER: u16 = 0x0008;
+const ARO_LOCATION: u16 = 0x0010;
+const ARO_BUSYSTATUS: u16 = 0x0020;
+const ARO_ATTACHMENT: u16 = 0x0040;
+const ARO_SUBTYPE: u16 = 0x0080;
+const ARO_APPTCOLOR: u16 = 0x0100;
+const ARO_EXCEPTIONAL_BODY: u16 = 0x0200;
+
+/// First `WriterVersion2` which writes the `ChangeHighlight` field in each `ExtendedException`.
+const WRITER_VERSION2_CHANGE_HIGHLIGHT: u32 = 0x3009;
+
+/// `RecurFrequency` from the `RecurrencePattern`. Yearly patterns are stored as monthly patterns
+/// with a [`Recurrence::period`] of 12, so this only matters for how the pattern is described.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecurFrequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// Days of the week from a [`RecurPattern::Week`] or [`RecurPattern::MonthNth`] pattern, with
+/// Sunday in the lowest bit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct DaysOfWeek(pub u32);
+
+impl DaysOfWeek {
+    pub const SUNDAY: u32 = 0x01;
+    pub const MONDAY: u32 = 0x02;
+    pub const TUESDAY: u32 = 0x04;
+    pub const WEDNESDAY: u32 = 0x08;
+    pub const THURSDAY: u32 = 0x10;
+    pub const FRIDAY: u32 = 0x20;
+    pub const SATURDAY: u32 = 0x40;
+
+    /// Test if the `weekday`, counting from 0 for Sunday, is in the set.
+    pub fn contains(&self, weekday: u32) -> bool {
+        weekday < 7 && self.0 & (1 << weekday) != 0
+    }
+}
+
+impl From<u32> for DaysOfWeek {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+/// `PatternType` and `PatternTypeSpecific` from the `RecurrencePattern`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecurPattern {
+    /// Every [`Recurrence::period`] minutes, which is always a whole number of days.
+    Day,
+
+    /// On the `DaysOfWeek` every [`Recurrence::period`] weeks.
+    Week(DaysOfWeek),
+
+    /// On the `day` of the month every [`Recurrence::period`] months, or the last day of shorter
+    /// months.
+    Month { day: u32 },
+
+    /// On the `nth` matching day of the month every [`Recurrence::period`] months, where an `nth`
+    /// of 5 means the last one, e.g. the second Tuesday or the last weekday.
+    MonthNth { days: DaysOfWeek, nth: u32 },
+
+    /// On the last day of the month every [`Recurrence::period`] months.
+    MonthEnd,
+
+    /// Same as [`RecurPattern::Month`] in the Hijri calendar.
+    HijriMonth { day: u32 },
+
+    /// Same as [`RecurPattern::MonthNth`] in the Hijri calendar.
+    HijriMonthNth { days: DaysOfWeek, nth: u32 },
+
+    /// Same as [`RecurPattern::MonthEnd`] in the Hijri calendar.
+    HijriMonthEnd,
+}
+
+/// `EndType` from the `RecurrencePattern`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecurEnd {
+    /// End on the [`Recurrence::end_date`].
+    AfterDate,
+
+    /// End after this many occurrences, including deleted ones. The [`Recurrence::end_date`] is
+    /// the date of the last one.
+    AfterCount(u32),
+
+    /// Never end.
+    Never,
+}
+
+/// `ExceptionInfo` and `ExtendedException` for a modified instance of a [`Recurrence`]. Fields
+/// which are [`None`] are the same as the recurring appointment.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RecurrenceException {
+    /// Start of the instance before it was modified.
+    pub original_start: Option<SystemTime>,
+
+    /// Start of the modified instance.
+    pub start: Option<SystemTime>,
+
+    /// End of the modified instance.
+    pub end: Option<SystemTime>,
+
+    /// `ARO_SUBJECT`
+    pub subject: Option<String>,
+
+    /// `ARO_LOCATION`
+    pub location: Option<String>,
+
+    /// `ARO_MEETINGTYPE`
+    pub meeting_type: Option<u32>,
+
+    /// `ARO_REMINDERDELTA`, in minutes before the start.
+    pub reminder_delta: Option<u32>,
+
+    /// `ARO_REMINDER`
+    pub reminder_set: Option<bool>,
+
+    /// `ARO_BUSYSTATUS`
+    pub busy_status: Option<u32>,
+
+    /// `ARO_ATTACHMENT`
+    pub has_attachment: Option<bool>,
+
+    /// `ARO_SUBTYPE`, which is set for all day events.
+    pub all_day: Option<bool>,
+
+    /// `ARO_APPTCOLOR`
+    pub color: Option<u32>,
+
+    /// `ARO_EXCEPTIONAL_BODY`, the instance has its own body, which is only stored in the
+    /// exception attachment.
+    pub exceptional_body: bool,
+}
+
+/// Decoded `AppointmentRecurrencePattern` from `PidLidAppointmentRecur`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Recurrence {
+    /// `RecurFrequency`
+    pub frequency: RecurFrequency,
+
+    /// `PatternType` and `PatternTypeSpecific`.
+    pub pattern: RecurPattern,
+
+    /// `CalendarType`, e.g. `CAL_GREGORIAN`.
+    pub calendar_type: u16,
+
+    /// `Period`, the interval between occurrences, in minutes for [`RecurPattern::Day`], weeks
+    /// for [`RecurPattern::Week`], and months for the monthly patterns.
+    pub period: u32,
+
+    /// `SlidingFlag`, which is only used by recurring tasks.
+    pub sliding: bool,
+
+    /// `EndType` and `OccurrenceCount`.
+    pub end: RecurEnd,
+
+    /// `FirstDOW`, the first day of the week, counting from 0 for Sunday.
+    pub first_day_of_week: u32,
+
+    /// `DeletedInstanceDates`, the original dates of deleted and modified instances.
+    pub deleted_instances: Vec<SystemTime>,
+
+    /// `ModifiedInstanceDates`, the dates of modified instances.
+    pub modified_instances: Vec<SystemTime>,
+
+    /// `StartDate`, midnight on the date of the first occurrence.
+    pub start_date: SystemTime,
+
+    /// `EndDate`, midnight on the date of the last occurrence. This is a placeholder far in the
+    /// future if the [`Recurrence::end`] is [`RecurEnd::Never`].
+    pub end_date: SystemTime,
+
+    /// `StartTimeOffset`, the start of each occurrence in minutes after midnight.
+    pub start_time_offset: u32,
+
+    /// `EndTimeOffset`, the end of each occurrence in minutes after midnight on the start date.
+    pub end_time_offset: u32,
+
+    /// `ExceptionInfo` and `ExtendedException` for each modified instance.
+    pub exceptions: Vec<RecurrenceException>,
+}
+
+impl Recurrence {
+    /// Read and decode `PidLidAppointmentRecur` from an appointment `message`, or return [`None`]
+    /// if it is not a recurring appointment.
+    pub fn read(message: &Message) -> Result<Option<Self>> {
+        let Some(tag) = appointment_recur_tag(message)? else {
+            return Ok(None);
+        };
+        match banner::read_property(message, tag.0) {
+            Ok(blob) => Self::parse(&blob).map(Some),
+            Err(error) if error.code() == sys::MAPI_E_NOT_FOUND => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Decode an `AppointmentRecurrencePattern`. A plain `RecurrencePattern`, e.g. from a
+    /// recurring task, is also accepted, with no time of day or exceptions. Malformed data
+    /// returns [`sys::MAPI_E_CORRUPT_DATA`].
+    pub fn parse(blob: &[u8]) -> Result<Self> {
+        let mut reader = BlobReader { data: blob };
+        let _reader_version = reader.u16()?;
+        let _writer_version = reader.u16()?;
+        let frequency = match reader.u16()? {
+            RECUR_FREQUENCY_DAILY => RecurFrequency::Daily,
+            RECUR_FREQUENCY_WEEKLY => RecurFrequency::Weekly,
+            RECUR_FREQUENCY_MONTHLY => RecurFrequency::Monthly,
+            RECUR_FREQUENCY_YEARLY => RecurFrequency::Yearly,
+            _ => return Err(corrupt_data()),
+        };
+        let pattern_type = reader.u16()?;
+        let calendar_type = reader.u16()?;
+        let _first_date_time = reader.u32()?;
+        let period = reader.u32()?;
+        let sliding = reader.u32()? != 0;
+        let pattern = match pattern_type {
+            PATTERN_TYPE_DAY => RecurPattern::Day,
+            PATTERN_TYPE_WEEK => RecurPattern::Week(DaysOfWeek(reader.u32()?)),
+            PATTERN_TYPE_MONTH => RecurPattern::Month { day: reader.u32()? },
+            PATTERN_TYPE_MONTH_NTH => RecurPattern::MonthNth {
+                days: DaysOfWeek(reader.u32()?),
+                nth: reader.u32()?,
+            },
+            PATTERN_TYPE_MONTH_END => {
+                let _day = reader.u32()?;
+                RecurPattern::MonthEnd
+            }
+            PATTERN_TYPE_HJ_MONTH => RecurPattern::HijriMonth { day: reader.u32()? },
+            PATTERN_TYPE_HJ_MONTH_NTH => RecurPattern::HijriMonthNth {
+                days: DaysOfWeek(reader.u32()?),
+                nth: reader.u32()?,
+            },
+            PATTERN_TYPE_HJ_MONTH_END => {
+                let _day = reader.u32()?;
+                RecurPattern::HijriMonthEnd
+            }
+            _ => return Err(corrupt_data()),
+        };
+        let end_type = reader.u32()?;
+        let occurrence_count = reader.u32()?;
+        let end = match end_type {
+            END_AFTER_DATE => RecurEnd::AfterDate,
+            END_AFTER_N_OCCURRENCES => RecurEnd::AfterCount(occurrence_count),
+            END_NEVER | END_NEVER_ALT => RecurEnd::Never,
+            _ => return Err(corrupt_data()),
+        };
+        let first_day_of_week = reader.u32()?;
+        let deleted_instances = reader.dates()?;
+        let modified_instances = reader.dates()?;
+        let start_date = from_rtime(i64::from(reader.u32()?));
+        let end_date = from_rtime(i64::from(reader.u32()?));
+
+        let mut recurrence = Self {
+            frequency,
+            pattern,
+            calendar_type,
+            period,
+            sliding,
+            end,
+            first_day_of_week,
+            deleted_instances,
+            modified_instances,
+            start_date,
+            end_date,
+            start_time_offset: 0,
+            end_time_offset: 0,
+            exceptions: Vec::new(),
+        };
+        if reader.data.is_empty() {
+            return Ok(recurrence);
+        }
+
+        let _reader_version2 = reader.u32()?;
+        let writer_version2 = reader.u32()?;
+        recurrence.start_time_offset = reader.u32()?;
+        recurrence.end_time_offset = reader.u32()?;
+        let count = reader.u16()?;
+        let mut exceptions = (0..count)
+            .map(|_| ExceptionInfo::read(&mut reader))
+            .collect::<Result<Vec<_>>>()?;
+        reader.reserved_block()?;
+        if !reader.data.is_empty() {
+            for exception in exceptions.iter_mut() {
+                exception.read_extended(&mut reader, writer_version2)?;
+            }
+            reader.reserved_block()?;
+        }
+        recurrence.exceptions = exceptions
+            .into_iter()
+            .map(RecurrenceException::from)
+            .collect();
+        Ok(recurrence)
+    }
+
+    /// Expand the occurrences which overlap the `range`, in order of their start times. Deleted
+    /// instances are skipped, and modified instances are returned with the times from their
+    /// [`RecurrenceException`], even if they were moved from outside the `range`.
+    ///
+    /// Hijri patterns and other non-Gregorian calendars return [`sys::MAPI_E_NO_SUPPORT`].
+    pub fn occurrences(&self, range: Range<SystemTime>) -> Result<Occurrences<'_>> {
+        if !matches!(self.calendar_type, CALENDAR_DEFAULT | CALENDAR_GREGORIAN)
+            || matches!(
+                self.pattern,
+                RecurPattern::HijriMonth { .. }
+                    | RecurPattern::HijriMonthNth { .. }
+                    | RecurPattern::HijriMonthEnd
+            )
+        {
+            return Err(Error::from(sys::MAPI_E_NO_SUPPORT));
+        }
+
+        let range = to_rtime(range.start)..to_rtime(range.end);
+        let mut exceptions: Vec<_> = self
+            .exceptions
+            .iter()
+            .filter_map(|exception| {
+                let start = to_rtime(exception.start?);
+                let end = exception.end.map(to_rtime).unwrap_or(start);
+                overlaps(&range, start, end).then_some((start, exception))
+            })
+            .collect();
+        exceptions.sort_by_key(|(start, _)| *start);
+
+        let mut deleted_days: Vec<_> = self
+            .deleted_instances
+            .iter()
+            .map(|date| to_rtime(*date).div_euclid(MINUTES_PER_DAY))
+            .collect();
+        deleted_days.sort_unstable();
+
+        Ok(Occurrences {
+            recurrence: self,
+            dates: PatternDates::new(self),
+            range,
+            deleted_days,
+            exceptions: exceptions.into(),
+            count: 0,
+            done: false,
+        })
+    }
+}
+
+/// Instance of a [`Recurrence`] returned from [`Occurrences`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Occurrence<'a> {
+    /// Start of the instance.
+    pub start: SystemTime,
+
+    /// End of the instance.
+    pub end: SystemTime,
+
+    /// Start of the instance according to the pattern, which is different from the
+    /// [`Occurrence::start`] if the instance was moved.
+    pub original_start: SystemTime,
+
+    /// Details of the modified instance, if it is an exception.
+    pub exception: Option<&'a RecurrenceException>,
+}
+
+/// Iterator returned from [`Recurrence::occurrences`].
+pub struct Occurrences<'a> {
+    recurrence: &'a Recurrence,
+    dates: PatternDates,
+    range: Range<i64>,
+    deleted_days: Vec<i64>,
+    exceptions: VecDeque<(i64, &'a RecurrenceException)>,
+    count: u32,
+    done: bool,
+}
+
+impl<'a> Occurrences<'a> {
+    /// Get the start and end of the next instance from the pattern which overlaps the range and
+    /// has not been deleted, in minutes.
+    fn next_regular(&mut self) -> Option<(i64, i64)> {
+        let end_day = to_rtime(self.recurrence.end_date).div_euclid(MINUTES_PER_DAY);
+        while !self.done {
+            let Some(day) = self.dates.next() else {
+                self.done = true;
+                break;
+            };
+            self.count += 1;
+            match self.recurrence.end {
+                RecurEnd::AfterCount(count) if self.count > count => {
+                    self.done = true;
+                    break;
+                }
+                RecurEnd::AfterDate | RecurEnd::AfterCount(_) if day > end_day => {
+                    self.done = true;
+                    break;
+                }
+                _ => {}
+            }
+
+            let start = day * MINUTES_PER_DAY + i64::from(self.recurrence.start_time_offset);
+            let end = day * MINUTES_PER_DAY + i64::from(self.recurrence.end_time_offset);
+            if start >= self.range.end {
+                self.done = true;
+                break;
+            }
+            if self.deleted_days.binary_search(&day).is_err() && overlaps(&self.range, start, end) {
+                return Some((start, end));
+            }
+        }
+        None
+    }
+
+    fn exception_occurrence(exception: &'a RecurrenceException) -> Occurrence<'a> {
+        let start = exception.start.unwrap_or(SystemTime::UNIX_EPOCH);
+        Occurrence {
+            start,
+            end: exception.end.unwrap_or(start),
+            original_start: exception.original_start.unwrap_or(start),
+            exception: Some(exception),
+        }
+    }
+}
+
+impl<'a> Iterator for Occurrences<'a> {
+    type Item = Occurrence<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let regular = self.next_regular();
+        match (regular, self.exceptions.front()) {
+            (Some((start, _)), Some((exception_start, _))) if *exception_start <= start => {
+                // Put the regular instance back, it comes after the exception.
+                self.dates.push_front(start.div_euclid(MINUTES_PER_DAY));
+                self.count -= 1;
+                self.done = false;
+                let (_, exception) = self.exceptions.pop_front()?;
+                Some(Self::exception_occurrence(exception))
+            }
+            (Some((start, end)), _) => Some(Occurrence {
+                start: from_rtime(start),
+                end: from_rtime(end),
+                original_start: from_rtime(start),
+                exception: None,
+            }),
+            (None, Some(_)) => {
+                let (_, exception) = self.exceptions.pop_front()?;
+                Some(Self::exception_occurrence(exception))
+            }
+            (None, None) => None,
+        }
+    }
+}
+
+/// Generate the days since January 1, 1601 which match the pattern of a [`Recurrence`], starting
+/// from the [`Recurrence::start_date`], one cycle of the pattern at a time.
+struct PatternDates {
+    pattern: RecurPattern,
+    period: i64,
+    start_day: i64,
+    first_day_of_week: i64,
+    cycle: i64,
+    pending: VecDeque<i64>,
+}
+
+/// Stop looking for a matching day after this many empty cycles, e.g. a `MonthNth` pattern which
+/// asks for a day that no month has.
+const MAX_EMPTY_CYCLES: usize = 1000;
+
+impl PatternDates {
+    fn new(recurrence: &Recurrence) -> Self {
+        Self {
+            pattern: recurrence.pattern,
+            period: i64::from(recurrence.period.max(1)),
+            start_day: to_rtime(recurrence.start_date).div_euclid(MINUTES_PER_DAY),
+            first_day_of_week: i64::from(recurrence.first_day_of_week % 7),
+            cycle: 0,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn push_front(&mut self, day: i64) {
+        self.pending.push_front(day);
+    }
+
+    /// Add the matching days in the next cycle of the pattern to `pending`.
+    fn fill_cycle(&mut self) {
+        let cycle = self.cycle;
+        self.cycle += 1;
+        match self.pattern {
+            RecurPattern::Day => {
+                let days = (self.period / MINUTES_PER_DAY).max(1);
+                self.pending.push_back(self.start_day + cycle * days);
+            }
+            RecurPattern::Week(days) => {
+                let offset = (weekday(self.start_day) - self.first_day_of_week).rem_euclid(7);
+                let week_start = self.start_day - offset + cycle * self.period * 7;
+                self.pending.extend(
+                    (week_start..week_start + 7).filter(|day| {
+                        *day >= self.start_day && days.contains(weekday(*day) as u32)
+                    }),
+                );
+            }
+            RecurPattern::Month { day } => {
+                let (first, length) = self.month(cycle);
+                let day = i64::from(day.max(1)).min(length);
+                self.push_if_started(first + day - 1);
+            }
+            RecurPattern::MonthEnd => {
+                let (first, length) = self.month(cycle);
+                self.push_if_started(first + length - 1);
+            }
+            RecurPattern::MonthNth { days, nth } => {
+                let (first, length) = self.month(cycle);
+                let mut matching =
+                    (first..first + length).filter(|day| days.contains(weekday(*day) as u32));
+                let day = if nth >= 5 {
+                    matching.next_back()
+                } else {
+                    matching.nth(nth.max(1) as usize - 1)
+                };
+                if let Some(day) = day {
+                    self.push_if_started(day);
+                }
+            }
+            RecurPattern::HijriMonth { .. }
+            | RecurPattern::HijriMonthNth { .. }
+            | RecurPattern::HijriMonthEnd => {}
+        }
+    }
+
+    /// Get the first day and the length of the month in the `cycle`.
+    fn month(&self, cycle: i64) -> (i64, i64) {
+        let (year, month) = month_of(self.start_day * MINUTES_PER_DAY);
+        let index = year * 12 + month - 1 + cycle * self.period;
+        let (year, month) = (index.div_euclid(12), index.rem_euclid(12) + 1);
+        let first = month_start(year, month) / MINUTES_PER_DAY;
+        let next = if month == 12 {
+            month_start(year + 1, 1)
+        } else {
+            month_start(year, month + 1)
+        } / MINUTES_PER_DAY;
+        (first, next - first)
+    }
+
+    fn push_if_started(&mut self, day: i64) {
+        if day >= self.start_day {
+            self.pending.push_back(day);
+        }
+    }
+}
+
+impl Iterator for PatternDates {
+    type Item = i64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for _ in 0..MAX_EMPTY_CYCLES {
+            if let Some(day) = self.pending.pop_front() {
+                return Some(day);
+            }
+            self.fill_cycle();
+        }
+        None
+    }
+}
+
+/// Get the day of the week for a number of days since January 1, 1601, which was a Monday,
+/// counting from 0 for Sunday.
+fn weekday(day: i64) -> i64 {
+    (day + 1).rem_euclid(7)
+}
+
+/// Test if an instance from `start` to `end` overlaps the `range`. Instances with no duration
+/// overlap if they start in the `range`.
+fn overlaps(range: &Range<i64>, start: i64, end: i64) -> bool {
+    start < range.end && (end > range.start || (start == end && start >= range.start))
+}
+
+fn corrupt_data() -> Error {
+    Error::from(sys::MAPI_E_CORRUPT_DATA)
+}
+
+/// Look up the property tag for `PidLidAppointmentRecur` without creating it.
+fn appointment_recur_tag(message: &Message) -> Result<Option<PropTag>> {
+    let mut name = sys::MAPINAMEID {
+        lpguid: &sys::PSETID_Appointment as *const _ as *mut _,
+        ulKind: sys::MNID_ID,
+        Kind: sys::MAPINAMEID_0 {
+            lID: LID_APPOINTMENT_RECUR,
+        },
+    };
+    let mut names = [ptr::from_mut(&mut name)];
+    let mut prop_tags: MAPIOutParam<sys::SPropTagArray> = MAPIOutParam::new();
+    unsafe {
+        message.mapi_prop().GetIDsFromNames(
+            names.len() as u32,
+            names.as_mut_ptr(),
+            0,
+            prop_tags.as_mut_ptr(),
+        )?;
+        let prop_tags = prop_tags.as_mut().ok_or_else(|| Error::from(E_POINTER))?;
+        if prop_tags.cValues < 1 {
+            return Ok(None);
+        }
+        let tag = PropTag(prop_tags.aulPropTag[0]);
+        if u32::from(tag.prop_type()) == sys::PT_ERROR {
+            return Ok(None);
+        }
+        Ok(Some(
+            tag.change_prop_type(PropType::new(sys::PT_BINARY as u16)),
+        ))
+    }
+}
+
+/// Read little-endian fields from the front of a recurrence blob.
+struct BlobReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> BlobReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(corrupt_data());
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Read a count followed by that many dates.
+    fn dates(&mut self) -> Result<Vec<SystemTime>> {
+        let count = self.u32()? as usize;
+        if count > self.data.len() / 4 {
+            return Err(corrupt_data());
+        }
+        (0..count)
+            .map(|_| Ok(from_rtime(i64::from(self.u32()?))))
+            .collect()
+    }
+
+    /// Skip a size followed by that many reserved bytes.
+    fn reserved_block(&mut self) -> Result<()> {
+        let size = self.u32()? as usize;
+        self.bytes(size)?;
+        Ok(())
+    }
+
+    /// Read an 8-bit string with `Length` and `Length2` fields.
+    fn ansi_string(&mut self) -> Result<&'a [u8]> {
+        let _length = self.u16()?;
+        let length = self.u16()? as usize;
+        self.bytes(length)
+    }
+
+    /// Read a UTF-16 string with a length in characters.
+    fn wide_string(&mut self) -> Result<String> {
+        let length = self.u16()? as usize;
+        let bytes = self.bytes(length * 2)?;
+        let chars: Vec<_> = bytes
+            .chunks_exact(2)
+            .map(|ch| u16::from_le_bytes([ch[0], ch[1]]))
+            .collect();
+        String::from_utf16(&chars).map_err(|_| corrupt_data())
+    }
+}
+
+/// `ExceptionInfo` with the 8-bit strings, which are replaced by the `ExtendedException` strings
+/// if the blob has them.
+struct ExceptionInfo<'a> {
+    start: u32,
+    end: u32,
+    original_start: u32,
+    flags: u16,
+    ansi_subject: Option<&'a [u8]>,
+    ansi_location: Option<&'a [u8]>,
+    exception: RecurrenceException,
+}
+
+impl<'a> ExceptionInfo<'a> {
+    fn read(reader: &mut BlobReader<'a>) -> Result<Self> {
+        let start = reader.u32()?;
+        let end = reader.u32()?;
+        let original_start = reader.u32()?;
+        let flags = reader.u16()?;
+        let mut info = Self {
+            start,
+            end,
+            original_start,
+            flags,
+            ansi_subject: None,
+            ansi_location: None,
+            exception: RecurrenceException {
+                exceptional_body: flags & ARO_EXCEPTIONAL_BODY != 0,
+                ..Default::default()
+            },
+        };
+        if flags & ARO_SUBJECT != 0 {
+            info.ansi_subject = Some(reader.ansi_string()?);
+        }
+        if flags & ARO_MEETINGTYPE != 0 {
+            info.exception.meeting_type = Some(reader.u32()?);
+        }
+        if flags & ARO_REMINDERDELTA != 0 {
+            info.exception.reminder_delta = Some(reader.u32()?);
+        }
+        if flags & ARO_REMINDER != 0 {
+            info.exception.reminder_set = Some(reader.u32()? != 0);
+        }
+        if flags & ARO_LOCATION != 0 {
+            info.ansi_location = Some(reader.ansi_string()?);
+        }
+        if flags & ARO_BUSYSTATUS != 0 {
+            info.exception.busy_status = Some(reader.u32()?);
+        }
+        if flags & ARO_ATTACHMENT != 0 {
+            info.exception.has_attachment = Some(reader.u32()? != 0);
+        }
+        if flags & ARO_SUBTYPE != 0 {
+            info.exception.all_day = Some(reader.u32()? != 0);
+        }
+        if flags & ARO_APPTCOLOR != 0 {
+            info.exception.color = Some(reader.u32()?);
+        }
+        Ok(info)
+    }
+
+    /// Read the matching `ExtendedException`.
+    fn read_extended(&mut self, reader: &mut BlobReader<'a>, writer_version2: u32) -> Result<()> {
+        if writer_version2 >= WRITER_VERSION2_CHANGE_HIGHLIGHT {
+            reader.reserved_block()?;
+        }
+        reader.reserved_block()?;
+        if self.flags & (ARO_SUBJECT | ARO_LOCATION) != 0 {
+            let _start = reader.u32()?;
+            let _end = reader.u32()?;
+            let _original_start = reader.u32()?;
+            if self.flags & ARO_SUBJECT != 0 {
+                self.exception.subject = Some(reader.wide_string()?);
+            }
+            if self.flags & ARO_LOCATION != 0 {
+                self.exception.location = Some(reader.wide_string()?);
+            }
+            reader.reserved_block()?;
+        }
+        Ok(())
+    }
+}
+
+impl From<ExceptionInfo<'_>> for RecurrenceException {
+    fn from(value: ExceptionInfo<'_>) -> Self {
+        let decode = |value: &[u8]| CodePage::ACP.decode(value).ok();
+        let mut exception = value.exception;
+        exception.start = Some(from_rtime(i64::from(value.start)));
+        exception.end = Some(from_rtime(i64::from(value.end)));
+        exception.original_start = Some(from_rtime(i64::from(value.original_start)));
+        if exception.subject.is_none() {
+            exception.subject = value.ansi_subject.and_then(decode);
+        }
+        if exception.location.is_none() {
+            exception.location = value.ansi_location.and_then(decode);
+        }
+        exception
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minutes since January 1, 1601 at midnight on a date.
+    fn date(year: i64, month: i64, day: i64) -> u32 {
+        (month_start(year, month) + (day - 1) * MINUTES_PER_DAY) as u32
+    }
+
+    fn time(year: i64, month: i64, day: i64) -> SystemTime {
+        from_rtime(i64::from(date(year, month, day)))
+    }
+
+    struct BlobWriter(Vec<u8>);
+
+    impl BlobWriter {
+        fn u16(&mut self, value: u16) -> &mut Self {
+            self.0.extend_from_slice(&value.to_le_bytes());
+            self
+        }
+
+        fn u32(&mut self, value: u32) -> &mut Self {
+            self.0.extend_from_slice(&value.to_le_bytes());
+            self
+        }
+
+        fn bytes(&mut self, value: &[u8]) -> &mut Self {
+            self.0.extend_from_slice(value);
+            self
+        }
+    }
+
+    /// Weekly on Monday and Wednesday from Monday, March 4, 2024, at 9:00 to 9:30 for 6
+    /// occurrences. The second occurrence is deleted, and the third is moved to 10:00 with a new
+    /// subject.
+    fn weekly_blob() -> Vec<u8> {
+        let mut blob = BlobWriter(Vec::new());
+        blob.u16(0x3004)
+            .u16(0x3004)
+            .u16(RECUR_FREQUENCY_WEEKLY)
+            .u16(PATTERN_TYPE_WEEK)
+            .u16(CALENDAR_DEFAULT)
+            .u32(0)
+            .u32(1)
+            .u32(0)
+            .u32(DaysOfWeek::MONDAY | DaysOfWeek::WEDNESDAY)
+            .u32(END_AFTER_N_OCCURRENCES)
+            .u32(6)
+            .u32(0)
+            .u32(2)
+            .u32(date(2024, 3, 6))
+            .u32(date(2024, 3, 11))
+            .u32(1)
+            .u32(date(2024, 3, 11))
+            .u32(date(2024, 3, 4))
+            .u32(date(2024, 3, 18))
+            .u32(0x3006)
+            .u32(0x3009)
+            .u32(9 * 60)
+            .u32(9 * 60 + 30)
+            .u16(1)
+            .u32(date(2024, 3, 11) + 10 * 60)
+            .u32(date(2024, 3, 11) + 10 * 60 + 30)
+            .u32(date(2024, 3, 11) + 9 * 60)
+            .u16(ARO_SUBJECT | ARO_BUSYSTATUS)
+            .u16(4)
+            .u16(3)
+            .bytes(b"Moe")
+            .u32(2)
+            .u32(0)
+            .u32(4)
+            .u32(0)
+            .u32(0)
+            .u32(date(2024, 3, 11) + 10 * 60)
+            .u32(date(2024, 3, 11) + 10 * 60 + 30)
+            .u32(date(2024, 3, 11) + 9 * 60)
+            .u16(3);
+        for ch in "Zoë".encode_utf16() {
+            blob.u16(ch);
+        }
+        blob.u32(0).u32(0);
+        blob.0
+    }
+
+    #[test]
+    fn parse_weekly() {
+        let recurrence = Recurrence::parse(&weekly_blob()).expect("parse failed");
+        assert_eq!(recurrence.frequency, RecurFrequency::Weekly);
+        assert_eq!(
+            recurrence.pattern,
+            RecurPattern::Week(DaysOfWeek(DaysOfWeek::MONDAY | DaysOfWeek::WEDNESDAY))
+        );
+        assert_eq!(recurrence.end, RecurEnd::AfterCount(6));
+        assert_eq!(recurrence.start_date, time(2024, 3, 4));
+        assert_eq!(recurrence.deleted_instances.len(), 2);
+        assert_eq!(recurrence.modified_instances, [time(2024, 3, 11)]);
+        assert_eq!(recurrence.start_time_offset, 9 * 60);
+
+        let [exception] = recurrence.exceptions.as_slice() else {
+            panic!("expected one exception");
+        };
+        assert_eq!(exception.subject.as_deref(), Some("Zoë"));
+        assert_eq!(exception.busy_status, Some(2));
+        assert_eq!(exception.location, None);
+    }
+
+    #[test]
+    fn expand_weekly() {
+        let recurrence = Recurrence::parse(&weekly_blob()).expect("parse failed");
+        let occurrences: Vec<_> = recurrence
+            .occurrences(time(2024, 1, 1)..time(2025, 1, 1))
+            .expect("occurrences failed")
+            .map(|occurrence| {
+                (
+                    to_rtime(occurrence.start) - i64::from(date(2024, 3, 1)),
+                    occurrence.exception.is_some(),
+                )
+            })
+            .collect();
+        let at = |day: i64, hour: i64| (day - 1) * MINUTES_PER_DAY + hour * 60;
+        assert_eq!(
+            occurrences,
+            [
+                (at(4, 9), false),
+                (at(11, 10), true),
+                (at(13, 9), false),
+                (at(18, 9), false),
+            ]
+        );
+
+        let occurrences = recurrence
+            .occurrences(time(2024, 3, 12)..time(2024, 3, 14))
+            .expect("occurrences failed")
+            .count();
+        assert_eq!(occurrences, 1);
+    }
+
+    #[test]
+    fn expand_month_nth() {
+        let recurrence = Recurrence {
+            frequency: RecurFrequency::Monthly,
+            pattern: RecurPattern::MonthNth {
+                days: DaysOfWeek(DaysOfWeek::FRIDAY),
+                nth: 5,
+            },
+            calendar_type: CALENDAR_GREGORIAN,
+            period: 1,
+            sliding: false,
+            end: RecurEnd::Never,
+            first_day_of_week: 0,
+            deleted_instances: Vec::new(),
+            modified_instances: Vec::new(),
+            start_date: time(2024, 1, 26),
+            end_date: from_rtime(0x5AE9_80DF),
+            start_time_offset: 0,
+            end_time_offset: 24 * 60,
+            exceptions: Vec::new(),
+        };
+        let starts: Vec<_> = recurrence
+            .occurrences(time(2024, 1, 1)..time(2024, 5, 1))
+            .expect("occurrences failed")
+            .map(|occurrence| occurrence.start)
+            .collect();
+        assert_eq!(
+            starts,
+            [
+                time(2024, 1, 26),
+                time(2024, 2, 23),
+                time(2024, 3, 29),
+                time(2024, 4, 26)
+            ]
+        );
+    }
+
+    #[test]
+    fn expand_month_end() {
+        let recurrence = Recurrence {
+            frequency: RecurFrequency::Monthly,
+            pattern: RecurPattern::Month { day: 31 },
+            calendar_type: CALENDAR_DEFAULT,
+            period: 1,
+            sliding: false,
+            end: RecurEnd::AfterDate,
+            first_day_of_week: 0,
+            deleted_instances: Vec::new(),
+            modified_instances: Vec::new(),
+            start_date: time(2024, 1, 31),
+            end_date: time(2024, 3, 31),
+            start_time_offset: 0,
+            end_time_offset: 60,
+            exceptions: Vec::new(),
+        };
+        let starts: Vec<_> = recurrence
+            .occurrences(time(2024, 1, 1)..time(2025, 1, 1))
+            .expect("occurrences failed")
+            .map(|occurrence| occurrence.start)
+            .collect();
+        assert_eq!(
+            starts,
+            [time(2024, 1, 31), time(2024, 2, 29), time(2024, 3, 31)]
+        );
+    }
+
+    #[test]
+    fn parse_corrupt() {
+        let blob = weekly_blob();
+        for len in [0, 3, 30, blob.len() - 1] {
+            assert!(Recurrence::parse(&blob[..len]).is_err());
+        }
+    }
+}
@@ -0,0 +1,756 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`Restriction`] and [`OwnedRestriction`].
+//!
+//! A [`sys::SRestriction`] is a tree of nested structures connected by raw pointers, and MAPI
+//! expects the whole tree to be allocated in a single chain of [`sys::MAPIAllocateBuffer`] and
+//! [`sys::MAPIAllocateMore`] calls. [`Restriction`] describes the same tree with owned Rust types,
+//! and [`Restriction::build`] serializes it into an [`OwnedRestriction`] which can be passed to
+//! [`sys::IMAPITable::Restrict`], [`sys::IMAPITable::FindRow`], or [`sys::HrQueryAllRows`].
+//! [`Restriction::from_sys`] goes the other way, e.g. for the criteria returned from
+//! [`sys::IMAPIContainer::GetSearchCriteria`] or the conditions of a rule. It is `unsafe` because
+//! it follows the raw pointers in the tree.
+
+use crate::{
+    prop_value::chain_prop_value, sys, MAPIBuffer, MAPIUninit, MapiArena, PropTag, PropValue,
+};
+use core::{ptr, slice};
+use windows::Win32::Foundation::E_INVALIDARG;
+use windows_core::*;
+
+/// Maximum nesting depth [`Restriction::from_sys`] will follow, so a cycle or a corrupt tree
+/// cannot overflow the stack.
+pub const MAX_RESTRICTION_DEPTH: usize = 64;
+
+/// Relational operators for [`Restriction::Property`], [`Restriction::CompareProps`], and
+/// [`Restriction::Size`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelOp {
+    /// [`sys::RELOP_LT`]
+    LessThan,
+
+    /// [`sys::RELOP_LE`]
+    LessThanOrEqual,
+
+    /// [`sys::RELOP_GT`]
+    GreaterThan,
+
+    /// [`sys::RELOP_GE`]
+    GreaterThanOrEqual,
+
+    /// [`sys::RELOP_EQ`]
+    Equal,
+
+    /// [`sys::RELOP_NE`]
+    NotEqual,
+
+    /// [`sys::RELOP_RE`]
+    RegularExpression,
+}
+
+impl From<RelOp> for u32 {
+    fn from(value: RelOp) -> Self {
+        match value {
+            RelOp::LessThan => sys::RELOP_LT,
+            RelOp::LessThanOrEqual => sys::RELOP_LE,
+            RelOp::GreaterThan => sys::RELOP_GT,
+            RelOp::GreaterThanOrEqual => sys::RELOP_GE,
+            RelOp::Equal => sys::RELOP_EQ,
+            RelOp::NotEqual => sys::RELOP_NE,
+            RelOp::RegularExpression => sys::RELOP_RE,
+        }
+    }
+}
+
+impl TryFrom<u32> for RelOp {
+    type Error = Error;
+
+    /// Any value other than one of the `RELOP_*` constants returns [`E_INVALIDARG`].
+    fn try_from(value: u32) -> Result<Self> {
+        match value {
+            sys::RELOP_LT => Ok(Self::LessThan),
+            sys::RELOP_LE => Ok(Self::LessThanOrEqual),
+            sys::RELOP_GT => Ok(Self::GreaterThan),
+            sys::RELOP_GE => Ok(Self::GreaterThanOrEqual),
+            sys::RELOP_EQ => Ok(Self::Equal),
+            sys::RELOP_NE => Ok(Self::NotEqual),
+            sys::RELOP_RE => Ok(Self::RegularExpression),
+            _ => Err(Error::from(E_INVALIDARG)),
+        }
+    }
+}
+
+/// Which part of the string [`Restriction::Content`] should match.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ContentMatch {
+    /// [`sys::FL_FULLSTRING`]
+    #[default]
+    FullString,
+
+    /// [`sys::FL_SUBSTRING`]
+    Substring,
+
+    /// [`sys::FL_PREFIX`]
+    Prefix,
+}
+
+/// Value of [`sys::SContentRestriction::ulFuzzyLevel`] for [`Restriction::Content`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FuzzyLevel {
+    /// Pass [`sys::FL_FULLSTRING`], [`sys::FL_SUBSTRING`], or [`sys::FL_PREFIX`].
+    pub content_match: ContentMatch,
+
+    /// Pass [`sys::FL_IGNORECASE`].
+    pub ignore_case: bool,
+
+    /// Pass [`sys::FL_IGNORENONSPACE`].
+    pub ignore_non_space: bool,
+
+    /// Pass [`sys::FL_LOOSE`].
+    pub loose: bool,
+}
+
+impl From<FuzzyLevel> for u32 {
+    fn from(value: FuzzyLevel) -> Self {
+        let content_match = match value.content_match {
+            ContentMatch::FullString => sys::FL_FULLSTRING,
+            ContentMatch::Substring => sys::FL_SUBSTRING,
+            ContentMatch::Prefix => sys::FL_PREFIX,
+        };
+        let ignore_case = if value.ignore_case {
+            sys::FL_IGNORECASE
+        } else {
+            0
+        };
+        let ignore_non_space = if value.ignore_non_space {
+            sys::FL_IGNORENONSPACE
+        } else {
+            0
+        };
+        let loose = if value.loose { sys::FL_LOOSE } else { 0 };
+
+        content_match | ignore_case | ignore_non_space | loose
+    }
+}
+
+impl From<u32> for FuzzyLevel {
+    /// Split [`sys::SContentRestriction::ulFuzzyLevel`] into the match type in the low word and
+    /// the flags in the high word. An unknown match type is treated as [`ContentMatch::FullString`].
+    fn from(value: u32) -> Self {
+        let content_match = match value & 0xFFFF {
+            sys::FL_SUBSTRING => ContentMatch::Substring,
+            sys::FL_PREFIX => ContentMatch::Prefix,
+            _ => ContentMatch::FullString,
+        };
+        Self {
+            content_match,
+            ignore_case: value & sys::FL_IGNORECASE != 0,
+            ignore_non_space: value & sys::FL_IGNORENONSPACE != 0,
+            loose: value & sys::FL_LOOSE != 0,
+        }
+    }
+}
+
+/// Relational operators for [`Restriction::Bitmask`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitmaskRelOp {
+    /// [`sys::BMR_EQZ`], none of the bits in the mask are set.
+    EqualZero,
+
+    /// [`sys::BMR_NEZ`], at least one of the bits in the mask is set.
+    NotEqualZero,
+}
+
+impl From<BitmaskRelOp> for u32 {
+    fn from(value: BitmaskRelOp) -> Self {
+        match value {
+            BitmaskRelOp::EqualZero => sys::BMR_EQZ,
+            BitmaskRelOp::NotEqualZero => sys::BMR_NEZ,
+        }
+    }
+}
+
+impl TryFrom<u32> for BitmaskRelOp {
+    type Error = Error;
+
+    /// Any value other than [`sys::BMR_EQZ`] or [`sys::BMR_NEZ`] returns [`E_INVALIDARG`].
+    fn try_from(value: u32) -> Result<Self> {
+        match value {
+            sys::BMR_EQZ => Ok(Self::EqualZero),
+            sys::BMR_NEZ => Ok(Self::NotEqualZero),
+            _ => Err(Error::from(E_INVALIDARG)),
+        }
+    }
+}
+
+/// Safe representation of a [`sys::SRestriction`] tree.
+pub enum Restriction<'a> {
+    /// [`sys::RES_AND`]
+    And(Vec<Restriction<'a>>),
+
+    /// [`sys::RES_OR`]
+    Or(Vec<Restriction<'a>>),
+
+    /// [`sys::RES_NOT`]
+    Not(Box<Restriction<'a>>),
+
+    /// [`sys::RES_CONTENT`]
+    Content {
+        fuzzy_level: FuzzyLevel,
+        value: PropValue<'a>,
+    },
+
+    /// [`sys::RES_PROPERTY`]
+    Property { relop: RelOp, value: PropValue<'a> },
+
+    /// [`sys::RES_COMPAREPROPS`]
+    CompareProps {
+        relop: RelOp,
+        left: PropTag,
+        right: PropTag,
+    },
+
+    /// [`sys::RES_BITMASK`]
+    Bitmask {
+        relop: BitmaskRelOp,
+        tag: PropTag,
+        mask: u32,
+    },
+
+    /// [`sys::RES_SIZE`]
+    Size {
+        relop: RelOp,
+        tag: PropTag,
+        size: u32,
+    },
+
+    /// [`sys::RES_EXIST`]
+    Exist(PropTag),
+
+    /// [`sys::RES_SUBRESTRICTION`], e.g. on [`sys::PR_MESSAGE_RECIPIENTS`] or
+    /// [`sys::PR_MESSAGE_ATTACHMENTS`].
+    SubRestriction {
+        subobject: PropTag,
+        restriction: Box<Restriction<'a>>,
+    },
+
+    /// [`sys::RES_COMMENT`], which attaches `values` to an optional `restriction` without changing
+    /// what it matches. Outlook uses these to annotate the criteria of search folders.
+    Comment {
+        values: Vec<PropValue<'a>>,
+        restriction: Option<Box<Restriction<'a>>>,
+    },
+
+    /// [`sys::RES_COUNT`], which stops matching after `count` rows match the `restriction`.
+    Count {
+        count: u32,
+        restriction: Box<Restriction<'a>>,
+    },
+}
+
+impl<'a> Restriction<'a> {
+    /// Convert a [`sys::SRestriction`] tree back into a [`Restriction`], which borrows the
+    /// property values from the tree. This is the reverse of [`Restriction::build`].
+    ///
+    /// [`sys::RES_ANNOTATION`], any other unknown restriction types, and trees nested more than
+    /// [`MAX_RESTRICTION_DEPTH`] levels deep return [`sys::MAPI_E_TOO_COMPLEX`]. `null` pointers to
+    /// child restrictions or property values return [`E_INVALIDARG`].
+    ///
+    /// # Safety
+    ///
+    /// The `value` must be a valid [`sys::SRestriction`], with valid pointers throughout the tree.
+    pub unsafe fn from_sys(value: &'a sys::SRestriction) -> Result<Self> {
+        Self::from_sys_at(value, 0)
+    }
+
+    unsafe fn from_sys_at(value: &'a sys::SRestriction, depth: usize) -> Result<Self> {
+        if depth >= MAX_RESTRICTION_DEPTH {
+            return Err(Error::from(sys::MAPI_E_TOO_COMPLEX));
+        }
+        let depth = depth + 1;
+        Ok(match value.rt {
+            sys::RES_AND => {
+                let and = &value.res.resAnd;
+                Self::And(Self::from_children(and.cRes, and.lpRes, depth)?)
+            }
+            sys::RES_OR => {
+                let or = &value.res.resOr;
+                Self::Or(Self::from_children(or.cRes, or.lpRes, depth)?)
+            }
+            sys::RES_NOT => Self::Not(Self::from_child(value.res.resNot.lpRes, depth)?),
+            sys::RES_CONTENT => {
+                let content = &value.res.resContent;
+                Self::Content {
+                    fuzzy_level: content.ulFuzzyLevel.into(),
+                    value: Self::from_value(content.lpProp)?,
+                }
+            }
+            sys::RES_PROPERTY => {
+                let property = &value.res.resProperty;
+                Self::Property {
+                    relop: property.relop.try_into()?,
+                    value: Self::from_value(property.lpProp)?,
+                }
+            }
+            sys::RES_COMPAREPROPS => {
+                let compare = &value.res.resCompareProps;
+                Self::CompareProps {
+                    relop: compare.relop.try_into()?,
+                    left: PropTag(compare.ulPropTag1),
+                    right: PropTag(compare.ulPropTag2),
+                }
+            }
+            sys::RES_BITMASK => {
+                let bitmask = &value.res.resBitMask;
+                Self::Bitmask {
+                    relop: bitmask.relBMR.try_into()?,
+                    tag: PropTag(bitmask.ulPropTag),
+                    mask: bitmask.ulMask,
+                }
+            }
+            sys::RES_SIZE => {
+                let size = &value.res.resSize;
+                Self::Size {
+                    relop: size.relop.try_into()?,
+                    tag: PropTag(size.ulPropTag),
+                    size: size.cb,
+                }
+            }
+            sys::RES_EXIST => Self::Exist(PropTag(value.res.resExist.ulPropTag)),
+            sys::RES_SUBRESTRICTION => {
+                let sub = &value.res.resSub;
+                Self::SubRestriction {
+                    subobject: PropTag(sub.ulSubObject),
+                    restriction: Self::from_child(sub.lpRes, depth)?,
+                }
+            }
+            sys::RES_COMMENT => {
+                let comment = &value.res.resComment;
+                let values = if comment.lpProp.is_null() {
+                    vec![]
+                } else {
+                    slice::from_raw_parts(comment.lpProp, comment.cValues as usize)
+                        .iter()
+                        .map(PropValue::from)
+                        .collect()
+                };
+                let restriction = if comment.lpRes.is_null() {
+                    None
+                } else {
+                    Some(Self::from_child(comment.lpRes, depth)?)
+                };
+                Self::Comment {
+                    values,
+                    restriction,
+                }
+            }
+            sys::RES_COUNT => {
+                let count = &value.res.resCount;
+                Self::Count {
+                    count: count.ulCount,
+                    restriction: Self::from_child(count.lpRes, depth)?,
+                }
+            }
+            _ => return Err(Error::from(sys::MAPI_E_TOO_COMPLEX)),
+        })
+    }
+
+    unsafe fn from_children(
+        count: u32,
+        children: *const sys::SRestriction,
+        depth: usize,
+    ) -> Result<Vec<Self>> {
+        if count == 0 {
+            return Ok(vec![]);
+        }
+        if children.is_null() {
+            return Err(Error::from(E_INVALIDARG));
+        }
+        slice::from_raw_parts(children, count as usize)
+            .iter()
+            .map(|child| Self::from_sys_at(child, depth))
+            .collect()
+    }
+
+    unsafe fn from_child(child: *const sys::SRestriction, depth: usize) -> Result<Box<Self>> {
+        let child = child.as_ref().ok_or_else(|| Error::from(E_INVALIDARG))?;
+        Ok(Box::new(Self::from_sys_at(child, depth)?))
+    }
+
+    unsafe fn from_value(value: *const sys::SPropValue) -> Result<PropValue<'a>> {
+        let value = value.as_ref().ok_or_else(|| Error::from(E_INVALIDARG))?;
+        Ok(PropValue::from(value))
+    }
+}
+
+impl Restriction<'_> {
+    /// Serialize the tree into a single chain of MAPI allocations.
+    ///
+    /// Any [`PropValue`] in the tree which holds raw pointers is copied the same way as in
+    /// [`crate::PropValueBuilder::build`], so those pointers must follow the contract of
+    /// [`crate::PropValueBuilder::value`].
+    pub fn build(&self) -> Result<OwnedRestriction> {
+        let mut buffer = MAPIUninit::<sys::SRestriction>::new(1)?;
+        let restriction = self.chain(&MapiArena::chained(&buffer))?;
+        buffer.uninit()?.write(restriction);
+        Ok(OwnedRestriction {
+            buffer: unsafe { buffer.assume_init() },
+        })
+    }
+
+    /// Copy the tree into allocations chained to `arena`, e.g. for a [`sys::PR_RULE_CONDITION`].
+    pub(crate) fn chain(&self, arena: &MapiArena) -> Result<sys::SRestriction> {
+        let mut result = sys::SRestriction::default();
+        match self {
+            Self::And(children) => {
+                result.rt = sys::RES_AND;
+                result.res.resAnd = sys::SAndRestriction {
+                    cRes: u32::try_from(children.len())?,
+                    lpRes: Self::chain_children(arena, children)?,
+                };
+            }
+            Self::Or(children) => {
+                result.rt = sys::RES_OR;
+                result.res.resOr = sys::SOrRestriction {
+                    cRes: u32::try_from(children.len())?,
+                    lpRes: Self::chain_children(arena, children)?,
+                };
+            }
+            Self::Not(child) => {
+                result.rt = sys::RES_NOT;
+                result.res.resNot = sys::SNotRestriction {
+                    ulReserved: 0,
+                    lpRes: Self::chain_children(arena, slice::from_ref(child.as_ref()))?,
+                };
+            }
+            Self::Content { fuzzy_level, value } => {
+                result.rt = sys::RES_CONTENT;
+                result.res.resContent = sys::SContentRestriction {
+                    ulFuzzyLevel: (*fuzzy_level).into(),
+                    ulPropTag: value.tag.0,
+                    lpProp: Self::chain_value(arena, value)?,
+                };
+            }
+            Self::Property { relop, value } => {
+                result.rt = sys::RES_PROPERTY;
+                result.res.resProperty = sys::SPropertyRestriction {
+                    relop: (*relop).into(),
+                    ulPropTag: value.tag.0,
+                    lpProp: Self::chain_value(arena, value)?,
+                };
+            }
+            Self::CompareProps { relop, left, right } => {
+                result.rt = sys::RES_COMPAREPROPS;
+                result.res.resCompareProps = sys::SComparePropsRestriction {
+                    relop: (*relop).into(),
+                    ulPropTag1: left.0,
+                    ulPropTag2: right.0,
+                };
+            }
+            Self::Bitmask { relop, tag, mask } => {
+                result.rt = sys::RES_BITMASK;
+                result.res.resBitMask = sys::SBitMaskRestriction {
+                    relBMR: (*relop).into(),
+                    ulPropTag: tag.0,
+                    ulMask: *mask,
+                };
+            }
+            Self::Size { relop, tag, size } => {
+                result.rt = sys::RES_SIZE;
+                result.res.resSize = sys::SSizeRestriction {
+                    relop: (*relop).into(),
+                    ulPropTag: tag.0,
+                    cb: *size,
+                };
+            }
+            Self::Exist(tag) => {
+                result.rt = sys::RES_EXIST;
+                result.res.resExist = sys::SExistRestriction {
+                    ulReserved1: 0,
+                    ulPropTag: tag.0,
+                    ulReserved2: 0,
+                };
+            }
+            Self::SubRestriction {
+                subobject,
+                restriction,
+            } => {
+                result.rt = sys::RES_SUBRESTRICTION;
+                result.res.resSub = sys::SSubRestriction {
+                    ulSubObject: subobject.0,
+                    lpRes: Self::chain_children(arena, slice::from_ref(restriction.as_ref()))?,
+                };
+            }
+            Self::Comment {
+                values,
+                restriction,
+            } => {
+                result.rt = sys::RES_COMMENT;
+                result.res.resComment = sys::SCommentRestriction {
+                    cValues: u32::try_from(values.len())?,
+                    lpRes: match restriction {
+                        Some(restriction) => {
+                            Self::chain_children(arena, slice::from_ref(restriction.as_ref()))?
+                        }
+                        None => ptr::null_mut(),
+                    },
+                    lpProp: Self::chain_values(arena, values)?,
+                };
+            }
+            Self::Count { count, restriction } => {
+                result.rt = sys::RES_COUNT;
+                result.res.resCount = sys::SCountRestriction {
+                    ulCount: *count,
+                    lpRes: Self::chain_children(arena, slice::from_ref(restriction.as_ref()))?,
+                };
+            }
+        }
+        Ok(result)
+    }
+
+    fn chain_children(
+        arena: &MapiArena,
+        children: &[Restriction],
+    ) -> Result<*mut sys::SRestriction> {
+        if children.is_empty() {
+            return Ok(ptr::null_mut());
+        }
+        let alloc = arena.alloc::<sys::SRestriction>(children.len())?;
+        for (element, child) in alloc.iter_mut().zip(children) {
+            *element = child.chain(arena)?;
+        }
+        Ok(alloc.as_mut_ptr())
+    }
+
+    fn chain_value(arena: &MapiArena, value: &PropValue) -> Result<*mut sys::SPropValue> {
+        // SAFETY: Raw pointers in the value follow the contract of `PropValueBuilder::value`, as
+        // documented on `Restriction::build`.
+        Ok(ptr::from_ref(unsafe { value.copy_to_arena(arena) }?).cast_mut())
+    }
+
+    fn chain_values(arena: &MapiArena, values: &[PropValue]) -> Result<*mut sys::SPropValue> {
+        if values.is_empty() {
+            return Ok(ptr::null_mut());
+        }
+        let alloc = arena.alloc::<sys::SPropValue>(values.len())?;
+        for (element, value) in alloc.iter_mut().zip(values) {
+            // SAFETY: Raw pointers in the values follow the contract of
+            // `PropValueBuilder::value`, as documented on `Restriction::build`.
+            *element = unsafe { chain_prop_value(arena, value) }?;
+        }
+        Ok(alloc.as_mut_ptr())
+    }
+}
+
+/// A [`sys::SRestriction`] tree built with [`Restriction::build`]. The whole tree is freed with a
+/// single call to [`sys::MAPIFreeBuffer`] when this is dropped.
+pub struct OwnedRestriction {
+    buffer: MAPIBuffer<'static, sys::SRestriction>,
+}
+
+impl OwnedRestriction {
+    /// Get a pointer to the root [`sys::SRestriction`], e.g. to pass to
+    /// [`sys::IMAPITable::Restrict`].
+    pub fn as_mut_ptr(&mut self) -> Result<*mut sys::SRestriction> {
+        Ok(ptr::from_mut(self.buffer.as_mut()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{to_pwstr_buffer, PropValueData};
+
+    #[test]
+    fn build_and_tree() {
+        let subject = to_pwstr_buffer("sixty-six");
+        let restriction = Restriction::And(vec![
+            Restriction::Content {
+                fuzzy_level: FuzzyLevel {
+                    content_match: ContentMatch::Substring,
+                    ignore_case: true,
+                    ..Default::default()
+                },
+                value: PropValue {
+                    tag: PropTag(sys::PR_SUBJECT_W),
+                    value: PropValueData::Unicode(subject.clone()),
+                },
+            },
+            Restriction::Not(Box::new(Restriction::Bitmask {
+                relop: BitmaskRelOp::NotEqualZero,
+                tag: PropTag(sys::PR_MESSAGE_FLAGS),
+                mask: sys::MSGFLAG_READ,
+            })),
+            Restriction::Exist(PropTag(sys::PR_ENTRYID)),
+        ]);
+        let mut restriction = restriction.build().expect("build failed");
+        let root = unsafe {
+            restriction
+                .as_mut_ptr()
+                .expect("as_mut_ptr failed")
+                .as_ref()
+                .expect("null restriction")
+        };
+        assert_eq!(root.rt, sys::RES_AND);
+        let children = unsafe {
+            assert_eq!(root.res.resAnd.cRes, 3);
+            slice::from_raw_parts(root.res.resAnd.lpRes, 3)
+        };
+
+        assert_eq!(children[0].rt, sys::RES_CONTENT);
+        let content = unsafe { children[0].res.resContent };
+        assert_eq!(content.ulFuzzyLevel, sys::FL_SUBSTRING | sys::FL_IGNORECASE);
+        assert_eq!(content.ulPropTag, sys::PR_SUBJECT_W);
+        let value = PropValue::from(unsafe { &*content.lpProp });
+        let PropValueData::Unicode(actual) = value.value else {
+            panic!("wrong type");
+        };
+        assert_eq!(actual, subject);
+
+        assert_eq!(children[1].rt, sys::RES_NOT);
+        let not = unsafe { &*children[1].res.resNot.lpRes };
+        assert_eq!(not.rt, sys::RES_BITMASK);
+        let bitmask = unsafe { not.res.resBitMask };
+        assert_eq!(bitmask.relBMR, sys::BMR_NEZ);
+        assert_eq!(bitmask.ulMask, sys::MSGFLAG_READ);
+
+        assert_eq!(children[2].rt, sys::RES_EXIST);
+        assert_eq!(
+            unsafe { children[2].res.resExist.ulPropTag },
+            sys::PR_ENTRYID
+        );
+    }
+
+    #[test]
+    fn build_empty_or() {
+        let mut restriction = Restriction::Or(vec![]).build().expect("build failed");
+        let root = unsafe { &*restriction.as_mut_ptr().expect("as_mut_ptr failed") };
+        assert_eq!(root.rt, sys::RES_OR);
+        unsafe {
+            assert_eq!(root.res.resOr.cRes, 0);
+            assert!(root.res.resOr.lpRes.is_null());
+        }
+    }
+
+    #[test]
+    fn build_property() {
+        let mut restriction = Restriction::Property {
+            relop: RelOp::GreaterThanOrEqual,
+            value: PropValue {
+                tag: PropTag(sys::PR_MESSAGE_SIZE),
+                value: PropValueData::Long(67),
+            },
+        }
+        .build()
+        .expect("build failed");
+        let root = unsafe { &*restriction.as_mut_ptr().expect("as_mut_ptr failed") };
+        assert_eq!(root.rt, sys::RES_PROPERTY);
+        let property = unsafe { root.res.resProperty };
+        assert_eq!(property.relop, sys::RELOP_GE);
+        assert_eq!(unsafe { (*property.lpProp).Value.l }, 67);
+    }
+
+    #[test]
+    fn round_trip() {
+        let subject = to_pwstr_buffer("sixty-six");
+        let restriction = Restriction::Comment {
+            values: vec![PropValue {
+                tag: PropTag(sys::PR_DISPLAY_NAME_W),
+                value: PropValueData::Unicode(subject.clone()),
+            }],
+            restriction: Some(Box::new(Restriction::Or(vec![
+                Restriction::Content {
+                    fuzzy_level: FuzzyLevel {
+                        content_match: ContentMatch::Prefix,
+                        loose: true,
+                        ..Default::default()
+                    },
+                    value: PropValue {
+                        tag: PropTag(sys::PR_SUBJECT_W),
+                        value: PropValueData::Unicode(subject.clone()),
+                    },
+                },
+                Restriction::Count {
+                    count: 5,
+                    restriction: Box::new(Restriction::Size {
+                        relop: RelOp::LessThan,
+                        tag: PropTag(sys::PR_BODY_W),
+                        size: 67,
+                    }),
+                },
+            ]))),
+        };
+        let mut built = restriction.build().expect("build failed");
+        let root = unsafe { &*built.as_mut_ptr().expect("as_mut_ptr failed") };
+        let parsed = unsafe { Restriction::from_sys(root) }.expect("from_sys failed");
+
+        let Restriction::Comment {
+            values,
+            restriction: Some(restriction),
+        } = parsed
+        else {
+            panic!("wrong restriction");
+        };
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0].tag, PropTag(sys::PR_DISPLAY_NAME_W));
+        let Restriction::Or(children) = *restriction else {
+            panic!("wrong child");
+        };
+        assert_eq!(children.len(), 2);
+
+        let Restriction::Content { fuzzy_level, value } = &children[0] else {
+            panic!("wrong content");
+        };
+        assert_eq!(u32::from(*fuzzy_level), sys::FL_PREFIX | sys::FL_LOOSE);
+        let PropValueData::Unicode(actual) = &value.value else {
+            panic!("wrong type");
+        };
+        assert_eq!(*actual, subject);
+
+        let Restriction::Count { count, restriction } = &children[1] else {
+            panic!("wrong count");
+        };
+        assert_eq!(*count, 5);
+        let Restriction::Size { relop, tag, size } = restriction.as_ref() else {
+            panic!("wrong size");
+        };
+        assert_eq!(*relop, RelOp::LessThan);
+        assert_eq!(*tag, PropTag(sys::PR_BODY_W));
+        assert_eq!(*size, 67);
+    }
+
+    #[test]
+    fn unsupported_type() {
+        let annotation = sys::SRestriction {
+            rt: sys::RES_ANNOTATION,
+            ..Default::default()
+        };
+        let result = unsafe { Restriction::from_sys(&annotation) };
+        assert_eq!(
+            result.err().map(|error| error.code()),
+            Some(sys::MAPI_E_TOO_COMPLEX)
+        );
+    }
+
+    #[test]
+    fn bounded_depth() {
+        let mut cycle = sys::SRestriction {
+            rt: sys::RES_NOT,
+            ..Default::default()
+        };
+        cycle.res.resNot.lpRes = ptr::addr_of_mut!(cycle);
+        let result = unsafe { Restriction::from_sys(&cycle) };
+        assert_eq!(
+            result.err().map(|error| error.code()),
+            Some(sys::MAPI_E_TOO_COMPLEX)
+        );
+
+        let null_child = sys::SRestriction {
+            rt: sys::RES_SUBRESTRICTION,
+            ..Default::default()
+        };
+        let result = unsafe { Restriction::from_sys(&null_child) };
+        assert_eq!(result.err().map(|error| error.code()), Some(E_INVALIDARG));
+    }
+}
@@ -0,0 +1,195 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`ResumeToken`] and [`ResumePosition`].
+//!
+//! MAPI bookmarks from [`sys::IMAPITable::CreateBookmark`] only live as long as the table, so they
+//! can't be used to resume a scan of a large folder after the process is interrupted.
+//! [`ResumeToken`] is a serializable surrogate: it remembers the key of the last row that was
+//! processed and how many rows came before it, and [`crate::Table::resume`] uses that to seek back
+//! to the same spot, or to the same row number if the row is gone.
+//!
+//! The token only positions by that key or by row number. It does not store the sort key values
+//! of the last row, so it can't find the nearest remaining row in sort order, and it should be
+//! used with a table which has the same columns, sort order, and restriction as the original
+//! scan.
+
+use crate::{sys, PropTag, PropValueData, Row};
+use core::mem;
+use windows::Win32::Foundation::*;
+use windows_core::*;
+
+/// Where [`crate::Table::resume`] positioned the table cursor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResumePosition {
+    /// The token was empty, so the cursor is at the beginning of the table.
+    Beginning,
+
+    /// The last row was found, and the cursor is on the row right after it.
+    Exact,
+
+    /// The last row was not found, so the cursor was moved to the same row number instead. Rows
+    /// may be skipped or repeated if the table changed in the meantime.
+    Approximate,
+}
+
+/// Serializable position in a table scan, which can be saved after each batch of rows and passed
+/// to [`crate::Table::resume`] to continue from the same place.
+///
+/// The default key is [`sys::PR_INSTANCE_KEY`], which is cheap to include in a contents table, but
+/// some providers only keep it stable for the lifetime of the table. Use [`ResumeToken::new`] with
+/// [`sys::PR_ENTRYID`] if the token needs to survive restarting the session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResumeToken {
+    key_tag: PropTag,
+    position: u32,
+    key: Vec<u8>,
+}
+
+impl ResumeToken {
+    const VERSION: u8 = 1;
+    const HEADER_SIZE: usize = 1 + 3 * mem::size_of::<u32>();
+
+    /// Create an empty token which identifies rows by the `key_tag` column. The `key_tag` must be
+    /// one of the columns in the table, and it must have the type [`sys::PT_BINARY`].
+    pub fn new(key_tag: PropTag) -> Self {
+        Self {
+            key_tag,
+            position: 0,
+            key: vec![],
+        }
+    }
+
+    /// Get the [`PropTag`] of the column used to identify rows.
+    pub fn key_tag(&self) -> PropTag {
+        self.key_tag
+    }
+
+    /// Get the number of rows processed so far.
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+
+    /// Get the key of the last row which was processed.
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Test if no rows have been processed yet.
+    pub fn is_empty(&self) -> bool {
+        self.position == 0 && self.key.is_empty()
+    }
+
+    /// Record that the row with the specified `key` has been processed.
+    pub fn advance(&mut self, key: &[u8]) {
+        self.position = self.position.saturating_add(1);
+        self.key = key.to_vec();
+    }
+
+    /// Record that a [`Row`] has been processed, reading the key from the
+    /// [`ResumeToken::key_tag`] column. If the row doesn't have a value for that column, this
+    /// returns [`sys::MAPI_E_NOT_FOUND`].
+    pub fn advance_row(&mut self, row: &Row) -> Result<()> {
+        let key_tag = self.key_tag.0;
+        let key = row
+            .iter()
+            .find_map(|value| match value.value {
+                PropValueData::Binary(key) if value.tag.0 == key_tag => Some(key.to_vec()),
+                _ => None,
+            })
+            .ok_or_else(|| Error::from_hresult(sys::MAPI_E_NOT_FOUND))?;
+        self.advance(&key);
+        Ok(())
+    }
+
+    /// Serialize the token so it can be persisted.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::HEADER_SIZE + self.key.len());
+        bytes.push(Self::VERSION);
+        bytes.extend_from_slice(&self.key_tag.0.to_le_bytes());
+        bytes.extend_from_slice(&self.position.to_le_bytes());
+        bytes.extend_from_slice(&(self.key.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.key);
+        bytes
+    }
+
+    /// Deserialize a token from [`ResumeToken::to_bytes`]. If the buffer is truncated or was
+    /// created by an incompatible version, this returns [`E_INVALIDARG`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let invalid = || Error::from_hresult(E_INVALIDARG);
+        let (&version, rest) = bytes.split_first().ok_or_else(invalid)?;
+        if version != Self::VERSION {
+            return Err(invalid());
+        }
+        let mut fields = rest
+            .chunks_exact(mem::size_of::<u32>())
+            .take(3)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+        let (Some(key_tag), Some(position), Some(key_len)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid());
+        };
+        let key = bytes.get(Self::HEADER_SIZE..).ok_or_else(invalid)?;
+        if key.len() != key_len as usize {
+            return Err(invalid());
+        }
+        Ok(Self {
+            key_tag: PropTag(key_tag),
+            position,
+            key: key.to_vec(),
+        })
+    }
+}
+
+impl Default for ResumeToken {
+    /// Identify rows by [`sys::PR_INSTANCE_KEY`].
+    fn default() -> Self {
+        Self::new(PropTag(sys::PR_INSTANCE_KEY))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut token = ResumeToken::default();
+        assert!(token.is_empty());
+        token.advance(&[58, 59, 60]);
+        token.advance(&[61, 62]);
+        assert!(!token.is_empty());
+        assert_eq!(token.position(), 2);
+        let bytes = token.to_bytes();
+        let actual = ResumeToken::from_bytes(&bytes).expect("from_bytes failed");
+        assert_eq!(actual, token);
+        assert_eq!(actual.key_tag().0, sys::PR_INSTANCE_KEY);
+        assert_eq!(actual.key(), [61, 62]);
+    }
+
+    #[test]
+    fn custom_key_tag() {
+        let mut token = ResumeToken::new(PropTag(sys::PR_ENTRYID));
+        token.advance(&[63]);
+        let actual = ResumeToken::from_bytes(&token.to_bytes()).expect("from_bytes failed");
+        assert_eq!(actual.key_tag().0, sys::PR_ENTRYID);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut token = ResumeToken::default();
+        token.advance(&[64, 65]);
+        let bytes = token.to_bytes();
+        for len in 0..bytes.len() {
+            assert!(ResumeToken::from_bytes(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn wrong_version() {
+        let mut bytes = ResumeToken::default().to_bytes();
+        bytes[0] = 0;
+        assert!(ResumeToken::from_bytes(&bytes).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`RetryPolicy`], [`RetryEvent`], and [`retry`].
+
+use crate::MapiStatus;
+use core::time::Duration;
+use std::thread;
+use windows_core::*;
+
+/// Passed to [`RetryPolicy::on_retry`] before waiting to try again, e.g. to log the error.
+#[derive(Debug)]
+pub struct RetryEvent<'a> {
+    /// Number of the attempt which failed, starting at 1.
+    pub attempt: u32,
+
+    /// Transient error returned from that attempt.
+    pub error: &'a Error,
+
+    /// How long [`retry`] is going to wait before the next attempt.
+    pub delay: Duration,
+}
+
+/// How many times to try a MAPI call which fails with a transient error, see
+/// [`MapiStatus::is_transient`], and how long to wait in between.
+///
+/// The delay starts at [`RetryPolicy::initial_delay`] and doubles after each attempt, up to
+/// [`RetryPolicy::max_delay`].
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one. `0` is treated the same as `1`.
+    pub max_attempts: u32,
+
+    /// Delay after the first failed attempt.
+    pub initial_delay: Duration,
+
+    /// Upper bound for the delay between attempts.
+    pub max_delay: Duration,
+
+    /// Called before each retry with the error and the delay.
+    pub on_retry: Option<fn(&RetryEvent)>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+            on_retry: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Get the delay after `attempt` fails, starting at 1 for the first attempt.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1_u32
+            .checked_shl(attempt.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.initial_delay
+            .checked_mul(factor)
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
+
+    /// Check if `error` from `attempt` should be retried.
+    pub fn should_retry(&self, error: &Error, attempt: u32) -> bool {
+        attempt < self.max_attempts && MapiStatus::from(error).is_transient()
+    }
+
+    /// Call `op` until it succeeds, fails with an error which is not transient, or runs out of
+    /// attempts. The last error is returned if every attempt fails.
+    pub fn run<T, F>(&self, op: F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        self.run_with_sleep(op, thread::sleep)
+    }
+
+    fn run_with_sleep<T, F, S>(&self, mut op: F, mut sleep: S) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+        S: FnMut(Duration),
+    {
+        let mut attempt = 1;
+        loop {
+            match op() {
+                Err(error) if self.should_retry(&error, attempt) => {
+                    let delay = self.delay(attempt);
+                    if let Some(on_retry) = self.on_retry {
+                        on_retry(&RetryEvent {
+                            attempt,
+                            error: &error,
+                            delay,
+                        });
+                    }
+                    sleep(delay);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Call `op` with [`RetryPolicy::run`].
+pub fn retry<T, F>(policy: &RetryPolicy, op: F) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+{
+    policy.run(op)
+}
+
+/// Call `op` with the `policy` set on a wrapper, or just once if there is none.
+pub(crate) fn with_retry<T, F>(policy: Option<RetryPolicy>, mut op: F) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+{
+    match policy {
+        Some(policy) => policy.run(op),
+        None => op(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            ..Default::default()
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(3), Duration::from_millis(400));
+        assert_eq!(policy.delay(4), Duration::from_millis(500));
+        assert_eq!(policy.delay(100), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn retry_transient() {
+        static RETRIES: AtomicU32 = AtomicU32::new(0);
+        let policy = RetryPolicy {
+            max_attempts: 4,
+            on_retry: Some(|event| {
+                assert_eq!(event.error.code(), sys::MAPI_E_NETWORK_ERROR);
+                RETRIES.fetch_add(1, Ordering::Relaxed);
+            }),
+            ..Default::default()
+        };
+        let mut calls = 0;
+        let mut delays = Vec::new();
+        let result = policy.run_with_sleep(
+            || {
+                calls += 1;
+                if calls < 3 {
+                    Err(sys::MAPI_E_NETWORK_ERROR.into())
+                } else {
+                    Ok(calls)
+                }
+            },
+            |delay| delays.push(delay),
+        );
+        assert_eq!(result.expect("retry failed"), 3);
+        assert_eq!(RETRIES.load(Ordering::Relaxed), 2);
+        assert_eq!(delays, [policy.delay(1), policy.delay(2)]);
+    }
+
+    #[test]
+    fn give_up() {
+        let policy = RetryPolicy::default();
+        let mut calls = 0;
+        let error = policy
+            .run_with_sleep(
+                || -> Result<()> {
+                    calls += 1;
+                    Err(sys::MAPI_E_TIMEOUT.into())
+                },
+                |_| {},
+            )
+            .expect_err("should fail");
+        assert_eq!(error.code(), sys::MAPI_E_TIMEOUT);
+        assert_eq!(calls, policy.max_attempts);
+
+        let mut calls = 0;
+        let error = policy
+            .run_with_sleep(
+                || -> Result<()> {
+                    calls += 1;
+                    Err(sys::MAPI_E_NOT_FOUND.into())
+                },
+                |_| panic!("should not retry"),
+            )
+            .expect_err("should fail");
+        assert_eq!(error.code(), sys::MAPI_E_NOT_FOUND);
+        assert_eq!(calls, 1);
+    }
+}
@@ -1,11 +1,14 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
-//! Define [`Row`].
+//! Define [`Row`] and [`RowRef`].
 
-use crate::{sys, PropValue};
+use crate::{
+    mapi_ptr::backend, prop_value::chain_prop_value, sys, MAPIUninit, MapiArena, PropTag, PropValue,
+};
 use core::{mem, slice};
-use std::ptr;
+use std::{collections::BTreeMap, ptr};
+use windows_core::*;
 
 /// Container for the members of a [`sys::SRow`] structure. The [`sys::SPropValue`] pointer should
 /// be freed in the destructor with a call to [`sys::MAPIFreeBuffer`].
@@ -14,11 +17,23 @@ use std::ptr;
 /// allocation, but the [`sys::SRow::lpProps`] member is a separate allocation. [`Row`] copies the
 /// [`sys::SRow::cValues`] member and takes ownership of the [`sys::SRow::lpProps`] pointer away
 /// from the [`sys::SRow`], leaving both [`sys::SRow`] members empty in the source structure.
+///
+/// # Threading
+///
+/// [`Row`] is [`Send`], so rows harvested from a [`crate::Table`] can be moved to other threads
+/// for processing. [`sys::MAPIFreeBuffer`] may be called on any thread, and nothing else holds on
+/// to the [`sys::SPropValue`] pointer once the [`Row`] owns it. Any interfaces in a
+/// [`sys::PT_OBJECT`] value are still bound to the apartment rules of the provider, but table
+/// rows normally only contain data.
 pub struct Row {
     count: usize,
     props: *mut sys::SPropValue,
 }
 
+// SAFETY: [`Row`] has exclusive ownership of the [`sys::SPropValue`] allocation, which can be freed
+// with [`sys::MAPIFreeBuffer`] on any thread.
+unsafe impl Send for Row {}
+
 impl Row {
     /// Take ownership of the [`sys::SRow`] members.
     pub fn new(row: &mut sys::SRow) -> Self {
@@ -42,6 +57,15 @@ impl Row {
         }
     }
 
+    /// Borrow the [`sys::SPropValue`] column values without converting them.
+    pub(crate) fn values(&self) -> &[sys::SPropValue] {
+        if self.props.is_null() {
+            &[]
+        } else {
+            unsafe { slice::from_raw_parts(self.props, self.count) }
+        }
+    }
+
     /// Iterate over the [`sys::SPropValue`] column values in the [`Row`].
     pub fn iter(&self) -> impl Iterator<Item = PropValue> {
         if self.props.is_null() {
@@ -55,6 +79,122 @@ impl Row {
         }
         .into_iter()
     }
+
+    /// Get the value of a column, matching on the [`PropTag::prop_id`] so that [`sys::PT_ERROR`]
+    /// values are also returned.
+    pub fn get(&self, tag: PropTag) -> Option<PropValue> {
+        find_value(self.values(), tag)
+    }
+
+    /// Collect the column values in a map keyed on [`PropTag::prop_id`], so they can be looked up
+    /// regardless of the column order.
+    pub fn to_map(&self) -> BTreeMap<u16, PropValue> {
+        values_to_map(self.values())
+    }
+}
+
+/// Borrowed view of a [`sys::SRow`] which is still owned by a [`crate::RowSet`], returned from
+/// [`crate::RowSet::iter`]. The [`PropValue`] items borrow from the [`sys::SRowSet`] allocation,
+/// so they can be inspected any number of times without taking ownership of the row.
+#[derive(Clone, Copy)]
+pub struct RowRef<'a> {
+    values: &'a [sys::SPropValue],
+}
+
+impl<'a> RowRef<'a> {
+    /// Borrow the members of a [`sys::SRow`].
+    pub(crate) fn new(row: &'a sys::SRow) -> Self {
+        let values = if row.lpProps.is_null() {
+            &[]
+        } else {
+            unsafe { slice::from_raw_parts(row.lpProps, row.cValues as usize) }
+        };
+        Self { values }
+    }
+
+    /// Test for a count of 0 properties or a null [`sys::SPropValue`] pointer.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Get the number of [`sys::SPropValue`] column values in the [`RowRef`].
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Borrow the [`sys::SPropValue`] column values without converting them.
+    pub(crate) fn values(&self) -> &'a [sys::SPropValue] {
+        self.values
+    }
+
+    /// Iterate over the [`sys::SPropValue`] column values in the [`RowRef`].
+    pub fn iter(&self) -> impl Iterator<Item = PropValue<'a>> {
+        self.values.iter().map(PropValue::from)
+    }
+
+    /// Get the value of a column, matching on the [`PropTag::prop_id`] so that [`sys::PT_ERROR`]
+    /// values are also returned.
+    pub fn get(&self, tag: PropTag) -> Option<PropValue<'a>> {
+        find_value(self.values, tag)
+    }
+
+    /// Collect the column values in a map keyed on [`PropTag::prop_id`], so they can be looked up
+    /// regardless of the column order.
+    pub fn to_map(&self) -> BTreeMap<u16, PropValue<'a>> {
+        values_to_map(self.values)
+    }
+
+    /// Make a deep copy of the values in a new [`sys::MAPIAllocateBuffer`] allocation, e.g. to
+    /// keep a row from a notification which is freed as soon as the callback returns. Strings are
+    /// copied without their `null` terminator and terminated again, so each copy ends in exactly
+    /// one `null` character.
+    pub fn to_row(&self) -> Result<Row> {
+        if self.values.is_empty() {
+            return Ok(Row {
+                count: 0,
+                props: ptr::null_mut(),
+            });
+        }
+
+        let buffer = MAPIUninit::<sys::SPropValue>::new(self.values.len())?;
+        {
+            let arena = MapiArena::chained(&buffer);
+            for (mut element, value) in buffer.iter().zip(self.iter()) {
+                // SAFETY: The values are borrowed from a row which MAPI returned.
+                element
+                    .uninit()?
+                    .write(unsafe { chain_prop_value(&arena, &value) }?);
+            }
+        }
+        let mut buffer = unsafe { buffer.assume_init() };
+        let mut row = sys::SRow {
+            cValues: u32::try_from(self.values.len())?,
+            lpProps: buffer.as_mut()?,
+            ..Default::default()
+        };
+
+        // The Row owns the property values now.
+        mem::forget(buffer);
+        Ok(Row::new(&mut row))
+    }
+}
+
+fn find_value(values: &[sys::SPropValue], tag: PropTag) -> Option<PropValue> {
+    let prop_id = tag.prop_id();
+    values
+        .iter()
+        .map(PropValue::from)
+        .find(|value| value.tag.prop_id() == prop_id)
+}
+
+/// If the same [`PropTag::prop_id`] appears in more than one column, keep the first one, the same
+/// as [`find_value`].
+fn values_to_map(values: &[sys::SPropValue]) -> BTreeMap<u16, PropValue> {
+    let mut map = BTreeMap::new();
+    for value in values.iter().map(PropValue::from) {
+        map.entry(value.tag.prop_id()).or_insert(value);
+    }
+    map
 }
 
 impl Drop for Row {
@@ -62,8 +202,154 @@ impl Drop for Row {
     fn drop(&mut self) {
         if !self.props.is_null() {
             unsafe {
-                sys::MAPIFreeBuffer(self.props as *mut _);
+                backend::free_buffer(self.props as *mut _);
             }
         }
     }
 }
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Row {
+    /// Serialize the column values as a sequence of [`crate::TrustedPropValue`], since MAPI
+    /// allocated them.
+    fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        // SAFETY: The values were returned from MAPI or copied by a PropValueBuilder.
+        serializer.collect_seq(
+            self.values()
+                .iter()
+                .map(|value| unsafe { crate::TrustedPropValue::new(PropValue::from(value)) }),
+        )
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for RowRef<'_> {
+    /// Serialize the column values as a sequence of [`crate::TrustedPropValue`], since they are
+    /// borrowed from a [`crate::RowSet`] which MAPI allocated.
+    fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        // SAFETY: The values are borrowed from a row which MAPI returned.
+        serializer.collect_seq(
+            self.iter()
+                .map(|value| unsafe { crate::TrustedPropValue::new(value) }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PropValueData;
+    use std::thread;
+
+    #[test]
+    fn send_to_thread() {
+        let mut props = ptr::null_mut();
+        unsafe {
+            assert_eq!(
+                backend::allocate_buffer(mem::size_of::<sys::SPropValue>() as u32, &mut props),
+                0
+            );
+        }
+        let props = props as *mut sys::SPropValue;
+        unsafe {
+            props.write(sys::SPropValue {
+                ulPropTag: sys::PR_IMPORTANCE,
+                Value: sys::__UPV { l: 2 },
+                ..Default::default()
+            });
+        }
+        let mut row = sys::SRow {
+            cValues: 1,
+            lpProps: props,
+            ..Default::default()
+        };
+        let row = Row::new(&mut row);
+        let root = props as usize;
+
+        let importance = thread::spawn(move || {
+            let importance = row.iter().next().map(|value| value.value);
+            match importance {
+                Some(PropValueData::Long(importance)) => Some(importance),
+                _ => None,
+            }
+        })
+        .join()
+        .expect("thread panicked");
+        assert_eq!(importance, Some(2));
+        assert!(!backend::is_allocated(root as *mut _));
+    }
+
+    #[test]
+    fn lookup_by_tag() {
+        let mut values = [
+            sys::SPropValue {
+                ulPropTag: sys::PR_IMPORTANCE,
+                Value: sys::__UPV { l: 2 },
+                ..Default::default()
+            },
+            sys::SPropValue {
+                ulPropTag: PropTag(sys::PR_SUBJECT_W)
+                    .change_prop_type(crate::PropType::new(sys::PT_ERROR as u16))
+                    .into(),
+                Value: sys::__UPV {
+                    err: sys::MAPI_E_NOT_FOUND.0,
+                },
+                ..Default::default()
+            },
+        ];
+        let row = Row {
+            count: values.len(),
+            props: values.as_mut_ptr(),
+        };
+
+        let importance = match row
+            .get(PropTag(sys::PR_IMPORTANCE))
+            .map(|value| value.value)
+        {
+            Some(PropValueData::Long(importance)) => Some(importance),
+            _ => None,
+        };
+        let subject = row
+            .get(PropTag(sys::PR_SUBJECT_W))
+            .map(|value| u32::from(value.tag.prop_type()));
+        let missing = row.get(PropTag(sys::PR_BODY_W)).is_none();
+        let keys: Vec<_> = row.to_map().into_keys().collect();
+        mem::forget(row);
+
+        assert_eq!(importance, Some(2));
+        assert_eq!(subject, Some(sys::PT_ERROR));
+        assert!(missing);
+        let mut expected = [
+            PropTag(sys::PR_IMPORTANCE).prop_id(),
+            PropTag(sys::PR_SUBJECT_W).prop_id(),
+        ];
+        expected.sort();
+        assert_eq!(keys, expected);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize() {
+        let mut values = [sys::SPropValue {
+            ulPropTag: sys::PR_IMPORTANCE,
+            Value: sys::__UPV { l: 2 },
+            ..Default::default()
+        }];
+        let row = Row {
+            count: values.len(),
+            props: values.as_mut_ptr(),
+        };
+        let json = serde_json::to_string(&row);
+        mem::forget(row);
+        assert_eq!(
+            json.expect("serialize failed"),
+            format!(r#"[{{"tag":{},"value":{{"Long":2}}}}]"#, sys::PR_IMPORTANCE)
+        );
+    }
+}
@@ -1,10 +1,11 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
-//! Define [`RowSet`].
+//! Define [`RowSet`] and [`RowSetBuilder`].
 
-use crate::{sys, Row};
-use core::{ptr, slice};
+use crate::{sys, CbNewSRowSet, MAPIUninit, OwnedPropValue, Row, RowRef};
+use core::{mem, ptr, slice};
+use windows_core::*;
 
 /// Container for a [`sys::SRowSet`] structure, such as the rows returned from
 /// [`sys::IMAPITable::QueryRows`].
@@ -14,16 +15,29 @@ use core::{ptr, slice};
 /// separately, as long as the pointer in the [`sys::SRowSet`] is replaced with `null`. The
 /// [`sys::FreeProws`] function will free any non-`null` property pointers in the [`sys::SRowSet`],
 /// but silently skip the ones that are `null`.
+///
+/// Like [`Row`], [`RowSet`] is [`Send`], so a batch of rows can be handed off to another thread
+/// before or after splitting it into individual rows.
 pub struct RowSet {
     rows: *mut sys::SRowSet,
 }
 
+// SAFETY: [`RowSet`] has exclusive ownership of the [`sys::SRowSet`] allocation, which can be freed
+// with [`sys::FreeProws`] on any thread.
+unsafe impl Send for RowSet {}
+
 impl RowSet {
     /// Get an out-param pointer for the [`sys::SRowSet`] pointer.
     pub fn as_mut_ptr(&mut self) -> *mut *mut sys::SRowSet {
         &mut self.rows
     }
 
+    /// Get the [`sys::SRowSet`] pointer, e.g. to pass a [`RowSet`] from [`RowSetBuilder::build`]
+    /// to an API which takes an `LPSRowSet`. The pointer is `null` if nothing has been allocated.
+    pub fn as_set_mut_ptr(&mut self) -> *mut sys::SRowSet {
+        self.rows
+    }
+
     /// Test for a `null` [`sys::SRowSet`] pointer or a pointer to 0 rows.
     pub fn is_empty(&self) -> bool {
         unsafe {
@@ -43,6 +57,53 @@ impl RowSet {
                 .unwrap_or_default()
         }
     }
+
+    /// Borrow the [`sys::SRow`] structures without taking ownership of their values.
+    pub(crate) fn rows(&self) -> &[sys::SRow] {
+        unsafe {
+            match self.rows.as_ref() {
+                Some(rows) => slice::from_raw_parts(rows.aRow.as_ptr(), rows.cRows as usize),
+                None => &[],
+            }
+        }
+    }
+
+    /// Iterate over the rows without taking ownership of their values. Each [`RowRef`] borrows
+    /// from the [`sys::SRowSet`], so the rows can be inspected more than once, and then selected
+    /// rows can be converted to an owned [`Row`] with [`RowSet::take`].
+    pub fn iter(&self) -> impl Iterator<Item = RowRef<'_>> {
+        self.rows().iter().map(RowRef::new)
+    }
+
+    /// Borrow the row at `index` without taking ownership of its values. Returns [`None`] if
+    /// `index` is out of bounds.
+    pub fn get(&self, index: usize) -> Option<RowRef<'_>> {
+        self.rows().get(index).map(RowRef::new)
+    }
+
+    /// Borrow the first row, or [`None`] if the [`RowSet`] is empty.
+    pub fn first(&self) -> Option<RowRef<'_>> {
+        self.rows().first().map(RowRef::new)
+    }
+
+    /// Borrow the last row, or [`None`] if the [`RowSet`] is empty.
+    pub fn last(&self) -> Option<RowRef<'_>> {
+        self.rows().last().map(RowRef::new)
+    }
+
+    /// Take ownership of the values in the row at `index`, leaving an empty row in its place.
+    /// Returns [`None`] if `index` is out of bounds.
+    pub fn take(&mut self, index: usize) -> Option<Row> {
+        let rows = unsafe {
+            match self.rows.as_mut() {
+                Some(rows) => {
+                    slice::from_raw_parts_mut(rows.aRow.as_mut_ptr(), rows.cRows as usize)
+                }
+                None => &mut [],
+            }
+        };
+        rows.get_mut(index).map(Row::new)
+    }
 }
 
 impl Default for RowSet {
@@ -90,3 +151,205 @@ impl Drop for RowSet {
         }
     }
 }
+
+/// Collect rows of [`OwnedPropValue`] from [`crate::PropValueBuilder`] and allocate a
+/// [`sys::SRowSet`] of any size for them, instead of declaring a fixed size
+/// [`crate::RowSetBuf`].
+///
+/// Each row keeps its own [`sys::SPropValue`] allocation, which the [`RowSet`] frees with
+/// [`sys::FreeProws`], the same as a [`sys::SRowSet`] returned from MAPI.
+#[derive(Default)]
+pub struct RowSetBuilder {
+    rows: Vec<OwnedPropValue>,
+}
+
+impl RowSetBuilder {
+    /// Add a row.
+    pub fn row(mut self, row: OwnedPropValue) -> Self {
+        self.push(row);
+        self
+    }
+
+    /// Add a row without consuming the builder, e.g. in a loop.
+    pub fn push(&mut self, row: OwnedPropValue) {
+        self.rows.push(row);
+    }
+
+    /// Get the number of rows which have been added.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Test if no rows have been added.
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Allocate the [`sys::SRowSet`] with [`sys::MAPIAllocateBuffer`] and move each row into it.
+    pub fn build(self) -> Result<RowSet> {
+        let count = self.rows.len();
+        // Check the counts up front, so a row is never taken out of its OwnedPropValue and then
+        // leaked by an early return.
+        u32::try_from(count)?;
+        let rows = MAPIUninit::<u8>::new(CbNewSRowSet(count.max(1)))?;
+        let mut rows: MAPIUninit<sys::SRowSet> = rows.into()?;
+        let header = rows.uninit()?.as_mut_ptr();
+        unsafe {
+            // Fill in the count as we go, so FreeProws only sees initialized rows.
+            ptr::addr_of_mut!((*header).cRows).write(0);
+        }
+        // The SRowSet is freed with FreeProws now.
+        mem::forget(rows);
+        let result = RowSet { rows: header };
+
+        for (index, row) in self.rows.into_iter().enumerate() {
+            let count = u32::try_from(row.len())?;
+            let (_, props) = row.into_raw();
+            unsafe {
+                ptr::addr_of_mut!((*header).aRow)
+                    .cast::<sys::SRow>()
+                    .add(index)
+                    .write(sys::SRow {
+                        ulAdrEntryPad: 0,
+                        cValues: count,
+                        lpProps: props,
+                    });
+                (*header).cRows = index as u32 + 1;
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for RowSet {
+    /// Serialize the rows as a sequence of [`Row`], without taking ownership of their values.
+    fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mapi_ptr::backend, PropTag, PropValue, PropValueData};
+    use std::thread;
+
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn send() {
+        assert_send::<RowSet>();
+        assert_send::<Row>();
+
+        let rows = RowSet::default();
+        let count = thread::spawn(move || rows.len())
+            .join()
+            .expect("thread panicked");
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn empty_rows() {
+        let rows = RowSet::default();
+        assert!(rows.is_empty());
+        assert!(rows.first().is_none());
+        assert!(rows.last().is_none());
+        assert!(rows.get(0).is_none());
+    }
+
+    #[test]
+    fn borrow_rows() {
+        let mut first = [sys::SPropValue {
+            ulPropTag: sys::PR_IMPORTANCE,
+            Value: sys::__UPV { l: 2 },
+            ..Default::default()
+        }];
+        let mut rows = crate::RowSetBuf {
+            aRow: [
+                sys::SRow {
+                    cValues: first.len() as u32,
+                    lpProps: first.as_mut_ptr(),
+                    ..Default::default()
+                },
+                Default::default(),
+            ],
+            ..Default::default()
+        };
+        let mut row_set = RowSet {
+            rows: rows.as_mut_ptr(),
+        };
+
+        assert_eq!(row_set.len(), 2);
+        assert!(!row_set.is_empty());
+        assert_eq!(row_set.first().map(|row| row.len()), Some(1));
+        assert_eq!(row_set.get(1).map(|row| row.len()), Some(0));
+        assert_eq!(row_set.last().map(|row| row.len()), Some(0));
+        assert!(row_set.get(2).is_none());
+
+        for _ in 0..2 {
+            let lens: Vec<_> = row_set.iter().map(|row| row.len()).collect();
+            assert_eq!(lens, [1, 0]);
+        }
+        let importance = row_set
+            .iter()
+            .next()
+            .and_then(|row| row.iter().next())
+            .map(|value| value.value);
+        assert!(matches!(importance, Some(crate::PropValueData::Long(2))));
+
+        assert!(row_set.take(2).is_none());
+        let row = row_set.take(0).expect("missing row");
+        assert_eq!(row.len(), 1);
+        assert!(row_set.iter().all(|row| row.is_empty()));
+
+        // Nothing here was allocated with MAPI, so skip the destructors.
+        mem::forget(row);
+        mem::forget(row_set);
+    }
+
+    #[test]
+    fn build_rows() {
+        let first = crate::PropValueBuilder::new()
+            .string(PropTag(sys::PR_SUBJECT_W), "Hello")
+            .expect("string failed");
+        // SAFETY: `PropValueData::Long` does not hold any pointers.
+        let first = unsafe {
+            first.value(PropValue {
+                tag: PropTag(sys::PR_IMPORTANCE),
+                value: PropValueData::Long(2),
+            })
+        }
+        .build()
+        .expect("build failed");
+        let mut builder = RowSetBuilder::default().row(first);
+        builder.push(
+            crate::PropValueBuilder::new()
+                .build()
+                .expect("build failed"),
+        );
+        assert_eq!(builder.len(), 2);
+
+        let mut row_set = builder.build().expect("build failed");
+        assert_eq!(row_set.len(), 2);
+        let lens: Vec<_> = row_set.iter().map(|row| row.len()).collect();
+        assert_eq!(lens, [2, 0]);
+        let importance = row_set
+            .first()
+            .and_then(|row| row.iter().nth(1))
+            .map(|value| value.value);
+        assert!(matches!(importance, Some(PropValueData::Long(2))));
+
+        unsafe {
+            let rows = row_set.as_set_mut_ptr();
+            let props: Vec<_> = (0..2)
+                .map(|index| (*rows).aRow.as_ptr().add(index).read().lpProps)
+                .collect();
+            assert!(props[1].is_null());
+            backend::free_list(row_set, rows as *mut _, props);
+        }
+    }
+}
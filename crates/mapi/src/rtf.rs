@@ -0,0 +1,142 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`RtfBody`].
+//!
+//! The RTF body of a message is stored in [`sys::PR_RTF_COMPRESSED`] in the compressed format
+//! described in [MS-OXRTFCP], so it needs to be wrapped with [`sys::WrapCompressedRTFStream`]
+//! before it can be read. Use [`crate::Message::open_rtf_body`] to do that, and
+//! [`crate::Message::set_rtf_body`] to replace it.
+//!
+//! [MS-OXRTFCP]: https://learn.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxrtfcp
+
+use crate::{sys, CodePage, ObjectKind, PropertyStream};
+use std::io::{self, Read, Write};
+use windows_core::*;
+
+/// Decompressed RTF from [`sys::PR_RTF_COMPRESSED`], returned from
+/// [`crate::Message::open_rtf_body`].
+pub struct RtfBody {
+    stream: PropertyStream,
+}
+
+impl RtfBody {
+    /// Call [`sys::WrapCompressedRTFStream`] on a [`sys::PR_RTF_COMPRESSED`] stream, e.g. from
+    /// [`crate::MAPIProp::open_property_stream`], to read the decompressed RTF.
+    pub fn wrap_compressed(compressed: &PropertyStream) -> Result<Self> {
+        Self::wrap(compressed, 0)
+    }
+
+    /// Call [`sys::WrapCompressedRTFStream`] with [`sys::MAPI_MODIFY`] on a
+    /// [`sys::PR_RTF_COMPRESSED`] stream opened for writing, so the RTF written to it is
+    /// compressed. Call [`Write::flush`] on the [`RtfBody`] and then on the `compressed` stream to
+    /// keep the changes.
+    pub fn wrap_compressed_for_write(compressed: &PropertyStream) -> Result<Self> {
+        Self::wrap(compressed, sys::MAPI_MODIFY)
+    }
+
+    fn wrap(compressed: &PropertyStream, flags: u32) -> Result<Self> {
+        let stream = unsafe { sys::WrapCompressedRTFStream(&compressed.stream, flags)? };
+        Ok(Self {
+            stream: PropertyStream::with_registration(
+                stream,
+                compressed.registration().child(ObjectKind::PropertyStream),
+            ),
+        })
+    }
+
+    /// Read the rest of the RTF and convert it to a UTF-8 [`String`].
+    ///
+    /// RTF is an 8-bit format, so the bytes are decoded with the code page in the `\ansicpg`
+    /// control word in the RTF header, or [`CodePage::WINDOWS_1252`] if there isn't one. This
+    /// does not extract the plain text, the result is still RTF, including all of the control
+    /// words and groups.
+    pub fn into_rtf_string(mut self) -> Result<String> {
+        let mut rtf = Vec::new();
+        self.read_to_end(&mut rtf)?;
+        header_code_page(&rtf).decode(&rtf)
+    }
+}
+
+impl Read for RtfBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for RtfBody {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Check for the `\fromhtml` control word in the RTF header, which means the RTF encapsulates an
+/// HTML body as described in [MS-OXRTFEX], and the HTML in [`sys::PR_HTML`] is the real body.
+///
+/// [MS-OXRTFEX]: https://learn.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxrtfex
+pub(crate) fn is_encapsulated_html(rtf: &[u8]) -> bool {
+    const FROMHTML: &[u8] = br"\fromhtml";
+
+    // The header has to come before the first group which is not the document itself.
+    let header = rtf
+        .iter()
+        .skip(1)
+        .position(|ch| *ch == b'{')
+        .map_or(rtf, |end| &rtf[..=end]);
+    header
+        .windows(FROMHTML.len())
+        .any(|window| window == FROMHTML)
+}
+
+/// Find the `\ansicpgN` control word in the RTF, which is the code page for the whole document.
+fn header_code_page(rtf: &[u8]) -> CodePage {
+    const ANSICPG: &[u8] = br"\ansicpg";
+
+    rtf.windows(ANSICPG.len())
+        .position(|window| window == ANSICPG)
+        .and_then(|start| {
+            let digits: Vec<_> = rtf[start + ANSICPG.len()..]
+                .iter()
+                .take_while(|ch| ch.is_ascii_digit())
+                .copied()
+                .collect();
+            String::from_utf8(digits).ok()?.parse().ok()
+        })
+        .map(CodePage)
+        .unwrap_or(CodePage::WINDOWS_1252)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ansicpg() {
+        let rtf = br"{\rtf1\ansi\ansicpg1251\deff0{\fonttbl{\f0 Calibri;}}Hello}";
+        assert_eq!(header_code_page(rtf), CodePage(1251));
+    }
+
+    #[test]
+    fn missing_ansicpg() {
+        let rtf = br"{\rtf1\ansi\deff0{\fonttbl{\f0 Calibri;}}Hello}";
+        assert_eq!(header_code_page(rtf), CodePage::WINDOWS_1252);
+    }
+
+    #[test]
+    fn fromhtml() {
+        let rtf = br"{\rtf1\ansi\fbidis\ansicpg1252\deff0\fromhtml1 {\fonttbl{\f0 Arial;}}}";
+        assert!(is_encapsulated_html(rtf));
+        let rtf = br"{\rtf1\ansi\deff0{\fonttbl{\f0 Calibri;}}\fromhtml1}";
+        assert!(!is_encapsulated_html(rtf));
+    }
+
+    #[test]
+    fn empty_ansicpg() {
+        let rtf = br"{\rtf1\ansi\ansicpg\deff0 Hello}";
+        assert_eq!(header_code_page(rtf), CodePage::WINDOWS_1252);
+    }
+}
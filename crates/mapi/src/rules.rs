@@ -0,0 +1,631 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`RulesTable`], [`Rule`], [`RuleAction`], and [`RuleInfo`].
+//!
+//! Server-side rules for a mailbox live in the [`sys::PR_RULES_TABLE`] of the Inbox, which MAPI
+//! exposes as a [`sys::IExchangeModifyTable`]. Each row is one rule, identified by its
+//! [`sys::PR_RULE_ID`], with the conditions and actions in [`sys::PR_RULE_CONDITION`] and
+//! [`sys::PR_RULE_ACTIONS`]. Open it with [`crate::Folder::rules`].
+
+use crate::{
+    alloc_pwstr, mapi_error::with_last_error, prop_value::chain_copy, prop_value::chain_prop_value,
+    sys, CbNewROWLIST, EntryId, InitEpoch, MAPIBuffer, MAPIUninit, MapiArena, ObjectKind,
+    ObjectRegistration, PropTag, PropType, PropValue, PropValueData, Restriction, Row, Table,
+    TableFlags,
+};
+use core::ptr;
+use windows::Win32::Foundation::E_INVALIDARG;
+use windows_core::*;
+
+/// [`sys::PR_RULE_NAME`] as a [`sys::PT_UNICODE`] property.
+const PR_RULE_NAME_W: PropTag =
+    PropTag(sys::PR_RULE_NAME).change_prop_type(PropType::new(sys::PT_UNICODE as u16));
+
+/// [`sys::PR_RULE_PROVIDER`] as a [`sys::PT_UNICODE`] property.
+const PR_RULE_PROVIDER_W: PropTag =
+    PropTag(sys::PR_RULE_PROVIDER).change_prop_type(PropType::new(sys::PT_UNICODE as u16));
+
+/// Action in the [`sys::PR_RULE_ACTIONS`] of a [`Rule`], which is serialized as a
+/// [`sys::ACTION`].
+pub enum RuleAction<'a> {
+    /// [`sys::OP_MOVE`] the message to the folder with `folder_entry_id` in the store with
+    /// `store_entry_id`.
+    Move {
+        store_entry_id: &'a EntryId,
+        folder_entry_id: &'a EntryId,
+    },
+
+    /// [`sys::OP_COPY`] the message to the folder with `folder_entry_id` in the store with
+    /// `store_entry_id`.
+    Copy {
+        store_entry_id: &'a EntryId,
+        folder_entry_id: &'a EntryId,
+    },
+
+    /// [`sys::OP_BOUNCE`] the message back to the sender with an `SCODE`, e.g.
+    /// [`sys::BOUNCE_MESSAGE_REJECTED`].
+    Bounce(i32),
+
+    /// [`sys::OP_TAG`] the message by setting a property on it.
+    Tag(PropValue<'a>),
+
+    /// [`sys::OP_DELETE`] the message.
+    Delete,
+
+    /// [`sys::OP_MARK_AS_READ`].
+    MarkAsRead,
+}
+
+impl RuleAction<'_> {
+    /// Copy the action into allocations chained to `arena`.
+    ///
+    /// # Safety
+    ///
+    /// Raw pointers in a [`RuleAction::Tag`] value must follow the contract of
+    /// [`crate::PropValueBuilder::value`].
+    unsafe fn chain(&self, arena: &MapiArena) -> Result<sys::ACTION> {
+        let move_copy = |store_entry_id: &EntryId, folder_entry_id: &EntryId| {
+            Ok::<_, Error>(sys::ACTION_0 {
+                actMoveCopy: sys::ACTION_0_0 {
+                    cbStoreEntryId: u32::try_from(store_entry_id.len())?,
+                    lpStoreEntryId: chain_copy(arena, store_entry_id.as_bytes())?.cast(),
+                    cbFldEntryId: u32::try_from(folder_entry_id.len())?,
+                    lpFldEntryId: chain_copy(arena, folder_entry_id.as_bytes())?.cast(),
+                },
+            })
+        };
+        let (acttype, data) = match self {
+            Self::Move {
+                store_entry_id,
+                folder_entry_id,
+            } => (sys::OP_MOVE, move_copy(store_entry_id, folder_entry_id)?),
+            Self::Copy {
+                store_entry_id,
+                folder_entry_id,
+            } => (sys::OP_COPY, move_copy(store_entry_id, folder_entry_id)?),
+            Self::Bounce(code) => (
+                sys::OP_BOUNCE,
+                sys::ACTION_0 {
+                    scBounceCode: *code,
+                },
+            ),
+            Self::Tag(value) => (
+                sys::OP_TAG,
+                sys::ACTION_0 {
+                    propTag: chain_prop_value(arena, value)?,
+                },
+            ),
+            Self::Delete => (sys::OP_DELETE, Default::default()),
+            Self::MarkAsRead => (sys::OP_MARK_AS_READ, Default::default()),
+        };
+        Ok(sys::ACTION {
+            acttype,
+            Anonymous: data,
+            ..Default::default()
+        })
+    }
+}
+
+/// Build a rule for [`RulesTable::add_rule`] or [`RulesTable::modify_rule`], with the
+/// [`Restriction`] for [`sys::PR_RULE_CONDITION`] and the [`RuleAction`] list for
+/// [`sys::PR_RULE_ACTIONS`].
+pub struct Rule<'a> {
+    id: Option<i64>,
+    name: String,
+    provider: String,
+    sequence: i32,
+    state: u32,
+    condition: Restriction<'a>,
+    actions: Vec<RuleAction<'a>>,
+}
+
+impl<'a> Rule<'a> {
+    /// Start a rule named `name` which runs when a message matches `condition`. The rule is
+    /// [`sys::ST_ENABLED`], runs first with a [`sys::PR_RULE_SEQUENCE`] of `0`, and belongs to
+    /// the `RuleOrganizer` provider which Outlook uses for the rules it creates.
+    pub fn new(name: &str, condition: Restriction<'a>) -> Self {
+        Self {
+            id: None,
+            name: name.to_string(),
+            provider: String::from("RuleOrganizer"),
+            sequence: 0,
+            state: sys::ST_ENABLED,
+            condition,
+            actions: Vec::new(),
+        }
+    }
+
+    /// Set the [`sys::PR_RULE_ID`] of an existing rule for [`RulesTable::modify_rule`].
+    pub fn id(mut self, id: i64) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    /// Set the [`sys::PR_RULE_PROVIDER`].
+    pub fn provider(mut self, provider: &str) -> Self {
+        self.provider = provider.to_string();
+        self
+    }
+
+    /// Set the [`sys::PR_RULE_SEQUENCE`]. Rules with a lower sequence run first.
+    pub fn sequence(mut self, sequence: i32) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    /// Set the [`sys::PR_RULE_STATE`], e.g. [`sys::ST_ENABLED`] or [`sys::ST_EXIT_LEVEL`].
+    pub fn state(mut self, state: u32) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// Add an action to [`sys::PR_RULE_ACTIONS`]. The actions run in the order they are added.
+    pub fn action(mut self, action: RuleAction<'a>) -> Self {
+        self.actions.push(action);
+        self
+    }
+
+    /// Copy the properties of the rule into allocations chained to `arena`.
+    ///
+    /// # Safety
+    ///
+    /// Raw pointers in the condition or in a [`RuleAction::Tag`] value must follow the contract
+    /// of [`crate::PropValueBuilder::value`].
+    unsafe fn chain(&self, arena: &MapiArena) -> Result<Vec<sys::SPropValue>> {
+        let mut props = Vec::with_capacity(7);
+        if let Some(id) = self.id {
+            props.push(chain_prop_value(arena, &rule_id_value(id))?);
+        }
+
+        let mut name = sys::SPropValue {
+            ulPropTag: PR_RULE_NAME_W.0,
+            ..Default::default()
+        };
+        name.Value.lpszW = alloc_pwstr(arena, &self.name)?;
+        props.push(name);
+
+        let mut provider = sys::SPropValue {
+            ulPropTag: PR_RULE_PROVIDER_W.0,
+            ..Default::default()
+        };
+        provider.Value.lpszW = alloc_pwstr(arena, &self.provider)?;
+        props.push(provider);
+
+        let mut sequence = sys::SPropValue {
+            ulPropTag: sys::PR_RULE_SEQUENCE,
+            ..Default::default()
+        };
+        sequence.Value.l = self.sequence;
+        props.push(sequence);
+
+        let mut state = sys::SPropValue {
+            ulPropTag: sys::PR_RULE_STATE,
+            ..Default::default()
+        };
+        state.Value.ul = self.state;
+        props.push(state);
+
+        let condition = arena.alloc::<sys::SRestriction>(1)?;
+        condition[0] = self.condition.chain(arena)?;
+        let mut value = sys::SPropValue {
+            ulPropTag: sys::PR_RULE_CONDITION,
+            ..Default::default()
+        };
+        value.Value.lpv = condition.as_mut_ptr().cast();
+        props.push(value);
+
+        let actions = arena.alloc::<sys::ACTION>(self.actions.len())?;
+        for (element, action) in actions.iter_mut().zip(self.actions.iter()) {
+            *element = action.chain(arena)?;
+        }
+        let list = arena.alloc::<sys::ACTIONS>(1)?;
+        list[0] = sys::ACTIONS {
+            ulVersion: sys::EDK_RULES_VERSION,
+            cActions: u32::try_from(self.actions.len())?,
+            lpAction: if actions.is_empty() {
+                ptr::null_mut()
+            } else {
+                actions.as_mut_ptr()
+            },
+        };
+        let mut value = sys::SPropValue {
+            ulPropTag: sys::PR_RULE_ACTIONS,
+            ..Default::default()
+        };
+        value.Value.lpv = list.as_mut_ptr().cast();
+        props.push(value);
+
+        Ok(props)
+    }
+}
+
+/// Columns from a row in the rules table, returned from [`RulesTable::rules`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RuleInfo {
+    /// [`sys::PR_RULE_ID`], which identifies the rule for [`RulesTable::modify_rule`] and
+    /// [`RulesTable::delete_rule`].
+    pub id: i64,
+
+    /// [`sys::PR_RULE_NAME`], or an empty string if it is missing.
+    pub name: String,
+
+    /// [`sys::PR_RULE_PROVIDER`], or an empty string if it is missing.
+    pub provider: String,
+
+    /// [`sys::PR_RULE_SEQUENCE`].
+    pub sequence: i32,
+
+    /// [`sys::PR_RULE_STATE`], e.g. [`sys::ST_ENABLED`].
+    pub state: u32,
+
+    /// [`sys::PR_RULE_LEVEL`].
+    pub level: i32,
+
+    /// [`sys::PR_RULE_USER_FLAGS`].
+    pub user_flags: u32,
+}
+
+impl RuleInfo {
+    /// Columns which [`RuleInfo::from_row`] expects in the rules table.
+    pub const COLUMNS: [PropTag; 7] = [
+        PropTag(sys::PR_RULE_ID),
+        PR_RULE_NAME_W,
+        PR_RULE_PROVIDER_W,
+        PropTag(sys::PR_RULE_SEQUENCE),
+        PropTag(sys::PR_RULE_STATE),
+        PropTag(sys::PR_RULE_LEVEL),
+        PropTag(sys::PR_RULE_USER_FLAGS),
+    ];
+
+    /// Read the [`RuleInfo::COLUMNS`] from a row in the rules table. Returns `None` if the row
+    /// does not have a [`sys::PR_RULE_ID`], since there is no way to modify or delete the rule.
+    pub fn from_row(row: &Row) -> Option<Self> {
+        Self::from_props(row.iter())
+    }
+
+    /// Read the [`RuleInfo::COLUMNS`] from any set of properties.
+    pub fn from_props<'a>(values: impl IntoIterator<Item = PropValue<'a>>) -> Option<Self> {
+        let mut id = None;
+        let mut info = Self::default();
+
+        for value in values {
+            let long = match value.value {
+                PropValueData::Long(long) => Some(long),
+                _ => None,
+            };
+            match value.tag {
+                PropTag(sys::PR_RULE_ID) => {
+                    if let PropValueData::LargeInteger(value) = value.value {
+                        id = Some(value);
+                    }
+                }
+                PR_RULE_NAME_W => info.name = value.value.as_string().unwrap_or_default(),
+                PR_RULE_PROVIDER_W => info.provider = value.value.as_string().unwrap_or_default(),
+                PropTag(sys::PR_RULE_SEQUENCE) => info.sequence = long.unwrap_or_default(),
+                PropTag(sys::PR_RULE_STATE) => info.state = long.unwrap_or_default() as u32,
+                PropTag(sys::PR_RULE_LEVEL) => info.level = long.unwrap_or_default(),
+                PropTag(sys::PR_RULE_USER_FLAGS) => {
+                    info.user_flags = long.unwrap_or_default() as u32
+                }
+                _ => {}
+            }
+        }
+
+        info.id = id?;
+        Some(info)
+    }
+}
+
+/// Wrapper for a [`sys::IExchangeModifyTable`] opened on [`sys::PR_RULES_TABLE`].
+pub struct RulesTable {
+    /// Access the [`sys::IExchangeModifyTable`].
+    pub table: sys::IExchangeModifyTable,
+
+    epoch: InitEpoch,
+    registration: ObjectRegistration,
+}
+
+impl RulesTable {
+    /// Wrap a [`sys::IExchangeModifyTable`] opened through another wrapper, so it belongs to the
+    /// same store.
+    pub(crate) fn with_registration(
+        table: sys::IExchangeModifyTable,
+        registration: ObjectRegistration,
+    ) -> Self {
+        Self {
+            table,
+            epoch: InitEpoch::current(),
+            registration,
+        }
+    }
+
+    /// Get the [`ObjectRegistration`] which tracks this table in the [`crate::ObjectRegistry`].
+    pub fn registration(&self) -> &ObjectRegistration {
+        &self.registration
+    }
+
+    /// Make sure MAPI is still initialized and the store is still connected.
+    fn check(&self) -> Result<()> {
+        self.epoch.check()?;
+        Ok(self.registration.check_connected()?)
+    }
+
+    /// Call [`sys::IExchangeModifyTable::GetTable`] and read the [`RuleInfo::COLUMNS`] of every
+    /// rule, in the order of their [`sys::PR_RULE_SEQUENCE`].
+    pub fn rules(&self) -> Result<Vec<RuleInfo>> {
+        self.check()?;
+        let table = unsafe {
+            self.table
+                .GetTable(TableFlags::default().into())
+                .map_err(|error| with_last_error(&self.table, error))?
+        };
+        let table = Table::with_registration(table, self.registration.child(ObjectKind::Table));
+        let mut rules: Vec<_> = table
+            .query_all_rows(&RuleInfo::COLUMNS, None, None)?
+            .into_iter()
+            .filter_map(|row| RuleInfo::from_row(&row))
+            .collect();
+        rules.sort_by_key(|rule| rule.sequence);
+        Ok(rules)
+    }
+
+    /// Add a new rule with [`sys::ROW_ADD`]. The store assigns the [`sys::PR_RULE_ID`], so do
+    /// not call [`Rule::id`]. Raw pointers in the [`Rule`] must follow the contract of
+    /// [`crate::PropValueBuilder::value`].
+    pub fn add_rule(&self, rule: &Rule) -> Result<()> {
+        self.modify_table(&[(sys::ROW_ADD, RowValues::Rule(rule))])
+    }
+
+    /// Replace an existing rule with [`sys::ROW_MODIFY`]. The rule is identified by the
+    /// [`sys::PR_RULE_ID`] from [`Rule::id`], and the rest of the properties replace the ones in
+    /// the table. Raw pointers in the [`Rule`] must follow the contract of
+    /// [`crate::PropValueBuilder::value`].
+    pub fn modify_rule(&self, rule: &Rule) -> Result<()> {
+        if rule.id.is_none() {
+            return Err(Error::from(E_INVALIDARG));
+        }
+        self.modify_table(&[(sys::ROW_MODIFY, RowValues::Rule(rule))])
+    }
+
+    /// Delete the rule with [`sys::PR_RULE_ID`] matching `rule_id` using [`sys::ROW_REMOVE`].
+    pub fn delete_rule(&self, rule_id: i64) -> Result<()> {
+        self.modify_table(&[(
+            sys::ROW_REMOVE,
+            RowValues::Values(&[rule_id_value(rule_id)]),
+        )])
+    }
+
+    /// Call [`sys::IExchangeModifyTable::ModifyTable`] with a [`sys::ROWLIST`] holding one
+    /// [`sys::ROWENTRY`] for each of the `entries`.
+    fn modify_table(&self, entries: &[(u32, RowValues)]) -> Result<()> {
+        self.check()?;
+        let mut row_list = row_list(entries)?;
+        unsafe {
+            self.table
+                .ModifyTable(0, row_list.as_mut()?)
+                .map_err(|error| with_last_error(&self.table, error))
+        }
+    }
+}
+
+/// [`PropValue`] for [`sys::PR_RULE_ID`], which identifies a rule in [`sys::ROW_MODIFY`] and
+/// [`sys::ROW_REMOVE`] entries.
+fn rule_id_value(rule_id: i64) -> PropValue<'static> {
+    PropValue {
+        tag: PropTag(sys::PR_RULE_ID),
+        value: PropValueData::LargeInteger(rule_id),
+    }
+}
+
+/// Properties for one [`sys::ROWENTRY`] in [`row_list`].
+enum RowValues<'a, 'b> {
+    /// Copy a list of [`PropValue`] values.
+    Values(&'b [PropValue<'a>]),
+
+    /// Serialize a [`Rule`].
+    Rule(&'b Rule<'a>),
+}
+
+impl RowValues<'_, '_> {
+    /// Copy the properties into allocations chained to `arena`.
+    #[allow(clippy::mut_from_ref)]
+    fn chain<'c>(&self, arena: &'c MapiArena) -> Result<&'c mut [sys::SPropValue]> {
+        let values = match self {
+            Self::Values(values) => values
+                .iter()
+                // SAFETY: Raw pointers in the values follow the contract of
+                // `PropValueBuilder::value`, as documented on the public methods.
+                .map(|value| unsafe { chain_prop_value(arena, value) })
+                .collect::<Result<Vec<_>>>()?,
+            // SAFETY: Raw pointers in the rule follow the contract of `PropValueBuilder::value`,
+            // as documented on the public methods.
+            Self::Rule(rule) => unsafe { rule.chain(arena) }?,
+        };
+        let props = arena.alloc::<sys::SPropValue>(values.len())?;
+        props.copy_from_slice(&values);
+        Ok(props)
+    }
+}
+
+/// Build a [`sys::ROWLIST`] with a [`sys::ROWENTRY`] for each pair of row flags and property
+/// values. The property values are copied into allocations chained to the [`sys::ROWLIST`], so
+/// they are all freed together with the returned buffer.
+fn row_list(entries: &[(u32, RowValues)]) -> Result<MAPIBuffer<'static, sys::ROWLIST>> {
+    let row_list = MAPIUninit::<u8>::new(CbNewROWLIST(entries.len().max(1)))?;
+    let mut row_list: MAPIUninit<sys::ROWLIST> = row_list.into()?;
+    let header = row_list.uninit()?.as_mut_ptr();
+    {
+        let arena = MapiArena::chained(&row_list);
+        for (index, (flags, values)) in entries.iter().enumerate() {
+            let props = values.chain(&arena)?;
+            unsafe {
+                ptr::addr_of_mut!((*header).aEntries)
+                    .cast::<sys::ROWENTRY>()
+                    .add(index)
+                    .write(sys::ROWENTRY {
+                        ulRowFlags: *flags,
+                        cValues: u32::try_from(props.len())?,
+                        rgPropVals: props.as_mut_ptr(),
+                    });
+            }
+        }
+    }
+    unsafe {
+        ptr::addr_of_mut!((*header).cEntries).write(u32::try_from(entries.len())?);
+        if entries.is_empty() {
+            ptr::addr_of_mut!((*header).aEntries).write(Default::default());
+        }
+        Ok(row_list.assume_init())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{to_pwstr_buffer, RelOp};
+    use core::slice;
+
+    #[test]
+    fn build_row_list() {
+        let name = to_pwstr_buffer("Move to Archive");
+        let added = [
+            PropValue {
+                tag: PropTag(sys::PR_RULE_NAME)
+                    .change_prop_type(PropType::new(sys::PT_UNICODE as u16)),
+                value: PropValueData::Unicode(name),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_RULE_SEQUENCE),
+                value: PropValueData::Long(10),
+            },
+        ];
+        let removed = [rule_id_value(42)];
+        let mut row_list = row_list(&[
+            (sys::ROW_ADD, RowValues::Values(&added)),
+            (sys::ROW_REMOVE, RowValues::Values(&removed)),
+        ])
+        .expect("row_list failed");
+        let row_list = row_list.as_mut().expect("as_mut failed");
+        assert_eq!(row_list.cEntries, 2);
+
+        let entries = unsafe {
+            slice::from_raw_parts(row_list.aEntries.as_ptr(), row_list.cEntries as usize)
+        };
+        assert_eq!(entries[0].ulRowFlags, sys::ROW_ADD);
+        assert_eq!(entries[0].cValues, 2);
+        assert_eq!(entries[1].ulRowFlags, sys::ROW_REMOVE);
+        assert_eq!(entries[1].cValues, 1);
+
+        let added = unsafe { slice::from_raw_parts(entries[0].rgPropVals, 2) };
+        let name = PropValue::from(&added[0]);
+        let PropValueData::Unicode(name) = name.value else {
+            panic!("wrong type");
+        };
+        assert_eq!(String::from_utf16_lossy(&name), "Move to Archive\0");
+        assert_eq!(added[1].ulPropTag, sys::PR_RULE_SEQUENCE);
+
+        let removed = unsafe { &*entries[1].rgPropVals };
+        assert_eq!(removed.ulPropTag, sys::PR_RULE_ID);
+        assert_eq!(unsafe { removed.Value.li }, 42);
+    }
+
+    #[test]
+    fn build_rule() {
+        let subject = crate::to_pwstr_buffer("Newsletter");
+        let store = EntryId::new(vec![1, 2, 3]);
+        let folder = EntryId::new(vec![4, 5]);
+        let rule = Rule::new(
+            "Archive newsletters",
+            Restriction::Property {
+                relop: RelOp::Equal,
+                value: PropValue {
+                    tag: PropTag(sys::PR_SUBJECT_W),
+                    value: PropValueData::Unicode(subject),
+                },
+            },
+        )
+        .id(7)
+        .sequence(10)
+        .action(RuleAction::Move {
+            store_entry_id: &store,
+            folder_entry_id: &folder,
+        })
+        .action(RuleAction::MarkAsRead);
+
+        let mut row_list =
+            row_list(&[(sys::ROW_MODIFY, RowValues::Rule(&rule))]).expect("row_list failed");
+        let row_list = row_list.as_mut().expect("as_mut failed");
+        let entry = unsafe { &*row_list.aEntries.as_ptr() };
+        let props = unsafe { slice::from_raw_parts(entry.rgPropVals, entry.cValues as usize) };
+        let tags: Vec<_> = props.iter().map(|prop| prop.ulPropTag).collect();
+        assert_eq!(
+            tags,
+            [
+                sys::PR_RULE_ID,
+                PR_RULE_NAME_W.0,
+                PR_RULE_PROVIDER_W.0,
+                sys::PR_RULE_SEQUENCE,
+                sys::PR_RULE_STATE,
+                sys::PR_RULE_CONDITION,
+                sys::PR_RULE_ACTIONS,
+            ]
+        );
+        assert_eq!(unsafe { props[0].Value.li }, 7);
+        assert_eq!(
+            PropValue::from(&props[1]).value.as_string().as_deref(),
+            Some("Archive newsletters")
+        );
+        assert_eq!(unsafe { props[3].Value.l }, 10);
+        assert_eq!(unsafe { props[4].Value.ul }, sys::ST_ENABLED);
+
+        let condition = unsafe { &*props[5].Value.lpv.cast::<sys::SRestriction>() };
+        assert_eq!(condition.rt, sys::RES_PROPERTY);
+        assert_eq!(
+            unsafe { condition.res.resProperty.ulPropTag },
+            sys::PR_SUBJECT_W
+        );
+
+        let actions = unsafe { &*props[6].Value.lpv.cast::<sys::ACTIONS>() };
+        assert_eq!(actions.ulVersion, sys::EDK_RULES_VERSION);
+        let actions = unsafe { slice::from_raw_parts(actions.lpAction, actions.cActions as usize) };
+        assert_eq!(actions.len(), 2);
+        assert_eq!(actions[0].acttype, sys::OP_MOVE);
+        let move_copy = unsafe { actions[0].Anonymous.actMoveCopy };
+        let folder_entry_id = unsafe {
+            slice::from_raw_parts(
+                move_copy.lpFldEntryId.cast::<u8>(),
+                move_copy.cbFldEntryId as usize,
+            )
+        };
+        assert_eq!(folder_entry_id, folder.as_bytes());
+        assert_eq!(move_copy.cbStoreEntryId, 3);
+        assert_eq!(actions[1].acttype, sys::OP_MARK_AS_READ);
+    }
+
+    #[test]
+    fn rule_info_from_props() {
+        let name = crate::to_pwstr_buffer("Archive newsletters");
+        let info = RuleInfo::from_props([
+            rule_id_value(7),
+            PropValue {
+                tag: PR_RULE_NAME_W,
+                value: PropValueData::Unicode(name),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_RULE_SEQUENCE),
+                value: PropValueData::Long(10),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_RULE_STATE),
+                value: PropValueData::Long(sys::ST_ENABLED as i32),
+            },
+        ])
+        .expect("missing PR_RULE_ID");
+        assert_eq!(info.id, 7);
+        assert_eq!(info.name, "Archive newsletters");
+        assert_eq!(info.sequence, 10);
+        assert_eq!(info.state, sys::ST_ENABLED);
+        assert!(RuleInfo::from_props([]).is_none());
+    }
+}
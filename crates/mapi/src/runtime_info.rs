@@ -0,0 +1,161 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`MapiRuntimeInfo`], [`FileVersion`], and [`mapi_runtime_info`].
+
+pub use outlook_mapi_sys::MapiModuleSource;
+
+use crate::to_pwstr_buffer;
+use core::{fmt, mem, ptr};
+use std::{env, path::PathBuf};
+use windows::Win32::{
+    Foundation::*,
+    Storage::FileSystem::{
+        GetFileVersionInfoSizeW, GetFileVersionInfoW, VerQueryValueW, VS_FIXEDFILEINFO,
+    },
+    System::LibraryLoader::GetModuleFileNameW,
+};
+use windows_core::*;
+
+/// Signature in [`VS_FIXEDFILEINFO::dwSignature`].
+const VS_FFI_SIGNATURE: u32 = 0xFEEF04BD;
+
+/// `FileVersion` from the version resource of a DLL, e.g. `16.0.17928.20114`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileVersion {
+    /// High word of [`VS_FIXEDFILEINFO::dwFileVersionMS`].
+    pub major: u16,
+
+    /// Low word of [`VS_FIXEDFILEINFO::dwFileVersionMS`].
+    pub minor: u16,
+
+    /// High word of [`VS_FIXEDFILEINFO::dwFileVersionLS`].
+    pub build: u16,
+
+    /// Low word of [`VS_FIXEDFILEINFO::dwFileVersionLS`].
+    pub revision: u16,
+}
+
+impl FileVersion {
+    /// Split the [`VS_FIXEDFILEINFO::dwFileVersionMS`] and
+    /// [`VS_FIXEDFILEINFO::dwFileVersionLS`] values.
+    pub fn new(version_ms: u32, version_ls: u32) -> Self {
+        Self {
+            major: (version_ms >> 16) as u16,
+            minor: version_ms as u16,
+            build: (version_ls >> 16) as u16,
+            revision: version_ls as u16,
+        }
+    }
+}
+
+impl fmt::Display for FileVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}.{}",
+            self.major, self.minor, self.build, self.revision
+        )
+    }
+}
+
+/// Details about the MAPI DLL which this crate calls into, returned from [`mapi_runtime_info`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MapiRuntimeInfo {
+    /// Full path of the DLL.
+    pub path: PathBuf,
+
+    /// `FileVersion` of the DLL, if it has a version resource.
+    pub file_version: Option<FileVersion>,
+
+    /// Architecture of the DLL, e.g. `x86_64`. It is loaded in this process, so it always
+    /// matches [`std::env::consts::ARCH`].
+    pub architecture: &'static str,
+
+    /// Whether it was Outlook's `olmapi32.dll`, and how it was found, or the `mapi32.dll` stub.
+    pub source: MapiModuleSource,
+}
+
+impl MapiRuntimeInfo {
+    /// Check if this is Outlook's `olmapi32.dll` rather than the `mapi32.dll` stub. See
+    /// [`crate::is_outlook_mapi_installed`].
+    pub fn is_outlook(&self) -> bool {
+        !matches!(self.source, MapiModuleSource::Mapi32)
+    }
+}
+
+/// Load the MAPI DLL the same way the bindings do, if it is not loaded already, and describe
+/// which one was loaded and where it came from.
+pub fn mapi_runtime_info() -> Result<MapiRuntimeInfo> {
+    let (module, source) = outlook_mapi_sys::mapi_module();
+    let path = module_path(module)?;
+    let file_version = file_version(&path);
+    Ok(MapiRuntimeInfo {
+        path,
+        file_version,
+        architecture: env::consts::ARCH,
+        source,
+    })
+}
+
+/// Call [`GetModuleFileNameW`], growing the buffer until the whole path fits.
+fn module_path(module: HMODULE) -> Result<PathBuf> {
+    let mut buffer = vec![0_u16; MAX_PATH as usize];
+    loop {
+        let len = unsafe { GetModuleFileNameW(Some(module), &mut buffer) } as usize;
+        if len == 0 {
+            return Err(Error::from_win32());
+        }
+        if len < buffer.len() {
+            return Ok(PathBuf::from(String::from_utf16(&buffer[..len])?));
+        }
+        buffer.resize(buffer.len() * 2, 0);
+    }
+}
+
+/// Read the [`VS_FIXEDFILEINFO`] from the version resource of the file at `path`.
+pub(crate) fn file_version(path: &std::path::Path) -> Option<FileVersion> {
+    let path = to_pwstr_buffer(path.to_str()?);
+    let path = PCWSTR::from_raw(path.as_ptr());
+    unsafe {
+        let size = GetFileVersionInfoSizeW(path, None);
+        if size == 0 {
+            return None;
+        }
+        let mut data = vec![0_u8; size as usize];
+        GetFileVersionInfoW(path, None, size, data.as_mut_ptr() as *mut _).ok()?;
+
+        let mut info = ptr::null_mut();
+        let mut len = 0;
+        if !VerQueryValueW(data.as_ptr() as *const _, w!("\\"), &mut info, &mut len).as_bool()
+            || info.is_null()
+            || (len as usize) < mem::size_of::<VS_FIXEDFILEINFO>()
+        {
+            return None;
+        }
+        let info = &*(info as *const VS_FIXEDFILEINFO);
+        (info.dwSignature == VS_FFI_SIGNATURE)
+            .then(|| FileVersion::new(info.dwFileVersionMS, info.dwFileVersionLS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_file_version() {
+        let version = FileVersion::new(0x0010_0000, 0x4608_4E82);
+        assert_eq!(
+            version,
+            FileVersion {
+                major: 16,
+                minor: 0,
+                build: 17928,
+                revision: 20098,
+            }
+        );
+        assert_eq!(version.to_string(), "16.0.17928.20098");
+        assert!(version > FileVersion::new(0x000F_0000, 0));
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`ServiceLogon`].
+//!
+//! A Windows service logs on with [`sys::MAPI_NT_SERVICE`], which needs to be passed to both
+//! [`sys::MAPIInitialize`] and [`sys::MAPILogonEx`]. MAPI reads the profiles from the
+//! `HKEY_CURRENT_USER` hive of the service account rather than the interactive user, and it can
+//! never display any UI, so a profile that works in Outlook often fails in a service.
+
+use crate::{sys, CodePage, Initialize, InitializeFlags, Logon, LogonFlags, PropTag, Table};
+use windows::Win32::Foundation::*;
+use windows_core::*;
+
+/// Initialize MAPI and logon to a profile from a Windows service, without a password prompt or
+/// any other UI.
+pub struct ServiceLogon<'a> {
+    /// Name of the profile in the service account's profile hive.
+    pub profile_name: &'a str,
+
+    /// Optional profile password.
+    pub password: Option<&'a str>,
+
+    /// Pass [`sys::MAPI_NO_MAIL`] to [`sys::MAPILogonEx`], which prevents the spooler from
+    /// sending or receiving mail for this session. This is the default, since a service usually
+    /// shares the profile with a client that already runs the spooler.
+    pub no_mail: bool,
+}
+
+impl<'a> ServiceLogon<'a> {
+    /// Logon to the named profile with [`sys::MAPI_NO_MAIL`] and no password.
+    pub fn new(profile_name: &'a str) -> Self {
+        Self {
+            profile_name,
+            password: None,
+            no_mail: true,
+        }
+    }
+
+    /// Call [`sys::MAPIInitialize`] and [`sys::MAPILogonEx`] with [`sys::MAPI_NT_SERVICE`].
+    ///
+    /// Before logging on, this checks that the profile exists in the profile table for the
+    /// service account and returns [`sys::MAPI_E_NOT_FOUND`] if it does not. Logon failures which
+    /// would require UI are returned with a message describing the likely misconfiguration.
+    pub fn logon(self) -> Result<Logon> {
+        let initialized = Initialize::new(InitializeFlags {
+            nt_service: true,
+            ..Default::default()
+        })?;
+
+        if !self.profile_exists()? {
+            return Err(Error::new(
+                sys::MAPI_E_NOT_FOUND,
+                format!(
+                    "profile \"{}\" was not found; MAPI_NT_SERVICE reads profiles from the \
+                     service account's registry hive, not the interactive user's",
+                    self.profile_name
+                ),
+            ));
+        }
+
+        Logon::new(
+            initialized,
+            HWND::default(),
+            Some(self.profile_name),
+            self.password,
+            LogonFlags {
+                explicit_profile: true,
+                extended: true,
+                new_session: true,
+                no_mail: self.no_mail,
+                nt_service: true,
+                ..Default::default()
+            },
+        )
+        .map_err(|err| logon_error(err, self.profile_name))
+    }
+
+    fn profile_exists(&self) -> Result<bool> {
+        let prof_admin = unsafe { sys::MAPIAdminProfiles(0)? };
+        let table = Table::new(unsafe { prof_admin.GetProfileTable(0)? });
+        let rows = table.query_all_rows(&[PropTag(sys::PR_DISPLAY_NAME_A)], None, None)?;
+        Ok(rows.into_iter().any(|row| {
+            row.iter().any(|value| {
+                // SAFETY: The value is borrowed from a row which MAPI returned.
+                unsafe { value.value.as_string_in(CodePage::ACP) }
+                    .is_some_and(|name| name.eq_ignore_ascii_case(self.profile_name))
+            })
+        }))
+    }
+}
+
+/// Replace the message on the errors that usually mean the profile is not set up for a service.
+fn logon_error(err: Error, profile_name: &str) -> Error {
+    let code = err.code();
+    if code == sys::MAPI_E_LOGON_FAILED || code == sys::MAPI_E_USER_CANCEL {
+        Error::new(
+            code,
+            format!(
+                "logon to profile \"{profile_name}\" needs UI, which is not allowed with \
+                 MAPI_NT_SERVICE; save the credentials in the profile for the service account"
+            ),
+        )
+    } else if code == sys::MAPI_E_UNCONFIGURED {
+        Error::new(
+            code,
+            format!(
+                "profile \"{profile_name}\" is not completely configured; finish configuring it \
+                 as the service account"
+            ),
+        )
+    } else {
+        err
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keep_error_codes() {
+        for code in [
+            sys::MAPI_E_LOGON_FAILED,
+            sys::MAPI_E_USER_CANCEL,
+            sys::MAPI_E_UNCONFIGURED,
+            E_OUTOFMEMORY,
+        ] {
+            assert_eq!(logon_error(Error::from(code), "Service").code(), code);
+        }
+    }
+}
@@ -0,0 +1,531 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`MailMessage`], [`MailRecipient`], [`MailAttachment`], [`SendMailFlags`], and
+//! [`SimpleMapiError`].
+//!
+//! Simple MAPI is the small C API in `mapi32.dll` which hands a message to the default mail
+//! client, e.g. for a "Send to mail recipient" command. It does not need a profile, a session, or
+//! [`sys::MAPIInitialize`]. [`MailMessage::send`] calls `MAPISendMailW` and builds the
+//! `MapiMessageW`, `MapiRecipDescW`, and `MapiFileDescW` structures for it.
+//!
+//! The generated bindings only include the ANSI [`sys::MAPISendMail`], so the Unicode structures
+//! are declared here and `MAPISendMailW` is loaded from `mapi32.dll` on demand.
+
+use crate::{sys, to_pwstr_buffer};
+use core::{ffi::c_void, fmt, iter, ptr};
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+use windows::Win32::{Foundation::*, System::LibraryLoader::*};
+use windows_core::*;
+
+/// `MAPI_DIALOG_MODELESS`, which is missing from [`sys`].
+const MAPI_DIALOG_MODELESS: u32 = 0x0000_0004 | sys::MAPI_DIALOG;
+
+/// `MapiRecipDescW` from `MAPI.h`.
+#[repr(C)]
+struct MapiRecipDescW {
+    reserved: u32,
+    recip_class: u32,
+    name: PWSTR,
+    address: PWSTR,
+    eid_size: u32,
+    entry_id: *mut c_void,
+}
+
+/// `MapiFileDescW` from `MAPI.h`.
+#[repr(C)]
+struct MapiFileDescW {
+    reserved: u32,
+    flags: u32,
+    position: u32,
+    path_name: PWSTR,
+    file_name: PWSTR,
+    file_type: *mut c_void,
+}
+
+/// `MapiMessageW` from `MAPI.h`.
+#[repr(C)]
+struct MapiMessageW {
+    reserved: u32,
+    subject: PWSTR,
+    note_text: PWSTR,
+    message_type: PWSTR,
+    date_received: PWSTR,
+    conversation_id: PWSTR,
+    flags: u32,
+    originator: *mut MapiRecipDescW,
+    recip_count: u32,
+    recips: *mut MapiRecipDescW,
+    file_count: u32,
+    files: *mut MapiFileDescW,
+}
+
+type SendMailW = unsafe extern "system" fn(usize, usize, *mut MapiMessageW, u32, u32) -> u32;
+
+/// Recipient type for a [`MailRecipient`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RecipientKind {
+    /// [`sys::MAPI_TO`]
+    #[default]
+    To,
+
+    /// [`sys::MAPI_CC`]
+    Cc,
+
+    /// [`sys::MAPI_BCC`]
+    Bcc,
+}
+
+impl From<RecipientKind> for u32 {
+    fn from(value: RecipientKind) -> Self {
+        match value {
+            RecipientKind::To => sys::MAPI_TO,
+            RecipientKind::Cc => sys::MAPI_CC,
+            RecipientKind::Bcc => sys::MAPI_BCC,
+        }
+    }
+}
+
+/// Recipient of a [`MailMessage`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MailRecipient {
+    /// Which line the recipient goes on.
+    pub kind: RecipientKind,
+
+    /// Display name. If there is no [`MailRecipient::address`], the mail client resolves this
+    /// name against its address book.
+    pub name: Option<String>,
+
+    /// Email address. Addresses without an address type, e.g. `someone@example.com`, are sent as
+    /// `SMTP:someone@example.com`.
+    pub address: Option<String>,
+}
+
+/// File attached to a [`MailMessage`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MailAttachment {
+    /// Full path of the file to attach. The mail client reads the file during
+    /// [`MailMessage::send`].
+    pub path: PathBuf,
+
+    /// Name shown for the attachment, if it should be different from the file name in the
+    /// [`MailAttachment::path`].
+    pub file_name: Option<String>,
+}
+
+/// Set of flags that can be passed to [`MailMessage::send`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SendMailFlags {
+    /// Pass [`sys::MAPI_DIALOG`] to show the message in the mail client for the user to edit and
+    /// send, instead of sending it right away.
+    pub dialog: bool,
+
+    /// Pass `MAPI_DIALOG_MODELESS` to show the message without blocking the caller. This implies
+    /// [`SendMailFlags::dialog`].
+    pub dialog_modeless: bool,
+
+    /// Pass [`sys::MAPI_LOGON_UI`] to let the mail client prompt the user to logon.
+    pub logon_ui: bool,
+
+    /// Pass [`sys::MAPI_NEW_SESSION`] to use a new session instead of sharing one.
+    pub new_session: bool,
+}
+
+impl From<SendMailFlags> for u32 {
+    fn from(value: SendMailFlags) -> Self {
+        let dialog = if value.dialog_modeless {
+            MAPI_DIALOG_MODELESS
+        } else if value.dialog {
+            sys::MAPI_DIALOG
+        } else {
+            0
+        };
+        let logon_ui = if value.logon_ui {
+            sys::MAPI_LOGON_UI
+        } else {
+            0
+        };
+        let new_session = if value.new_session {
+            sys::MAPI_NEW_SESSION
+        } else {
+            0
+        };
+
+        dialog | logon_ui | new_session
+    }
+}
+
+/// Error code returned from a Simple MAPI function, e.g. [`sys::MAPI_E_UNKNOWN_RECIPIENT`].
+///
+/// Simple MAPI does not return an `HRESULT`, so this is converted to [`sys::MAPI_E_USER_CANCEL`]
+/// for [`sys::MAPI_E_USER_ABORT`], and [`E_FAIL`] with a message including the code for
+/// everything else.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SimpleMapiError {
+    /// The Simple MAPI error code.
+    pub code: u32,
+}
+
+impl SimpleMapiError {
+    /// Turn the result of a Simple MAPI function into a [`Result`].
+    pub fn check(code: u32) -> core::result::Result<(), Self> {
+        match code {
+            sys::SUCCESS_SUCCESS => Ok(()),
+            code => Err(Self { code }),
+        }
+    }
+
+    /// Get the name of the error code, if it is one of the documented values.
+    pub fn name(&self) -> Option<&'static str> {
+        Some(match self.code {
+            sys::MAPI_E_USER_ABORT => "MAPI_E_USER_ABORT",
+            sys::MAPI_E_FAILURE => "MAPI_E_FAILURE",
+            sys::MAPI_E_LOGIN_FAILURE => "MAPI_E_LOGIN_FAILURE",
+            sys::MAPI_E_DISK_FULL => "MAPI_E_DISK_FULL",
+            sys::MAPI_E_INSUFFICIENT_MEMORY => "MAPI_E_INSUFFICIENT_MEMORY",
+            sys::MAPI_E_ACCESS_DENIED => "MAPI_E_ACCESS_DENIED",
+            sys::MAPI_E_TOO_MANY_SESSIONS => "MAPI_E_TOO_MANY_SESSIONS",
+            sys::MAPI_E_TOO_MANY_FILES => "MAPI_E_TOO_MANY_FILES",
+            sys::MAPI_E_TOO_MANY_RECIPIENTS => "MAPI_E_TOO_MANY_RECIPIENTS",
+            sys::MAPI_E_ATTACHMENT_NOT_FOUND => "MAPI_E_ATTACHMENT_NOT_FOUND",
+            sys::MAPI_E_ATTACHMENT_OPEN_FAILURE => "MAPI_E_ATTACHMENT_OPEN_FAILURE",
+            sys::MAPI_E_ATTACHMENT_WRITE_FAILURE => "MAPI_E_ATTACHMENT_WRITE_FAILURE",
+            sys::MAPI_E_UNKNOWN_RECIPIENT => "MAPI_E_UNKNOWN_RECIPIENT",
+            sys::MAPI_E_BAD_RECIPTYPE => "MAPI_E_BAD_RECIPTYPE",
+            sys::MAPI_E_TEXT_TOO_LARGE => "MAPI_E_TEXT_TOO_LARGE",
+            sys::MAPI_E_AMBIGUOUS_RECIPIENT => "MAPI_E_AMBIGUOUS_RECIPIENT",
+            sys::MAPI_E_INVALID_RECIPS => "MAPI_E_INVALID_RECIPS",
+            sys::MAPI_E_NOT_SUPPORTED => "MAPI_E_NOT_SUPPORTED",
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for SimpleMapiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "Simple MAPI failed with {name} ({})", self.code),
+            None => write!(f, "Simple MAPI failed with {}", self.code),
+        }
+    }
+}
+
+impl std::error::Error for SimpleMapiError {}
+
+impl From<SimpleMapiError> for Error {
+    fn from(value: SimpleMapiError) -> Self {
+        match value.code {
+            sys::MAPI_E_USER_ABORT => Error::from(sys::MAPI_E_USER_CANCEL),
+            _ => Error::new(E_FAIL, value.to_string()),
+        }
+    }
+}
+
+/// Build a message to send through the default mail client with [`MailMessage::send`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MailMessage {
+    /// Subject line.
+    pub subject: Option<String>,
+
+    /// Plain text body.
+    pub body: Option<String>,
+
+    /// Recipients on the To, Cc, and Bcc lines.
+    pub recipients: Vec<MailRecipient>,
+
+    /// Files to attach.
+    pub attachments: Vec<MailAttachment>,
+}
+
+impl MailMessage {
+    /// Start with an empty message.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the [`MailMessage::subject`].
+    pub fn subject(mut self, subject: &str) -> Self {
+        self.subject = Some(subject.to_string());
+        self
+    }
+
+    /// Set the plain text [`MailMessage::body`].
+    pub fn body(mut self, body: &str) -> Self {
+        self.body = Some(body.to_string());
+        self
+    }
+
+    /// Add a [`MailRecipient`].
+    pub fn recipient(mut self, recipient: MailRecipient) -> Self {
+        self.recipients.push(recipient);
+        self
+    }
+
+    /// Add a [`RecipientKind::To`] recipient with only an email address.
+    pub fn to(self, address: &str) -> Self {
+        self.address(RecipientKind::To, address)
+    }
+
+    /// Add a [`RecipientKind::Cc`] recipient with only an email address.
+    pub fn cc(self, address: &str) -> Self {
+        self.address(RecipientKind::Cc, address)
+    }
+
+    /// Add a [`RecipientKind::Bcc`] recipient with only an email address.
+    pub fn bcc(self, address: &str) -> Self {
+        self.address(RecipientKind::Bcc, address)
+    }
+
+    /// Attach a file using its own file name.
+    pub fn attach<P>(mut self, path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.attachments.push(MailAttachment {
+            path: path.as_ref().to_path_buf(),
+            file_name: None,
+        });
+        self
+    }
+
+    /// Attach a file with a different name.
+    pub fn attach_as<P>(mut self, path: P, file_name: &str) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.attachments.push(MailAttachment {
+            path: path.as_ref().to_path_buf(),
+            file_name: Some(file_name.to_string()),
+        });
+        self
+    }
+
+    /// Call `MAPISendMailW` to send the message, or to show it to the user first with
+    /// [`SendMailFlags::dialog`]. The `ui_param` is the parent window for any UI.
+    ///
+    /// If `MAPISendMailW` is not available, this returns [`SimpleMapiError`] with
+    /// [`sys::MAPI_E_NOT_SUPPORTED`]. If the user cancels, this returns
+    /// [`sys::MAPI_E_USER_CANCEL`].
+    pub fn send(&self, ui_param: HWND, flags: SendMailFlags) -> Result<()> {
+        let send_mail = send_mail_w().ok_or(SimpleMapiError {
+            code: sys::MAPI_E_NOT_SUPPORTED,
+        })?;
+
+        let mut subject = self.subject.as_deref().map(to_pwstr_buffer);
+        let mut body = self.body.as_deref().map(to_pwstr_buffer);
+        let mut names: Vec<_> = self
+            .recipients
+            .iter()
+            .map(|recipient| recipient.name.as_deref().map(to_pwstr_buffer))
+            .collect();
+        let mut addresses: Vec<_> = self
+            .recipients
+            .iter()
+            .map(|recipient| {
+                recipient
+                    .address
+                    .as_deref()
+                    .map(|address| to_pwstr_buffer(&address_with_type(address)))
+            })
+            .collect();
+        let mut paths: Vec<_> = self
+            .attachments
+            .iter()
+            .map(|attachment| {
+                HSTRING::from(attachment.path.as_path())
+                    .iter()
+                    .copied()
+                    .chain(iter::once(0))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let mut file_names: Vec<_> = self
+            .attachments
+            .iter()
+            .map(|attachment| attachment.file_name.as_deref().map(to_pwstr_buffer))
+            .collect();
+
+        let mut recips: Vec<_> = self
+            .recipients
+            .iter()
+            .zip(names.iter_mut().zip(addresses.iter_mut()))
+            .map(|(recipient, (name, address))| MapiRecipDescW {
+                reserved: 0,
+                recip_class: recipient.kind.into(),
+                name: as_pwstr(name),
+                address: as_pwstr(address),
+                eid_size: 0,
+                entry_id: ptr::null_mut(),
+            })
+            .collect();
+        let mut files: Vec<_> = paths
+            .iter_mut()
+            .zip(file_names.iter_mut())
+            .map(|(path, file_name)| MapiFileDescW {
+                reserved: 0,
+                flags: 0,
+                // Attachments go at the end instead of replacing a character in the body.
+                position: u32::MAX,
+                path_name: PWSTR(path.as_mut_ptr()),
+                file_name: as_pwstr(file_name),
+                file_type: ptr::null_mut(),
+            })
+            .collect();
+
+        let mut message = MapiMessageW {
+            reserved: 0,
+            subject: as_pwstr(&mut subject),
+            note_text: as_pwstr(&mut body),
+            message_type: PWSTR::null(),
+            date_received: PWSTR::null(),
+            conversation_id: PWSTR::null(),
+            flags: 0,
+            originator: ptr::null_mut(),
+            recip_count: u32::try_from(recips.len())?,
+            recips: if recips.is_empty() {
+                ptr::null_mut()
+            } else {
+                recips.as_mut_ptr()
+            },
+            file_count: u32::try_from(files.len())?,
+            files: if files.is_empty() {
+                ptr::null_mut()
+            } else {
+                files.as_mut_ptr()
+            },
+        };
+
+        let result = unsafe { send_mail(0, ui_param.0 as usize, &mut message, flags.into(), 0) };
+        Ok(SimpleMapiError::check(result)?)
+    }
+
+    fn address(self, kind: RecipientKind, address: &str) -> Self {
+        self.recipient(MailRecipient {
+            kind,
+            name: None,
+            address: Some(address.to_string()),
+        })
+    }
+}
+
+/// Load `MAPISendMailW` from `mapi32.dll`, which forwards it to the default mail client.
+fn send_mail_w() -> Option<SendMailW> {
+    static SEND_MAIL_W: OnceLock<Option<SendMailW>> = OnceLock::new();
+    *SEND_MAIL_W.get_or_init(|| unsafe {
+        let module = LoadLibraryW(w!("mapi32")).ok()?;
+        let proc = GetProcAddress(module, s!("MAPISendMailW"))?;
+        Some(core::mem::transmute::<
+            unsafe extern "system" fn() -> isize,
+            SendMailW,
+        >(proc))
+    })
+}
+
+fn as_pwstr(value: &mut Option<Vec<u16>>) -> PWSTR {
+    value
+        .as_mut()
+        .map_or(PWSTR::null(), |value| PWSTR(value.as_mut_ptr()))
+}
+
+/// Simple MAPI expects an address type prefix, e.g. `SMTP:`, on [`MailRecipient::address`].
+fn address_with_type(address: &str) -> String {
+    if address.contains(':') {
+        address.to_string()
+    } else {
+        format!("SMTP:{address}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mem;
+
+    #[test]
+    fn unicode_layout() {
+        assert_eq!(
+            mem::size_of::<MapiMessageW>(),
+            mem::size_of::<sys::MapiMessage>()
+        );
+        assert_eq!(
+            mem::size_of::<MapiRecipDescW>(),
+            mem::size_of::<sys::MapiRecipDesc>()
+        );
+        assert_eq!(
+            mem::size_of::<MapiFileDescW>(),
+            mem::size_of::<sys::MapiFileDesc>()
+        );
+    }
+
+    #[test]
+    fn address_type() {
+        assert_eq!(
+            address_with_type("someone@example.com"),
+            "SMTP:someone@example.com"
+        );
+        assert_eq!(address_with_type("EX:/o=Contoso"), "EX:/o=Contoso");
+    }
+
+    #[test]
+    fn send_mail_flags() {
+        assert_eq!(u32::from(SendMailFlags::default()), 0);
+        assert_eq!(
+            u32::from(SendMailFlags {
+                dialog: true,
+                logon_ui: true,
+                ..Default::default()
+            }),
+            sys::MAPI_DIALOG | sys::MAPI_LOGON_UI
+        );
+        assert_eq!(
+            u32::from(SendMailFlags {
+                dialog_modeless: true,
+                ..Default::default()
+            }),
+            0x0C
+        );
+    }
+
+    #[test]
+    fn builder() {
+        let message = MailMessage::new()
+            .subject("Report")
+            .to("a@example.com")
+            .bcc("b@example.com")
+            .attach_as(r"C:\temp\report.pdf", "Report.pdf");
+        assert_eq!(message.subject.as_deref(), Some("Report"));
+        assert_eq!(
+            message
+                .recipients
+                .iter()
+                .map(|recipient| recipient.kind)
+                .collect::<Vec<_>>(),
+            [RecipientKind::To, RecipientKind::Bcc]
+        );
+        assert_eq!(
+            message.attachments[0].file_name.as_deref(),
+            Some("Report.pdf")
+        );
+    }
+
+    #[test]
+    fn simple_mapi_error() {
+        assert_eq!(SimpleMapiError::check(sys::SUCCESS_SUCCESS), Ok(()));
+        let err = SimpleMapiError::check(sys::MAPI_E_UNKNOWN_RECIPIENT).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Simple MAPI failed with MAPI_E_UNKNOWN_RECIPIENT (14)"
+        );
+        assert_eq!(Error::from(err).code(), E_FAIL);
+        assert_eq!(
+            Error::from(SimpleMapiError {
+                code: sys::MAPI_E_USER_ABORT
+            })
+            .code(),
+            sys::MAPI_E_USER_CANCEL
+        );
+    }
+}
@@ -1,12 +1,23 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
-//! Public macros and `const` functions to support SizedXXX types originally from `MAPIDefs.h`.
+//! Public macros, `const` functions, and const-generic types to support SizedXXX types originally
+//! from `MAPIDefs.h`.
+//!
+//! These are in three tiers:
+//!
+//! - Stable: the `CbNewXXX` and `CbXXX` functions, and the const-generic `XXXBuf` types like
+//!   [`PropTagArrayBuf`], [`PropProblemArrayBuf`], [`AdrListBuf`], [`RowSetBuf`], and
+//!   [`SortOrderSetBuf`].
+//! - Deprecated: the `SizedXXX!` macros which have an `XXXBuf` replacement. They still compile,
+//!   but now declare a type alias for the replacement, so existing code can migrate gradually.
+//! - Supported without a replacement: [`SizedENTRYID!`] and the `SizedDtblXXX!` macros, which
+//!   declare structs with inline byte or string buffers that do not fit a single element type.
 
 #![allow(non_snake_case)]
 
-use crate::sys;
-use core::mem;
+use crate::{sys, PropTag};
+use core::{mem, ptr};
 
 /// All of the SizedXXX structs are declared with 1 ([`sys::MAPI_DIM`]) element in accordance with
 /// C/C++ syntax rules that say you can't declare a zero-length array. We need to deduct that
@@ -81,14 +92,87 @@ pub const fn CbSPropTagArray(prop_tag_array: &sys::SPropTagArray) -> usize {
     CbNewSPropTagArray(prop_tag_array.cValues as usize)
 }
 
-/// Declare a variable length struct with the same layout as [`sys::SPropTagArray`] and implement
-/// casting functions:
+/// Fixed size buffer with the same layout as [`sys::SPropTagArray`], holding `N` entries in
+/// [`PropTagArrayBuf::aulPropTag`]. This replaces the [`SizedSPropTagArray!`] macro, and since it
+/// is a regular generic type, it can be named in function signatures and shared between modules.
+///
+/// ### Sample
+/// ```
+/// # use outlook_mapi::{sys, PropTagArrayBuf};
+/// #
+/// let prop_tag_array = PropTagArrayBuf::new([sys::PR_ENTRYID, sys::PR_DISPLAY_NAME_W]);
+///
+/// let prop_tag_array: *const sys::SPropTagArray = prop_tag_array.as_ptr();
+/// ```
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PropTagArrayBuf<const N: usize> {
+    /// Number of entries in [`PropTagArrayBuf::aulPropTag`], which should always be `N`.
+    pub cValues: u32,
+
+    /// Property tags, in the order they should be returned.
+    pub aulPropTag: [u32; N],
+}
+
+impl<const N: usize> PropTagArrayBuf<N> {
+    /// Create a [`PropTagArrayBuf`] holding all of the `tags`.
+    pub const fn new(tags: [u32; N]) -> Self {
+        Self {
+            cValues: N as u32,
+            aulPropTag: tags,
+        }
+    }
+
+    /// Iterate over the property tags as [`PropTag`] values.
+    pub fn tags(&self) -> impl Iterator<Item = PropTag> + '_ {
+        self.aulPropTag.iter().copied().map(PropTag)
+    }
+
+    /// Cast to a [`sys::SPropTagArray`] pointer.
+    pub fn as_ptr(&self) -> *const sys::SPropTagArray {
+        ptr::from_ref(self).cast()
+    }
+
+    /// Cast to a mutable [`sys::SPropTagArray`] pointer.
+    pub fn as_mut_ptr(&mut self) -> *mut sys::SPropTagArray {
+        ptr::from_mut(self).cast()
+    }
+}
+
+impl<const N: usize> Default for PropTagArrayBuf<N> {
+    fn default() -> Self {
+        Self::new([sys::PR_NULL; N])
+    }
+}
+
+impl<const N: usize> From<[u32; N]> for PropTagArrayBuf<N> {
+    fn from(value: [u32; N]) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<const N: usize> From<[PropTag; N]> for PropTagArrayBuf<N> {
+    fn from(value: [PropTag; N]) -> Self {
+        Self::new(value.map(u32::from))
+    }
+}
+
+impl<const N: usize> From<PropTagArrayBuf<N>> for Vec<PropTag> {
+    fn from(value: PropTagArrayBuf<N>) -> Self {
+        value.tags().collect()
+    }
+}
+
+/// Declare a type alias for [`PropTagArrayBuf`] with `count` entries. This used to declare a
+/// separate variable length struct with the same layout as [`sys::SPropTagArray`], so the alias
+/// keeps existing code which uses the struct fields or the casting functions compiling:
 ///
 /// - `fn as_ptr(&self) -> *const sys::SPropTagArray`
 /// - `fn as_mut_ptr(&mut self) -> *mut sys::SPropTagArray`.
 ///
 /// ### Sample
 /// ```
+/// # #![allow(deprecated)]
 /// # use outlook_mapi::{sys, SizedSPropTagArray};
 /// #
 /// SizedSPropTagArray! { PropTagArray[2] }
@@ -103,23 +187,12 @@ pub const fn CbSPropTagArray(prop_tag_array: &sys::SPropTagArray) -> usize {
 ///
 /// let prop_tag_array: *const sys::SPropTagArray = prop_tag_array.as_ptr();
 /// ```
+#[deprecated(note = "use `PropTagArrayBuf<N>` instead")]
 #[macro_export]
 #[allow(non_snake_case)]
 macro_rules! SizedSPropTagArray {
     ($name:ident [ $count:expr ]) => {
-        #[repr(C)]
-        #[allow(non_snake_case)]
-        struct $name {
-            pub cValues: u32,
-            pub aulPropTag: [u32; $count],
-        }
-
-        $crate::impl_sized_struct_casts!($name, $crate::sys::SPropTagArray);
-
-        $crate::impl_sized_struct_default!($name {
-            cValues: $count as u32,
-            aulPropTag: [$crate::sys::PR_NULL; $count],
-        });
+        type $name = $crate::PropTagArrayBuf<{ $count }>;
     };
 }
 
@@ -135,14 +208,77 @@ pub const fn CbSPropProblemArray(prop_problem_array: &sys::SPropProblemArray) ->
     CbNewSPropProblemArray(prop_problem_array.cProblem as usize)
 }
 
-/// Declare a variable length struct with the same layout as [`sys::SPropProblemArray`] and
-/// implement casting functions:
+/// Fixed size buffer with the same layout as [`sys::SPropProblemArray`], holding `N` entries in
+/// [`PropProblemArrayBuf::aProblem`]. This replaces the [`SizedSPropProblemArray!`] macro.
 ///
-/// - `fn as_ptr(&self) -> *const sys::SPropProblemArray`
-/// - `fn as_mut_ptr(&mut self) -> *mut sys::SPropProblemArray`.
+/// ### Sample
+/// ```
+/// # use outlook_mapi::{sys, PropProblemArrayBuf};
+/// #
+/// let prop_problem_array = PropProblemArrayBuf::new([
+///     sys::SPropProblem {
+///         ulIndex: 0,
+///         ulPropTag: sys::PR_ENTRYID,
+///         scode: sys::MAPI_E_NOT_FOUND.0,
+///     },
+/// ]);
+///
+/// let prop_problem_array: *const sys::SPropProblemArray = prop_problem_array.as_ptr();
+/// ```
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PropProblemArrayBuf<const N: usize> {
+    /// Number of entries in [`PropProblemArrayBuf::aProblem`], which should always be `N`.
+    pub cProblem: u32,
+
+    /// Problems reported by the provider, e.g. from [`sys::IMAPIProp::SetProps`].
+    pub aProblem: [sys::SPropProblem; N],
+}
+
+impl<const N: usize> PropProblemArrayBuf<N> {
+    /// Create a [`PropProblemArrayBuf`] holding all of the `problems`.
+    pub const fn new(problems: [sys::SPropProblem; N]) -> Self {
+        Self {
+            cProblem: N as u32,
+            aProblem: problems,
+        }
+    }
+
+    /// Cast to a [`sys::SPropProblemArray`] pointer.
+    pub fn as_ptr(&self) -> *const sys::SPropProblemArray {
+        ptr::from_ref(self).cast()
+    }
+
+    /// Cast to a mutable [`sys::SPropProblemArray`] pointer.
+    pub fn as_mut_ptr(&mut self) -> *mut sys::SPropProblemArray {
+        ptr::from_mut(self).cast()
+    }
+}
+
+impl<const N: usize> Default for PropProblemArrayBuf<N> {
+    fn default() -> Self {
+        const DEFAULT_VALUE: sys::SPropProblem = sys::SPropProblem {
+            ulIndex: 0,
+            ulPropTag: sys::PR_NULL,
+            scode: 0,
+        };
+
+        Self::new([DEFAULT_VALUE; N])
+    }
+}
+
+impl<const N: usize> From<[sys::SPropProblem; N]> for PropProblemArrayBuf<N> {
+    fn from(value: [sys::SPropProblem; N]) -> Self {
+        Self::new(value)
+    }
+}
+
+/// Declare a type alias for [`PropProblemArrayBuf`] with `count` entries. It keeps the field
+/// names and casting functions of the struct this macro used to declare.
 ///
 /// ### Sample
 /// ```
+/// # #![allow(deprecated)]
 /// # use outlook_mapi::{sys, SizedSPropProblemArray};
 /// #
 /// SizedSPropProblemArray! { PropProblemArray[2] }
@@ -165,31 +301,12 @@ pub const fn CbSPropProblemArray(prop_problem_array: &sys::SPropProblemArray) ->
 ///
 /// let prop_problem_array: *const sys::SPropProblemArray = prop_problem_array.as_ptr();
 /// ```
+#[deprecated(note = "use `PropProblemArrayBuf<N>` instead")]
 #[macro_export]
 #[allow(non_snake_case)]
 macro_rules! SizedSPropProblemArray {
     ($name:ident [ $count:expr ]) => {
-        #[repr(C)]
-        #[allow(non_snake_case)]
-        struct $name {
-            pub cProblem: u32,
-            pub aProblem: [$crate::sys::SPropProblem; $count],
-        }
-
-        $crate::impl_sized_struct_casts!($name, $crate::sys::SPropProblemArray);
-
-        {
-            const DEFAULT_VALUE: $crate::sys::SPropProblem = $crate::sys::SPropProblem {
-                ulIndex: 0,
-                ulPropTag: $crate::sys::PR_NULL,
-                scode: 0,
-            };
-
-            $crate::impl_sized_struct_default!($name {
-                cProblem: $count as u32,
-                aProblem: [DEFAULT_VALUE; $count],
-            });
-        }
+        type $name = $crate::PropProblemArrayBuf<{ $count }>;
     };
 }
 
@@ -249,14 +366,77 @@ pub const fn CbADRLIST(adr_list: &sys::ADRLIST) -> usize {
     CbNewADRLIST(adr_list.cEntries as usize)
 }
 
-/// Declare a variable length struct with the same layout as [`sys::ADRLIST`] and implement casting
-/// functions:
+/// Fixed size buffer with the same layout as [`sys::ADRLIST`], holding `N` entries in
+/// [`AdrListBuf::aEntries`]. This replaces the [`SizedADRLIST!`] macro.
 ///
-/// - `fn as_ptr(&self) -> *const sys::ADRLIST`
-/// - `fn as_mut_ptr(&mut self) -> *mut sys::ADRLIST`.
+/// ### Sample
+/// ```
+/// # use outlook_mapi::{sys, AdrListBuf};
+/// #
+/// let adr_list = AdrListBuf::new([
+///     sys::ADRENTRY {
+///         ulReserved1: 0,
+///         cValues: 0,
+///         rgPropVals: core::ptr::null_mut(),
+///     },
+/// ]);
+///
+/// let adr_list: *const sys::ADRLIST = adr_list.as_ptr();
+/// ```
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdrListBuf<const N: usize> {
+    /// Number of entries in [`AdrListBuf::aEntries`], which should always be `N`.
+    pub cEntries: u32,
+
+    /// Recipient entries, each with its own property values.
+    pub aEntries: [sys::ADRENTRY; N],
+}
+
+impl<const N: usize> AdrListBuf<N> {
+    /// Create a [`AdrListBuf`] holding all of the `entries`.
+    pub const fn new(entries: [sys::ADRENTRY; N]) -> Self {
+        Self {
+            cEntries: N as u32,
+            aEntries: entries,
+        }
+    }
+
+    /// Cast to a [`sys::ADRLIST`] pointer.
+    pub fn as_ptr(&self) -> *const sys::ADRLIST {
+        ptr::from_ref(self).cast()
+    }
+
+    /// Cast to a mutable [`sys::ADRLIST`] pointer.
+    pub fn as_mut_ptr(&mut self) -> *mut sys::ADRLIST {
+        ptr::from_mut(self).cast()
+    }
+}
+
+impl<const N: usize> Default for AdrListBuf<N> {
+    fn default() -> Self {
+        const DEFAULT_VALUE: sys::ADRENTRY = sys::ADRENTRY {
+            ulReserved1: 0,
+            cValues: 0,
+            rgPropVals: ptr::null_mut(),
+        };
+
+        Self::new([DEFAULT_VALUE; N])
+    }
+}
+
+impl<const N: usize> From<[sys::ADRENTRY; N]> for AdrListBuf<N> {
+    fn from(value: [sys::ADRENTRY; N]) -> Self {
+        Self::new(value)
+    }
+}
+
+/// Declare a type alias for [`AdrListBuf`] with `count` entries. It keeps the field
+/// names and casting functions of the struct this macro used to declare.
 ///
 /// ### Sample
 /// ```
+/// # #![allow(deprecated)]
 /// use core::ptr;
 /// # use outlook_mapi::{sys, SizedADRLIST};
 ///
@@ -280,31 +460,12 @@ pub const fn CbADRLIST(adr_list: &sys::ADRLIST) -> usize {
 ///
 /// let adr_list: *const sys::ADRLIST = adr_list.as_ptr();
 /// ```
+#[deprecated(note = "use `AdrListBuf<N>` instead")]
 #[macro_export]
 #[allow(non_snake_case)]
 macro_rules! SizedADRLIST {
     ($name:ident [ $count:expr ]) => {
-        #[repr(C)]
-        #[allow(non_snake_case)]
-        struct $name {
-            pub cEntries: u32,
-            pub aEntries: [$crate::sys::ADRENTRY; $count],
-        }
-
-        $crate::impl_sized_struct_casts!($name, $crate::sys::ADRLIST);
-
-        {
-            const DEFAULT_VALUE: $crate::sys::ADRENTRY = $crate::sys::ADRENTRY {
-                ulReserved1: 0,
-                cValues: 0,
-                rgPropVals: core::ptr::null_mut(),
-            };
-
-            $crate::impl_sized_struct_default!($name {
-                cEntries: $count as u32,
-                aEntries: [DEFAULT_VALUE; $count],
-            });
-        }
+        type $name = $crate::AdrListBuf<{ $count }>;
     };
 }
 
@@ -319,14 +480,77 @@ pub const fn CbSRowSet(row_set: &sys::SRowSet) -> usize {
     CbNewSRowSet(row_set.cRows as usize)
 }
 
-/// Declare a variable length struct with the same layout as [`sys::SRowSet`] and implement casting
-/// functions:
+/// Fixed size buffer with the same layout as [`sys::SRowSet`], holding `N` entries in
+/// [`RowSetBuf::aRow`]. This replaces the [`SizedSRowSet!`] macro.
 ///
-/// - `fn as_ptr(&self) -> *const sys::SRowSet`
-/// - `fn as_mut_ptr(&mut self) -> *mut sys::SRowSet`.
+/// ### Sample
+/// ```
+/// # use outlook_mapi::{sys, RowSetBuf};
+/// #
+/// let row_set = RowSetBuf::new([
+///     sys::SRow {
+///         ulAdrEntryPad: 0,
+///         cValues: 0,
+///         lpProps: core::ptr::null_mut(),
+///     },
+/// ]);
+///
+/// let row_set: *const sys::SRowSet = row_set.as_ptr();
+/// ```
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RowSetBuf<const N: usize> {
+    /// Number of entries in [`RowSetBuf::aRow`], which should always be `N`.
+    pub cRows: u32,
+
+    /// Rows, each with its own property values.
+    pub aRow: [sys::SRow; N],
+}
+
+impl<const N: usize> RowSetBuf<N> {
+    /// Create a [`RowSetBuf`] holding all of the `rows`.
+    pub const fn new(rows: [sys::SRow; N]) -> Self {
+        Self {
+            cRows: N as u32,
+            aRow: rows,
+        }
+    }
+
+    /// Cast to a [`sys::SRowSet`] pointer.
+    pub fn as_ptr(&self) -> *const sys::SRowSet {
+        ptr::from_ref(self).cast()
+    }
+
+    /// Cast to a mutable [`sys::SRowSet`] pointer.
+    pub fn as_mut_ptr(&mut self) -> *mut sys::SRowSet {
+        ptr::from_mut(self).cast()
+    }
+}
+
+impl<const N: usize> Default for RowSetBuf<N> {
+    fn default() -> Self {
+        const DEFAULT_VALUE: sys::SRow = sys::SRow {
+            ulAdrEntryPad: 0,
+            cValues: 0,
+            lpProps: ptr::null_mut(),
+        };
+
+        Self::new([DEFAULT_VALUE; N])
+    }
+}
+
+impl<const N: usize> From<[sys::SRow; N]> for RowSetBuf<N> {
+    fn from(value: [sys::SRow; N]) -> Self {
+        Self::new(value)
+    }
+}
+
+/// Declare a type alias for [`RowSetBuf`] with `count` entries. It keeps the field
+/// names and casting functions of the struct this macro used to declare.
 ///
 /// ### Sample
 /// ```
+/// # #![allow(deprecated)]
 /// use core::ptr;
 /// # use outlook_mapi::{sys, SizedSRowSet};
 ///
@@ -350,32 +574,24 @@ pub const fn CbSRowSet(row_set: &sys::SRowSet) -> usize {
 ///
 /// let row_set: *const sys::SRowSet = row_set.as_ptr();
 /// ```
+#[deprecated(note = "use `RowSetBuf<N>` instead")]
 #[macro_export]
 #[allow(non_snake_case)]
 macro_rules! SizedSRowSet {
     ($name:ident [ $count:expr ]) => {
-        #[repr(C)]
-        #[allow(non_snake_case)]
-        struct $name {
-            pub cRows: u32,
-            pub aRow: [$crate::sys::SRow; $count],
-        }
-
-        $crate::impl_sized_struct_casts!($name, $crate::sys::SRowSet);
+        type $name = $crate::RowSetBuf<{ $count }>;
+    };
+}
 
-        {
-            const DEFAULT_VALUE: $crate::sys::SRow = $crate::sys::SRow {
-                ulAdrEntryPad: 0,
-                cValues: 0,
-                lpProps: core::ptr::null_mut(),
-            };
+/// Get the size of a [`sys::ROWLIST`] struct with `count` entries in [`sys::ROWLIST::aEntries`].
+pub const fn CbNewROWLIST(count: usize) -> usize {
+    size_of_container::<sys::ROWLIST, sys::ROWENTRY>(count)
+}
 
-            $crate::impl_sized_struct_default!($name {
-                cRows: $count as u32,
-                aRow: [DEFAULT_VALUE; $count],
-            });
-        }
-    };
+/// Get the size of a [`sys::ROWLIST`] struct with [`sys::ROWLIST::cEntries`] entries in
+/// [`sys::ROWLIST::aEntries`].
+pub const fn CbROWLIST(row_list: &sys::ROWLIST) -> usize {
+    CbNewROWLIST(row_list.cEntries as usize)
 }
 
 /// Get the size of a [`sys::SSortOrderSet`] struct with `count` entries in
@@ -390,14 +606,83 @@ pub const fn CbSSortOrderSet(sort_order_set: &sys::SSortOrderSet) -> usize {
     CbNewSSortOrderSet(sort_order_set.cSorts as usize)
 }
 
-/// Declare a variable length struct with the same layout as [`sys::SSortOrderSet`] and implement
-/// casting functions:
+/// Fixed size buffer with the same layout as [`sys::SSortOrderSet`], holding `N` entries in
+/// [`SortOrderSetBuf::aSort`]. This replaces the [`SizedSSortOrderSet!`] macro.
+///
+/// ### Sample
+/// ```
+/// # use outlook_mapi::{sys, SortOrderSetBuf};
+/// #
+/// let sort_order_set = SortOrderSetBuf::new([
+///     sys::SSortOrder {
+///         ulPropTag: sys::PR_MESSAGE_DELIVERY_TIME,
+///         ulOrder: sys::TABLE_SORT_DESCEND,
+///     },
+/// ]);
 ///
-/// - `fn as_ptr(&self) -> *const sys::SSortOrderSet`
-/// - `fn as_mut_ptr(&mut self) -> *mut sys::SSortOrderSet`.
+/// let sort_order_set: *const sys::SSortOrderSet = sort_order_set.as_ptr();
+/// ```
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SortOrderSetBuf<const N: usize> {
+    /// Number of entries in [`SortOrderSetBuf::aSort`], which should always be `N`.
+    pub cSorts: u32,
+
+    /// Number of leading sort keys which are category columns.
+    pub cCategories: u32,
+
+    /// Number of the categories which start out expanded.
+    pub cExpanded: u32,
+
+    /// Sort keys, starting with the categories.
+    pub aSort: [sys::SSortOrder; N],
+}
+
+impl<const N: usize> SortOrderSetBuf<N> {
+    /// Create a [`SortOrderSetBuf`] holding all of the `sorts`.
+    pub const fn new(sorts: [sys::SSortOrder; N]) -> Self {
+        Self {
+            cSorts: N as u32,
+            cCategories: 0,
+            cExpanded: 0,
+            aSort: sorts,
+        }
+    }
+
+    /// Cast to a [`sys::SSortOrderSet`] pointer.
+    pub fn as_ptr(&self) -> *const sys::SSortOrderSet {
+        ptr::from_ref(self).cast()
+    }
+
+    /// Cast to a mutable [`sys::SSortOrderSet`] pointer.
+    pub fn as_mut_ptr(&mut self) -> *mut sys::SSortOrderSet {
+        ptr::from_mut(self).cast()
+    }
+}
+
+impl<const N: usize> Default for SortOrderSetBuf<N> {
+    fn default() -> Self {
+        const DEFAULT_VALUE: sys::SSortOrder = sys::SSortOrder {
+            ulPropTag: sys::PR_NULL,
+            ulOrder: sys::TABLE_SORT_ASCEND,
+        };
+
+        Self::new([DEFAULT_VALUE; N])
+    }
+}
+
+impl<const N: usize> From<[sys::SSortOrder; N]> for SortOrderSetBuf<N> {
+    fn from(value: [sys::SSortOrder; N]) -> Self {
+        Self::new(value)
+    }
+}
+
+/// Declare a type alias for [`SortOrderSetBuf`] with `count` entries. It keeps the field
+/// names and casting functions of the struct this macro used to declare.
 ///
 /// ### Sample
 /// ```
+/// # #![allow(deprecated)]
 /// # use outlook_mapi::{sys, SizedSSortOrderSet};
 /// #
 /// SizedSSortOrderSet! { SortOrderSet[3] }
@@ -424,34 +709,12 @@ pub const fn CbSSortOrderSet(sort_order_set: &sys::SSortOrderSet) -> usize {
 ///
 /// let sort_order_set: *const sys::SSortOrderSet = sort_order_set.as_ptr();
 /// ```
+#[deprecated(note = "use `SortOrderSetBuf<N>` instead")]
 #[macro_export]
 #[allow(non_snake_case)]
 macro_rules! SizedSSortOrderSet {
     ($name:ident [ $count:expr ]) => {
-        #[repr(C)]
-        #[allow(non_snake_case)]
-        struct $name {
-            pub cSorts: u32,
-            pub cCategories: u32,
-            pub cExpanded: u32,
-            pub aSort: [$crate::sys::SSortOrder; $count],
-        }
-
-        $crate::impl_sized_struct_casts!($name, $crate::sys::SSortOrderSet);
-
-        {
-            const DEFAULT_VALUE: $crate::sys::SSortOrder = $crate::sys::SSortOrder {
-                ulPropTag: $crate::sys::PR_NULL,
-                ulOrder: $crate::sys::TABLE_SORT_ASCEND,
-            };
-
-            $crate::impl_sized_struct_default!($name {
-                cSorts: $count as u32,
-                cCategories: 0,
-                cExpanded: 0,
-                aSort: [DEFAULT_VALUE; $count],
-            });
-        }
+        type $name = $crate::SortOrderSetBuf<{ $count }>;
     };
 }
 
@@ -1096,6 +1359,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(deprecated)]
     fn sized_prop_tag_array() {
         SizedSPropTagArray!(PropTagArray[2]);
 
@@ -1118,6 +1382,32 @@ mod tests {
     }
 
     #[test]
+    fn prop_tag_array_buf() {
+        assert_eq!(mem::size_of::<PropTagArrayBuf<2>>(), CbNewSPropTagArray(2));
+        let mut prop_tag_array =
+            PropTagArrayBuf::from([PropTag(sys::PR_ENTRYID), PropTag(sys::PR_DISPLAY_NAME_W)]);
+        assert_eq!(prop_tag_array.cValues, 2);
+
+        let sys_prop_tag_array = unsafe { prop_tag_array.as_mut_ptr().as_ref() }.unwrap();
+        assert_eq!(CbNewSPropTagArray(2), CbSPropTagArray(sys_prop_tag_array));
+        assert_eq!(sys_prop_tag_array.aulPropTag, [sys::PR_ENTRYID]);
+
+        let tags: Vec<PropTag> = prop_tag_array.into();
+        assert_eq!(
+            tags,
+            [PropTag(sys::PR_ENTRYID), PropTag(sys::PR_DISPLAY_NAME_W)]
+        );
+    }
+
+    #[test]
+    fn prop_tag_array_buf_default() {
+        let prop_tag_array = PropTagArrayBuf::<3>::default();
+        assert_eq!(prop_tag_array.cValues, 3);
+        assert_eq!(prop_tag_array.aulPropTag, [sys::PR_NULL; 3]);
+    }
+
+    #[test]
+    #[allow(deprecated)]
     fn sized_prop_problem_array() {
         SizedSPropProblemArray!(PropProblemArray[2]);
 
@@ -1163,6 +1453,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn list_bufs() {
+        assert_eq!(
+            mem::size_of::<PropProblemArrayBuf<2>>(),
+            CbNewSPropProblemArray(2)
+        );
+        assert_eq!(mem::size_of::<AdrListBuf<2>>(), CbNewADRLIST(2));
+        assert_eq!(mem::size_of::<RowSetBuf<2>>(), CbNewSRowSet(2));
+        assert_eq!(mem::size_of::<SortOrderSetBuf<3>>(), CbNewSSortOrderSet(3));
+
+        let mut row_set = RowSetBuf::<2>::default();
+        let sys_row_set = unsafe { row_set.as_mut_ptr().as_ref() }.unwrap();
+        assert_eq!(CbNewSRowSet(2), CbSRowSet(sys_row_set));
+
+        let sort_order_set = SortOrderSetBuf::from([sys::SSortOrder {
+            ulPropTag: sys::PR_SUBJECT_W,
+            ulOrder: sys::TABLE_SORT_DESCEND,
+        }]);
+        let sys_sort_order_set = unsafe { sort_order_set.as_ptr().as_ref() }.unwrap();
+        assert_eq!(sys_sort_order_set.cSorts, 1);
+        assert_eq!(sys_sort_order_set.cCategories, 0);
+        assert_eq!(sys_sort_order_set.aSort[0].ulPropTag, sys::PR_SUBJECT_W);
+    }
+
     #[test]
     fn sized_flat_lists() {
         assert_eq!(mem::size_of::<sys::FLATENTRY>(), CbNewFLATENTRY(1));
@@ -1172,6 +1486,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(deprecated)]
     fn sized_adr_list() {
         SizedADRLIST!(AdrList[2]);
 
@@ -1209,6 +1524,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(deprecated)]
     fn sized_row_set() {
         SizedSRowSet!(RowSet[2]);
 
@@ -1246,6 +1562,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(deprecated)]
     fn sized_sort_order_set() {
         SizedSSortOrderSet!(SortOrderSet[3]);
 
@@ -0,0 +1,574 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`StatusObject`], [`StatusInfo`], [`ValidateStateFlags`], [`FlushQueuesFlags`], and
+//! [`FlushDirection`].
+//!
+//! The session status table has a row for the MAPI subsystem, the spooler, and each of the
+//! providers in the profile. Open it with [`crate::Logon::status_table`], or open an
+//! [`sys::IMAPIStatus`] for every row with [`crate::Logon::status_objects`].
+
+use crate::{
+    mapi_error::with_last_error, sys, EntryId, InitEpoch, MAPIProp, ObjectKind, ObjectRegistration,
+    PropTag, PropValue, PropValueData, Row,
+};
+use core::ptr;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+use windows::Win32::Foundation::*;
+use windows_core::*;
+
+/// Columns from a row in the session status table.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StatusInfo {
+    /// [`sys::PR_ENTRYID`], which [`crate::Logon::status_objects`] uses to open the
+    /// [`sys::IMAPIStatus`].
+    pub entry_id: EntryId,
+
+    /// [`sys::PR_DISPLAY_NAME_W`], or an empty string if it is missing.
+    pub display_name: String,
+
+    /// [`sys::PR_PROVIDER_DISPLAY_W`], or an empty string if it is missing.
+    pub provider_display: String,
+
+    /// [`sys::PR_RESOURCE_TYPE`], e.g. [`sys::MAPI_SUBSYSTEM`], [`sys::MAPI_SPOOLER`], or
+    /// [`sys::MAPI_TRANSPORT_PROVIDER`].
+    pub resource_type: u32,
+
+    /// [`sys::PR_RESOURCE_METHODS`], e.g. [`sys::STATUS_VALIDATE_STATE`] or
+    /// [`sys::STATUS_FLUSH_QUEUES`] if the object supports those methods.
+    pub resource_methods: u32,
+
+    /// [`sys::PR_STATUS_CODE`], e.g. [`sys::STATUS_AVAILABLE`] or [`sys::STATUS_OFFLINE`].
+    pub status_code: u32,
+
+    /// [`sys::PR_STATUS_STRING_W`], if the provider sets one.
+    pub status_string: Option<String>,
+}
+
+impl StatusInfo {
+    /// Columns which [`StatusInfo::from_row`] expects in the session status table.
+    pub const COLUMNS: [PropTag; 7] = [
+        PropTag(sys::PR_ENTRYID),
+        PropTag(sys::PR_DISPLAY_NAME_W),
+        PropTag(sys::PR_PROVIDER_DISPLAY_W),
+        PropTag(sys::PR_RESOURCE_TYPE),
+        PropTag(sys::PR_RESOURCE_METHODS),
+        PropTag(sys::PR_STATUS_CODE),
+        PropTag(sys::PR_STATUS_STRING_W),
+    ];
+
+    /// Read the [`StatusInfo::COLUMNS`] from a row in the session status table. Returns `None`
+    /// if the row does not have a [`sys::PR_ENTRYID`], since there is no way to open the object.
+    pub fn from_row(row: &Row) -> Option<Self> {
+        Self::from_props(row.iter())
+    }
+
+    /// Read the [`StatusInfo::COLUMNS`] from any set of properties.
+    pub fn from_props<'a>(values: impl IntoIterator<Item = PropValue<'a>>) -> Option<Self> {
+        let mut entry_id = None;
+        let mut info = Self::default();
+
+        for value in values {
+            let long = match value.value {
+                PropValueData::Long(long) => Some(long as u32),
+                _ => None,
+            };
+            match value.tag.0 {
+                sys::PR_ENTRYID => entry_id = EntryId::try_from(&value.value).ok(),
+                sys::PR_DISPLAY_NAME_W => {
+                    info.display_name = value.value.as_string().unwrap_or_default()
+                }
+                sys::PR_PROVIDER_DISPLAY_W => {
+                    info.provider_display = value.value.as_string().unwrap_or_default()
+                }
+                sys::PR_RESOURCE_TYPE => info.resource_type = long.unwrap_or_default(),
+                sys::PR_RESOURCE_METHODS => info.resource_methods = long.unwrap_or_default(),
+                sys::PR_STATUS_CODE => info.status_code = long.unwrap_or_default(),
+                sys::PR_STATUS_STRING_W => info.status_string = value.value.as_string(),
+                _ => {}
+            }
+        }
+
+        info.entry_id = entry_id?;
+        Some(info)
+    }
+
+    /// Test if [`sys::PR_RESOURCE_METHODS`] includes a method, e.g. [`sys::STATUS_FLUSH_QUEUES`].
+    pub fn supports(&self, method: u32) -> bool {
+        self.resource_methods & method == method
+    }
+}
+
+/// Set of flags that can be passed to [`sys::IMAPIStatus::ValidateState`].
+#[derive(Default)]
+pub struct ValidateStateFlags {
+    /// Pass [`sys::ABORT_XP_HEADER_OPERATION`].
+    pub abort_xp_header_operation: bool,
+
+    /// Pass [`sys::CONFIG_CHANGED`].
+    pub config_changed: bool,
+
+    /// Pass [`sys::FORCE_XP_CONNECT`].
+    pub force_xp_connect: bool,
+
+    /// Pass [`sys::FORCE_XP_DISCONNECT`].
+    pub force_xp_disconnect: bool,
+
+    /// Pass [`sys::PROCESS_XP_HEADER_CACHE`].
+    pub process_xp_header_cache: bool,
+
+    /// Pass [`sys::REFRESH_XP_HEADER_CACHE`].
+    pub refresh_xp_header_cache: bool,
+
+    /// Pass [`sys::SHOW_XP_SESSION_UI`].
+    pub show_xp_session_ui: bool,
+
+    /// Pass [`sys::SUPPRESS_UI`].
+    pub suppress_ui: bool,
+}
+
+impl From<ValidateStateFlags> for u32 {
+    fn from(value: ValidateStateFlags) -> Self {
+        let abort_xp_header_operation = if value.abort_xp_header_operation {
+            sys::ABORT_XP_HEADER_OPERATION
+        } else {
+            0
+        };
+        let config_changed = if value.config_changed {
+            sys::CONFIG_CHANGED
+        } else {
+            0
+        };
+        let force_xp_connect = if value.force_xp_connect {
+            sys::FORCE_XP_CONNECT
+        } else {
+            0
+        };
+        let force_xp_disconnect = if value.force_xp_disconnect {
+            sys::FORCE_XP_DISCONNECT
+        } else {
+            0
+        };
+        let process_xp_header_cache = if value.process_xp_header_cache {
+            sys::PROCESS_XP_HEADER_CACHE
+        } else {
+            0
+        };
+        let refresh_xp_header_cache = if value.refresh_xp_header_cache {
+            sys::REFRESH_XP_HEADER_CACHE
+        } else {
+            0
+        };
+        let show_xp_session_ui = if value.show_xp_session_ui {
+            sys::SHOW_XP_SESSION_UI
+        } else {
+            0
+        };
+        let suppress_ui = if value.suppress_ui {
+            sys::SUPPRESS_UI
+        } else {
+            0
+        };
+
+        abort_xp_header_operation
+            | config_changed
+            | force_xp_connect
+            | force_xp_disconnect
+            | process_xp_header_cache
+            | refresh_xp_header_cache
+            | show_xp_session_ui
+            | suppress_ui
+    }
+}
+
+/// Set of flags that can be passed to [`sys::IMAPIStatus::FlushQueues`].
+#[derive(Default)]
+pub struct FlushQueuesFlags {
+    /// Pass [`sys::FLUSH_ASYNC_OK`].
+    pub async_ok: bool,
+
+    /// Pass [`sys::FLUSH_DOWNLOAD`].
+    pub download: bool,
+
+    /// Pass [`sys::FLUSH_FORCE`].
+    pub force: bool,
+
+    /// Pass [`sys::FLUSH_NO_UI`].
+    pub no_ui: bool,
+
+    /// Pass [`sys::FLUSH_UPLOAD`].
+    pub upload: bool,
+}
+
+impl From<FlushQueuesFlags> for u32 {
+    fn from(value: FlushQueuesFlags) -> Self {
+        let async_ok = if value.async_ok {
+            sys::FLUSH_ASYNC_OK
+        } else {
+            0
+        };
+        let download = if value.download {
+            sys::FLUSH_DOWNLOAD
+        } else {
+            0
+        };
+        let force = if value.force { sys::FLUSH_FORCE } else { 0 };
+        let no_ui = if value.no_ui { sys::FLUSH_NO_UI } else { 0 };
+        let upload = if value.upload { sys::FLUSH_UPLOAD } else { 0 };
+
+        async_ok | download | force | no_ui | upload
+    }
+}
+
+/// How often [`crate::Logon::flush_queues`] reads the spooler status while it waits.
+pub const FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long [`crate::Logon::flush_queues`] waits for the spooler to report that it is busy. With
+/// [`sys::FLUSH_ASYNC_OK`], `FlushQueues` may return before the spooler updates its status, so it
+/// is not finished until it has been busy, or it never started within this grace period.
+pub const FLUSH_START_GRACE: Duration = Duration::from_secs(2);
+
+/// Which queues [`crate::Logon::flush_queues`] should flush.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FlushDirection {
+    /// Send pending messages with [`sys::FLUSH_UPLOAD`].
+    Upload,
+
+    /// Receive new messages with [`sys::FLUSH_DOWNLOAD`].
+    Download,
+
+    /// Send and receive.
+    #[default]
+    Both,
+}
+
+impl FlushDirection {
+    /// Get the [`FlushQueuesFlags`] for the spooler, without any UI. This includes
+    /// [`sys::FLUSH_ASYNC_OK`], since [`crate::Logon::flush_queues`] polls for completion.
+    pub fn flags(self) -> FlushQueuesFlags {
+        FlushQueuesFlags {
+            upload: self != Self::Download,
+            download: self != Self::Upload,
+            no_ui: true,
+            async_ok: true,
+            ..Default::default()
+        }
+    }
+
+    /// Test if the spooler's [`sys::PR_STATUS_CODE`] says it is still working on the queues in
+    /// this direction.
+    pub fn is_busy(self, status_code: u32) -> bool {
+        let upload = if self != Self::Download {
+            sys::STATUS_OUTBOUND_ACTIVE | sys::STATUS_OUTBOUND_FLUSH
+        } else {
+            0
+        };
+        let download = if self != Self::Upload {
+            sys::STATUS_INBOUND_ACTIVE | sys::STATUS_INBOUND_FLUSH
+        } else {
+            0
+        };
+        status_code & (upload | download) != 0
+    }
+}
+
+/// Call `busy` every `interval` until it returns `false` after having returned `true` at least
+/// once, or until it has returned `false` for all of `grace`, and fail with
+/// [`sys::MAPI_E_TIMEOUT`] once `timeout` has passed. If `timeout` is too long to add to
+/// [`Instant::now`], e.g. [`Duration::MAX`], this waits without a deadline.
+pub(crate) fn wait_for_idle<F>(
+    timeout: Duration,
+    interval: Duration,
+    grace: Duration,
+    mut busy: F,
+) -> Result<()>
+where
+    F: FnMut() -> Result<bool>,
+{
+    let start = Instant::now();
+    let deadline = start.checked_add(timeout);
+    let mut started = false;
+    loop {
+        if busy()? {
+            started = true;
+        } else if started || start.elapsed() >= grace {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let delay = match deadline {
+            Some(deadline) if now >= deadline => {
+                return Err(Error::new(
+                    sys::MAPI_E_TIMEOUT,
+                    "the spooler did not finish flushing the queues",
+                ));
+            }
+            Some(deadline) => interval.min(deadline - now),
+            None => interval,
+        };
+        thread::sleep(delay);
+    }
+}
+
+/// Hold on to a [`sys::IMAPIStatus`] from the session status table, e.g. for a transport
+/// provider, and expose its methods without `unsafe`.
+pub struct StatusObject {
+    /// Access the [`sys::IMAPIStatus`].
+    pub status: sys::IMAPIStatus,
+
+    /// Columns from the row in the status table, if it was opened with
+    /// [`crate::Logon::status_objects`].
+    pub info: Option<StatusInfo>,
+
+    epoch: InitEpoch,
+    registration: ObjectRegistration,
+}
+
+impl StatusObject {
+    /// Wrap a [`sys::IMAPIStatus`] returned from one of the [`sys`] interface methods.
+    pub fn new(status: sys::IMAPIStatus) -> Self {
+        Self {
+            status,
+            info: None,
+            epoch: InitEpoch::current(),
+            registration: ObjectRegistration::new(ObjectKind::StatusObject),
+        }
+    }
+
+    /// Wrap a [`sys::IMAPIStatus`] opened from a row in the status table.
+    pub(crate) fn with_info(status: sys::IMAPIStatus, info: StatusInfo) -> Self {
+        Self {
+            info: Some(info),
+            ..Self::new(status)
+        }
+    }
+
+    /// Get the [`ObjectRegistration`] which tracks this object in the [`crate::ObjectRegistry`].
+    pub fn registration(&self) -> &ObjectRegistration {
+        &self.registration
+    }
+
+    /// Call [`sys::IMAPIStatus::SettingsDialog`] to show the configuration property sheet for the
+    /// provider, optionally with [`sys::UI_READONLY`].
+    pub fn settings_dialog(&self, ui_param: HWND, read_only: bool) -> Result<()> {
+        self.epoch.check()?;
+        let flags = if read_only { sys::UI_READONLY } else { 0 };
+        unsafe {
+            self.status
+                .SettingsDialog(ui_param.0 as usize, flags)
+                .map_err(|error| with_last_error(self.mapi_prop(), error))
+        }
+    }
+
+    /// Call [`sys::IMAPIStatus::ValidateState`] to check the state of the provider or the MAPI
+    /// subsystem, e.g. to connect or disconnect a transport.
+    pub fn validate_state(&self, ui_param: HWND, flags: ValidateStateFlags) -> Result<()> {
+        self.epoch.check()?;
+        unsafe {
+            self.status
+                .ValidateState(ui_param.0 as usize, flags.into())
+                .map_err(|error| with_last_error(self.mapi_prop(), error))
+        }
+    }
+
+    /// Call [`sys::IMAPIStatus::FlushQueues`] on the spooler or a transport provider to send or
+    /// receive pending messages. Pass a `target` to flush a single transport through the
+    /// spooler's status object.
+    pub fn flush_queues(
+        &self,
+        ui_param: HWND,
+        target: Option<&EntryId>,
+        flags: FlushQueuesFlags,
+    ) -> Result<()> {
+        self.epoch.check()?;
+        let (count, target) = match target {
+            Some(target) => (u32::try_from(target.len())?, target.as_ptr() as *mut _),
+            None => (0, ptr::null_mut()),
+        };
+        unsafe {
+            self.status
+                .FlushQueues(ui_param.0 as usize, count, target, flags.into())
+                .map_err(|error| with_last_error(self.mapi_prop(), error))
+        }
+    }
+}
+
+impl MAPIProp for StatusObject {
+    fn mapi_prop(&self) -> &sys::IMAPIProp {
+        &self.status
+    }
+
+    fn interface_id(&self) -> GUID {
+        sys::IMAPIStatus::IID
+    }
+
+    fn init_epoch(&self) -> InitEpoch {
+        self.epoch
+    }
+
+    fn registration(&self) -> Option<&ObjectRegistration> {
+        Some(&self.registration)
+    }
+}
+
+impl From<sys::IMAPIStatus> for StatusObject {
+    fn from(value: sys::IMAPIStatus) -> Self {
+        Self::new(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::to_pwstr_buffer;
+
+    #[test]
+    fn validate_state_flags() {
+        assert_eq!(u32::from(ValidateStateFlags::default()), 0);
+        assert_eq!(
+            u32::from(ValidateStateFlags {
+                force_xp_connect: true,
+                suppress_ui: true,
+                ..Default::default()
+            }),
+            sys::FORCE_XP_CONNECT | sys::SUPPRESS_UI
+        );
+    }
+
+    #[test]
+    fn flush_queues_flags() {
+        assert_eq!(u32::from(FlushQueuesFlags::default()), 0);
+        assert_eq!(
+            u32::from(FlushQueuesFlags {
+                upload: true,
+                download: true,
+                ..Default::default()
+            }),
+            sys::FLUSH_UPLOAD | sys::FLUSH_DOWNLOAD
+        );
+    }
+
+    #[test]
+    fn flush_direction() {
+        let flags = u32::from(FlushDirection::Upload.flags());
+        assert_eq!(
+            flags,
+            sys::FLUSH_UPLOAD | sys::FLUSH_NO_UI | sys::FLUSH_ASYNC_OK
+        );
+        let flags = u32::from(FlushDirection::Both.flags());
+        assert_eq!(
+            flags,
+            sys::FLUSH_UPLOAD | sys::FLUSH_DOWNLOAD | sys::FLUSH_NO_UI | sys::FLUSH_ASYNC_OK
+        );
+
+        let sending = sys::STATUS_AVAILABLE | sys::STATUS_OUTBOUND_FLUSH;
+        assert!(FlushDirection::Upload.is_busy(sending));
+        assert!(FlushDirection::Both.is_busy(sending));
+        assert!(!FlushDirection::Download.is_busy(sending));
+        assert!(!FlushDirection::Both.is_busy(sys::STATUS_AVAILABLE));
+    }
+
+    #[test]
+    fn wait_for_spooler() {
+        let mut polls = 0;
+        wait_for_idle(
+            Duration::from_secs(5),
+            Duration::ZERO,
+            Duration::ZERO,
+            || {
+                polls += 1;
+                Ok(polls < 3)
+            },
+        )
+        .expect("wait failed");
+        assert_eq!(polls, 3);
+
+        let error = wait_for_idle(Duration::ZERO, Duration::ZERO, Duration::ZERO, || Ok(true))
+            .expect_err("wait should time out");
+        assert_eq!(error.code(), sys::MAPI_E_TIMEOUT);
+
+        let error = wait_for_idle(
+            Duration::from_secs(5),
+            Duration::ZERO,
+            Duration::ZERO,
+            || Err(Error::from(sys::MAPI_E_NETWORK_ERROR)),
+        )
+        .expect_err("wait should fail");
+        assert_eq!(error.code(), sys::MAPI_E_NETWORK_ERROR);
+    }
+
+    #[test]
+    fn wait_for_spooler_to_start() {
+        // The spooler is idle for two polls before it picks up the flush, so the first idle
+        // status does not count.
+        let mut polls = 0;
+        wait_for_idle(Duration::MAX, Duration::ZERO, Duration::MAX, || {
+            polls += 1;
+            Ok(polls == 3 || polls == 4)
+        })
+        .expect("wait failed");
+        assert_eq!(polls, 5);
+
+        // If the spooler never reports that it is busy, stop waiting after the grace period.
+        let mut polls = 0;
+        wait_for_idle(
+            Duration::MAX,
+            Duration::from_millis(1),
+            Duration::from_millis(5),
+            || {
+                polls += 1;
+                Ok(false)
+            },
+        )
+        .expect("wait failed");
+        assert!(polls > 1);
+    }
+
+    #[test]
+    fn status_info_from_props() {
+        let entry_id = [0x0, 0x0, 0x0, 0x0, 0x1, 0x2, 0x3];
+        let display_name = to_pwstr_buffer("Outlook Spooler");
+        let values = [
+            PropValue {
+                tag: PropTag(sys::PR_ENTRYID),
+                value: PropValueData::Binary(&entry_id),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_DISPLAY_NAME_W),
+                value: PropValueData::Unicode(display_name),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_RESOURCE_TYPE),
+                value: PropValueData::Long(sys::MAPI_SPOOLER as i32),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_RESOURCE_METHODS),
+                value: PropValueData::Long(
+                    (sys::STATUS_VALIDATE_STATE | sys::STATUS_FLUSH_QUEUES) as i32,
+                ),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_STATUS_CODE),
+                value: PropValueData::Long(sys::STATUS_AVAILABLE as i32),
+            },
+            PropValue {
+                tag: PropTag(sys::PR_STATUS_STRING_W),
+                value: PropValueData::Error(sys::MAPI_E_NOT_FOUND),
+            },
+        ];
+
+        let info = StatusInfo::from_props(values).expect("missing entry ID");
+        assert_eq!(info.entry_id, EntryId::from(entry_id.as_slice()));
+        assert_eq!(info.display_name, "Outlook Spooler");
+        assert_eq!(info.provider_display, "");
+        assert_eq!(info.resource_type, sys::MAPI_SPOOLER);
+        assert!(info.supports(sys::STATUS_FLUSH_QUEUES));
+        assert!(!info.supports(sys::STATUS_SETTINGS_DIALOG));
+        assert_eq!(info.status_code, sys::STATUS_AVAILABLE);
+        assert_eq!(info.status_string, None);
+
+        assert!(StatusInfo::from_props([]).is_none());
+    }
+}
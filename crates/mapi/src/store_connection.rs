@@ -0,0 +1,145 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`StoreDisconnected`].
+//!
+//! When a store provider loses its connection, e.g. the Exchange server goes away or Outlook shuts
+//! down the session, every object opened from the store is dead, and each call fails with some
+//! RPC or provider error which depends on the provider. After
+//! [`crate::MsgStore::watch_disconnect`] sees a [`sys::fnevCriticalError`] notification like that,
+//! the [`crate::MsgStore`] and every [`crate::Folder`], [`crate::Message`], [`crate::Table`], etc.
+//! opened from it return [`StoreDisconnected`] instead of calling into the provider.
+
+use crate::sys;
+use core::fmt;
+use std::sync::atomic::{AtomicI32, Ordering};
+use windows::Win32::Foundation::RPC_E_DISCONNECTED;
+use windows_core::*;
+
+/// Error returned from the safe wrappers after their [`crate::MsgStore`] was disconnected.
+///
+/// The methods on the wrappers return a [`windows_core::Error`] like everything else, so this is
+/// converted to [`sys::MAPI_E_END_OF_SESSION`] with a message including the original error. Call
+/// `check_connected`, e.g. [`crate::MsgStore::check_connected`], to get the typed error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StoreDisconnected {
+    /// Error from the [`sys::fnevCriticalError`] notification which disconnected the store.
+    pub error: HRESULT,
+}
+
+impl StoreDisconnected {
+    /// Check if a [`sys::fnevCriticalError`] notification with this error means the store is
+    /// dead: [`sys::MAPI_E_END_OF_SESSION`], [`sys::MAPI_E_NETWORK_ERROR`], or
+    /// [`RPC_E_DISCONNECTED`].
+    pub fn is_disconnect_error(error: HRESULT) -> bool {
+        matches!(
+            error,
+            sys::MAPI_E_END_OF_SESSION | sys::MAPI_E_NETWORK_ERROR | RPC_E_DISCONNECTED
+        )
+    }
+}
+
+impl fmt::Display for StoreDisconnected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "message store was disconnected with 0x{:08X}",
+            self.error.0 as u32
+        )
+    }
+}
+
+impl std::error::Error for StoreDisconnected {}
+
+impl From<StoreDisconnected> for Error {
+    fn from(value: StoreDisconnected) -> Self {
+        Error::new(sys::MAPI_E_END_OF_SESSION, value.to_string())
+    }
+}
+
+/// Connection state shared by a [`crate::MsgStore`] and everything opened from it.
+#[derive(Debug, Default)]
+pub(crate) struct StoreConnection {
+    /// The [`StoreDisconnected::error`], or `0` while the store is still connected. Any error
+    /// which disconnects the store is a failure, so it is never `0`.
+    error: AtomicI32,
+}
+
+impl StoreConnection {
+    /// Mark the store as disconnected. Only the first error is kept.
+    pub fn disconnect(&self, error: HRESULT) {
+        let _ = self
+            .error
+            .compare_exchange(0, error.0, Ordering::AcqRel, Ordering::Acquire);
+    }
+
+    /// Return [`StoreDisconnected`] if [`StoreConnection::disconnect`] was called.
+    pub fn check(&self) -> core::result::Result<(), StoreDisconnected> {
+        match self.error.load(Ordering::Acquire) {
+            0 => Ok(()),
+            error => Err(StoreDisconnected {
+                error: HRESULT(error),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ObjectKind, ObjectRegistration};
+
+    #[test]
+    fn first_error_wins() {
+        let connection = StoreConnection::default();
+        assert_eq!(connection.check(), Ok(()));
+
+        connection.disconnect(sys::MAPI_E_NETWORK_ERROR);
+        connection.disconnect(sys::MAPI_E_END_OF_SESSION);
+        assert_eq!(
+            connection.check(),
+            Err(StoreDisconnected {
+                error: sys::MAPI_E_NETWORK_ERROR
+            })
+        );
+    }
+
+    #[test]
+    fn shared_by_children() {
+        let store = ObjectRegistration::new_store();
+        let message = store.child(ObjectKind::Message);
+        let table = message.child(ObjectKind::Table);
+        let unrelated = ObjectRegistration::new(ObjectKind::Table);
+
+        store
+            .connection()
+            .expect("missing connection")
+            .disconnect(RPC_E_DISCONNECTED);
+        let disconnected = Err(StoreDisconnected {
+            error: RPC_E_DISCONNECTED,
+        });
+        assert_eq!(message.check_connected(), disconnected);
+        assert_eq!(table.check_connected(), disconnected);
+        assert_eq!(unrelated.check_connected(), Ok(()));
+        assert_eq!(ObjectRegistration::new_store().check_connected(), Ok(()));
+    }
+
+    #[test]
+    fn disconnect_errors() {
+        assert!(StoreDisconnected::is_disconnect_error(
+            sys::MAPI_E_END_OF_SESSION
+        ));
+        assert!(StoreDisconnected::is_disconnect_error(RPC_E_DISCONNECTED));
+        assert!(!StoreDisconnected::is_disconnect_error(
+            sys::MAPI_E_NOT_FOUND
+        ));
+    }
+
+    #[test]
+    fn error_code() {
+        let err = Error::from(StoreDisconnected {
+            error: RPC_E_DISCONNECTED,
+        });
+        assert_eq!(err.code(), sys::MAPI_E_END_OF_SESSION);
+    }
+}
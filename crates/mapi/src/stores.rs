@@ -0,0 +1,163 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`StoreInfo`] and [`StoreKind`].
+
+use crate::{sys, EntryId, PropTag, PropValueData, Row};
+
+/// [`sys::PR_MDB_PROVIDER`] for the primary user's Exchange mailbox.
+const PRIMARY_USER_PROVIDER: [u8; 16] = [
+    0x54, 0x94, 0xA1, 0xC0, 0x29, 0x7F, 0x10, 0x1B, 0xA5, 0x87, 0x08, 0x00, 0x2B, 0x2A, 0x25, 0x17,
+];
+
+/// [`sys::PR_MDB_PROVIDER`] for a delegate's Exchange mailbox.
+const DELEGATE_PROVIDER: [u8; 16] = [
+    0x9E, 0xB4, 0x77, 0x00, 0x74, 0xE4, 0x11, 0xCE, 0x8C, 0x5E, 0x00, 0xAA, 0x00, 0x42, 0x54, 0xE2,
+];
+
+/// [`sys::PR_MDB_PROVIDER`] for Exchange public folders.
+const PUBLIC_PROVIDER: [u8; 16] = [
+    0x78, 0xB2, 0xFA, 0x70, 0xAF, 0xF7, 0x11, 0xCD, 0x9B, 0xC8, 0x00, 0xAA, 0x00, 0x2F, 0xC4, 0x5A,
+];
+
+/// [`sys::PR_MDB_PROVIDER`] for the Exchange local store.
+const LOCAL_STORE_PROVIDER: [u8; 16] = [
+    0x2D, 0xE5, 0x6B, 0xA1, 0x64, 0x6E, 0x11, 0xD2, 0x8D, 0x4E, 0x00, 0xC0, 0x4F, 0xAE, 0x23, 0x71,
+];
+
+/// Kind of message store, based on the [`sys::PR_MDB_PROVIDER`] in the message store table.
+///
+/// The `pbExchangeProvider*Guid` constants in [`sys`] are declared as strings, so they can't be
+/// compared byte-for-byte with the property value. This matches the bytes from `edkmdb.h`
+/// instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StoreKind {
+    /// The primary user's Exchange mailbox, i.e. `pbExchangeProviderPrimaryUserGuid`.
+    PrimaryMailbox,
+
+    /// Another user's Exchange mailbox opened as a delegate, i.e.
+    /// `pbExchangeProviderDelegateGuid`.
+    Delegate,
+
+    /// Exchange public folders, i.e. `pbExchangeProviderPublicGuid`.
+    PublicFolders,
+
+    /// The Exchange local store, i.e. `pbExchangeProviderLocalStoreGuid`.
+    LocalStore,
+
+    /// Any other store provider, e.g. a PST file, or a store which did not return
+    /// [`sys::PR_MDB_PROVIDER`].
+    Other,
+}
+
+impl From<&[u8]> for StoreKind {
+    fn from(value: &[u8]) -> Self {
+        if value == PRIMARY_USER_PROVIDER {
+            Self::PrimaryMailbox
+        } else if value == DELEGATE_PROVIDER {
+            Self::Delegate
+        } else if value == PUBLIC_PROVIDER {
+            Self::PublicFolders
+        } else if value == LOCAL_STORE_PROVIDER {
+            Self::LocalStore
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// Summary of a row in the message store table, returned from
+/// [`crate::Logon::message_stores`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoreInfo {
+    /// [`sys::PR_DISPLAY_NAME_W`], or an empty string if it is missing.
+    pub display_name: String,
+
+    /// [`sys::PR_ENTRYID`], which can be passed to [`crate::Logon::open_msg_store`].
+    pub entry_id: EntryId,
+
+    /// [`sys::PR_DEFAULT_STORE`].
+    pub is_default: bool,
+
+    /// Kind of store, based on [`sys::PR_MDB_PROVIDER`].
+    pub kind: StoreKind,
+}
+
+impl StoreInfo {
+    /// Columns which [`StoreInfo::from_row`] expects in the message store table.
+    pub const COLUMNS: [PropTag; 4] = [
+        PropTag(sys::PR_ENTRYID),
+        PropTag(sys::PR_DISPLAY_NAME_W),
+        PropTag(sys::PR_DEFAULT_STORE),
+        PropTag(sys::PR_MDB_PROVIDER),
+    ];
+
+    /// Read the [`StoreInfo::COLUMNS`] from a row in the message store table. Returns `None` if
+    /// the row does not have a [`sys::PR_ENTRYID`], since there is no way to open the store.
+    pub fn from_row(row: &Row) -> Option<Self> {
+        let mut entry_id = None;
+        let mut display_name = String::new();
+        let mut is_default = false;
+        let mut kind = StoreKind::Other;
+
+        for value in row.iter() {
+            match value.tag.0 {
+                sys::PR_ENTRYID => entry_id = EntryId::try_from(&value.value).ok(),
+                sys::PR_DISPLAY_NAME_W => {
+                    display_name = value.value.as_string().unwrap_or_default()
+                }
+                sys::PR_DEFAULT_STORE => {
+                    is_default = matches!(value.value, PropValueData::Boolean(value) if value != 0)
+                }
+                sys::PR_MDB_PROVIDER => {
+                    if let PropValueData::Binary(value) = value.value {
+                        kind = StoreKind::from(value);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Some(Self {
+            display_name,
+            entry_id: entry_id?,
+            is_default,
+            kind,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_providers() {
+        assert_eq!(
+            StoreKind::from(PRIMARY_USER_PROVIDER.as_slice()),
+            StoreKind::PrimaryMailbox
+        );
+        assert_eq!(
+            StoreKind::from(DELEGATE_PROVIDER.as_slice()),
+            StoreKind::Delegate
+        );
+        assert_eq!(
+            StoreKind::from(PUBLIC_PROVIDER.as_slice()),
+            StoreKind::PublicFolders
+        );
+        assert_eq!(
+            StoreKind::from(LOCAL_STORE_PROVIDER.as_slice()),
+            StoreKind::LocalStore
+        );
+    }
+
+    #[test]
+    fn other_provider() {
+        assert_eq!(StoreKind::from([0_u8; 16].as_slice()), StoreKind::Other);
+        assert_eq!(
+            StoreKind::from(&PRIMARY_USER_PROVIDER[..15]),
+            StoreKind::Other
+        );
+        assert_eq!(StoreKind::from([].as_slice()), StoreKind::Other);
+    }
+}
@@ -0,0 +1,82 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`to_pwstr_buffer`], [`to_pcstr_buffer`], [`alloc_pwstr`], and [`alloc_pcstr`].
+//!
+//! Most MAPI methods take `null` terminated strings, either UTF-16 with
+//! [`crate::sys::MAPI_UNICODE`] or 8-bit strings in the system ANSI code page without it. These
+//! helpers convert a Rust `&str` to either form, in a [`Vec`] which the caller keeps alive for the
+//! duration of the call, or in a [`MapiArena`] when the string is part of a larger MAPI structure.
+
+use crate::{CodePage, MapiArena};
+use core::iter;
+use windows_core::*;
+
+/// Encode `value` as UTF-16 and append a `null` terminator, e.g. to pass as a [`PCWSTR`] or to
+/// store in a [`crate::PropValueData::Unicode`] value.
+pub fn to_pwstr_buffer(value: &str) -> Vec<u16> {
+    value.encode_utf16().chain(iter::once(0)).collect()
+}
+
+/// Encode `value` in the system ANSI code page, [`CodePage::ACP`], and append a `null`
+/// terminator, e.g. to pass as a [`PCSTR`] to a method which was called without
+/// [`crate::sys::MAPI_UNICODE`]. See [`CodePage::encode_with_nul`] for the errors this can
+/// return.
+pub fn to_pcstr_buffer(value: &str) -> Result<Vec<u8>> {
+    CodePage::ACP.encode_with_nul(value)
+}
+
+/// Same as [`to_pwstr_buffer`], but allocate the string from `arena`, so it lives as long as the
+/// rest of the structure it is part of.
+pub fn alloc_pwstr(arena: &MapiArena, value: &str) -> Result<PWSTR> {
+    Ok(PWSTR(arena.alloc_str_w(value)?.as_mut_ptr()))
+}
+
+/// Same as [`to_pcstr_buffer`], but allocate the string from `arena`, so it lives as long as the
+/// rest of the structure it is part of.
+pub fn alloc_pcstr(arena: &MapiArena, value: &str) -> Result<PSTR> {
+    Ok(PSTR(
+        arena.alloc_bytes(&to_pcstr_buffer(value)?)?.as_mut_ptr(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pwstr_buffer() {
+        let buffer = to_pwstr_buffer("Zoë");
+        assert_eq!(buffer, ['Z' as u16, 'o' as u16, 'ë' as u16, 0]);
+        assert_eq!(
+            unsafe { PCWSTR(buffer.as_ptr()).to_string() }.expect("invalid string"),
+            "Zoë"
+        );
+        assert_eq!(to_pwstr_buffer(""), [0]);
+    }
+
+    #[test]
+    fn arena_pwstr() {
+        let arena = MapiArena::new().expect("new failed");
+        let value = alloc_pwstr(&arena, "Zoë").expect("alloc_pwstr failed");
+        assert_eq!(unsafe { value.to_string() }.expect("invalid string"), "Zoë");
+    }
+
+    #[test]
+    fn pcstr_buffer() {
+        assert_eq!(
+            to_pcstr_buffer("Zoë").expect("to_pcstr_buffer failed"),
+            [b'Z', b'o', 0xEB, 0]
+        );
+        assert_eq!(to_pcstr_buffer("").expect("to_pcstr_buffer failed"), [0]);
+        assert!(to_pcstr_buffer("Zoë 😀").is_err());
+    }
+
+    #[test]
+    fn arena_pcstr() {
+        let arena = MapiArena::new().expect("new failed");
+        let value = alloc_pcstr(&arena, "Zoë").expect("alloc_pcstr failed");
+        assert_eq!(unsafe { value.as_bytes() }, [b'Z', b'o', 0xEB]);
+        assert!(alloc_pcstr(&arena, "Zoë 😀").is_err());
+    }
+}
@@ -0,0 +1,743 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`Table`], [`TableFlags`], [`SeekOrigin`], [`SortOrder`], [`SortOrderBuilder`],
+//! and [`TableRows`].
+
+use crate::{
+    column_tracker::payload_size, mapi_error::with_last_error, prop_tag::prop_tag_array,
+    retry::with_retry, sys, AdviseConnection, CbNewSSortOrderSet, ColumnTracker, InitEpoch,
+    LimitExceeded, MAPIBuffer, MAPIUninit, ObjectKind, ObjectRegistration, PropTag, PropValue,
+    PropValueData, ReadLimits, RelOp, Restriction, ResumePosition, ResumeToken, RetryPolicy, Row,
+    RowSet, TableEvent, TableNotificationSink, TrackedRow,
+};
+use core::{cell::Cell, mem, ptr};
+use std::{time::Instant, vec};
+use windows::Win32::Foundation::E_INVALIDARG;
+use windows_core::*;
+
+/// Set of flags that can be passed to methods which open a [`sys::IMAPITable`], such as
+/// [`sys::IMAPIContainer::GetContentsTable`] or [`sys::IMAPIContainer::GetHierarchyTable`].
+#[derive(Default)]
+pub struct TableFlags {
+    /// Pass [`sys::MAPI_ASSOCIATED`].
+    pub associated: bool,
+
+    /// Pass [`sys::CONVENIENT_DEPTH`].
+    pub convenient_depth: bool,
+
+    /// Pass [`sys::MAPI_DEFERRED_ERRORS`].
+    pub deferred_errors: bool,
+
+    /// Pass [`sys::SHOW_SOFT_DELETES`].
+    pub show_soft_deletes: bool,
+
+    /// Pass [`sys::MAPI_UNICODE`].
+    pub unicode: bool,
+}
+
+impl From<TableFlags> for u32 {
+    fn from(value: TableFlags) -> Self {
+        let associated = if value.associated {
+            sys::MAPI_ASSOCIATED
+        } else {
+            0
+        };
+        let convenient_depth = if value.convenient_depth {
+            sys::CONVENIENT_DEPTH
+        } else {
+            0
+        };
+        let deferred_errors = if value.deferred_errors {
+            sys::MAPI_DEFERRED_ERRORS
+        } else {
+            0
+        };
+        let show_soft_deletes = if value.show_soft_deletes {
+            sys::SHOW_SOFT_DELETES
+        } else {
+            0
+        };
+        let unicode = if value.unicode { sys::MAPI_UNICODE } else { 0 };
+
+        associated | convenient_depth | deferred_errors | show_soft_deletes | unicode
+    }
+}
+
+/// Predefined bookmarks which can be passed to [`sys::IMAPITable::SeekRow`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeekOrigin {
+    /// [`sys::BOOKMARK_BEGINNING`]
+    Beginning,
+
+    /// [`sys::BOOKMARK_CURRENT`]
+    Current,
+
+    /// [`sys::BOOKMARK_END`]
+    End,
+}
+
+impl From<SeekOrigin> for usize {
+    fn from(value: SeekOrigin) -> Self {
+        (match value {
+            SeekOrigin::Beginning => sys::BOOKMARK_BEGINNING,
+            SeekOrigin::Current => sys::BOOKMARK_CURRENT,
+            SeekOrigin::End => sys::BOOKMARK_END,
+        }) as usize
+    }
+}
+
+/// Direction of one column in a [`SortOrder`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortDirection {
+    /// [`sys::TABLE_SORT_ASCEND`]
+    #[default]
+    Ascending,
+
+    /// [`sys::TABLE_SORT_DESCEND`]
+    Descending,
+}
+
+impl From<SortDirection> for u32 {
+    fn from(value: SortDirection) -> Self {
+        match value {
+            SortDirection::Ascending => sys::TABLE_SORT_ASCEND,
+            SortDirection::Descending => sys::TABLE_SORT_DESCEND,
+        }
+    }
+}
+
+/// Columns to sort a table by, which [`Table::sort`] passes to [`sys::IMAPITable::SortTable`] in
+/// a [`sys::SSortOrderSet`]. The first column is the primary sort key.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SortOrder {
+    /// Each column and the direction to sort it.
+    pub columns: Vec<(PropTag, SortDirection)>,
+}
+
+impl SortOrder {
+    /// Add a column sorted in [`SortDirection::Ascending`] order.
+    pub fn ascending(mut self, tag: PropTag) -> Self {
+        self.columns.push((tag, SortDirection::Ascending));
+        self
+    }
+
+    /// Add a column sorted in [`SortDirection::Descending`] order.
+    pub fn descending(mut self, tag: PropTag) -> Self {
+        self.columns.push((tag, SortDirection::Descending));
+        self
+    }
+
+    /// Build a [`sys::SSortOrderSet`] without any categories. All of the members are `u32`
+    /// values, so it fits in a `Vec<u32>`, like [`prop_tag_array`].
+    pub(crate) fn build(&self) -> Result<Vec<u32>> {
+        let count = u32::try_from(self.columns.len())?;
+        Ok([count, 0, 0]
+            .into_iter()
+            .chain(
+                self.columns
+                    .iter()
+                    .flat_map(|(tag, direction)| [tag.0, u32::from(*direction)]),
+            )
+            .collect())
+    }
+}
+
+/// Build a [`sys::SSortOrderSet`] of any size for [`Table::sort_with`], including the number of
+/// columns which are categories and how many of those start out expanded.
+///
+/// The first `categories` columns group the rows into a categorized view, and the rest sort the
+/// rows within each category. [`SortOrderBuilder::build`] fails with [`E_INVALIDARG`] if there are
+/// more categories than columns, or more expanded categories than categories.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SortOrderBuilder {
+    columns: Vec<(PropTag, SortDirection)>,
+    categories: usize,
+    expanded: usize,
+}
+
+impl SortOrderBuilder {
+    /// Add a column sorted in [`SortDirection::Ascending`] order.
+    pub fn ascending(self, tag: PropTag) -> Self {
+        self.column(tag, SortDirection::Ascending)
+    }
+
+    /// Add a column sorted in [`SortDirection::Descending`] order.
+    pub fn descending(self, tag: PropTag) -> Self {
+        self.column(tag, SortDirection::Descending)
+    }
+
+    /// Add a column sorted in either direction.
+    pub fn column(mut self, tag: PropTag, direction: SortDirection) -> Self {
+        self.columns.push((tag, direction));
+        self
+    }
+
+    /// Set [`sys::SSortOrderSet::cCategories`] and [`sys::SSortOrderSet::cExpanded`].
+    pub fn categories(mut self, categories: usize, expanded: usize) -> Self {
+        self.categories = categories;
+        self.expanded = expanded;
+        self
+    }
+
+    /// Allocate a [`sys::SSortOrderSet`] with [`sys::MAPIAllocateBuffer`] and fill it in.
+    pub fn build(&self) -> Result<MAPIBuffer<'static, sys::SSortOrderSet>> {
+        if self.categories > self.columns.len() {
+            return Err(Error::new(
+                E_INVALIDARG,
+                "more sort categories than sort columns",
+            ));
+        }
+        if self.expanded > self.categories {
+            return Err(Error::new(
+                E_INVALIDARG,
+                "more expanded categories than sort categories",
+            ));
+        }
+        let count = self.columns.len();
+        let order = MAPIUninit::<u8>::new(CbNewSSortOrderSet(count.max(1)))?;
+        let mut order: MAPIUninit<sys::SSortOrderSet> = order.into()?;
+        let header = order.uninit()?.as_mut_ptr();
+        unsafe {
+            ptr::addr_of_mut!((*header).cSorts).write(u32::try_from(count)?);
+            ptr::addr_of_mut!((*header).cCategories).write(u32::try_from(self.categories)?);
+            ptr::addr_of_mut!((*header).cExpanded).write(u32::try_from(self.expanded)?);
+            if count == 0 {
+                ptr::addr_of_mut!((*header).aSort).write(Default::default());
+            }
+            let sorts = ptr::addr_of_mut!((*header).aSort).cast::<sys::SSortOrder>();
+            for (index, (tag, direction)) in self.columns.iter().enumerate() {
+                sorts.add(index).write(sys::SSortOrder {
+                    ulPropTag: tag.0,
+                    ulOrder: u32::from(*direction),
+                });
+            }
+            Ok(order.assume_init())
+        }
+    }
+}
+
+impl From<SortOrder> for SortOrderBuilder {
+    fn from(value: SortOrder) -> Self {
+        Self {
+            columns: value.columns,
+            ..Default::default()
+        }
+    }
+}
+
+/// Hold on to a [`sys::IMAPITable`] and expose the common table operations without `unsafe`.
+pub struct Table {
+    /// Access the [`sys::IMAPITable`].
+    pub table: sys::IMAPITable,
+
+    epoch: InitEpoch,
+    registration: ObjectRegistration,
+
+    /// Incremented every time the cursor or columns change, so [`TableRows`] knows when to throw
+    /// away the rest of its current batch.
+    cursor: Cell<u64>,
+
+    /// Limits from [`Table::set_read_limits`], which override [`ReadLimits::global`].
+    limits: Cell<Option<ReadLimits>>,
+
+    /// Policy from [`Table::set_retry_policy`].
+    retry: Cell<Option<RetryPolicy>>,
+}
+
+impl Table {
+    /// Wrap a [`sys::IMAPITable`] returned from one of the [`sys`] interface methods.
+    pub fn new(table: sys::IMAPITable) -> Self {
+        Self::with_registration(table, ObjectRegistration::new(ObjectKind::Table))
+    }
+
+    /// Wrap a [`sys::IMAPITable`] opened through another wrapper, so it belongs to the same store.
+    pub(crate) fn with_registration(
+        table: sys::IMAPITable,
+        registration: ObjectRegistration,
+    ) -> Self {
+        Self {
+            table,
+            epoch: InitEpoch::current(),
+            registration,
+            cursor: Default::default(),
+            limits: Default::default(),
+            retry: Default::default(),
+        }
+    }
+
+    /// Get the [`ObjectRegistration`] which tracks this table in the [`crate::ObjectRegistry`].
+    pub fn registration(&self) -> &ObjectRegistration {
+        &self.registration
+    }
+
+    /// Make sure MAPI is still initialized and the store is still connected.
+    fn check(&self) -> Result<()> {
+        self.epoch.check()?;
+        Ok(self.registration.check_connected()?)
+    }
+
+    /// Override the [`ReadLimits::global`] limits for this table. Pass [`None`] to go back to the
+    /// global limits.
+    pub fn set_read_limits(&self, limits: Option<ReadLimits>) {
+        self.limits.set(limits);
+    }
+
+    /// Get the limits from [`Table::set_read_limits`], or the [`ReadLimits::global`] limits if
+    /// there is no override.
+    pub fn read_limits(&self) -> ReadLimits {
+        self.limits.get().unwrap_or_else(ReadLimits::global)
+    }
+
+    /// Retry [`Table::query_rows`] and [`Table::query_all_rows`] if they fail with a transient
+    /// error, see [`RetryPolicy`]. Pass [`None`] to stop retrying.
+    pub fn set_retry_policy(&self, policy: Option<RetryPolicy>) {
+        self.retry.set(policy);
+    }
+
+    /// Get the policy from [`Table::set_retry_policy`].
+    pub fn retry_policy(&self) -> Option<RetryPolicy> {
+        self.retry.get()
+    }
+
+    /// Call [`sys::IMAPITable::SetColumns`] with [`sys::TBL_BATCH`], which defers the work until
+    /// the next call that reads rows from the table.
+    pub fn set_columns(&self, columns: &[PropTag]) -> Result<()> {
+        self.check()?;
+        let mut columns = prop_tag_array(columns)?;
+        self.move_cursor();
+        unsafe {
+            self.table
+                .SetColumns(columns.as_mut_ptr() as *mut _, sys::TBL_BATCH)
+                .map_err(|error| with_last_error(&self.table, error))
+        }
+    }
+
+    /// Call [`sys::IMAPITable::GetRowCount`].
+    pub fn get_row_count(&self) -> Result<usize> {
+        self.check()?;
+        let mut count = 0;
+        unsafe {
+            self.table
+                .GetRowCount(0, &mut count)
+                .map_err(|error| with_last_error(&self.table, error))?;
+        }
+        Ok(count as usize)
+    }
+
+    /// Call [`sys::IMAPITable::SeekRow`] and return the number of rows that were actually sought,
+    /// which may be less than `count` if it reached the beginning or end of the table.
+    pub fn seek_row(&self, origin: SeekOrigin, count: i32) -> Result<i32> {
+        self.check()?;
+        let mut sought = 0;
+        self.move_cursor();
+        unsafe {
+            self.table
+                .SeekRow(origin.into(), count, &mut sought)
+                .map_err(|error| with_last_error(&self.table, error))?;
+        }
+        Ok(sought)
+    }
+
+    /// Call [`sys::IMAPITable::QueryRows`] to read up to `count` rows, starting at the current
+    /// position. An empty [`RowSet`] means there are no more rows.
+    ///
+    /// The rows are checked against the [`Table::read_limits`]. If there are too many rows in the
+    /// batch, or any of the values is too big, this returns [`sys::MAPI_E_TOO_BIG`].
+    ///
+    /// `QueryRows` moves the cursor even if it fails, so if there is a [`Table::retry_policy`],
+    /// this calls [`sys::IMAPITable::CreateBookmark`] first and seeks back to it before each
+    /// retry.
+    pub fn query_rows(&self, count: usize) -> Result<RowSet> {
+        self.check()?;
+        let count = i32::try_from(count)?;
+        let query = || {
+            let mut rows = RowSet::default();
+            unsafe {
+                self.table
+                    .QueryRows(count, 0, rows.as_mut_ptr())
+                    .map_err(|error| with_last_error(&self.table, error))?;
+            }
+            Ok(rows)
+        };
+        let rows = match self.retry_policy() {
+            Some(policy) => {
+                let mut bookmark = 0;
+                unsafe {
+                    self.table
+                        .CreateBookmark(&mut bookmark)
+                        .map_err(|error| with_last_error(&self.table, error))?;
+                }
+                let mut attempted = false;
+                let result = policy.run(|| {
+                    if mem::replace(&mut attempted, true) {
+                        unsafe {
+                            self.table
+                                .SeekRow(bookmark, 0, ptr::null_mut())
+                                .map_err(|error| with_last_error(&self.table, error))?;
+                        }
+                    }
+                    query()
+                });
+                unsafe {
+                    let _ = self.table.FreeBookmark(bookmark);
+                }
+                result?
+            }
+            None => query()?,
+        };
+        self.read_limits().check_rows(&rows)?;
+        Ok(rows)
+    }
+
+    /// Stream the rows from the current position to the end of the table, calling
+    /// [`Table::query_rows`] to read up to `batch_size` rows at a time as the [`TableRows`]
+    /// iterator needs them. Unlike [`Table::query_all_rows`], only one batch is held in memory.
+    ///
+    /// Calling [`Table::set_columns`], [`Table::seek_row`], [`Table::restrict`], or
+    /// [`Table::resume`] while iterating discards the rest of the current batch, and the next
+    /// call to [`Iterator::next`] reads from the new position, even if the iterator already
+    /// reached the end of the table.
+    ///
+    /// Each batch is checked against the [`Table::read_limits`], and the iterator returns
+    /// [`sys::MAPI_E_TOO_BIG`] once it has yielded [`ReadLimits::max_rows`] rows and there are
+    /// still more rows in the table.
+    pub fn iter_rows(&self, batch_size: usize) -> TableRows<'_> {
+        TableRows {
+            table: self,
+            batch_size: batch_size.max(1),
+            cursor: self.cursor.get(),
+            rows: Vec::new().into_iter(),
+            count: 0,
+            done: false,
+        }
+    }
+
+    /// Call [`sys::IMAPITable::Restrict`] to filter the rows in the table. Pass [`None`] to remove
+    /// the current restriction.
+    pub fn restrict(&self, restriction: Option<&Restriction>) -> Result<()> {
+        self.check()?;
+        let mut restriction = restriction.map(Restriction::build).transpose()?;
+        let restriction = match restriction.as_mut() {
+            Some(restriction) => restriction.as_mut_ptr()?,
+            None => ptr::null_mut(),
+        };
+        self.move_cursor();
+        unsafe {
+            self.table
+                .Restrict(restriction, 0)
+                .map_err(|error| with_last_error(&self.table, error))
+        }
+    }
+
+    /// Call [`sys::IMAPITable::SortTable`] to sort the rows in the table by the columns in the
+    /// [`SortOrder`].
+    pub fn sort(&self, order: &SortOrder) -> Result<()> {
+        self.check()?;
+        let mut order = order.build()?;
+        self.move_cursor();
+        unsafe {
+            self.table
+                .SortTable(order.as_mut_ptr() as *mut _, 0)
+                .map_err(|error| with_last_error(&self.table, error))
+        }
+    }
+
+    /// Call [`sys::IMAPITable::SortTable`] with the [`sys::SSortOrderSet`] from a
+    /// [`SortOrderBuilder`], e.g. to categorize the rows.
+    pub fn sort_with(&self, order: &SortOrderBuilder) -> Result<()> {
+        self.check()?;
+        let mut order = order.build()?;
+        self.move_cursor();
+        unsafe {
+            self.table
+                .SortTable(order.as_mut()?, 0)
+                .map_err(|error| with_last_error(&self.table, error))
+        }
+    }
+
+    /// Call [`sys::IMAPITable::Advise`] to register for [`sys::fnevTableModified`] notifications,
+    /// and pass each one to `callback` as a [`TableEvent`]. The rows in the events have the
+    /// columns from [`Table::set_columns`]. Notifications stop when the [`AdviseConnection`] is
+    /// dropped. See [`TableNotificationSink`] for how `callback` is called.
+    pub fn advise<F>(&self, callback: F) -> Result<AdviseConnection>
+    where
+        F: Fn(TableEvent) + Send + Sync + 'static,
+    {
+        self.check()?;
+        let sink = TableNotificationSink::create(callback);
+        let mut connection = 0;
+        unsafe {
+            self.table
+                .Advise(sys::fnevTableModified, &sink, &mut connection)
+                .map_err(|error| with_last_error(&self.table, error))?;
+        }
+        Ok(AdviseConnection::table(
+            self.table.clone(),
+            connection,
+            self.epoch,
+        ))
+    }
+
+    /// Call [`sys::HrQueryAllRows`] to set the `columns` and read every row from the beginning of
+    /// the table. If `restriction` is not [`None`], only the matching rows are returned. If
+    /// `max_rows` is [`None`], there is no limit on the number of rows.
+    ///
+    /// If [`ReadLimits::max_rows`] from the [`Table::read_limits`] is lower than `max_rows`, it is
+    /// passed to [`sys::HrQueryAllRows`] instead, and a [`sys::MAPI_E_TABLE_TOO_BIG`] error is
+    /// returned as [`sys::MAPI_E_TOO_BIG`]. The values are also checked against the limits.
+    pub fn query_all_rows(
+        &self,
+        columns: &[PropTag],
+        restriction: Option<&Restriction>,
+        max_rows: Option<usize>,
+    ) -> Result<RowSet> {
+        self.check()?;
+        let mut columns = prop_tag_array(columns)?;
+        let mut restriction = restriction.map(Restriction::build).transpose()?;
+        let restriction = match restriction.as_mut() {
+            Some(restriction) => restriction.as_mut_ptr()?,
+            None => ptr::null_mut(),
+        };
+        let limits = self.read_limits();
+        let limited = match (max_rows, limits.max_rows) {
+            (Some(max_rows), Some(limit)) => limit < max_rows,
+            (None, limit) => limit.is_some(),
+            _ => false,
+        };
+        let max_rows = if limited { limits.max_rows } else { max_rows };
+        let max_rows = i32::try_from(max_rows.unwrap_or_default())?;
+        self.move_cursor();
+        let result = with_retry(self.retry_policy(), || {
+            let mut rows = RowSet::default();
+            unsafe {
+                sys::HrQueryAllRows(
+                    &self.table,
+                    columns.as_mut_ptr() as *mut _,
+                    restriction,
+                    ptr::null_mut(),
+                    max_rows,
+                    rows.as_mut_ptr(),
+                )
+            }
+            .map(|()| rows)
+        });
+        let rows = match result {
+            Err(err) if limited && err.code() == sys::MAPI_E_TABLE_TOO_BIG => {
+                return Err(LimitExceeded::RowCount {
+                    count: max_rows as usize + 1,
+                    max: max_rows as usize,
+                }
+                .into());
+            }
+            result => result.map_err(|error| with_last_error(&self.table, error))?,
+        };
+        limits.check_rows(&rows)?;
+        Ok(rows)
+    }
+
+    /// Call [`sys::IMAPITable::FindRow`] to move the cursor to the row after the one recorded in
+    /// the [`ResumeToken`]. If that row is no longer in the table, call
+    /// [`sys::IMAPITable::SeekRow`] to move to the same row number instead. The token does not
+    /// record the sort key values, so the table should be sorted and restricted the same way as
+    /// when the token was saved.
+    pub fn resume(&self, token: &ResumeToken) -> Result<ResumePosition> {
+        self.check()?;
+        if token.is_empty() {
+            self.seek_row(SeekOrigin::Beginning, 0)?;
+            return Ok(ResumePosition::Beginning);
+        }
+
+        let mut restriction = Restriction::Property {
+            relop: RelOp::Equal,
+            value: PropValue {
+                tag: token.key_tag(),
+                value: PropValueData::Binary(token.key()),
+            },
+        }
+        .build()?;
+
+        self.move_cursor();
+        match unsafe {
+            self.table.FindRow(
+                restriction.as_mut_ptr()?,
+                sys::BOOKMARK_BEGINNING as usize,
+                0,
+            )
+        } {
+            Ok(()) => {
+                self.seek_row(SeekOrigin::Current, 1)?;
+                Ok(ResumePosition::Exact)
+            }
+            Err(err) if err.code() == sys::MAPI_E_NOT_FOUND => {
+                self.seek_row(SeekOrigin::Beginning, i32::try_from(token.position())?)?;
+                Ok(ResumePosition::Approximate)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Read every row from the current position to the end of the table, passing each one to `f`
+    /// as a [`TrackedRow`].
+    ///
+    /// The first batch uses all of the columns in the [`ColumnTracker`]. Once the first batch has
+    /// been processed, the tracker narrows the column set to the columns `f` actually read, and
+    /// the remaining batches only request those columns.
+    pub fn for_each_row<F>(&self, tracker: &mut ColumnTracker, mut f: F) -> Result<()>
+    where
+        F: FnMut(TrackedRow) -> Result<()>,
+    {
+        self.set_columns(tracker.columns())?;
+        loop {
+            let start = Instant::now();
+            let rows = self.query_rows(tracker.next_batch_size())?;
+            let elapsed = start.elapsed();
+            if rows.is_empty() {
+                break;
+            }
+
+            let mut count = 0;
+            let mut bytes = 0;
+            for row in rows {
+                count += 1;
+                bytes += row.iter().map(|value| payload_size(&value)).sum::<usize>();
+                f(tracker.track(&row))?;
+            }
+            tracker.record_batch(count, bytes, elapsed);
+
+            if tracker.narrow() {
+                self.set_columns(tracker.columns())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn move_cursor(&self) {
+        self.cursor.set(self.cursor.get().wrapping_add(1));
+    }
+}
+
+/// Iterator returned from [`Table::iter_rows`], which reads the table one batch at a time.
+///
+/// If [`sys::IMAPITable::QueryRows`] fails, the error is returned once, and then the iterator
+/// stops until the cursor is moved again.
+pub struct TableRows<'a> {
+    table: &'a Table,
+    batch_size: usize,
+    cursor: u64,
+    rows: vec::IntoIter<Row>,
+    count: usize,
+    done: bool,
+}
+
+impl TableRows<'_> {
+    fn count_row(&mut self, row: Row) -> Result<Row> {
+        self.count += 1;
+        match self.table.read_limits().check_row_count(self.count) {
+            Ok(()) => Ok(row),
+            Err(err) => {
+                self.done = true;
+                Err(err.into())
+            }
+        }
+    }
+}
+
+impl Iterator for TableRows<'_> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cursor = self.table.cursor.get();
+        if cursor != self.cursor {
+            self.cursor = cursor;
+            self.rows = Vec::new().into_iter();
+            self.count = 0;
+            self.done = false;
+        }
+
+        if self.done {
+            return None;
+        }
+        if let Some(row) = self.rows.next() {
+            return Some(self.count_row(row));
+        }
+
+        match self.table.query_rows(self.batch_size) {
+            Ok(rows) if rows.is_empty() => {
+                self.done = true;
+                None
+            }
+            Ok(rows) => {
+                self.rows = rows.into_iter();
+                let row = self.rows.next()?;
+                Some(self.count_row(row))
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl From<sys::IMAPITable> for Table {
+    fn from(value: sys::IMAPITable) -> Self {
+        Self::new(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_sort_order() {
+        let order = SortOrder::default()
+            .descending(PropTag(sys::PR_MESSAGE_DELIVERY_TIME))
+            .ascending(PropTag(sys::PR_SUBJECT_W));
+        assert_eq!(
+            order.build().expect("build failed"),
+            [
+                2,
+                0,
+                0,
+                sys::PR_MESSAGE_DELIVERY_TIME,
+                sys::TABLE_SORT_DESCEND,
+                sys::PR_SUBJECT_W,
+                sys::TABLE_SORT_ASCEND,
+            ]
+        );
+        assert_eq!(
+            SortOrder::default().build().expect("build failed"),
+            [0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn build_sort_order_set() {
+        let builder = SortOrderBuilder::default()
+            .ascending(PropTag(sys::PR_CONVERSATION_TOPIC_W))
+            .descending(PropTag(sys::PR_MESSAGE_DELIVERY_TIME))
+            .ascending(PropTag(sys::PR_SUBJECT_W))
+            .categories(1, 1);
+        let mut order = builder.build().expect("build failed");
+        let order = order.as_mut().expect("as_mut failed");
+        assert_eq!(order.cSorts, 3);
+        assert_eq!(order.cCategories, 1);
+        assert_eq!(order.cExpanded, 1);
+        let sorts = unsafe { core::slice::from_raw_parts(order.aSort.as_ptr(), 3) };
+        assert_eq!(sorts[0].ulPropTag, sys::PR_CONVERSATION_TOPIC_W);
+        assert_eq!(sorts[1].ulOrder, sys::TABLE_SORT_DESCEND);
+        assert_eq!(sorts[2].ulPropTag, sys::PR_SUBJECT_W);
+
+        let mut empty = SortOrderBuilder::default().build().expect("build failed");
+        assert_eq!(empty.as_mut().expect("as_mut failed").cSorts, 0);
+
+        let too_many = SortOrderBuilder::default()
+            .ascending(PropTag(sys::PR_SUBJECT_W))
+            .categories(2, 0);
+        assert_eq!(too_many.build().err().map(|e| e.code()), Some(E_INVALIDARG));
+        let too_many = builder.categories(1, 2);
+        assert_eq!(too_many.build().err().map(|e| e.code()), Some(E_INVALIDARG));
+    }
+}
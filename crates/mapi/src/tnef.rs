@@ -0,0 +1,424 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Define [`TnefReader`], [`TnefWriter`], [`TnefEncoding`], [`TnefCodePage`], and
+//! [`TnefProblem`].
+//!
+//! TNEF is the format of the `winmail.dat` attachment, which carries the MAPI properties of a
+//! message through transports that only understand MIME. `OpenTnefStreamEx` returns an `ITnef`
+//! object which either decodes the stream into a [`Message`], or encodes the properties of a
+//! [`Message`] into the stream.
+//!
+//! The generated bindings do not include the TNEF API, so `ITnef` and its structures are declared
+//! here, and `OpenTnefStreamEx` and `GetTnefStreamCodepage` are loaded from the MAPI DLL on
+//! demand.
+
+use crate::{prop_tag::prop_tag_array, sys, AddrBook, MAPIOutParam, MAPIProp, Message, PropTag};
+use core::{ffi::c_void, ptr};
+use std::sync::OnceLock;
+use windows::Win32::{
+    Foundation::*,
+    System::{
+        Com::{IStream, STREAM_SEEK_SET},
+        LibraryLoader::*,
+    },
+};
+use windows_core::*;
+
+/// `TNEF_DECODE`, which is missing from [`sys`].
+const TNEF_DECODE: u32 = 0x0000_0000;
+
+/// `TNEF_ENCODE`, which is missing from [`sys`].
+const TNEF_ENCODE: u32 = 0x0000_0002;
+
+/// `TNEF_PURE`, which is missing from [`sys`].
+const TNEF_PURE: u32 = 0x0001_0000;
+
+/// `TNEF_COMPATIBILITY`, which is missing from [`sys`].
+const TNEF_COMPATIBILITY: u32 = 0x0002_0000;
+
+/// `TNEF_BEST_DATA`, which is missing from [`sys`].
+const TNEF_BEST_DATA: u32 = 0x0004_0000;
+
+/// `TNEF_PROP_EXCLUDE`, which is missing from [`sys`].
+const TNEF_PROP_EXCLUDE: u32 = 0x0000_0002;
+
+/// Stream name Outlook uses for the TNEF attachment.
+const TNEF_STREAM_NAME: PCSTR = s!("winmail.dat");
+
+mod itnef {
+    #![allow(dead_code, non_snake_case)]
+
+    use super::STnefProblemArray;
+    use crate::sys;
+    use core::ffi::c_void;
+    use windows_core::*;
+
+    /// `ITnef` from `TNEF.h`. It does not have a published IID, and it is only ever returned from
+    /// `OpenTnefStreamEx`, so it is never queried.
+    #[windows_interface::interface]
+    pub unsafe trait ITnef: IUnknown {
+        pub fn AddProps(
+            &self,
+            flags: u32,
+            elem_id: u32,
+            data: *mut c_void,
+            prop_list: *mut sys::SPropTagArray,
+        ) -> HRESULT;
+        pub fn ExtractProps(
+            &self,
+            flags: u32,
+            prop_list: *mut sys::SPropTagArray,
+            problems: *mut *mut STnefProblemArray,
+        ) -> HRESULT;
+        pub fn Finish(
+            &self,
+            flags: u32,
+            key: *mut u16,
+            problems: *mut *mut STnefProblemArray,
+        ) -> HRESULT;
+        pub fn OpenTaggedBody(
+            &self,
+            message: *mut c_void,
+            flags: u32,
+            stream: *mut *mut c_void,
+        ) -> HRESULT;
+        pub fn SetProps(
+            &self,
+            flags: u32,
+            elem_id: u32,
+            count: u32,
+            props: *mut sys::SPropValue,
+        ) -> HRESULT;
+        pub fn EncodeRecips(&self, flags: u32, recipient_table: *mut c_void) -> HRESULT;
+    }
+}
+
+use itnef::ITnef;
+
+/// `STnefProblem` from `TNEF.h`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct STnefProblem {
+    component: u32,
+    attribute: u32,
+    prop_tag: u32,
+    scode: i32,
+}
+
+/// `STnefProblemArray` from `TNEF.h`.
+#[repr(C)]
+struct STnefProblemArray {
+    count: u32,
+    problems: [STnefProblem; 1],
+}
+
+type OpenTnefStreamEx = unsafe extern "system" fn(
+    *mut c_void,
+    *mut c_void,
+    *const u8,
+    u32,
+    *mut c_void,
+    u16,
+    *mut c_void,
+    *mut *mut c_void,
+) -> HRESULT;
+
+type GetTnefStreamCodepage = unsafe extern "system" fn(*mut c_void, *mut u32, *mut u32) -> HRESULT;
+
+/// How [`TnefWriter`] should encode the message properties.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TnefEncoding {
+    /// Pass `TNEF_BEST_DATA`, which writes the MAPI properties and the down-level attributes
+    /// older clients understand.
+    #[default]
+    BestData,
+
+    /// Pass `TNEF_PURE`, which only writes the MAPI properties.
+    Pure,
+
+    /// Pass `TNEF_COMPATIBILITY`, which writes the down-level attributes wherever it can.
+    Compatibility,
+}
+
+impl From<TnefEncoding> for u32 {
+    fn from(value: TnefEncoding) -> Self {
+        match value {
+            TnefEncoding::BestData => TNEF_BEST_DATA,
+            TnefEncoding::Pure => TNEF_PURE,
+            TnefEncoding::Compatibility => TNEF_COMPATIBILITY,
+        }
+    }
+}
+
+/// Code pages of the string attributes in a TNEF stream, from `GetTnefStreamCodepage`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TnefCodePage {
+    /// Primary code page, e.g. `1252`.
+    pub code_page: u32,
+
+    /// Secondary code page, which is usually `0`.
+    pub sub_code_page: u32,
+}
+
+impl TnefCodePage {
+    /// Call `GetTnefStreamCodepage` to read the code page attribute of a TNEF stream. This rewinds
+    /// the stream before and after reading it, so it can still be passed to [`TnefReader::new`].
+    pub fn from_stream(stream: &IStream) -> Result<Self> {
+        let get_code_page = get_tnef_stream_codepage().ok_or_else(not_supported)?;
+        let mut code_page = Self {
+            code_page: 0,
+            sub_code_page: 0,
+        };
+        unsafe {
+            stream.Seek(0, STREAM_SEEK_SET, None)?;
+            get_code_page(
+                stream.as_raw(),
+                &mut code_page.code_page,
+                &mut code_page.sub_code_page,
+            )
+            .ok()?;
+            stream.Seek(0, STREAM_SEEK_SET, None)?;
+        }
+        Ok(code_page)
+    }
+}
+
+/// Problem with a single attribute or property reported by [`TnefReader::extract`] or
+/// [`TnefWriter::finish`]. These are not fatal, the rest of the properties are still processed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TnefProblem {
+    /// Which part of the message had the problem, `u32::MAX` for the message itself or the
+    /// [`sys::PR_ATTACH_NUM`] of an attachment.
+    pub component: u32,
+
+    /// The TNEF attribute ID.
+    pub attribute: u32,
+
+    /// The MAPI property, if the problem was with a property.
+    pub tag: PropTag,
+
+    /// What went wrong.
+    pub error: HRESULT,
+}
+
+impl From<STnefProblem> for TnefProblem {
+    fn from(value: STnefProblem) -> Self {
+        Self {
+            component: value.component,
+            attribute: value.attribute,
+            tag: PropTag(value.prop_tag),
+            error: HRESULT(value.scode),
+        }
+    }
+}
+
+/// Decode a TNEF stream, e.g. the [`sys::PR_ATTACH_DATA_BIN`] of a `winmail.dat` attachment, into
+/// a [`Message`].
+pub struct TnefReader<'a> {
+    tnef: ITnef,
+    message: &'a Message,
+}
+
+impl<'a> TnefReader<'a> {
+    /// Call `OpenTnefStreamEx` with `TNEF_DECODE`. The decoded properties and attachments are
+    /// written to `message`, which is usually a new message from
+    /// [`crate::Folder::create_message`] or an embedded message. The `addr_book` is used to
+    /// resolve the recipients.
+    pub fn new(stream: &'a IStream, message: &'a Message, addr_book: &'a AddrBook) -> Result<Self> {
+        let tnef = open_tnef_stream(stream, message, addr_book, TNEF_DECODE, 0)?;
+        Ok(Self { tnef, message })
+    }
+
+    /// Call `ITnef::ExtractProps` to decode all of the properties, recipients, and attachments
+    /// except for the `excluded` properties. Once this returns, read them from the [`Message`]
+    /// with [`MAPIProp::get_props`] and [`Message::attachments`], and call
+    /// [`MAPIProp::save_changes`] to keep them.
+    pub fn extract(&self, excluded: &[PropTag]) -> Result<Vec<TnefProblem>> {
+        self.message.init_epoch().check()?;
+        let mut excluded = prop_tag_array(excluded)?;
+        let mut problems = MAPIOutParam::new();
+        unsafe {
+            self.tnef
+                .ExtractProps(
+                    TNEF_PROP_EXCLUDE,
+                    excluded.as_mut_ptr() as *mut _,
+                    problems.as_mut_ptr(),
+                )
+                .ok()?;
+        }
+        Ok(collect_problems(problems))
+    }
+
+    /// Get the [`Message`] the stream is decoded into.
+    pub fn message(&self) -> &Message {
+        self.message
+    }
+}
+
+/// Encode the properties of a [`Message`] into a TNEF stream, e.g. to send it as a `winmail.dat`
+/// attachment.
+pub struct TnefWriter<'a> {
+    tnef: ITnef,
+    message: &'a Message,
+}
+
+impl<'a> TnefWriter<'a> {
+    /// Call `OpenTnefStreamEx` with `TNEF_ENCODE` and the [`TnefEncoding`]. The `key` should be
+    /// nonzero, it is used to tag attachment positions in the down-level body.
+    pub fn new(
+        stream: &'a IStream,
+        message: &'a Message,
+        addr_book: &'a AddrBook,
+        key: u16,
+        encoding: TnefEncoding,
+    ) -> Result<Self> {
+        let flags = TNEF_ENCODE | u32::from(encoding);
+        let tnef = open_tnef_stream(stream, message, addr_book, flags, key)?;
+        Ok(Self { tnef, message })
+    }
+
+    /// Call `ITnef::AddProps` to queue all of the message properties, including the attachments,
+    /// except for the `excluded` properties.
+    pub fn add_props(&self, excluded: &[PropTag]) -> Result<()> {
+        self.message.init_epoch().check()?;
+        let mut excluded = prop_tag_array(excluded)?;
+        unsafe {
+            self.tnef
+                .AddProps(
+                    TNEF_PROP_EXCLUDE,
+                    0,
+                    ptr::null_mut(),
+                    excluded.as_mut_ptr() as *mut _,
+                )
+                .ok()
+        }
+    }
+
+    /// Call `ITnef::EncodeRecips` with the recipient table of the message. Meeting requests need
+    /// this to carry the attendees, since the recipients are not message properties.
+    pub fn encode_recipients(&self) -> Result<()> {
+        let table = self.message.get_recipient_table(Default::default())?;
+        unsafe { self.tnef.EncodeRecips(0, table.table.as_raw()).ok() }
+    }
+
+    /// Call `ITnef::Finish` to write everything which was queued to the stream. Call
+    /// [`std::io::Write::flush`] on the stream afterwards if it is a
+    /// [`crate::PropertyStream`].
+    pub fn finish(self) -> Result<Vec<TnefProblem>> {
+        self.message.init_epoch().check()?;
+        let mut key = 0;
+        let mut problems = MAPIOutParam::new();
+        unsafe {
+            self.tnef.Finish(0, &mut key, problems.as_mut_ptr()).ok()?;
+        }
+        Ok(collect_problems(problems))
+    }
+}
+
+fn open_tnef_stream(
+    stream: &IStream,
+    message: &Message,
+    addr_book: &AddrBook,
+    flags: u32,
+    key: u16,
+) -> Result<ITnef> {
+    message.init_epoch().check()?;
+    let open = open_tnef_stream_ex().ok_or_else(not_supported)?;
+    let mut tnef = ptr::null_mut();
+    unsafe {
+        open(
+            ptr::null_mut(),
+            stream.as_raw(),
+            TNEF_STREAM_NAME.as_ptr(),
+            flags,
+            message.message.as_raw(),
+            key,
+            addr_book.addr_book.as_raw(),
+            &mut tnef,
+        )
+        .ok()?;
+        if tnef.is_null() {
+            return Err(Error::from(E_POINTER));
+        }
+        Ok(ITnef::from_raw(tnef))
+    }
+}
+
+fn collect_problems(mut problems: MAPIOutParam<STnefProblemArray>) -> Vec<TnefProblem> {
+    let Some(problems) = (unsafe { problems.as_mut() }) else {
+        return Vec::new();
+    };
+    let count = problems.count as usize;
+    let problems = problems.problems.as_ptr();
+    (0..count)
+        .map(|index| TnefProblem::from(unsafe { ptr::read_unaligned(problems.add(index)) }))
+        .collect()
+}
+
+fn not_supported() -> Error {
+    Error::new(
+        sys::MAPI_E_NO_SUPPORT,
+        "the MAPI DLL does not export the TNEF API",
+    )
+}
+
+/// Look up an export in `olmapi32.dll` if Outlook is installed, or in `mapi32.dll`.
+fn mapi_proc(name: PCSTR) -> Option<unsafe extern "system" fn() -> isize> {
+    unsafe {
+        #[cfg(feature = "olmapi32")]
+        if let Some(proc) = outlook_mapi_sys::ensure_olmapi32()
+            .ok()
+            .and_then(|module| GetProcAddress(module, name))
+        {
+            return Some(proc);
+        }
+
+        GetProcAddress(LoadLibraryW(w!("mapi32")).ok()?, name)
+    }
+}
+
+fn open_tnef_stream_ex() -> Option<OpenTnefStreamEx> {
+    static OPEN_TNEF_STREAM_EX: OnceLock<Option<OpenTnefStreamEx>> = OnceLock::new();
+    *OPEN_TNEF_STREAM_EX.get_or_init(|| unsafe {
+        let proc = mapi_proc(s!("OpenTnefStreamEx"))?;
+        Some(core::mem::transmute::<
+            unsafe extern "system" fn() -> isize,
+            OpenTnefStreamEx,
+        >(proc))
+    })
+}
+
+fn get_tnef_stream_codepage() -> Option<GetTnefStreamCodepage> {
+    static GET_TNEF_STREAM_CODEPAGE: OnceLock<Option<GetTnefStreamCodepage>> = OnceLock::new();
+    *GET_TNEF_STREAM_CODEPAGE.get_or_init(|| unsafe {
+        let proc = mapi_proc(s!("GetTnefStreamCodepage"))?;
+        Some(core::mem::transmute::<
+            unsafe extern "system" fn() -> isize,
+            GetTnefStreamCodepage,
+        >(proc))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding_flags() {
+        assert_eq!(u32::from(TnefEncoding::default()), TNEF_BEST_DATA);
+        assert_eq!(TNEF_ENCODE | u32::from(TnefEncoding::Pure), 0x0001_0002);
+    }
+
+    #[test]
+    fn problem_from_sys() {
+        let problem = TnefProblem::from(STnefProblem {
+            component: u32::MAX,
+            attribute: 0x0006_8003,
+            prop_tag: sys::PR_SUBJECT_W,
+            scode: sys::MAPI_E_NOT_FOUND.0,
+        });
+        assert_eq!(problem.tag, PropTag(sys::PR_SUBJECT_W));
+        assert_eq!(problem.error, sys::MAPI_E_NOT_FOUND);
+    }
+}